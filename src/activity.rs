@@ -0,0 +1,140 @@
+use std::{collections::HashMap, fmt::Debug, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::WindowAddr;
+
+/// Source of the current time in epoch seconds, replaceable in tests.
+pub trait Clock: Debug + Send {
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowTimes {
+    /// When the window opened, or when we adopted it if it existed before us.
+    pub opened_at: u64,
+    pub last_focus: u64,
+}
+
+/// Creation and last focus time of every tracked window.
+#[derive(Debug)]
+pub struct Activity {
+    clock: Box<dyn Clock>,
+    windows: HashMap<WindowAddr, WindowTimes>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new(Box::new(SystemClock))
+    }
+}
+
+impl Activity {
+    pub fn new(clock: Box<dyn Clock>) -> Self {
+        Self {
+            clock,
+            windows: HashMap::new(),
+        }
+    }
+
+    pub fn window_added(&mut self, window: &WindowAddr) {
+        let now = self.clock.now();
+        self.windows.entry(*window).or_insert(WindowTimes { opened_at: now, last_focus: now });
+    }
+
+    pub fn window_focused(&mut self, window: &WindowAddr) {
+        let now = self.clock.now();
+        self.windows.entry(*window)
+            .and_modify(|t| t.last_focus = now)
+            .or_insert(WindowTimes { opened_at: now, last_focus: now });
+    }
+
+    /// Takes over times saved by a previous run.
+    pub fn restore(&mut self, window: WindowAddr, times: WindowTimes) {
+        self.windows.insert(window, times);
+    }
+
+    pub fn window_closed(&mut self, window: &WindowAddr) {
+        self.windows.remove(window);
+    }
+
+    /// Epoch seconds by the clock the times are taken with.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    pub fn times(&self, window: &WindowAddr) -> Option<WindowTimes> {
+        self.windows.get(window).copied()
+    }
+
+    /// Windows not focused for at least `secs`, least recently focused first.
+    pub fn stale(&self, secs: u64) -> Vec<(WindowAddr, WindowTimes)> {
+        let now = self.clock.now();
+        let mut windows: Vec<(WindowAddr, WindowTimes)> = self.windows.iter()
+            .filter(|(_, t)| now.saturating_sub(t.last_focus) >= secs)
+            .map(|(w, t)| (*w, *t))
+            .collect();
+        windows.sort_by(|a, b| a.1.last_focus.cmp(&b.1.last_focus).then_with(|| a.0.cmp(&b.0)));
+        windows
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+
+    use crate::addr;
+
+    use super::{Activity, Clock, WindowTimes};
+
+    /// Clock which only moves when told to.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct ManualClock(Arc<AtomicU64>);
+
+    impl ManualClock {
+        pub(crate) fn advance(&self, secs: u64) {
+            self.0.fetch_add(secs, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn stale_windows() {
+        let clock = ManualClock::default();
+        clock.advance(1000);
+        let mut activity = Activity::new(Box::new(clock.clone()));
+
+        activity.window_added(&addr("7e3"));
+        activity.window_added(&addr("f1f0"));
+        clock.advance(100);
+        activity.window_focused(&addr("f1f0"));
+        // adding again keeps the original time
+        activity.window_added(&addr("f1f0"));
+
+        assert_eq!(activity.times(&addr("f1f0")), Some(WindowTimes { opened_at: 1000, last_focus: 1100 }));
+
+        clock.advance(50);
+        let stale: Vec<_> = activity.stale(100).into_iter().map(|(w, _)| w).collect();
+        assert_eq!(stale, vec![addr("7e3")]);
+
+        let stale: Vec<_> = activity.stale(0).into_iter().map(|(w, _)| w).collect();
+        assert_eq!(stale, vec![addr("7e3"), addr("f1f0")]);
+
+        activity.window_closed(&addr("7e3"));
+        assert!(activity.stale(100).is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+use std::{collections::VecDeque, sync::{Mutex, OnceLock}, time::Duration};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{config::AdoptionConfig, ctrl::Ctrl, dispatch::handle_changes_batched, hooks::Hooks, monitor::Changes, state::{Changes as MonitorChanges, WindowAddr, WindowInfo}};
+
+/// The dispatches putting the windows found at startup where their tags say, sent a chunk at a time so hundreds of
+/// moves don't hold up Hyprland's rendering. The state is adopted right away, only the dispatches trickle out:
+/// windows coming into view first, then the ones parked on hidden tags.
+#[derive(Debug, Default)]
+pub struct Adoption {
+    /// Changes of one window each, in the order they go out. Those of no window, like the focus, follow the
+    /// windows coming into view.
+    pending: VecDeque<Changes>,
+    windows_per_batch: usize,
+    delay: Duration,
+    next: Option<JoinHandle<()>>,
+}
+
+static ADOPTION: OnceLock<Mutex<Adoption>> = OnceLock::new();
+
+pub fn adoption() -> &'static Mutex<Adoption> {
+    ADOPTION.get_or_init(Default::default)
+}
+
+impl Adoption {
+    /// Queues the startup changes, replacing what's left of an earlier adoption.
+    pub fn start(&mut self, all_changes: Vec<Changes>, config: &AdoptionConfig) {
+        self.cancel();
+        self.windows_per_batch = config.windows_per_batch;
+        self.delay = Duration::from_millis(config.batch_delay_ms);
+
+        let mut shown = vec![];
+        let mut rest = vec![];
+        let mut parked = vec![];
+        for changes in all_changes {
+            let one = |window: &WindowInfo, added: bool| Changes {
+                active_monitor_index: changes.active_monitor_index,
+                tag_count: changes.tag_count,
+                changes: MonitorChanges {
+                    window_added: if added { vec![window.clone()] } else { vec![] },
+                    window_removed: if added { vec![] } else { vec![window.clone()] },
+                    ..Default::default()
+                },
+                source: changes.source.clone(),
+            };
+            shown.extend(changes.changes.window_added.iter().map(|w| one(w, true)));
+            parked.extend(changes.changes.window_removed.iter().map(|w| one(w, false)));
+            let left = MonitorChanges { window_added: vec![], window_removed: vec![], ..changes.changes };
+            if left.focus.is_some() || left.shown_tags != 0 || left.hidden_tags != 0 || !left.dimmed.is_empty() || !left.undimmed.is_empty() {
+                rest.push(Changes { changes: left, ..changes });
+            }
+        }
+        self.pending = shown.into_iter().chain(rest).chain(parked).collect();
+        if !self.pending.is_empty() {
+            tracing::info!(changes = self.pending.len(), windows_per_batch = self.windows_per_batch, "adopting windows");
+        }
+    }
+
+    /// Drops what's left to send, like when the session the windows are of went away.
+    pub fn cancel(&mut self) {
+        if let Some(task) = self.next.take() {
+            task.abort();
+        }
+        if !self.pending.is_empty() {
+            tracing::info!(changes = self.pending.len(), "adoption cancelled");
+        }
+        self.pending.clear();
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// The changes of the next `windows_per_batch` windows, all of them when that's 0.
+    pub fn take_chunk(&mut self) -> Vec<Changes> {
+        let mut chunk = vec![];
+        let mut windows = 0;
+        while let Some(changes) = self.pending.front() {
+            let n = window_count(changes);
+            if self.windows_per_batch > 0 && windows + n > self.windows_per_batch && windows > 0 {
+                break;
+            }
+            windows += n;
+            chunk.extend(self.pending.pop_front());
+        }
+        chunk
+    }
+
+    /// The changes up to the last one of the windows, which have to go out before anything else moves them.
+    pub fn take_through(&mut self, windows: &[WindowAddr]) -> Vec<Changes> {
+        if windows.is_empty() {
+            return vec![];
+        }
+        match self.pending.iter().rposition(|c| mentions(c).any(|w| windows.contains(w))) {
+            Some(last) => self.pending.drain(..=last).collect(),
+            None => vec![],
+        }
+    }
+
+    /// Leaves out the window, which closed before its turn.
+    pub fn forget(&mut self, window: &WindowAddr) {
+        self.pending.retain(|c| !mentions(c).any(|w| w == window));
+    }
+
+    /// Sends `Ctrl::AdoptionChunk` after the delay, if anything is left.
+    pub(crate) fn schedule_next(&mut self, tx: mpsc::Sender<Ctrl>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let delay = self.delay;
+        self.next = Some(tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(err) = tx.send(Ctrl::AdoptionChunk).await {
+                tracing::error!(%err, "failed to send Ctrl::AdoptionChunk");
+            }
+        }));
+    }
+}
+
+fn window_count(changes: &Changes) -> usize {
+    changes.changes.window_added.len() + changes.changes.window_removed.len()
+}
+
+/// The windows the changes move or focus.
+fn mentions(changes: &Changes) -> impl Iterator<Item = &WindowAddr> {
+    changes.changes.window_added.iter().chain(changes.changes.window_removed.iter()).map(|w| &w.addr)
+        .chain(changes.changes.focus.iter())
+}
+
+/// Starts the adoption with the changes of placing the windows found at startup, sending the first chunk now.
+pub(crate) fn begin(hooks: &Hooks, all_changes: Vec<Changes>, config: &AdoptionConfig, tx: mpsc::Sender<Ctrl>) {
+    adoption().lock().unwrap().start(all_changes, config);
+    send_chunk(hooks, tx);
+}
+
+/// Sends the next chunk, scheduling the one after it.
+pub(crate) fn send_chunk(hooks: &Hooks, tx: mpsc::Sender<Ctrl>) {
+    let chunk = adoption().lock().unwrap().take_chunk();
+    if !chunk.is_empty() {
+        tracing::debug!(changes = chunk.len(), "adoption chunk");
+        handle_changes_batched(hooks, chunk);
+    }
+    let mut adoption = adoption().lock().unwrap();
+    adoption.schedule_next(tx);
+    if adoption.is_done() {
+        tracing::debug!("adoption done");
+    }
+}
+
+/// The pending adoption changes which have to go out before `all_changes`, for the windows it moves or focuses
+/// to first be put where they were adopted to.
+pub(crate) fn take_pending<'a>(all_changes: impl IntoIterator<Item = &'a Changes>) -> Vec<Changes> {
+    let windows: Vec<WindowAddr> = all_changes.into_iter().flat_map(mentions).copied().collect();
+    let first = adoption().lock().unwrap().take_through(&windows);
+    if !first.is_empty() {
+        tracing::debug!(changes = first.len(), "sending adoption changes ahead of a later change");
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{addr, config::AdoptionConfig, monitor::{ChangeSource, Changes}, state::{Changes as MonitorChanges, WindowInfo, DEFAULT_TAG_COUNT}};
+
+    use super::Adoption;
+
+    fn infos(windows: &[&str]) -> Vec<WindowInfo> {
+        windows.iter().map(|w| WindowInfo { addr: addr(w), tag: 1 }).collect()
+    }
+
+    /// The windows of the changes, `+` for ones coming into view and `-` for parked ones.
+    fn windows(all_changes: &[Changes]) -> Vec<String> {
+        all_changes.iter().flat_map(|c| {
+            c.changes.window_added.iter().map(|w| format!("+{}", w.addr))
+                .chain(c.changes.window_removed.iter().map(|w| format!("-{}", w.addr)))
+                .chain(c.changes.focus.iter().map(|w| format!("focus {}", w)))
+                .collect::<Vec<_>>()
+        }).collect()
+    }
+
+    #[test]
+    fn chunks_visible_first() {
+        let startup = || vec![
+            Changes {
+                active_monitor_index: 0,
+                tag_count: DEFAULT_TAG_COUNT,
+                changes: MonitorChanges { window_removed: infos(&["a1", "a2", "a3"]), ..Default::default() },
+                source: ChangeSource::Startup,
+            },
+            Changes {
+                active_monitor_index: 1,
+                tag_count: DEFAULT_TAG_COUNT,
+                changes: MonitorChanges { window_added: infos(&["b1", "b2"]), focus: Some(addr("b2")), ..Default::default() },
+                source: ChangeSource::Startup,
+            },
+            Changes {
+                active_monitor_index: 0,
+                tag_count: DEFAULT_TAG_COUNT,
+                changes: MonitorChanges { window_added: infos(&["a4"]), ..Default::default() },
+                source: ChangeSource::Startup,
+            },
+        ];
+        let mut adoption = Adoption::default();
+        adoption.start(startup(), &AdoptionConfig { windows_per_batch: 2, batch_delay_ms: 10 });
+        assert_eq!(windows(&adoption.take_chunk()), ["+b1", "+b2"]);
+        // the focus follows the windows coming into view
+        assert_eq!(windows(&adoption.take_chunk()), ["+a4", "focus b2", "-a1"]);
+        assert_eq!(adoption.take_chunk()[0].active_monitor_index, 0);
+        assert_eq!(windows(&adoption.take_chunk()), Vec::<String>::new());
+        assert!(adoption.is_done());
+
+        // a change to a window goes after everything up to its adoption
+        adoption.start(startup(), &AdoptionConfig { windows_per_batch: 2, batch_delay_ms: 10 });
+        assert_eq!(windows(&adoption.take_through(&[addr("ff")])), Vec::<String>::new());
+        assert_eq!(windows(&adoption.take_through(&[addr("a4")])), ["+b1", "+b2", "+a4"]);
+        adoption.forget(&addr("a2"));
+        assert_eq!(windows(&adoption.take_chunk()), ["focus b2", "-a1", "-a3"]);
+
+        // all at once
+        adoption.start(startup(), &AdoptionConfig { windows_per_batch: 0, batch_delay_ms: 10 });
+        assert_eq!(adoption.take_chunk().len(), 7);
+        adoption.cancel();
+        assert!(adoption.is_done());
+    }
+}
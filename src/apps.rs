@@ -0,0 +1,177 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crate::state::WindowAddr;
+
+/// How long a window launched by `activate` has to appear to be placed on its app tag.
+pub const LAUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Window classes with their focus recency, and launch rules waiting for a window.
+#[derive(Debug, Default)]
+pub struct Apps {
+    windows: HashMap<WindowAddr, AppWindow>,
+    clock: u64,
+    pending: Vec<PendingRule>,
+    captures: Vec<Capture>,
+}
+
+#[derive(Debug)]
+struct AppWindow {
+    class: String,
+    last_used: u64,
+}
+
+#[derive(Debug)]
+struct PendingRule {
+    class: String,
+    tag: u8,
+    expires: Instant,
+}
+
+/// A launch rule for the window of a command whose class isn't known, which takes the next window of any class.
+#[derive(Debug)]
+struct Capture {
+    tag: u8,
+    command: String,
+    expires: Instant,
+}
+
+impl Apps {
+    pub fn window_opened(&mut self, window: WindowAddr, class: &str) {
+        self.clock += 1;
+        self.windows.insert(window, AppWindow { class: class.to_string(), last_used: self.clock });
+    }
+
+    pub fn window_focused(&mut self, window: &WindowAddr) {
+        self.clock += 1;
+        if let Some(w) = self.windows.get_mut(window) {
+            w.last_used = self.clock;
+        }
+    }
+
+    pub fn window_closed(&mut self, window: &WindowAddr) {
+        self.windows.remove(window);
+    }
+
+    /// Windows of the class, most recently used first.
+    pub fn windows_of(&self, class: &str) -> Vec<WindowAddr> {
+        let mut windows: Vec<(&WindowAddr, &AppWindow)> = self.windows.iter()
+            .filter(|(_, w)| w.class == class)
+            .collect();
+        windows.sort_by_key(|(_, w)| std::cmp::Reverse(w.last_used));
+        windows.into_iter().map(|(addr, _)| *addr).collect()
+    }
+
+    /// Places the next window of the class on `tag`, if it appears before `expires`.
+    pub fn arm(&mut self, class: &str, tag: u8, expires: Instant) {
+        self.pending.retain(|r| r.class != class);
+        self.pending.push(PendingRule { class: class.to_string(), tag, expires });
+    }
+
+    /// Consumes the rule armed for the class, or else the oldest capture. Expired rules are dropped on the way,
+    /// expired captures are left for `expired_captures`.
+    pub fn take_rule(&mut self, class: &str, now: Instant) -> Option<u8> {
+        self.pending.retain(|r| {
+            let alive = r.expires > now;
+            if !alive {
+                tracing::debug!(class = r.class, "launch rule expired");
+            }
+            alive
+        });
+
+        if let Some(index) = self.pending.iter().position(|r| r.class == class) {
+            return Some(self.pending.remove(index).tag);
+        }
+        let index = self.captures.iter().position(|c| c.expires > now)?;
+        let capture = self.captures.remove(index);
+        tracing::debug!(class, tag = capture.tag, command = capture.command, "captured started window");
+        Some(capture.tag)
+    }
+
+    /// Places the next window of any class on `tag` if it appears before `expires`, for the command started for it.
+    pub fn arm_capture(&mut self, tag: u8, command: &str, expires: Instant) {
+        self.captures.push(Capture { tag, command: command.to_string(), expires });
+    }
+
+    /// Drops a capture of the command's window, returning whether there was one.
+    pub fn cancel_capture(&mut self, tag: u8, command: &str) -> bool {
+        match self.captures.iter().position(|c| c.tag == tag && c.command == command) {
+            Some(index) => {
+                self.captures.remove(index);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Drops the captures no window came for in time, returning their tags and commands.
+    pub fn expired_captures(&mut self, now: Instant) -> Vec<(u8, String)> {
+        let (expired, alive) = std::mem::take(&mut self.captures).into_iter().partition(|c| c.expires <= now);
+        self.captures = alive;
+        expired.into_iter().map(|c: Capture| (c.tag, c.command)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::addr;
+
+    use super::Apps;
+
+    #[test]
+    fn most_recently_used_first() {
+        let mut apps = Apps::default();
+        apps.window_opened(addr("a"), "spotify");
+        apps.window_opened(addr("b"), "spotify");
+        apps.window_opened(addr("c"), "kitty");
+
+        assert_eq!(apps.windows_of("spotify"), vec![addr("b"), addr("a")]);
+
+        apps.window_focused(&addr("c"));
+        apps.window_focused(&addr("a"));
+        assert_eq!(apps.windows_of("spotify"), vec![addr("a"), addr("b")]);
+
+        apps.window_closed(&addr("a"));
+        assert_eq!(apps.windows_of("spotify"), vec![addr("b")]);
+        assert!(apps.windows_of("firefox").is_empty());
+    }
+
+    #[test]
+    fn launch_rules() {
+        let now = Instant::now();
+        let mut apps = Apps::default();
+
+        apps.arm("spotify", 9, now + Duration::from_secs(10));
+        assert_eq!(apps.take_rule("kitty", now), None);
+        assert_eq!(apps.take_rule("spotify", now), Some(9));
+
+        // one-shot
+        assert_eq!(apps.take_rule("spotify", now), None);
+
+        apps.arm("spotify", 9, now + Duration::from_secs(10));
+        assert_eq!(apps.take_rule("spotify", now + Duration::from_secs(11)), None);
+    }
+
+    #[test]
+    fn captures() {
+        let now = Instant::now();
+        let mut apps = Apps::default();
+
+        apps.arm_capture(3, "foot", now + Duration::from_secs(10));
+        apps.arm_capture(3, "firefox", now + Duration::from_secs(10));
+        apps.arm("spotify", 9, now + Duration::from_secs(10));
+        // a rule for the class goes first
+        assert_eq!(apps.take_rule("spotify", now), Some(9));
+        assert_eq!(apps.take_rule("foot", now), Some(3));
+        assert!(apps.cancel_capture(3, "firefox"));
+        assert!(!apps.cancel_capture(3, "firefox"));
+        assert_eq!(apps.take_rule("kitty", now), None);
+
+        apps.arm_capture(4, "foot", now + Duration::from_secs(10));
+        assert!(apps.expired_captures(now).is_empty());
+        assert_eq!(apps.take_rule("foot", now + Duration::from_secs(11)), None);
+        assert_eq!(apps.expired_captures(now + Duration::from_secs(11)), vec![(4, "foot".to_string())]);
+        assert!(apps.expired_captures(now + Duration::from_secs(11)).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, sync::{Mutex, OnceLock}, time::{Duration, Instant}};
+
+use tokio::net::UnixStream;
+
+use crate::{config::CtrlAuthConfig, now};
+
+/// Least time between two log entries about the same user being turned away.
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What a ctrl connection may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Full,
+    /// Commands which change nothing, like `query` and `subscribe`.
+    ReadOnly,
+    Denied,
+}
+
+/// The process at the other end of a ctrl connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    /// None when its credentials couldn't be read.
+    pub uid: Option<u32>,
+    pub access: Access,
+}
+
+impl Peer {
+    /// Logs that the peer was turned away from `what`, at most once a minute per user.
+    pub fn rejected(&self, what: &str) {
+        if let Some(suppressed) = rejections().lock().unwrap().log(self.uid, now()) {
+            tracing::warn!(uid = self.uid, what, suppressed, "ctrl access denied");
+        }
+    }
+}
+
+/// The ctrl access of the user `uid` whose process runs with the group `gid`, when hyprtag runs as `owner`.
+pub fn access(config: &CtrlAuthConfig, owner: u32, uid: u32, gid: u32) -> Access {
+    if !config.enabled || uid == owner || config.allow_uids.contains(&uid) || config.allow_gids.contains(&gid) {
+        Access::Full
+    } else if config.read_only_uids.contains(&uid) || config.read_only_gids.contains(&gid) {
+        Access::ReadOnly
+    } else {
+        Access::Denied
+    }
+}
+
+/// Who connected, from the credentials the kernel keeps for the socket. Turned away when they can't be read and
+/// the checks are on.
+pub fn peer(stream: &UnixStream) -> Peer {
+    let config = auth_config().lock().unwrap();
+    match stream.peer_cred() {
+        Ok(cred) => Peer { uid: Some(cred.uid()), access: access(&config, unsafe { libc::geteuid() }, cred.uid(), cred.gid()) },
+        Err(err) => {
+            tracing::error!(%err, "failed to read ctrl peer credentials");
+            Peer { uid: None, access: if config.enabled { Access::Denied } else { Access::Full } }
+        },
+    }
+}
+
+pub fn set_auth_config(config: CtrlAuthConfig) {
+    *auth_config().lock().unwrap() = config;
+}
+
+fn auth_config() -> &'static Mutex<CtrlAuthConfig> {
+    static AUTH_CONFIG: OnceLock<Mutex<CtrlAuthConfig>> = OnceLock::new();
+    AUTH_CONFIG.get_or_init(|| Mutex::new(CtrlAuthConfig::default()))
+}
+
+/// When each user was last logged as turned away, with how many times since.
+#[derive(Debug, Default)]
+pub struct Rejections {
+    logged: HashMap<Option<u32>, (Instant, u64)>,
+}
+
+impl Rejections {
+    /// Whether to log a rejection of `uid` now, with how many of its rejections went unlogged since the last entry.
+    pub fn log(&mut self, uid: Option<u32>, now: Instant) -> Option<u64> {
+        match self.logged.get_mut(&uid) {
+            Some((at, suppressed)) if now.duration_since(*at) < LOG_INTERVAL => {
+                *suppressed += 1;
+                None
+            },
+            Some((at, suppressed)) => {
+                *at = now;
+                Some(std::mem::take(suppressed))
+            },
+            None => {
+                self.logged.insert(uid, (now, 0));
+                Some(0)
+            },
+        }
+    }
+}
+
+fn rejections() -> &'static Mutex<Rejections> {
+    static REJECTIONS: OnceLock<Mutex<Rejections>> = OnceLock::new();
+    REJECTIONS.get_or_init(|| Mutex::new(Rejections::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::config::CtrlAuthConfig;
+
+    use super::{access, peer, Access, Rejections};
+
+    #[test]
+    fn access_by_credentials() {
+        let config = CtrlAuthConfig {
+            enabled: true,
+            allow_uids: vec![1001],
+            allow_gids: vec![50],
+            read_only_uids: vec![1002],
+            read_only_gids: vec![60],
+        };
+        assert_eq!(access(&config, 1000, 1000, 1000), Access::Full);
+        assert_eq!(access(&config, 1000, 1001, 1001), Access::Full);
+        assert_eq!(access(&config, 1000, 1003, 50), Access::Full);
+        assert_eq!(access(&config, 1000, 1002, 1002), Access::ReadOnly);
+        assert_eq!(access(&config, 1000, 1004, 60), Access::ReadOnly);
+        assert_eq!(access(&config, 1000, 1005, 1005), Access::Denied);
+        // root is no exception
+        assert_eq!(access(&config, 1000, 0, 0), Access::Denied);
+
+        // everyone who can reach the socket when the checks are off
+        assert_eq!(access(&CtrlAuthConfig::default(), 1000, 1005, 1005), Access::Full);
+    }
+
+    #[tokio::test]
+    async fn own_connections() {
+        let (client, _server) = tokio::net::UnixStream::pair().unwrap();
+        let peer = peer(&client);
+        assert_eq!(peer.uid, Some(unsafe { libc::geteuid() }));
+        assert_eq!(peer.access, Access::Full);
+    }
+
+    #[test]
+    fn rate_limited_log() {
+        let mut rejections = Rejections::default();
+        let start = Instant::now();
+        assert_eq!(rejections.log(Some(1005), start), Some(0));
+        assert_eq!(rejections.log(Some(1005), start + Duration::from_secs(1)), None);
+        assert_eq!(rejections.log(Some(1005), start + Duration::from_secs(2)), None);
+        // other users are logged on their own
+        assert_eq!(rejections.log(Some(1006), start + Duration::from_secs(2)), Some(0));
+        assert_eq!(rejections.log(Some(1005), start + Duration::from_secs(61)), Some(2));
+        assert_eq!(rejections.log(Some(1005), start + Duration::from_secs(62)), None);
+    }
+}
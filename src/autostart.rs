@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use tokio::{process::Command, sync::mpsc, task::JoinHandle};
+
+use crate::{ctrl::Ctrl, hyprctl::mock};
+
+/// How long a command started for a tag has to bring up its window to have it placed there.
+pub const AUTOSTART_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which tags started their commands this session. A tag's `autostart` commands run the first time it's shown
+/// empty, and not again until `autostart_reset`.
+#[derive(Debug, Default)]
+pub struct Autostart {
+    started: u64,
+    expiry: Option<JoinHandle<()>>,
+}
+
+impl Autostart {
+    /// Marks the tag started, returning whether it wasn't yet.
+    pub fn start(&mut self, tag: u8) -> bool {
+        let started = self.started & 1<<(tag - 1) != 0;
+        self.started |= 1<<(tag - 1);
+        !started
+    }
+
+    /// Lets the tag, or all of them, start their commands again.
+    pub fn reset(&mut self, tag: Option<u8>) {
+        match tag {
+            Some(tag) => self.started &= !(1<<(tag - 1)),
+            None => self.started = 0,
+        }
+    }
+
+    /// Sends `Ctrl::AutostartExpired` once the captures armed now expired, replacing the previously scheduled one.
+    pub(crate) fn schedule_expiry(&mut self, tx: mpsc::Sender<Ctrl>) {
+        if let Some(task) = self.expiry.take() {
+            task.abort();
+        }
+        self.expiry = Some(tokio::spawn(async move {
+            tokio::time::sleep(AUTOSTART_TIMEOUT).await;
+            if let Err(err) = tx.send(Ctrl::AutostartExpired).await {
+                tracing::error!(%err, "failed to send Ctrl::AutostartExpired");
+            }
+        }));
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(task) = self.expiry.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Runs the command with the daemon's environment. When it exits with an error, the failure is sent back as
+/// `Ctrl::AutostartFailed`.
+pub(crate) fn spawn(tag: u8, command: &str, tx: mpsc::Sender<Ctrl>) -> std::io::Result<()> {
+    if let Some(mock) = mock() {
+        mock.lock().unwrap().record("exec", command);
+        return Ok(());
+    }
+    tracing::info!(tag, command, "autostarting");
+    let mut child = Command::new("sh").arg("-c").arg(command).spawn()?;
+    let command = command.to_string();
+    tokio::spawn(async move {
+        let reason = match child.wait().await {
+            Ok(status) if status.success() => return,
+            Ok(status) => format!("exited with {}", status),
+            Err(err) => err.to_string(),
+        };
+        if let Err(err) = tx.send(Ctrl::AutostartFailed(tag, command, reason)).await {
+            tracing::error!(%err, "failed to send Ctrl::AutostartFailed");
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Autostart;
+
+    #[test]
+    fn once_per_session() {
+        let mut autostart = Autostart::default();
+        assert!(autostart.start(3));
+        assert!(!autostart.start(3));
+        assert!(autostart.start(40));
+
+        autostart.reset(Some(3));
+        assert!(autostart.start(3));
+        assert!(!autostart.start(40));
+        autostart.reset(None);
+        assert!(autostart.start(40));
+    }
+}
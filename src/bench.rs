@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufStream}, net::UnixStream};
+
+use crate::{hyprland_dir, protocol};
+
+/// Cycles run before measuring, so process and connection setup don't skew the numbers.
+const WARMUP_CYCLES: usize = 3;
+/// How long to wait for a socket2 event after a command with `--events`.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    cycles: usize,
+    tags: Vec<u8>,
+    events: bool,
+}
+
+/// `hyprtag bench [-n N] --tag a,b [--events]`: times show/restore cycles against the running daemon.
+pub async fn run(args: &[String]) -> anyhow::Result<()> {
+    let options = parse_args(args)?;
+
+    let mut ctrl = BufStream::new(UnixStream::connect(hyprland_dir()?.join(protocol::SOCKET)).await?);
+    let mut events = if options.events {
+        Some(BufStream::new(UnixStream::connect(hyprland_dir()?.join(".socket2.sock")).await?))
+    } else {
+        None
+    };
+
+    let mut show = vec![];
+    let mut restore = vec![];
+    let mut show_event = vec![];
+    let mut missed_events = 0;
+
+    for cycle in 0..WARMUP_CYCLES + options.cycles {
+        for tag in options.tags.iter() {
+            let (elapsed, event) = timed(&mut ctrl, events.as_mut(), &format!("sync show {}", tag)).await?;
+            let (restore_elapsed, _) = timed(&mut ctrl, events.as_mut(), "sync restore").await?;
+
+            if cycle < WARMUP_CYCLES {
+                continue;
+            }
+            show.push(elapsed);
+            restore.push(restore_elapsed);
+            match event {
+                Some(e) => show_event.push(e),
+                None if options.events => missed_events += 1,
+                None => (),
+            }
+        }
+    }
+
+    println!("{} cycles over tags {:?}", options.cycles, options.tags);
+    print_summary("show", &mut show);
+    print_summary("restore", &mut restore);
+    if options.events {
+        print_summary("show -> event", &mut show_event);
+        if missed_events > 0 {
+            println!("{} shows caused no event within {:?}", missed_events, EVENT_TIMEOUT);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a command and waits for its completion reply, and for the next event when watching them.
+async fn timed(ctrl: &mut BufStream<UnixStream>, events: Option<&mut BufStream<UnixStream>>, cmd: &str) -> anyhow::Result<(Duration, Option<Duration>)> {
+    let start = Instant::now();
+    ctrl.write_all(format!("{}\n", cmd).as_bytes()).await?;
+    ctrl.flush().await?;
+
+    let mut reply = String::new();
+    if ctrl.read_line(&mut reply).await? == 0 {
+        bail!("daemon closed the connection");
+    }
+    let elapsed = start.elapsed();
+    if reply.trim_end() != "ok" {
+        bail!("{}: {}", cmd, reply.trim_end());
+    }
+
+    let event = match events {
+        Some(events) => {
+            let mut line = String::new();
+            match tokio::time::timeout(EVENT_TIMEOUT, events.read_line(&mut line)).await {
+                Ok(r) => {
+                    r?;
+                    Some(start.elapsed())
+                },
+                Err(_) => None,
+            }
+        },
+        None => None,
+    };
+
+    Ok((elapsed, event))
+}
+
+fn parse_args(args: &[String]) -> anyhow::Result<Options> {
+    let mut options = Options { cycles: 20, tags: vec![], events: false };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-n" => options.cycles = match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) if n > 0 => n,
+                _ => bail!("-n needs a positive number"),
+            },
+            "--tag" => options.tags = match args.next() {
+                Some(tags) => tags.split(',').map(|t| t.parse::<u8>()).collect::<Result<_, _>>()?,
+                None => bail!("--tag needs a list of tags"),
+            },
+            "--events" => options.events = true,
+            arg => bail!("unknown argument: {}", arg),
+        }
+    }
+
+    if options.tags.is_empty() {
+        bail!("pick tags to switch to with --tag a,b, preferably empty ones");
+    }
+    if options.tags.iter().any(|t| !(1..=32).contains(t)) {
+        bail!("tags need to be between 1 and 32");
+    }
+
+    Ok(options)
+}
+
+/// Min, median and 95th percentile.
+fn summary(samples: &mut [Duration]) -> Option<(Duration, Duration, Duration)> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort();
+    let percentile = |p: usize| samples[((samples.len() - 1) * p + 50) / 100];
+    Some((samples[0], percentile(50), percentile(95)))
+}
+
+fn print_summary(name: &str, samples: &mut [Duration]) {
+    if let Some((min, median, p95)) = summary(samples) {
+        println!("{:<14} min {:>10.3?}  median {:>10.3?}  p95 {:>10.3?}", name, min, median, p95);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_args, summary, Options};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_bench_args() {
+        assert_eq!(parse_args(&args("--tag 8,9 -n 5")).unwrap(), Options { cycles: 5, tags: vec![8, 9], events: false });
+        assert!(parse_args(&args("--events --tag 9")).unwrap().events);
+        assert!(parse_args(&args("-n 5")).is_err());
+        assert!(parse_args(&args("--tag 0")).is_err());
+        assert!(parse_args(&args("--tag 9 -n 0")).is_err());
+    }
+
+    #[test]
+    fn summarize_samples() {
+        let mut samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let (min, median, p95) = summary(&mut samples).unwrap();
+        assert_eq!(min, Duration::from_millis(1));
+        assert_eq!(median, Duration::from_millis(51));
+        assert_eq!(p95, Duration::from_millis(95));
+
+        assert!(summary(&mut []).is_none());
+    }
+}
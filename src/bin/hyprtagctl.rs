@@ -0,0 +1,808 @@
+//! `hyprtagctl`: sends a command to the hyprtag daemon over its control socket and prints the reply.
+
+use std::{io::{Cursor, Write}, os::unix::process::CommandExt, path::PathBuf, process::{Command, ExitCode, Stdio}, time::Duration};
+
+use anyhow::{bail, Context};
+use hyprtag::{format, line::read_reply, protocol::{self, Arg, CommandInfo, UnknownCommand, COMMANDS}};
+use serde_json::Value;
+use tokio::{io::{AsyncWriteExt, BufStream}, net::UnixStream};
+
+const USAGE: &str = "\
+usage: hyprtagctl [--signature SIG] [--timeout SECS | --wait] [--retry | --preview] [--json] COMMAND [ARGS...]
+       hyprtagctl [--format TEMPLATE [--each PATH]] query|outputs|pager|badges|snapshot|summary|explain|subscribe|rule list
+       hyprtagctl help [COMMAND] [--json] | COMMAND --help
+       hyprtagctl completions bash|fish
+       hyprtagctl bench|waybar-config|doctor [ARGS...]
+
+  --signature SIG  Hyprland session to talk to, also read from HYPRTAG_SIGNATURE.
+                   Defaults to HYPRLAND_INSTANCE_SIGNATURE, or the only running session
+  --timeout SECS   how long the daemon gets to answer, 5 seconds by default. --wait waits for as long as it takes
+  --retry          send the command up to 3 times until it's answered, running it once
+  --preview        print what the command would dispatch and change, without running it
+  --json           print JSON replies as they are, instead of as tables through jq
+  --format         render JSON replies by the template, see `hyprtag --help`
+
+`help` describes the commands as the running daemon knows them, or as they were cached when it last answered,
+or as built into hyprtagctl. `completions` prints a bash or fish script completing them.
+
+Commands print their reply, if any. An `err:` reply goes to stderr and exits 1, no reply or no daemon exits 2.
+";
+
+/// How long the daemon gets to answer without `--timeout`, as some commands query hyprctl first.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Attempts of a `--retry` command.
+const RETRIES: usize = 3;
+/// How long `help` and completions wait for the daemon before doing without it.
+const LIVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Subcommands of hyprtagctl itself, completed along with the daemon's commands.
+const CLIENT_COMMANDS: &[&str] = &["bench", "waybar-config", "doctor", "completions"];
+/// Options which take a value.
+const VALUE_OPTIONS: &[&str] = &["--signature", "--timeout", "--format", "--each"];
+const OPTIONS: &[&str] = &["--signature", "--timeout", "--wait", "--retry", "--preview", "--json", "--format", "--each", "--help"];
+
+/// An `err:` reply, or a report which isn't clean.
+const FAILED: u8 = 1;
+/// Wrong usage, no daemon to talk to or no reply from it.
+const ERROR: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    signature: Option<String>,
+    /// `None` with `--wait`.
+    timeout: Option<Duration>,
+    retry: bool,
+    preview: bool,
+    json: bool,
+    /// `COMMAND --help`, describing the command instead of sending it.
+    help: bool,
+    format: Option<String>,
+    each: Option<String>,
+    command: Vec<String>,
+}
+
+/// The options and the command, which has to be one of `commands`.
+fn parse_args(args: &[String], commands: &[CommandInfo]) -> anyhow::Result<Options> {
+    let mut options = Options {
+        signature: None,
+        timeout: Some(DEFAULT_TIMEOUT),
+        retry: false,
+        preview: false,
+        json: false,
+        help: false,
+        format: None,
+        each: None,
+        command: vec![],
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--signature" => options.signature = Some(args.next().context("--signature requires a value")?.clone()),
+            "--timeout" => {
+                let secs: f64 = args.next().context("--timeout requires a value")?.parse().context("invalid --timeout")?;
+                options.timeout = Some(Duration::try_from_secs_f64(secs).context("invalid --timeout")?);
+            },
+            "--wait" => options.timeout = None,
+            "--retry" => options.retry = true,
+            "--preview" => options.preview = true,
+            "--json" => options.json = true,
+            "--help" | "-h" => options.help = true,
+            "--format" => options.format = Some(args.next().context("--format requires a value")?.clone()),
+            "--each" => options.each = Some(args.next().context("--each requires a value")?.clone()),
+            _ => options.command.push(arg.clone()),
+        }
+    }
+    match options.command.first() {
+        None => bail!("no command given"),
+        Some(cmd) if !commands.iter().any(|c| c.name == *cmd) => return Err(UnknownCommand::new(cmd).into()),
+        Some(_) => {},
+    }
+    if options.retry && options.preview {
+        bail!("--retry and --preview don't go together");
+    }
+    if options.each.is_some() && options.format.is_none() {
+        bail!("--each goes with --format");
+    }
+    if options.format.is_some() && !formattable(&options.command) {
+        bail!("--format works with query, outputs, pager, badges, snapshot, summary, explain, rule list and subscribe");
+    }
+    Ok(options)
+}
+
+/// Commands replying with JSON a template can render.
+fn formattable(command: &[String]) -> bool {
+    match command.first().map(String::as_str) {
+        Some("query" | "outputs" | "pager" | "badges" | "snapshot" | "summary" | "explain" | "subscribe") => true,
+        Some("rule") => command.get(1).is_some_and(|a| a == "list"),
+        _ => false,
+    }
+}
+
+/// Talks to the daemon, one connection per request like the daemon expects of keybinds.
+struct Client {
+    socket: PathBuf,
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    async fn connect(&self) -> anyhow::Result<BufStream<UnixStream>> {
+        let stream = UnixStream::connect(&self.socket).await
+            .with_context(|| format!("cannot connect to hyprtag at {}", self.socket.display()))?;
+        Ok(BufStream::new(stream))
+    }
+
+    /// The reply to the line, `None` when the daemon didn't answer in time or closed the connection.
+    async fn request(&self, line: &str) -> anyhow::Result<Option<String>> {
+        let mut stream = self.connect().await?;
+        stream.write_all(format!("{}\n", line).as_bytes()).await?;
+        stream.flush().await?;
+        let reply = read_reply(&mut stream);
+        match self.timeout {
+            Some(timeout) => Ok(tokio::time::timeout(timeout, reply).await.ok().transpose()?.flatten()),
+            None => Ok(reply.await?),
+        }
+    }
+
+    /// The reply to the line, failing when there's none.
+    async fn expect(&self, line: &str) -> anyhow::Result<String> {
+        self.request(line).await?.context(NoReply)
+    }
+}
+
+#[derive(Debug)]
+struct NoReply;
+
+impl std::fmt::Display for NoReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no reply from hyprtag")
+    }
+}
+
+impl std::error::Error for NoReply {}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None | Some("--help" | "-h") => {
+            print!("{}", USAGE);
+            return ExitCode::from(if args.is_empty() { ERROR } else { 0 });
+        },
+        // the daemon binary's own subcommands
+        Some("bench" | "waybar-config" | "doctor") => {
+            let err = Command::new("hyprtag").args(&args).exec();
+            eprintln!("hyprtagctl: cannot run hyprtag: {}", err);
+            return ExitCode::from(ERROR);
+        },
+        Some("completions") => return match args.get(1).map(String::as_str).and_then(completion_script) {
+            Some(script) => {
+                print!("{}", script);
+                ExitCode::SUCCESS
+            },
+            None => {
+                eprintln!("hyprtagctl: usage: hyprtagctl completions bash|fish");
+                ExitCode::from(ERROR)
+            },
+        },
+        // what the completion scripts call with the words typed so far
+        Some("complete") => {
+            for candidate in complete(&args[1..]).await {
+                println!("{}", candidate);
+            }
+            return ExitCode::SUCCESS;
+        },
+        _ => {},
+    }
+
+    let options = match parse_args(&args, &known_commands()) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("hyprtagctl: {:#}\n\n{}", err, USAGE);
+            return ExitCode::from(ERROR);
+        },
+    };
+    match run(options).await {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("hyprtagctl: {:#}", err);
+            ExitCode::from(ERROR)
+        },
+    }
+}
+
+async fn run(options: Options) -> anyhow::Result<u8> {
+    if options.help || options.command[0] == "help" {
+        let name = match options.help {
+            true => options.command.first(),
+            false => options.command.get(1),
+        };
+        return help(options.signature.as_deref(), name.map(String::as_str), options.json).await;
+    }
+
+    let client = Client { socket: protocol::socket_path(options.signature.as_deref())?, timeout: options.timeout };
+    let line = options.command.join(" ");
+    let args: Vec<&str> = options.command.iter().map(String::as_str).collect();
+
+    if let Some(template) = &options.format {
+        let template = format::parse_template(template)?;
+        let each = options.each.as_deref().map(format::parse_each).transpose()?;
+        let mut out = std::io::stdout().lock();
+        if args[0] == "subscribe" {
+            let mut stream = client.connect().await?;
+            stream.write_all(b"subscribe\n").await?;
+            stream.flush().await?;
+            while let Some(reply) = read_reply(&mut stream).await? {
+                format::render_lines(Cursor::new(reply), each.as_deref(), &template, &mut out)?;
+            }
+            return Ok(0);
+        }
+        let reply = client.expect(&line).await?;
+        let ok = format::render_lines(Cursor::new(reply), each.as_deref(), &template, &mut out)?;
+        return Ok(if ok { 0 } else { FAILED });
+    }
+
+    if options.retry {
+        // the id makes the daemon reply with the first attempt's result instead of running the command twice
+        let id = std::fs::read_to_string("/proc/sys/kernel/random/uuid").context("cannot make a request id")?;
+        for _ in 0..RETRIES {
+            if let Some(reply) = client.request(&format!("id:{} {}", id.trim(), line)).await? {
+                return Ok(print_reply(&reply));
+            }
+        }
+        bail!(NoReply);
+    }
+
+    if options.preview {
+        let reply = client.expect(&format!("preview {}", line)).await?;
+        if is_err(&reply) {
+            return Ok(print_reply(&reply));
+        }
+        print_with_jq(&reply, PREVIEW, options.json)?;
+        return Ok(0);
+    }
+
+    match args.as_slice() {
+        ["move", "--to-new", rest @ ..] => {
+            let line = match rest {
+                [] => "move_to_empty",
+                ["--follow"] => "move_to_empty follow",
+                _ => bail!("usage: hyprtagctl move --to-new [--follow]"),
+            };
+            Ok(print_reply(&client.expect(line).await?))
+        },
+        ["outputs", ..] => {
+            let reply = client.expect("outputs").await?;
+            print_with_jq(&reply, OUTPUTS, options.json)?;
+            Ok(0)
+        },
+        ["explain", window] => {
+            let reply = client.expect(&format!("explain {}", window)).await?;
+            if is_err(&reply) {
+                return Ok(print_reply(&reply));
+            }
+            print_with_jq(&reply, EXPLAIN, options.json)?;
+            Ok(0)
+        },
+        ["explain", ..] => bail!("usage: hyprtagctl explain <address> [--json]"),
+        ["subscribe"] => {
+            // a line per change until interrupted
+            let mut stream = client.connect().await?;
+            stream.write_all(b"subscribe\n").await?;
+            stream.flush().await?;
+            let mut out = std::io::stdout().lock();
+            while let Some(reply) = read_reply(&mut stream).await? {
+                writeln!(out, "{}", reply)?;
+                out.flush()?;
+            }
+            Ok(0)
+        },
+        ["summary"] => {
+            let reply = client.expect("summary").await?;
+            if options.json || is_err(&reply) {
+                return Ok(print_reply(&reply));
+            }
+            print!("{}", summary_table(&reply)?);
+            Ok(0)
+        },
+        ["rule", sub, ..] if ["list", "add", "remove", "test", "save"].contains(sub) => {
+            let reply = client.expect(&line).await?;
+            if is_err(&reply) {
+                return Ok(print_reply(&reply));
+            }
+            match *sub {
+                "list" => print_with_jq(&reply, RULE_LIST, options.json)?,
+                "test" => print_with_jq(&reply, RULE_TEST, options.json)?,
+                _ => println!("{}", reply),
+            }
+            Ok(0)
+        },
+        ["rule", ..] => bail!("usage: hyprtagctl rule list|add <name> class=<regex> [title=<regex>] tag=<n> [monitor=<name>] [sticky] [ignore]|remove <name>|test <address|class>|save [--json]"),
+        ["config", "check", path] => {
+            // the daemon runs elsewhere, so it gets an absolute path
+            let path = std::fs::canonicalize(path).with_context(|| format!("cannot read {}", path))?;
+            let report = client.expect(&format!("config check {}", path.display())).await?;
+            if options.json {
+                println!("{}", report);
+            } else {
+                print!("{}", check_report(&report)?);
+            }
+            Ok(if report.contains(r#""ok":true"#) { 0 } else { FAILED })
+        },
+        ["config", "check", ..] => bail!("usage: hyprtagctl config check <config.json>"),
+        ["config"] => {
+            let reply = client.expect("config").await?;
+            match serde_json::from_str::<Value>(&reply) {
+                Ok(config) if !options.json => println!("{}", serde_json::to_string_pretty(&config)?),
+                _ => println!("{}", reply),
+            }
+            Ok(0)
+        },
+        ["diff", path] => {
+            let snapshot = std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path))?;
+            let snapshot: Value = serde_json::from_str(&snapshot).with_context(|| format!("cannot parse {}", path))?;
+            // only the state gets compared, the clients and monitors would push long sessions past the line limit
+            let before = serde_json::json!({ "state": snapshot["state"] });
+            let report = client.expect(&format!("diff {}", before)).await?;
+            print_with_jq(&report, DIFF, options.json)?;
+            Ok(if report.contains(r#""clean":true"#) { 0 } else { FAILED })
+        },
+        ["diff", ..] => bail!("usage: hyprtagctl diff <snapshot.json>"),
+        _ => {
+            let reply = client.expect(&line).await?;
+            // a bare `ok` is what every command without a reply of its own answers
+            if reply == "ok" && !options.json && !has_reply(args[0]) {
+                return Ok(0);
+            }
+            Ok(print_reply(&reply))
+        },
+    }
+}
+
+/// Where the commands `help` describes came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Live,
+    Cached,
+    Bundled,
+}
+
+/// The reply to the line from the daemon, if it's up and answers it soon without an error.
+async fn live_reply(signature: Option<&str>, line: &str) -> Option<String> {
+    let client = Client { socket: protocol::socket_path(signature).ok()?, timeout: Some(LIVE_TIMEOUT) };
+    client.request(line).await.ok().flatten().filter(|reply| !is_err(reply))
+}
+
+/// `$XDG_CACHE_HOME/hyprtag/commands.json`, the commands as the daemon last described them.
+fn cache_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("hyprtag").join("commands.json"))
+}
+
+fn cached_commands() -> Option<Vec<CommandInfo>> {
+    serde_json::from_str(&std::fs::read_to_string(cache_path()?).ok()?).ok()
+}
+
+fn cache_commands(commands: &[CommandInfo]) -> anyhow::Result<()> {
+    let path = cache_path().context("no cache dir")?;
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    std::fs::write(&path, serde_json::to_string(commands)?)?;
+    Ok(())
+}
+
+/// The commands as the running daemon describes them, caching them, or as last cached or built in when it's down.
+async fn commands(signature: Option<&str>) -> (Vec<CommandInfo>, Source) {
+    let live = live_reply(signature, "help").await.and_then(|reply| serde_json::from_str::<Vec<CommandInfo>>(&reply).ok());
+    if let Some(commands) = live {
+        // the cache is only a fallback, help goes on without it
+        let _ = cache_commands(&commands);
+        return (commands, Source::Live);
+    }
+    match cached_commands() {
+        Some(commands) => (commands, Source::Cached),
+        None => (COMMANDS.to_vec(), Source::Bundled),
+    }
+}
+
+/// The built in commands along with those a newer daemon described, which may be sent without hyprtagctl knowing them.
+fn known_commands() -> Vec<CommandInfo> {
+    let mut commands = COMMANDS.to_vec();
+    for cached in cached_commands().into_iter().flatten() {
+        if !commands.iter().any(|c| c.name == cached.name) {
+            commands.push(cached);
+        }
+    }
+    commands
+}
+
+async fn help(signature: Option<&str>, name: Option<&str>, json: bool) -> anyhow::Result<u8> {
+    let (commands, source) = commands(signature).await;
+    let Some(name) = name else {
+        match json {
+            true => println!("{}", serde_json::to_string(&commands)?),
+            false => print!("{}", command_list(&commands, source)),
+        }
+        return Ok(0);
+    };
+    let info = commands.iter().find(|c| c.name == name).ok_or_else(|| UnknownCommand::new(name))?;
+    match json {
+        true => println!("{}", serde_json::to_string(info)?),
+        false => print!("{}", command_help(info)),
+    }
+    Ok(0)
+}
+
+/// The commands with their arguments, one per line.
+fn command_list(commands: &[CommandInfo], source: Source) -> String {
+    let mut s = format!("commands {}:\n", match source {
+        Source::Live => "of the running hyprtag",
+        Source::Cached => "as cached from hyprtag, which isn't running",
+        Source::Bundled => "built into hyprtagctl, hyprtag isn't running",
+    });
+    let width = commands.iter().map(|c| c.usage().chars().count()).max().unwrap_or_default();
+    for info in commands {
+        s += &format!("  {:width$}  {}\n", info.usage(), info.about, width = width);
+    }
+    s += "\nhyprtagctl help COMMAND describes one of them.\n";
+    s
+}
+
+/// The command's usage, what it does and what it needs.
+fn command_help(info: &CommandInfo) -> String {
+    let mut s = format!("usage: hyprtagctl {}\n\n{}\n", info.usage(), info.about);
+    if let Some(feature) = &info.feature {
+        s += &format!("\nNeeds the {} feature.\n", feature);
+    }
+    s
+}
+
+fn completion_script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH_COMPLETION),
+        "fish" => Some(FISH_COMPLETION),
+        _ => None,
+    }
+}
+
+/// The candidates for the last of the words, which is the one being typed.
+async fn complete(words: &[String]) -> Vec<String> {
+    let (current, typed) = match words.split_last() {
+        Some((current, typed)) => (current.as_str(), typed),
+        None => ("", words),
+    };
+    if current.starts_with('-') {
+        return OPTIONS.iter().filter(|o| o.starts_with(current)).map(|o| o.to_string()).collect();
+    }
+    let signature = typed.iter().position(|w| w == "--signature").and_then(|i| typed.get(i + 1));
+    let (commands, _) = commands(signature.map(String::as_str)).await;
+    let Some(arg) = completed_arg(&commands, typed) else {
+        return vec![];
+    };
+    let query = match arg {
+        Arg::Tag | Arg::Tags | Arg::Monitor | Arg::Window => live_reply(signature.map(String::as_str), "query").await
+            .and_then(|reply| serde_json::from_str(&reply).ok()),
+        _ => None,
+    };
+    candidates(&commands, &arg, query.as_ref()).into_iter().filter(|c| c.starts_with(current)).collect()
+}
+
+/// What the word after the typed ones is, options left out.
+fn completed_arg(commands: &[CommandInfo], typed: &[String]) -> Option<Arg> {
+    let mut words = vec![];
+    let mut typed = typed.iter();
+    while let Some(word) = typed.next() {
+        if VALUE_OPTIONS.contains(&word.as_str()) {
+            // the option's value is being typed
+            typed.next()?;
+        } else if !word.starts_with('-') {
+            words.push(word.as_str());
+        }
+    }
+    arg_at(commands, &words)
+}
+
+/// The argument following the command line `words`.
+fn arg_at(commands: &[CommandInfo], words: &[&str]) -> Option<Arg> {
+    let Some((cmd, args)) = words.split_first() else {
+        return Some(Arg::Command);
+    };
+    match *cmd {
+        "completions" => return args.is_empty().then(|| Arg::Words("bash|fish".into())),
+        // only the command's name
+        "help" => return args.is_empty().then_some(Arg::Command),
+        _ => {},
+    }
+    let info = commands.iter().find(|c| c.name == *cmd)?;
+    // a wrapped command completes as a command line of its own
+    if let Some(i) = info.args.iter().position(|a| *a == Arg::Command) {
+        if args.len() >= i {
+            return arg_at(commands, &args[i..]);
+        }
+    }
+    match info.args.get(args.len()) {
+        Some(arg) => Some(arg.clone()),
+        // like `reorder <tag> <address>...`
+        None if info.syntax.ends_with("...") => info.args.last().cloned(),
+        None => None,
+    }
+}
+
+/// What may be typed for the argument, with the tags, monitors and windows of the daemon's `query` reply.
+fn candidates(commands: &[CommandInfo], arg: &Arg, query: Option<&Value>) -> Vec<String> {
+    let query = query.unwrap_or(&Value::Null);
+    let monitors = query["monitors"].as_array().map(Vec::as_slice).unwrap_or_default();
+    match arg {
+        Arg::Command => commands.iter().map(|c| c.name.to_string()).chain(CLIENT_COMMANDS.iter().map(|c| c.to_string())).collect(),
+        Arg::Words(words) => words.split('|').map(str::to_string).collect(),
+        Arg::Tag | Arg::Tags => {
+            let active = monitors.iter()
+                .find(|m| m["id"] == query["active_monitor"] || m["name"] == query["active_monitor"])
+                .or(monitors.first());
+            let mut tags = vec![];
+            for tag in active.and_then(|m| m["tags"].as_array()).into_iter().flatten() {
+                let number = tag["tag"].to_string();
+                match tag["name"].as_str() {
+                    Some(name) if !name.is_empty() && name != number => tags.extend([number, name.to_string()]),
+                    _ => tags.push(number),
+                }
+            }
+            tags
+        },
+        Arg::Monitor => monitors.iter().filter_map(|m| m["name"].as_str().map(str::to_string)).collect(),
+        Arg::Window => query["windows"].as_object().into_iter().flat_map(|w| w.keys().cloned()).collect(),
+        Arg::Text => vec![],
+    }
+}
+
+/// Commands whose reply is worth printing even when it's just `ok`.
+fn has_reply(cmd: &str) -> bool {
+    ["snapshot", "query", "stale", "sync", "badges", "pager", "throw", "conflicts", "txn"].contains(&cmd)
+}
+
+fn is_err(reply: &str) -> bool {
+    reply.starts_with("err:")
+}
+
+/// Prints the reply, an error to stderr, with the exit code for it.
+fn print_reply(reply: &str) -> u8 {
+    if is_err(reply) {
+        eprintln!("{}", reply);
+        return FAILED;
+    }
+    println!("{}", reply);
+    0
+}
+
+/// Prints the JSON reply as jq's `program` renders it, or as it is with `--json` or without jq.
+fn print_with_jq(reply: &str, program: &str, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", reply);
+        return Ok(());
+    }
+    let mut jq = match Command::new("jq").arg("-r").arg(program).stdin(Stdio::piped()).spawn() {
+        Ok(jq) => jq,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            println!("{}", reply);
+            return Ok(());
+        },
+        Err(err) => return Err(err).context("cannot run jq"),
+    };
+    writeln!(jq.stdin.take().unwrap(), "{}", reply)?;
+    jq.wait()?;
+    Ok(())
+}
+
+/// The summary as a table, the names padded by their width on a terminal.
+fn summary_table(reply: &str) -> anyhow::Result<String> {
+    let summary: Value = serde_json::from_str(reply).with_context(|| format!("unexpected reply: {}", reply))?;
+    let row = format::parse_template("{name|trunc:16|pad:16}  {time|pad:7}  {windows} windows  {occupancy} open")?;
+    let mut table = format!("since {}\n", local_time(summary["since"].as_i64().unwrap_or_default()));
+    for tag in summary["tags"].as_array().into_iter().flatten() {
+        let mut tag = tag.clone();
+        let secs = tag["foreground_secs"].as_u64().unwrap_or_default();
+        tag["time"] = format!("{}h{}m", secs / 3600, secs % 3600 / 60).into();
+        table += &row.render(&tag);
+        table.push('\n');
+    }
+    Ok(table)
+}
+
+/// `YYYY-MM-DD HH:MM` in the local time zone.
+fn local_time(secs: i64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the call, localtime_r keeps neither
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min)
+}
+
+/// The `config check` report: its errors with the line of a parse error, or `ok`.
+fn check_report(report: &str) -> anyhow::Result<String> {
+    let report: Value = serde_json::from_str(report).with_context(|| format!("unexpected reply: {}", report))?;
+    let mut s = String::new();
+    for err in report["errors"].as_array().into_iter().flatten() {
+        s += &format!("error: {}\n", err.as_str().unwrap_or_default());
+    }
+    if let Some(line) = report["line"].as_u64() {
+        s += &format!("  line {}: {}\n", line, report["context"].as_str().unwrap_or_default());
+    }
+    if report["ok"] == true {
+        s += "ok\n";
+    }
+    Ok(s)
+}
+
+const BASH_COMPLETION: &str = r#"_hyprtagctl() {
+    local IFS=$'\n'
+    COMPREPLY=($(compgen -W "$(hyprtagctl complete "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)" -- "${COMP_WORDS[COMP_CWORD]}"))
+}
+complete -F _hyprtagctl hyprtagctl
+"#;
+
+const FISH_COMPLETION: &str = "complete -c hyprtagctl -f -a '(hyprtagctl complete (commandline -opc)[2..] (commandline -ct))'\n";
+
+const PREVIEW: &str = r#"
+    (.dispatches[] | "hyprctl       \(.)"),
+    (.visible_tags[] | "visible tags  \(.monitor): \(.before) -> \(.after)"),
+    (.windows[] | "window        \(.addr): \(.before // "-" | if type == "object" then "\(.monitor) tag \(.tag)" else . end) -> \(.after // "-" | if type == "object" then "\(.monitor) tag \(.tag)" else . end)")"#;
+
+const OUTPUTS: &str = r#"
+    ["INDEX", "NAME", "ID", "MANAGED", "WORKSPACE", "HIDDEN", "VISIBLE", "TAGS", "DESCRIPTION"],
+    (.[] | [.index, .name, .id, .managed, .visible_workspace, "\(.hidden_workspace_base)+N", .visible_tags, .tag_count, .description])
+    | @tsv"#;
+
+const EXPLAIN: &str = r#"
+    "window \(.window): \(.current.monitor) tag \(.current.tag)",
+    (.history | reverse[] | "  \(.at | strflocaltime("%H:%M:%S"))  \(.from // "-" | if type == "object" then "\(.monitor) tag \(.tag)" else . end) -> \(.monitor) tag \(.tag)  by \(.source.kind) \(.source.command // .source.event // (if .source.class then "\(.source.class) \(.source.tag)" else "" end))\(if .note then " (\(.note))" else "" end)")"#;
+
+const RULE_LIST: &str = r#"
+    .[] | "\(.name)\t\(.origin)\(if .shadowed then " (replaced)" else "" end)\tclass=\(.class)\(if .title then " title=\(.title)" else "" end) tag=\(.tag)\(if .monitor then " monitor=\(.monitor)" else "" end)\(if .sticky then " sticky" else "" end)\(if .ignore then " ignore" else "" end)""#;
+
+const RULE_TEST: &str = r#"
+    "\(.class) \(if .title != "" then "\"\(.title)\" " else "" end)-> \(.matched // "no rule")",
+    (.checked[] | "  \(if .matches then "+" else "-" end) \(.name) (\(.origin)): \(.why)")"#;
+
+const DIFF: &str = r#"
+    (.visible_tags[] | "visible tags  \(.monitor): \(.before) -> \(.after)"),
+    (.windows[] | "window        \(.addr): \(.before // "-" | if type == "object" then "\(.monitor) tag \(.tag)" else . end) -> \(.after // "-" | if type == "object" then "\(.monitor) tag \(.tag)" else . end)"),
+    (.misplaced[] | "misplaced     \(.addr): on workspace \(.actual_workspace), expected \(.expected_workspace)"),
+    (.untracked[] | "untracked     \(.)"),
+    (.dead[] | "dead          \(.)"),
+    (if .clean then "clean" else empty end)"#;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyprtag::protocol::{Arg, CommandInfo, COMMANDS};
+
+    use super::{arg_at, candidates, check_report, command_help, command_list, completed_arg, summary_table, Options, Source};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    fn parse_args(args: &[String]) -> anyhow::Result<Options> {
+        super::parse_args(args, COMMANDS)
+    }
+
+    #[test]
+    fn client_args() {
+        assert_eq!(parse_args(&args("show 3")).unwrap(), Options {
+            signature: None,
+            timeout: Some(Duration::from_secs(5)),
+            retry: false,
+            preview: false,
+            json: false,
+            help: false,
+            format: None,
+            each: None,
+            command: args("show 3"),
+        });
+        assert!(parse_args(&args("move --help")).unwrap().help);
+        let options = parse_args(&args("--signature abc --timeout 0.5 --retry move 2")).unwrap();
+        assert_eq!(options.signature.as_deref(), Some("abc"));
+        assert_eq!(options.timeout, Some(Duration::from_millis(500)));
+        assert!(options.retry);
+        assert_eq!(options.command, args("move 2"));
+        // options may follow the command
+        let options = parse_args(&args("query --format {active_monitor} --wait")).unwrap();
+        assert_eq!((options.format.as_deref(), options.timeout), (Some("{active_monitor}"), None));
+        assert_eq!(parse_args(&args("rule list --format {name} --each .")).unwrap().each.as_deref(), Some("."));
+
+        let err = |s: &str| parse_args(&args(s)).unwrap_err().to_string();
+        assert_eq!(err("shwo 3"), "unknown command 'shwo', did you mean 'show'?");
+        assert_eq!(err("--json"), "no command given");
+        assert_eq!(err("show 3 --timeout"), "--timeout requires a value");
+        assert_eq!(err("show 3 --timeout -1"), "invalid --timeout");
+        assert_eq!(err("show 3 --format {x}"), "--format works with query, outputs, pager, badges, snapshot, summary, explain, rule list and subscribe");
+        assert_eq!(err("rule add x --format {x}"), "--format works with query, outputs, pager, badges, snapshot, summary, explain, rule list and subscribe");
+        assert_eq!(err("query --each monitors"), "--each goes with --format");
+        assert_eq!(err("--retry --preview show 2"), "--retry and --preview don't go together");
+
+        // commands a newer daemon described
+        let mut commands = COMMANDS.to_vec();
+        commands.push(CommandInfo { name: "swap".into(), ..COMMANDS[0].clone() });
+        assert_eq!(super::parse_args(&args("swap 3"), &commands).unwrap().command, args("swap 3"));
+    }
+
+    #[test]
+    fn help_text() {
+        let move_ = COMMANDS.iter().find(|c| c.name == "move").unwrap();
+        assert_eq!(command_help(move_), format!("usage: hyprtagctl move <tag> [--force]\n\n{}\n", move_.about));
+        let query = COMMANDS.iter().find(|c| c.name == "query").unwrap();
+        assert!(command_help(query).ends_with(".\n\nNeeds the json-proto feature.\n"));
+
+        let list = command_list(&COMMANDS[..2], Source::Cached);
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines[0], "commands as cached from hyprtag, which isn't running:");
+        assert_eq!(lines[1], format!("  move <tag> [--force]  {}", move_.about));
+        assert_eq!(lines[2], format!("  move_show <tag>       {}", COMMANDS[1].about));
+    }
+
+    #[test]
+    fn completions() {
+        let arg = |s: &str| completed_arg(COMMANDS, &args(s));
+        assert_eq!(arg(""), Some(Arg::Command));
+        assert_eq!(arg("--timeout 2 --json"), Some(Arg::Command));
+        assert_eq!(arg("--timeout"), None);
+        assert_eq!(arg("move"), Some(Arg::Tag));
+        assert_eq!(arg("move 3"), Some(Arg::Words("--force".into())));
+        assert_eq!(arg("move 3 --force"), Some(Arg::Words("--force".into())));
+        assert_eq!(arg("move 3 --force x"), None);
+        assert_eq!(arg("place"), Some(Arg::Monitor));
+        assert_eq!(arg("reorder 2 aa bb"), Some(Arg::Window));
+        // into wrapped commands
+        assert_eq!(arg("sync"), Some(Arg::Command));
+        assert_eq!(arg("sync show_on"), Some(Arg::Monitor));
+        assert_eq!(arg("preview sync toggle 2"), Some(Arg::Words("focus".into())));
+        assert_eq!(arg("help"), Some(Arg::Command));
+        assert_eq!(arg("help move"), None);
+        assert_eq!(arg("completions"), Some(Arg::Words("bash|fish".into())));
+        assert_eq!(arg_at(COMMANDS, &["bogus"]), None);
+
+        let names = candidates(COMMANDS, &Arg::Command, None);
+        assert!(COMMANDS.iter().all(|c| names.contains(&c.name.to_string())));
+        assert!(names.contains(&"completions".to_string()));
+        assert_eq!(candidates(COMMANDS, &Arg::Words("on|off".into()), None), ["on", "off"]);
+        assert_eq!(candidates(COMMANDS, &Arg::Tag, None), Vec::<String>::new());
+
+        let query = serde_json::json!({
+            "active_monitor": "DP-2",
+            "monitors": [
+                {"id": "DP-1", "name": "DP-1", "tags": [{"tag": 1, "name": "1"}]},
+                {"id": "DP-2", "name": "DP-2", "tags": [{"tag": 1, "name": "web"}, {"tag": 2, "name": "2"}]},
+            ],
+            "windows": {"aa": {}, "bb": {}},
+        });
+        assert_eq!(candidates(COMMANDS, &Arg::Tag, Some(&query)), ["1", "web", "2"]);
+        assert_eq!(candidates(COMMANDS, &Arg::Monitor, Some(&query)), ["DP-1", "DP-2"]);
+        assert_eq!(candidates(COMMANDS, &Arg::Window, Some(&query)), ["aa", "bb"]);
+        assert_eq!(candidates(COMMANDS, &Arg::Text, Some(&query)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rendered_replies() {
+        let summary = r#"{"since": 0, "tags": [
+            {"tag": 1, "name": "web", "foreground_secs": 3720, "windows": 2, "occupancy": 1},
+            {"tag": 2, "name": "仕事", "foreground_secs": 60, "windows": 10, "occupancy": 3}
+        ]}"#;
+        let table = summary_table(summary).unwrap();
+        let rows: Vec<&str> = table.lines().skip(1).collect();
+        assert_eq!(rows, [
+            "web               1h2m     2 windows  1 open",
+            "仕事              0h1m     10 windows  3 open",
+        ]);
+        assert!(table.starts_with("since 19"));
+
+        assert_eq!(check_report(r#"{"ok": true, "errors": []}"#).unwrap(), "ok\n");
+        assert_eq!(
+            check_report(r#"{"ok": false, "errors": ["expected `,`"], "line": 3, "context": "  \"a\": 1"}"#).unwrap(),
+            "error: expected `,`\n  line 3:   \"a\": 1\n",
+        );
+    }
+}
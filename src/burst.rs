@@ -0,0 +1,129 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{config::BurstConfig, ctrl::Ctrl, monitor::Changes, state::WindowAddr};
+
+/// Windows opening in quick succession, like a browser restoring its session. While a burst is on, the
+/// dispatches of new windows are held back and focus changes only remember the last window, so everything
+/// goes out as one batch once the windows stop coming.
+#[derive(Debug, Default)]
+pub struct OpenBurst {
+    /// Times of the recent openwindow events.
+    opens: VecDeque<Instant>,
+    /// When the burst started, `None` outside of one.
+    started: Option<Instant>,
+    held: Vec<Changes>,
+    focus: Option<WindowAddr>,
+    end: Option<JoinHandle<()>>,
+}
+
+impl OpenBurst {
+    /// Counts an openwindow event, returning whether its changes are to be held.
+    /// A burst starts once more than `windows` opened within `within_ms`.
+    pub fn window_opened(&mut self, now: Instant, config: &BurstConfig) -> bool {
+        let within = Duration::from_millis(config.within_ms);
+        self.opens.retain(|t| now.duration_since(*t) < within);
+        self.opens.push_back(now);
+        if self.started.is_none() && self.opens.len() > config.windows {
+            tracing::debug!(windows = self.opens.len(), "window burst started");
+            self.started = Some(now);
+        }
+        self.started.is_some()
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn hold(&mut self, changes: Changes) {
+        self.held.push(changes);
+    }
+
+    /// Remembers the focused window, replacing the one focused before it during the burst.
+    pub fn hold_focus(&mut self, window: WindowAddr) {
+        self.focus = Some(window);
+    }
+
+    /// When the burst ends: `within_ms` after the last window opened, and at the latest `flush_timeout_ms`
+    /// after it started.
+    pub fn deadline(&self, config: &BurstConfig) -> Option<Instant> {
+        let started = self.started?;
+        let quiet = *self.opens.back()? + Duration::from_millis(config.within_ms);
+        Some(quiet.min(started + Duration::from_millis(config.flush_timeout_ms)))
+    }
+
+    /// Sends `Ctrl::BurstEnded` at the deadline, replacing the previously scheduled end.
+    pub(crate) fn schedule_end(&mut self, config: &BurstConfig, tx: mpsc::Sender<Ctrl>) {
+        let deadline = match self.deadline(config) {
+            Some(d) => d,
+            None => return,
+        };
+        if let Some(task) = self.end.take() {
+            task.abort();
+        }
+        self.end = Some(tokio::spawn(async move {
+            tokio::time::sleep_until(deadline.into()).await;
+            if let Err(err) = tx.send(Ctrl::BurstEnded).await {
+                tracing::error!(%err, "failed to send Ctrl::BurstEnded");
+            }
+        }));
+    }
+
+    /// Ends the burst, returning the held changes and the last focused window.
+    pub fn take(&mut self) -> (Vec<Changes>, Option<WindowAddr>) {
+        if let Some(task) = self.end.take() {
+            task.abort();
+        }
+        self.started = None;
+        self.opens.clear();
+        (std::mem::take(&mut self.held), self.focus.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{config::BurstConfig, monitor::{ChangeSource, Changes}, state::{Changes as MonitorChanges, DEFAULT_TAG_COUNT}};
+
+    use super::OpenBurst;
+
+    fn config() -> BurstConfig {
+        BurstConfig { windows: 2, within_ms: 200, flush_timeout_ms: 1000 }
+    }
+
+    #[test]
+    fn burst_detection() {
+        let config = config();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut burst = OpenBurst::default();
+
+        // windows opened far enough apart never start one
+        assert!(!burst.window_opened(at(0), &config));
+        assert!(!burst.window_opened(at(150), &config));
+        assert!(!burst.window_opened(at(400), &config));
+        assert!(!burst.is_on());
+
+        assert!(!burst.window_opened(at(450), &config));
+        assert!(burst.window_opened(at(500), &config));
+        assert_eq!(burst.deadline(&config), Some(at(700)));
+        burst.hold(Changes { active_monitor_index: 0, tag_count: DEFAULT_TAG_COUNT, changes: MonitorChanges::default(), source: ChangeSource::default() });
+        burst.hold_focus("aa".parse().unwrap());
+        burst.hold_focus("bb".parse().unwrap());
+
+        // it keeps going while windows keep coming, but not past the flush timeout
+        for ms in (600..1600).step_by(100) {
+            assert!(burst.window_opened(at(ms), &config));
+        }
+        assert_eq!(burst.deadline(&config), Some(at(1500)));
+
+        let (held, focus) = burst.take();
+        assert_eq!(held.len(), 1);
+        assert_eq!(focus, Some("bb".parse().unwrap()));
+        assert!(!burst.is_on());
+        assert_eq!(burst.deadline(&config), None);
+        assert!(!burst.window_opened(at(1600), &config));
+    }
+}
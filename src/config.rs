@@ -0,0 +1,861 @@
+use std::{collections::{BTreeMap, HashMap}, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{hooks::TagHook, rules::{self, CompiledRule, WindowRule}, state::{ActiveTagPolicy, DEFAULT_TAG_COUNT, MAX_TAGS}, summary::SummarySettings, text};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// File the config was loaded from, if any.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+    /// Delay before a focus change is reflected in the active window. 0 disables debouncing.
+    pub focus_debounce_ms: u64,
+    /// Hooks run when a tag becomes visible, and reverted when it gets hidden again.
+    pub on_show: Vec<TagHook>,
+    /// Where windows go when they first appear: `"active"`, a tag number or a name from `tag_names`.
+    pub new_window_tag: NewWindowTag,
+    /// Tags windows launched by `activate` are placed on, keyed by window class.
+    pub app_tags: HashMap<String, u8>,
+    /// Where windows go when they open, by class and title, the first matching rule winning. Rules in
+    /// `rules.d/*.json` next to the config follow these.
+    pub rules: Vec<WindowRule>,
+    /// Tags of each monitor, 1 to 64. 32 when unset. Read at startup only, as hidden workspaces are numbered by it.
+    pub tag_count: Option<u8>,
+    /// Names of tags, like in the generated waybar config. Tags without one go by their number. Kept in NFC, the
+    /// form commands are matched in.
+    #[serde(deserialize_with = "nfc_names")]
+    pub tag_names: BTreeMap<u8, String>,
+    /// Per tag settings, keyed by tag number.
+    pub tags: BTreeMap<u8, TagConfig>,
+    /// Groups of tags in the order bars should show them, each with its tags in order. Tags in no group follow
+    /// by number.
+    pub tag_groups: Vec<TagGroup>,
+    /// How XWayland windows are treated on tag changes: `"normal"`, `"sticky"` or `"pin-tag"`.
+    pub xwayland_policy: XwaylandPolicy,
+    /// Which visible tag is the active one, the tag new windows and hooks go by, when several are visible:
+    /// `"focused-window"`, `"lowest-visible"` or `"last-shown"`.
+    pub active_tag_policy: ActiveTagPolicy,
+    /// Moves the cursor to the focused window after a tag switch, so focus_follows_mouse doesn't undo the focus.
+    pub warp_cursor: bool,
+    /// New windows go to the tag of the window whose process launched them, like a terminal they were started from.
+    pub inherit_tag_from_parent: bool,
+    /// `show` of the only visible tag goes back to the previous view, like dwm's view.
+    pub show_toggles_back: bool,
+    /// Hyprland submaps in which ctrl commands changing tags or windows are rejected.
+    pub suppress_in_submaps: Vec<String>,
+    /// What happens when closing a window leaves nothing visible: `"stay"`, `"restore_prev"` or `"nearest_occupied"`.
+    pub on_tag_emptied: OnTagEmptied,
+    /// What snapshots hide about windows on monitors in presentation mode.
+    pub presentation: PresentationConfig,
+    /// When windows opening in quick succession get their dispatches sent as one batch.
+    pub open_burst: BurstConfig,
+    /// How the dispatches placing the windows found at startup are spread out.
+    pub adoption: AdoptionConfig,
+    /// A removed monitor added back within this many milliseconds, as turning displays off and on does on some
+    /// hardware, keeps its windows and tags. 0 removes monitors right away.
+    pub monitor_grace_ms: u64,
+    /// The monitor `show` and `toggle` act on: `"focused"` or `"cursor"`, the one under the mouse. A command
+    /// suffixed with `@focused` or `@cursor` picks its own.
+    pub command_target: CommandTarget,
+    /// When Hyprland restarts under a new instance signature, move over to it: serve the ctrl socket in its session
+    /// directory and start over with its windows. Otherwise hyprtag exits once the event stream is gone.
+    pub follow_new_instance: bool,
+    /// Who may send commands on the ctrl socket, beyond the permissions of its directory.
+    pub ctrl_auth: CtrlAuthConfig,
+    /// Per monitor settings, keyed by monitor name.
+    pub monitors: HashMap<String, MonitorConfig>,
+    /// How `summary` counts the day.
+    pub summary: SummaryConfig,
+    /// Per setup overrides, selected by the connected monitors.
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagConfig {
+    /// Left out of cycling, `move_to_empty` and the generated bar, like a utility tag rules park windows on.
+    /// Commands naming the tag still work.
+    pub hidden: bool,
+    /// hyprctl commands run when the tag becomes visible, before its windows are moved in, like
+    /// `keyword general:gaps_in 0`.
+    pub hyprctl_on_show: Vec<String>,
+    /// hyprctl commands run once the tag is hidden, after its windows were moved out. Without any, the options
+    /// `hyprctl_on_show` set with `keyword` go back to what they were before.
+    pub hyprctl_on_hide: Vec<String>,
+    /// Icon bars show for the tag, like a Nerd Font glyph.
+    pub icon: Option<String>,
+    /// Commands run the first time the tag is shown empty in a session, like `foot --working-directory ~/proj`.
+    /// The windows opening next are placed on the tag. `autostart_reset` lets them run again.
+    pub autostart: Vec<String>,
+    /// The monitor the tag lives on, by connector name: showing it, moving windows to it and rules placing windows
+    /// on it act there whichever monitor is focused. The focused monitor stands in while it's disconnected.
+    pub monitor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagGroup {
+    pub name: String,
+    pub tags: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MonitorConfig {
+    /// `false` leaves the monitor alone: no tags, no workspace moves, and ctrl commands targeting it are rejected.
+    pub managed: bool,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self { managed: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresentationConfig {
+    /// Window titles are replaced with `redacted`.
+    pub redact_titles: bool,
+    /// Window classes are replaced with `redacted`.
+    pub redact_classes: bool,
+}
+
+impl Default for PresentationConfig {
+    fn default() -> Self {
+        Self { redact_titles: true, redact_classes: true }
+    }
+}
+
+/// Checks of the credentials of processes connecting to the ctrl socket. Once enabled, the user running hyprtag and
+/// the listed users and groups may run any command, the read-only ones only commands which change nothing like
+/// `query` and `subscribe`, and anyone else is turned away. Groups are matched against the primary group of the
+/// connecting process.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CtrlAuthConfig {
+    pub enabled: bool,
+    pub allow_uids: Vec<u32>,
+    pub allow_gids: Vec<u32>,
+    pub read_only_uids: Vec<u32>,
+    pub read_only_gids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BurstConfig {
+    /// A burst starts once more than this many windows opened within `within_ms`.
+    pub windows: usize,
+    /// It ends when no window opened for this long.
+    pub within_ms: u64,
+    /// Held dispatches are sent after this long even if windows keep coming.
+    pub flush_timeout_ms: u64,
+}
+
+impl Default for BurstConfig {
+    fn default() -> Self {
+        Self { windows: 2, within_ms: 250, flush_timeout_ms: 1000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdoptionConfig {
+    /// Windows moved per batch, visible tags first. 0 moves them all at once.
+    pub windows_per_batch: usize,
+    /// Wait between batches, so Hyprland gets to render in between.
+    pub batch_delay_ms: u64,
+}
+
+impl Default for AdoptionConfig {
+    fn default() -> Self {
+        Self { windows_per_batch: 20, batch_delay_ms: 50 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SummaryConfig {
+    /// Local time the totals start over at, as `HH:MM`.
+    pub reset_at: String,
+    /// Longest a single focus counts, so a suspend with a window focused doesn't count as hours on its tag.
+    pub max_interval_secs: u64,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self { reset_at: "04:00".to_string(), max_interval_secs: 600 }
+    }
+}
+
+impl SummaryConfig {
+    /// `reset_at` in seconds after midnight, `None` when it isn't a valid time.
+    fn reset_at_secs(&self) -> Option<u32> {
+        let (hours, minutes) = self.reset_at.split_once(':')?;
+        match (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?) {
+            (hours @ 0..=23, minutes @ 0..=59) => Some(hours * 3600 + minutes * 60),
+            _ => None,
+        }
+    }
+
+    /// The settings, with the default reset time when `reset_at` is invalid.
+    pub fn settings(&self) -> SummarySettings {
+        SummarySettings {
+            reset_at: self.reset_at_secs().unwrap_or(SummarySettings::default().reset_at),
+            max_interval: self.max_interval_secs,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Monitor names or descriptions which all need to be connected for this profile to apply.
+    pub monitors: Vec<String>,
+    pub focus_debounce_ms: Option<u64>,
+    pub on_show: Option<Vec<TagHook>>,
+    pub new_window_tag: Option<NewWindowTag>,
+    pub xwayland_policy: Option<XwaylandPolicy>,
+    pub active_tag_policy: Option<ActiveTagPolicy>,
+    pub warp_cursor: Option<bool>,
+    pub show_toggles_back: Option<bool>,
+    pub suppress_in_submaps: Option<Vec<String>>,
+    pub on_tag_emptied: Option<OnTagEmptied>,
+    pub presentation: Option<PresentationConfig>,
+    pub open_burst: Option<BurstConfig>,
+    pub monitor_grace_ms: Option<u64>,
+    pub command_target: Option<CommandTarget>,
+    /// Tags shown on each monitor when the profile becomes active, keyed by monitor name.
+    pub default_tags: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "serde_json::Value", into = "serde_json::Value")]
+pub enum NewWindowTag {
+    #[default]
+    Active,
+    Tag(u8),
+    /// A tag by its name in `tag_names`, resolved by `Config::resolved_new_window_tag`.
+    Named(String),
+}
+
+/// Some XWayland apps lose popups and focus when moved between workspaces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum XwaylandPolicy {
+    /// Treated like any other window.
+    #[default]
+    Normal,
+    /// Always visible, hiding their tag leaves them where they are.
+    Sticky,
+    /// Stay on the tag they first appeared on, moves need `--force`.
+    PinTag,
+}
+
+/// Which monitor tag commands act on, which differ with focus_follows_mouse off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandTarget {
+    /// The monitor with keyboard focus.
+    #[default]
+    Focused,
+    /// The monitor under the cursor, or the focused one when it can't be found.
+    Cursor,
+}
+
+/// Where the view goes when the last window of the visible tags closes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnTagEmptied {
+    /// The empty tag stays visible.
+    #[default]
+    Stay,
+    /// Back to the previous view, like `restore`.
+    RestorePrev,
+    /// The tag with windows closest to the emptied one, the lower one on a tie.
+    NearestOccupied,
+}
+
+impl TryFrom<serde_json::Value> for NewWindowTag {
+    type Error = String;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        match value {
+            serde_json::Value::String(s) if s == "active" => Ok(Self::Active),
+            serde_json::Value::String(s) if !s.is_empty() => Ok(Self::Named(s)),
+            serde_json::Value::Number(n) => match n.as_u64() {
+                Some(tag) if (1..=MAX_TAGS as u64).contains(&tag) => Ok(Self::Tag(tag as u8)),
+                _ => Err(format!("new_window_tag out of range: {}", n)),
+            },
+            v => Err(format!("invalid new_window_tag: {}", v)),
+        }
+    }
+}
+
+impl From<NewWindowTag> for serde_json::Value {
+    fn from(value: NewWindowTag) -> Self {
+        match value {
+            NewWindowTag::Active => "active".into(),
+            NewWindowTag::Tag(tag) => tag.into(),
+            NewWindowTag::Named(name) => name.into(),
+        }
+    }
+}
+
+/// Result of `config check`.
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    pub ok: bool,
+    /// The parse error, or the problems of a config which parsed.
+    pub errors: Vec<String>,
+    /// Where the parse error is, with the text of that line.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub context: Option<String>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        if !path.exists() {
+            tracing::debug!(?path, "no config file, using defaults");
+            return Ok(Self::default());
+        }
+
+        let s = std::fs::read_to_string(&path)?;
+        let mut config = Self::parse(&s)?;
+        if let Some(dir) = path.parent() {
+            config.rules.extend(drop_in_rules(dir));
+        }
+        for problem in config.problems() {
+            tracing::warn!(?path, problem, "config problem");
+        }
+        tracing::info!(?path, ?config, "config loaded");
+        config.source = Some(path);
+
+        Ok(config)
+    }
+
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Values which parse but can't work, like tags out of range.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !(1..=MAX_TAGS).contains(&self.tag_count()) {
+            problems.push(format!("tag_count: {} is out of range 1-{}", self.tag_count(), MAX_TAGS));
+        }
+        let max = self.tag_count().clamp(1, MAX_TAGS);
+        let mut check_tag = |what: String, tag: u8| {
+            if !(1..=max).contains(&tag) {
+                problems.push(format!("{}: tag {} is out of range 1-{}", what, tag, max));
+            }
+        };
+
+        if let NewWindowTag::Tag(tag) = self.new_window_tag {
+            check_tag("new_window_tag".to_string(), tag);
+        }
+
+        for (class, tag) in self.app_tags.iter() {
+            check_tag(format!("app_tags.{}", class), *tag);
+        }
+        for tag in self.tag_names.keys() {
+            check_tag("tag_names".to_string(), *tag);
+        }
+        for tag in self.tags.keys() {
+            check_tag("tags".to_string(), *tag);
+        }
+        for hook in self.on_show.iter() {
+            check_tag("on_show".to_string(), hook.tag);
+        }
+        for group in self.tag_groups.iter() {
+            for tag in group.tags.iter() {
+                check_tag(format!("tag_groups.{}", group.name), *tag);
+            }
+        }
+        for (name, profile) in self.profiles.iter() {
+            for hook in profile.on_show.iter().flatten() {
+                check_tag(format!("profiles.{}.on_show", name), hook.tag);
+            }
+            for (monitor, tags) in profile.default_tags.iter() {
+                for tag in tags {
+                    check_tag(format!("profiles.{}.default_tags.{}", name, monitor), *tag);
+                }
+            }
+        }
+
+        for rule in self.rules.iter() {
+            check_tag(format!("rules.{}", rule.name), rule.tag);
+        }
+
+        for (i, (tag, name)) in self.tag_names.iter().enumerate() {
+            // commands take tags by number or by name
+            if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || name.contains(char::is_whitespace) {
+                problems.push(format!("tag_names: name {:?} of tag {} isn't usable in commands, it must not be empty, start with a digit or have spaces", name, tag));
+            }
+            if !name.is_empty() && (name.contains(char::is_control) || text::width(name) == 0) {
+                problems.push(format!("tag_names: name {:?} of tag {} can't be shown, it has control characters or nothing visible", name, tag));
+            }
+            if self.tag_names.values().take(i).any(|n| text::same_name(n, name)) {
+                problems.push(format!("tag_names: {} names more than one tag", name));
+            }
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Err(err) = CompiledRule::new(rule.clone()) {
+                problems.push(format!("rules: {}", err));
+            }
+            if self.rules[..i].iter().any(|r| r.name == rule.name) {
+                problems.push(format!("rules: rule {} is listed twice", rule.name));
+            }
+        }
+
+        if let NewWindowTag::Named(name) = &self.new_window_tag {
+            if self.tag_by_name(name).is_none() {
+                problems.push(format!("new_window_tag: no tag is named {}", name));
+            }
+        }
+
+        let mut grouped: BTreeMap<u8, &str> = BTreeMap::new();
+        for (i, group) in self.tag_groups.iter().enumerate() {
+            if self.tag_groups[..i].iter().any(|g| g.name == group.name) {
+                problems.push(format!("tag_groups: group {} is listed twice", group.name));
+            }
+            for tag in group.tags.iter() {
+                if let Some(other) = grouped.insert(*tag, &group.name) {
+                    problems.push(format!("tag_groups.{}: tag {} is already in group {}", group.name, tag, other));
+                }
+            }
+        }
+
+        if self.summary.reset_at_secs().is_none() {
+            problems.push(format!("summary.reset_at: {} is not a HH:MM time", self.summary.reset_at));
+        }
+
+        #[cfg(not(feature = "hooks"))]
+        if !self.on_show.is_empty() || self.profiles.values().any(|p| p.on_show.as_ref().is_some_and(|h| !h.is_empty())) {
+            problems.push("on_show: hooks are not compiled in".to_string());
+        }
+        #[cfg(not(feature = "hooks"))]
+        if self.tags.values().any(|t| !t.hyprctl_on_show.is_empty() || !t.hyprctl_on_hide.is_empty()) {
+            problems.push("tags: hyprctl_on_show and hyprctl_on_hide need hooks, which are not compiled in".to_string());
+        }
+
+        problems.sort();
+        problems
+    }
+
+    /// Validates a config without applying it.
+    pub fn check(s: &str) -> CheckReport {
+        let mut report = CheckReport::default();
+        match serde_json::from_str::<Self>(s) {
+            Ok(config) => report.errors = config.problems(),
+            Err(err) => {
+                if err.line() > 0 {
+                    report.line = Some(err.line());
+                    report.column = Some(err.column());
+                    report.context = s.lines().nth(err.line() - 1).map(|l| l.to_string());
+                }
+                report.errors.push(err.to_string());
+            },
+        }
+        report.ok = report.errors.is_empty();
+        report
+    }
+
+    pub fn check_file(path: &Path) -> CheckReport {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Self::check(&s),
+            Err(err) => CheckReport {
+                errors: vec![format!("{}: {}", path.display(), err)],
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Where `rule save` writes the runtime rules, in `rules.d` next to the config file.
+    pub fn runtime_rules_path(&self) -> Option<PathBuf> {
+        let path = self.source.clone().or_else(config_path)?;
+        Some(rules::runtime_rules_path(path.parent()?))
+    }
+
+    /// Names of the monitors configured with `managed: false`.
+    pub fn unmanaged_monitors(&self) -> Vec<String> {
+        self.monitors.iter().filter(|(_, m)| !m.managed).map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Tags pinned to a monitor, with its name.
+    pub fn tag_monitors(&self) -> BTreeMap<u8, String> {
+        self.tags.iter().filter_map(|(tag, t)| Some((*tag, t.monitor.clone()?))).collect()
+    }
+
+    /// Tags of each monitor.
+    pub fn tag_count(&self) -> u8 {
+        self.tag_count.unwrap_or(DEFAULT_TAG_COUNT)
+    }
+
+    /// Mask of the tags configured as hidden.
+    pub fn hidden_tags(&self) -> u64 {
+        self.tags.iter()
+            .filter(|(tag, t)| t.hidden && (1..=self.tag_count().min(MAX_TAGS)).contains(*tag))
+            .fold(0, |mask, (tag, _)| mask | 1<<(tag - 1))
+    }
+
+    /// The tag `tag_names` gives the name, compared as commands compare tag names.
+    pub fn tag_by_name(&self, name: &str) -> Option<u8> {
+        self.tag_names.iter().find(|(_, n)| text::same_name(n, name)).map(|(tag, _)| *tag)
+    }
+
+    /// `new_window_tag` with a name resolved to its tag. A name no tag has places windows like `"active"`, which
+    /// `problems` reports.
+    pub fn resolved_new_window_tag(&self) -> NewWindowTag {
+        match &self.new_window_tag {
+            NewWindowTag::Named(name) => self.tag_by_name(name).map_or(NewWindowTag::Active, NewWindowTag::Tag),
+            new_window_tag => new_window_tag.clone(),
+        }
+    }
+
+    /// This config with the named profile's overrides applied.
+    pub fn with_profile(&self, name: &str) -> Self {
+        let mut config = self.clone();
+        let profile = match self.profiles.get(name) {
+            Some(p) => p,
+            None => return config,
+        };
+
+        if let Some(v) = profile.focus_debounce_ms {
+            config.focus_debounce_ms = v;
+        }
+        if let Some(v) = &profile.on_show {
+            config.on_show = v.clone();
+        }
+        if let Some(v) = &profile.new_window_tag {
+            config.new_window_tag = v.clone();
+        }
+        if let Some(v) = profile.xwayland_policy {
+            config.xwayland_policy = v;
+        }
+        if let Some(v) = profile.active_tag_policy {
+            config.active_tag_policy = v;
+        }
+        if let Some(v) = profile.warp_cursor {
+            config.warp_cursor = v;
+        }
+        if let Some(v) = profile.show_toggles_back {
+            config.show_toggles_back = v;
+        }
+        if let Some(v) = &profile.suppress_in_submaps {
+            config.suppress_in_submaps = v.clone();
+        }
+        if let Some(v) = profile.on_tag_emptied {
+            config.on_tag_emptied = v;
+        }
+        if let Some(v) = &profile.presentation {
+            config.presentation = v.clone();
+        }
+        if let Some(v) = &profile.open_burst {
+            config.open_burst = v.clone();
+        }
+        if let Some(v) = profile.monitor_grace_ms {
+            config.monitor_grace_ms = v;
+        }
+        if let Some(v) = profile.command_target {
+            config.command_target = v;
+        }
+
+        config
+    }
+}
+
+fn nfc_names<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<u8, String>, D::Error> {
+    let names = BTreeMap::<u8, String>::deserialize(deserializer)?;
+    Ok(names.into_iter().map(|(tag, name)| (tag, text::nfc(&name))).collect())
+}
+
+/// Rules of the drop-ins in `rules.d`, by file name. Saved runtime rules are left to `Rules::load_runtime`.
+fn drop_in_rules(config_dir: &Path) -> Vec<WindowRule> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(config_dir.join("rules.d")) {
+        Ok(entries) => entries.filter_map(|e| Some(e.ok()?.path())).collect(),
+        Err(_) => return vec![],
+    };
+    paths.retain(|p| p.extension().is_some_and(|e| e == "json") && !p.ends_with(rules::RUNTIME_RULES));
+    paths.sort();
+    paths.iter().flat_map(|path| match rules::read_rules(path) {
+        Ok(rules) => rules.unwrap_or_default(),
+        Err(err) => {
+            tracing::warn!(%err, "ignoring window rules drop-in");
+            vec![]
+        },
+    }).collect()
+}
+
+fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("HYPRTAG_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let dir = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(dir.join("hyprtag").join("config.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::SummarySettings;
+
+    use crate::state::ActiveTagPolicy;
+
+    use super::{CommandTarget, Config, NewWindowTag, OnTagEmptied, PresentationConfig, XwaylandPolicy};
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse("{}").unwrap();
+        assert_eq!(config.focus_debounce_ms, 0);
+        assert!(!config.warp_cursor);
+
+        let config = Config::parse(r#"{"focus_debounce_ms": 150}"#).unwrap();
+        assert_eq!(config.focus_debounce_ms, 150);
+
+        let config = Config::parse(r#"{"warp_cursor": true}"#).unwrap();
+        assert!(config.warp_cursor);
+
+        let config = Config::parse(r#"{
+            "on_show": [
+                {"tag": 7, "hyprctl": ["keyword general:col.active_border rgb(ff8800)"], "exec": "swww img ~/walls/focus.png"}
+            ]
+        }"#).unwrap();
+        assert_eq!(config.on_show.len(), 1);
+        assert_eq!(config.on_show[0].tag, 7);
+        assert_eq!(config.on_show[0].hyprctl.len(), 1);
+        #[cfg(feature = "hooks")]
+        assert!(config.problems().is_empty());
+        #[cfg(not(feature = "hooks"))]
+        assert_eq!(config.problems(), vec!["on_show: hooks are not compiled in"]);
+    }
+
+    #[test]
+    fn parse_new_window_tag() {
+        let config = Config::parse("{}").unwrap();
+        assert_eq!(config.new_window_tag, NewWindowTag::Active);
+
+        let config = Config::parse(r#"{"new_window_tag": "active"}"#).unwrap();
+        assert_eq!(config.new_window_tag, NewWindowTag::Active);
+
+        let config = Config::parse(r#"{"new_window_tag": 9}"#).unwrap();
+        assert_eq!(config.new_window_tag, NewWindowTag::Tag(9));
+
+        assert!(Config::parse(r#"{"new_window_tag": 0}"#).is_err());
+        assert!(Config::parse(r#"{"new_window_tag": ""}"#).is_err());
+
+        // by name, through tag_names
+        let config = Config::parse(r#"{"new_window_tag": "Inbox", "tag_names": {"1": "web", "7": "Inbox"}}"#).unwrap();
+        assert_eq!(config.new_window_tag, NewWindowTag::Named("Inbox".into()));
+        assert_eq!(config.resolved_new_window_tag(), NewWindowTag::Tag(7));
+        assert!(config.problems().is_empty());
+        assert_eq!(serde_json::to_value(&config).unwrap()["new_window_tag"], "Inbox");
+
+        let config = Config::parse(r#"{"new_window_tag": "inbox", "tag_names": {"1": "web"}}"#).unwrap();
+        assert_eq!(config.resolved_new_window_tag(), NewWindowTag::Active);
+        assert_eq!(config.problems(), vec!["new_window_tag: no tag is named inbox"]);
+    }
+
+    #[test]
+    fn parse_monitors() {
+        let config = Config::parse(r#"{"monitors": {"HDMI-A-1": {"managed": false}, "DP-1": {}}}"#).unwrap();
+        assert!(config.monitors["DP-1"].managed);
+        assert_eq!(config.unmanaged_monitors(), vec!["HDMI-A-1".to_string()]);
+    }
+
+    #[test]
+    fn parse_app_tags() {
+        let config = Config::parse(r#"{"app_tags": {"spotify": 9}}"#).unwrap();
+        assert_eq!(config.app_tags["spotify"], 9);
+    }
+
+    #[test]
+    fn parse_tag_names() {
+        let config = Config::parse(r#"{"tag_names": {"1": "web", "9": "chat"}}"#).unwrap();
+        assert_eq!(config.tag_names[&9], "chat");
+        assert!(config.problems().is_empty());
+        let config = Config::parse(r#"{"tags": {"20": {"hidden": true}, "21": {}, "33": {"hidden": true}}}"#).unwrap();
+        assert_eq!(config.hidden_tags(), 1<<19);
+        assert_eq!(config.problems(), vec!["tags: tag 33 is out of range 1-32"]);
+
+        let config = Config::parse(r#"{"tag_names": {"33": "nope"}}"#).unwrap();
+        assert_eq!(config.problems(), vec!["tag_names: tag 33 is out of range 1-32"]);
+        // up to the configured count
+        let config = Config::parse(r#"{"tag_count": 40, "tag_names": {"33": "more"}, "tags": {"38": {"hidden": true}}, "new_window_tag": 41}"#).unwrap();
+        assert_eq!(config.tag_count(), 40);
+        assert_eq!(config.hidden_tags(), 1<<37);
+        assert_eq!(config.problems(), vec!["new_window_tag: tag 41 is out of range 1-40"]);
+        let config = Config::parse(r#"{"tag_count": 65}"#).unwrap();
+        assert_eq!(config.problems(), vec!["tag_count: 65 is out of range 1-64"]);
+        let config = Config::parse(r#"{"tag_names": {"1": "web", "2": "web", "3": "2nd", "4": "my code"}}"#).unwrap();
+        assert_eq!(config.problems(), vec![
+            "tag_names: name \"2nd\" of tag 3 isn't usable in commands, it must not be empty, start with a digit or have spaces",
+            "tag_names: name \"my code\" of tag 4 isn't usable in commands, it must not be empty, start with a digit or have spaces",
+            "tag_names: web names more than one tag",
+        ]);
+    }
+
+    #[test]
+    fn unicode_tag_names() {
+        // decomposed names are kept composed, and named once only in either form
+        let config = Config::parse(r#"{"tag_names": {"1": "仕事", "2": "🎵", "3": "cafe\u0301", "4": "\u304b\u3099っこう"}}"#).unwrap();
+        assert_eq!(config.tag_names[&3], "café");
+        assert_eq!(config.tag_names[&4], "がっこう");
+        assert!(config.problems().is_empty());
+
+        let config = Config::parse(r#"{"tag_names": {"1": "café", "2": "cafe\u0301", "3": "\u0301", "4": "a\tb", "5": "仕\u3000事"}}"#).unwrap();
+        assert_eq!(config.problems(), vec![
+            "tag_names: café names more than one tag",
+            "tag_names: name \"\\u{301}\" of tag 3 can't be shown, it has control characters or nothing visible",
+            "tag_names: name \"a\\tb\" of tag 4 can't be shown, it has control characters or nothing visible",
+            "tag_names: name \"a\\tb\" of tag 4 isn't usable in commands, it must not be empty, start with a digit or have spaces",
+            "tag_names: name \"仕\\u{3000}事\" of tag 5 isn't usable in commands, it must not be empty, start with a digit or have spaces",
+        ]);
+    }
+
+    #[test]
+    fn parse_tag_groups() {
+        let config = Config::parse(r#"{
+            "tags": {"1": {"icon": "W"}},
+            "tag_groups": [{"name": "web", "tags": [2, 1]}, {"name": "chat", "tags": [9]}]
+        }"#).unwrap();
+        assert_eq!(config.tags[&1].icon.as_deref(), Some("W"));
+        assert_eq!(config.tag_groups[0].tags, vec![2, 1]);
+        assert!(config.problems().is_empty());
+
+        let config = Config::parse(r#"{
+            "tag_groups": [{"name": "web", "tags": [1, 2, 1]}, {"name": "chat", "tags": [2, 40]}, {"name": "web", "tags": []}]
+        }"#).unwrap();
+        assert_eq!(config.problems(), vec![
+            "tag_groups.chat: tag 2 is already in group web",
+            "tag_groups.chat: tag 40 is out of range 1-32",
+            "tag_groups.web: tag 1 is already in group web",
+            "tag_groups: group web is listed twice",
+        ]);
+    }
+
+    #[test]
+    fn check_config() {
+        let report = Config::check(r#"{"app_tags": {"spotify": 9}}"#);
+        assert!(report.ok);
+        assert!(report.errors.is_empty());
+
+        let report = Config::check("{\n    \"focus_debounce_ms\": 150,\n    \"warp_cursor\": yes\n}");
+        assert!(!report.ok);
+        assert_eq!(report.line, Some(3));
+        assert_eq!(report.context.as_deref(), Some("    \"warp_cursor\": yes"));
+
+        let report = Config::check(r#"{"app_tags": {"spotify": 40}, "profiles": {"desk": {"default_tags": {"DP-1": [0]}}}}"#);
+        assert!(!report.ok);
+        assert_eq!(report.errors, vec![
+            "app_tags.spotify: tag 40 is out of range 1-32",
+            "profiles.desk.default_tags.DP-1: tag 0 is out of range 1-32",
+        ]);
+        assert_eq!(report.line, None);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let config = Config::parse(r#"{"rules": [
+            {"name": "chat", "class": "^(slack|discord)$", "tag": 5},
+            {"name": "video", "class": "firefox", "title": "(YouTube", "tag": 7, "sticky": true},
+            {"name": "chat", "class": "Element", "tag": 40}
+        ]}"#).unwrap();
+        assert_eq!(config.rules[1].title.as_deref(), Some("(YouTube"));
+        assert!(config.rules[1].sticky && !config.rules[1].ignore);
+        assert_eq!(config.problems(), vec![
+            "rules.chat: tag 40 is out of range 1-32",
+            "rules: invalid title regex /(YouTube/ at column 1: unclosed group",
+            "rules: rule chat is listed twice",
+        ]);
+
+        let dir = std::env::temp_dir().join(format!("hyprtag-rules-d-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("rules.d")).unwrap();
+        std::fs::write(dir.join("rules.d/work.json"), r#"[{"name": "mail", "class": "thunderbird", "tag": 4}]"#).unwrap();
+        std::fs::write(dir.join("rules.d/runtime.json"), r#"[{"name": "saved", "class": "kitty", "tag": 2}]"#).unwrap();
+        std::fs::write(dir.join("rules.d/notes.txt"), "").unwrap();
+        let names: Vec<String> = super::drop_in_rules(&dir).into_iter().map(|r| r.name).collect();
+        // saved runtime rules stay runtime rules
+        assert_eq!(names, vec!["mail"]);
+        let config = Config { source: Some(dir.join("config.json")), ..Default::default() };
+        assert_eq!(config.runtime_rules_path(), Some(dir.join("rules.d/runtime.json")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn serialize_config() {
+        let config = Config::parse(r#"{"new_window_tag": 9, "xwayland_policy": "pin-tag"}"#).unwrap();
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["new_window_tag"], 9);
+        assert_eq!(json["xwayland_policy"], "pin-tag");
+        assert_eq!(serde_json::to_value(Config::default()).unwrap()["new_window_tag"], "active");
+    }
+
+    #[test]
+    fn parse_xwayland_policy() {
+        let config = Config::parse("{}").unwrap();
+        assert_eq!(config.xwayland_policy, XwaylandPolicy::Normal);
+
+        let config = Config::parse(r#"{"xwayland_policy": "pin-tag"}"#).unwrap();
+        assert_eq!(config.xwayland_policy, XwaylandPolicy::PinTag);
+
+        assert!(Config::parse(r#"{"xwayland_policy": "pinned"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_active_tag_policy() {
+        assert_eq!(Config::parse("{}").unwrap().active_tag_policy, ActiveTagPolicy::FocusedWindow);
+        let config = Config::parse(r#"{"active_tag_policy": "last-shown"}"#).unwrap();
+        assert_eq!(config.active_tag_policy, ActiveTagPolicy::LastShown);
+        assert!(Config::parse(r#"{"active_tag_policy": "lowest"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_summary() {
+        let settings = Config::parse("{}").unwrap().summary.settings();
+        assert_eq!((settings.reset_at, settings.max_interval), (4 * 3600, 600));
+
+        let config = Config::parse(r#"{"summary": {"reset_at": "23:30", "max_interval_secs": 300}}"#).unwrap();
+        assert_eq!(config.summary.settings(), SummarySettings { reset_at: 23 * 3600 + 30 * 60, max_interval: 300 });
+        assert!(config.problems().is_empty());
+
+        let config = Config::parse(r#"{"summary": {"reset_at": "24:00"}}"#).unwrap();
+        assert_eq!(config.problems(), vec!["summary.reset_at: 24:00 is not a HH:MM time"]);
+        assert_eq!(config.summary.settings().reset_at, 4 * 3600);
+    }
+
+    #[test]
+    fn parse_on_tag_emptied() {
+        assert_eq!(Config::parse("{}").unwrap().on_tag_emptied, OnTagEmptied::Stay);
+        let config = Config::parse(r#"{"on_tag_emptied": "nearest_occupied"}"#).unwrap();
+        assert_eq!(config.on_tag_emptied, OnTagEmptied::NearestOccupied);
+        assert!(Config::parse(r#"{"on_tag_emptied": "nearest"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_command_target() {
+        assert_eq!(Config::parse("{}").unwrap().command_target, CommandTarget::Focused);
+        let config = Config::parse(r#"{"command_target": "cursor", "profiles": {"desk": {"command_target": "focused"}}}"#).unwrap();
+        assert_eq!(config.command_target, CommandTarget::Cursor);
+        assert_eq!(config.with_profile("desk").command_target, CommandTarget::Focused);
+        assert!(Config::parse(r#"{"command_target": "mouse"}"#).is_err());
+    }
+
+    #[test]
+    fn parse_presentation() {
+        assert_eq!(Config::parse("{}").unwrap().presentation, PresentationConfig { redact_titles: true, redact_classes: true });
+        let config = Config::parse(r#"{"presentation": {"redact_classes": false}}"#).unwrap();
+        assert_eq!(config.presentation, PresentationConfig { redact_titles: true, redact_classes: false });
+    }
+}
@@ -0,0 +1,2871 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, OnceLock}};
+
+use anyhow::{anyhow, bail, Context};
+use tokio::{io::{AsyncWriteExt, BufStream}, net::{UnixListener, UnixStream}, sync::{mpsc, oneshot}, task::JoinSet};
+
+#[cfg(feature = "json-proto")]
+use crate::{config::Config, dispatch, events::UNKNOWN_EVENTS, fullscreen::fullscreen_windows, hyprctl, schema::{ConfigReply, ExplainReply, PreviewReply, QueryReply}, snapshot::{self, Snapshot, StateSnapshot}, subscribe::{self, Subscription}};
+use crate::{
+    adopt,
+    auth::{self, Access, Peer},
+    config::CommandTarget,
+    dispatch::{handle_changes, handle_changes_batched, handle_steps_checked},
+    events,
+    exit::Fatal,
+    features,
+    focus::focus_echoes,
+    hooks::{self, Hooks},
+    hyprctl::{hyprctl_batch, hyprctl_clients, hyprctl_enqueue, ClientInfo, CursorPos, MonitorInfo},
+    hyprland_dir,
+    idempotency::{outcome, split_id, RecentIds, Seen, RECENT_IDS},
+    line::{read_line_limited, reply_lines, ReadLine, MAX_LINE},
+    monitor::{ChangeSource, Changes, Checkpoint, Monitor, MonitorsState, ViewMode},
+    now,
+    prefix::pending_prefix,
+    profile::Profiles,
+    profile_changed,
+    protocol::{self, UnknownCommand},
+    rules::{CompiledRule, WindowRule},
+    schema::{RuleSaveReply, StaleWindow, ThrowReply, WindowConflict},
+    state::{check_tag, check_tags, WindowAddr, MAX_TAGS},
+    text,
+    txn::{Step, Transaction, TxnOp, TXN_TIMEOUT},
+};
+
+/// The reply of a `sync` command, `ok` with a note when its command was redirected.
+#[derive(Debug)]
+pub struct SyncReply {
+    reply: oneshot::Sender<String>,
+    ok: String,
+}
+
+impl SyncReply {
+    /// Replies with how the command went, an `ok` once the command's dispatches ran.
+    pub(crate) fn done(self, r: &anyhow::Result<()>) {
+        match r {
+            // the worker runs jobs in order, so this runs after the command's dispatches
+            Ok(()) => hyprctl_enqueue(async move {
+                let _ = self.reply.send(self.ok);
+            }),
+            Err(err) => {
+                let _ = self.reply.send(format!("err: {}", err));
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Ctrl {
+    ShowTag(u8),
+    /// Shows the tags of the mask at once, like `show 1,3,5`.
+    ShowTags(u64),
+    /// Shows the next tag with windows when true, the previous one otherwise.
+    CycleTag(bool),
+    /// Tag, and whether to focus the tag when it gets shown.
+    ToggleTag(u8, bool),
+    /// Tag, window, and whether to move XWayland windows pinned to their tag anyway.
+    MoveToTag(u8, Option<WindowAddr>, bool),
+    /// Moves the active window to the tag and shows it, in one change `restore` undoes at once.
+    MoveShow(u8),
+    /// Moves the active window to the first empty tag on its monitor, showing that tag when following, and replies with it.
+    MoveToEmpty(bool, oneshot::Sender<String>),
+    RestorePrevTags,
+    /// First digit of the tag taken by the next command.
+    Prefix(u8),
+    AbortPrefix,
+    MoveToNextMonitor,
+    /// Monitor selector, tag, and window to move there in one step.
+    Place(String, u8, Option<WindowAddr>),
+    /// Monitor selector, tag, and whether to focus it: shows only the tag on that monitor.
+    ShowOn(String, u8, bool),
+    /// Places the focused window on the monitor under the cursor, on the tag or the one active there.
+    MoveToCursorMonitor(Option<u8>),
+    /// Where the cursor was and the monitors' geometries, for the window `MoveToCursorMonitor` moves, with the reply
+    /// of a `sync` waiting for the move.
+    CursorQueried(Option<u8>, WindowAddr, CursorPos, Vec<MonitorInfo>, Option<SyncReply>),
+    /// `show` or `toggle` with a `@cursor` or `@focused` suffix, acting on that monitor whatever `command_target` says.
+    Target(CommandTarget, Box<Ctrl>),
+    /// Where the cursor was for a command acting on the monitor under it, `None` when the query failed, with the
+    /// reply of a `sync` waiting for the command.
+    TargetQueried(Box<Ctrl>, ChangeSource, Option<(CursorPos, Vec<MonitorInfo>)>, Option<SyncReply>),
+    /// Places the focused window on the monitor's tag and shows that tag there, keeping focus where it is.
+    /// Replies with the placement and the monitor's visible tags.
+    Throw(String, u8, oneshot::Sender<String>),
+    FocusMonitor(String),
+    /// Focuses the window focused before the current one on the active monitor, like alt-tab.
+    FocusLast,
+    Promote(Option<WindowAddr>),
+    Demote(Option<WindowAddr>),
+    Reorder(u8, Vec<WindowAddr>),
+    MonitorAdded(Monitor),
+    MonitorsRefreshed(Vec<MonitorInfo>),
+    /// Monitors queried after a config reload, reconciled with the tracked ones.
+    MonitorsReloaded(Vec<MonitorInfo>),
+    ClientsRefreshed(Vec<ClientInfo>),
+    /// A removed monitor didn't come back within its grace period.
+    MonitorGone(String),
+    /// Clients queried after a monitor came back, to move windows Hyprland moved meanwhile back where they belong.
+    PlacementsChecked(Vec<ClientInfo>),
+    FocusSettled(WindowAddr),
+    /// No window opened for a while, or the burst went on for too long.
+    BurstEnded,
+    /// Time for the next chunk of the startup adoption's dispatches.
+    AdoptionChunk,
+    /// Lets the tag, or every tag, run its autostart commands again.
+    AutostartReset(Option<u8>),
+    /// Windows of autostarted commands had their time to come up.
+    AutostartExpired,
+    /// An autostarted command of the tag failed, for the reason given.
+    AutostartFailed(u8, String, String),
+    Profile(String),
+    Activate(String, String),
+    Mirror(u8, String),
+    Unmirror,
+    /// How the active monitor shows hidden tags.
+    Mode(ViewMode),
+    /// Adds the window, or the focused one, to its monitor's overlay, which keeps it visible off any tag,
+    /// or removes it from there.
+    Overlay(bool, Option<WindowAddr>),
+    /// Turns presentation mode on or off for the selected monitor, or for all of them.
+    Presentation(bool, Option<String>),
+    /// Runs the command even on a monitor in presentation mode.
+    Override(Box<Ctrl>),
+    /// A command read from the ctrl socket, with the line it was sent as.
+    Request(String, Box<Ctrl>),
+    /// Windows not focused for the given seconds, optionally moved to a tag.
+    Stale(u64, Option<u8>, oneshot::Sender<String>),
+    /// Per tag foreground time, windows and occupancy since the daily reset.
+    Summary(oneshot::Sender<String>),
+    /// Starts the summary's totals over.
+    SummaryReset,
+    /// Windows whose flags conflicted, with the flag each one went without.
+    Conflicts(oneshot::Sender<String>),
+    /// Window rules, runtime ones first, with the file rules they replace.
+    RuleList(oneshot::Sender<String>),
+    /// Adds a runtime window rule, replacing the one of the same name.
+    RuleAdd(Box<CompiledRule>, oneshot::Sender<String>),
+    /// Removes a runtime window rule.
+    RuleRemove(String, oneshot::Sender<String>),
+    /// Which rule a window, or a window of the class, gets when it opens, and why.
+    RuleTest(RuleSubject, oneshot::Sender<String>),
+    /// Writes the runtime rules to the drop-in they're loaded from on the next start.
+    RuleSave(oneshot::Sender<String>),
+    /// Begins, commits or aborts a transaction. A commit replies once its dispatches went through, or with the
+    /// step which failed.
+    Txn(TxnOp, oneshot::Sender<String>),
+    /// The transaction with the id was open for too long.
+    TxnExpired(u64),
+    /// A committed transaction failed as described, and goes back to the checkpoint.
+    TxnFailed(String, Box<Checkpoint>, oneshot::Sender<String>),
+    #[cfg(feature = "json-proto")]
+    Snapshot(oneshot::Sender<String>),
+    /// Per monitor, the visible and occupied tags and the active window, and the windows with their tags.
+    /// True for `query full`, which adds how each window got on its tag.
+    #[cfg(feature = "json-proto")]
+    Query(bool, oneshot::Sender<String>),
+    /// Per monitor, tags with windows added or marked urgent since they were last visible.
+    #[cfg(feature = "json-proto")]
+    Badges(oneshot::Sender<String>),
+    /// Every monitor with its workspaces and visible tags.
+    #[cfg(feature = "json-proto")]
+    Outputs(oneshot::Sender<String>),
+    /// Every monitor's tags as a grid, with their windows, visibility and urgency.
+    #[cfg(feature = "json-proto")]
+    Pager(oneshot::Sender<String>),
+    /// Streams a line per monitor whose tags, windows or focus change, starting with every monitor as it is.
+    #[cfg(feature = "json-proto")]
+    Subscribe(oneshot::Sender<Subscription>),
+    /// Runs the command and replies `ok` once the dispatches it queued have run.
+    Sync(Box<Ctrl>, oneshot::Sender<String>),
+    #[cfg(feature = "json-proto")]
+    Diff(Box<Snapshot>, oneshot::Sender<String>),
+    /// The effective config with its source file and the active profile.
+    #[cfg(feature = "json-proto")]
+    Config(oneshot::Sender<String>),
+    /// Validates the config file without applying it.
+    #[cfg(feature = "json-proto")]
+    ConfigCheck(PathBuf, oneshot::Sender<String>),
+    /// The window's recent assignments to monitors and tags, with what made them.
+    #[cfg(feature = "json-proto")]
+    Explain(WindowAddr, oneshot::Sender<String>),
+    /// Runs the command on a copy of the state and replies with the dispatches it would send and the tags and
+    /// windows it would change.
+    #[cfg(feature = "json-proto")]
+    Preview(Box<Ctrl>, oneshot::Sender<String>),
+    /// The commands of the registry, or the one named, with their syntax, arguments and what they do.
+    #[cfg(feature = "json-proto")]
+    Help(Option<&'static protocol::CommandInfo>, oneshot::Sender<String>),
+}
+
+/// What `rule test` checks the rules against.
+#[derive(Debug, PartialEq)]
+pub enum RuleSubject {
+    /// An open window, by its class and title.
+    Window(WindowAddr),
+    /// A window of the class with no title.
+    Class(String),
+}
+
+impl Ctrl {
+    /// Commands sent by users which change tags, windows or the profile, as opposed to queries and internal messages.
+    fn is_user_change(&self) -> bool {
+        match self {
+            Ctrl::ShowTag(_)
+            | Ctrl::ShowTags(_)
+            | Ctrl::CycleTag(_)
+            | Ctrl::ToggleTag(_, _)
+            | Ctrl::MoveToTag(_, _, _)
+            | Ctrl::MoveShow(_)
+            | Ctrl::MoveToEmpty(_, _)
+            | Ctrl::RestorePrevTags
+            | Ctrl::Prefix(_)
+            | Ctrl::AbortPrefix
+            | Ctrl::MoveToNextMonitor
+            | Ctrl::Place(_, _, _)
+            | Ctrl::MoveToCursorMonitor(_)
+            | Ctrl::ShowOn(_, _, _)
+            | Ctrl::Throw(_, _, _)
+            | Ctrl::FocusMonitor(_)
+            | Ctrl::FocusLast
+            | Ctrl::Promote(_)
+            | Ctrl::Demote(_)
+            | Ctrl::Reorder(_, _)
+            | Ctrl::Profile(_)
+            | Ctrl::Activate(_, _)
+            | Ctrl::Mirror(_, _)
+            | Ctrl::Unmirror
+            | Ctrl::Mode(_)
+            | Ctrl::Overlay(_, _)
+            | Ctrl::Stale(_, Some(_), _) => true,
+            Ctrl::Sync(msg, _)
+            | Ctrl::Override(msg)
+            | Ctrl::Request(_, msg)
+            | Ctrl::Target(_, msg)
+            | Ctrl::TargetQueried(msg, _, _, _) => msg.is_user_change(),
+            _ => false,
+        }
+    }
+
+    /// Commands which only report and change nothing, which read-only ctrl connections may run.
+    fn is_read_only(&self) -> bool {
+        match self {
+            Ctrl::Stale(_, None, _)
+            | Ctrl::Summary(_)
+            | Ctrl::Conflicts(_)
+            | Ctrl::RuleList(_)
+            | Ctrl::RuleTest(_, _) => true,
+            #[cfg(feature = "json-proto")]
+            Ctrl::Snapshot(_)
+            | Ctrl::Query(_, _)
+            | Ctrl::Badges(_)
+            | Ctrl::Outputs(_)
+            | Ctrl::Pager(_)
+            | Ctrl::Subscribe(_)
+            | Ctrl::Diff(_, _)
+            | Ctrl::Config(_)
+            | Ctrl::Explain(_, _)
+            | Ctrl::Preview(_, _)
+            | Ctrl::Help(_, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Where the changes made by the message come from. Messages the daemon sends itself follow up on an event or
+    /// a query.
+    fn source(&self) -> ChangeSource {
+        match self {
+            Ctrl::Request(line, _) => ChangeSource::Ctrl { command: line.clone() },
+            Ctrl::FocusSettled(_) => ChangeSource::Event { event: "activewindowv2".to_string() },
+            Ctrl::BurstEnded => ChangeSource::Event { event: "openwindow".to_string() },
+            Ctrl::CursorQueried(tag, _, _, _, _) => ChangeSource::Ctrl {
+                command: tag.map_or("move_to_cursor_monitor".to_string(), |tag| format!("move_to_cursor_monitor {}", tag)),
+            },
+            Ctrl::TargetQueried(_, source, _, _) => source.clone(),
+            Ctrl::MonitorGone(_) => ChangeSource::Event { event: "monitorremoved".to_string() },
+            Ctrl::TxnExpired(_) | Ctrl::TxnFailed(_, _, _) => ChangeSource::Ctrl { command: "txn rollback".to_string() },
+            Ctrl::AdoptionChunk => ChangeSource::Startup,
+            Ctrl::MonitorAdded(_)
+            | Ctrl::MonitorsRefreshed(_)
+            | Ctrl::MonitorsReloaded(_)
+            | Ctrl::ClientsRefreshed(_)
+            | Ctrl::PlacementsChecked(_) => {
+                ChangeSource::Reconcile
+            },
+            msg => ChangeSource::Ctrl { command: format!("{:?}", msg) },
+        }
+    }
+
+    /// Commands whose changes `command_changes` knows without asking Hyprland and which don't reply, which can run
+    /// in a transaction.
+    fn transactable(&self) -> bool {
+        match self {
+            Ctrl::ShowTag(_)
+            | Ctrl::ShowTags(_)
+            | Ctrl::CycleTag(_)
+            | Ctrl::ToggleTag(_, _)
+            | Ctrl::MoveToTag(_, _, _)
+            | Ctrl::MoveShow(_)
+            | Ctrl::RestorePrevTags
+            | Ctrl::MoveToNextMonitor
+            | Ctrl::Place(_, _, _)
+            | Ctrl::ShowOn(_, _, _)
+            | Ctrl::FocusLast => true,
+            Ctrl::Target(target, msg) => *target == CommandTarget::Focused && msg.transactable(),
+            Ctrl::Override(msg) => msg.transactable(),
+            _ => false,
+        }
+    }
+
+    /// Commands whose changes `command_changes` knows without asking Hyprland, which can be previewed.
+    #[cfg(feature = "json-proto")]
+    fn previewable(&self) -> bool {
+        match self {
+            Ctrl::ShowTag(_)
+            | Ctrl::ShowTags(_)
+            | Ctrl::CycleTag(_)
+            | Ctrl::ToggleTag(_, _)
+            | Ctrl::MoveToTag(_, _, _)
+            | Ctrl::MoveShow(_)
+            | Ctrl::MoveToEmpty(_, _)
+            | Ctrl::RestorePrevTags
+            | Ctrl::MoveToNextMonitor
+            | Ctrl::Place(_, _, _)
+            | Ctrl::ShowOn(_, _, _)
+            | Ctrl::Throw(_, _, _)
+            | Ctrl::FocusLast => true,
+            Ctrl::Target(target, msg) => *target == CommandTarget::Focused && msg.previewable(),
+            Ctrl::Override(msg) => msg.previewable(),
+            _ => false,
+        }
+    }
+}
+
+/// Number of ctrl lines received with a command we don't know.
+pub static UNKNOWN_COMMANDS: AtomicU64 = AtomicU64::new(0);
+
+/// Path of the ctrl socket in the Hyprland instance's runtime dir.
+pub fn socket_path() -> anyhow::Result<PathBuf> {
+    Ok(hyprland_dir()?.join(protocol::SOCKET))
+}
+
+/// Binds the ctrl socket and serves connections until accepting fails.
+pub async fn listen(tx: mpsc::Sender<Ctrl>) -> anyhow::Result<()> {
+    listen_at(&socket_path()?, tx).await
+}
+
+/// Serves the ctrl socket at `path`. Connections are served by tasks which end with this one, so none outlive
+/// the session they were for.
+async fn listen_at(path: &Path, tx: mpsc::Sender<Ctrl>) -> anyhow::Result<()> {
+    // a previous run, or a previous attempt of this task, may have left the socket behind
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path).context(Fatal::CtrlSocket)?;
+    let recent = Arc::new(Mutex::new(RecentIds::new(RECENT_IDS)));
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _addr) = accepted?;
+                let peer = auth::peer(&stream);
+                connections.spawn(handle_ctrl_socket(tx.clone(), recent.clone(), stream, peer));
+            }
+            Some(_) = connections.join_next() => (),
+        }
+    }
+}
+
+/// Serves one connection. Commands prefixed with `id:<key>` always reply, and a retry with the same key gets the
+/// first attempt's reply instead of running the command again. A peer without access is told so and disconnected,
+/// a read-only one gets an error for each command changing something.
+async fn handle_ctrl_socket(tx: mpsc::Sender<Ctrl>, recent: Arc<Mutex<RecentIds>>, stream: UnixStream, peer: Peer) {
+    let mut stream = BufStream::new(stream);
+    let mut buf = String::new();
+    let mut replies = 0;
+
+    if peer.access == Access::Denied {
+        peer.rejected("connection");
+        write_line(&mut stream, "err: permission denied").await;
+        return;
+    }
+
+    loop {
+        buf.clear();
+        match read_line_limited(&mut stream, &mut buf, MAX_LINE).await {
+            Err(err) => {
+                tracing::error!(%err, "failed to read");
+                break;
+            },
+            Ok(ReadLine::Eof) => break,
+            Ok(ReadLine::TooLong) => {
+                tracing::error!("ctrl line longer than {} bytes, closing", MAX_LINE);
+                write_line(&mut stream, "err: line too long").await;
+                break;
+            },
+            Ok(ReadLine::Line) => (),
+        }
+
+        let line = buf.trim_end_matches(['\r', '\n']);
+        tracing::debug!("ctrl recv: {}", line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let (id, line) = match split_id(line) {
+            Ok(r) => r,
+            Err(err) => {
+                tracing::error!(%err, "invalid ctrl command: {}", line);
+                write_line(&mut stream, &format!("err: {}", err)).await;
+                continue;
+            },
+        };
+
+        // the connection is the subscriber's from then on
+        #[cfg(feature = "json-proto")]
+        if line == "subscribe" {
+            let (subscribed_tx, subscribed_rx) = oneshot::channel();
+            if tx.send(Ctrl::Request(line.to_string(), Box::new(Ctrl::Subscribe(subscribed_tx)))).await.is_err() {
+                tracing::error!("daemon is shutting down");
+            } else if let Ok(subscription) = subscribed_rx.await {
+                subscribe::stream(&mut stream, subscription).await;
+            }
+            break;
+        }
+
+        let done = match id.map(|id| recent.lock().unwrap().check(id, line)) {
+            Some(Seen::Duplicate(rx)) => {
+                tracing::info!(id, "duplicate ctrl command: {}", line);
+                if let Some(result) = outcome(rx).await {
+                    write_reply(&mut stream, &result, &mut replies).await;
+                }
+                continue;
+            },
+            Some(Seen::Conflict) => {
+                tracing::error!(id, "id reused for a different command: {}", line);
+                write_line(&mut stream, "err: id was used for a different command").await;
+                continue;
+            },
+            Some(Seen::New(done)) => Some(done),
+            None => None,
+        };
+
+        let (msg, reply_rx) = match parse_request(line) {
+            Ok((msg, _)) if peer.access == Access::ReadOnly && !msg.is_read_only() => {
+                peer.rejected(line);
+                let result = "err: permission denied, read-only access".to_string();
+                if let Some(done) = done {
+                    let _ = done.send(Some(result.clone()));
+                }
+                write_line(&mut stream, &result).await;
+                continue;
+            },
+            // commands without a reply of their own run as sync, replying `ok` or `err: ...`
+            Ok((msg, None)) => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                (Ctrl::Sync(Box::new(msg), reply_tx), Some(reply_rx))
+            },
+            Ok(r) => r,
+            // a typo'd keybind gets told, even without an id
+            Err(err) if err.is::<UnknownCommand>() => {
+                let count = UNKNOWN_COMMANDS.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::warn!(line, count, "{}", err);
+                let result = format!("err: {}", err);
+                if let Some(done) = done {
+                    let _ = done.send(Some(result.clone()));
+                }
+                write_line(&mut stream, &result).await;
+                continue;
+            },
+            Err(err) => {
+                tracing::error!(%err, "invalid ctrl command: {}", line);
+                let result = format!("err: {}", err);
+                if let Some(done) = done {
+                    let _ = done.send(Some(result.clone()));
+                }
+                write_line(&mut stream, &result).await;
+                continue;
+            },
+        };
+
+        if tx.send(Ctrl::Request(line.to_string(), Box::new(msg))).await.is_err() {
+            tracing::error!("daemon is shutting down");
+            break;
+        }
+        if let Some(rx) = reply_rx {
+            if let Ok(result) = rx.await {
+                if let Some(done) = &done {
+                    let _ = done.send(Some(result.clone()));
+                }
+                write_reply(&mut stream, &result, &mut replies).await;
+            }
+        }
+    }
+}
+
+/// Parses one protocol line into a message for the daemon, with the receiver of its reply for commands which have one.
+pub(crate) fn parse_request(line: &str) -> anyhow::Result<(Ctrl, Option<oneshot::Receiver<String>>)> {
+    if let Some(line) = line.strip_suffix(" --override") {
+        let (msg, reply) = parse_request(line)?;
+        return Ok((Ctrl::Override(Box::new(msg)), reply));
+    }
+
+    let name = line.split(' ').next().unwrap_or_default();
+    if let Some((cmd, target)) = name.split_once('@') {
+        let target = match target {
+            "cursor" => CommandTarget::Cursor,
+            "focused" => CommandTarget::Focused,
+            _ => bail!("invalid command target: @{}", target),
+        };
+        if !matches!(cmd, "show" | "toggle") {
+            bail!("only show and toggle take a command target, not {}", cmd);
+        }
+        let (msg, reply) = parse_request(&format!("{}{}", cmd, &line[name.len()..]))?;
+        return Ok((Ctrl::Target(target, Box::new(msg)), reply));
+    }
+
+    let chunks: Vec<&str> = line.split(" ").collect();
+    let cmd = chunks[0];
+    let args = &chunks[1..];
+
+    let msg = match cmd {
+        "move" => {
+            let force = match args.get(1) {
+                None => false,
+                Some(&"--force") => true,
+                Some(arg) => bail!("invalid move arg: {}", arg),
+            };
+            Ctrl::MoveToTag(parse_tag(cmd, args)?, None, force)
+        },
+        "move_show" => Ctrl::MoveShow(parse_tag(cmd, args)?),
+        "move_to_empty" => {
+            let follow = match args.first() {
+                None => false,
+                Some(&"follow") => true,
+                Some(arg) => bail!("invalid move_to_empty arg: {}", arg),
+            };
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::MoveToEmpty(follow, reply_tx), Some(reply_rx)));
+        },
+        "show" => match args.first() {
+            Some(arg) if arg.starts_with("mask:") || arg.contains(',') => Ctrl::ShowTags(parse_tags(cmd, arg)?),
+            _ => Ctrl::ShowTag(parse_tag(cmd, args)?),
+        },
+        "cycle_next" => Ctrl::CycleTag(true),
+        "cycle_prev" => Ctrl::CycleTag(false),
+        "toggle" => {
+            let focus = match args.get(1) {
+                None => false,
+                Some(&"focus") => true,
+                Some(arg) => bail!("invalid toggle arg: {}", arg),
+            };
+            Ctrl::ToggleTag(parse_tag(cmd, args)?, focus)
+        },
+        "restore" => Ctrl::RestorePrevTags,
+
+        "prefix" => Ctrl::Prefix(parse_tag(cmd, args)?),
+        "abort_prefix" => Ctrl::AbortPrefix,
+
+        "profile" => match args.first() {
+            Some(name) => Ctrl::Profile(name.to_string()),
+            None => bail!("require profile args"),
+        },
+
+        "move_to_next_monitor" => Ctrl::MoveToNextMonitor,
+
+        "place" => match args {
+            [monitor, tag, rest @ ..] if rest.len() <= 1 => {
+                Ctrl::Place(monitor.to_string(), parse_tag(cmd, &[tag])?, rest.first().map(|a| a.parse()).transpose()?)
+            },
+            _ => bail!("require place args: <monitor> <tag> [address]"),
+        },
+
+        "move_to_cursor_monitor" => match args {
+            [] => Ctrl::MoveToCursorMonitor(None),
+            [tag] => Ctrl::MoveToCursorMonitor(Some(parse_tag(cmd, &[tag])?)),
+            _ => bail!("require move_to_cursor_monitor args: [tag]"),
+        },
+
+        "show_on" => match args {
+            [monitor, tag] => Ctrl::ShowOn(monitor.to_string(), parse_tag(cmd, &[tag])?, true),
+            [monitor, tag, "nofocus"] => Ctrl::ShowOn(monitor.to_string(), parse_tag(cmd, &[tag])?, false),
+            _ => bail!("require show_on args: <monitor> <tag> [nofocus]"),
+        },
+
+        // a click on a pager cell
+        "pager_goto" => match args {
+            [monitor, tag] => Ctrl::ShowOn(monitor.to_string(), parse_tag(cmd, &[tag])?, true),
+            _ => bail!("require pager_goto args: <monitor> <tag>"),
+        },
+
+        "throw" => match args {
+            [monitor, tag] => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                return Ok((Ctrl::Throw(monitor.to_string(), parse_tag(cmd, &[tag])?, reply_tx), Some(reply_rx)));
+            },
+            _ => bail!("require throw args: <monitor> <tag>"),
+        },
+
+        "focus_monitor" => match args.first() {
+            Some(selector) => Ctrl::FocusMonitor(selector.to_string()),
+            None => bail!("require focus_monitor args"),
+        },
+
+        "focus_last" => Ctrl::FocusLast,
+
+        "promote" => Ctrl::Promote(args.first().map(|a| a.parse()).transpose()?),
+        "demote" => Ctrl::Demote(args.first().map(|a| a.parse()).transpose()?),
+
+        "reorder" => {
+            let tag = parse_tag(cmd, args)?;
+            Ctrl::Reorder(tag, args[1..].iter().map(|a| a.parse()).collect::<Result<_, _>>()?)
+        },
+
+        "activate" => {
+            if args.len() < 2 {
+                bail!("require activate args");
+            }
+            Ctrl::Activate(args[0].to_string(), args[1..].join(" "))
+        },
+
+        "mirror" => match args.get(1) {
+            Some(monitor) => Ctrl::Mirror(parse_tag(cmd, args)?, monitor.to_string()),
+            None => bail!("require mirror args"),
+        },
+
+        "unmirror" => Ctrl::Unmirror,
+
+        "autostart_reset" => match args {
+            [] => Ctrl::AutostartReset(None),
+            [_] => match parse_tag(cmd, args)? {
+                0 => bail!("no such tag: 0"),
+                tag => Ctrl::AutostartReset(Some(tag)),
+            },
+            _ => bail!("require autostart_reset args: [tag]"),
+        },
+
+        "mode" => match args.first() {
+            Some(&"normal") => Ctrl::Mode(ViewMode::Normal),
+            Some(&"dim") => Ctrl::Mode(ViewMode::Dim),
+            Some(arg) => bail!("invalid mode: {}", arg),
+            None => bail!("require mode args"),
+        },
+
+        "overlay" => {
+            let window = args.get(1).map(|a| a.parse()).transpose()?;
+            match args.first() {
+                Some(&"add") if args.len() <= 2 => Ctrl::Overlay(true, window),
+                Some(&"remove") if args.len() <= 2 => Ctrl::Overlay(false, window),
+                _ => bail!("require overlay args: add|remove [address]"),
+            }
+        },
+
+        "presentation" => {
+            let on = match args.first() {
+                Some(&"on") => true,
+                Some(&"off") => false,
+                _ => bail!("require presentation args: on|off [monitor]"),
+            };
+            match args {
+                [_] => Ctrl::Presentation(on, None),
+                [_, monitor] => Ctrl::Presentation(on, Some(monitor.to_string())),
+                _ => bail!("require presentation args: on|off [monitor]"),
+            }
+        },
+
+        "stale" => {
+            let secs = match args.first().map(|a| a.parse::<u64>()) {
+                Some(Ok(secs)) => secs,
+                Some(Err(_)) => bail!("invalid seconds: {}", args[0]),
+                None => bail!("require stale args"),
+            };
+            let tag = match args.get(1) {
+                None => None,
+                Some(&"move") => Some(parse_tag("stale move", &args[2..])?),
+                Some(arg) => bail!("invalid stale arg: {}", arg),
+            };
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Stale(secs, tag, reply_tx), Some(reply_rx)));
+        },
+
+        "summary" => match args.first() {
+            None => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                return Ok((Ctrl::Summary(reply_tx), Some(reply_rx)));
+            },
+            Some(&"reset") => Ctrl::SummaryReset,
+            Some(arg) => bail!("invalid summary arg: {}", arg),
+        },
+
+        "conflicts" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Conflicts(reply_tx), Some(reply_rx)));
+        },
+
+        "rule" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let msg = match args.first() {
+                Some(&"list") => Ctrl::RuleList(reply_tx),
+                Some(&"add") => Ctrl::RuleAdd(Box::new(parse_rule(&args[1..])?), reply_tx),
+                Some(&"remove") => match args.get(1) {
+                    Some(name) => Ctrl::RuleRemove(name.to_string(), reply_tx),
+                    None => bail!("require rule remove args: <name>"),
+                },
+                Some(&"test") => match args.get(1) {
+                    Some(arg) if arg.starts_with("0x") => Ctrl::RuleTest(RuleSubject::Window(arg.parse()?), reply_tx),
+                    Some(class) => Ctrl::RuleTest(RuleSubject::Class(class.to_string()), reply_tx),
+                    None => bail!("require rule test args: <address|class>"),
+                },
+                Some(&"save") => Ctrl::RuleSave(reply_tx),
+                Some(arg) => bail!("invalid rule arg: {}", arg),
+                None => bail!("require rule args: list, add, remove, test or save"),
+            };
+            return Ok((msg, Some(reply_rx)));
+        },
+
+        "txn" => {
+            let op = match args {
+                ["begin"] => TxnOp::Begin,
+                ["commit"] => TxnOp::Commit,
+                ["abort"] => TxnOp::Abort,
+                _ => bail!("require txn args: begin|commit|abort"),
+            };
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Txn(op, reply_tx), Some(reply_rx)));
+        },
+
+        "sync" => {
+            let (inner, inner_reply) = parse_request(line["sync".len()..].trim_start())?;
+            if inner_reply.is_some() {
+                bail!("{} already replies", args.first().unwrap_or(&""));
+            }
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Sync(Box::new(inner), reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "snapshot" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Snapshot(reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "query" => {
+            let full = match args {
+                [] => false,
+                ["full"] => true,
+                _ => bail!("require query args: [full]"),
+            };
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Query(full, reply_tx), Some(reply_rx)));
+        },
+
+        // the connection handles a plain `subscribe` itself
+        #[cfg(feature = "json-proto")]
+        "subscribe" => bail!("subscribe takes no args or id"),
+
+        #[cfg(feature = "json-proto")]
+        "badges" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Badges(reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "outputs" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Outputs(reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "pager" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Pager(reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "config" => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let msg = match args.first() {
+                None => Ctrl::Config(reply_tx),
+                Some(&"check") => match line["config check".len()..].trim() {
+                    "" => bail!("require config check args"),
+                    path => Ctrl::ConfigCheck(PathBuf::from(path), reply_tx),
+                },
+                Some(arg) => bail!("invalid config arg: {}", arg),
+            };
+            return Ok((msg, Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "diff" => {
+            let before: Snapshot = serde_json::from_str(line["diff".len()..].trim())?;
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Diff(Box::new(before), reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "explain" => {
+            let window = args.first().context("require explain args")?.parse()?;
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Explain(window, reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "preview" => {
+            // the command's own reply is dropped, the preview replies instead
+            let (inner, _) = parse_request(line["preview".len()..].trim_start())?;
+            if !inner.previewable() {
+                bail!("{} can't be previewed", args.first().unwrap_or(&""));
+            }
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Preview(Box::new(inner), reply_tx), Some(reply_rx)));
+        },
+
+        #[cfg(feature = "json-proto")]
+        "help" => {
+            let info = match args.first() {
+                Some(name) => Some(protocol::command_info(name).ok_or_else(|| UnknownCommand::new(name))?),
+                None => None,
+            };
+            let (reply_tx, reply_rx) = oneshot::channel();
+            return Ok((Ctrl::Help(info, reply_tx), Some(reply_rx)));
+        },
+
+        "" => bail!("missing command"),
+        _ => match features::missing_for(cmd) {
+            Some(feature) => bail!("'{}' is not compiled in, it needs the {} feature", cmd, feature),
+            None => return Err(UnknownCommand::new(cmd).into()),
+        },
+    };
+
+    Ok((msg, None))
+}
+
+/// Whether the command acts on the monitor under the cursor, by its suffix or the configured `command_target`.
+fn targets_cursor(msg: &Ctrl, configured: CommandTarget) -> bool {
+    match msg {
+        Ctrl::Target(target, _) => *target == CommandTarget::Cursor,
+        Ctrl::ShowTag(_) | Ctrl::ShowTags(_) | Ctrl::ToggleTag(_, _) => configured == CommandTarget::Cursor,
+        _ => false,
+    }
+}
+
+/// Runs `show` or `toggle` on the monitor at `index`.
+fn run_on(state: &mut MonitorsState, profiles: &Profiles, hooks: &mut Hooks, index: usize, msg: Ctrl) -> anyhow::Result<()> {
+    let changes = changes_on(state, profiles, index, msg)?;
+    tracing::debug!(?changes, "show changes");
+    handle_changes(hooks, changes);
+    Ok(())
+}
+
+/// Changes of `show` or `toggle` run on the monitor at `index`, or on the one the tag is pinned to. Several tags
+/// are shown where the lowest of them is pinned.
+fn changes_on(state: &mut MonitorsState, profiles: &Profiles, index: usize, msg: Ctrl) -> anyhow::Result<Changes> {
+    let index = match &msg {
+        Ctrl::ShowTag(tag) | Ctrl::ToggleTag(tag, _) => state.monitor_for_tag(*tag, index),
+        Ctrl::ShowTags(tags) => state.monitor_for_tag(tags.trailing_zeros() as u8 + 1, index),
+        _ => index,
+    };
+    let tags = match msg {
+        Ctrl::ShowTag(tag) => {
+            check_tag(tag, state.tag_count())?;
+            1<<(tag-1)
+        },
+        Ctrl::ShowTags(tags) => {
+            check_tags(tags, state.tag_count())?;
+            tags
+        },
+        Ctrl::ToggleTag(tag, focus) => return state.toggle_tag_on(index, tag, focus),
+        msg => bail!("only show and toggle take a command target, not {:?}", msg),
+    };
+    if profiles.config().show_toggles_back && state.visible_tags_of(index) == Some(tags) {
+        state.restore_prev_tags_on(index)
+    } else {
+        state.set_visible_tags_on(index, tags)
+    }
+}
+
+/// Changes of a command moving windows or showing tags, the same whether it runs or is previewed.
+fn command_changes(state: &mut MonitorsState, profiles: &Profiles, msg: Ctrl) -> anyhow::Result<Vec<Changes>> {
+    let changes = match msg {
+        Ctrl::MoveToTag(tag, window, force) => state.move_window(tag, window, force)?,
+        Ctrl::MoveShow(tag) => return state.move_show(tag, None),
+        Ctrl::MoveToEmpty(follow, _) => {
+            let tag = state.first_empty_tag()?;
+            let mut all_changes = vec![state.move_window(tag, None, false)?];
+            if follow {
+                all_changes.push(state.set_visible_tags(1<<(tag-1))?);
+            }
+            return Ok(all_changes);
+        },
+        Ctrl::CycleTag(forward) => state.cycle_tag(forward)?,
+        msg @ (Ctrl::ShowTag(_) | Ctrl::ShowTags(_) | Ctrl::ToggleTag(_, _)) => {
+            let index = state.active_monitor_index();
+            changes_on(state, profiles, index, msg)?
+        },
+        Ctrl::Target(CommandTarget::Focused, msg) => return command_changes(state, profiles, *msg),
+        Ctrl::RestorePrevTags => state.restore_prev_tags()?,
+        Ctrl::MoveToNextMonitor => {
+            let next = state.next_monitor() as usize;
+            state.active_managed()?;
+            let tag = state.active_tag(next).context("no next monitor")?;
+            let mut changes = state.place_window(next, tag, None)?;
+            // focus follows the window
+            changes.changes.focus = changes.changes.window_added.first().map(|w| w.addr);
+            changes
+        },
+        Ctrl::Place(monitor, tag, window) => {
+            let index = state.resolve_monitor(&monitor)?;
+            state.place_window(index, tag, window)?
+        },
+        Ctrl::ShowOn(monitor, tag, focus) => {
+            let index = state.resolve_monitor(&monitor)?;
+            state.show_on(index, tag, focus)?
+        },
+        Ctrl::Throw(monitor, tag, _) => return Ok(throw(state, &monitor, tag)?.1),
+        Ctrl::FocusLast => state.focus_last()?,
+        Ctrl::Override(msg) => return command_changes(state, profiles, *msg),
+        msg => bail!("{:?} doesn't only move windows and show tags", msg),
+    };
+    Ok(vec![changes])
+}
+
+/// What the command would do, run on a copy of the state: the dispatches it would send, and the visible tags and
+/// windows it would change. Neither the state nor Hyprland see it.
+#[cfg(feature = "json-proto")]
+fn preview(state: &MonitorsState, profiles: &Profiles, msg: Ctrl) -> anyhow::Result<PreviewReply> {
+    let mut copy = state.preview();
+    let all_changes = command_changes(&mut copy, profiles, msg)?;
+    let dispatches: Vec<String> = {
+        let fullscreen = fullscreen_windows().lock().unwrap();
+        all_changes.iter().flat_map(|changes| dispatch::dispatches(changes, &fullscreen)).collect()
+    };
+    let changed = snapshot::diff(&state.snapshot(), &copy.snapshot(), &[], state.tag_count());
+    Ok(PreviewReply { dispatches, visible_tags: changed.visible_tags, windows: changed.windows })
+}
+
+/// Places the focused window on the monitor's tag, then shows the tag there. Placing checks everything showing
+/// could fail on, so either both happen or neither does.
+fn throw(state: &mut MonitorsState, monitor: &str, tag: u8) -> anyhow::Result<(ThrowReply, Vec<Changes>)> {
+    let index = state.resolve_monitor(monitor)?;
+    let window = match state.active_window() {
+        Some(w) => w,
+        None => bail!("no focused window"),
+    };
+    let placed = state.place_window(index, tag, Some(window))?;
+    let shown = state.show_on(index, tag, false)?;
+    let reply = ThrowReply {
+        window: window.to_string(),
+        monitor: state.monitor_name(index).unwrap_or_default().to_string(),
+        tag,
+        visible_tags: state.visible_tags_of(index).unwrap_or_default(),
+    };
+    Ok((reply, vec![placed, shown]))
+}
+
+/// `<name> class=<regex> [title=<regex>] tag=<n> [monitor=<name>] [sticky] [ignore]`, with the regexes compiled.
+fn parse_rule(args: &[&str]) -> anyhow::Result<CompiledRule> {
+    let (name, args) = match args.split_first() {
+        Some(split) => split,
+        None => bail!("require rule add args: <name> class=<regex> [title=<regex>] tag=<n> [monitor=<name>] [sticky] [ignore]"),
+    };
+    let (mut class, mut title, mut tag, mut monitor, mut sticky, mut ignore) = (None, None, None, None, false, false);
+    for arg in args {
+        match arg.split_once('=') {
+            Some(("class", re)) => class = Some(re.to_string()),
+            Some(("title", re)) => title = Some(re.to_string()),
+            Some(("tag", n)) => tag = Some(parse_tag("rule add", &[n])?),
+            Some(("monitor", name)) => monitor = Some(name.to_string()),
+            None if *arg == "sticky" => sticky = true,
+            None if *arg == "ignore" => ignore = true,
+            _ => bail!("invalid rule add arg: {}", arg),
+        }
+    }
+    CompiledRule::new(WindowRule {
+        name: name.to_string(),
+        class: class.context("rule add requires class=<regex>")?,
+        title,
+        tag: tag.context("rule add requires tag=<n>")?,
+        monitor,
+        sticky,
+        ignore,
+    })
+}
+
+/// A tag by its number or its name from `tag_names`. Numbers are checked against the configured count once the
+/// command runs, only those no count allows fail here.
+fn parse_tag(cmd: &str, args: &[&str]) -> anyhow::Result<u8> {
+    match args.first() {
+        Some(arg) => match arg.parse::<u8>() {
+            Ok(tag) if tag > MAX_TAGS => bail!("no such tag: {}, there are at most {} tags", tag, MAX_TAGS),
+            Ok(tag) => Ok(tag),
+            Err(_) if arg.starts_with(|c: char| c.is_ascii_digit()) && arg.chars().all(|c| c.is_ascii_digit()) => {
+                bail!("no such tag: {}, there are at most {} tags", arg, MAX_TAGS)
+            },
+            Err(_) if arg.starts_with(|c: char| c.is_ascii_digit()) => bail!("invalid tag: {}", arg),
+            Err(_) => {
+                let names = tag_names().lock().unwrap();
+                match names.iter().find(|(_, name)| text::same_name(name, arg)) {
+                    Some((tag, _)) => Ok(*tag),
+                    None if names.is_empty() => bail!("unknown tag name: {}, no tags are named", arg),
+                    None => bail!("unknown tag name: {}, expected a number or one of {}", arg,
+                        names.values().map(String::as_str).collect::<Vec<_>>().join(", ")),
+                }
+            },
+        },
+        None => bail!("require {} args", cmd),
+    }
+}
+
+/// Tags of `show 1,3,5` or `show mask:0b10101`, an invalid one failing them all.
+fn parse_tags(cmd: &str, arg: &str) -> anyhow::Result<u64> {
+    let tags = match arg.strip_prefix("mask:") {
+        Some(mask) => {
+            let parsed = match mask.get(..2) {
+                Some("0b") => u64::from_str_radix(&mask[2..], 2),
+                Some("0x") => u64::from_str_radix(&mask[2..], 16),
+                _ => mask.parse(),
+            };
+            parsed.map_err(|_| anyhow!("invalid tag mask: {}", mask))?
+        },
+        None => arg.split(',').try_fold(0u64, |tags, entry| {
+            if entry.is_empty() {
+                bail!("invalid tag list: {}", arg);
+            }
+            let tag = parse_tag(cmd, &[entry])?;
+            check_tag(tag, MAX_TAGS)?;
+            Ok(tags | 1<<(tag - 1))
+        })?,
+    };
+    check_tags(tags, MAX_TAGS)?;
+    Ok(tags)
+}
+
+/// Names commands may give tags by, from the config's `tag_names`.
+pub fn set_tag_names(names: &BTreeMap<u8, String>) {
+    *tag_names().lock().unwrap() = names.clone();
+}
+
+fn tag_names() -> &'static Mutex<BTreeMap<u8, String>> {
+    static TAG_NAMES: OnceLock<Mutex<BTreeMap<u8, String>>> = OnceLock::new();
+    TAG_NAMES.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// Writes a reply, streamed in chunks when it's long.
+async fn write_reply(stream: &mut BufStream<UnixStream>, reply: &str, replies: &mut u64) {
+    *replies += 1;
+    for line in reply_lines(reply, *replies) {
+        write_line(stream, &line).await;
+    }
+}
+
+async fn write_line(stream: &mut BufStream<UnixStream>, line: &str) {
+    let r = async {
+        stream.write_all(line.as_bytes()).await?;
+        stream.write_all(b"\n").await?;
+        stream.flush().await
+    };
+    if let Err(err) = r.await {
+        tracing::error!(%err, "failed to write reply");
+    }
+}
+
+/// Runs a command in the open transaction, which holds back its dispatches until the commit.
+fn run_in_txn(state: &mut MonitorsState, profiles: &Profiles, msg: Ctrl, at_cursor: bool) -> anyhow::Result<()> {
+    if at_cursor || !msg.transactable() {
+        let err = "a transaction is open, commit or abort it first";
+        reject(msg, err.to_string());
+        bail!(err);
+    }
+    let command = match state.source() {
+        ChangeSource::Ctrl { command } => command.clone(),
+        source => source.to_string(),
+    };
+    let changes = command_changes(state, profiles, msg)?;
+    if let Some(txn) = state.txn() {
+        txn.push(Step { command, changes });
+    }
+    Ok(())
+}
+
+/// Sends the dispatches of the open transaction's steps one batch after another, and rolls it back when hyprctl
+/// rejects one.
+fn commit(state: &mut MonitorsState, hooks: &Hooks, reply: oneshot::Sender<String>, tx: mpsc::Sender<Ctrl>) {
+    let (checkpoint, steps) = match state.txn().take() {
+        Some(txn) => txn.end(),
+        None => {
+            let _ = reply.send("err: no transaction is open".to_string());
+            return;
+        },
+    };
+    let commands: Vec<String> = steps.iter().map(|step| step.command.clone()).collect();
+    let done = handle_steps_checked(hooks, steps.into_iter().map(|step| step.changes).collect());
+    tokio::spawn(async move {
+        match done.await {
+            Ok(Ok(())) => {
+                let _ = reply.send("ok".to_string());
+            },
+            Ok(Err(failed)) => {
+                let failure = format!("step {} ({}) failed: {}", failed.step + 1, commands[failed.step], failed.err);
+                if let Err(err) = tx.send(Ctrl::TxnFailed(failure, Box::new(checkpoint), reply)).await {
+                    tracing::error!(%err, "failed to send Ctrl::TxnFailed");
+                }
+            },
+            Err(_) => tracing::error!("transaction dispatches were dropped"),
+        }
+    });
+}
+
+/// Ends the transaction without sending anything, so its commands are undone in the state alone.
+fn abort(state: &mut MonitorsState, txn: Transaction) {
+    let (checkpoint, steps) = txn.end();
+    tracing::info!(steps = steps.len(), "transaction aborted");
+    if let Err(err) = state.rollback(checkpoint) {
+        tracing::error!(%err, "failed to abort the transaction");
+    }
+}
+
+/// Replies with the error to commands which reply, for commands rejected before they ran.
+fn reject(msg: Ctrl, err: String) {
+    match msg {
+        Ctrl::Sync(_, reply) | Ctrl::Stale(_, _, reply) | Ctrl::Summary(reply) | Ctrl::Conflicts(reply) | Ctrl::MoveToEmpty(_, reply) | Ctrl::Throw(_, _, reply)
+        | Ctrl::RuleList(reply) | Ctrl::RuleAdd(_, reply) | Ctrl::RuleRemove(_, reply) | Ctrl::RuleTest(_, reply) | Ctrl::RuleSave(reply)
+        | Ctrl::Txn(_, reply) => {
+            let _ = reply.send(format!("err: {}", err));
+        },
+        Ctrl::Override(msg) => reject(*msg, err),
+        _ => (),
+    }
+}
+
+/// The tag the command acts on, once completed by the prefix.
+fn command_tag(msg: &Ctrl) -> Option<u8> {
+    match msg {
+        Ctrl::ShowTag(tag)
+        | Ctrl::ToggleTag(tag, _)
+        | Ctrl::MoveToTag(tag, _, _)
+        | Ctrl::MoveShow(tag)
+        | Ctrl::Reorder(tag, _)
+        | Ctrl::Mirror(tag, _)
+        | Ctrl::Place(_, tag, _)
+        | Ctrl::ShowOn(_, tag, _)
+        | Ctrl::Throw(_, tag, _)
+        | Ctrl::Stale(_, Some(tag), _)
+        | Ctrl::MoveToCursorMonitor(Some(tag)) => Some(*tag),
+        Ctrl::Target(_, msg) | Ctrl::Override(msg) => command_tag(msg),
+        _ => None,
+    }
+}
+
+/// Tells when the command names an output Hyprland mirrors another monitor onto, which it runs on instead.
+fn mirror_redirect(state: &MonitorsState, msg: &Ctrl) -> Option<String> {
+    match msg {
+        Ctrl::Place(selector, _, _) | Ctrl::ShowOn(selector, _, _) | Ctrl::Throw(selector, _, _) | Ctrl::FocusMonitor(selector) => {
+            let source = state.mirror_source(selector)?;
+            Some(format!("monitor:{} mirrors {}, ran on {}", selector, source, source))
+        },
+        Ctrl::Target(_, msg) | Ctrl::Override(msg) => mirror_redirect(state, msg),
+        _ => None,
+    }
+}
+
+/// The monitor in presentation mode the command would change: the selected monitor for commands taking one,
+/// the active monitor otherwise.
+fn presenting_target<'a>(state: &'a MonitorsState, msg: &Ctrl) -> Option<&'a str> {
+    let index = match msg {
+        Ctrl::Place(selector, _, _) | Ctrl::ShowOn(selector, _, _) | Ctrl::Throw(selector, _, _) | Ctrl::FocusMonitor(selector) => {
+            state.resolve_monitor(selector).ok()?
+        },
+        Ctrl::Sync(msg, _) => return presenting_target(state, msg),
+        Ctrl::Override(_) => return None,
+        Ctrl::TargetQueried(msg, _, _, _) if matches!(**msg, Ctrl::Override(_)) => return None,
+        Ctrl::TargetQueried(_, _, query, _) => state.cursor_target(query.as_ref()).unwrap_or(state.active_monitor_index()),
+        _ => state.active_monitor_index(),
+    };
+    state.is_presenting(index).then(|| state.monitor_name(index)).flatten()
+}
+
+pub(crate) fn handle_ctrl(state: &mut MonitorsState, profiles: &mut Profiles, hooks: &mut Hooks, msg: Ctrl, tx: mpsc::Sender<Ctrl>) {
+    state.set_source(msg.source());
+    let msg = match msg {
+        Ctrl::Request(_, msg) => *msg,
+        msg => msg,
+    };
+    if let Err(err) = handle_ctrl_with(state, profiles, hooks, msg, tx, false, &mut None) {
+        tracing::error!(%err, source = %state.source(), "ctrl command failed");
+    }
+    state.record_placements();
+}
+
+/// Handles the command, which `presenting` lets change monitors in presentation mode. Commands replying on their
+/// own get their error as the reply, `Ctrl::Sync` the error of its command. Commands waiting on a query take the
+/// `sync` reply along, to send once they ran.
+fn handle_ctrl_with(state: &mut MonitorsState, profiles: &mut Profiles, hooks: &mut Hooks, mut msg: Ctrl, tx: mpsc::Sender<Ctrl>, presenting: bool, sync: &mut Option<SyncReply>) -> anyhow::Result<()> {
+    tracing::debug!(?msg, "handle_ctrl");
+    if let Some(submap) = state.submap().filter(|s| profiles.config().suppress_in_submaps.iter().any(|n| n == s)) {
+        if msg.is_user_change() {
+            tracing::info!(submap, ?msg, "ctrl command suppressed");
+            let err = format!("suppressed (submap {})", submap);
+            reject(msg, err.clone());
+            bail!(err);
+        }
+    }
+    if let Some(note) = mirror_redirect(state, &msg) {
+        tracing::info!(note, "ctrl command redirected");
+    }
+    // held dispatches of new windows go out first, the command may move them again
+    if msg.is_user_change() && state.burst().is_on() {
+        events::burst_ended(state, hooks);
+    }
+    // commands acting on the monitor under the cursor are checked once it's known
+    let at_cursor = targets_cursor(&msg, profiles.config().command_target);
+    if msg.is_user_change() && !presenting && !at_cursor {
+        if let Some(monitor) = presenting_target(state, &msg) {
+            tracing::info!(monitor, ?msg, "ctrl command rejected in presentation mode");
+            let err = format!("monitor:{} is in presentation mode, add --override to run it anyway", monitor);
+            reject(msg, err.clone());
+            bail!(err);
+        }
+    }
+    if let Err(err) = pending_prefix().lock().unwrap().apply(&mut msg, state.tag_count(), now()) {
+        reject(msg, err.to_string());
+        return Err(err);
+    }
+    // `show 0` only makes sense after a prefix
+    if let Some(Err(err)) = command_tag(&msg).map(|tag| check_tag(tag, state.tag_count())) {
+        reject(msg, err.to_string());
+        return Err(err);
+    }
+    // a synced command joins the transaction once unwrapped
+    if msg.is_user_change() && !matches!(msg, Ctrl::Sync(_, _)) && state.txn().is_some() {
+        return run_in_txn(state, profiles, msg, at_cursor);
+    }
+    if at_cursor {
+        let msg = match msg {
+            Ctrl::Target(_, msg) => *msg,
+            msg => msg,
+        };
+        let msg = if presenting { Ctrl::Override(Box::new(msg)) } else { msg };
+        state.request_command_target(msg, sync.take(), tx);
+        return Ok(());
+    }
+    match msg {
+        msg @ (Ctrl::MoveToTag(_, _, _)
+        | Ctrl::MoveShow(_)
+        | Ctrl::CycleTag(_)
+        | Ctrl::RestorePrevTags
+        | Ctrl::MoveToNextMonitor
+        | Ctrl::Place(_, _, _)
+        | Ctrl::ShowOn(_, _, _)
+        | Ctrl::FocusLast) => {
+            handle_changes_batched(hooks, command_changes(state, profiles, msg)?);
+        },
+
+        Ctrl::MoveToEmpty(follow, reply) => {
+            let r = state.first_empty_tag().and_then(|tag| Ok((tag, state.move_window(tag, None, false)?)));
+            let (tag, changes) = match r {
+                Ok(r) => r,
+                Err(err) => {
+                    let _ = reply.send(format!("err: {}", err));
+                    return Err(err);
+                },
+            };
+            handle_changes(hooks, changes);
+
+            if follow {
+                match state.set_visible_tags(1<<(tag-1)) {
+                    Ok(changes) => handle_changes(hooks, changes),
+                    Err(err) => tracing::error!(%err, "Ctrl::MoveToEmpty error"),
+                }
+            }
+            let _ = reply.send(tag.to_string());
+        },
+
+        msg @ (Ctrl::ShowTag(_) | Ctrl::ShowTags(_) | Ctrl::ToggleTag(_, _)) => {
+            let index = state.active_monitor_index();
+            run_on(state, profiles, hooks, index, msg)?;
+        },
+
+        Ctrl::Target(_, msg) => {
+            let index = state.active_monitor_index();
+            run_on(state, profiles, hooks, index, *msg)?;
+        },
+
+        Ctrl::TargetQueried(msg, _, query, sync) => {
+            let index = match state.cursor_target(query.as_ref()) {
+                Ok(index) => index,
+                Err(err) => {
+                    tracing::warn!(%err, "acting on the focused monitor instead of the one under the cursor");
+                    state.active_monitor_index()
+                },
+            };
+            let msg = match *msg {
+                Ctrl::Override(msg) => *msg,
+                msg => msg,
+            };
+            let r = run_on(state, profiles, hooks, index, msg);
+            if let Some(sync) = sync {
+                sync.done(&r);
+            }
+            r?;
+        },
+
+        Ctrl::Prefix(digit) => pending_prefix().lock().unwrap().arm(digit, state.tag_count(), now())?,
+
+        Ctrl::AbortPrefix => pending_prefix().lock().unwrap().abort(),
+
+        Ctrl::MoveToCursorMonitor(tag) => match state.active_window() {
+            Some(window) => state.request_cursor_monitor(tag, window, sync.take(), tx),
+            None => bail!("no focused window"),
+        },
+
+        Ctrl::CursorQueried(tag, window, cursor, monitors, sync) => {
+            let r = state.move_to_cursor_monitor(cursor, &monitors, tag, window).map(|changes| handle_changes(hooks, changes));
+            if let Some(sync) = sync {
+                sync.done(&r);
+            }
+            r?;
+        },
+
+        Ctrl::Throw(monitor, tag, reply) => {
+            match throw(state, &monitor, tag) {
+                Ok((thrown, all_changes)) => {
+                    handle_changes_batched(hooks, all_changes);
+                    let _ = reply.send(serde_json::to_string(&thrown).unwrap_or_default());
+                },
+                Err(err) => {
+                    let _ = reply.send(format!("err: {}", err));
+                    return Err(err);
+                },
+            }
+        },
+
+        Ctrl::FocusMonitor(selector) => {
+            let (name, window) = state.resolve_monitor(&selector).and_then(|index| state.focus_monitor(index))?;
+
+            let mut args = vec![
+                format!("dispatch focusmonitor {}", name),
+            ];
+            if let Some(window) = window {
+                args.push(format!("dispatch focuswindow address:0x{}", window));
+                focus_echoes().lock().unwrap().expect(window, now());
+            }
+            hyprctl_batch(args);
+        },
+
+        Ctrl::Promote(window) => state.promote_window(window)?,
+
+        Ctrl::Demote(window) => state.demote_window(window)?,
+
+        Ctrl::Reorder(tag, windows) => state.reorder_tag(tag, windows)?,
+
+        Ctrl::MonitorAdded(monitor) => {
+            // back within its grace period, nothing was torn down
+            if let Some(tx) = state.monitor_returned(&monitor) {
+                state.request_placement_check(tx.clone());
+                events::release_held(state, profiles, hooks, tx);
+                return Ok(());
+            }
+
+            tracing::info!("handle new monitor: {}", monitor.name);
+            if let Err(err) = state.monitor_added_with_object(monitor) {
+                tracing::error!(%err, "failed to add monitor");
+                return Ok(());
+            }
+
+            if profiles.select(&state.monitor_ids()) {
+                profile_changed(state, profiles, hooks);
+            }
+        },
+
+        Ctrl::MonitorsRefreshed(monitors) => {
+            for (name, source) in state.monitors_diff(&monitors).linked {
+                events::monitor_linked(state, hooks, &name, &source);
+            }
+            state.monitors_refreshed(monitors);
+
+            if profiles.select(&state.monitor_ids()) {
+                profile_changed(state, profiles, hooks);
+            }
+        },
+
+        Ctrl::MonitorsReloaded(monitors) => {
+            let diff = state.monitors_diff(&monitors);
+            diff.log();
+            for name in &diff.removed {
+                events::monitor_removed(state, hooks, name);
+            }
+            for (name, source) in &diff.linked {
+                events::monitor_linked(state, hooks, name, source);
+            }
+            state.monitors_refreshed(monitors);
+            state.reassert_monitor_workspaces();
+
+            if profiles.select(&state.monitor_ids()) {
+                profile_changed(state, profiles, hooks);
+            }
+        },
+
+        Ctrl::ClientsRefreshed(clients) => {
+            for changes in state.clients_refreshed(&clients) {
+                handle_changes(hooks, changes);
+            }
+        },
+
+        Ctrl::MonitorGone(name) => {
+            let tx = match state.removals().gone(&name) {
+                Some(tx) => tx,
+                None => return Ok(()),
+            };
+            tracing::info!(monitor = name, "monitor didn't come back");
+            events::monitor_removed(state, hooks, &name);
+
+            if profiles.select(&state.monitor_ids()) {
+                profile_changed(state, profiles, hooks);
+            }
+            events::release_held(state, profiles, hooks, tx);
+        },
+
+        Ctrl::PlacementsChecked(clients) => {
+            let dispatches = state.misplaced_dispatches(&clients);
+            if !dispatches.is_empty() {
+                tracing::info!(windows = dispatches.len(), "moving windows back after the monitor came back");
+                hyprctl_batch(dispatches);
+            }
+        },
+
+        Ctrl::Profile(name) => {
+            if profiles.force(&name, &state.monitor_ids())? {
+                profile_changed(state, profiles, hooks);
+            }
+        },
+
+        Ctrl::BurstEnded => events::burst_ended(state, hooks),
+
+        Ctrl::AdoptionChunk => adopt::send_chunk(hooks, tx),
+
+        Ctrl::AutostartReset(tag) => state.autostart_reset(tag),
+
+        Ctrl::AutostartExpired => state.autostart_expired(),
+
+        Ctrl::AutostartFailed(tag, command, reason) => state.autostart_failed(tag, &command, reason),
+
+        Ctrl::FocusSettled(window) => {
+            if let Err(err) = state.focus_settled(window) {
+                tracing::debug!(%err, "Ctrl::FocusSettled error");
+            }
+        },
+
+        Ctrl::Activate(class, cmd) => {
+            if let Some(window) = state.app_window(&class) {
+                handle_changes(hooks, state.goto_window(&window)?);
+                return Ok(());
+            }
+
+            if let Some(tag) = profiles.config().app_tags.get(&class) {
+                handle_changes(hooks, state.prepare_launch(&class, *tag)?);
+            }
+            hooks::exec(&cmd);
+        },
+
+        Ctrl::Mirror(tag, monitor) => handle_changes(hooks, state.mirror(tag, &monitor)?),
+
+        Ctrl::Unmirror => handle_changes(hooks, state.unmirror()?),
+
+        Ctrl::Mode(mode) => handle_changes(hooks, state.set_view_mode(mode)?),
+
+        Ctrl::Overlay(true, window) => handle_changes(hooks, state.overlay_add(window)?),
+
+        Ctrl::Overlay(false, window) => state.overlay_remove(window)?,
+
+        Ctrl::Presentation(on, monitor) => {
+            let index = monitor.map(|selector| state.resolve_monitor(&selector)).transpose()?;
+            state.set_presentation(on, index)?;
+            tracing::info!(on, "presentation mode");
+        },
+
+        Ctrl::Override(msg) => return handle_ctrl_with(state, profiles, hooks, *msg, tx, true, sync),
+
+        Ctrl::Request(_, msg) => return handle_ctrl_with(state, profiles, hooks, *msg, tx, presenting, sync),
+
+        Ctrl::Stale(secs, tag, reply) => {
+            let stale = state.stale_windows(secs);
+            if let Some(tag) = tag {
+                let windows: Vec<WindowAddr> = stale.iter().map(|(w, _)| *w).collect();
+                for changes in state.move_windows(tag, &windows) {
+                    handle_changes(hooks, changes);
+                }
+            }
+
+            let windows: Vec<StaleWindow> = stale.iter().map(|(w, t)| StaleWindow {
+                addr: w.to_string(),
+                opened_at: t.opened_at,
+                last_focus: t.last_focus,
+            }).collect();
+            let _ = reply.send(serde_json::to_string(&windows).unwrap_or_default());
+        },
+
+        Ctrl::Summary(reply) => {
+            let report = state.summary(&profiles.config().tag_names);
+            let _ = reply.send(serde_json::to_string(&report).unwrap_or_default());
+        },
+
+        Ctrl::SummaryReset => {
+            state.reset_summary();
+            tracing::info!("summary reset");
+        },
+
+        Ctrl::Conflicts(reply) => {
+            let conflicts: Vec<WindowConflict> = state.provenance().conflicts().into_iter().map(|(w, c)| WindowConflict {
+                addr: w.to_string(),
+                conflict: c.clone(),
+                message: c.conflict.to_string(),
+            }).collect();
+            let _ = reply.send(serde_json::to_string(&conflicts).unwrap_or_default());
+        },
+
+        Ctrl::RuleList(reply) => {
+            let _ = reply.send(serde_json::to_string(&state.rules().list()).unwrap_or_default());
+        },
+
+        Ctrl::RuleAdd(rule, reply) => {
+            let name = rule.rule.name.clone();
+            let replaced = state.rules_mut().add(*rule);
+            tracing::info!(name, replaced, "window rule added");
+            let _ = reply.send("ok".to_string());
+        },
+
+        Ctrl::RuleRemove(name, reply) => {
+            let removed = state.rules_mut().remove(&name);
+            let _ = reply.send(match removed {
+                Ok(()) => "ok".to_string(),
+                Err(err) => format!("err: {}", err),
+            });
+        },
+
+        Ctrl::RuleTest(RuleSubject::Class(class), reply) => {
+            let _ = reply.send(serde_json::to_string(&state.rules().test(&class, "")).unwrap_or_default());
+        },
+
+        Ctrl::RuleTest(RuleSubject::Window(window), reply) => {
+            let rules = state.rules().clone();
+            tokio::spawn(async move {
+                let clients = match hyprctl_clients().await {
+                    Ok(clients) => clients,
+                    Err(err) => {
+                        let _ = reply.send(format!("err: {}", err));
+                        return;
+                    },
+                };
+                let _ = reply.send(match clients.iter().find(|c| c.window().is_ok_and(|w| w == window)) {
+                    Some(client) => serde_json::to_string(&rules.test(&client.class, &client.title)).unwrap_or_default(),
+                    None => format!("err: no window:{}", window),
+                });
+            });
+        },
+
+        Ctrl::RuleSave(reply) => {
+            let saved = profiles.config().runtime_rules_path()
+                .context("no config directory to save rules in")
+                .and_then(|path| Ok((state.rules().save(&path)?, path)));
+            let _ = reply.send(match saved {
+                Ok((saved, path)) => serde_json::to_string(&RuleSaveReply { saved, path }).unwrap_or_default(),
+                Err(err) => format!("err: {:#}", err),
+            });
+        },
+
+        Ctrl::Txn(TxnOp::Begin, reply) => {
+            let r = match state.txn() {
+                Some(_) => "err: a transaction is already open".to_string(),
+                None => {
+                    let checkpoint = state.checkpoint();
+                    *state.txn() = Some(Transaction::begin(checkpoint, tx));
+                    "ok".to_string()
+                },
+            };
+            let _ = reply.send(r);
+        },
+
+        Ctrl::Txn(TxnOp::Commit, reply) => commit(state, hooks, reply, tx),
+
+        Ctrl::Txn(TxnOp::Abort, reply) => {
+            let r = match state.txn().take() {
+                Some(txn) => {
+                    abort(state, txn);
+                    "ok".to_string()
+                },
+                None => "err: no transaction is open".to_string(),
+            };
+            let _ = reply.send(r);
+        },
+
+        Ctrl::TxnExpired(id) => {
+            if state.txn().as_ref().is_some_and(|txn| txn.id() == id) {
+                tracing::warn!(timeout = ?TXN_TIMEOUT, "transaction left open, aborting it");
+                if let Some(txn) = state.txn().take() {
+                    abort(state, txn);
+                }
+            }
+        },
+
+        Ctrl::TxnFailed(failure, checkpoint, reply) => {
+            tracing::error!(failure, "transaction failed, rolling back");
+            let r = match state.rollback(*checkpoint) {
+                Ok(all_changes) => {
+                    handle_changes_batched(hooks, all_changes);
+                    format!("err: {}, rolled back", failure)
+                },
+                Err(err) => format!("err: {}, rollback failed: {}", failure, err),
+            };
+            let _ = reply.send(r);
+        },
+
+        Ctrl::Sync(msg, reply) => {
+            let ok = match mirror_redirect(state, &msg) {
+                Some(note) => format!("ok ({})", note),
+                None => "ok".to_string(),
+            };
+            let mut sync = Some(SyncReply { reply, ok });
+            let r = handle_ctrl_with(state, profiles, hooks, *msg, tx, presenting, &mut sync);
+            // unless the command took it along to reply once its query came back
+            if let Some(sync) = sync {
+                sync.done(&r);
+            }
+            r?;
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Badges(reply) => {
+            let _ = reply.send(serde_json::to_string(&state.badges()).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Outputs(reply) => {
+            let _ = reply.send(serde_json::to_string(&state.outputs(profiles.config())).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Pager(reply) => {
+            let _ = reply.send(serde_json::to_string(&state.pager(profiles.config())).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Subscribe(subscribed) => {
+            let _ = subscribed.send(subscribe::subscribe(state, profiles.config()));
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Preview(msg, reply) => {
+            let previewed = match preview(state, profiles, *msg) {
+                Ok(previewed) => serde_json::to_string(&previewed).unwrap_or_default(),
+                Err(err) => format!("err: {}", err),
+            };
+            let _ = reply.send(previewed);
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Explain(window, reply) => {
+            let history = state.provenance().history(&window);
+            let conflict = state.provenance().conflict(&window);
+            if history.is_none() && conflict.is_none() {
+                let _ = reply.send(format!("err: no assignments known for window:{}", window));
+                return Ok(());
+            }
+            let explained = ExplainReply {
+                window: window.to_string(),
+                current: history.and_then(|h| h.back()).cloned(),
+                history: history.into_iter().flatten().cloned().collect(),
+                conflict: conflict.cloned(),
+            };
+            let _ = reply.send(serde_json::to_string(&explained).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Snapshot(reply) => {
+            let now = StateSnapshot { seq: dispatch::seq(), ..state.snapshot() };
+            let presenting = state.presenting_monitor_ids();
+            let redaction = profiles.config().presentation.clone();
+            tokio::spawn(async move {
+                match Snapshot::take(now).await {
+                    Ok(mut snapshot) => {
+                        snapshot.redact(&presenting, &redaction);
+                        let _ = reply.send(serde_json::to_string(&snapshot).unwrap_or_default());
+                    },
+                    Err(err) => tracing::error!(%err, "Ctrl::Snapshot error"),
+                }
+            });
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Query(full, reply) => {
+            let query = QueryReply {
+                seq: dispatch::seq(),
+                profile: profiles.active().map(str::to_string),
+                pending_prefix: pending_prefix().lock().unwrap().pending(now()),
+                healthy: crate::health::dispatch_health().lock().unwrap().healthy(),
+                unknown_events: UNKNOWN_EVENTS.load(Ordering::Relaxed),
+                ..state.query(profiles.config(), full)
+            };
+            let _ = reply.send(serde_json::to_string(&query).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Config(reply) => {
+            let config = ConfigReply {
+                source: profiles.config().source.clone(),
+                profile: profiles.active().map(str::to_string),
+                config: profiles.config().clone(),
+            };
+            let _ = reply.send(serde_json::to_string(&config).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Help(info, reply) => {
+            let help = match info {
+                Some(info) => serde_json::to_string(info),
+                None => serde_json::to_string(protocol::COMMANDS),
+            };
+            let _ = reply.send(help.unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::ConfigCheck(path, reply) => {
+            let report = Config::check_file(&path);
+            let _ = reply.send(serde_json::to_string(&report).unwrap_or_default());
+        },
+
+        #[cfg(feature = "json-proto")]
+        Ctrl::Diff(before, reply) => {
+            let (now, tag_count) = (state.snapshot(), state.tag_count());
+            tokio::spawn(async move {
+                match hyprctl::hyprctl_clients().await {
+                    Ok(clients) => {
+                        let report = snapshot::diff(&before.state, &now, &clients, tag_count);
+                        let _ = reply.send(serde_json::to_string(&report).unwrap_or_default());
+                    },
+                    Err(err) => tracing::error!(%err, "Ctrl::Diff error"),
+                }
+            });
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::{Arc, Mutex}, time::Duration};
+
+    use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::UnixStream, sync::{mpsc, oneshot}};
+
+    use crate::{
+        addr,
+        auth::{Access, Peer},
+        config::{CommandTarget, Config},
+        events::{handle_event, Event},
+        hooks::Hooks,
+        hyprctl::{CursorPos, MonitorInfo},
+        idempotency::{RecentIds, RECENT_IDS},
+        line::MAX_LINE,
+        monitor::{ChangeSource, Monitor, MonitorsState, ViewMode},
+        profile::Profiles,
+        profile_changed,
+        protocol::COMMANDS,
+        state::DEFAULT_TAG_COUNT,
+    };
+
+    use super::{handle_ctrl, handle_ctrl_socket, listen_at, parse_request, set_tag_names, Ctrl, RuleSubject, UnknownCommand};
+
+    const OWNER: Peer = Peer { uid: Some(1000), access: Access::Full };
+
+    fn info(id: u8, name: &str, focused: bool) -> MonitorInfo {
+        MonitorInfo { id, name: name.into(), focused, ..Default::default() }
+    }
+
+    /// DP-1, focused.
+    fn one_monitor() -> MonitorsState {
+        MonitorsState::new(vec![info(0, "DP-1", true)], DEFAULT_TAG_COUNT)
+    }
+
+    /// DP-1, focused, and DP-2.
+    fn two_monitors() -> MonitorsState {
+        MonitorsState::new(vec![info(0, "DP-1", true), info(1, "DP-2", false)], DEFAULT_TAG_COUNT)
+    }
+
+    #[tokio::test]
+    async fn reject_long_line() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let (client, server) = UnixStream::pair().unwrap();
+        let handler = tokio::spawn(handle_ctrl_socket(tx, Arc::new(Mutex::new(RecentIds::new(RECENT_IDS))), server, OWNER));
+
+        let mut client = BufReader::new(client);
+        client.get_mut().write_all(b"show 2\n").await.unwrap();
+        match rx.recv().await {
+            Some(Ctrl::Request(line, msg)) if line == "show 2" => match *msg {
+                Ctrl::Sync(msg, reply) if matches!(*msg, Ctrl::ShowTag(2)) => reply.send("ok".into()).unwrap(),
+                msg => panic!("unexpected {:?}", msg),
+            },
+            msg => panic!("unexpected {:?}", msg),
+        }
+        let mut reply = String::new();
+        client.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "ok\n");
+
+        // the connection gets reset after the reply, as the rest of the line is never read
+        client.get_mut().write_all(&vec![b'x'; MAX_LINE + 1024]).await.unwrap();
+        let mut reply = String::new();
+        client.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "err: line too long\n");
+        handler.await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn connections_end_with_listener() {
+        let path = std::env::temp_dir().join(format!("hyprtag-ctrl-{}.sock", std::process::id()));
+        let (tx, mut rx) = mpsc::channel(10);
+        let listener = tokio::spawn({
+            let path = path.clone();
+            async move { listen_at(&path, tx).await }
+        });
+
+        let mut client = loop {
+            match UnixStream::connect(&path).await {
+                Ok(client) => break client,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        client.write_all(b"show 2\n").await.unwrap();
+        assert!(matches!(rx.recv().await, Some(Ctrl::Request(line, _)) if line == "show 2"));
+
+        // a session going away takes its connections down, instead of leaving them on the old channel
+        listener.abort();
+        let mut reply = String::new();
+        assert_eq!(BufReader::new(client).read_line(&mut reply).await.unwrap(), 0);
+        assert!(rx.recv().await.is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicate_id() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_ctrl_socket(tx, Arc::new(Mutex::new(RecentIds::new(RECENT_IDS))), server, OWNER));
+        let mut client = BufReader::new(client);
+
+        let mut request = async |line: &str| {
+            client.get_mut().write_all(line.as_bytes()).await.unwrap();
+            let mut reply = String::new();
+            client.read_line(&mut reply).await.unwrap();
+            reply
+        };
+
+        // commands with an id reply once their dispatches ran
+        let daemon = tokio::spawn(async move {
+            let mut runs = vec![];
+            while let Some(Ctrl::Request(_, msg)) = rx.recv().await {
+                if let Ctrl::Sync(msg, reply) = *msg {
+                    runs.push(*msg);
+                    reply.send("ok".into()).unwrap();
+                }
+            }
+            runs
+        });
+
+        assert_eq!(request("id:a move 3\n").await, "ok\n");
+        assert_eq!(request("id:a move 3\n").await, "ok\n");
+        assert_eq!(request("id:a move 4\n").await, "err: id was used for a different command\n");
+        assert_eq!(request("id:b bogus\n").await, "err: unknown command 'bogus'\n");
+        assert_eq!(request("id:b bogus\n").await, "err: unknown command 'bogus'\n");
+        drop(client);
+
+        let runs = daemon.await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert!(matches!(runs[0], Ctrl::MoveToTag(3, None, false)));
+    }
+
+    #[test]
+    fn unknown_command_suggestions() {
+        let err = parse_request("sho 2").unwrap_err();
+        assert_eq!(err.to_string(), "unknown command 'sho', did you mean 'show'?");
+        let unknown = |cmd: &str| parse_request(cmd).unwrap_err().downcast::<UnknownCommand>().unwrap();
+        assert_eq!(unknown("mvoe 3").suggestion, Some("move"));
+        assert_eq!(unknown("snapshoot").suggestion, Some("snapshot"));
+        assert_eq!(unknown("unmirorr").suggestion, Some("unmirror"));
+        // too far from anything, or too short to tell
+        assert_eq!(unknown("xyzzy").suggestion, None);
+        assert_eq!(unknown("x").suggestion, None);
+        assert_eq!(unknown("xyzzy").to_string(), "unknown command 'xyzzy'");
+
+        // known commands may fail on their args, but never as unknown
+        for cmd in COMMANDS {
+            if let Err(err) = parse_request(&cmd.name) {
+                assert!(!err.is::<UnknownCommand>(), "{}", cmd.name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn peer_access() {
+        let connect = |access| {
+            let (tx, rx) = mpsc::channel(10);
+            let (client, server) = UnixStream::pair().unwrap();
+            let handler = tokio::spawn(handle_ctrl_socket(tx, Arc::new(Mutex::new(RecentIds::new(RECENT_IDS))), server, Peer { uid: Some(1005), access }));
+            (BufReader::new(client), rx, handler)
+        };
+
+        // turned away before reading anything
+        let (mut client, mut rx, handler) = connect(Access::Denied);
+        let mut reply = String::new();
+        client.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "err: permission denied\n");
+        handler.await.unwrap();
+        assert!(rx.recv().await.is_none());
+
+        // queries go through, changes don't, not even with an id
+        let (mut client, mut rx, _handler) = connect(Access::ReadOnly);
+        client.get_mut().write_all(b"show 2\nid:a move 3\nstale 60\n").await.unwrap();
+        for _ in 0..2 {
+            let mut reply = String::new();
+            client.read_line(&mut reply).await.unwrap();
+            assert_eq!(reply, "err: permission denied, read-only access\n");
+        }
+        assert!(matches!(rx.recv().await, Some(Ctrl::Request(line, msg)) if line == "stale 60" && matches!(*msg, Ctrl::Stale(60, None, _))));
+    }
+
+    #[tokio::test]
+    async fn unknown_command_reply() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(handle_ctrl_socket(tx, Arc::new(Mutex::new(RecentIds::new(RECENT_IDS))), server, OWNER));
+        let mut client = BufReader::new(client);
+
+        // replied to without an id, like every command
+        client.get_mut().write_all(b"sho 2\nshow 3x\nid: show 2\nshow 2\n").await.unwrap();
+        let mut reply = String::new();
+        client.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "err: unknown command 'sho', did you mean 'show'?\n");
+        // so are commands failing to parse, and a bad id
+        reply.clear();
+        client.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "err: invalid tag: 3x\n");
+        reply.clear();
+        client.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "err: id needs a key and a command\n");
+        assert!(matches!(rx.recv().await, Some(Ctrl::Request(_, msg)) if matches!(&*msg, Ctrl::Sync(msg, _) if matches!(**msg, Ctrl::ShowTag(2)))));
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[tokio::test]
+    async fn subscribe() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = one_monitor();
+        let (tx, mut rx) = mpsc::channel(10);
+        let (client, server) = UnixStream::pair().unwrap();
+        let handler = tokio::spawn(handle_ctrl_socket(tx, Arc::new(Mutex::new(RecentIds::new(RECENT_IDS))), server, OWNER));
+        let mut client = BufReader::new(client);
+
+        client.get_mut().write_all(b"subscribe\nshow 2\n").await.unwrap();
+        let msg = rx.recv().await.unwrap();
+        assert!(matches!(&msg, Ctrl::Request(line, msg) if line == "subscribe" && matches!(**msg, Ctrl::Subscribe(_))));
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), msg, mpsc::channel(1).0);
+
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!((&line["event"], &line["monitor"], &line["focused"]), (&serde_json::json!("monitor"), &serde_json::json!("DP-1"), &serde_json::json!(true)));
+        assert!(line["source"].is_null());
+
+        // what a subscriber sends isn't run, and hanging up ends it
+        drop(client);
+        handler.await.unwrap();
+        assert!(rx.recv().await.is_none());
+        assert!(parse_request("subscribe now").is_err());
+    }
+
+    #[tokio::test]
+    async fn change_sources() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut hooks = Hooks::new(vec![]);
+        let monitors = vec![info(0, "DP-1", true)];
+        let mut state = MonitorsState::new(monitors.clone(), DEFAULT_TAG_COUNT);
+        let (tx, _rx) = mpsc::channel(10);
+
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::Request("show 2".into(), Box::new(Ctrl::ShowTag(2))), mpsc::channel(1).0);
+        assert_eq!(state.source(), &ChangeSource::Ctrl { command: "show 2".into() });
+
+        handle_event(&mut state, &mut profiles, &mut hooks, Event::ActiveWindow(addr("aa")), tx);
+        assert_eq!(state.source(), &ChangeSource::Event { event: "activewindowv2".into() });
+
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::MonitorsReloaded(monitors), mpsc::channel(1).0);
+        assert_eq!(state.source(), &ChangeSource::Reconcile);
+
+        // the changes made on the way carry it
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::Request("toggle 1".into(), Box::new(Ctrl::ToggleTag(1, false))), mpsc::channel(1).0);
+        assert_eq!(state.set_visible_tags(0b1).unwrap().source, ChangeSource::Ctrl { command: "toggle 1".into() });
+    }
+
+    fn show_twice(show_toggles_back: bool) -> u64 {
+        let config = Config::parse(&format!(r#"{{"show_toggles_back": {}}}"#, show_toggles_back)).unwrap();
+        let mut profiles = Profiles::new(config);
+        let mut hooks = Hooks::new(vec![]);
+        let mut state = one_monitor();
+
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(2), mpsc::channel(1).0);
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(3), mpsc::channel(1).0);
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(3), mpsc::channel(1).0);
+        state.visible_tags()
+    }
+
+    #[tokio::test]
+    async fn show_toggles_back() {
+        assert_eq!(show_twice(false), 0b100);
+        assert_eq!(show_twice(true), 0b10);
+    }
+
+    #[tokio::test]
+    async fn show_several_tags() {
+        assert!(matches!(parse_request("show 1,3").unwrap(), (Ctrl::ShowTags(0b101), None)));
+        assert!(matches!(parse_request("show mask:0b10101").unwrap(), (Ctrl::ShowTags(0b10101), None)));
+        assert!(matches!(parse_request("show mask:0x11").unwrap(), (Ctrl::ShowTags(0b10001), None)));
+        assert!(matches!(parse_request("show@cursor 1,3").unwrap(), (Ctrl::Target(CommandTarget::Cursor, msg), None) if matches!(*msg, Ctrl::ShowTags(0b101))));
+        // one bad entry fails the whole command
+        assert_eq!(parse_request("show 1,70").unwrap_err().to_string(), "no such tag: 70, there are at most 64 tags");
+        assert_eq!(parse_request("show 1,,3").unwrap_err().to_string(), "invalid tag list: 1,,3");
+        assert_eq!(parse_request("show mask:0b0").unwrap_err().to_string(), "no tags given");
+        assert_eq!(parse_request("show mask:12z").unwrap_err().to_string(), "invalid tag mask: 12z");
+
+        let mut profiles = Profiles::new(Config::default());
+        let mut hooks = Hooks::new(vec![]);
+        let mut state = one_monitor();
+        // tags past the state's count fail once the command runs
+        for (line, err) in [("show 1,40", "err: no such tag: 40, tags go up to 32"), ("show mask:0x100000000", "err: no such tag: 33, tags go up to 32")] {
+            assert_eq!(sync(&mut state, &mut profiles, parse_request(line).unwrap().0).await.unwrap(), err);
+        }
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(2), mpsc::channel(1).0);
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTags(0b101), mpsc::channel(1).0);
+        assert_eq!(state.visible_tags(), 0b101);
+        // a single change to go back from
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::RestorePrevTags, mpsc::channel(1).0);
+        assert_eq!(state.visible_tags(), 0b10);
+    }
+
+    #[tokio::test]
+    async fn show_pinned_tag() {
+        let config = Config::parse(r#"{"tags": {"9": {"monitor": "DP-2"}, "5": {"monitor": "HDMI-A-1"}}}"#).unwrap();
+        let mut profiles = Profiles::new(config);
+        let mut state = two_monitors();
+        state.set_tag_monitors(profiles.config().tag_monitors());
+
+        // on the monitor it's pinned to, from the focused one
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(9)).await.unwrap(), "ok");
+        assert_eq!((state.visible_tags_of(0), state.visible_tags_of(1)), (Some(0b1), Some(1<<8)));
+        state.set_visible_tags_on(1, 0b1).unwrap();
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ToggleTag(9, false)).await.unwrap(), "ok");
+        assert_eq!((state.visible_tags_of(0), state.visible_tags_of(1)), (Some(0b1), Some(1<<8 | 0b1)));
+        // the focused monitor stands in for one which isn't connected
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(5)).await.unwrap(), "ok");
+        assert_eq!(state.visible_tags_of(0), Some(1<<4));
+    }
+
+    #[tokio::test]
+    async fn invalid_tags() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = one_monitor();
+        state.window_opened("7e3".parse().unwrap(), "kitty", "").unwrap();
+
+        // as a keybind would send them, past the parsing
+        for tag in [0, 33, 255] {
+            let err = format!("err: no such tag: {}, tags go up to 32", tag);
+            for msg in [
+                Ctrl::ShowTag(tag),
+                Ctrl::ToggleTag(tag, true),
+                Ctrl::MoveToTag(tag, None, false),
+                Ctrl::ShowOn("DP-1".into(), tag, true),
+                Ctrl::Target(CommandTarget::Focused, Box::new(Ctrl::ShowTag(tag))),
+            ] {
+                assert_eq!(sync(&mut state, &mut profiles, msg).await.unwrap(), err);
+            }
+        }
+        assert_eq!(state.visible_tags_of(0), Some(0b1));
+
+        // a prefix makes `show 0` tag 10
+        sync(&mut state, &mut profiles, Ctrl::Prefix(1)).await.unwrap();
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(0)).await.unwrap(), "ok");
+        assert_eq!(state.visible_tags_of(0), Some(1<<9));
+
+        // checked against the count the state was made with
+        let mut state = MonitorsState::new(vec![info(0, "DP-1", true)], 40);
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(33)).await.unwrap(), "ok");
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(41)).await.unwrap(), "err: no such tag: 41, tags go up to 40");
+    }
+
+    #[tokio::test]
+    async fn command_errors() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = one_monitor();
+
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::MoveToTag(2, None, false)).await.unwrap(), "err: no focused window");
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::MoveToTag(1, None, false)).await.unwrap(), "err: the window is already in dest tag");
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::Prefix(2)).await.unwrap(), "ok");
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::Profile("work".into())).await.unwrap(), "err: no such profile: work");
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::Prefix(0)).await.unwrap(), "err: invalid prefix: 0, tags only go up to 32");
+    }
+
+    /// Runs the command as `sync`, returning the receiver of its reply.
+    fn sync(state: &mut MonitorsState, profiles: &mut Profiles, msg: Ctrl) -> oneshot::Receiver<String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        handle_ctrl(state, profiles, &mut Hooks::new(vec![]), Ctrl::Sync(Box::new(msg), reply_tx), mpsc::channel(1).0);
+        reply_rx
+    }
+
+    #[tokio::test]
+    async fn suppress_in_submap() {
+        let config = Config::parse(r#"{"suppress_in_submaps": ["resize"]}"#).unwrap();
+        let mut profiles = Profiles::new(config);
+        let mut state = one_monitor();
+
+        state.submap_changed("resize");
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(2)).await.unwrap(), "err: suppressed (submap resize)");
+        assert_eq!(state.visible_tags(), 0b1);
+        // queries keep working
+        #[cfg(feature = "json-proto")]
+        {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::Badges(reply_tx), mpsc::channel(1).0);
+            assert!(reply_rx.await.is_ok());
+        }
+
+        // other submaps don't suppress anything
+        state.submap_changed("launch");
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(2)).await.unwrap(), "ok");
+        assert_eq!(state.visible_tags(), 0b10);
+
+        state.submap_changed("resize");
+        assert!(sync(&mut state, &mut profiles, Ctrl::ShowTag(3)).await.unwrap().starts_with("err: suppressed"));
+        state.submap_changed("");
+        assert_eq!(state.submap(), None);
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowTag(3)).await.unwrap(), "ok");
+        assert_eq!(state.visible_tags(), 0b100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn txn_timeout() {
+        use crate::txn::{TxnOp, TXN_TIMEOUT};
+
+        assert!(matches!(parse_request("txn begin").unwrap().0, Ctrl::Txn(TxnOp::Begin, _)));
+        assert!(matches!(parse_request("txn commit").unwrap().0, Ctrl::Txn(TxnOp::Commit, _)));
+        assert!(matches!(parse_request("txn abort").unwrap().0, Ctrl::Txn(TxnOp::Abort, _)));
+        assert!(parse_request("txn bogus").is_err());
+
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = one_monitor();
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let run = |state: &mut MonitorsState, profiles: &mut Profiles, line: &str| {
+            let (msg, reply_rx) = parse_request(line).unwrap();
+            handle_ctrl(state, profiles, &mut Hooks::new(vec![]), msg, tx.clone());
+            reply_rx
+        };
+
+        assert_eq!(run(&mut state, &mut profiles, "txn begin").unwrap().await.unwrap(), "ok");
+        assert_eq!(run(&mut state, &mut profiles, "txn begin").unwrap().await.unwrap(), "err: a transaction is already open");
+        run(&mut state, &mut profiles, "move 3");
+        assert_eq!(state.first_empty_tag().unwrap(), 1);
+        assert_eq!(run(&mut state, &mut profiles, "move_to_empty").unwrap().await.unwrap(), "err: a transaction is open, commit or abort it first");
+
+        // abandoned, so it goes back
+        tokio::time::advance(TXN_TIMEOUT).await;
+        let msg = rx.recv().await.unwrap();
+        assert!(matches!(msg, Ctrl::TxnExpired(_)));
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), msg, tx.clone());
+        assert_eq!(state.first_empty_tag().unwrap(), 2);
+        assert_eq!(run(&mut state, &mut profiles, "txn abort").unwrap().await.unwrap(), "err: no transaction is open");
+    }
+
+    #[tokio::test]
+    async fn mirror_redirect() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = MonitorsState::new(vec![
+            info(0, "DP-1", true),
+            MonitorInfo { mirror_of: Some(0), ..info(1, "HDMI-A-1", false) },
+        ], DEFAULT_TAG_COUNT);
+
+        let reply = sync(&mut state, &mut profiles, Ctrl::ShowOn("HDMI-A-1".into(), 2, true)).await.unwrap();
+        assert_eq!(reply, "ok (monitor:HDMI-A-1 mirrors DP-1, ran on DP-1)");
+        assert_eq!(state.visible_tags(), 0b10);
+        assert_eq!(sync(&mut state, &mut profiles, Ctrl::ShowOn("DP-1".into(), 3, true)).await.unwrap(), "ok");
+    }
+
+    /// Parses the command, then runs it as `sync`.
+    fn request(state: &mut MonitorsState, profiles: &mut Profiles, line: &str) -> oneshot::Receiver<String> {
+        let (msg, _) = parse_request(line).unwrap();
+        sync(state, profiles, msg)
+    }
+
+    #[tokio::test]
+    async fn presentation_mode() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = two_monitors();
+
+        assert_eq!(request(&mut state, &mut profiles, "presentation on DP-1").await.unwrap(), "ok");
+        assert!(state.snapshot().monitors[0].presentation);
+        assert!(!state.snapshot().monitors[1].presentation);
+        assert_eq!(
+            request(&mut state, &mut profiles, "show 2").await.unwrap(),
+            "err: monitor:DP-1 is in presentation mode, add --override to run it anyway",
+        );
+        assert_eq!(state.visible_tags_of(0), Some(0b1));
+        // commands selecting another monitor still run
+        assert_eq!(request(&mut state, &mut profiles, "show_on DP-2 3 nofocus").await.unwrap(), "ok");
+        assert_eq!(state.visible_tags_of(1), Some(0b100));
+        assert!(request(&mut state, &mut profiles, "show_on DP-1 3").await.unwrap().starts_with("err: monitor:DP-1"));
+
+        assert_eq!(request(&mut state, &mut profiles, "show 2 --override").await.unwrap(), "ok");
+        assert_eq!(state.visible_tags_of(0), Some(0b10));
+        assert_eq!(state.presenting_monitor_ids(), vec![0]);
+
+        // everywhere, which a single monitor can't opt out of
+        assert_eq!(request(&mut state, &mut profiles, "presentation on").await.unwrap(), "ok");
+        assert_eq!(state.presenting_monitor_ids(), vec![0, 1]);
+        request(&mut state, &mut profiles, "presentation off DP-2").await.unwrap();
+        assert!(request(&mut state, &mut profiles, "show_on DP-2 4").await.unwrap().starts_with("err: monitor:DP-2"));
+
+        assert_eq!(request(&mut state, &mut profiles, "presentation off").await.unwrap(), "ok");
+        assert!(state.presenting_monitor_ids().is_empty());
+        assert_eq!(request(&mut state, &mut profiles, "show 3").await.unwrap(), "ok");
+        assert_eq!(state.visible_tags_of(0), Some(0b100));
+
+        assert!(parse_request("presentation").is_err());
+        assert!(parse_request("presentation maybe").is_err());
+        assert!(matches!(parse_request("move 3 --force --override").unwrap().0, Ctrl::Override(msg) if matches!(*msg, Ctrl::MoveToTag(3, None, true))));
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[tokio::test]
+    async fn outputs() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = MonitorsState::new(vec![
+            MonitorInfo { description: "Dell U2720Q".into(), ..info(0, "DP-1", true) },
+            info(3, "HDMI-A-1", false),
+        ], DEFAULT_TAG_COUNT);
+        state.set_unmanaged_monitors(vec!["HDMI-A-1".into()]);
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::ToggleTag(3, false), mpsc::channel(1).0);
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::Outputs(reply_tx), mpsc::channel(1).0);
+        let mut outputs: serde_json::Value = serde_json::from_str(&reply_rx.await.unwrap()).unwrap();
+        for output in outputs.as_array_mut().unwrap() {
+            let layout = output.as_object_mut().unwrap().remove("layout").unwrap();
+            assert_eq!(layout[0]["tags"].as_array().unwrap().len(), 32);
+        }
+        assert_eq!(outputs, serde_json::json!([
+            {
+                "index": 0, "name": "DP-1", "description": "Dell U2720Q", "id": 0, "managed": true,
+                "visible_workspace": 1, "hidden_workspace_base": 100, "visible_tags": 0b101, "tag_count": 32,
+            },
+            {
+                "index": 1, "name": "HDMI-A-1", "description": "", "id": 3, "managed": false,
+                "visible_workspace": 2, "hidden_workspace_base": 132, "visible_tags": 1, "tag_count": 32,
+            },
+        ]));
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[tokio::test]
+    async fn query() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = two_monitors();
+        state.set_tag_names(&BTreeMap::from([(3, "mail".to_string())]));
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        state.window_opened(addr("1de"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::MoveToTag(3, None, false), mpsc::channel(1).0);
+
+        let mut query = |line: &str| {
+            let (msg, reply_rx) = parse_request(line).unwrap();
+            handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), msg, mpsc::channel(1).0);
+            reply_rx.unwrap()
+        };
+        let reply = query("query").await.unwrap();
+        assert!(!reply.contains('\n'));
+        let mut reply: serde_json::Value = serde_json::from_str(&reply).unwrap();
+        let mut tags = vec![];
+        for monitor in reply["monitors"].as_array_mut().unwrap() {
+            assert!(monitor.as_object_mut().unwrap().remove("layout").is_some());
+            tags.push(monitor.as_object_mut().unwrap().remove("tags").unwrap());
+        }
+        let windows = reply.as_object_mut().unwrap().remove("windows").unwrap();
+        assert!(reply["seq"].is_u64());
+        reply.as_object_mut().unwrap().remove("seq");
+        assert_eq!(reply, serde_json::json!({
+            "schema_version": 1, "active_monitor": "DP-1", "profile": null, "submap": null, "pending_prefix": null, "txn_open": false, "healthy": true,
+            "unknown_events": 0,
+            "monitors": [
+                {
+                    "id": "DP-1", "name": "DP-1", "visible_tags": 1, "occupied_tags": [1, 3], "active_window": "1de",
+                    "overlay": [], "presentation": false,
+                },
+                {
+                    "id": "DP-2", "name": "DP-2", "visible_tags": 1, "occupied_tags": [], "active_window": null,
+                    "overlay": [], "presentation": false,
+                },
+            ],
+        }));
+        assert_eq!(tags[0].as_array().unwrap().len(), 32);
+        assert_eq!(tags[0].as_array().unwrap()[..3], [
+            serde_json::json!({"id": "DP-1:1", "tag": 1, "name": "1", "pinned_to": null, "visible": true, "windows": 1, "new_since_seen": 0}),
+            serde_json::json!({"id": "DP-1:2", "tag": 2, "name": "2", "pinned_to": null, "visible": false, "windows": 0, "new_since_seen": 0}),
+            serde_json::json!({"id": "DP-1:3", "tag": 3, "name": "mail", "pinned_to": null, "visible": false, "windows": 1, "new_since_seen": 1}),
+        ]);
+        assert_eq!(tags[1][0]["id"], "DP-2:1");
+        assert_eq!(windows.as_object().unwrap().keys().collect::<Vec<_>>(), vec!["1de", "7e3"]);
+        assert_eq!((&windows["1de"]["monitor"], &windows["1de"]["tag"]), (&serde_json::json!("DP-1"), &serde_json::json!(3)));
+        assert_eq!(windows["1de"]["tag_id"], "DP-1:3");
+        assert!(windows["1de"]["opened_at"].is_u64());
+        assert_eq!(windows["1de"]["flags"]["overlay"], false);
+        assert!(windows["1de"]["assignment"].is_null());
+
+        let full: serde_json::Value = serde_json::from_str(&query("query full").await.unwrap()).unwrap();
+        assert_eq!(full["windows"]["1de"]["assignment"]["tag"], 3);
+        assert!(parse_request("query bogus").is_err());
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[tokio::test]
+    async fn pager() {
+        let mut profiles = Profiles::new(Config::parse(r#"{"tag_names": {"2": "web"}}"#).unwrap());
+        let mut state = two_monitors();
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        state.window_opened(addr("1de"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::MoveToTag(3, None, false), mpsc::channel(1).0);
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::ShowTag(2), mpsc::channel(1).0);
+        state.window_urgent(&addr("7e3")).unwrap();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::Pager(reply_tx), mpsc::channel(1).0);
+        let rows: serde_json::Value = serde_json::from_str(&reply_rx.await.unwrap()).unwrap();
+        assert_eq!(rows.as_array().unwrap().len(), 2);
+        assert_eq!((&rows[0]["id"], &rows[0]["monitor"]), (&serde_json::json!("DP-1"), &serde_json::json!("DP-1")));
+        assert_eq!(rows[0]["managed"], true);
+        assert_eq!(rows[0]["cells"].as_array().unwrap().len(), 32);
+        assert_eq!(rows[0]["cells"].as_array().unwrap()[..3], [
+            serde_json::json!({"id": "DP-1:1", "tag": 1, "name": "1", "windows": 1, "visible": false, "urgent": true, "focused": false}),
+            serde_json::json!({"id": "DP-1:2", "tag": 2, "name": "web", "windows": 0, "visible": true, "urgent": false, "focused": true}),
+            serde_json::json!({"id": "DP-1:3", "tag": 3, "name": "3", "windows": 1, "visible": false, "urgent": false, "focused": false}),
+        ]);
+        assert_eq!(rows[1]["cells"][0], serde_json::json!({"id": "DP-2:1", "tag": 1, "name": "1", "windows": 0, "visible": true, "urgent": false, "focused": false}));
+    }
+
+    /// DP-2 with window aa on it, removed with a grace period, and a focus change held meanwhile.
+    fn monitor_blinked(profiles: &mut Profiles, hooks: &mut Hooks, tx: mpsc::Sender<Ctrl>) -> MonitorsState {
+        let mut state = MonitorsState::new(vec![
+            MonitorInfo { description: "Dell U2720Q".into(), ..info(0, "DP-1", true) },
+            MonitorInfo { description: "LG 27GL850".into(), ..info(1, "DP-2", false) },
+        ], DEFAULT_TAG_COUNT);
+        state.focused_monitor_changed("DP-2").unwrap();
+        state.focus_window_changed(addr("aa")).unwrap();
+
+        handle_event(&mut state, profiles, hooks, Event::MonitorRemoved("DP-2".into()), tx.clone());
+        handle_event(&mut state, profiles, hooks, Event::ActiveWindow(addr("bb")), tx);
+        assert_eq!(state.monitor_ids().len(), 2);
+        assert_eq!(state.active_window(), Some(addr("aa")));
+        state
+    }
+
+    fn monitor_windows(state: &MonitorsState, name: &str) -> Vec<String> {
+        let snapshot = state.snapshot();
+        snapshot.monitors.iter().filter(|m| m.name == name).flat_map(|m| m.tags.values().flatten().cloned()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn monitor_back_within_grace() {
+        let mut profiles = Profiles::new(Config::parse(r#"{"monitor_grace_ms": 1000}"#).unwrap());
+        let mut hooks = Hooks::new(vec![]);
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut state = monitor_blinked(&mut profiles, &mut hooks, tx);
+
+        // back under another connector, which keeps its windows
+        let back = MonitorInfo { description: "LG 27GL850".into(), ..info(2, "DP-3", false) };
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::MonitorAdded(Monitor::new(&back, DEFAULT_TAG_COUNT)), mpsc::channel(1).0);
+        let names: Vec<String> = state.monitor_ids().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["DP-1", "DP-3"]);
+        assert!(monitor_windows(&state, "DP-3").contains(&"aa".to_string()));
+        assert_eq!(state.active_window(), Some(addr("bb")));
+        assert!(!state.removals().is_pending());
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!matches!(rx.try_recv(), Ok(Ctrl::MonitorGone(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn monitor_gone_after_grace() {
+        let mut profiles = Profiles::new(Config::parse(r#"{"monitor_grace_ms": 1000}"#).unwrap());
+        let mut hooks = Hooks::new(vec![]);
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut state = monitor_blinked(&mut profiles, &mut hooks, tx);
+
+        let msg = rx.recv().await.unwrap();
+        assert!(matches!(&msg, Ctrl::MonitorGone(name) if name == "DP-2"));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, msg, mpsc::channel(1).0);
+        let names: Vec<String> = state.monitor_ids().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["DP-1"]);
+        assert!(monitor_windows(&state, "DP-1").contains(&"aa".to_string()));
+        // the held focus change is handled once the removal is done
+        assert_eq!(state.active_window(), Some(addr("bb")));
+        assert_eq!(state.source(), &ChangeSource::Event { event: "activewindowv2".into() });
+    }
+
+    /// The cursor on DP-2, right of DP-1, as its query would find it.
+    fn cursor_on_dp2() -> Option<(CursorPos, Vec<MonitorInfo>)> {
+        let geometry = |id, name: &str, x| MonitorInfo { id, name: name.into(), x, width: 1920, height: 1080, ..Default::default() };
+        Some((CursorPos { x: 2000, y: 500 }, vec![geometry(0, "DP-1", 0), geometry(1, "DP-2", 1920)]))
+    }
+
+    fn queried(msg: Ctrl, query: Option<(CursorPos, Vec<MonitorInfo>)>) -> Ctrl {
+        Ctrl::TargetQueried(Box::new(msg), ChangeSource::Ctrl { command: "show@cursor".into() }, query, None)
+    }
+
+    #[tokio::test]
+    async fn command_target() {
+        let mut profiles = Profiles::new(Config::parse(r#"{"command_target": "cursor"}"#).unwrap());
+        let mut hooks = Hooks::new(vec![]);
+        let mut state = two_monitors();
+        let visible = |state: &MonitorsState| (state.visible_tags_of(0).unwrap(), state.visible_tags_of(1).unwrap());
+
+        // waits for the cursor query
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(2), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b1, 0b1));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, queried(Ctrl::ShowTag(2), cursor_on_dp2()), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b1, 0b10));
+        assert_eq!(state.source(), &ChangeSource::Ctrl { command: "show@cursor".into() });
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, queried(Ctrl::ToggleTag(3, false), cursor_on_dp2()), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b1, 0b110));
+
+        // a failed query falls back to the focused monitor
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, queried(Ctrl::ShowTag(4), None), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b1000, 0b110));
+
+        // a synced one replies once it ran, not once the query went out
+        let (tx, mut rx) = mpsc::channel(1);
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::Sync(Box::new(Ctrl::ShowTag(3)), reply_tx), tx);
+        let query = rx.recv().await.unwrap();
+        assert!(matches!(query, Ctrl::TargetQueried(_, _, _, Some(_))));
+        assert!(reply_rx.try_recv().is_err());
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, query, mpsc::channel(1).0);
+        assert_eq!(reply_rx.await.unwrap(), "ok");
+        assert_eq!(visible(&state), (0b100, 0b110));
+
+        // the suffix wins over the config
+        let focused = Ctrl::Target(CommandTarget::Focused, Box::new(Ctrl::ShowTag(5)));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, focused, mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b10000, 0b110));
+
+        profiles = Profiles::new(Config::default());
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(6), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b100000, 0b110));
+        let cursor = Ctrl::Target(CommandTarget::Cursor, Box::new(Ctrl::ShowTag(7)));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, cursor, mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b100000, 0b110));
+
+        // presentation mode is checked on the monitor under the cursor
+        state.set_presentation(true, Some(1)).unwrap();
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, queried(Ctrl::ShowTag(7), cursor_on_dp2()), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b100000, 0b110));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, queried(Ctrl::ShowTag(7), None), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b1000000, 0b110));
+        let overridden = Ctrl::Override(Box::new(Ctrl::ShowTag(7)));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, queried(overridden, cursor_on_dp2()), mpsc::channel(1).0);
+        assert_eq!(visible(&state), (0b1000000, 0b1000000));
+    }
+
+    #[tokio::test]
+    async fn window_provenance() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut hooks = Hooks::new(vec![]);
+        let mut state = two_monitors();
+        state.focused_monitor_changed("DP-2").unwrap();
+
+        state.prepare_launch("kitty", 3).unwrap();
+        let opened = Event::OpenWindow { window: addr("aa"), class: "kitty".into(), title: String::new() };
+        handle_event(&mut state, &mut profiles, &mut hooks, opened, mpsc::channel(1).0);
+        let moved = Ctrl::Request("move 5 aa".into(), Box::new(Ctrl::MoveToTag(5, Some(addr("aa")), false)));
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, moved, mpsc::channel(1).0);
+        // showing a tag moves no window
+        handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::ShowTag(2), mpsc::channel(1).0);
+        handle_event(&mut state, &mut profiles, &mut hooks, Event::MonitorRemoved("DP-2".into()), mpsc::channel(1).0);
+
+        let history: Vec<_> = state.provenance().history(&addr("aa")).unwrap().iter()
+            .map(|a| (a.monitor.as_str(), a.tag, a.from.as_ref().map(|p| (p.monitor.as_str(), p.tag)), a.source.to_string()))
+            .collect();
+        assert_eq!(history, vec![
+            ("DP-2", 3, None, "rule kitty 3".to_string()),
+            ("DP-2", 5, Some(("DP-2", 3)), "ctrl 'move 5 aa'".to_string()),
+            ("DP-1", 1, Some(("DP-2", 5)), "event monitorremoved".to_string()),
+        ]);
+
+        #[cfg(feature = "json-proto")]
+        {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::Explain(addr("aa"), reply_tx), mpsc::channel(1).0);
+            let explained: serde_json::Value = serde_json::from_str(&reply_rx.await.unwrap()).unwrap();
+            assert_eq!(explained["current"]["source"], serde_json::json!({"kind": "event", "event": "monitorremoved"}));
+            assert_eq!(explained["history"][0]["source"]["kind"], "rule");
+        }
+
+        handle_event(&mut state, &mut profiles, &mut hooks, Event::CloseWindow(addr("aa")), mpsc::channel(1).0);
+        assert!(state.provenance().history(&addr("aa")).is_none());
+    }
+
+    fn move_to_empty(state: &mut MonitorsState, profiles: &mut Profiles, follow: bool) -> oneshot::Receiver<String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        handle_ctrl(state, profiles, &mut Hooks::new(vec![]), Ctrl::MoveToEmpty(follow, reply_tx), mpsc::channel(1).0);
+        reply_rx
+    }
+
+    #[tokio::test]
+    async fn move_to_empty_tag() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = one_monitor();
+        assert_eq!(move_to_empty(&mut state, &mut profiles, false).await.unwrap(), "err: no focused window");
+
+        state.focus_window_changed(addr("aa")).unwrap();
+        state.focus_window_changed(addr("bb")).unwrap();
+        state.move_window(2, Some(addr("bb")), false).unwrap();
+        state.focus_window_changed(addr("cc")).unwrap();
+
+        assert_eq!(move_to_empty(&mut state, &mut profiles, false).await.unwrap(), "3");
+        assert_eq!(state.visible_tags(), 0b1);
+
+        state.focus_window_changed(addr("aa")).unwrap();
+        assert_eq!(move_to_empty(&mut state, &mut profiles, true).await.unwrap(), "4");
+        assert_eq!(state.visible_tags(), 0b1000);
+    }
+
+    #[tokio::test]
+    async fn reload_changed_monitors() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = two_monitors();
+        state.focused_monitor_changed("DP-2").unwrap();
+        state.focus_window_changed(addr("aa")).unwrap();
+
+        // DP-2 is gone after the reload, HDMI-A-1 is new, and DP-1 got a new id
+        let reloaded = vec![
+            info(2, "DP-1", true),
+            info(3, "HDMI-A-1", false),
+        ];
+        let diff = state.monitors_diff(&reloaded);
+        assert_eq!(diff.added, vec!["HDMI-A-1"]);
+        assert_eq!(diff.removed, vec!["DP-2"]);
+        assert_eq!(diff.ids_changed, vec![("DP-1".to_string(), 0, 2)]);
+
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::MonitorsReloaded(reloaded.clone()), mpsc::channel(1).0);
+        let names: Vec<String> = state.monitor_ids().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["DP-1", "HDMI-A-1"]);
+        // the window of the removed monitor is still tracked
+        assert!(state.snapshot().monitors[0].tags.values().flatten().any(|w| w == "aa"));
+
+        // replaying the same reload changes nothing
+        assert!(state.monitors_diff(&reloaded).is_empty());
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::MonitorsReloaded(reloaded), mpsc::channel(1).0);
+        assert_eq!(state.monitor_ids().len(), 2);
+    }
+
+    async fn throw(state: &mut MonitorsState, profiles: &mut Profiles, monitor: &str, tag: u8) -> String {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        handle_ctrl(state, profiles, &mut Hooks::new(vec![]), Ctrl::Throw(monitor.into(), tag, reply_tx), mpsc::channel(1).0);
+        reply_rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn throw_to_monitor() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut state = MonitorsState::new(vec![
+            info(0, "DP-1", true),
+            info(1, "HDMI-A-1", false),
+        ], DEFAULT_TAG_COUNT);
+        assert_eq!(throw(&mut state, &mut profiles, "HDMI-A-1", 5).await, "err: no focused window");
+
+        state.focus_window_changed(addr("bb")).unwrap();
+        state.focus_window_changed(addr("aa")).unwrap();
+        assert_eq!(throw(&mut state, &mut profiles, "TV", 5).await, "err: no such monitor:TV");
+
+        let reply: serde_json::Value = serde_json::from_str(&throw(&mut state, &mut profiles, "HDMI-A-1", 5).await).unwrap();
+        assert_eq!(reply, serde_json::json!({"window": "aa", "monitor": "HDMI-A-1", "tag": 5, "visible_tags": 0b10000}));
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.active_monitor, 0);
+        assert_eq!(snapshot.monitors[1].tags[&5], vec!["aa"]);
+        assert_eq!(snapshot.monitors[1].visible_tags, 0b10000);
+
+        // already there, but the tag gets shown again
+        state.focus_window_changed(addr("bb")).unwrap();
+        handle_ctrl(&mut state, &mut profiles, &mut Hooks::new(vec![]), Ctrl::ToggleTag(3, false), mpsc::channel(1).0);
+        let reply: serde_json::Value = serde_json::from_str(&throw(&mut state, &mut profiles, "DP-1", 1).await).unwrap();
+        assert_eq!(reply["visible_tags"], 0b1);
+        assert_eq!(state.visible_tags(), 0b1);
+    }
+
+    async fn reply(state: &mut MonitorsState, profiles: &mut Profiles, line: &str) -> String {
+        let (msg, reply_rx) = match parse_request(line) {
+            Ok(parsed) => parsed,
+            Err(err) => return format!("err: {}", err),
+        };
+        handle_ctrl(state, profiles, &mut Hooks::new(vec![]), msg, mpsc::channel(1).0);
+        reply_rx.unwrap().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn rule_commands() {
+        let dir = std::env::temp_dir().join(format!("hyprtag-rule-save-{}", std::process::id()));
+        let config = Config { source: Some(dir.join("config.json")), ..Config::parse(r#"{"rules": [{"name": "term", "class": "foot", "tag": 2}]}"#).unwrap() };
+        let mut profiles = Profiles::new(config);
+        let mut state = one_monitor();
+        profile_changed(&mut state, &profiles, &mut Hooks::new(vec![]));
+
+        let err = reply(&mut state, &mut profiles, "rule add term class=^(kitty|foot tag=3").await;
+        assert_eq!(err, "err: invalid class regex /^(kitty|foot/ at column 2: unclosed group");
+        assert_eq!(reply(&mut state, &mut profiles, "rule add term class=^(kitty|foot)$ tag=3 sticky").await, "ok");
+
+        let test: serde_json::Value = serde_json::from_str(&reply(&mut state, &mut profiles, "rule test foot").await).unwrap();
+        assert_eq!(test["matched"], "term");
+        assert_eq!(test["checked"][0]["origin"], "runtime");
+        let list: serde_json::Value = serde_json::from_str(&reply(&mut state, &mut profiles, "rule list").await).unwrap();
+        assert_eq!(list[1], serde_json::json!({"name": "term", "class": "foot", "tag": 2, "sticky": false, "ignore": false, "origin": "file", "shadowed": true}));
+
+        let opened = Event::OpenWindow { window: addr("aa"), class: "foot".into(), title: "~".into() };
+        handle_event(&mut state, &mut profiles, &mut Hooks::new(vec![]), opened, mpsc::channel(1).0);
+        assert_eq!(state.snapshot().monitors[0].tags[&3], vec!["aa"]);
+
+        let saved: serde_json::Value = serde_json::from_str(&reply(&mut state, &mut profiles, "rule save").await).unwrap();
+        assert_eq!(saved["saved"], 1);
+        assert!(std::fs::read_to_string(dir.join("rules.d/runtime.json")).unwrap().contains("^(kitty|foot)$"));
+
+        assert_eq!(reply(&mut state, &mut profiles, "rule remove term").await, "ok");
+        assert_eq!(reply(&mut state, &mut profiles, "rule remove term").await, "err: rule term comes from the config, edit it there");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parse_requests() {
+        assert!(matches!(parse_request("show 3").unwrap(), (Ctrl::ShowTag(3), None)));
+        assert!(matches!(parse_request("move 2").unwrap(), (Ctrl::MoveToTag(2, None, false), None)));
+        assert!(matches!(parse_request("move 2 --force").unwrap(), (Ctrl::MoveToTag(2, None, true), None)));
+        assert!(parse_request("move 2 now").is_err());
+        assert!(matches!(parse_request("move_to_empty").unwrap(), (Ctrl::MoveToEmpty(false, _), Some(_))));
+        assert!(matches!(parse_request("move_to_empty follow").unwrap(), (Ctrl::MoveToEmpty(true, _), Some(_))));
+        assert!(parse_request("move_to_empty now").is_err());
+        assert!(matches!(parse_request("move_show 2").unwrap(), (Ctrl::MoveShow(2), None)));
+        assert!(parse_request("move_show").is_err());
+        assert!(matches!(parse_request("mode dim").unwrap(), (Ctrl::Mode(ViewMode::Dim), None)));
+        assert!(matches!(parse_request("mode normal").unwrap(), (Ctrl::Mode(ViewMode::Normal), None)));
+        assert!(parse_request("mode blur").is_err());
+        assert!(matches!(parse_request("overlay add").unwrap(), (Ctrl::Overlay(true, None), None)));
+        assert!(matches!(parse_request("overlay remove 0xaa").unwrap(), (Ctrl::Overlay(false, Some(_)), None)));
+        assert!(parse_request("overlay pin").is_err());
+        assert!(parse_request("overlay add kitty").is_err());
+        assert!(matches!(parse_request("toggle 3").unwrap(), (Ctrl::ToggleTag(3, false), None)));
+        assert!(matches!(parse_request("toggle 3 focus").unwrap(), (Ctrl::ToggleTag(3, true), None)));
+        assert!(matches!(parse_request("show@cursor 3").unwrap(), (Ctrl::Target(CommandTarget::Cursor, msg), None) if matches!(*msg, Ctrl::ShowTag(3))));
+        assert!(matches!(
+            parse_request("toggle@focused 3 focus").unwrap(),
+            (Ctrl::Target(CommandTarget::Focused, msg), None) if matches!(*msg, Ctrl::ToggleTag(3, true)),
+        ));
+        assert!(matches!(parse_request("show@cursor 3 --override").unwrap().0, Ctrl::Override(msg) if matches!(*msg, Ctrl::Target(_, _))));
+        assert!(parse_request("show@mouse 3").is_err());
+        assert!(parse_request("move@cursor 3").is_err());
+        assert!(parse_request("toggle 3 nope").is_err());
+        assert!(matches!(parse_request("restore").unwrap(), (Ctrl::RestorePrevTags, None)));
+        assert!(matches!(parse_request("prefix 1").unwrap(), (Ctrl::Prefix(1), None)));
+        assert!(matches!(parse_request("abort_prefix").unwrap(), (Ctrl::AbortPrefix, None)));
+        match parse_request("place DP-2 4 0xaa").unwrap() {
+            (Ctrl::Place(monitor, 4, window), None) => {
+                assert_eq!(monitor, "DP-2");
+                assert_eq!(window, Some(addr("aa")));
+            },
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(matches!(parse_request("place next 4").unwrap(), (Ctrl::Place(_, 4, None), None)));
+        assert!(matches!(parse_request("move_to_cursor_monitor").unwrap(), (Ctrl::MoveToCursorMonitor(None), None)));
+        assert!(matches!(parse_request("move_to_cursor_monitor 3").unwrap(), (Ctrl::MoveToCursorMonitor(Some(3)), None)));
+        assert!(parse_request("move_to_cursor_monitor 3 4").is_err());
+        assert!(parse_request("place DP-2").is_err());
+        assert!(matches!(parse_request("show_on DP-2 5").unwrap(), (Ctrl::ShowOn(_, 5, true), None)));
+        assert!(matches!(parse_request("show_on DP-2 5 nofocus").unwrap(), (Ctrl::ShowOn(_, 5, false), None)));
+        assert!(matches!(parse_request("pager_goto DP-2 5").unwrap(), (Ctrl::ShowOn(_, 5, true), None)));
+        assert!(parse_request("pager_goto DP-2").is_err());
+        assert!(parse_request("show_on DP-2 5 now").is_err());
+        assert!(matches!(parse_request("throw HDMI-A-1 5").unwrap(), (Ctrl::Throw(_, 5, _), Some(_))));
+        assert!(parse_request("throw HDMI-A-1").is_err());
+
+        match parse_request("reorder 2 0xaa bb").unwrap() {
+            (Ctrl::Reorder(2, windows), None) => assert_eq!(windows, vec![addr("aa"), addr("bb")]),
+            r => panic!("unexpected: {:?}", r),
+        }
+        match parse_request("activate spotify flatpak run com.spotify.Client").unwrap() {
+            (Ctrl::Activate(class, cmd), None) => {
+                assert_eq!(class, "spotify");
+                assert_eq!(cmd, "flatpak run com.spotify.Client");
+            },
+            r => panic!("unexpected: {:?}", r),
+        }
+        assert!(matches!(parse_request("stale 86400").unwrap(), (Ctrl::Stale(86400, None, _), Some(_))));
+        assert!(matches!(parse_request("stale 86400 move 9").unwrap(), (Ctrl::Stale(86400, Some(9), _), Some(_))));
+        assert!(matches!(parse_request("summary").unwrap(), (Ctrl::Summary(_), Some(_))));
+        assert!(matches!(parse_request("summary reset").unwrap(), (Ctrl::SummaryReset, None)));
+        assert!(parse_request("summary week").is_err());
+        assert!(matches!(parse_request("conflicts").unwrap(), (Ctrl::Conflicts(_), Some(_))));
+        match parse_request("rule add video class=firefox title=YouTube tag=7 ignore").unwrap() {
+            (Ctrl::RuleAdd(rule, _), Some(_)) => assert!(rule.rule.ignore && !rule.rule.sticky && rule.rule.tag == 7 && rule.rule.monitor.is_none()),
+            r => panic!("unexpected: {:?}", r),
+        }
+        match parse_request("rule add music class=^spotify$ tag=9 monitor=HDMI-A-1").unwrap() {
+            (Ctrl::RuleAdd(rule, _), Some(_)) => assert_eq!(rule.rule.monitor.as_deref(), Some("HDMI-A-1")),
+            r => panic!("unexpected: {:?}", r),
+        }
+        assert!(parse_request("rule add video title=YouTube tag=7").is_err());
+        assert!(parse_request("rule add video class=firefox tag=7 floating").is_err());
+        assert!(matches!(parse_request("rule test 0x7e3").unwrap(), (Ctrl::RuleTest(RuleSubject::Window(_), _), Some(_))));
+        assert!(matches!(parse_request("rule test kitty").unwrap(), (Ctrl::RuleTest(RuleSubject::Class(_), _), Some(_))));
+        assert!(matches!(parse_request("rule list").unwrap(), (Ctrl::RuleList(_), Some(_))));
+        assert!(parse_request("rule remove").is_err());
+        assert!(parse_request("stale 86400 move").is_err());
+        assert!(matches!(parse_request("mirror 5 HDMI-A-1").unwrap(), (Ctrl::Mirror(5, _), None)));
+        assert!(parse_request("mirror 5").is_err());
+        match parse_request("sync show 3").unwrap() {
+            (Ctrl::Sync(msg, _), Some(_)) => assert!(matches!(*msg, Ctrl::ShowTag(3))),
+            r => panic!("unexpected: {:?}", r),
+        }
+        assert!(parse_request("sync snapshot").is_err());
+
+        assert!(parse_request("show").is_err());
+        assert!(parse_request("show x").is_err());
+        assert!(parse_request("diff {").is_err());
+        #[cfg(feature = "json-proto")]
+        assert!(matches!(parse_request(r#"diff {"state":{"active_monitor":0,"monitors":[]}}"#).unwrap(), (Ctrl::Diff(..), Some(_))));
+        assert!(parse_request("nope").is_err());
+        assert!(parse_request("promote kitty").is_err());
+        assert!(parse_request("reorder 2 aa zz").is_err());
+    }
+
+    #[test]
+    fn tags_by_name() {
+        set_tag_names(&BTreeMap::from([
+            (2, "code".to_string()), (9, "chat".to_string()), (10, "仕事".to_string()), (11, "🎵".to_string()), (12, "café".to_string()),
+        ]));
+        assert!(matches!(parse_request("show code").unwrap(), (Ctrl::ShowTag(2), None)));
+        assert!(matches!(parse_request("show 仕事").unwrap(), (Ctrl::ShowTag(10), None)));
+        assert!(matches!(parse_request("move 🎵").unwrap(), (Ctrl::MoveToTag(11, None, false), _)));
+        // typed decomposed, as some input methods do
+        assert!(matches!(parse_request("show cafe\u{301}").unwrap(), (Ctrl::ShowTag(12), None)));
+        assert!(matches!(parse_request("toggle chat").unwrap(), (Ctrl::ToggleTag(9, _), None)));
+        assert!(matches!(parse_request("move chat").unwrap(), (Ctrl::MoveToTag(9, None, false), _)));
+        // numbers keep working, named or not
+        assert!(matches!(parse_request("show 2").unwrap(), (Ctrl::ShowTag(2), None)));
+        assert!(matches!(parse_request("show 3").unwrap(), (Ctrl::ShowTag(3), None)));
+        assert_eq!(parse_request("show mail").unwrap_err().to_string(), "unknown tag name: mail, expected a number or one of code, chat, 仕事, 🎵, café");
+        assert_eq!(parse_request("show 3x").unwrap_err().to_string(), "invalid tag: 3x");
+        // past the configured count once run, past any count right away
+        assert!(matches!(parse_request("show 33").unwrap(), (Ctrl::ShowTag(33), None)));
+        assert_eq!(parse_request("show 65").unwrap_err().to_string(), "no such tag: 65, there are at most 64 tags");
+        assert_eq!(parse_request("move 300").unwrap_err().to_string(), "no such tag: 300, there are at most 64 tags");
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[test]
+    fn parse_json_requests() {
+        assert!(matches!(parse_request("snapshot").unwrap(), (Ctrl::Snapshot(_), Some(_))));
+        assert!(matches!(parse_request("badges").unwrap(), (Ctrl::Badges(_), Some(_))));
+        assert!(matches!(parse_request("outputs").unwrap(), (Ctrl::Outputs(_), Some(_))));
+        assert!(matches!(parse_request("pager").unwrap(), (Ctrl::Pager(_), Some(_))));
+        assert!(matches!(parse_request("config").unwrap(), (Ctrl::Config(_), Some(_))));
+        match parse_request("config check /tmp/my config.json").unwrap() {
+            (Ctrl::ConfigCheck(path, _), Some(_)) => assert_eq!(path.to_str(), Some("/tmp/my config.json")),
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(parse_request("config check").is_err());
+
+        match parse_request("preview show@focused 3").unwrap() {
+            (Ctrl::Preview(msg, _), Some(_)) => assert!(matches!(*msg, Ctrl::Target(CommandTarget::Focused, ref m) if matches!(**m, Ctrl::ShowTag(3)))),
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(matches!(parse_request("preview throw DP-2 4").unwrap(), (Ctrl::Preview(_, _), Some(_))));
+        let err = parse_request("preview focus_monitor DP-2").unwrap_err();
+        assert_eq!(err.to_string(), "focus_monitor can't be previewed");
+        assert!(parse_request("preview show@cursor 3").is_err());
+        assert!(parse_request("preview").is_err());
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[tokio::test]
+    async fn preview_leaves_state_alone() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut hooks = Hooks::new(vec![]);
+        let mut state = two_monitors();
+        state.set_visible_tags(0b10).unwrap();
+        state.set_visible_tags(0b1).unwrap();
+        for window in ["aa", "bb"] {
+            state.window_opened(addr(window), "kitty", "").unwrap();
+            state.focus_window_changed(addr(window)).unwrap();
+        }
+        let before = state.snapshot();
+
+        let mut preview = async |msg: Ctrl| {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            handle_ctrl(&mut state, &mut profiles, &mut hooks, Ctrl::Preview(Box::new(msg), reply_tx), mpsc::channel(1).0);
+            let reply = reply_rx.await.unwrap();
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap_or(serde_json::Value::String(reply))
+        };
+
+        let moved = preview(Ctrl::MoveToTag(3, None, false)).await;
+        assert_eq!(moved["dispatches"], serde_json::json!(["dispatch movetoworkspacesilent 101,address:0xbb"]));
+        assert_eq!(moved["windows"], serde_json::json!([{"addr": "bb", "before": {"monitor": "DP-1", "tag": 1}, "after": {"monitor": "DP-1", "tag": 3}}]));
+
+        let restored = preview(Ctrl::RestorePrevTags).await;
+        assert_eq!(restored["visible_tags"], serde_json::json!([{"monitor": "DP-1", "before": 1, "after": 2}]));
+        let placed = preview(Ctrl::Place("DP-2".into(), 5, Some(addr("aa")))).await;
+        assert_eq!(placed["windows"][0]["after"], serde_json::json!({"monitor": "DP-2", "tag": 5}));
+        assert_eq!(preview(Ctrl::ShowOn("HDMI-A-1".into(), 2, true)).await, "err: no such monitor:HDMI-A-1");
+
+        // prev_tags included
+        assert_eq!(state.snapshot(), before);
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[tokio::test]
+    async fn help_describes_the_registry() {
+        let mut profiles = Profiles::new(Config::default());
+        let mut hooks = Hooks::new(vec![]);
+        let mut state = one_monitor();
+        let mut help = async |line: &str| {
+            let (msg, reply_rx) = parse_request(line).unwrap();
+            handle_ctrl(&mut state, &mut profiles, &mut hooks, msg, mpsc::channel(1).0);
+            reply_rx.unwrap().await.unwrap()
+        };
+
+        let all: Vec<crate::protocol::CommandInfo> = serde_json::from_str(&help("help").await).unwrap();
+        assert_eq!(all, COMMANDS);
+        let one: crate::protocol::CommandInfo = serde_json::from_str(&help("help move").await).unwrap();
+        assert_eq!(one.usage(), "move <tag> [--force]");
+        assert_eq!(one.feature, None);
+        let one: crate::protocol::CommandInfo = serde_json::from_str(&help("help query").await).unwrap();
+        assert_eq!(one.feature.as_deref(), Some("json-proto"));
+
+        let err = parse_request("help shwo").unwrap_err();
+        assert_eq!(err.to_string(), "unknown command 'shwo', did you mean 'show'?");
+    }
+
+    #[cfg(not(feature = "json-proto"))]
+    #[test]
+    fn json_requests_not_compiled_in() {
+        let err = parse_request("snapshot").unwrap_err();
+        assert!(err.downcast_ref::<UnknownCommand>().is_none());
+        assert_eq!(err.to_string(), "'snapshot' is not compiled in, it needs the json-proto feature");
+        assert!(parse_request("config check /tmp/config.json").is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{sync::atomic::{AtomicBool, AtomicU64, Ordering}, time::Duration};
+
+use tokio::sync::oneshot;
+
+use crate::{
+    adopt::take_pending,
+    focus::{focus_echoes, move_echoes},
+    fullscreen::{fullscreen_windows, Fullscreen},
+    hooks::Hooks,
+    hyprctl::{hyprctl_batch, hyprctl_batch_now, hyprctl_clients, hyprctl_enqueue, hyprctl_enqueue_droppable, hyprctl_with_cmd, mock, ClientInfo},
+    monitor::{hidden_workspace, visible_workspace, Changes},
+    now,
+    state::WindowAddr,
+};
+
+static WARP_CURSOR: AtomicBool = AtomicBool::new(false);
+
+/// Opacity of windows on hidden tags in dim mode.
+pub const DIM_ALPHA: f32 = 0.6;
+
+/// Number of state changes handled so far, so clients can order query replies taken at different times.
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// How long the geometry lookup for a cursor warp may hold up the hyprctl worker.
+const WARP_LOOKUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub fn set_warp_cursor(on: bool) {
+    WARP_CURSOR.store(on, Ordering::Relaxed);
+}
+
+/// hyprctl commands moving windows and focus as described by `changes`.
+/// Fullscreen windows coming back into view are made fullscreen again.
+pub fn dispatches(changes: &Changes, fullscreen: &Fullscreen) -> Vec<String> {
+    let mut args: Vec<String> = vec![];
+    args.extend(
+        changes.changes.window_removed.iter()
+            .map(|w| format!("dispatch movetoworkspacesilent {},address:0x{}",
+                             hidden_workspace(changes.tag_count, changes.active_monitor_index, w.tag), w.addr))
+    );
+    args.extend(
+        changes.changes.window_added.iter()
+            .map(|w| format!("dispatch movetoworkspacesilent {},address:0x{}", visible_workspace(changes.active_monitor_index), w.addr))
+    );
+    args.extend(fullscreen.restore_dispatches(changes.changes.window_added.iter().map(|w| &w.addr)));
+    if let Some(focus) = &changes.changes.focus {
+        args.push(format!("dispatch focuswindow address:0x{}", focus));
+    }
+    args.extend(dim_dispatches(changes));
+    args
+}
+
+/// setprop dispatches for dim mode, which leaves windows of hidden tags in place and changes their opacity.
+fn dim_dispatches(changes: &Changes) -> impl Iterator<Item = String> + '_ {
+    let dimmed = changes.changes.dimmed.iter().map(|w| format!("dispatch setprop address:0x{} alpha {}", w, DIM_ALPHA));
+    let undimmed = changes.changes.undimmed.iter().map(|w| format!("dispatch setprop address:0x{} alpha unset", w));
+    dimmed.chain(undimmed)
+}
+
+/// movecursor dispatch to the middle of the window, if Hyprland reported it.
+pub fn warp_dispatch(window: &WindowAddr, clients: &[ClientInfo]) -> Option<String> {
+    let client = clients.iter().find(|c| c.window().is_ok_and(|w| w == *window))?;
+    Some(format!("dispatch movecursor {} {}", client.at[0] + client.size[0] / 2, client.at[1] + client.size[1] / 2))
+}
+
+/// Moves the cursor to the window once the dispatches queued before have run. Any failure means no warp.
+fn warp_cursor(window: WindowAddr) {
+    hyprctl_enqueue_droppable(&format!("cursor warp to {}", window), async move {
+        let clients = match tokio::time::timeout(WARP_LOOKUP_TIMEOUT, hyprctl_clients()).await {
+            Ok(Ok(clients)) => clients,
+            Ok(Err(err)) => {
+                tracing::debug!(%err, "no cursor warp, failed to fetch clients");
+                return;
+            },
+            Err(_) => {
+                tracing::debug!("no cursor warp, fetching clients timed out");
+                return;
+            },
+        };
+        match warp_dispatch(&window, &clients) {
+            Some(dispatch) => {
+                if let Err(err) = hyprctl_with_cmd(vec![dispatch]).await {
+                    tracing::debug!(%err, "cursor warp failed");
+                }
+            },
+            None => tracing::debug!(%window, "no cursor warp, window is gone"),
+        }
+    });
+}
+
+/// Sequence number of the last state change handled.
+pub fn seq() -> u64 {
+    SEQ.load(Ordering::SeqCst)
+}
+
+pub fn handle_changes(hooks: &Hooks, changes: Changes) {
+    handle_changes_batched(hooks, vec![changes]);
+}
+
+/// A transaction step whose dispatches hyprctl rejected.
+#[derive(Debug)]
+pub struct StepFailed {
+    pub step: usize,
+    pub err: anyhow::Error,
+}
+
+/// Handles several changes with their dispatches sent as one batch, so nothing can run in between.
+pub fn handle_changes_batched(hooks: &Hooks, mut all_changes: Vec<Changes>) {
+    all_changes.splice(0..0, take_pending(&all_changes));
+    let args = batch_dispatches(&all_changes);
+    // show-side keywords go first and hide-side ones after, so windows never show up with another tag's gaps
+    for changes in &all_changes {
+        hooks.tags_showing(changes.changes.shown_tags);
+    }
+    hyprctl_batch(args);
+    changes_sent(hooks, all_changes);
+}
+
+/// Handles the changes of each step like `handle_changes_batched`, but each step's dispatches go out as a batch of
+/// their own once the one before went through. The receiver gets the first step hyprctl rejected, if any.
+pub fn handle_steps_checked(hooks: &Hooks, mut steps: Vec<Vec<Changes>>) -> oneshot::Receiver<Result<(), StepFailed>> {
+    let pending = take_pending(steps.iter().flatten());
+    if let Some(first) = steps.first_mut() {
+        first.splice(0..0, pending);
+    }
+    let batches: Vec<Vec<String>> = steps.iter().map(|changes| batch_dispatches(changes)).collect();
+    for changes in steps.iter().flatten() {
+        hooks.tags_showing(changes.changes.shown_tags);
+    }
+    let (done_tx, done_rx) = oneshot::channel();
+    hyprctl_enqueue(async move {
+        for (step, args) in batches.into_iter().enumerate().filter(|(_, args)| !args.is_empty()) {
+            if let Err(err) = hyprctl_batch_now(args).await {
+                let _ = done_tx.send(Err(StepFailed { step, err }));
+                return;
+            }
+        }
+        let _ = done_tx.send(Ok(()));
+    });
+    for changes in steps {
+        changes_sent(hooks, changes);
+    }
+    done_rx
+}
+
+/// The dispatches of several changes, in order.
+fn batch_dispatches(all_changes: &[Changes]) -> Vec<String> {
+    let mut args = vec![];
+    let fullscreen = fullscreen_windows().lock().unwrap();
+    for changes in all_changes {
+        let seq = SEQ.fetch_add(1, Ordering::SeqCst) + 1;
+        let dispatches = dispatches(changes, &fullscreen);
+        tracing::debug!(seq, source = %changes.source, dispatches = dispatches.len(), "changes");
+        // a replay shows what each batch of dispatches was for
+        if let Some(mock) = mock().filter(|_| !dispatches.is_empty()) {
+            mock.lock().unwrap().record("source", &changes.source.to_string());
+        }
+        args.extend(dispatches);
+    }
+    args
+}
+
+/// Expects the echoes of the dispatches just queued, and tells hooks which tags changed.
+fn changes_sent(hooks: &Hooks, all_changes: Vec<Changes>) {
+    for changes in all_changes {
+        if let Some(focus) = changes.changes.focus {
+            focus_echoes().lock().unwrap().expect(focus, now());
+        }
+        for w in changes.changes.window_added.iter() {
+            move_echoes().lock().unwrap().expect(w.addr, now());
+        }
+        if WARP_CURSOR.load(Ordering::Relaxed) {
+            if let Some(focus) = changes.changes.focus {
+                warp_cursor(focus);
+            }
+        }
+
+        hooks.tags_changed(changes.changes.shown_tags, changes.changes.hidden_tags, changes.changes.active_tag, &changes.source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{addr, fullscreen::Fullscreen, hyprctl::ClientInfo, monitor::{ChangeSource, Changes}, state::{Changes as MonitorChanges, WindowInfo, DEFAULT_TAG_COUNT}};
+
+    use super::{dispatches, handle_changes, seq, warp_dispatch};
+
+    #[test]
+    fn changes_to_dispatches() {
+        let changes = Changes {
+            active_monitor_index: 1,
+            tag_count: DEFAULT_TAG_COUNT,
+            changes: MonitorChanges {
+                window_added: vec![WindowInfo { addr: addr("aa"), tag: 1 }],
+                window_removed: vec![WindowInfo { addr: addr("bb"), tag: 3 }],
+                focus: Some(addr("aa")),
+                ..Default::default()
+            },
+            source: ChangeSource::default(),
+        };
+
+        let mut fullscreen = Fullscreen::default();
+        assert_eq!(dispatches(&changes, &fullscreen), vec![
+            "dispatch movetoworkspacesilent 135,address:0xbb",
+            "dispatch movetoworkspacesilent 2,address:0xaa",
+            "dispatch focuswindow address:0xaa",
+        ]);
+
+        // fullscreen is restored before the final focus
+        fullscreen.set(&addr("aa"), 2);
+        fullscreen.set(&addr("bb"), 2);
+        assert_eq!(dispatches(&changes, &fullscreen), vec![
+            "dispatch movetoworkspacesilent 135,address:0xbb",
+            "dispatch movetoworkspacesilent 2,address:0xaa",
+            "dispatch focuswindow address:0xaa",
+            "dispatch fullscreenstate 2 -1",
+            "dispatch focuswindow address:0xaa",
+        ]);
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        let hooks = crate::hooks::Hooks::new(vec![]);
+        let mut last = seq();
+        for _ in 0..3 {
+            handle_changes(&hooks, Changes { active_monitor_index: 0, tag_count: DEFAULT_TAG_COUNT, changes: MonitorChanges::default(), source: ChangeSource::default() });
+            // other tests may handle changes too, but it never goes back
+            assert!(seq() > last);
+            last = seq();
+        }
+    }
+
+    #[test]
+    fn warp_to_window_center() {
+        let clients: Vec<ClientInfo> = serde_json::from_str(r#"[
+            {"address": "0xaa", "workspace": {"id": 2, "name": "2"}, "monitor": 1, "at": [1930, 10], "size": [940, 1060]}
+        ]"#).unwrap();
+
+        assert_eq!(warp_dispatch(&addr("aa"), &clients), Some("dispatch movecursor 2400 540".into()));
+        assert_eq!(warp_dispatch(&addr("bb"), &clients), None);
+    }
+}
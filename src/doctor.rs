@@ -0,0 +1,323 @@
+use std::{os::unix::net::UnixStream, path::Path};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::io::{AsyncWriteExt, BufStream};
+
+use crate::{
+    config::Config,
+    hyprctl::{hyprctl_clients, hyprctl_monitors, hyprctl_with_cmd, hyprctl_workspaces, hyprland_version, ClientInfo, WorkspaceInfo},
+    features,
+    line::read_reply,
+    monitor::{hidden_workspace, visible_workspace},
+    protocol,
+    session,
+    state::{DEFAULT_TAG_COUNT, MAX_TAGS},
+};
+
+/// Oldest Hyprland whose events and replies hyprtag understands.
+const MIN_HYPRLAND: (u32, u32) = (0, 35);
+/// Newest Hyprland hyprtag was tried with. Newer ones likely work, but are worth mentioning in a bug report.
+const MAX_TESTED_HYPRLAND: (u32, u32) = (0, 45);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    /// Exit code of `hyprtag doctor`, the worst status of its checks.
+    pub fn code(self) -> u8 {
+        match self {
+            Status::Pass => 0,
+            Status::Warn => 1,
+            Status::Fail => 2,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into() }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    json: bool,
+    fix: bool,
+    signature: Option<String>,
+}
+
+/// `hyprtag doctor [--json] [--fix] [--signature SIG]`: checks the environment the daemon would run in, without
+/// needing it to run. `--fix` moves windows stranded on hidden workspaces back into view.
+pub async fn run(args: &[String]) -> anyhow::Result<Status> {
+    let options = parse_args(args)?;
+    // hidden workspaces are numbered by the daemon's tag count
+    let tag_count = Config::load().map_or(DEFAULT_TAG_COUNT, |config| config.tag_count().clamp(1, MAX_TAGS));
+    let checks = checks(&options, tag_count).await;
+    let status = checks.iter().map(|c| c.status).max().unwrap_or(Status::Pass);
+
+    if options.json {
+        println!("{}", serde_json::json!({ "status": status, "checks": checks }));
+    } else {
+        for check in checks.iter() {
+            println!("{:<5} {:<10} {}", check.status.label(), check.name, check.detail);
+        }
+        let count = |status| checks.iter().filter(|c| c.status == status).count();
+        match (count(Status::Fail), count(Status::Warn)) {
+            (0, 0) => println!("all good"),
+            (failed, warned) => println!("{} failed, {} warnings", failed, warned),
+        }
+    }
+    Ok(status)
+}
+
+fn parse_args(args: &[String]) -> anyhow::Result<Options> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--fix" => options.fix = true,
+            "--signature" => options.signature = Some(args.next().context("--signature requires a value")?.clone()),
+            arg => bail!("unknown doctor arg: {}", arg),
+        }
+    }
+    Ok(options)
+}
+
+/// Runs the checks in order, stopping at the first failure the rest depend on.
+async fn checks(options: &Options, tag_count: u8) -> Vec<Check> {
+    let mut checks = vec![Check::new("build", Status::Pass, format!("hyprtag {} ({})", env!("CARGO_PKG_VERSION"), features::summary()))];
+
+    let dir = match session::select(options.signature.as_deref(), &session::runtime_roots()) {
+        Ok(dir) => dir,
+        Err(err) => {
+            checks.push(Check::new("session", Status::Fail, format!("{:#}", err)));
+            return checks;
+        },
+    };
+    let sig = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").unwrap_or_default();
+    checks.push(Check::new("session", Status::Pass, format!("{} in {}", sig, dir.display())));
+
+    for (socket, what) in [(".socket.sock", "requests"), (".socket2.sock", "events")] {
+        let path = dir.join(socket);
+        checks.push(match UnixStream::connect(&path) {
+            Ok(_) => Check::new("socket", Status::Pass, format!("{} accepts {}", path.display(), what)),
+            Err(err) => Check::new("socket", Status::Fail, format!("{}: {}", path.display(), err)),
+        });
+    }
+    if checks.iter().any(|c| c.status == Status::Fail) {
+        return checks;
+    }
+
+    match hyprland_version(None).await {
+        Ok(tag) => checks.push(version_check(&tag)),
+        Err(err) => {
+            checks.push(Check::new("hyprland", Status::Fail, format!("{:#}", err)));
+            return checks;
+        },
+    }
+
+    let running = instance_check(&dir.join(protocol::SOCKET));
+    let daemon_running = running.status != Status::Pass;
+    checks.push(running);
+    if daemon_running {
+        checks.push(match daemon_conflicts(&dir.join(protocol::SOCKET)).await {
+            Ok(conflicts) => conflicts_check(&conflicts),
+            Err(err) => Check::new("flags", Status::Warn, format!("failed to ask the daemon for flag conflicts: {:#}", err)),
+        });
+    }
+
+    let (monitors, workspaces, clients) = match (hyprctl_monitors().await, hyprctl_workspaces().await, hyprctl_clients().await) {
+        (Ok(m), Ok(w), Ok(c)) => (m, w, c),
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+            checks.push(Check::new("workspaces", Status::Fail, format!("failed to query Hyprland: {:#}", err)));
+            return checks;
+        },
+    };
+    checks.push(hidden_range_check(tag_count, monitors.len(), &workspaces));
+
+    if daemon_running {
+        checks.push(Check::new("stranded", Status::Pass, "skipped, the running daemon keeps hidden windows there"));
+        return checks;
+    }
+    let rescues = rescue_dispatches(tag_count, monitors.len(), &clients);
+    checks.push(if rescues.is_empty() {
+        Check::new("stranded", Status::Pass, "no windows on hidden workspaces")
+    } else if options.fix {
+        let count = rescues.len();
+        match hyprctl_with_cmd(rescues).await {
+            Ok(()) => Check::new("stranded", Status::Pass, format!("moved {} windows back from hidden workspaces", count)),
+            Err(err) => Check::new("stranded", Status::Fail, format!("failed to move them back: {:#}", err)),
+        }
+    } else {
+        Check::new("stranded", Status::Warn, format!("{} windows left on hidden workspaces, --fix moves them back", rescues.len()))
+    });
+
+    checks
+}
+
+/// `v0.41.2`, possibly followed by a commit description, as `(0, 41)`.
+fn parse_version(tag: &str) -> Option<(u32, u32)> {
+    let mut parts = tag.trim_start_matches('v').split(['.', '-']);
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+fn version_check(tag: &str) -> Check {
+    let range = format!("v{}.{} to v{}.{}", MIN_HYPRLAND.0, MIN_HYPRLAND.1, MAX_TESTED_HYPRLAND.0, MAX_TESTED_HYPRLAND.1);
+    match parse_version(tag) {
+        None => Check::new("hyprland", Status::Warn, format!("unknown version '{}', hyprtag supports {}", tag, range)),
+        Some(v) if v < MIN_HYPRLAND => Check::new("hyprland", Status::Fail, format!("{} is too old, hyprtag supports {}", tag, range)),
+        Some(v) if v > MAX_TESTED_HYPRLAND => Check::new("hyprland", Status::Warn, format!("{} is newer than tested, hyprtag supports {}", tag, range)),
+        Some(_) => Check::new("hyprland", Status::Pass, tag),
+    }
+}
+
+/// A daemon already serving the ctrl socket would lose it to the next one started.
+fn instance_check(path: &Path) -> Check {
+    match UnixStream::connect(path) {
+        Ok(_) => Check::new("instance", Status::Warn, "a hyprtag daemon is running, starting another takes over its ctrl socket"),
+        Err(_) if path.exists() => Check::new("instance", Status::Pass, "no hyprtag running, its stale socket is removed at startup"),
+        Err(_) => Check::new("instance", Status::Pass, "no hyprtag running"),
+    }
+}
+
+/// The `conflicts` reply of the running daemon.
+async fn daemon_conflicts(path: &Path) -> anyhow::Result<Vec<Value>> {
+    let mut ctrl = BufStream::new(tokio::net::UnixStream::connect(path).await?);
+    ctrl.write_all(b"conflicts\n").await?;
+    ctrl.flush().await?;
+    let reply = read_reply(&mut ctrl).await?.context("daemon closed the connection")?;
+    serde_json::from_str(&reply).with_context(|| format!("unexpected reply: {}", reply))
+}
+
+/// Windows which got flags that can't hold together, like a launch rule's tag on an unmanaged monitor.
+fn conflicts_check(conflicts: &[Value]) -> Check {
+    if conflicts.is_empty() {
+        return Check::new("flags", Status::Pass, "no window flag conflicts");
+    }
+    let windows: Vec<String> = conflicts.iter()
+        .map(|c| format!("{}: {}", c["addr"].as_str().unwrap_or("?"), c["message"].as_str().unwrap_or("?")))
+        .collect();
+    Check::new("flags", Status::Warn, format!("{} windows with conflicting flags, {}", windows.len(), windows.join("; ")))
+}
+
+/// The monitor whose hidden workspaces include `id`.
+fn hidden_workspace_monitor(tag_count: u8, monitor_count: usize, id: i64) -> Option<usize> {
+    (0..monitor_count).find(|i| (hidden_workspace(tag_count, *i, 0)..=hidden_workspace(tag_count, *i, tag_count - 1)).contains(&id))
+}
+
+/// Workspaces in the hidden range which exist without windows, because they're shown or persistent.
+fn hidden_range_check(tag_count: u8, monitor_count: usize, workspaces: &[WorkspaceInfo]) -> Check {
+    let taken: Vec<String> = workspaces.iter()
+        .filter(|w| w.windows == 0 && hidden_workspace_monitor(tag_count, monitor_count, w.id).is_some())
+        .map(|w| format!("{} on {}", w.id, w.monitor))
+        .collect();
+    let range = format!("{}-{}", hidden_workspace(tag_count, 0, 0), hidden_workspace(tag_count, monitor_count.max(1) - 1, tag_count - 1));
+    if taken.is_empty() {
+        Check::new("workspaces", Status::Pass, format!("hidden workspaces {} are free", range))
+    } else {
+        Check::new("workspaces", Status::Warn, format!("hidden workspaces {} are in use: {}", range, taken.join(", ")))
+    }
+}
+
+/// Dispatches moving windows left on hidden workspaces to the visible workspace of their monitor.
+fn rescue_dispatches(tag_count: u8, monitor_count: usize, clients: &[ClientInfo]) -> Vec<String> {
+    clients.iter()
+        .filter_map(|c| {
+            let index = hidden_workspace_monitor(tag_count, monitor_count, c.workspace.id)?;
+            Some(format!("dispatch movetoworkspacesilent {},address:{}", visible_workspace(index), c.address))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hyprctl::{ClientInfo, WorkspaceInfo, WorkspaceRef};
+
+    use super::{conflicts_check, hidden_range_check, parse_args, parse_version, rescue_dispatches, version_check, Options, Status};
+
+    fn workspace(id: i64, windows: u32) -> WorkspaceInfo {
+        WorkspaceInfo { id, name: id.to_string(), monitor: "DP-1".into(), windows }
+    }
+
+    #[test]
+    fn doctor_args() {
+        let args: Vec<String> = ["--json", "--fix"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(parse_args(&args).unwrap(), Options { json: true, fix: true, signature: None });
+        assert!(parse_args(&["--signature".to_string()]).is_err());
+        assert!(parse_args(&["--bogus".to_string()]).is_err());
+    }
+
+    #[test]
+    fn supported_versions() {
+        assert_eq!(parse_version("v0.41.2-45-g1234"), Some((0, 41)));
+        assert_eq!(parse_version("0.40.0"), Some((0, 40)));
+        assert_eq!(parse_version(""), None);
+
+        assert_eq!(version_check("v0.41.2").status, Status::Pass);
+        assert_eq!(version_check("v0.45.0").status, Status::Pass);
+        assert_eq!(version_check("v0.34.0").status, Status::Fail);
+        assert_eq!(version_check("v0.46.0").status, Status::Warn);
+        assert_eq!(version_check("git").status, Status::Warn);
+    }
+
+    #[test]
+    fn flag_conflicts() {
+        assert_eq!(conflicts_check(&[]).status, Status::Pass);
+        let conflicts = [serde_json::json!({"addr": "7e3", "message": "rule conflicts with unmanaged, which wins"})];
+        let check = conflicts_check(&conflicts);
+        assert_eq!(check.status, Status::Warn);
+        assert_eq!(check.detail, "1 windows with conflicting flags, 7e3: rule conflicts with unmanaged, which wins");
+    }
+
+    #[test]
+    fn hidden_workspaces() {
+        // windows on hidden workspaces aren't a conflict, an empty one exists because something keeps it
+        let check = hidden_range_check(32, 2, &[workspace(1, 3), workspace(105, 1), workspace(140, 0), workspace(164, 0)]);
+        assert_eq!(check.status, Status::Warn);
+        assert_eq!(check.detail, "hidden workspaces 100-163 are in use: 140 on DP-1");
+        assert_eq!(hidden_range_check(32, 1, &[workspace(140, 0)]).status, Status::Pass);
+        // fewer tags leave a shorter range per monitor
+        assert_eq!(hidden_range_check(8, 2, &[workspace(140, 0)]).detail, "hidden workspaces 100-115 are free");
+
+        let client = |address: &str, id| ClientInfo {
+            address: address.into(),
+            workspace: WorkspaceRef { id, name: id.to_string() },
+            monitor: 0,
+            class: String::new(),
+            title: String::new(),
+            fullscreen: 0,
+            xwayland: false,
+            pid: 0,
+            at: [0, 0],
+            size: [0, 0],
+        };
+        let clients = [client("0xa", 1), client("0xb", 103), client("0xc", 140), client("0xd", 200)];
+        assert_eq!(rescue_dispatches(32, 2, &clients), vec![
+            "dispatch movetoworkspacesilent 1,address:0xb",
+            "dispatch movetoworkspacesilent 2,address:0xc",
+        ]);
+    }
+}
@@ -0,0 +1,518 @@
+use std::{sync::atomic::{AtomicBool, AtomicU64, Ordering}, time::Duration};
+
+use anyhow::{bail, Context};
+use tokio::{io::BufStream, net::UnixStream, sync::mpsc};
+
+use crate::{
+    adopt::adoption,
+    ctrl::Ctrl,
+    dispatch::{handle_changes, handle_changes_batched},
+    exit::Fatal,
+    focus::{focus_echoes, move_echoes},
+    fullscreen::{fullscreen_windows, FULLSCREEN},
+    health,
+    hooks::Hooks,
+    hyprland_dir,
+    line::{read_line_limited, ReadLine, MAX_LINE},
+    monitor::{ChangeSource, Changes, MonitorsState},
+    now,
+    profile::Profiles,
+    profile_changed,
+    state::{Changes as MonitorChanges, WindowAddr, WindowInfo},
+};
+
+/// Parses an event from its first and second argument and the whole line.
+type Parser = fn(&str, &str, &str) -> anyhow::Result<Event>;
+
+/// Events parsed into an `Event`, with the parser of each. `Event::parse` goes by this table alone.
+pub const HANDLED_EVENTS: &[(&str, Parser)] = &[
+    ("focusedmon", |id, _, _| Ok(Event::FocusedMon(id.to_string()))),
+    ("focusedmonv2", |id, _, _| Ok(Event::FocusedMonV2(id.to_string()))),
+    ("openwindow", |id, _, line| {
+        // openwindow>>ADDRESS,WORKSPACENAME,WINDOWCLASS,WINDOWTITLE
+        let mut fields = line.trim_end().splitn(4, ',').skip(2);
+        let class = fields.next().unwrap_or_default().to_string();
+        let title = fields.next().unwrap_or_default().to_string();
+        Ok(Event::OpenWindow { window: id.parse()?, class, title })
+    }),
+    ("closewindow", |id, _, _| Ok(Event::CloseWindow(id.parse()?))),
+    ("activewindowv2", |id, _, _| Ok(if id.is_empty() { Event::FocusCleared } else { Event::ActiveWindow(id.parse()?) })),
+    ("monitoradded", |id, _, _| Ok(Event::MonitorAdded(id.to_string()))),
+    ("monitorremoved", |id, _, _| Ok(Event::MonitorRemoved(id.to_string()))),
+    ("fullscreen", |id, _, _| Ok(Event::Fullscreen(id == "1"))),
+    ("urgent", |id, _, _| Ok(Event::Urgent(id.parse()?))),
+    ("submap", |id, _, _| Ok(Event::Submap(id.to_string()))),
+    ("configreloaded", |_, _, _| Ok(Event::ConfigReloaded)),
+    // movewindowv2>>ADDRESS,WORKSPACEID,WORKSPACENAME
+    ("movewindowv2", |id, extra, _| Ok(Event::MoveWindow { window: id.parse()?, workspace: extra.parse()? })),
+];
+
+/// Handled events which mean something without an argument: nothing focused, the default submap, and a reload.
+const WITHOUT_ARGUMENT: &[&str] = &["activewindowv2", "submap", "configreloaded"];
+
+/// Events we know about and deliberately drop.
+pub const IGNORED_EVENTS: &[&str] = &[
+    "workspace", "workspacev2",
+    "activewindow",
+    "monitoraddedv2", "monitorremovedv2",
+    "createworkspace", "createworkspacev2",
+    "destroyworkspace", "destroyworkspacev2",
+    "moveworkspace", "moveworkspacev2",
+    "renameworkspace",
+    "activespecial", "activespecialv2",
+    "activelayout",
+    // names the workspace only, movewindowv2 has its id
+    "movewindow",
+    "openlayer", "closelayer",
+    "changefloatingmode",
+    "minimized",
+    "screencast",
+    "windowtitle", "windowtitlev2",
+    "togglegroup", "moveintogroup", "moveoutofgroup", "ignoregrouplock", "lockgroups",
+    "pin",
+    "bell",
+];
+
+/// Set once a focusedmonv2 arrived. Hyprland sends both versions for every change, so focusedmon is skipped from then on.
+static FOCUSEDMON_V2: AtomicBool = AtomicBool::new(false);
+
+/// Number of events seen which are neither handled nor known to be ignorable.
+pub static UNKNOWN_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Hyprland events we act on, parsed from the socket2 stream.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    FocusedMon(String),
+    /// Monitor name, or Hyprland's monitor id in some versions.
+    FocusedMonV2(String),
+    OpenWindow { window: WindowAddr, class: String, title: String },
+    CloseWindow(WindowAddr),
+    ActiveWindow(WindowAddr),
+    /// activewindowv2 without an address: focus went to the desktop or a layer surface.
+    FocusCleared,
+    MonitorAdded(String),
+    MonitorRemoved(String),
+    /// The active window entered or left fullscreen.
+    Fullscreen(bool),
+    Urgent(WindowAddr),
+    /// Name of the active submap, empty for the default one.
+    Submap(String),
+    /// `hyprctl reload` ran, which may have changed monitors and workspace rules.
+    ConfigReloaded,
+    /// A window went to the workspace with the id, by our dispatches or the user.
+    MoveWindow { window: WindowAddr, workspace: i64 },
+}
+
+impl Event {
+    /// The name of the event on the socket.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FocusedMon(_) => "focusedmon",
+            Self::FocusedMonV2(_) => "focusedmonv2",
+            Self::OpenWindow { .. } => "openwindow",
+            Self::CloseWindow(_) => "closewindow",
+            Self::ActiveWindow(_) | Self::FocusCleared => "activewindowv2",
+            Self::MonitorAdded(_) => "monitoradded",
+            Self::MonitorRemoved(_) => "monitorremoved",
+            Self::Fullscreen(_) => "fullscreen",
+            Self::Urgent(_) => "urgent",
+            Self::Submap(_) => "submap",
+            Self::ConfigReloaded => "configreloaded",
+            Self::MoveWindow { .. } => "movewindowv2",
+        }
+    }
+
+    /// The window the event names, except for closewindow which is about one going away.
+    pub fn window(&self) -> Option<WindowAddr> {
+        match self {
+            Self::OpenWindow { window, .. } | Self::ActiveWindow(window) | Self::Urgent(window) | Self::MoveWindow { window, .. } => {
+                Some(*window)
+            },
+            _ => None,
+        }
+    }
+
+    /// Parses one event line. Events we don't handle are `None`, and so are ones without an argument, except
+    /// activewindowv2 and submap where it means nothing is focused or the default submap, and configreloaded.
+    pub fn parse(line: &str) -> anyhow::Result<Option<Self>> {
+        let (cmd, id, extra) = parse_line(line)?;
+        let Some((_, parse)) = HANDLED_EVENTS.iter().find(|(name, _)| *name == cmd) else {
+            return Ok(None);
+        };
+        if id.is_empty() && !WITHOUT_ARGUMENT.contains(&cmd) {
+            return Ok(None);
+        }
+        parse(id, extra, line).map(Some)
+    }
+}
+
+/// Whether the event is in either the handled or the ignored list.
+pub fn is_known(name: &str) -> bool {
+    HANDLED_EVENTS.iter().any(|(handled, _)| *handled == name) || IGNORED_EVENTS.contains(&name)
+}
+
+/// Reads Hyprland's event socket until it closes, forwarding the events we handle.
+/// With `strict`, events missing from both event lists are logged with their payload.
+pub async fn read_events(tx: mpsc::Sender<Event>, strict: bool) -> anyhow::Result<()> {
+    let sock = hyprland_dir()?.join(".socket2.sock");
+    let mut stream = BufStream::new(UnixStream::connect(&sock).await.context(Fatal::EventSocket)?);
+    let mut buf = String::new();
+
+    loop {
+        buf.clear();
+        match read_line_limited(&mut stream, &mut buf, MAX_LINE).await? {
+            ReadLine::Eof => return Ok(()),
+            // the stream is corrupt, reconnect
+            ReadLine::TooLong => bail!("event line longer than {} bytes", MAX_LINE),
+            ReadLine::Line => (),
+        }
+        if let Some(event) = event_line(&buf, strict) {
+            if tx.send(event).await.is_err() {
+                bail!("event receiver closed");
+            }
+        }
+    }
+}
+
+/// The event of a line read from the event socket, if it's one we handle.
+pub(crate) fn event_line(line: &str, strict: bool) -> Option<Event> {
+    tracing::debug!("[event] {:?}", line);
+
+    if strict {
+        let name = line.split(">>").next().unwrap_or_default().trim_end();
+        if !is_known(name) {
+            let count = UNKNOWN_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::warn!(name, payload = line.trim_end(), count, "unknown event");
+        }
+    }
+
+    match Event::parse(line) {
+        Ok(event) => event,
+        Err(err) => {
+            tracing::error!(%err, "invalid message received");
+            None
+        },
+    }
+}
+
+pub(crate) fn parse_line(line: &str) -> anyhow::Result<(&str, &str, &str)> {
+    let line = &line[..line.len() - 1]; // remove \n
+    let chunks: Vec<&str> = line.split(">>").collect();
+
+    if chunks.len() >= 2 {
+        let args: Vec<&str> = chunks[1].split(",").collect();
+        if args.len() >= 2 {
+            Ok((chunks[0], args[0], args[1]))
+        } else {
+            Ok((chunks[0], args[0], ""))
+        }
+    } else if chunks.len() == 1 {
+        Ok((chunks[0], "", ""))
+    } else {
+        bail!("invalid line: {}", line)
+    }
+}
+
+pub(crate) fn handle_event(state: &mut MonitorsState, profiles: &mut Profiles, hooks: &mut Hooks, event: Event, tx: mpsc::Sender<Ctrl>) {
+    handle_event_with(state, profiles, hooks, event, tx);
+    state.record_placements();
+}
+
+fn handle_event_with(state: &mut MonitorsState, profiles: &mut Profiles, hooks: &mut Hooks, event: Event, tx: mpsc::Sender<Ctrl>) {
+    // while a removed monitor may come back, the state stays as it was
+    if state.removals().is_pending() && !matches!(event, Event::MonitorAdded(_) | Event::MonitorRemoved(_)) {
+        tracing::debug!(?event, "holding event until the removed monitor is back or gone");
+        state.removals().hold(event);
+        return;
+    }
+    state.set_source(ChangeSource::Event { event: event.name().to_string() });
+    if let Some(window) = event.window() {
+        state.window_seen(window);
+    }
+    match event {
+        Event::FocusedMon(name) => {
+            if FOCUSEDMON_V2.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Err(err) = state.focused_monitor_changed(&name) {
+                tracing::error!(%err, "focusedmon error");
+                // the monitor may be new or have come back under a new id, the refresh makes it active
+                state.request_monitors_refresh(tx);
+            }
+        },
+
+        Event::FocusedMonV2(monitor) => {
+            FOCUSEDMON_V2.store(true, Ordering::Relaxed);
+            if let Err(err) = state.focused_monitor_changed_v2(&monitor) {
+                tracing::error!(%err, "focusedmonv2 error");
+                state.request_monitors_refresh(tx);
+            }
+        },
+
+        Event::OpenWindow { window, class, title } => {
+            // Hyprland may hand out the address of a closed window again
+            health::dispatch_health().lock().unwrap().window_opened(&window);
+            let config = &profiles.config().open_burst;
+            let held = state.burst().window_opened(now(), config);
+            if held {
+                state.burst().schedule_end(config, tx.clone());
+            }
+            match state.window_opened(window, &class, &title) {
+                Ok(changes) if held => state.burst().hold(changes),
+                Ok(changes) => handle_changes(hooks, changes),
+                Err(err) => tracing::error!(%err, "openwindow error"),
+            }
+            // openwindow doesn't say whether it's an XWayland client, or its pid
+            if state.tracks_xwayland() || state.inherits_tags() {
+                state.request_clients_refresh(tx);
+            }
+        },
+
+        Event::CloseWindow(window) => {
+            tracing::info!("closewindow: {}", window);
+            health::dispatch_health().lock().unwrap().window_closed(&window);
+            fullscreen_windows().lock().unwrap().forget(&window);
+            adoption().lock().unwrap().forget(&window);
+            match state.window_closed(window, profiles.config().on_tag_emptied) {
+                Ok(Some(changes)) => handle_changes(hooks, changes),
+                Ok(None) => (),
+                Err(err) => tracing::error!(%err, "closewindow error"),
+            }
+        },
+
+        Event::ActiveWindow(window) => {
+            // windows of a burst grab focus one after another, only the last one matters
+            if state.burst().is_on() {
+                state.burst().hold_focus(window);
+                return;
+            }
+            let echo = focus_echoes().lock().unwrap().is_echo(&window, now());
+            let was_provisional = state.is_provisional(&window);
+            let focus_debounce_ms = profiles.config().focus_debounce_ms;
+            let r = if focus_debounce_ms == 0 {
+                state.focus_window_changed(window)
+            } else {
+                let delay = Duration::from_millis(focus_debounce_ms);
+                state.focus_window_changed_debounced(window, delay, tx.clone())
+            };
+            // a focus change doesn't prove the window exists, a clients query will
+            if !was_provisional && state.is_provisional(&window) {
+                state.request_validity_checks(tx);
+            }
+            match r {
+                Ok(changes) => handle_changes(hooks, changes),
+                Err(err) => {
+                    tracing::error!(%err, "activewindowv2 error");
+                    return;
+                },
+            }
+            if !echo {
+                state.user_focused(window);
+            }
+        },
+
+        Event::FocusCleared => {
+            tracing::debug!("focus cleared");
+            state.focus_cleared();
+        },
+
+        Event::Fullscreen(on) => {
+            if let Some(window) = state.active_window() {
+                fullscreen_windows().lock().unwrap().set(&window, if on { FULLSCREEN } else { 0 });
+            }
+        },
+
+        Event::Urgent(window) => {
+            if let Some(changes) = state.window_confirmed(window) {
+                handle_changes(hooks, changes);
+            }
+            if let Err(err) = state.window_urgent(&window) {
+                tracing::error!(%err, "urgent error");
+            }
+        },
+
+        Event::Submap(name) => {
+            tracing::debug!(name, "submap");
+            state.submap_changed(&name);
+        },
+
+        Event::MonitorAdded(name) => {
+            if let Err(err) = state.monitor_added(&name, tx) {
+                tracing::error!(%err, "monitoradded error");
+            }
+        },
+
+        Event::MonitorRemoved(name) => {
+            if state.mirror_output_removed(&name) {
+                return;
+            }
+            let grace = profiles.config().monitor_grace_ms;
+            if grace > 0 {
+                if let Err(err) = state.monitor_removed_later(&name, Duration::from_millis(grace), tx) {
+                    tracing::error!(%err, "monitorremoved error");
+                }
+                return;
+            }
+            monitor_removed(state, hooks, &name);
+
+            if profiles.select(&state.monitor_ids()) {
+                profile_changed(state, profiles, hooks);
+            }
+        },
+
+        Event::ConfigReloaded => {
+            tracing::info!("config reloaded, checking monitors");
+            state.request_monitors_reload(tx);
+        },
+
+        Event::MoveWindow { window, workspace } => {
+            if let Some(changes) = state.window_confirmed(window) {
+                handle_changes(hooks, changes);
+            }
+            health::dispatch_health().lock().unwrap().window_moved(window, workspace);
+            if move_echoes().lock().unwrap().is_echo(&window, now()) {
+                return;
+            }
+            if let Err(err) = state.window_moved(window, workspace) {
+                tracing::error!(%err, "movewindowv2 error");
+            }
+        },
+    }
+}
+
+/// Ends a burst of opening windows, sending the held dispatches and the last focus change as one batch.
+pub(crate) fn burst_ended(state: &mut MonitorsState, hooks: &Hooks) {
+    let (mut all_changes, focus) = state.burst().take();
+    if let Some(window) = focus {
+        match state.focus_window_changed(window) {
+            Ok(changes) => all_changes.push(changes),
+            Err(err) => tracing::error!(%err, "activewindowv2 error"),
+        }
+    }
+    if !all_changes.is_empty() {
+        tracing::debug!(changes = all_changes.len(), "window burst ended");
+        handle_changes_batched(hooks, all_changes);
+    }
+}
+
+/// Handles the events held while a removed monitor was waited for, once none is anymore.
+pub(crate) fn release_held(state: &mut MonitorsState, profiles: &mut Profiles, hooks: &mut Hooks, tx: mpsc::Sender<Ctrl>) {
+    // the monitor's windows moved because of what ended the removal, not the held events
+    state.record_placements();
+    let held = state.removals().take_held();
+    if !held.is_empty() {
+        tracing::debug!(events = held.len(), "handling held events");
+    }
+    for event in held {
+        handle_event(state, profiles, hooks, event, tx.clone());
+    }
+}
+
+/// Moves the windows of a removed monitor onto another one and ends a mirror involving it.
+pub(crate) fn monitor_removed(state: &mut MonitorsState, hooks: &Hooks, name: &str) {
+    let removed = state.monitor_removed(name);
+    windows_moved_away(state, hooks, name, removed);
+}
+
+/// Takes a monitor Hyprland now mirrors `source` onto out of tracking, its windows going to `source`.
+pub(crate) fn monitor_linked(state: &mut MonitorsState, hooks: &Hooks, name: &str, source: &str) {
+    let linked = state.monitor_linked(name, source);
+    windows_moved_away(state, hooks, name, linked);
+}
+
+/// Dispatches the moves of the windows of a monitor gone from tracking.
+fn windows_moved_away(state: &mut MonitorsState, hooks: &Hooks, name: &str, r: anyhow::Result<(usize, usize, Vec<WindowAddr>)>) {
+    let (active_monitor_index, active_tag_index, removed_windows) = match r {
+        Ok(w) => w,
+        Err(err) => {
+            tracing::error!(%err, "monitorremoved error");
+            return;
+        },
+    };
+
+    let changes = Changes {
+        active_monitor_index,
+        tag_count: state.tag_count(),
+        changes: MonitorChanges {
+            window_added: removed_windows.iter().map(|w| WindowInfo {
+                addr: *w,
+                tag: active_tag_index as u8 + 1,
+            }).collect(),
+            ..Default::default()
+        },
+        source: state.source().clone(),
+    };
+    handle_changes(hooks, changes);
+
+    if let Some(changes) = state.mirror_monitor_removed(name) {
+        handle_changes(hooks, changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::addr;
+
+    use super::{parse_line, Event, HANDLED_EVENTS, IGNORED_EVENTS};
+
+    #[test]
+    fn test_parse_line() {
+        let line = "openwindow>>12345\n";
+
+        let (command, id, extra) = parse_line(line).unwrap();
+        assert_eq!(command, "openwindow");
+        assert_eq!(id, "12345");
+        assert_eq!(extra, "");
+
+        let line = "movewindow>>123456,2\n";
+
+        let (command, id, extra) = parse_line(line).unwrap();
+        assert_eq!(command, "movewindow");
+        assert_eq!(id, "123456");
+        assert_eq!(extra, "2");
+    }
+
+    #[test]
+    fn parse_events() {
+        assert_eq!(Event::parse("focusedmon>>DP-1,2\n").unwrap(), Some(Event::FocusedMon("DP-1".into())));
+        assert_eq!(Event::parse("focusedmonv2>>DP-1,2\n").unwrap(), Some(Event::FocusedMonV2("DP-1".into())));
+        assert_eq!(Event::parse("openwindow>>abc,1,kitty,~\n").unwrap(), Some(Event::OpenWindow {
+            window: addr("abc"),
+            class: "kitty".into(),
+            title: "~".into(),
+        }));
+        // titles may have commas
+        assert!(matches!(Event::parse("openwindow>>abc,1,firefox,Inbox, 3 unread\n").unwrap(), Some(Event::OpenWindow { title, .. }) if title == "Inbox, 3 unread"));
+        assert_eq!(Event::parse("closewindow>>abc\n").unwrap(), Some(Event::CloseWindow(addr("abc"))));
+        assert_eq!(Event::parse("activewindowv2>>abc\n").unwrap(), Some(Event::ActiveWindow(addr("abc"))));
+        assert_eq!(Event::parse("monitorremoved>>DP-2\n").unwrap(), Some(Event::MonitorRemoved("DP-2".into())));
+        assert_eq!(Event::parse("fullscreen>>1\n").unwrap(), Some(Event::Fullscreen(true)));
+        assert_eq!(Event::parse("fullscreen>>0\n").unwrap(), Some(Event::Fullscreen(false)));
+        assert_eq!(Event::parse("movewindowv2>>abc,2,2\n").unwrap(), Some(Event::MoveWindow { window: addr("abc"), workspace: 2 }));
+        assert_eq!(Event::parse("movewindowv2>>abc,-98,special:scratch\n").unwrap(), Some(Event::MoveWindow { window: addr("abc"), workspace: -98 }));
+        assert_eq!(Event::parse("movewindow>>abc,2\n").unwrap(), None);
+        assert_eq!(Event::parse("urgent>>abc\n").unwrap(), Some(Event::Urgent(addr("abc"))));
+        assert_eq!(Event::parse("closewindow>>0x0000ABC\n").unwrap(), Some(Event::CloseWindow(addr("abc"))));
+        assert!(Event::parse("closewindow>>kitty\n").is_err());
+
+        // no window focused
+        assert_eq!(Event::parse("activewindowv2>>\n").unwrap(), Some(Event::FocusCleared));
+        assert_eq!(Event::parse("activewindowv2>>,\n").unwrap(), Some(Event::FocusCleared));
+        assert_eq!(Event::parse("submap>>resize\n").unwrap(), Some(Event::Submap("resize".into())));
+        assert_eq!(Event::parse("submap>>\n").unwrap(), Some(Event::Submap(String::new())));
+        assert_eq!(Event::parse("configreloaded>>\n").unwrap(), Some(Event::ConfigReloaded));
+        assert_eq!(Event::parse("workspace>>2\n").unwrap(), None);
+    }
+
+    #[test]
+    fn event_lists() {
+        for (name, _) in HANDLED_EVENTS {
+            assert!(!IGNORED_EVENTS.contains(name), "{} is both handled and ignored", name);
+            let event = Event::parse(&format!("{}>>abc,2,kitty,~\n", name)).unwrap();
+            assert_eq!(event.map(|e| e.name()), Some(*name), "{} isn't parsed as itself", name);
+        }
+        for name in IGNORED_EVENTS {
+            assert_eq!(Event::parse(&format!("{}>>DP-1,abc\n", name)).unwrap(), None, "{} is parsed", name);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+/// Exit code for errors without a code of their own.
+pub const ERROR: u8 = 1;
+
+/// Fatal conditions with their own exit code, so a supervisor can tell whether restarting helps.
+/// Attached to errors as context, and found again with `downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fatal {
+    /// No Hyprland session could be picked, or hyprctl can't reach Hyprland.
+    NoHyprland,
+    /// Connecting to Hyprland's event socket kept failing.
+    EventSocket,
+    /// Binding or serving the ctrl socket kept failing.
+    CtrlSocket,
+    /// Hyprland closed the event stream, or reading it kept failing.
+    EventStreamClosed,
+    /// The hyprctl binary isn't in PATH.
+    NoHyprctl,
+}
+
+impl Fatal {
+    pub const ALL: [Fatal; 5] = [Fatal::NoHyprland, Fatal::EventSocket, Fatal::CtrlSocket, Fatal::EventStreamClosed, Fatal::NoHyprctl];
+
+    pub fn code(self) -> u8 {
+        match self {
+            Fatal::NoHyprland => 10,
+            Fatal::EventSocket => 11,
+            Fatal::CtrlSocket => 12,
+            Fatal::EventStreamClosed => 13,
+            Fatal::NoHyprctl => 14,
+        }
+    }
+}
+
+impl fmt::Display for Fatal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Fatal::NoHyprland => "cannot find Hyprland",
+            Fatal::EventSocket => "cannot connect to the event socket",
+            Fatal::CtrlSocket => "cannot serve the ctrl socket",
+            Fatal::EventStreamClosed => "event stream closed",
+            Fatal::NoHyprctl => "hyprctl is not in PATH",
+        })
+    }
+}
+
+/// Exit code for the daemon's result.
+pub fn exit_code(r: &anyhow::Result<()>) -> u8 {
+    match r {
+        Ok(()) => 0,
+        Err(err) => err.downcast_ref::<Fatal>().map(|f| f.code()).unwrap_or(ERROR),
+    }
+}
+
+/// The exit code section of `--help`.
+pub fn codes_help() -> String {
+    let mut s = format!("  {:>3}  stopped cleanly\n  {:>3}  any other error\n", 0, ERROR);
+    for fatal in Fatal::ALL {
+        s += &format!("  {:>3}  {}\n", fatal.code(), fatal);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::{codes_help, exit_code, Fatal, ERROR};
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(&Ok(())), 0);
+        assert_eq!(exit_code(&Err(anyhow!("state is broken"))), ERROR);
+
+        let err: anyhow::Result<()> = Err(std::io::Error::from(std::io::ErrorKind::NotFound)).context(Fatal::EventSocket);
+        assert_eq!(exit_code(&err), 11);
+        // the code survives more context on top
+        assert_eq!(exit_code(&err.context("events task failed")), 11);
+
+        assert_eq!(exit_code(&Err(anyhow!(Fatal::EventStreamClosed))), 13);
+
+        let help = codes_help();
+        assert!(help.contains(" 10  cannot find Hyprland\n"));
+        assert!(help.contains(" 12  cannot serve the ctrl socket\n"));
+    }
+}
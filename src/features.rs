@@ -0,0 +1,59 @@
+use crate::protocol;
+
+/// Cargo features of this build, with whether each is compiled in.
+pub const FEATURES: &[(&str, bool)] = &[
+    ("hooks", cfg!(feature = "hooks")),
+    ("json-proto", cfg!(feature = "json-proto")),
+    ("persistence", cfg!(feature = "persistence")),
+    ("replay", cfg!(feature = "replay")),
+];
+
+pub fn enabled(feature: &str) -> bool {
+    FEATURES.iter().any(|(name, on)| *name == feature && *on)
+}
+
+/// The feature `cmd` needs which this build was made without.
+pub fn missing_for(cmd: &str) -> Option<&'static str> {
+    let feature = protocol::command_info(cmd)?.feature.as_deref()?;
+    (!enabled(feature)).then_some(feature)
+}
+
+/// The features as `+hooks -persistence`, for `--version` and `doctor`.
+pub fn summary() -> String {
+    FEATURES.iter()
+        .map(|(name, on)| format!("{}{}", if *on { '+' } else { '-' }, name))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::COMMANDS;
+
+    use super::{enabled, missing_for, summary};
+
+    #[test]
+    fn feature_commands() {
+        for cmd in COMMANDS {
+            if let Some(feature) = &cmd.feature {
+                assert_eq!(missing_for(&cmd.name).is_none(), enabled(feature));
+            }
+        }
+        assert_eq!(missing_for("show"), None);
+        assert!(!enabled("bogus"));
+    }
+
+    #[cfg(feature = "json-proto")]
+    #[test]
+    fn default_features() {
+        assert_eq!(missing_for("snapshot"), None);
+        assert!(summary().contains("+json-proto"));
+    }
+
+    #[cfg(not(feature = "json-proto"))]
+    #[test]
+    fn minimal_features() {
+        assert_eq!(missing_for("snapshot"), Some("json-proto"));
+        assert!(summary().contains("-json-proto"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Something holding a window in place, from the strongest to the weakest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flag {
+    /// On a monitor hyprtag leaves alone, so on no tag.
+    Unmanaged,
+    /// Shown on another monitor by `mirror`.
+    Mirrored,
+    /// Kept on its monitor's visible workspace, on no tag.
+    Overlay,
+    /// Always visible, by a sticky window rule or as an XWayland window under `xwayland_policy: sticky`.
+    Sticky,
+    /// An XWayland window kept on its tag under `xwayland_policy: pin-tag`.
+    Pinned,
+    /// Placed on a tag by a launch rule when it opens.
+    Rule,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Flag::Unmanaged => "unmanaged",
+            Flag::Mirrored => "mirrored",
+            Flag::Overlay => "overlay",
+            Flag::Sticky => "sticky",
+            Flag::Pinned => "pinned",
+            Flag::Rule => "rule",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Pairs of flags which can't hold together, the first one winning. Every other pair holds: sticky and pinned
+/// windows keep their flag while mirrored or an overlay and get their first tag from a rule, and on an unmanaged
+/// monitor the flag just has nothing to do.
+const CONFLICTS: &[(Flag, Flag)] = &[
+    // the monitor has no tags, so the rule's tag is dropped and the window isn't tracked
+    (Flag::Unmanaged, Flag::Rule),
+    (Flag::Unmanaged, Flag::Overlay),
+    (Flag::Unmanaged, Flag::Mirrored),
+    // mirroring takes a tag's windows, which an overlay window isn't on
+    (Flag::Mirrored, Flag::Overlay),
+    (Flag::Overlay, Flag::Rule),
+    (Flag::Sticky, Flag::Pinned),
+];
+
+/// The flags of a window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct WindowFlags {
+    pub unmanaged: bool,
+    pub mirrored: bool,
+    pub overlay: bool,
+    pub sticky: bool,
+    pub pinned: bool,
+    /// The tag of the rule placing the window.
+    pub rule: Option<u8>,
+}
+
+impl WindowFlags {
+    pub fn has(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::Unmanaged => self.unmanaged,
+            Flag::Mirrored => self.mirrored,
+            Flag::Overlay => self.overlay,
+            Flag::Sticky => self.sticky,
+            Flag::Pinned => self.pinned,
+            Flag::Rule => self.rule.is_some(),
+        }
+    }
+
+    fn clear(&mut self, flag: Flag) {
+        match flag {
+            Flag::Unmanaged => self.unmanaged = false,
+            Flag::Mirrored => self.mirrored = false,
+            Flag::Overlay => self.overlay = false,
+            Flag::Sticky => self.sticky = false,
+            Flag::Pinned => self.pinned = false,
+            Flag::Rule => self.rule = None,
+        }
+    }
+}
+
+/// Flags a window was about to get which can't hold together.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlagConflict {
+    pub kept: Flag,
+    pub dropped: Flag,
+    /// What the window goes on with, without the dropped flags.
+    pub resolved: WindowFlags,
+}
+
+impl fmt::Display for FlagConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} conflicts with {}, which wins", self.dropped, self.kept)
+    }
+}
+
+impl std::error::Error for FlagConflict {}
+
+/// The flags as they hold together, or the first conflict with what's left once every losing flag is dropped.
+pub fn resolve_flags(flags: WindowFlags) -> Result<WindowFlags, FlagConflict> {
+    let mut resolved = flags;
+    let mut conflict = None;
+    for (kept, dropped) in CONFLICTS {
+        if resolved.has(*kept) && resolved.has(*dropped) {
+            resolved.clear(*dropped);
+            conflict.get_or_insert((*kept, *dropped));
+        }
+    }
+    match conflict {
+        Some((kept, dropped)) => Err(FlagConflict { kept, dropped, resolved }),
+        None => Ok(resolved),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_flags, Flag, WindowFlags};
+
+    const ALL: [Flag; 6] = [Flag::Unmanaged, Flag::Mirrored, Flag::Overlay, Flag::Sticky, Flag::Pinned, Flag::Rule];
+
+    fn with(flags: &[Flag]) -> WindowFlags {
+        let mut w = WindowFlags::default();
+        for flag in flags {
+            match flag {
+                Flag::Unmanaged => w.unmanaged = true,
+                Flag::Mirrored => w.mirrored = true,
+                Flag::Overlay => w.overlay = true,
+                Flag::Sticky => w.sticky = true,
+                Flag::Pinned => w.pinned = true,
+                Flag::Rule => w.rule = Some(4),
+            }
+        }
+        w
+    }
+
+    #[test]
+    fn pairwise_outcomes() {
+        use Flag::*;
+        // every pair, with the flag dropped when they conflict
+        let table = [
+            (Unmanaged, Mirrored, Some(Mirrored)),
+            (Unmanaged, Overlay, Some(Overlay)),
+            (Unmanaged, Sticky, None),
+            (Unmanaged, Pinned, None),
+            (Unmanaged, Rule, Some(Rule)),
+            (Mirrored, Overlay, Some(Overlay)),
+            (Mirrored, Sticky, None),
+            (Mirrored, Pinned, None),
+            (Mirrored, Rule, None),
+            (Overlay, Sticky, None),
+            (Overlay, Pinned, None),
+            (Overlay, Rule, Some(Rule)),
+            (Sticky, Pinned, Some(Pinned)),
+            (Sticky, Rule, None),
+            (Pinned, Rule, None),
+        ];
+        assert_eq!(table.len(), ALL.len() * (ALL.len() - 1) / 2);
+
+        for (a, b, dropped) in table {
+            let flags = with(&[a, b]);
+            match (resolve_flags(flags), dropped) {
+                (Ok(resolved), None) => assert_eq!(resolved, flags, "{} {}", a, b),
+                (Err(conflict), Some(dropped)) => {
+                    assert_eq!(conflict.dropped, dropped, "{} {}", a, b);
+                    assert_eq!(conflict.kept, if dropped == a { b } else { a });
+                    assert_eq!(conflict.resolved, with(&[conflict.kept]));
+                },
+                (r, _) => panic!("{} {}: {:?}", a, b, r),
+            }
+        }
+    }
+
+    #[test]
+    fn several_conflicts() {
+        use Flag::*;
+        let conflict = resolve_flags(with(&[Unmanaged, Overlay, Rule, Sticky, Pinned])).unwrap_err();
+        // the strongest conflict is reported, every losing flag is dropped
+        assert_eq!((conflict.kept, conflict.dropped), (Unmanaged, Rule));
+        assert_eq!(conflict.resolved, with(&[Unmanaged, Sticky]));
+        assert_eq!(conflict.to_string(), "rule conflicts with unmanaged, which wins");
+
+        assert_eq!(resolve_flags(WindowFlags::default()).unwrap(), WindowFlags::default());
+        assert!(ALL.iter().all(|flag| resolve_flags(with(&[*flag])).is_ok()));
+    }
+}
@@ -0,0 +1,103 @@
+use std::{collections::VecDeque, sync::{Mutex, OnceLock}, time::{Duration, Instant}};
+
+use crate::state::WindowAddr;
+
+/// How long the activewindowv2 echo of a focuswindow dispatch is waited for.
+pub const ECHO_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Windows remembered per monitor, for `focus_last` to fall back on when the previous window is gone.
+const FOCUS_HISTORY: usize = 8;
+
+/// Windows our focuswindow dispatches focused, whose activewindowv2 echo isn't the user's doing.
+#[derive(Debug, Default)]
+pub struct FocusEchoes {
+    expected: VecDeque<(WindowAddr, Instant)>,
+}
+
+impl FocusEchoes {
+    pub fn expect(&mut self, window: WindowAddr, now: Instant) {
+        self.expected.push_back((window, now + ECHO_TIMEOUT));
+    }
+
+    /// Whether the focus change is the echo of one of our dispatches, which is used up with the ones before it.
+    pub fn is_echo(&mut self, window: &WindowAddr, now: Instant) -> bool {
+        self.expected.retain(|(_, expires)| now < *expires);
+        match self.expected.iter().position(|(w, _)| w == window) {
+            Some(index) => {
+                self.expected.drain(..=index);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+pub fn focus_echoes() -> &'static Mutex<FocusEchoes> {
+    static FOCUS_ECHOES: OnceLock<Mutex<FocusEchoes>> = OnceLock::new();
+    FOCUS_ECHOES.get_or_init(|| Mutex::new(FocusEchoes::default()))
+}
+
+/// Windows our dispatches moved onto a visible workspace, whose movewindowv2 echo isn't the user moving them.
+pub fn move_echoes() -> &'static Mutex<FocusEchoes> {
+    static MOVE_ECHOES: OnceLock<Mutex<FocusEchoes>> = OnceLock::new();
+    MOVE_ECHOES.get_or_init(|| Mutex::new(FocusEchoes::default()))
+}
+
+/// Windows the user focused on a monitor, most recent first. The first two are the pair `focus_last` switches
+/// between.
+#[derive(Debug, Clone, Default)]
+pub struct FocusHistory {
+    recent: VecDeque<WindowAddr>,
+}
+
+impl FocusHistory {
+    pub fn focused(&mut self, window: WindowAddr) {
+        self.recent.retain(|w| *w != window);
+        self.recent.push_front(window);
+        self.recent.truncate(FOCUS_HISTORY);
+    }
+
+    pub fn forget(&mut self, window: &WindowAddr) {
+        self.recent.retain(|w| w != window);
+    }
+
+    /// Most recent first.
+    pub fn windows(&self) -> impl Iterator<Item = &WindowAddr> {
+        self.recent.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{FocusEchoes, FocusHistory, ECHO_TIMEOUT, FOCUS_HISTORY};
+
+    #[test]
+    fn focus_echoes() {
+        let now = Instant::now();
+        let (a, b) = ("a".parse().unwrap(), "b".parse().unwrap());
+        let mut echoes = FocusEchoes::default();
+
+        echoes.expect(a, now);
+        echoes.expect(b, now);
+        // an echo skipped by Hyprland is dropped along with a later one
+        assert!(echoes.is_echo(&b, now));
+        assert!(!echoes.is_echo(&a, now));
+
+        echoes.expect(a, now);
+        assert!(!echoes.is_echo(&a, now + ECHO_TIMEOUT));
+    }
+
+    #[test]
+    fn bounded_history() {
+        let mut history = FocusHistory::default();
+        for i in 0..FOCUS_HISTORY + 2 {
+            history.focused(format!("{:x}", i + 1).parse().unwrap());
+        }
+        history.focused("3".parse().unwrap());
+        let windows: Vec<String> = history.windows().map(|w| w.to_string()).collect();
+        assert_eq!(windows.len(), FOCUS_HISTORY);
+        assert_eq!(windows[..3], ["3", "a", "9"]);
+    }
+}
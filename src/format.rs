@@ -0,0 +1,359 @@
+use std::{fmt, io::{BufRead, Write}};
+
+use anyhow::{bail, Context};
+use serde_json::Value;
+
+use crate::text;
+
+/// A `--format` template: text with `{path}` fields filled in from a JSON reply.
+///
+/// - `{monitors.0.name}` follows object keys and array indexes. A key applied to an array is applied to each of its
+///   elements, so `{monitors.name}` is the name of every monitor. `{.}` is the value itself.
+/// - Arrays are joined with `,`, or with what `|join:SEP` gives. Objects render as JSON.
+/// - Missing and null fields render empty, or as what `|default:TEXT` gives.
+/// - `|json` renders the value as JSON, strings quoted.
+/// - `|trunc:N` cuts the value to N terminal columns, ending it in `…`. `|pad:N` pads it with spaces to N columns.
+///   Kanji and emoji count as two columns, combining marks as none, so tables of names line up.
+/// - Newlines in values render as `\n`, so every rendered template stays a line.
+/// - In the text and filter arguments `\n` and `\t` are a newline and a tab, `\{`, `\}`, `\|` and `\\` the
+///   character itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field { path: Vec<String>, filters: Vec<Filter> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Join(String),
+    Default(String),
+    Json,
+    Truncate(usize),
+    Pad(usize),
+}
+
+/// What's wrong with a template, and where: the column of the character, counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl TemplateError {
+    /// The error under the template, with a caret at the column.
+    pub fn annotate(&self, template: &str) -> String {
+        format!("{}\n{}\n{}^", self, template, " ".repeat(self.column - 1))
+    }
+}
+
+fn error(column: usize, message: impl Into<String>) -> TemplateError {
+    TemplateError { column, message: message.into() }
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let mut parts = vec![];
+        let mut chars = template.chars().enumerate().map(|(i, c)| (i + 1, c)).peekable();
+        let mut text = String::new();
+        while let Some((column, c)) = chars.next() {
+            match c {
+                '\\' => text.push(escaped(column, chars.next())?),
+                '}' => return Err(error(column, "`}` without a `{`, write `\\}` for the character")),
+                '{' => {
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    let mut field = vec![(column, String::new())];
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((column, '\\')) => field.last_mut().unwrap().1.push(escaped(column, chars.next())?),
+                            Some((column, '|')) => field.push((column, String::new())),
+                            Some((column, '{')) => return Err(error(column, "`{` inside a field")),
+                            Some((_, c)) => field.last_mut().unwrap().1.push(c),
+                            None => return Err(error(column, "`{` without a `}`")),
+                        }
+                    }
+                    let (_, path) = field.remove(0);
+                    parts.push(Part::Field {
+                        path: parse_path(column, &path)?,
+                        filters: field.into_iter().map(|(column, f)| parse_filter(column, &f)).collect::<Result<_, _>>()?,
+                    });
+                },
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+
+    pub fn render(&self, value: &Value) -> String {
+        let mut s = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => s.push_str(text),
+                Part::Field { path, filters } => s.push_str(&render_field(value, path, filters)),
+            }
+        }
+        s
+    }
+}
+
+fn escaped(column: usize, c: Option<(usize, char)>) -> Result<char, TemplateError> {
+    match c.map(|(_, c)| c) {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some(c @ ('{' | '}' | '|' | '\\')) => Ok(c),
+        Some(c) => Err(error(column, format!("unknown escape `\\{}`", c))),
+        None => Err(error(column, "`\\` at the end")),
+    }
+}
+
+fn parse_path(column: usize, path: &str) -> Result<Vec<String>, TemplateError> {
+    let path = path.trim();
+    if path == "." {
+        return Ok(vec![]);
+    }
+    if path.is_empty() {
+        return Err(error(column, "empty field, `{.}` is the whole value"));
+    }
+    let keys: Vec<String> = path.split('.').map(str::to_string).collect();
+    if keys.iter().any(|k| k.is_empty()) {
+        return Err(error(column, format!("empty key in `{}`", path)));
+    }
+    Ok(keys)
+}
+
+fn parse_filter(column: usize, filter: &str) -> Result<Filter, TemplateError> {
+    let (name, arg) = match filter.split_once(':') {
+        Some((name, arg)) => (name.trim(), Some(arg.to_string())),
+        None => (filter.trim(), None),
+    };
+    match (name, arg) {
+        ("join", Some(sep)) => Ok(Filter::Join(sep)),
+        ("default", Some(text)) => Ok(Filter::Default(text)),
+        ("json", None) => Ok(Filter::Json),
+        ("trunc" | "pad", Some(columns)) => {
+            let columns = columns.trim().parse().map_err(|_| error(column, format!("`{}` takes a number of columns, not `{}`", name, columns)))?;
+            Ok(if name == "pad" { Filter::Pad(columns) } else { Filter::Truncate(columns) })
+        },
+        ("join" | "default" | "trunc" | "pad", None) => Err(error(column, format!("`{}` needs an argument, like `|{}:...`", name, name))),
+        ("json", Some(_)) => Err(error(column, "`json` takes no argument")),
+        _ => Err(error(column, format!("unknown filter `{}`, expected join, default, json, trunc or pad", name))),
+    }
+}
+
+/// The values at `path`, through every element of the arrays on the way.
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Vec<&'a Value> {
+    let Some((key, rest)) = path.split_first() else {
+        return vec![value];
+    };
+    match value {
+        Value::Object(map) => map.get(key).map_or(vec![], |v| lookup(v, rest)),
+        Value::Array(items) => match key.parse::<usize>() {
+            Ok(i) => items.get(i).map_or(vec![], |v| lookup(v, rest)),
+            Err(_) => items.iter().flat_map(|v| lookup(v, path)).collect(),
+        },
+        _ => vec![],
+    }
+}
+
+fn render_field(value: &Value, path: &[String], filters: &[Filter]) -> String {
+    let values = lookup(value, path);
+    // the elements of an array the path ends at
+    let values: Vec<&Value> = match values.as_slice() {
+        [Value::Array(items)] => items.iter().collect(),
+        _ => values,
+    };
+    let json = filters.contains(&Filter::Json);
+    let rendered: Vec<String> = values.into_iter()
+        .filter(|v| !v.is_null())
+        .map(|v| match v {
+            Value::String(s) if !json => s.replace('\n', "\\n"),
+            v => v.to_string(),
+        })
+        .collect();
+    let s = if rendered.is_empty() {
+        filters.iter().rev().find_map(|f| match f {
+            Filter::Default(text) => Some(text.clone()),
+            _ => None,
+        }).unwrap_or_default()
+    } else {
+        let sep = filters.iter().rev().find_map(|f| match f {
+            Filter::Join(sep) => Some(sep.as_str()),
+            _ => None,
+        }).unwrap_or(",");
+        rendered.join(sep)
+    };
+    // cut before padding, whatever order they're given in
+    let s = match filters.iter().rev().find_map(|f| match f {
+        Filter::Truncate(columns) => Some(*columns),
+        _ => None,
+    }) {
+        Some(columns) => text::truncate(&s, columns),
+        None => s,
+    };
+    match filters.iter().rev().find_map(|f| match f {
+        Filter::Pad(columns) => Some(*columns),
+        _ => None,
+    }) {
+        Some(columns) => text::pad(&s, columns),
+        None => s,
+    }
+}
+
+/// `hyprtag format [--each PATH] TEMPLATE`: renders the template for every JSON line on stdin, or for every
+/// element at PATH of it. Other lines go through as they are, `err:` replies to stderr, failing the command.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let (each, template) = match args {
+        [template] => (None, template),
+        [flag, path, template] if flag == "--each" => (Some(parse_each(path)?), template),
+        _ => bail!("usage: hyprtag format [--each PATH] TEMPLATE"),
+    };
+    let template = parse_template(template)?;
+    if !render_lines(std::io::stdin().lock(), each.as_deref(), &template, &mut std::io::stdout().lock())? {
+        bail!("the daemon replied with an error");
+    }
+    Ok(())
+}
+
+/// The template, with the error pointed at when it doesn't parse.
+pub fn parse_template(template: &str) -> anyhow::Result<Template> {
+    Template::parse(template).map_err(|err| anyhow::anyhow!("invalid template: {}", err.annotate(template)))
+}
+
+/// The path of an `--each` option.
+pub fn parse_each(path: &str) -> anyhow::Result<Vec<String>> {
+    parse_path(1, path).map_err(|e| anyhow::anyhow!("invalid --each: {}", e.message))
+}
+
+/// Renders the template for every JSON line read, or for every element at `each` of it, as `hyprtag format` does.
+/// False when there was an `err:` reply among the lines.
+pub fn render_lines(input: impl BufRead, each: Option<&[String]>, template: &Template, out: &mut impl Write) -> anyhow::Result<bool> {
+    let mut failed = false;
+    for line in input.lines() {
+        let line = line.context("failed to read the replies")?;
+        if line.starts_with("err:") {
+            eprintln!("{}", line);
+            failed = true;
+            continue;
+        }
+        let value: Value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(_) => {
+                writeln!(out, "{}", line)?;
+                continue;
+            },
+        };
+        let records = match each {
+            Some(path) => lookup(&value, path).into_iter().flat_map(|v| match v {
+                Value::Array(items) => items.iter().collect(),
+                v => vec![v],
+            }).collect(),
+            None => vec![&value],
+        };
+        for record in records {
+            writeln!(out, "{}", template.render(record))?;
+        }
+        out.flush()?;
+    }
+    Ok(!failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::text;
+
+    use super::{Template, TemplateError};
+
+    fn render(template: &str, value: &serde_json::Value) -> String {
+        Template::parse(template).unwrap().render(value)
+    }
+
+    #[test]
+    fn templates() {
+        let query = json!({
+            "active_monitor": "DP-1",
+            "submap": null,
+            "monitors": [
+                {"id": "DP-1", "visible_tags": 5, "occupied_tags": [1, 3], "tags": [{"tag": 1, "visible": true}, {"tag": 2, "visible": false}]},
+                {"id": "HDMI-A-1", "visible_tags": 1, "occupied_tags": [], "tags": []},
+            ],
+            "windows": {"7e3": {"tag": 3, "title": "a\nb", "flags": {"sticky": true}}},
+        });
+        for (template, expected) in [
+            ("plain text", "plain text"),
+            ("{active_monitor}", "DP-1"),
+            ("{monitors.0.id} {monitors.0.visible_tags}", "DP-1 5"),
+            ("{monitors.id}", "DP-1,HDMI-A-1"),
+            ("{monitors.id|join: }", "DP-1 HDMI-A-1"),
+            ("{monitors.0.occupied_tags}", "1,3"),
+            ("[{monitors.1.occupied_tags|default:none}]", "[none]"),
+            ("{monitors.tags.tag}", "1,2"),
+            ("{submap|default:default}", "default"),
+            ("{missing.field}|", "|"),
+            ("{windows.7e3.flags}", r#"{"sticky":true}"#),
+            ("{windows.7e3.title}", "a\\nb"),
+            ("{windows.7e3.title|json}", r#""a\nb""#),
+            ("{active_monitor|json}", r#""DP-1""#),
+            ("{monitors.9.id|default:-}", "-"),
+            (r"\{{active_monitor}\}\tx\\", "{DP-1}\tx\\"),
+            ("{monitors.id|join:\\|}", "DP-1|HDMI-A-1"),
+            ("{ active_monitor }", "DP-1"),
+        ] {
+            assert_eq!(render(template, &query), expected, "{}", template);
+        }
+        assert_eq!(render("{.}", &json!("x")), "x");
+        assert_eq!(render("{.}", &json!([1, 2])), "1,2");
+    }
+
+    #[test]
+    fn wide_names() {
+        let summary = json!({"tags": [
+            {"tag": 1, "name": "web"},
+            {"tag": 2, "name": "仕事"},
+            {"tag": 3, "name": "🎵"},
+            {"tag": 4, "name": "cafe\u{301}"},
+            {"tag": 5, "name": "仕事のメールと予定"},
+        ]});
+        let rows: Vec<String> = (0..5).map(|i| render(&format!("{{tags.{}.name|trunc:8|pad:8}}|", i), &summary)).collect();
+        assert_eq!(rows, ["web     |", "仕事    |", "🎵      |", "cafe\u{301}    |", "仕事の… |"]);
+        assert!(rows.iter().all(|row| text::width(row) == 9));
+        assert_eq!(render("{tags.name|trunc:12}", &summary), "web,仕事,🎵…");
+        assert_eq!(render("{missing|default:なし|pad:6}|", &summary), "なし  |");
+    }
+
+    #[test]
+    fn template_errors() {
+        let err = |template: &str| Template::parse(template).unwrap_err();
+        assert_eq!(err("a {b"), TemplateError { column: 3, message: "`{` without a `}`".into() });
+        assert_eq!(err("a}").column, 2);
+        assert_eq!(err("{a{b}}").column, 3);
+        assert_eq!(err("x {}").column, 3);
+        assert_eq!(err("{a..b}").message, "empty key in `a..b`");
+        assert_eq!(err("{a|upper}"), TemplateError { column: 3, message: "unknown filter `upper`, expected join, default, json, trunc or pad".into() });
+        assert_eq!(err("{a|join}").column, 3);
+        assert_eq!(err("{a|pad:wide}").message, "`pad` takes a number of columns, not `wide`");
+        assert_eq!(err(r"\x").message, "unknown escape `\\x`");
+        assert_eq!(err(r"ab\").column, 3);
+
+        assert_eq!(err("a {b").annotate("a {b"), "column 3: `{` without a `}`\na {b\n  ^");
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, sync::{Mutex, OnceLock}};
+
+use crate::{hyprctl::ClientInfo, state::WindowAddr};
+
+/// Hyprland's fullscreen mode for a real fullscreen window, as opposed to 1 for maximized.
+pub const FULLSCREEN: u8 = 2;
+
+/// Fullscreen windows, so fullscreen can be restored when a silent move to a hidden workspace dropped it.
+#[derive(Debug, Default)]
+pub struct Fullscreen {
+    windows: HashMap<WindowAddr, u8>,
+}
+
+impl Fullscreen {
+    /// Records the window's fullscreen mode. 0 means it isn't fullscreen.
+    pub fn set(&mut self, addr: &WindowAddr, mode: u8) {
+        if mode == 0 {
+            self.windows.remove(addr);
+        } else {
+            self.windows.insert(*addr, mode);
+        }
+    }
+
+    /// Records fullscreen windows which existed before we started.
+    pub fn adopt_clients(&mut self, clients: &[ClientInfo]) {
+        for client in clients.iter().filter(|c| c.fullscreen != 0) {
+            match client.window() {
+                Ok(window) => self.set(&window, client.fullscreen),
+                Err(err) => tracing::error!(%err, "skipping fullscreen client"),
+            }
+        }
+    }
+
+    /// Forgets a window, e.g. because it was closed.
+    pub fn forget(&mut self, addr: &WindowAddr) {
+        self.windows.remove(addr);
+    }
+
+    pub fn mode(&self, addr: &WindowAddr) -> Option<u8> {
+        self.windows.get(addr).copied()
+    }
+
+    /// Dispatches making the windows fullscreen again. Each one is focused on the way.
+    pub fn restore_dispatches<'a>(&self, windows: impl Iterator<Item = &'a WindowAddr>) -> Vec<String> {
+        windows
+            .filter_map(|w| self.mode(w).map(|mode| (w, mode)))
+            .flat_map(|(w, mode)| [
+                format!("dispatch focuswindow address:0x{}", w),
+                format!("dispatch fullscreenstate {} -1", mode),
+            ])
+            .collect()
+    }
+}
+
+pub fn fullscreen_windows() -> &'static Mutex<Fullscreen> {
+    static FULLSCREEN_WINDOWS: OnceLock<Mutex<Fullscreen>> = OnceLock::new();
+    FULLSCREEN_WINDOWS.get_or_init(|| Mutex::new(Fullscreen::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{addr, hyprctl::ClientInfo};
+
+    use super::{Fullscreen, FULLSCREEN};
+
+    #[test]
+    fn adopt_fullscreen_clients() {
+        let clients: Vec<ClientInfo> = serde_json::from_str(r#"[
+            {"address": "0xaa", "workspace": {"id": 1, "name": "1"}, "monitor": 0, "class": "mpv", "title": "", "fullscreen": 2},
+            {"address": "0xbb", "workspace": {"id": 1, "name": "1"}, "monitor": 0, "class": "kitty", "title": "", "fullscreen": 0},
+            {"address": "0xcc", "workspace": {"id": 2, "name": "2"}, "monitor": 0, "class": "mpv", "title": "", "fullscreen": true}
+        ]"#).unwrap();
+
+        let mut fullscreen = Fullscreen::default();
+        fullscreen.adopt_clients(&clients);
+        assert_eq!(fullscreen.mode(&addr("aa")), Some(2));
+        assert_eq!(fullscreen.mode(&addr("bb")), None);
+        assert_eq!(fullscreen.mode(&addr("cc")), Some(FULLSCREEN));
+
+        assert_eq!(fullscreen.restore_dispatches([addr("bb"), addr("aa")].iter()), vec![
+            "dispatch focuswindow address:0xaa",
+            "dispatch fullscreenstate 2 -1",
+        ]);
+
+        fullscreen.set(&addr("aa"), 0);
+        fullscreen.forget(&addr("cc"));
+        assert!(fullscreen.restore_dispatches([addr("aa"), addr("cc")].iter()).is_empty());
+    }
+}
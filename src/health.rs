@@ -0,0 +1,225 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Mutex, OnceLock}};
+
+use tokio::sync::Notify;
+
+use crate::{hyprctl::ClientInfo, state::WindowAddr};
+
+/// Consecutive dispatch failures before a window is reported as out of sync.
+pub const DESYNC_THRESHOLD: u32 = 3;
+
+/// Closed windows remembered to leave their dispatches out of batches still queued.
+const CLOSED_WINDOWS: usize = 64;
+
+/// Tracks windows whose dispatches keep failing, so a desync is reported instead of retried silently.
+#[derive(Debug, Default)]
+pub struct DispatchHealth {
+    failures: HashMap<WindowAddr, Failure>,
+    /// The last windows which closed, newest last.
+    closed: VecDeque<WindowAddr>,
+    /// Where Hyprland last put each window, as far as a dispatch that went through or a movewindowv2 tells.
+    workspaces: HashMap<WindowAddr, i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The last dispatch that failed for the window.
+    pub dispatch: String,
+    /// Where the failing dispatch moves the window to, if it moves it.
+    pub expected_workspace: Option<i64>,
+    pub count: u32,
+}
+
+impl DispatchHealth {
+    /// Records the result of a batch. Returns windows that just crossed the desync threshold.
+    pub fn record(&mut self, dispatches: &[String], ok: bool) -> Vec<(WindowAddr, Failure)> {
+        let mut desynced = vec![];
+
+        for dispatch in dispatches {
+            let addr = match dispatch_addr(dispatch) {
+                Some(addr) => addr,
+                None => continue,
+            };
+
+            if ok {
+                self.failures.remove(&addr);
+                if let Some(workspace) = dispatch_workspace(dispatch) {
+                    self.workspaces.insert(addr, workspace);
+                }
+                continue;
+            }
+
+            let failure = self.failures.entry(addr).or_insert(Failure {
+                dispatch: String::new(),
+                expected_workspace: None,
+                count: 0,
+            });
+            failure.dispatch = dispatch.clone();
+            failure.expected_workspace = dispatch_workspace(dispatch);
+            failure.count += 1;
+
+            if failure.count == DESYNC_THRESHOLD {
+                desynced.push((addr, failure.clone()));
+            }
+        }
+
+        desynced
+    }
+
+    /// Forgets a window, e.g. because it was closed.
+    pub fn forget(&mut self, addr: &WindowAddr) {
+        self.failures.remove(addr);
+    }
+
+    /// Forgets the closed window, and leaves its dispatches out of the batches sent from now on.
+    pub fn window_closed(&mut self, addr: &WindowAddr) {
+        self.forget(addr);
+        self.workspaces.remove(addr);
+        if self.closed.len() == CLOSED_WINDOWS {
+            self.closed.pop_front();
+        }
+        self.closed.push_back(*addr);
+    }
+
+    /// Records where the windows which existed before we started are.
+    pub fn adopt_clients(&mut self, clients: &[ClientInfo]) {
+        for client in clients {
+            if let Ok(window) = client.window() {
+                self.workspaces.insert(window, client.workspace.id);
+            }
+        }
+    }
+
+    /// Hyprland moved a window, whoever asked it to.
+    pub fn window_moved(&mut self, addr: WindowAddr, workspace: i64) {
+        self.workspaces.insert(addr, workspace);
+    }
+
+    /// Where Hyprland last put the window, if that's known.
+    pub fn workspace(&self, addr: &WindowAddr) -> Option<i64> {
+        self.workspaces.get(addr).copied()
+    }
+
+    /// A window opened with the address of a closed one.
+    pub fn window_opened(&mut self, addr: &WindowAddr) {
+        self.closed.retain(|a| a != addr);
+    }
+
+    /// The dispatches, without those of windows which closed since they were made.
+    pub fn live(&self, dispatches: Vec<String>) -> Vec<String> {
+        dispatches.into_iter().filter(|d| {
+            let closed = dispatch_addr(d).is_some_and(|addr| self.closed.contains(&addr));
+            if closed {
+                tracing::debug!(dispatch = d, "leaving out the dispatch of a closed window");
+            }
+            !closed
+        }).collect()
+    }
+
+    pub fn desynced(&self) -> impl Iterator<Item = (&WindowAddr, &Failure)> {
+        self.failures.iter().filter(|(_, f)| f.count >= DESYNC_THRESHOLD)
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.desynced().next().is_none()
+    }
+}
+
+pub fn dispatch_health() -> &'static Mutex<DispatchHealth> {
+    static HEALTH: OnceLock<Mutex<DispatchHealth>> = OnceLock::new();
+    HEALTH.get_or_init(|| Mutex::new(DispatchHealth::default()))
+}
+
+/// Notified when a window goes out of sync or back in, so the main loop tells subscribers.
+pub fn desync_changed() -> &'static Notify {
+    static CHANGED: OnceLock<Notify> = OnceLock::new();
+    CHANGED.get_or_init(Notify::new)
+}
+
+/// Window address targeted by a dispatch.
+pub(crate) fn dispatch_addr(dispatch: &str) -> Option<WindowAddr> {
+    let (_, rest) = dispatch.split_once("address:0x")?;
+    rest.split(|c: char| !c.is_ascii_hexdigit()).next()?.parse().ok()
+}
+
+/// Workspace a dispatch moves its window to.
+fn dispatch_workspace(dispatch: &str) -> Option<i64> {
+    let (command, rest) = dispatch.strip_prefix("dispatch ")?.split_once(' ')?;
+    if command != "movetoworkspacesilent" && command != "movetoworkspace" {
+        return None;
+    }
+    rest.split_once(',')?.0.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dispatch_addr, dispatch_workspace, DispatchHealth, CLOSED_WINDOWS, DESYNC_THRESHOLD};
+
+    #[test]
+    fn parse_dispatch_addr() {
+        assert_eq!(dispatch_addr("dispatch movetoworkspacesilent 101,address:0x55ab12"), Some(0x55ab12.into()));
+        assert_eq!(dispatch_addr("dispatch focuswindow address:0xdead"), Some(0xdead.into()));
+        assert_eq!(dispatch_addr("dispatch focusmonitor DP-1"), None);
+        assert_eq!(dispatch_workspace("dispatch movetoworkspacesilent 101,address:0x55ab12"), Some(101));
+        assert_eq!(dispatch_workspace("dispatch movetoworkspace 2,address:0x55ab12"), Some(2));
+        assert_eq!(dispatch_workspace("dispatch focuswindow address:0xdead"), None);
+    }
+
+    #[test]
+    fn desync_after_threshold() {
+        let mut health = DispatchHealth::default();
+        let batch = vec![
+            "dispatch movetoworkspacesilent 101,address:0xaaaa".to_string(),
+            "dispatch movetoworkspacesilent 1,address:0xbbbb".to_string(),
+        ];
+
+        for _ in 1..DESYNC_THRESHOLD {
+            assert!(health.record(&batch, false).is_empty());
+        }
+        assert!(health.healthy());
+
+        let desynced = health.record(&batch, false);
+        assert_eq!(desynced.len(), 2);
+        assert_eq!(desynced[0].1.count, DESYNC_THRESHOLD);
+        assert_eq!(desynced.iter().map(|(_, f)| f.expected_workspace).collect::<Vec<_>>(), [Some(101), Some(1)]);
+        assert!(!health.healthy());
+
+        // reported once, not on every further failure
+        assert!(health.record(&batch, false).is_empty());
+
+        health.record(&batch[..1], true);
+        assert_eq!(health.desynced().count(), 1);
+        assert_eq!(health.workspace(&0xaaaa.into()), Some(101));
+        health.window_moved(0xaaaa.into(), 3);
+        assert_eq!(health.workspace(&0xaaaa.into()), Some(3));
+
+        health.forget(&0xbbbb.into());
+        assert!(health.healthy());
+    }
+
+    #[test]
+    fn closed_windows() {
+        let mut health = DispatchHealth::default();
+        let batch = || vec![
+            "dispatch movetoworkspacesilent 101,address:0xaaaa".to_string(),
+            "dispatch focusmonitor DP-1".to_string(),
+            "dispatch focuswindow address:0xaaaa".to_string(),
+            "dispatch movetoworkspacesilent 1,address:0xbbbb".to_string(),
+        ];
+        assert_eq!(health.live(batch()), batch());
+
+        health.record(&batch()[..1], false);
+        health.window_closed(&0xaaaa.into());
+        assert!(health.failures.is_empty());
+        assert_eq!(health.live(batch()), vec![batch()[1].clone(), batch()[3].clone()]);
+
+        health.window_opened(&0xaaaa.into());
+        assert_eq!(health.live(batch()), batch());
+        health.window_closed(&0xaaaa.into());
+
+        // only the last ones are remembered
+        for addr in 1..=CLOSED_WINDOWS as u64 {
+            health.window_closed(&addr.into());
+        }
+        assert_eq!(health.live(batch()), batch());
+    }
+}
@@ -0,0 +1,292 @@
+#[cfg(feature = "hooks")]
+use std::{collections::{BTreeMap, HashMap}, sync::{atomic::{AtomicU32, Ordering}, Arc, Mutex}};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+#[cfg(feature = "hooks")]
+use crate::hyprctl::{hyprctl_enqueue, hyprctl_getoption, hyprctl_with_cmd};
+#[cfg(feature = "hooks")]
+use crate::{config::TagConfig, state::MAX_TAGS};
+use crate::{hyprctl::mock, monitor::ChangeSource};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagHook {
+    pub tag: u8,
+    /// hyprctl commands run when the tag becomes visible. `keyword` options are restored on hide.
+    #[serde(default)]
+    pub hyprctl: Vec<String>,
+    /// Shell command spawned when the tag becomes visible. `HYPRTAG_TAG` is the tag, `HYPRTAG_SOURCE` what showed it
+    /// (`ctrl`, `event`, `rule`, `reconcile` or `startup`), `HYPRTAG_SOURCE_DETAIL` the command, event or rule and
+    /// `HYPRTAG_ACTIVE_TAG` the monitor's active tag by `active_tag_policy`.
+    pub exec: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Hooks {
+    on_show: Vec<TagHook>,
+    #[cfg(feature = "hooks")]
+    saved_options: Arc<Mutex<HashMap<String, String>>>,
+    /// Tags with `hyprctl_on_show` or `hyprctl_on_hide` commands.
+    #[cfg(feature = "hooks")]
+    tag_commands: BTreeMap<u8, TagConfig>,
+    /// Tags whose `hyprctl_on_show` commands are in effect.
+    #[cfg(feature = "hooks")]
+    styled: AtomicU32,
+}
+
+impl Hooks {
+    pub fn new(on_show: Vec<TagHook>) -> Self {
+        Self {
+            on_show,
+            #[cfg(feature = "hooks")]
+            saved_options: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "hooks")]
+            tag_commands: BTreeMap::new(),
+            #[cfg(feature = "hooks")]
+            styled: AtomicU32::new(0),
+        }
+    }
+
+    /// Replaces the configured hooks. Options saved by the previous hooks are still restored on hide.
+    pub fn set_hooks(&mut self, on_show: Vec<TagHook>) {
+        self.on_show = on_show;
+    }
+
+    /// Replaces the tags' hyprctl commands. Options saved for the previous ones are still restored on hide.
+    #[cfg(feature = "hooks")]
+    pub fn set_tag_commands(&mut self, tags: &BTreeMap<u8, TagConfig>) {
+        self.tag_commands = tags.iter()
+            .filter(|(tag, t)| (1..=MAX_TAGS).contains(*tag) && !(t.hyprctl_on_show.is_empty() && t.hyprctl_on_hide.is_empty()))
+            .map(|(tag, t)| (*tag, t.clone()))
+            .collect();
+    }
+
+    #[cfg(not(feature = "hooks"))]
+    pub fn set_tag_commands(&mut self, _tags: &std::collections::BTreeMap<u8, crate::config::TagConfig>) {}
+
+    /// Runs the shown tags' `hyprctl_on_show` commands. Called before the dispatches moving their windows in, so the
+    /// windows don't show up with the old gaps and borders first.
+    #[cfg(feature = "hooks")]
+    pub fn tags_showing(&self, shown: u64) {
+        for (tag, config) in self.tag_commands.iter().filter(|(tag, _)| shown & 1<<(*tag - 1) != 0) {
+            if self.styled.fetch_or(1<<(tag - 1), Ordering::Relaxed) & 1<<(tag - 1) != 0 {
+                continue;
+            }
+            tracing::debug!(tag, "tag shown commands");
+            self.run_saving_options(config.hyprctl_on_show.clone());
+        }
+    }
+
+    #[cfg(not(feature = "hooks"))]
+    pub fn tags_showing(&self, _shown: u64) {}
+
+    #[cfg(feature = "hooks")]
+    pub fn tags_changed(&self, shown: u64, hidden: u64, active_tag: Option<u8>, source: &ChangeSource) {
+        for hook in matching(&self.on_show, hidden) {
+            self.hide(hook);
+        }
+        for (tag, config) in self.tag_commands.iter().filter(|(tag, _)| hidden & 1<<(*tag - 1) != 0) {
+            self.tag_hidden(*tag, config);
+        }
+        for hook in matching(&self.on_show, shown) {
+            self.show(hook, active_tag.unwrap_or(hook.tag), source);
+        }
+    }
+
+    /// Built without hooks, they never run.
+    #[cfg(not(feature = "hooks"))]
+    pub fn tags_changed(&self, _shown: u64, _hidden: u64, _active_tag: Option<u8>, _source: &ChangeSource) {}
+
+    #[cfg(feature = "hooks")]
+    fn show(&self, hook: &TagHook, active_tag: u8, source: &ChangeSource) {
+        tracing::debug!(?hook, %source, "tag shown hook");
+
+        self.run_saving_options(hook.hyprctl.clone());
+
+        if let Some(cmd) = &hook.exec {
+            exec_with_env(cmd, &hook_env(hook.tag, active_tag, source));
+        }
+    }
+
+    #[cfg(feature = "hooks")]
+    fn hide(&self, hook: &TagHook) {
+        tracing::debug!(?hook, "tag hidden hook");
+
+        let options: Vec<String> = hook.hyprctl.iter()
+            .filter_map(|c| keyword_option(c))
+            .map(|o| o.to_string())
+            .collect();
+        self.restore_options(options);
+    }
+
+    /// Runs the tag's `hyprctl_on_hide` commands. Without them, restores the options its `hyprctl_on_show` set, except
+    /// those another tag still showing sets too.
+    #[cfg(feature = "hooks")]
+    fn tag_hidden(&self, tag: u8, config: &TagConfig) {
+        let styled = self.styled.fetch_and(!(1<<(tag - 1)), Ordering::Relaxed);
+        if styled & 1<<(tag - 1) == 0 {
+            return;
+        }
+        tracing::debug!(tag, "tag hidden commands");
+
+        if !config.hyprctl_on_hide.is_empty() {
+            let commands = config.hyprctl_on_hide.clone();
+            hyprctl_enqueue(async move {
+                if let Err(err) = hyprctl_with_cmd(commands).await {
+                    tracing::error!(%err, "tag hidden commands error");
+                }
+            });
+            return;
+        }
+
+        let kept: Vec<&str> = self.tag_commands.iter()
+            .filter(|(t, _)| **t != tag && styled & 1<<(*t - 1) != 0)
+            .flat_map(|(_, t)| t.hyprctl_on_show.iter().filter_map(|c| keyword_option(c)))
+            .collect();
+        let options = config.hyprctl_on_show.iter()
+            .filter_map(|c| keyword_option(c))
+            .filter(|o| !kept.contains(o))
+            .map(|o| o.to_string())
+            .collect();
+        self.restore_options(options);
+    }
+
+    /// Queues the commands, saving the options they set the first time so they can be restored.
+    #[cfg(feature = "hooks")]
+    fn run_saving_options(&self, commands: Vec<String>) {
+        if commands.is_empty() {
+            return;
+        }
+
+        let saved = self.saved_options.clone();
+        hyprctl_enqueue(async move {
+            for option in commands.iter().filter_map(|c| keyword_option(c)) {
+                if saved.lock().unwrap().contains_key(option) {
+                    continue;
+                }
+                match hyprctl_getoption(option).await {
+                    Ok(value) => {
+                        saved.lock().unwrap().insert(option.to_string(), value);
+                    },
+                    Err(err) => tracing::error!(%err, option, "failed to save option"),
+                }
+            }
+
+            if let Err(err) = hyprctl_with_cmd(commands).await {
+                tracing::error!(%err, "tag shown hook error");
+            }
+        });
+    }
+
+    /// Queues setting the options back to their saved values.
+    #[cfg(feature = "hooks")]
+    fn restore_options(&self, options: Vec<String>) {
+        if options.is_empty() {
+            return;
+        }
+
+        let saved = self.saved_options.clone();
+        hyprctl_enqueue(async move {
+            let args: Vec<String> = {
+                let mut saved = saved.lock().unwrap();
+                options.iter()
+                    .filter_map(|o| saved.remove(o).map(|v| format!("keyword {} {}", o, v)))
+                    .collect()
+            };
+            if args.is_empty() {
+                return;
+            }
+
+            if let Err(err) = hyprctl_with_cmd(args).await {
+                tracing::error!(%err, "tag hidden hook error");
+            }
+        });
+    }
+}
+
+#[cfg(feature = "hooks")]
+fn matching(hooks: &[TagHook], tags: u64) -> impl Iterator<Item = &TagHook> {
+    hooks.iter().filter(move |h| (1..=MAX_TAGS).contains(&h.tag) && tags & 1<<(h.tag - 1) != 0)
+}
+
+#[cfg(feature = "hooks")]
+fn keyword_option(cmd: &str) -> Option<&str> {
+    let mut words = cmd.split_whitespace();
+    if words.next()? == "keyword" {
+        words.next()
+    } else {
+        None
+    }
+}
+
+/// Environment of an exec hook.
+#[cfg(feature = "hooks")]
+fn hook_env(tag: u8, active_tag: u8, source: &ChangeSource) -> [(&'static str, String); 4] {
+    [
+        ("HYPRTAG_TAG", tag.to_string()),
+        ("HYPRTAG_SOURCE", source.kind().to_string()),
+        ("HYPRTAG_SOURCE_DETAIL", source.detail()),
+        ("HYPRTAG_ACTIVE_TAG", active_tag.to_string()),
+    ]
+}
+
+/// Spawns a shell command without waiting for it.
+pub(crate) fn exec(cmd: &str) {
+    exec_with_env(cmd, &[]);
+}
+
+fn exec_with_env(cmd: &str, env: &[(&str, String)]) {
+    if let Some(mock) = mock() {
+        mock.lock().unwrap().record("exec", cmd);
+        return;
+    }
+    match Command::new("sh").arg("-c").arg(cmd).envs(env.iter().map(|(k, v)| (k, v))).spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                if let Err(err) = child.wait().await {
+                    tracing::error!(%err, "command failed");
+                }
+            });
+        },
+        Err(err) => tracing::error!(%err, cmd, "failed to exec"),
+    }
+}
+
+#[cfg(all(test, feature = "hooks"))]
+mod tests {
+    use crate::monitor::ChangeSource;
+
+    use super::{hook_env, keyword_option, matching, TagHook};
+
+    fn hook(tag: u8) -> TagHook {
+        TagHook { tag, hyprctl: vec![], exec: None }
+    }
+
+    #[test]
+    fn parse_keyword_option() {
+        assert_eq!(keyword_option("keyword general:col.active_border rgb(ff8800)"), Some("general:col.active_border"));
+        assert_eq!(keyword_option("dispatch workspace 1"), None);
+        assert_eq!(keyword_option("keyword"), None);
+    }
+
+    #[test]
+    fn matching_hooks() {
+        let hooks = vec![hook(1), hook(7), hook(0), hook(65)];
+
+        let tags: Vec<u8> = matching(&hooks, 1<<6).map(|h| h.tag).collect();
+        assert_eq!(tags, vec![7]);
+
+        let tags: Vec<u8> = matching(&hooks, u64::MAX).map(|h| h.tag).collect();
+        assert_eq!(tags, vec![1, 7]);
+    }
+
+    #[test]
+    fn exec_hook_env() {
+        let env = hook_env(3, 3, &ChangeSource::Ctrl { command: "show 3".into() });
+        assert_eq!(env.map(|(_, v)| v), ["3", "ctrl", "show 3", "3"]);
+
+        let env = hook_env(9, 2, &ChangeSource::Rule { class: "spotify".into(), tag: 9 });
+        assert_eq!(env.map(|(_, v)| v), ["9", "rule", "spotify 9", "2"]);
+    }
+}
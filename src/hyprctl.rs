@@ -1,33 +1,500 @@
-use serde::Deserialize;
-use tokio::{net::UnixStream, io::{BufStream, AsyncWriteExt, AsyncBufReadExt}, process::Command};
+use std::{collections::{HashMap, VecDeque}, ffi::OsStr, future::Future, io::ErrorKind, pin::Pin, process::Output, sync::{Mutex, OnceLock}};
 
-use crate::hyprland_dir;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{net::UnixStream, io::{BufStream, AsyncWriteExt, AsyncBufReadExt}, process::Command, sync::Notify, time::Instant};
 
-#[derive(Debug, Deserialize)]
+use crate::{exit::Fatal, fullscreen::FULLSCREEN, health::{dispatch_addr, dispatch_health, desync_changed}, hyprland_dir, state::{InvalidWindowAddr, WindowAddr}};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MonitorInfo {
     pub id: u8,
     pub name: String,
+    #[serde(default)]
+    pub description: String,
     pub focused: bool,
+    /// Position in the layout, in logical pixels.
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    /// Size of the mode in physical pixels, before scale and transform.
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+    #[serde(default)]
+    pub scale: f64,
+    /// wl_output transform, the odd ones rotate by 90 or 270 degrees.
+    #[serde(default)]
+    pub transform: u8,
+    /// Id of the monitor whose content Hyprland shows on this one.
+    #[serde(default, rename = "mirrorOf", deserialize_with = "mirror_of")]
+    pub mirror_of: Option<u8>,
+}
+
+impl MonitorInfo {
+    /// Whether the layout point is on the monitor, which spans its mode scaled down, turned when rotated.
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+        let (width, height) = if self.transform % 2 == 1 { (self.height, self.width) } else { (self.width, self.height) };
+        let (width, height) = ((width as f64 / scale).round() as i64, (height as f64 / scale).round() as i64);
+        let (left, top) = (self.x as i64, self.y as i64);
+        (left..left + width).contains(&x) && (top..top + height).contains(&y)
+    }
+}
+
+/// Position of the cursor in the layout, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CursorPos {
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientInfo {
+    /// Window address with the 0x prefix.
+    pub address: String,
+    pub workspace: WorkspaceRef,
+    pub monitor: i64,
+    #[serde(default)]
+    pub class: String,
+    #[serde(default)]
+    pub title: String,
+    /// Fullscreen mode, 0 when not fullscreen. Older Hyprland reports a bool.
+    #[serde(default, deserialize_with = "fullscreen_mode")]
+    pub fullscreen: u8,
+    #[serde(default)]
+    pub xwayland: bool,
+    /// Process owning the window, 0 or -1 when unknown.
+    #[serde(default)]
+    pub pid: i64,
+    /// Position of the top left corner in layout coordinates.
+    #[serde(default)]
+    pub at: [i64; 2],
+    #[serde(default)]
+    pub size: [i64; 2],
+}
+
+impl ClientInfo {
+    pub fn window(&self) -> Result<WindowAddr, InvalidWindowAddr> {
+        self.address.parse()
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        u32::try_from(self.pid).ok().filter(|pid| *pid > 0)
+    }
+}
+
+fn fullscreen_mode<'de, D: serde::Deserializer<'de>>(d: D) -> Result<u8, D::Error> {
+    match serde_json::Value::deserialize(d)? {
+        serde_json::Value::Bool(b) => Ok(if b { FULLSCREEN } else { 0 }),
+        serde_json::Value::Number(n) => Ok(n.as_u64().unwrap_or(0).min(u8::MAX as u64) as u8),
+        _ => Ok(0),
+    }
+}
+
+/// Hyprland reports the id as a string, `none` when not mirroring.
+fn mirror_of<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<u8>, D::Error> {
+    match serde_json::Value::deserialize(d)? {
+        serde_json::Value::String(s) => Ok(s.parse().ok()),
+        serde_json::Value::Number(n) => Ok(n.as_u64().and_then(|n| u8::try_from(n).ok())),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceRef {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceInfo {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub monitor: String,
+    /// Number of windows on the workspace.
+    #[serde(default)]
+    pub windows: u32,
+}
+
+/// Stands in for Hyprland during `--replay`: queries are answered from the capture, and everything which would
+/// be sent to Hyprland is logged with the time since the replay started instead.
+#[derive(Debug)]
+pub struct MockHyprland {
+    pub monitors: Vec<MonitorInfo>,
+    pub clients: Vec<ClientInfo>,
+    /// Values of options, keyed by name.
+    pub options: HashMap<String, String>,
+    pub log: Vec<String>,
+    /// Until when hyprctl hangs, as a stalled Hyprland would.
+    pub stalled_until: Option<Instant>,
+    /// Dispatches containing one of these fail, as ones naming a window Hyprland doesn't know would. The rest of
+    /// their batch is skipped, as older Hyprland versions do.
+    pub rejects: Vec<String>,
+    started: Instant,
+}
+
+impl Default for MockHyprland {
+    fn default() -> Self {
+        Self {
+            monitors: vec![],
+            clients: vec![],
+            options: HashMap::new(),
+            log: vec![],
+            stalled_until: None,
+            rejects: vec![],
+            started: Instant::now(),
+        }
+    }
+}
+
+impl MockHyprland {
+    pub fn record(&mut self, kind: &str, line: &str) {
+        self.log.push(format!("{} {} {}", self.started.elapsed().as_millis(), kind, line));
+    }
+}
+
+/// Waits out a stall of the mock.
+async fn mock_stall(mock: &Mutex<MockHyprland>) {
+    let until = mock.lock().unwrap().stalled_until;
+    if let Some(until) = until {
+        tokio::time::sleep_until(until).await;
+    }
+}
+
+static MOCK: OnceLock<Mutex<MockHyprland>> = OnceLock::new();
+
+/// The mock backend, when replaying.
+pub fn mock() -> Option<&'static Mutex<MockHyprland>> {
+    MOCK.get()
+}
+
+/// Replaces Hyprland with the mock for the rest of the process. The hyprctl worker runs on the current runtime,
+/// so queued dispatches follow its clock.
+pub fn use_mock(mock: MockHyprland) -> anyhow::Result<()> {
+    if MOCK.set(Mutex::new(mock)).is_err() {
+        bail!("mock backend is already in use");
+    }
+    if WORKER.set(Queue::default()).is_err() {
+        bail!("hyprctl worker is already running");
+    }
+    if let Some(queue) = WORKER.get() {
+        tokio::spawn(queue.run());
+    }
+    Ok(())
+}
+
+/// Runs hyprctl from PATH, or from `path` when given. A missing binary is a `Fatal::NoHyprctl` error naming the
+/// directories searched, rather than a bare "No such file or directory".
+async fn hyprctl(args: &[&str], path: Option<&OsStr>) -> anyhow::Result<Output> {
+    let mut cmd = Command::new("hyprctl");
+    cmd.args(args);
+    if let Some(path) = path {
+        cmd.env("PATH", path);
+    }
+    match cmd.output().await {
+        Ok(out) => Ok(out),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let searched = match path.map(|p| p.to_os_string()).or_else(|| std::env::var_os("PATH")) {
+                Some(p) => p.to_string_lossy().to_string(),
+                None => "unset".to_string(),
+            };
+            Err(anyhow!(err)
+                .context(format!("hyprctl not found in PATH={}, it comes with Hyprland", searched))
+                .context(Fatal::NoHyprctl))
+        },
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Version {
+    #[serde(default)]
+    tag: String,
+}
+
+/// Makes sure Hyprland can be talked to with one harmless request, and describes the backend for the startup log.
+pub async fn check_backend() -> anyhow::Result<String> {
+    if mock().is_some() {
+        return Ok("mock".to_string());
+    }
+    check_hyprctl(None).await
+}
+
+async fn check_hyprctl(path: Option<&OsStr>) -> anyhow::Result<String> {
+    Ok(format!("hyprctl, Hyprland {}", hyprland_version(path).await?))
+}
+
+/// The version tag of the running Hyprland, like `v0.41.2`.
+pub async fn hyprland_version(path: Option<&OsStr>) -> anyhow::Result<String> {
+    let out = hyprctl(&["version", "-j"], path).await?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(anyhow!("hyprctl version failed: {}", stderr.trim())).context(Fatal::NoHyprland);
+    }
+    let version: Version = serde_json::from_slice(&out.stdout)
+        .with_context(|| format!("unexpected hyprctl version output: {}", String::from_utf8_lossy(&out.stdout).trim()))
+        .context(Fatal::NoHyprland)?;
+    Ok(version.tag)
 }
 
 pub async fn hyprctl_monitors() -> anyhow::Result<Vec<MonitorInfo>> {
-    let out = Command::new("hyprctl").args(vec!["monitors", "-j"]).output().await?;
+    if let Some(mock) = mock() {
+        mock_stall(mock).await;
+        return Ok(mock.lock().unwrap().monitors.clone());
+    }
+    let out = hyprctl(&["monitors", "-j"], None).await?;
     Ok(serde_json::from_slice(&out.stdout)?)
 }
 
+pub async fn hyprctl_clients() -> anyhow::Result<Vec<ClientInfo>> {
+    if let Some(mock) = mock() {
+        mock_stall(mock).await;
+        return Ok(mock.lock().unwrap().clients.clone());
+    }
+    let out = hyprctl(&["clients", "-j"], None).await?;
+    Ok(serde_json::from_slice(&out.stdout)?)
+}
+
+pub async fn hyprctl_cursorpos() -> anyhow::Result<CursorPos> {
+    if mock().is_some() {
+        bail!("captures don't record the cursor position");
+    }
+    let out = hyprctl(&["cursorpos", "-j"], None).await?;
+    serde_json::from_slice(&out.stdout)
+        .with_context(|| format!("unexpected hyprctl cursorpos output: {}", String::from_utf8_lossy(&out.stdout).trim()))
+}
+
+pub async fn hyprctl_workspaces() -> anyhow::Result<Vec<WorkspaceInfo>> {
+    if mock().is_some() {
+        return Ok(vec![]);
+    }
+    let out = hyprctl(&["workspaces", "-j"], None).await?;
+    Ok(serde_json::from_slice(&out.stdout)?)
+}
+
+#[derive(Debug, Deserialize)]
+struct OptionValue {
+    custom: Option<String>,
+    str: Option<String>,
+    int: Option<i64>,
+    float: Option<f64>,
+}
+
+/// Fetches the current value of a config option in a form `hyprctl keyword` accepts.
+pub async fn hyprctl_getoption(option: &str) -> anyhow::Result<String> {
+    if let Some(mock) = mock() {
+        return mock.lock().unwrap().options.get(option).cloned().with_context(|| format!("option {} isn't in the capture", option));
+    }
+    let out = hyprctl(&["getoption", option, "-j"], None).await?;
+    parse_option_value(&out.stdout)
+}
+
+fn parse_option_value(json: &[u8]) -> anyhow::Result<String> {
+    let value: OptionValue = serde_json::from_slice(json)?;
+
+    if let Some(custom) = value.custom {
+        return Ok(custom);
+    }
+    if let Some(s) = value.str.filter(|s| !s.is_empty() && s != "[[EMPTY]]") {
+        return Ok(s);
+    }
+    match (value.int, value.float) {
+        (Some(0), Some(f)) if f != 0.0 => Ok(f.to_string()),
+        (Some(i), _) => Ok(i.to_string()),
+        (None, Some(f)) => Ok(f.to_string()),
+        (None, None) => bail!("option has no value"),
+    }
+}
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Jobs the worker falls this far behind on start being dropped, the oldest droppable one first.
+const QUEUE_CAPACITY: usize = 64;
+
+struct Queued {
+    /// Whether a later job makes it moot, like a focus or cursor warp.
+    droppable: bool,
+    /// What it does, for the log.
+    what: String,
+    job: Job,
+}
+
+/// Jobs waiting for the hyprctl worker. Queuing never waits, so a stalled Hyprland can't hold up event reading
+/// or ctrl handling, only the dispatches themselves.
+#[derive(Default)]
+struct Queue {
+    jobs: Mutex<VecDeque<Queued>>,
+    ready: Notify,
+}
+
+impl Queue {
+    fn push(&self, queued: Queued) {
+        let dropped = push_bounded(&mut self.jobs.lock().unwrap(), queued, QUEUE_CAPACITY);
+        if let Some(dropped) = dropped {
+            tracing::warn!(what = dropped.what, capacity = QUEUE_CAPACITY, "hyprctl is behind, dropping a queued dispatch");
+            if let Some(mock) = mock() {
+                mock.lock().unwrap().record("dropped", &dropped.what);
+            }
+        }
+        self.ready.notify_one();
+    }
+
+    async fn run(&self) {
+        loop {
+            let queued = self.jobs.lock().unwrap().pop_front();
+            match queued {
+                Some(queued) => queued.job.await,
+                None => self.ready.notified().await,
+            }
+        }
+    }
+}
+
+/// Queues the job, dropping the oldest droppable one once `capacity` are waiting. Jobs which aren't droppable
+/// are never dropped, past the capacity they're queued with a warning.
+fn push_bounded(jobs: &mut VecDeque<Queued>, queued: Queued, capacity: usize) -> Option<Queued> {
+    let mut dropped = None;
+    if jobs.len() >= capacity {
+        match jobs.iter().position(|q| q.droppable) {
+            Some(i) => dropped = jobs.remove(i),
+            None if queued.droppable => return Some(queued),
+            None => tracing::warn!(queued = jobs.len(), "hyprctl is behind, nothing queued can be dropped"),
+        }
+    }
+    jobs.push_back(queued);
+    dropped
+}
+
+static WORKER: OnceLock<Queue> = OnceLock::new();
+
+/// Runs on a thread of its own, so it doesn't go away with the runtime which happened to queue the first job.
+fn worker() -> &'static Queue {
+    let mut started = false;
+    let queue = WORKER.get_or_init(|| {
+        started = true;
+        Queue::default()
+    });
+    if started {
+        let run = move || {
+            let rt = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(rt) => rt,
+                Err(err) => {
+                    tracing::error!(%err, "failed to start hyprctl worker");
+                    return;
+                },
+            };
+            rt.block_on(queue.run());
+        };
+        if let Err(err) = std::thread::Builder::new().name("hyprctl".into()).spawn(run) {
+            tracing::error!(%err, "failed to spawn hyprctl worker");
+        }
+    }
+    queue
+}
+
+/// Runs the job on the hyprctl worker, after every previously queued job has finished. It's never dropped.
+pub fn hyprctl_enqueue<F>(job: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    worker().push(Queued { droppable: false, what: "job".to_string(), job: Box::pin(job) });
+}
+
+/// Like `hyprctl_enqueue`, but dropped when the worker falls behind, for jobs a later one makes moot.
+pub fn hyprctl_enqueue_droppable<F>(what: &str, job: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    worker().push(Queued { droppable: true, what: what.to_string(), job: Box::pin(job) });
+}
+
+/// Whether the dispatch only moves focus or the cursor, which the next one doing so overrides anyway.
+fn droppable_dispatch(dispatch: &str) -> bool {
+    ["dispatch focusmonitor ", "dispatch focuswindow ", "dispatch movecursor "].iter().any(|d| dispatch.starts_with(d))
+}
+
 pub fn hyprctl_batch(args: Vec<String>) {
-    if args.len() == 0 {
+    if args.is_empty() {
         tracing::debug!("no args");
         return;
     }
 
-    tokio::spawn(async move {
-        if let Err(err) = hyprctl_with_cmd(args).await {
-            tracing::error!(%err, "hyprctl err");
+    let droppable = args.iter().all(|d| droppable_dispatch(d)).then(|| args.join(";"));
+    let job = async move {
+        let _ = hyprctl_batch_now(args).await;
+    };
+    match droppable {
+        Some(what) => hyprctl_enqueue_droppable(&what, job),
+        None => hyprctl_enqueue(job),
+    }
+}
+
+/// Sends the batch right away, noting the windows whose dispatches hyprctl keeps rejecting.
+///
+/// Dispatches of windows which closed since the batch was made are left out. When one fails anyway, as a window
+/// can close before its closewindow arrives, the dispatches after it Hyprland didn't run are sent again without
+/// that window's. The batch still fails with the first error.
+pub(crate) async fn hyprctl_batch_now(args: Vec<String>) -> anyhow::Result<()> {
+    let mut args = dispatch_health().lock().unwrap().live(args);
+    let mut first_err = None;
+    while !args.is_empty() {
+        let replies = match hyprctl_batch_replies(&args).await {
+            Ok(replies) => replies,
+            Err(err) => {
+                tracing::error!(%err, "hyprctl err");
+                record_dispatches(&args, false);
+                return Err(first_err.unwrap_or(err));
+            },
+        };
+        let ran = replies.iter().take_while(|r| r.is_ok()).count();
+        record_dispatches(&args[..ran], true);
+        if ran == args.len() {
+            break;
         }
-    });
+
+        // no reply at all means it didn't run either
+        let reason = match replies.get(ran) {
+            Some(Err(reason)) => reason.clone(),
+            _ => "no reply".to_string(),
+        };
+        let failed = &args[ran];
+        tracing::error!(dispatch = failed, reason, "hyprctl err");
+        record_dispatches(std::slice::from_ref(failed), false);
+        first_err.get_or_insert_with(|| anyhow!("hyprctl returns error: {}", reason));
+
+        // the dispatches of the failed window go, those Hyprland ran after it stay done
+        let dead = dispatch_addr(failed);
+        let rest: Vec<String> = args.iter().enumerate().skip(ran + 1)
+            .filter(|(i, d)| !matches!(replies.get(*i), Some(Ok(()))) && (dead.is_none() || dispatch_addr(d) != dead))
+            .map(|(_, d)| d.clone())
+            .collect();
+        if !rest.is_empty() {
+            tracing::info!(dispatches = rest.len(), "sending the rest of a failed batch again");
+        }
+        args = rest;
+    }
+    first_err.map_or(Ok(()), Err)
+}
+
+/// Notes how the dispatches went, reporting windows whose dispatches hyprctl keeps rejecting, or which got back in
+/// sync.
+fn record_dispatches(dispatches: &[String], ok: bool) {
+    let mut health = dispatch_health().lock().unwrap();
+    let before = health.desynced().count();
+    let desynced = health.record(dispatches, ok);
+    if health.desynced().count() != before {
+        desync_changed().notify_one();
+    }
+    for (addr, failure) in desynced {
+        tracing::error!(%addr, dispatch = failure.dispatch, count = failure.count, "window is out of sync, hyprctl keeps rejecting its dispatch");
+    }
 }
 
+#[allow(dead_code)]
 async fn hyprctl_with_sock(args: Vec<String>) -> anyhow::Result<()> {
     let socket = hyprland_dir()?.join(".socket.sock").to_string_lossy().to_string();
     let socket = UnixStream::connect(socket).await?;
@@ -38,7 +505,7 @@ async fn hyprctl_with_sock(args: Vec<String>) -> anyhow::Result<()> {
     for arg in args.iter() {
         buf.truncate(0);
         buf.push_str(arg);
-        buf.push_str("\n");
+        buf.push('\n');
         stream.write_all(buf.as_bytes()).await?;
 
         stream.read_line(&mut buf).await?;
@@ -50,15 +517,170 @@ async fn hyprctl_with_sock(args: Vec<String>) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn hyprctl_with_cmd(args: Vec<String>) -> anyhow::Result<()> {
-    let args = vec![
-        "--batch".into(),
-        args.join(";"),
-    ];
-    tracing::debug!("hyprctl: {}", args.join(" "));
-    let out = Command::new("hyprctl").args(args).output().await?;
+pub(crate) async fn hyprctl_with_cmd(args: Vec<String>) -> anyhow::Result<()> {
+    let replies = hyprctl_batch_replies(&args).await?;
+    if let Some(Err(reason)) = replies.iter().find(|r| r.is_err()) {
+        bail!("hyprctl returns error: {}", reason);
+    }
+    if replies.len() < args.len() {
+        bail!("hyprctl returns error: {} of {} dispatches ran", replies.len(), args.len());
+    }
+    Ok(())
+}
+
+/// Runs the batch, returning the reply to each command Hyprland ran, in order. It stops at the first failing one
+/// or runs the rest anyway, depending on the version.
+async fn hyprctl_batch_replies(args: &[String]) -> anyhow::Result<Vec<Result<(), String>>> {
+    if let Some(mock) = mock() {
+        mock_stall(mock).await;
+        let mut mock = mock.lock().unwrap();
+        let ran = args.iter().take_while(|d| !mock.rejects.iter().any(|r| d.contains(r.as_str()))).count();
+        if ran > 0 {
+            mock.record("hyprctl", &args[..ran].join(";"));
+        }
+        let mut replies = vec![Ok(()); ran];
+        if let Some(rejected) = args.get(ran) {
+            mock.record("rejected", rejected);
+            replies.push(Err("No such window found".to_string()));
+        }
+        return Ok(replies);
+    }
+    let batch = args.join(";");
+    tracing::debug!("hyprctl: --batch {}", batch);
+    let out = hyprctl(&["--batch", &batch], None).await?;
 
     tracing::debug!("hyprctl result: {:?}", out);
 
-    Ok(())
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    if !out.status.success() {
+        bail!("hyprctl returns error: {}", stdout.trim());
+    }
+    Ok(batch_replies(&stdout))
+}
+
+/// The replies in a batch output, `ok` or an error line each. Replies may or may not be separated by newlines.
+fn batch_replies(stdout: &str) -> Vec<Result<(), String>> {
+    let mut replies = vec![];
+    let mut rest = stdout.trim_start();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("ok") {
+            replies.push(Ok(()));
+            rest = after;
+        } else {
+            let (line, after) = rest.split_once('\n').unwrap_or((rest, ""));
+            replies.push(Err(line.trim().to_string()));
+            rest = after;
+        }
+        rest = rest.trim_start();
+    }
+    replies
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exit::{exit_code, Fatal};
+
+    use std::collections::VecDeque;
+
+    use super::{batch_replies, check_hyprctl, droppable_dispatch, parse_option_value, push_bounded, CursorPos, MonitorInfo, Queued};
+
+    fn queued(what: &str, droppable: bool) -> Queued {
+        Queued { droppable, what: what.to_string(), job: Box::pin(async {}) }
+    }
+
+    fn names(jobs: &VecDeque<Queued>) -> Vec<&str> {
+        jobs.iter().map(|q| q.what.as_str()).collect()
+    }
+
+    #[test]
+    fn batch_output() {
+        assert_eq!(batch_replies("okokok"), vec![Ok(()); 3]);
+        assert_eq!(batch_replies("ok\n\nok\n\n"), vec![Ok(()); 2]);
+        assert_eq!(batch_replies(""), vec![]);
+        assert_eq!(batch_replies("ok\n\nNo such window found\n\nok"), vec![Ok(()), Err("No such window found".to_string()), Ok(())]);
+    }
+
+    #[test]
+    fn queue_overflow() {
+        let mut jobs = VecDeque::new();
+        assert!(push_bounded(&mut jobs, queued("move", false), 3).is_none());
+        assert!(push_bounded(&mut jobs, queued("focus 1", true), 3).is_none());
+        assert!(push_bounded(&mut jobs, queued("focus 2", true), 3).is_none());
+
+        // the oldest droppable job goes
+        assert_eq!(push_bounded(&mut jobs, queued("hide", false), 3).unwrap().what, "focus 1");
+        assert_eq!(push_bounded(&mut jobs, queued("focus 3", true), 3).unwrap().what, "focus 2");
+        assert_eq!(names(&jobs), vec!["move", "hide", "focus 3"]);
+
+        // with nothing droppable left, a droppable job is dropped itself and the rest are queued anyway
+        push_bounded(&mut jobs, queued("show", false), 3);
+        assert_eq!(push_bounded(&mut jobs, queued("focus 4", true), 3).unwrap().what, "focus 4");
+        assert!(push_bounded(&mut jobs, queued("restore", false), 3).is_none());
+        assert_eq!(names(&jobs), vec!["move", "hide", "show", "restore"]);
+
+        assert!(droppable_dispatch("dispatch focuswindow address:0x7e3"));
+        assert!(!droppable_dispatch("dispatch movetoworkspacesilent 1,address:0x7e3"));
+    }
+
+    #[test]
+    fn monitor_geometry() {
+        let json = r#"{"id": 1, "name": "DP-2", "focused": false, "x": -1080, "y": -200, "width": 1920, "height": 1080, "scale": 1.0, "transform": 1}"#;
+        let portrait: MonitorInfo = serde_json::from_str(json).unwrap();
+        // turned on its side, it's 1080 wide and 1920 high, left of and above the origin
+        assert!(portrait.contains(-1080, -200));
+        assert!(portrait.contains(-1, 1719));
+        assert!(!portrait.contains(0, 0));
+        assert!(!portrait.contains(-500, 1720));
+
+        let scaled = MonitorInfo { x: 2560, width: 3840, height: 2160, scale: 2.0, ..Default::default() };
+        assert!(scaled.contains(4479, 1079));
+        assert!(!scaled.contains(4480, 0));
+        // captures from before geometry was queried have none
+        assert!(!MonitorInfo::default().contains(0, 0));
+
+        let mirror = |json: &str| serde_json::from_str::<MonitorInfo>(json).unwrap().mirror_of;
+        assert_eq!(mirror(r#"{"id": 1, "name": "HDMI-A-1", "focused": false, "mirrorOf": "0"}"#), Some(0));
+        assert_eq!(mirror(r#"{"id": 1, "name": "HDMI-A-1", "focused": false, "mirrorOf": "none"}"#), None);
+        assert_eq!(mirror(r#"{"id": 1, "name": "HDMI-A-1", "focused": false}"#), None);
+        // as written back into captures
+        let info = MonitorInfo { mirror_of: Some(2), ..Default::default() };
+        assert_eq!(mirror(&serde_json::to_string(&info).unwrap()), Some(2));
+
+        let cursor: CursorPos = serde_json::from_str(r#"{"x": -20, "y": 300}"#).unwrap();
+        assert_eq!(cursor, CursorPos { x: -20, y: 300 });
+    }
+
+    #[test]
+    fn option_value() {
+        let v = parse_option_value(br#"{"option": "general:gaps_in", "int": 5, "set": true}"#).unwrap();
+        assert_eq!(v, "5");
+
+        let v = parse_option_value(br#"{"option": "decoration:dim_strength", "float": 0.5, "set": true}"#).unwrap();
+        assert_eq!(v, "0.5");
+
+        let v = parse_option_value(br#"{"option": "general:col.active_border", "custom": "ff8800ee 0deg", "set": true}"#).unwrap();
+        assert_eq!(v, "ff8800ee 0deg");
+
+        // older versions report every field
+        let v = parse_option_value(br#"{"option": "general:gaps_out", "int": 20, "float": 0.0, "str": "[[EMPTY]]"}"#).unwrap();
+        assert_eq!(v, "20");
+    }
+
+    #[tokio::test]
+    async fn missing_hyprctl() {
+        let dir = std::env::temp_dir().join(format!("hyprtag-path-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let err = check_hyprctl(Some(dir.as_os_str())).await.unwrap_err();
+        assert!(format!("{:#}", err).contains(&format!("hyprctl not found in PATH={}", dir.display())), "{:#}", err);
+        assert_eq!(exit_code(&Err(err)), Fatal::NoHyprctl.code());
+
+        // found, but not talking to Hyprland
+        std::os::unix::fs::symlink("/bin/echo", dir.join("hyprctl")).unwrap();
+        let err = check_hyprctl(Some(dir.as_os_str())).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("unexpected hyprctl version output: version -j"), "{:#}", err);
+        assert_eq!(exit_code(&Err(err)), Fatal::NoHyprland.code());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
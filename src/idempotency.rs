@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use tokio::sync::watch;
+
+/// How many ids are remembered. Retries come within seconds, so this only needs to cover a burst of commands.
+pub const RECENT_IDS: usize = 256;
+
+/// Result of a command, `None` until it completed.
+pub type Outcome = watch::Receiver<Option<String>>;
+
+/// What to do with a command carrying an idempotency key.
+#[derive(Debug)]
+pub enum Seen {
+    /// First time this id is seen: run the command and publish its reply through the sender.
+    New(watch::Sender<Option<String>>),
+    /// A retry: reply with the first attempt's result, waiting for it if it's still running.
+    Duplicate(Outcome),
+    /// The id was already used for a different command.
+    Conflict,
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: String,
+    command: String,
+    outcome: Outcome,
+}
+
+/// Least recently used idempotency keys with the command they were sent with and its result.
+#[derive(Debug)]
+pub struct RecentIds {
+    capacity: usize,
+    entries: VecDeque<Entry>,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Looks the id up, remembering it with the command when it's new.
+    pub fn check(&mut self, id: &str, command: &str) -> Seen {
+        if let Some(index) = self.entries.iter().position(|e| e.id == id) {
+            let entry = &self.entries[index];
+            let seen = if entry.command == command {
+                Seen::Duplicate(entry.outcome.clone())
+            } else {
+                Seen::Conflict
+            };
+            if let Some(entry) = self.entries.remove(index) {
+                self.entries.push_back(entry);
+            }
+            return seen;
+        }
+
+        let (tx, rx) = watch::channel(None);
+        self.entries.push_back(Entry {
+            id: id.to_string(),
+            command: command.to_string(),
+            outcome: rx,
+        });
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+        Seen::New(tx)
+    }
+}
+
+/// Waits for the result of the first attempt. `None` if it never completed.
+pub async fn outcome(mut rx: Outcome) -> Option<String> {
+    loop {
+        if let Some(result) = rx.borrow().clone() {
+            return Some(result);
+        }
+        rx.changed().await.ok()?;
+    }
+}
+
+/// Splits an `id:<key>` prefix off a protocol line.
+pub fn split_id(line: &str) -> anyhow::Result<(Option<&str>, &str)> {
+    let rest = match line.strip_prefix("id:") {
+        Some(rest) => rest,
+        None => return Ok((None, line)),
+    };
+    match rest.split_once(' ') {
+        Some((id, cmd)) if !id.is_empty() && !cmd.trim().is_empty() => Ok((Some(id), cmd.trim_start())),
+        _ => anyhow::bail!("id needs a key and a command"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{outcome, split_id, RecentIds, Seen};
+
+    #[tokio::test]
+    async fn duplicate_ids() {
+        let mut recent = RecentIds::new(2);
+
+        let tx = match recent.check("a", "move 3") {
+            Seen::New(tx) => tx,
+            seen => panic!("unexpected {:?}", seen),
+        };
+        // a retry while the first attempt is still running waits for it
+        let pending = match recent.check("a", "move 3") {
+            Seen::Duplicate(rx) => tokio::spawn(outcome(rx)),
+            seen => panic!("unexpected {:?}", seen),
+        };
+        tx.send(Some("ok".into())).unwrap();
+        assert_eq!(pending.await.unwrap(), Some("ok".into()));
+
+        match recent.check("a", "move 3") {
+            Seen::Duplicate(rx) => assert_eq!(outcome(rx).await, Some("ok".into())),
+            seen => panic!("unexpected {:?}", seen),
+        }
+        assert!(matches!(recent.check("a", "move 4"), Seen::Conflict));
+
+        // "a" was used last, so "b" is the one evicted
+        assert!(matches!(recent.check("b", "show 1"), Seen::New(_)));
+        recent.check("a", "move 3");
+        assert!(matches!(recent.check("c", "show 2"), Seen::New(_)));
+        assert!(matches!(recent.check("a", "move 3"), Seen::Duplicate(_)));
+        assert!(matches!(recent.check("b", "show 1"), Seen::New(_)));
+    }
+
+    #[tokio::test]
+    async fn abandoned_attempt() {
+        let mut recent = RecentIds::new(2);
+        let tx = match recent.check("a", "move 3") {
+            Seen::New(tx) => tx,
+            seen => panic!("unexpected {:?}", seen),
+        };
+        drop(tx);
+        match recent.check("a", "move 3") {
+            Seen::Duplicate(rx) => assert_eq!(outcome(rx).await, None),
+            seen => panic!("unexpected {:?}", seen),
+        }
+    }
+
+    #[test]
+    fn split_ids() {
+        assert_eq!(split_id("move 3").unwrap(), (None, "move 3"));
+        assert_eq!(split_id("id:abc move 3").unwrap(), (Some("abc"), "move 3"));
+        assert!(split_id("id:abc").is_err());
+        assert!(split_id("id: move 3").is_err());
+    }
+}
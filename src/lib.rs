@@ -0,0 +1,10 @@
+//! What the daemon shares with hyprtagctl and the benchmarks: the tag state machine, and the control socket
+//! protocol with the session lookup and reply rendering the client needs.
+
+pub mod exit;
+pub mod format;
+pub mod line;
+pub mod protocol;
+pub mod session;
+pub mod state;
+pub mod text;
@@ -0,0 +1,161 @@
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::text::floor_char_boundary;
+
+/// Longest line accepted from the ctrl and event sockets, including the newline.
+pub const MAX_LINE: usize = 64 * 1024;
+
+/// Replies longer than this are streamed in chunks, so clients can keep reading them line by line.
+pub const STREAM_THRESHOLD: usize = 16 * 1024;
+/// Reply bytes per `data` line of a streamed reply.
+pub const CHUNK: usize = 4 * 1024;
+/// Longest reply sent, anything beyond is cut off.
+pub const MAX_REPLY: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum ReadLine {
+    Eof,
+    Line,
+    /// The line didn't end within the limit. What was read is left in the buffer.
+    TooLong,
+}
+
+/// `read_line` which stops reading after `max` bytes instead of growing the buffer without bound.
+pub async fn read_line_limited<R>(reader: &mut R, buf: &mut String, max: usize) -> io::Result<ReadLine>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let n = reader.take(max as u64 + 1).read_line(buf).await?;
+    if n == 0 {
+        Ok(ReadLine::Eof)
+    } else if n > max {
+        Ok(ReadLine::TooLong)
+    } else {
+        Ok(ReadLine::Line)
+    }
+}
+
+/// Protocol lines for a reply. Short replies are a single line, long ones are framed as `begin <id>`,
+/// `data <chunk>` lines, `truncated <bytes>` when cut off at `MAX_REPLY`, and `end <id>`.
+pub fn reply_lines(reply: &str, id: u64) -> Vec<String> {
+    if reply.len() <= STREAM_THRESHOLD {
+        return vec![reply.to_string()];
+    }
+
+    let mut lines = vec![format!("begin {}", id)];
+    let mut rest = &reply[..floor_char_boundary(reply, MAX_REPLY)];
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(floor_char_boundary(rest, CHUNK));
+        lines.push(format!("data {}", chunk));
+        rest = tail;
+    }
+    if reply.len() > MAX_REPLY {
+        tracing::warn!(len = reply.len(), "truncating reply");
+        lines.push(format!("truncated {}", reply.len()));
+    }
+    lines.push(format!("end {}", id));
+    lines
+}
+
+/// Reads one reply as `reply_lines` wrote it, joining a streamed one back together. `None` at EOF.
+pub async fn read_reply<R>(reader: &mut R) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let line = line.trim_end_matches('\n');
+    if !line.starts_with("begin ") {
+        return Ok(Some(line.to_string()));
+    }
+
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "streamed reply ended early"));
+        }
+        let line = line.trim_end_matches('\n');
+        if let Some(chunk) = line.strip_prefix("data ") {
+            reply.push_str(chunk);
+        } else if line.starts_with("end ") {
+            return Ok(Some(reply));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncWriteExt, BufReader}, net::UnixStream};
+
+    use super::{read_line_limited, read_reply, reply_lines, ReadLine, CHUNK, MAX_LINE, MAX_REPLY};
+
+    /// Joins a streamed reply back together like hyprtagctl does, with whether it was truncated.
+    fn reassemble(lines: &[String]) -> (String, bool) {
+        assert_eq!(lines.first().map(|l| l.as_str()), Some("begin 7"));
+        assert_eq!(lines.last().map(|l| l.as_str()), Some("end 7"));
+        let reply = lines.iter().filter_map(|l| l.strip_prefix("data ")).collect();
+        (reply, lines.iter().any(|l| l.starts_with("truncated ")))
+    }
+
+    #[test]
+    fn stream_long_replies() {
+        assert_eq!(reply_lines("ok", 7), vec!["ok"]);
+
+        // multi-byte characters are never split across chunks
+        let reply = format!("[{}]", "\"fenêtre\",".repeat(10_000));
+        let lines = reply_lines(&reply, 7);
+        assert!(lines.len() > 2);
+        assert!(lines.iter().all(|l| l.len() < MAX_LINE && l.len() <= CHUNK + "data ".len()));
+        assert_eq!(reassemble(&lines), (reply, false));
+
+        let reply = "x".repeat(MAX_REPLY + 10);
+        let lines = reply_lines(&reply, 7);
+        assert_eq!(lines[lines.len() - 2], format!("truncated {}", MAX_REPLY + 10));
+        let (reassembled, truncated) = reassemble(&lines);
+        assert!(truncated);
+        assert_eq!(reassembled.len(), MAX_REPLY);
+    }
+
+    #[tokio::test]
+    async fn read_streamed_reply() {
+        let (mut w, r) = UnixStream::pair().unwrap();
+        let mut r = BufReader::new(r);
+
+        let long = "y".repeat(3 * CHUNK * 2);
+        for line in reply_lines("ok", 1).iter().chain(reply_lines(&long, 2).iter()) {
+            w.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+        }
+        drop(w);
+
+        assert_eq!(read_reply(&mut r).await.unwrap().as_deref(), Some("ok"));
+        assert_eq!(read_reply(&mut r).await.unwrap(), Some(long));
+        assert_eq!(read_reply(&mut r).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn limit_line_length() {
+        let (mut w, r) = UnixStream::pair().unwrap();
+        let mut r = BufReader::new(r);
+
+        w.write_all(b"show 1\n").await.unwrap();
+        w.write_all(&[b'a'; 16]).await.unwrap();
+        w.write_all(b"\n").await.unwrap();
+        drop(w);
+
+        let mut buf = String::new();
+        assert_eq!(read_line_limited(&mut r, &mut buf, 16).await.unwrap(), ReadLine::Line);
+        assert_eq!(buf, "show 1\n");
+
+        buf.clear();
+        assert_eq!(read_line_limited(&mut r, &mut buf, 16).await.unwrap(), ReadLine::TooLong);
+        assert!(buf.len() <= 17);
+
+        buf.clear();
+        assert_eq!(read_line_limited(&mut r, &mut buf, 16).await.unwrap(), ReadLine::Eof);
+    }
+}
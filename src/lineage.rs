@@ -0,0 +1,176 @@
+use std::{collections::{HashSet, VecDeque}, fmt::Debug, path::PathBuf};
+
+use crate::state::WindowAddr;
+
+/// How many window pids are remembered.
+pub const PID_CACHE: usize = 512;
+
+/// How far up the process tree the window which launched a new one is looked for.
+const MAX_DEPTH: usize = 32;
+
+/// Source of process parentage, replaceable in tests.
+pub trait Ancestry: Debug + Send {
+    /// Parent pid of the process, `None` when it's gone.
+    fn parent(&self, pid: u32) -> Option<u32>;
+}
+
+/// Reads parent pids from `<root>/<pid>/stat`.
+#[derive(Debug)]
+pub struct ProcAncestry {
+    pub root: PathBuf,
+}
+
+impl Default for ProcAncestry {
+    fn default() -> Self {
+        Self { root: PathBuf::from("/proc") }
+    }
+}
+
+impl Ancestry for ProcAncestry {
+    fn parent(&self, pid: u32) -> Option<u32> {
+        let stat = std::fs::read_to_string(self.root.join(pid.to_string()).join("stat")).ok()?;
+        parse_ppid(&stat)
+    }
+}
+
+/// The ppid field of a stat line. The command name before it is in parentheses and may contain anything.
+fn parse_ppid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Pids of tracked windows, and new windows waiting for a clients query to tell which window launched them.
+#[derive(Debug)]
+pub struct Lineage {
+    ancestry: Box<dyn Ancestry>,
+    pids: VecDeque<(u32, WindowAddr)>,
+    pending: HashSet<WindowAddr>,
+}
+
+impl Default for Lineage {
+    fn default() -> Self {
+        Self::new(Box::new(ProcAncestry::default()))
+    }
+}
+
+impl Lineage {
+    pub fn new(ancestry: Box<dyn Ancestry>) -> Self {
+        Self {
+            ancestry,
+            pids: VecDeque::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    /// Remembers the window's pid, forgetting the oldest one past `PID_CACHE`.
+    pub fn window_pid(&mut self, window: WindowAddr, pid: u32) {
+        if self.pids.contains(&(pid, window)) {
+            return;
+        }
+        self.pids.retain(|(_, w)| *w != window);
+        self.pids.push_back((pid, window));
+        if self.pids.len() > PID_CACHE {
+            self.pids.pop_front();
+        }
+    }
+
+    /// Marks a new window to be placed with the window which launched it.
+    pub fn window_opened(&mut self, window: WindowAddr) {
+        self.pending.insert(window);
+    }
+
+    pub fn window_closed(&mut self, window: &WindowAddr) {
+        self.pids.retain(|(_, w)| w != window);
+        self.pending.remove(window);
+    }
+
+    /// Takes a pending window and finds the window owning the closest ancestor of its process.
+    pub fn launched_by(&mut self, window: WindowAddr, pid: u32) -> Option<WindowAddr> {
+        if !self.pending.remove(&window) {
+            return None;
+        }
+        let mut pid = pid;
+        for _ in 0..MAX_DEPTH {
+            pid = self.ancestry.parent(pid)?;
+            if pid <= 1 {
+                return None;
+            }
+            // most recently seen first, when one process has several windows
+            if let Some((_, parent)) = self.pids.iter().rev().find(|(p, w)| *p == pid && *w != window) {
+                return Some(*parent);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use crate::{addr, state::WindowAddr};
+
+    use super::{parse_ppid, Ancestry, Lineage, ProcAncestry, PID_CACHE};
+
+    /// Process tree given as child to parent pids.
+    #[derive(Debug, Default)]
+    pub(crate) struct FakeAncestry(pub(crate) HashMap<u32, u32>);
+
+    impl Ancestry for FakeAncestry {
+        fn parent(&self, pid: u32) -> Option<u32> {
+            self.0.get(&pid).copied()
+        }
+    }
+
+    #[test]
+    fn parse_stat() {
+        assert_eq!(parse_ppid("4242 (kitty) S 1200 4242 4242 0 -1"), Some(1200));
+        // the command name can contain spaces and parentheses
+        assert_eq!(parse_ppid("4242 (my (odd) app) R 77 4242"), Some(77));
+        assert_eq!(parse_ppid("4242 (kitty"), None);
+    }
+
+    #[test]
+    fn proc_fixture() {
+        let root = std::env::temp_dir().join(format!("hyprtag-proc-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("300")).unwrap();
+        std::fs::write(root.join("300/stat"), "300 (cargo build) S 200 300 200 0 -1\n").unwrap();
+
+        let ancestry = ProcAncestry { root: root.clone() };
+        assert_eq!(ancestry.parent(300), Some(200));
+        assert_eq!(ancestry.parent(301), None);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn find_launching_window() {
+        // terminal 100 -> shell 200 -> app 300, and init 1 -> launcher 400
+        let ancestry = FakeAncestry(HashMap::from([(200, 100), (300, 200), (400, 1), (500, 400)]));
+        let mut lineage = Lineage::new(Box::new(ancestry));
+        lineage.window_pid(addr("7e3"), 100);
+
+        // only pending windows are looked up
+        assert_eq!(lineage.launched_by(addr("a99"), 300), None);
+        lineage.window_opened(addr("a99"));
+        assert_eq!(lineage.launched_by(addr("a99"), 300), Some(addr("7e3")));
+        assert_eq!(lineage.launched_by(addr("a99"), 300), None);
+
+        lineage.window_opened(addr("b0b"));
+        assert_eq!(lineage.launched_by(addr("b0b"), 500), None);
+
+        lineage.window_closed(&addr("7e3"));
+        lineage.window_opened(addr("a99"));
+        assert_eq!(lineage.launched_by(addr("a99"), 300), None);
+    }
+
+    #[test]
+    fn bounded_pid_cache() {
+        let mut lineage = Lineage::new(Box::new(FakeAncestry::default()));
+        for pid in 0..PID_CACHE as u32 + 1 {
+            lineage.window_pid(WindowAddr::from(pid as u64 + 1), pid + 2);
+        }
+        assert_eq!(lineage.pids.len(), PID_CACHE);
+        assert!(!lineage.pids.iter().any(|(_, w)| *w == WindowAddr::from(1)));
+    }
+}
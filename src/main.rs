@@ -1,408 +1,518 @@
-use std::path::{Path, PathBuf};
+use std::{future::Future, path::PathBuf, process::ExitCode, time::{Duration, Instant}};
 
-use anyhow::bail;
-use hyprctl::{hyprctl_batch, hyprctl_monitors};
-use tokio::{net::{UnixStream, UnixListener}, io::{BufStream, AsyncBufReadExt}, sync::mpsc};
+use anyhow::{anyhow, Context};
+use hyprctl::{hyprctl_clients, hyprctl_monitors};
+use tokio::{sync::mpsc, task::JoinHandle};
 use tracing_subscriber::EnvFilter;
 
-use monitor::{MonitorsState, Changes, Monitor};
-use state::{Changes as MonitorChanges};
-
-use crate::state::WindowInfo;
-
+use config::Config;
+use ctrl::handle_ctrl;
+use dispatch::handle_changes;
+use events::handle_event;
+use exit::Fatal;
+use hooks::Hooks;
+use profile::Profiles;
+use monitor::MonitorsState;
+
+pub mod activity;
+pub mod adopt;
+pub mod apps;
+pub mod autostart;
+pub mod auth;
+pub mod bench;
+pub mod burst;
+pub mod config;
+pub mod ctrl;
+pub mod dispatch;
+pub mod doctor;
+pub mod events;
+pub mod features;
+pub mod flags;
+pub mod focus;
+pub mod fullscreen;
+pub mod health;
+pub mod hooks;
 pub mod monitor;
-pub mod state;
+pub mod prefix;
 pub mod hyprctl;
+mod idempotency;
+pub mod lineage;
+#[cfg(feature = "persistence")]
+pub mod persist;
+pub mod probe;
+pub mod profile;
+pub mod provenance;
+pub mod recent;
+#[cfg(feature = "replay")]
+pub mod replay;
+#[cfg(feature = "persistence")]
+pub mod restore;
+pub mod rules;
+pub mod schema;
+pub mod snapshot;
+#[cfg(feature = "json-proto")]
+pub mod subscribe;
+pub mod summary;
+pub mod txn;
+pub mod unplug;
+pub mod validity;
+pub mod waybar;
+
+use hyprtag::{exit, format, line, protocol, session, state, text};
+
+/// The daemon's tests' counterpart of `state::addr`, which is only there for the library's own tests.
+#[cfg(test)]
+pub(crate) fn addr(s: &str) -> state::WindowAddr {
+    s.parse().unwrap()
+}
 
+/// Initial delay before restarting a failed task. Doubles with every failure in a row.
+const RESTART_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(10);
+/// Failures in a row after which a task is given up on.
+const MAX_RESTARTS: u32 = 5;
+/// A task which ran at least this long before failing starts over with the initial backoff.
+const HEALTHY_RUN: Duration = Duration::from_secs(30);
+/// How long a restarted Hyprland gets to show up under a new signature, with `follow_new_instance`.
+const REHOME_TIMEOUT: Duration = Duration::from_secs(30);
+const REHOME_POLL: Duration = Duration::from_millis(500);
+
+/// Why a supervised task stopped for good.
 #[derive(Debug)]
-enum Ctrl {
-    ShowTag(u8),
-    ToggleTag(u8),
-    MoveToTag(u8, Option<String>),
-    RestorePrevTags,
-    MoveToNextMonitor,
-    MonitorAdded(Monitor),
+enum Exit {
+    /// The task ran to completion, e.g. Hyprland closed the event socket.
+    Finished(&'static str),
+    /// The task kept failing.
+    Failed(&'static str, anyhow::Error),
 }
 
+const USAGE: &str = "\
+usage: hyprtag [--strict-events] [--signature SIG] [--no-restore]
+       hyprtag --replay CAPTURE [--speed N]
+       hyprtag bench [-n N] --tag a,b [--events]
+       hyprtag waybar-config [--tags N] [--monitor NAME] [--include-hidden]
+       hyprtag doctor [--json] [--fix] [--signature SIG]
+       hyprtag format [--each PATH] TEMPLATE
+       hyprtag --version
+
+  --strict-events  log events hyprtag neither handles nor knows to ignore
+  --signature SIG  Hyprland instance to manage, also read from HYPRTAG_SIGNATURE.
+                   Defaults to HYPRLAND_INSTANCE_SIGNATURE, or the only running instance
+  --no-restore     start without the tags saved by the last run in $XDG_RUNTIME_DIR/hyprtag/state.json
+  --replay CAPTURE run a captured session against a mock Hyprland and print what was sent to it
+                   and the final state, on a virtual clock. --speed paces it at N times the capture.
+                   Needs the replay feature
+  waybar-config    print waybar modules for the tags, named by the running daemon's tag_names.
+                   Tags configured as hidden are left out unless --include-hidden
+  doctor           check the session, Hyprland version and hidden workspaces without the daemon.
+                   Exits 0 when all is good, 1 with warnings, 2 with failures. --fix moves windows
+                   stranded on hidden workspaces back into view
+  format           render the template for every JSON reply on stdin, like `{monitors.id|join: }`.
+                   --each renders it for every element at PATH instead. hyprtagctl --format renders the same way
+  --version        print the version and the cargo features it was built with
+";
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).compact().init();
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let logs = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).compact();
+    let replaying = args.iter().any(|a| a == "--replay");
+    // a replay's output goes to stdout
+    if replaying {
+        logs.with_writer(std::io::stderr).init();
+    } else {
+        logs.init();
+    }
 
-    let monitors = hyprctl_monitors().await?;
-    tracing::error!(?monitors, "monitors");
+    if args.first().is_some_and(|a| a == "bench") {
+        let r = bench::run(&args[1..]).await;
+        if let Err(err) = &r {
+            eprintln!("hyprtag bench: {:#}", err);
+        }
+        return ExitCode::from(exit::exit_code(&r));
+    }
+    if args.first().is_some_and(|a| a == "waybar-config") {
+        let r = waybar::run(&args[1..]).await;
+        if let Err(err) = &r {
+            eprintln!("hyprtag waybar-config: {:#}", err);
+        }
+        return ExitCode::from(exit::exit_code(&r));
+    }
+    if args.first().is_some_and(|a| a == "format") {
+        let r = format::run(&args[1..]);
+        if let Err(err) = &r {
+            eprintln!("hyprtag format: {:#}", err);
+        }
+        return ExitCode::from(exit::exit_code(&r));
+    }
+    if args.first().is_some_and(|a| a == "doctor") {
+        return match doctor::run(&args[1..]).await {
+            Ok(status) => ExitCode::from(status.code()),
+            Err(err) => {
+                eprintln!("hyprtag doctor: {:#}", err);
+                ExitCode::from(doctor::Status::Fail.code())
+            },
+        };
+    }
+    if args.iter().any(|a| a == "--version") {
+        println!("hyprtag {} ({})", env!("CARGO_PKG_VERSION"), features::summary());
+        return ExitCode::SUCCESS;
+    }
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        print!("{}\nexit codes:\n{}", USAGE, exit::codes_help());
+        return ExitCode::SUCCESS;
+    }
 
-    let mut monitors = MonitorsState::from(monitors);
+    if replaying {
+        // on a runtime of its own with a virtual clock
+        #[cfg(feature = "replay")]
+        let r = std::thread::spawn(move || replay::run(&args)).join()
+            .unwrap_or_else(|_| Err(anyhow!("replay panicked")));
+        #[cfg(not(feature = "replay"))]
+        let r: anyhow::Result<()> = Err(anyhow!("built without the replay feature"));
+        if let Err(err) = &r {
+            eprintln!("hyprtag replay: {:#}", err);
+        }
+        return ExitCode::from(exit::exit_code(&r));
+    }
 
-    let hypr_dir = hyprland_dir()?;
-    let hypr_event_sock = hypr_dir.join(".socket2.sock").to_string_lossy().to_string();
+    let r = run(&args).await;
+    let code = exit::exit_code(&r);
+    match &r {
+        Ok(()) => tracing::info!(code, "hyprtag exiting"),
+        Err(err) => tracing::error!(code, reason = %format!("{:#}", err), "hyprtag exiting"),
+    }
+    ExitCode::from(code)
+}
 
-    let hypr_event_sock = UnixStream::connect(&hypr_event_sock).await?;
-    let mut hypr_event_stream = BufStream::new(hypr_event_sock);
+async fn run(args: &[String]) -> anyhow::Result<()> {
+    // log events we neither handle nor know to be ignorable, to spot new Hyprland events
+    let strict_events = args.iter().any(|a| a == "--strict-events");
+    let restore = !args.iter().any(|a| a == "--no-restore");
+    let signature = match args.iter().position(|a| a == "--signature") {
+        Some(i) => Some(args.get(i + 1).context("--signature requires a value")?.as_str()),
+        None => None,
+    };
+    let mut dir = session::select(signature, &session::runtime_roots())?;
 
-    let ctrl_sock = hypr_dir.join(".hyprtagctl.sock").to_string_lossy().to_string();
-    let ctrl_sock = UnixListener::bind(&ctrl_sock)?;
+    let mut profiles = Profiles::new(Config::load()?);
 
-    let (tx, mut rx) = mpsc::channel(10);
-    let tx_inner = tx.clone();
-    tokio::spawn(async move {
-        ctrl_listener(tx_inner, ctrl_sock).await
-    });
+    let backend = hyprctl::check_backend().await?;
+    tracing::info!(backend, "talking to Hyprland");
 
     loop {
-        let mut buf = String::new();
-
-        tokio::select! {
-            r = hypr_event_stream.read_line(&mut buf) => {
-                match r {
-                    Err(err) => bail!(err),
-                    Ok(r) => {
-                        if r == 0 {
-                            break;
-                        }
-                        handle_event_stream(&mut monitors, &buf, tx.clone());
-                    },
-                }
-            }
-
-            msg = rx.recv() => {
-                match msg {
-                    None => {
-                        // tx closed
-                        break;
-                    },
-
-                    Some(msg) => {
-                        handle_ctrl(&mut monitors, msg);
-                    },
-                }
+        let r = serve(&mut profiles, strict_events, restore).await;
+        if !lost_hyprland(&r) {
+            return r;
+        }
+        let roots = session::runtime_roots();
+        if !profiles.config().follow_new_instance {
+            if let Some((sig, new_dir)) = session::new_instance(&roots, &dir) {
+                tracing::error!(sig, dir = %new_dir.display(), "Hyprland restarted under a new signature, set follow_new_instance to follow it");
             }
+            return r;
         }
-    }
 
-    Ok(())
-}
-
-async fn ctrl_listener(tx: mpsc::Sender<Ctrl>, listener: UnixListener) {
-    loop {
-        match listener.accept().await {
-            Err(err) => tracing::error!(%err, "accept failed"),
-
-            Ok((stream, _addr)) => {
-                let tx = tx.clone();
-                tokio::spawn(async move {
-                    handle_ctrl_socket(tx, stream).await
-                });
-            }
+        tracing::error!(dir = %dir.display(), timeout = ?REHOME_TIMEOUT, "lost Hyprland, waiting for a new instance");
+        let (sig, new_dir) = match session::wait_for_new_instance(&roots, &dir, REHOME_TIMEOUT, REHOME_POLL).await {
+            Some(found) => found,
+            None => {
+                tracing::error!("no new Hyprland instance showed up");
+                return r;
+            },
+        };
+        // the old directory may outlive its Hyprland, and hyprtagctl would find the dead socket in it
+        if let Err(err) = ctrl::socket_path().and_then(|path| Ok(std::fs::remove_file(path)?)) {
+            tracing::warn!(%err, "failed to remove the old ctrl socket");
         }
+        session::rehome(&sig, &new_dir);
+        reset_session_globals();
+        tracing::warn!(sig, dir = %new_dir.display(), "Hyprland restarted, following the new instance");
+        dir = new_dir;
     }
 }
 
-async fn handle_ctrl_socket(tx: mpsc::Sender<Ctrl>, stream: UnixStream) {
-    let mut stream = BufStream::new(stream);
-    let mut buf = String::new();
+/// Serves the session until Hyprland goes away or a task is given up on, starting from what Hyprland has now.
+/// With `restore`, windows go back on the tags the last run saved them on.
+#[cfg_attr(not(feature = "persistence"), allow(unused_variables))]
+async fn serve(profiles: &mut Profiles, strict_events: bool, restore: bool) -> anyhow::Result<()> {
+    let monitors = hyprctl_monitors().await.context(Fatal::NoHyprland)?;
+    tracing::error!(?monitors, "monitors");
+    let monitor_count = monitors.len();
+    // fixed for the session, windows are parked on hidden workspaces numbered by it
+    let mut monitors = MonitorsState::new(monitors, profiles.config().tag_count());
+    // before anything is parked on a hidden workspace
+    probe::probe_hidden_workspaces(monitor_count, monitors.tag_count()).await;
+    #[cfg(feature = "persistence")]
+    if let Some(path) = summary::summary_path() {
+        monitors.set_summary(summary::Summary::load(path));
+    }
+    if let Some(path) = profiles.config().runtime_rules_path() {
+        monitors.rules_mut().load_runtime(&path);
+    }
+    profiles.select(&monitors.monitor_ids());
+    let mut hooks = Hooks::new(profiles.config().on_show.clone());
+    profile_changed(&mut monitors, profiles, &mut hooks);
 
-    loop {
-        let r = stream.read_line(&mut buf).await;
-        match r {
-            Err(err) => {
-                tracing::error!(%err, "failed to read");
-                continue;
-            },
+    let (tx, mut rx) = mpsc::channel(10);
 
-            Ok(r) => {
-                if r == 0 {
-                    break;
-                }
+    // after the profile, so windows on unmanaged monitors are known as such
+    match hyprctl_clients().await {
+        Ok(clients) => {
+            clients.iter().for_each(|c| monitors.client_known(c));
+            fullscreen::fullscreen_windows().lock().unwrap().adopt_clients(&clients);
+            health::dispatch_health().lock().unwrap().adopt_clients(&clients);
+            let mut adopted = vec![];
+            #[cfg(feature = "persistence")]
+            if let Some(saved) = restore::state_path().filter(|_| restore)
+                .and_then(|path| restore::load(&path, std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok().as_deref())) {
+                adopted.extend(monitors.restore(&saved, &clients));
+            }
+            adopted.extend(monitors.bootstrap(&clients));
+            adopt::begin(&hooks, adopted, &profiles.config().adoption, tx.clone());
+            monitors.record_placements();
+            monitors.run_autostart(profiles.config(), tx.clone());
+        },
+        Err(err) => tracing::error!(%err, "failed to fetch clients"),
+    }
 
-                let mut p = &buf[..];
-                if p.ends_with("\r\n") {
-                    p = &buf[..buf.len()-2];
-                } else if p.ends_with("\n") {
-                    p = &buf[..buf.len()-1];
-                }
+    let (event_tx, mut event_rx) = mpsc::channel(100);
+    let (exit_tx, mut exit_rx) = mpsc::channel(1);
 
-                tracing::debug!("ctrl recv: {}", p);
+    let ctrl_tx = tx.clone();
+    let tasks = [
+        supervise("events", RESTART_BACKOFF, exit_tx.clone(), move || events::read_events(event_tx.clone(), strict_events)),
+        supervise("ctrl", RESTART_BACKOFF, exit_tx, move || ctrl::listen(ctrl_tx.clone())),
+    ];
 
-                let chunks: Vec<&str> = p.split(" ").collect();
+    #[cfg(feature = "json-proto")]
+    let mut watch = subscribe::Watch::default();
+    #[cfg(feature = "persistence")]
+    let mut saver = restore::state_path().map(restore::Saver::new);
+    let r = loop {
+        tokio::select! {
+            Some(event) = event_rx.recv() => {
+                handle_event(&mut monitors, profiles, &mut hooks, event, tx.clone());
+            }
 
-                if chunks.len() == 0 {
-                    tracing::error!("invalid input: {}", p);
-                    continue;
-                }
-                let cmd = chunks[0];
-                let args = &chunks[1..];
-
-                match cmd {
-                    "move" => {
-                        if args.len() < 1 {
-                            tracing::error!("require move args");
-                            continue;
-                        }
-
-                        let tag = match args[0].parse::<u8>() {
-                            Ok(tag) => tag,
-                            Err(_) => {
-                                tracing::error!("invalid tag: {}", args[0]);
-                                continue;
-                            },
-                        };
-
-                        tx.send(Ctrl::MoveToTag(tag, None)).await.expect("send error");
-                    },
-                    "show" => {
-                        if args.len() < 1 {
-                            tracing::error!("require move args");
-                            continue;
-                        }
-
-                        let tag = match args[0].parse::<u8>() {
-                            Ok(tag) => tag,
-                            Err(_) => {
-                                tracing::error!("invalid tag: {}", args[0]);
-                                continue;
-                            },
-                        };
-                        tx.send(Ctrl::ShowTag(tag)).await.expect("send error");
-                    },
-                    "toggle" => {
-                        if args.len() < 1 {
-                            tracing::error!("require move args");
-                            continue;
-                        }
-
-                        let tag = match args[0].parse::<u8>() {
-                            Ok(tag) => tag,
-                            Err(_) => {
-                                tracing::error!("invalid tag: {}", args[0]);
-                                continue;
-                            },
-                        };
-                        tx.send(Ctrl::ToggleTag(tag)).await.expect("send error");
-                    },
-                    "restore" => {
-                        tx.send(Ctrl::RestorePrevTags).await.expect("send error");
-                    },
+            Some(msg) = rx.recv() => {
+                handle_ctrl(&mut monitors, profiles, &mut hooks, msg, tx.clone());
+            }
 
-                    "move_to_next_monitor" => {
-                        tx.send(Ctrl::MoveToNextMonitor).await.expect("send error");
+            // subscribers are told below
+            _ = health::desync_changed().notified() => {}
+
+            exit = exit_rx.recv() => {
+                match exit {
+                    Some(Exit::Failed(name, err)) => break Err(task_failed(name, err)),
+                    // Hyprland went away
+                    Some(Exit::Finished("events")) => break Err(anyhow!(Fatal::EventStreamClosed)),
+                    Some(Exit::Finished(name)) => {
+                        tracing::info!(task = name, "task finished, shutting down");
+                        break Ok(());
                     },
-
-                    _ => {},
+                    None => break Ok(()),
                 }
-            },
+            }
+        }
+        // a tag shown empty for the first time starts its commands
+        monitors.run_autostart(profiles.config(), tx.clone());
+        #[cfg(feature = "json-proto")]
+        watch.publish(&monitors, profiles.config());
+        #[cfg(feature = "persistence")]
+        if let Some(saver) = &mut saver {
+            saver.save(monitors.saved_state());
         }
+    };
+
+    for task in tasks {
+        task.abort();
     }
+    monitors.stop_timers();
+    adopt::adoption().lock().unwrap().cancel();
+    r
 }
 
-pub(crate) fn hyprland_dir() -> anyhow::Result<PathBuf> {
-    let sig = std::env::var("HYPRLAND_INSTANCE_SIGNATURE")?;
-    Ok(Path::new("/tmp/hypr").join(sig))
+/// Whether the task exit means Hyprland went away, as it does when it restarts.
+fn lost_hyprland(r: &anyhow::Result<()>) -> bool {
+    let code = exit::exit_code(r);
+    code == Fatal::EventStreamClosed.code() || code == Fatal::EventSocket.code()
 }
 
-fn parse_line<'a>(line: &'a str) -> anyhow::Result<(&'a str, &'a str, &'a str)> {
-    let line = &line[..line.len() - 1]; // remove \n
-    let chunks: Vec<&str> = line.split(">>").collect();
+/// Forgets what the process-wide state knows about the windows of a Hyprland which went away.
+fn reset_session_globals() {
+    *fullscreen::fullscreen_windows().lock().unwrap() = Default::default();
+    *focus::focus_echoes().lock().unwrap() = Default::default();
+    *focus::move_echoes().lock().unwrap() = Default::default();
+    *health::dispatch_health().lock().unwrap() = Default::default();
+    *prefix::pending_prefix().lock().unwrap() = Default::default();
+}
 
-    if chunks.len() >= 2 {
-        let args: Vec<&str> = chunks[1].split(",").collect();
-        if args.len() >= 2 {
-            Ok((chunks[0], args[0], args[1]))
-        } else {
-            Ok((chunks[0], args[0], &""))
-        }
-    } else if chunks.len() == 1 {
-        Ok((chunks[0], &"", &""))
-    } else {
-        bail!("invalid line: {}", line)
+/// The error a task was given up on with, carrying the exit code for the task unless it has a more specific one.
+fn task_failed(name: &'static str, err: anyhow::Error) -> anyhow::Error {
+    let err = err.context(format!("{} task failed", name));
+    if err.downcast_ref::<Fatal>().is_some() {
+        return err;
+    }
+    match name {
+        "events" => err.context(Fatal::EventStreamClosed),
+        "ctrl" => err.context(Fatal::CtrlSocket),
+        _ => err,
     }
 }
 
-fn handle_event_stream(state: &mut MonitorsState, buf: &str, tx: mpsc::Sender<Ctrl>) {
-    tracing::debug!("[event] {:?}", buf);
+/// Aborts the task when dropped, so aborting a supervisor takes its current run down with it.
+struct AbortOnDrop<T>(JoinHandle<T>);
 
-    match parse_line(&buf) {
-        Err(err) => {
-            tracing::error!(%err, "invalid message received");
-        },
-        Ok((cmd, id, extra)) => {
-            if id == "" {
-                return;
-            }
-            match cmd {
-                "focusedmon" => {
-                    if let Err(err) = state.focused_monitor_changed(id) {
-                        tracing::error!(%err, "focusedmon error")
-                    }
-                },
-
-                "openwindow" => {
-                    if let Err(err) = state.new_window_added(id.into()) {
-                        tracing::error!(%err, "openwindow error");
-                    }
-                },
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
 
-                "closewindow" => {
-                    tracing::info!("closewindow: {}", id);
-                    if let Err(err) = state.window_removed(id.into()) {
-                        tracing::error!(%err, "closewindow error");
-                    }
+/// Runs `task`, restarting it with backoff when it fails or panics.
+/// Reports on `exit` once it finishes, or when it keeps failing and is given up on.
+fn supervise<F, Fut>(name: &'static str, backoff: Duration, exit: mpsc::Sender<Exit>, mut task: F) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut failures = 0;
+        loop {
+            let started = Instant::now();
+            let mut run = AbortOnDrop(tokio::spawn(task()));
+            let err = match (&mut run.0).await {
+                Ok(Ok(())) => {
+                    let _ = exit.send(Exit::Finished(name)).await;
+                    return;
                 },
+                Ok(Err(err)) => err,
+                Err(err) => anyhow!("task panicked: {}", err),
+            };
 
-                "activewindowv2" => {
-                    if let Err(err) = state.focus_window_changed(id.into()) {
-                        tracing::error!(%err, "activewindowv2 error");
-                    }
-                },
+            if started.elapsed() >= HEALTHY_RUN {
+                failures = 0;
+            }
+            failures += 1;
+            if failures > MAX_RESTARTS {
+                let _ = exit.send(Exit::Failed(name, err)).await;
+                return;
+            }
 
-                "monitoradded" => {
-                    if let Err(err) = state.monitor_added(id, tx) {
-                        tracing::error!(%err, "monitoradded error");
-                    }
-                },
+            let delay = backoff.saturating_mul(1 << (failures - 1)).min(MAX_RESTART_BACKOFF);
+            tracing::error!(%err, task = name, ?delay, "task failed, restarting");
+            tokio::time::sleep(delay).await;
+        }
+    })
+}
 
-                "monitorremoved" => {
-                    let (active_monitor_index, active_tag_index, removed_windows) = match state.monitor_removed(id) {
-                        Ok(w) => w,
-                        Err(err) => {
-                            tracing::error!(%err, "monitorremoved error");
-                            return;
-                        },
-                    };
-
-                    let changes = Changes {
-                        active_monitor_index,
-                        changes: MonitorChanges {
-                            window_added: removed_windows.iter().map(|w| WindowInfo {
-                                addr: w.into(),
-                                tag: active_tag_index as u8 + 1,
-                            }).collect(),
-                            window_removed: vec![],
-                            focus: None,
-                        },
-                    };
-                    handle_changes(changes);
-                },
+pub(crate) fn hyprland_dir() -> anyhow::Result<PathBuf> {
+    session::session_dir()
+}
 
-                //// disable manual window move. this breaks tag toggle feature
-                //"movewindow" => {
-                //    let dest_monitor = extra.parse::<u8>().expect("invalid event");
-                //    if let Err(err) = state.window_moved(id.into(), dest_monitor) {
-                //        tracing::error!(%err, "movewindow error")
-                //    }
-                //},
+/// The current time for timeouts, which follows the virtual clock of a replay.
+pub(crate) fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
 
-                _ => (),
-            }
-        },
+/// Applies the active profile's settings and shows its default tags. Tag assignments are kept.
+pub(crate) fn profile_changed(state: &mut MonitorsState, profiles: &Profiles, hooks: &mut Hooks) {
+    tracing::info!(profile = ?profiles.active(), "profile applied");
+
+    let config = profiles.config();
+    state.set_new_window_tag(config.resolved_new_window_tag());
+    state.set_xwayland_policy(config.xwayland_policy);
+    state.set_active_tag_policy(config.active_tag_policy);
+    state.set_inherit_tag_from_parent(config.inherit_tag_from_parent);
+    state.set_hidden_tags(config.hidden_tags());
+    state.set_tag_monitors(config.tag_monitors());
+    state.set_unmanaged_monitors(config.unmanaged_monitors());
+    dispatch::set_warp_cursor(config.warp_cursor);
+    ctrl::set_tag_names(&config.tag_names);
+    state.set_tag_names(&config.tag_names);
+    auth::set_auth_config(config.ctrl_auth.clone());
+    hooks.set_hooks(config.on_show.clone());
+    hooks.set_tag_commands(&config.tags);
+    state.set_summary_settings(config.summary.settings());
+    state.rules_mut().set_file_rules(&config.rules);
+
+    if let Some(profile) = profiles.active_profile() {
+        for changes in state.show_default_tags(&profile.default_tags) {
+            handle_changes(hooks, changes);
+        }
     }
 }
 
-fn handle_ctrl(state: &mut MonitorsState, msg: Ctrl) {
-    tracing::debug!(?msg, "handle_ctrl");
-    match msg {
-        Ctrl::MoveToTag(tag, window) => {
-            let changes = match state.move_window(tag, window) {
-                Ok(changes) => changes,
-                Err(err) => {
-                    tracing::error!(%err, "Ctrl::MoveToTag error");
-                    return;
-                },
-            };
-
-            handle_changes(changes);
-        },
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicU32, Ordering}, Arc}, time::Duration};
 
-        Ctrl::ShowTag(tag) => {
-            let changes = match state.set_visible_tags(1<<(tag-1)) {
-                Ok(changes) => changes,
-                Err(err) => {
-                    tracing::error!(%err, "Ctrl::ShowTag error");
-                    return;
-                },
-            };
-            tracing::debug!(?changes, "showTag changes");
-            handle_changes(changes);
-        },
+    use anyhow::{anyhow, bail, Context};
+    use tokio::sync::mpsc;
 
-        Ctrl::ToggleTag(tag) => {
-            let changes = match state.toggle_tag(tag) {
-                Ok(changes) => changes,
-                Err(err) => {
-                    tracing::error!(%err, "Ctrl::ToggleTag error");
-                    return;
-                },
-            };
-            handle_changes(changes);
-        },
+    use crate::{exit::{exit_code, Fatal}, lost_hyprland, supervise, task_failed, Exit, MAX_RESTARTS};
 
-        Ctrl::RestorePrevTags => {
-            let changes = match state.restore_prev_tags() {
-                Ok(changes) => changes,
-                Err(err) => {
-                    tracing::error!(%err, "Ctrl::RestorePrevTags error");
-                    return;
-                },
-            };
-            handle_changes(changes);
-        },
+    #[tokio::test]
+    async fn restart_failed_task() {
+        let (exit_tx, mut exit_rx) = mpsc::channel(1);
+        let runs = Arc::new(AtomicU32::new(0));
 
-        Ctrl::MoveToNextMonitor => {
-            let next_monitor = state.next_monitor();
-            let args = vec![
-                format!("dispatch movetoworkspace {}", next_monitor + 1),
-            ];
-            hyprctl_batch(args);
-
-            if let Err(err) = state.move_window_to_monitor(next_monitor, None) {
-                tracing::error!(%err, "failed to move window to next monitor");
-            }
-        },
-
-        Ctrl::MonitorAdded(monitor) => {
-            tracing::info!("handle new monitor: {}", monitor.name);
-            if let Err(err) = state.monitor_added_with_object(monitor) {
-                tracing::error!(%err, "failed to add monitor");
+        let r = runs.clone();
+        supervise("test", Duration::from_millis(1), exit_tx, move || {
+            let r = r.clone();
+            async move {
+                if r.fetch_add(1, Ordering::SeqCst) < 2 {
+                    bail!("boom");
+                }
+                Ok(())
             }
-        },
-    }
-}
+        });
 
-fn handle_changes(changes: Changes) {
-    let mut args: Vec<String> = vec![];
-    args.extend(
-        changes.changes.window_removed.iter()
-            .map(|w| format!("dispatch movetoworkspacesilent {},address:0x{}",
-                             w.tag + 100 + (32*changes.active_monitor_index as u8), w.addr)).collect::<Vec<String>>()
-    );
-    args.extend(
-        changes.changes.window_added.iter()
-            .map(|w| format!("dispatch movetoworkspacesilent {},address:0x{}", changes.active_monitor_index + 1, w.addr)).collect::<Vec<String>>()
-    );
-    if let Some(focus) = changes.changes.focus {
-        args.push(format!("dispatch focuswindow address:0x{}", focus));
+        assert!(matches!(exit_rx.recv().await, Some(Exit::Finished("test"))));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
     }
 
-    hyprctl_batch(args);
-}
+    #[tokio::test]
+    async fn give_up_on_failing_task() {
+        let (exit_tx, mut exit_rx) = mpsc::channel(1);
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let r = runs.clone();
+        supervise("test", Duration::from_millis(1), exit_tx, move || {
+            let r = r.clone();
+            async move {
+                // panics count as failures too
+                if r.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                    panic!("boom");
+                }
+                bail!("boom")
+            }
+        });
 
-#[cfg(test)]
-mod tests {
-    use crate::parse_line;
+        assert!(matches!(exit_rx.recv().await, Some(Exit::Failed("test", _))));
+        assert_eq!(runs.load(Ordering::SeqCst), MAX_RESTARTS + 1);
+    }
 
     #[test]
-    fn test_parse_line() {
-        let line = "openwindow>>12345\n";
+    fn task_exit_codes() {
+        let err = task_failed("events", anyhow!("connection reset"));
+        assert_eq!(exit_code(&Err(err)), Fatal::EventStreamClosed.code());
 
-        let (command, id, extra) = parse_line(line).unwrap();
-        assert_eq!(command, "openwindow");
-        assert_eq!(id, "12345");
-        assert_eq!(extra, "");
+        let err = task_failed("events", Err::<(), _>(anyhow!("refused")).context(Fatal::EventSocket).unwrap_err());
+        assert_eq!(exit_code(&Err(err)), Fatal::EventSocket.code());
 
-        let line = "movewindow>>123456,2\n";
+        let err = task_failed("ctrl", anyhow!("address in use"));
+        assert_eq!(exit_code(&Err(err)), Fatal::CtrlSocket.code());
+    }
 
-        let (command, id, extra) = parse_line(line).unwrap();
-        assert_eq!(command, "movewindow");
-        assert_eq!(id, "123456");
-        assert_eq!(extra, "2");
+    #[test]
+    fn hyprland_lost() {
+        // the event stream closing or its socket refusing connections is what a Hyprland restart looks like
+        assert!(lost_hyprland(&Err(anyhow!(Fatal::EventStreamClosed))));
+        assert!(lost_hyprland(&Err(task_failed("events", anyhow!("refused").context(Fatal::EventSocket)))));
+        assert!(!lost_hyprland(&Err(task_failed("ctrl", anyhow!("address in use")))));
+        assert!(!lost_hyprland(&Ok(())));
     }
 }
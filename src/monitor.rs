@@ -1,30 +1,343 @@
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, fmt, sync::atomic::{AtomicI64, Ordering}, time::Duration};
+
 use anyhow::bail;
-use tokio::{runtime::Handle, sync::mpsc};
-use tracing::instrument::WithSubscriber;
+use serde::Serialize;
+use tokio::{sync::mpsc, task::JoinHandle};
 
-use crate::{state::{State, Changes as MonitorChanges}, hyprctl::{MonitorInfo, hyprctl_monitors, hyprctl_batch}, Ctrl};
+use crate::{activity::{Activity, Clock, WindowTimes}, apps::{Apps, LAUNCH_TIMEOUT}, autostart::{self, Autostart, AUTOSTART_TIMEOUT}, burst::OpenBurst, flags::{resolve_flags, FlagConflict, WindowFlags}, focus::FocusHistory, provenance::{AutostartFailure, Provenance}, recent::RecentWindows, rules::Rules, txn::Transaction, unplug::PendingRemovals, validity::{Validity, PROVISIONAL_TIMEOUT}, lineage::{Ancestry, Lineage}, config::{Config, NewWindowTag, OnTagEmptied, PresentationConfig, XwaylandPolicy}, schema::{tag_id, MonitorEvent, MonitorQuery, PagerCell, PagerRow, QueryReply, TagQuery, WindowQuery, SCHEMA_VERSION}, snapshot::{self, MonitorSnapshot, OutputInfo, StateSnapshot, REDACTED}, summary::{self, Summary, SummarySettings}, state::{check_tag, cycle_tag, ActiveTagPolicy, State, Changes as MonitorChanges, WindowAddr, WindowInfo, MAX_TAGS}, hyprctl::{ClientInfo, CursorPos, MonitorInfo, hyprctl_clients, hyprctl_cursorpos, hyprctl_monitors, hyprctl_batch}, ctrl::{Ctrl, SyncReply}, now};
+#[cfg(feature = "persistence")]
+use crate::restore::{SavedMonitor, SavedState};
 
 #[derive(Debug, Clone)]
 pub struct Monitor {
     pub id: u8,
     pub name: String,
+    pub description: String,
     state: State,
 }
 
+impl Monitor {
+    /// The monitor Hyprland reported, with `tag_count` tags.
+    pub fn new(info: &MonitorInfo, tag_count: u8) -> Self {
+        Self {
+            id: info.id,
+            name: info.name.clone(),
+            description: info.description.clone(),
+            state: State::new(tag_count),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MonitorsState {
     monitors: Vec<Monitor>,
     active_monitor_index: usize,
+    /// Tags of each monitor, which tag numbers and hidden workspaces go up to.
+    tag_count: u8,
+    /// Source of the changes made from now on.
+    source: ChangeSource,
+    pending_focus: Option<JoinHandle<()>>,
+    /// focusedmon events we expect as the echo of our own focusmonitor dispatches.
+    expected_focusedmon: VecDeque<String>,
+    new_window_tag: NewWindowTag,
+    apps: Apps,
+    activity: Activity,
+    mirror: Option<Mirror>,
+    xwayland_policy: XwaylandPolicy,
+    active_tag_policy: ActiveTagPolicy,
+    /// Windows Hyprland reported as XWayland clients.
+    xwayland: HashSet<WindowAddr>,
+    /// Names of monitors hyprtag leaves alone.
+    unmanaged: HashSet<String>,
+    /// Outputs Hyprland mirrors another monitor onto, by name, with the name of that monitor. They aren't tracked,
+    /// commands naming them go to the monitor they mirror.
+    linked: BTreeMap<String, String>,
+    /// Windows seen on unmanaged monitors, which aren't on any tag.
+    unmanaged_windows: HashSet<WindowAddr>,
+    /// Active Hyprland submap, `None` for the default one.
+    submap: Option<String>,
+    /// Names of monitors in dim mode.
+    dim: HashSet<String>,
+    /// Windows dim mode dimmed, to undo when their tag is shown or the mode ends.
+    dimmed_windows: HashSet<WindowAddr>,
+    inherit_tag_from_parent: bool,
+    /// Tags configured as hidden, which cycling and `move_to_empty` pass over.
+    hidden_tags: u64,
+    /// Names of tags from the config, given to monitors as they connect.
+    tag_names: BTreeMap<u8, String>,
+    /// Tags pinned to a monitor, with its name.
+    tag_monitors: BTreeMap<u8, String>,
+    lineage: Lineage,
+    /// Windows kept on a monitor's visible workspace whatever tags are shown, keyed by monitor name.
+    /// They aren't on any tag and never get focus from us.
+    overlay: HashMap<String, Vec<WindowAddr>>,
+    /// The overlay window Hyprland last focused, until focus moves to a tracked window.
+    focused_overlay: Option<WindowAddr>,
+    /// Names of monitors in presentation mode, which rejects commands changing their tags.
+    presentation: HashSet<String>,
+    /// Presentation mode for every monitor, including ones connected later.
+    presentation_everywhere: bool,
+    burst: OpenBurst,
+    /// Windows named by recent events, to tell a closed window we never knew from one we lost track of.
+    recent_windows: RecentWindows,
+    /// Windows the user focused, keyed by monitor name.
+    focus_history: HashMap<String, FocusHistory>,
+    /// Removed monitors which may come back, still tracked until they do or their grace period ends.
+    removals: PendingRemovals,
+    /// Why each window is on its tag.
+    provenance: Provenance,
+    /// Tags which ran their autostart commands this session.
+    autostart: Autostart,
+    /// Foreground time and windows per tag for `summary`.
+    summary: Summary,
+    /// Where windows go when they open, by class and title.
+    rules: Rules,
+    /// Windows a sticky rule keeps visible whatever tags are shown.
+    sticky_windows: HashSet<WindowAddr>,
+    /// Which tracked windows are known to exist.
+    validity: Validity,
+    /// The open transaction, whose commands' dispatches wait for `txn commit`.
+    txn: Option<Transaction>,
+}
+
+/// Where windows were and which tags showed, for a failed transaction to go back to.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    monitors: Vec<Monitor>,
+    active_monitor_index: usize,
+}
+
+/// Why a closed window wasn't tracked.
+#[derive(Debug, PartialEq)]
+enum Untracked {
+    /// No event named it before, like a tooltip closing before its openwindow arrived.
+    NeverSeen,
+    /// It was around, so our state went out of sync.
+    Lost,
+}
+
+/// A tag's windows shown on another monitor while still belonging to their own monitor and tag.
+#[derive(Debug, Clone)]
+struct Mirror {
+    tag: u8,
+    source: String,
+    dest: String,
+    windows: Vec<WindowAddr>,
+}
+
+/// Monitors added, removed, or reported under a new id, keyed by connector name.
+#[derive(Debug, Default, PartialEq)]
+pub struct MonitorsDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Name, old id and new id.
+    pub ids_changed: Vec<(String, u8, u8)>,
+    /// Tracked monitors Hyprland now mirrors another one onto, with the name of that one.
+    pub linked: Vec<(String, String)>,
+    /// Outputs which stopped mirroring, to be tracked like any other monitor.
+    pub unlinked: Vec<String>,
+}
+
+impl MonitorsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.ids_changed.is_empty() && self.linked.is_empty()
+            && self.unlinked.is_empty()
+    }
+
+    /// Logs a one line summary, with the details at debug level.
+    pub fn log(&self) {
+        if self.is_empty() {
+            tracing::info!("monitors unchanged");
+            return;
+        }
+        tracing::info!(
+            added = self.added.len(),
+            removed = self.removed.len(),
+            ids_changed = self.ids_changed.len(),
+            linked = self.linked.len(),
+            unlinked = self.unlinked.len(),
+            "monitors changed",
+        );
+        for name in &self.added {
+            tracing::debug!(name, "monitor added");
+        }
+        for name in &self.removed {
+            tracing::debug!(name, "monitor removed");
+        }
+        for (name, old, new) in &self.ids_changed {
+            tracing::debug!(name, old, new, "monitor id changed");
+        }
+        for (name, source) in &self.linked {
+            tracing::debug!(name, source, "monitor mirrors another");
+        }
+        for name in &self.unlinked {
+            tracing::debug!(name, "monitor stopped mirroring");
+        }
+    }
+}
+
+/// How a monitor treats the windows of tags which aren't visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewMode {
+    /// Moved away to hidden workspaces.
+    Normal,
+    /// Left on the visible workspace, dimmed.
+    Dim,
 }
 
 #[derive(Debug)]
 pub struct Changes {
     pub active_monitor_index: usize,
+    /// Tags of each monitor, which its hidden workspaces are numbered by.
+    pub tag_count: u8,
     pub changes: MonitorChanges,
+    /// What made the change, for logs and hooks.
+    pub source: ChangeSource,
+}
+
+/// Where a change came from. Set by the entry point handling a command or event, and carried by the `Changes`
+/// made while handling it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChangeSource {
+    /// Starting up, like showing the default tags.
+    #[default]
+    Startup,
+    /// A ctrl command, as it was sent.
+    Ctrl { command: String },
+    /// A Hyprland event, or a timer it started like the focus debounce.
+    Event { event: String },
+    /// A launch rule placing the new window of the class on the tag.
+    Rule { class: String, tag: u8 },
+    /// Monitors or clients queried again, and our state brought in line with them.
+    Reconcile,
+}
+
+impl ChangeSource {
+    /// `startup`, `ctrl`, `event`, `rule` or `reconcile`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ChangeSource::Startup => "startup",
+            ChangeSource::Ctrl { .. } => "ctrl",
+            ChangeSource::Event { .. } => "event",
+            ChangeSource::Rule { .. } => "rule",
+            ChangeSource::Reconcile => "reconcile",
+        }
+    }
+
+    /// The command, the event name, or the rule's class and tag.
+    pub fn detail(&self) -> String {
+        match self {
+            ChangeSource::Startup | ChangeSource::Reconcile => String::new(),
+            ChangeSource::Ctrl { command } => command.clone(),
+            ChangeSource::Event { event } => event.clone(),
+            ChangeSource::Rule { class, tag } => format!("{} {}", class, tag),
+        }
+    }
+
+    /// The source as shown for a monitor in presentation mode. A rule's class goes with `redact_classes`, and a
+    /// command's arguments, which may name classes and titles, with either redaction.
+    pub fn redacted(&self, config: &PresentationConfig) -> ChangeSource {
+        match self {
+            ChangeSource::Rule { tag, .. } if config.redact_classes => ChangeSource::Rule { class: REDACTED.to_string(), tag: *tag },
+            ChangeSource::Ctrl { command } if config.redact_classes || config.redact_titles => {
+                let name = command.split_whitespace().next().unwrap_or_default();
+                let command = if name.len() < command.trim().len() { format!("{} {}", name, REDACTED) } else { command.clone() };
+                ChangeSource::Ctrl { command }
+            },
+            source => source.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ChangeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeSource::Ctrl { command } => write!(f, "ctrl '{}'", command),
+            ChangeSource::Startup | ChangeSource::Reconcile => f.write_str(self.kind()),
+            _ => write!(f, "{} {}", self.kind(), self.detail()),
+        }
+    }
+}
+
+/// Queries Hyprland's monitors in the background and sends them back wrapped by `msg`.
+fn request_monitors(tx: mpsc::Sender<Ctrl>, msg: fn(Vec<MonitorInfo>) -> Ctrl) {
+    tokio::spawn(async move {
+        match hyprctl_monitors().await {
+            Ok(monitors) => {
+                if let Err(err) = tx.send(msg(monitors)).await {
+                    tracing::error!(%err, "failed to send monitors");
+                }
+            },
+            Err(err) => tracing::error!(%err, "failed to fetch monitor info"),
+        }
+    });
+}
+
+/// Queries Hyprland's clients and sends them back as `Ctrl::ClientsRefreshed`.
+async fn refresh_clients(tx: mpsc::Sender<Ctrl>) {
+    match hyprctl_clients().await {
+        Ok(clients) => {
+            if let Err(err) = tx.send(Ctrl::ClientsRefreshed(clients)).await {
+                tracing::error!(%err, "failed to send Ctrl::ClientsRefreshed");
+            }
+        },
+        Err(err) => tracing::error!(%err, "failed to fetch clients"),
+    }
+}
+
+/// Workspace showing the visible tags of the monitor at `monitor_index`.
+pub fn visible_workspace(monitor_index: usize) -> i64 {
+    monitor_index as i64 + 1
+}
+
+/// Workspace the hidden workspaces are numbered after, unless the startup probe found Hyprland rejecting them.
+pub const DEFAULT_HIDDEN_BASE: i64 = 100;
+
+static HIDDEN_BASE: AtomicI64 = AtomicI64::new(DEFAULT_HIDDEN_BASE);
+
+/// The workspace the hidden workspaces are numbered after, the first one being the next.
+pub fn hidden_base() -> i64 {
+    HIDDEN_BASE.load(Ordering::Relaxed)
+}
+
+pub fn set_hidden_base(base: i64) {
+    HIDDEN_BASE.store(base, Ordering::Relaxed);
 }
 
-impl From<Vec<MonitorInfo>> for MonitorsState {
-    fn from(value: Vec<MonitorInfo>) -> Self {
+/// Workspace parking the windows of a hidden tag. Each monitor has a range of `tag_count` of them.
+pub fn hidden_workspace(tag_count: u8, monitor_index: usize, tag: u8) -> i64 {
+    hidden_workspace_in(hidden_base(), tag_count, monitor_index, tag)
+}
+
+pub(crate) fn hidden_workspace_in(base: i64, tag_count: u8, monitor_index: usize, tag: u8) -> i64 {
+    tag as i64 + base + tag_count as i64 * monitor_index as i64
+}
+
+/// Index of the monitor and the tag a hidden workspace is for, the inverse of `hidden_workspace`.
+pub fn hidden_workspace_tag(tag_count: u8, workspace: i64) -> Option<(usize, u8)> {
+    hidden_workspace_tag_in(hidden_base(), tag_count, workspace)
+}
+
+fn hidden_workspace_tag_in(base: i64, tag_count: u8, workspace: i64) -> Option<(usize, u8)> {
+    let n = usize::try_from(workspace - hidden_workspace_in(base, tag_count, 0, 1)).ok()?;
+    Some((n / tag_count as usize, (n % tag_count as usize) as u8 + 1))
+}
+
+/// Outputs Hyprland mirrors another monitor onto, by name, with the name of that monitor.
+fn mirror_links(infos: &[MonitorInfo]) -> BTreeMap<String, String> {
+    infos.iter().filter_map(|info| {
+        let source = infos.iter().find(|s| Some(s.id) == info.mirror_of && s.mirror_of.is_none())?;
+        Some((info.name.clone(), source.name.clone()))
+    }).collect()
+}
+
+impl MonitorsState {
+    /// The monitors Hyprland reported, each with `tag_count` tags.
+    pub fn new(value: Vec<MonitorInfo>, tag_count: u8) -> Self {
+        let tag_count = tag_count.clamp(1, MAX_TAGS);
+        let linked = mirror_links(&value);
+        let value: Vec<&MonitorInfo> = value.iter().filter(|i| !linked.contains_key(&i.name)).collect();
         let focused = value.iter().enumerate().find_map(|(i, m)| {
             if m.focused {
                 Some(i)
@@ -33,17 +346,48 @@ impl From<Vec<MonitorInfo>> for MonitorsState {
             }
         }).unwrap_or(0);
 
-        let monitors = value.iter().map(|m| {
-            Monitor {
-                id: m.id,
-                name: m.name.clone(),
-                state: State::new(),
-            }
-        }).collect();
+        let monitors = value.into_iter().map(|info| Monitor::new(info, tag_count)).collect();
 
         Self {
             monitors,
             active_monitor_index: focused,
+            tag_count,
+            pending_focus: None,
+            expected_focusedmon: VecDeque::new(),
+            new_window_tag: NewWindowTag::default(),
+            apps: Apps::default(),
+            activity: Activity::default(),
+            mirror: None,
+            xwayland_policy: XwaylandPolicy::default(),
+            active_tag_policy: ActiveTagPolicy::default(),
+            xwayland: HashSet::new(),
+            unmanaged: HashSet::new(),
+            linked,
+            unmanaged_windows: HashSet::new(),
+            submap: None,
+            dim: HashSet::new(),
+            dimmed_windows: HashSet::new(),
+            inherit_tag_from_parent: false,
+            hidden_tags: 0,
+            tag_names: BTreeMap::new(),
+            tag_monitors: BTreeMap::new(),
+            lineage: Lineage::default(),
+            overlay: HashMap::new(),
+            focused_overlay: None,
+            presentation: HashSet::new(),
+            presentation_everywhere: false,
+            burst: OpenBurst::default(),
+            recent_windows: RecentWindows::default(),
+            focus_history: HashMap::new(),
+            removals: PendingRemovals::default(),
+            provenance: Provenance::default(),
+            autostart: Autostart::default(),
+            summary: Summary::default(),
+            rules: Rules::default(),
+            sticky_windows: HashSet::new(),
+            validity: Validity::default(),
+            txn: None,
+            source: ChangeSource::default(),
         }
     }
 }
@@ -58,197 +402,3743 @@ impl MonitorsState {
         s
     }
 
+    /// The next managed monitor, or the active one when there is no other.
     pub fn next_monitor(&self) -> u8 {
-        let next_index = self.active_monitor_index + 1;
-        if next_index < self.monitors.len() {
-            next_index as u8
-        } else {
-            0
-        }
+        let len = self.monitors.len();
+        (1..len)
+            .map(|offset| (self.active_monitor_index + offset) % len)
+            .find(|i| self.is_managed(*i))
+            .unwrap_or(self.active_monitor_index) as u8
     }
 
-    pub fn focused_monitor_changed(&mut self, name: &str) -> anyhow::Result<()> {
-        let index = self.monitors.iter().enumerate().find_map(|(i, m)| {
-            if m.name == name {
-                Some(i)
-            } else {
-                None
-            }
-        });
+    /// A copy to preview commands on: the monitors, their tags and windows and how they're shown. Timers, pending
+    /// queries and window history are left out, so nothing done to the copy reaches Hyprland or this state.
+    pub fn preview(&self) -> Self {
+        Self {
+            monitors: self.monitors.clone(),
+            active_monitor_index: self.active_monitor_index,
+            source: self.source.clone(),
+            new_window_tag: self.new_window_tag.clone(),
+            mirror: self.mirror.clone(),
+            xwayland_policy: self.xwayland_policy,
+            active_tag_policy: self.active_tag_policy,
+            xwayland: self.xwayland.clone(),
+            unmanaged: self.unmanaged.clone(),
+            linked: self.linked.clone(),
+            unmanaged_windows: self.unmanaged_windows.clone(),
+            submap: self.submap.clone(),
+            dim: self.dim.clone(),
+            dimmed_windows: self.dimmed_windows.clone(),
+            inherit_tag_from_parent: self.inherit_tag_from_parent,
+            hidden_tags: self.hidden_tags,
+            tag_names: self.tag_names.clone(),
+            tag_monitors: self.tag_monitors.clone(),
+            overlay: self.overlay.clone(),
+            focused_overlay: self.focused_overlay,
+            presentation: self.presentation.clone(),
+            presentation_everywhere: self.presentation_everywhere,
+            focus_history: self.focus_history.clone(),
+            sticky_windows: self.sticky_windows.clone(),
+            validity: self.validity.clone(),
+            ..Self::new(vec![], self.tag_count)
+        }
+    }
 
-        match index {
-            Some(index) => {
-                self.active_monitor_index = index;
-                Ok(())
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            seq: 0,
+            active_monitor: self.active_monitor_index,
+            monitors: self.monitors.iter().map(|m| MonitorSnapshot {
+                name: m.name.clone(),
+                id: m.id,
+                visible_tags: m.state.visible_tags(),
+                prev_tags: m.state.prev_tags(),
+                active_window: m.state.active_window().map(|w| w.to_string()),
+                tags: m.state.tag_windows()
+                    .filter(|(_, windows)| !windows.is_empty())
+                    .map(|(tag, windows)| (tag, windows.iter().map(|w| w.to_string()).collect()))
+                    .collect(),
+                new_since_seen: m.state.new_since_seen().collect(),
+                unmanaged: self.unmanaged.contains(&m.name),
+                dim: self.dim.contains(&m.name),
+                overlay: self.overlay.get(&m.name).map(|ws| ws.iter().map(|w| w.to_string()).collect()).unwrap_or_default(),
+                presentation: self.is_presenting_named(&m.name),
+            }).collect(),
+            window_times: self.monitors.iter()
+                .flat_map(|m| m.state.all_window_addrs())
+                .filter_map(|w| self.activity.times(&w).map(|t| (w.to_string(), t)))
+                .collect(),
+            submap: self.submap.clone(),
+            xwayland_policy: self.xwayland_policy,
+            hidden_tags: self.hidden_tags,
+            xwayland: {
+                let mut windows: Vec<String> = self.xwayland.iter().map(|w| w.to_string()).collect();
+                windows.sort();
+                windows
             },
+        }
+    }
 
-            None =>  bail!("no such monitor:{}", name)
+    /// Monitors and windows for `query`. `full` adds how each window got on its tag. The caller fills in what
+    /// isn't kept here.
+    pub fn query(&self, config: &Config, full: bool) -> QueryReply {
+        let mut windows = BTreeMap::new();
+        for m in &self.monitors {
+            for (tag, tag_windows) in m.state.tag_windows() {
+                for w in tag_windows {
+                    let times = self.activity.times(w);
+                    windows.insert(w.to_string(), WindowQuery {
+                        monitor: m.name.clone(),
+                        tag,
+                        tag_id: tag_id(&m.name, tag),
+                        opened_at: times.map(|t| t.opened_at),
+                        last_focus: times.map(|t| t.last_focus),
+                        xwayland: self.is_xwayland(w),
+                        flags: self.window_flags(w),
+                        assignment: if full { self.provenance.latest(w).cloned() } else { None },
+                    });
+                }
+            }
+        }
+        QueryReply {
+            schema_version: SCHEMA_VERSION,
+            seq: 0,
+            active_monitor: self.monitors.get(self.active_monitor_index).map(|m| m.name.clone()),
+            profile: None,
+            submap: self.submap.clone(),
+            pending_prefix: None,
+            txn_open: self.txn.is_some(),
+            healthy: true,
+            unknown_events: 0,
+            monitors: self.monitors.iter().map(|m| MonitorQuery {
+                id: m.name.clone(),
+                name: m.name.clone(),
+                visible_tags: m.state.visible_tags(),
+                occupied_tags: m.state.tag_windows().filter(|(_, ws)| !ws.is_empty()).map(|(tag, _)| tag).collect(),
+                active_window: m.state.active_window().map(|w| w.to_string()),
+                overlay: self.overlay.get(&m.name).map(|ws| ws.iter().map(|w| w.to_string()).collect()).unwrap_or_default(),
+                presentation: self.is_presenting_named(&m.name),
+                tags: {
+                    let new_since_seen: HashMap<u8, usize> = m.state.new_since_seen().collect();
+                    m.state.tag_windows().map(|(tag, windows)| TagQuery {
+                        id: tag_id(&m.name, tag),
+                        tag,
+                        name: m.state.tag_name(tag),
+                        pinned_to: self.tag_monitors.get(&tag).cloned(),
+                        visible: m.state.visible_tags() & 1<<(tag - 1) != 0,
+                        windows: windows.len(),
+                        new_since_seen: new_since_seen.get(&tag).copied().unwrap_or(0),
+                    }).collect()
+                },
+                layout: snapshot::layout(config, m.state.tag_windows().count()),
+            }).collect(),
+            windows,
         }
     }
 
-    pub fn focused_monitor_changed_by_num(&mut self, n: u8) {
-        let index = n - 1;
-        unimplemented!()
+    /// Every monitor with its workspaces and visible tags, managed or not.
+    pub fn outputs(&self, config: &Config) -> Vec<OutputInfo> {
+        self.monitors.iter().enumerate().map(|(index, m)| OutputInfo {
+            index,
+            name: m.name.clone(),
+            description: m.description.clone(),
+            id: m.id,
+            managed: self.is_managed(index),
+            visible_workspace: visible_workspace(index),
+            hidden_workspace_base: hidden_workspace(self.tag_count, index, 0),
+            visible_tags: m.state.visible_tags(),
+            tag_count: m.state.tag_windows().count(),
+            layout: snapshot::layout(config, m.state.tag_windows().count()),
+        }).collect()
     }
 
-    pub fn new_window_added(&mut self, window: String) -> anyhow::Result<()> {
-        tracing::debug!(?window, "new_window_added");
-        for (i, monitor) in self.monitors.iter().enumerate() {
-            if i == self.active_monitor_index {
-                continue;
+    /// Every monitor's tags as one grid, a row per monitor in hyprtag's order.
+    pub fn pager(&self, config: &Config) -> Vec<PagerRow> {
+        self.monitors.iter().enumerate().map(|(index, m)| {
+            let urgent: Vec<u8> = m.state.urgent_tags().collect();
+            let focused = (index == self.active_monitor_index).then(|| m.state.active_tag_index() as u8 + 1);
+            PagerRow {
+                id: m.name.clone(),
+                monitor: m.name.clone(),
+                managed: self.is_managed(index),
+                cells: m.state.tag_windows().map(|(tag, windows)| PagerCell {
+                    id: tag_id(&m.name, tag),
+                    tag,
+                    name: config.tag_names.get(&tag).cloned().unwrap_or_else(|| tag.to_string()),
+                    windows: windows.len(),
+                    visible: m.state.visible_tags() & 1<<(tag - 1) != 0,
+                    urgent: urgent.contains(&tag),
+                    focused: focused == Some(tag),
+                }).collect(),
             }
+        }).collect()
+    }
 
-            if let Some(_) = monitor.state.find_window_tag_index(&window) {
-                bail!("window:{} is already in other tag", window);
+    /// What `subscribe` tells about each monitor, in hyprtag's order.
+    pub fn monitor_events(&self, config: &Config) -> Vec<MonitorEvent> {
+        self.monitors.iter().enumerate().map(|(index, m)| MonitorEvent {
+            monitor: m.name.clone(),
+            focused: index == self.active_monitor_index,
+            visible_tags: m.state.visible_tags(),
+            occupied_tags: m.state.occupied_tags(),
+            active_window: m.state.active_window().map(|w| w.to_string()),
+            windows: m.state.tag_windows().filter(|(_, ws)| !ws.is_empty()).map(|(tag, ws)| (tag, ws.len())).collect(),
+            layout: snapshot::layout(config, m.state.tag_windows().count()),
+        }).collect()
+    }
+
+    /// Name and description of every monitor, for profile matching.
+    pub fn monitor_ids(&self) -> Vec<(String, String)> {
+        self.monitors.iter().map(|m| (m.name.clone(), m.description.clone())).collect()
+    }
+
+    /// Shows the given tags on each named monitor. Unknown monitors are skipped.
+    pub fn show_default_tags(&mut self, defaults: &HashMap<String, Vec<u8>>) -> Vec<Changes> {
+        let mut all_changes = vec![];
+        for (index, monitor) in self.monitors.iter_mut().enumerate() {
+            let tags = match defaults.get(&monitor.name) {
+                Some(_) if self.unmanaged.contains(&monitor.name) => continue,
+                Some(tags) => tags,
+                None => continue,
+            };
+
+            let mask = tags.iter()
+                .filter(|t| (1..=self.tag_count).contains(*t))
+                .fold(0u64, |mask, t| mask | 1<<(t - 1));
+            match monitor.state.set_visible_tags(mask) {
+                Ok(changes) => all_changes.push(Changes {
+                    source: self.source.clone(),
+                    active_monitor_index: index,
+                    tag_count: self.tag_count,
+                    changes,
+                }),
+                Err(err) => tracing::error!(%err, "failed to show default tags on {}", monitor.name),
             }
         }
-        self.monitors[self.active_monitor_index].state.new_window_added(window)
+        all_changes
     }
 
-    pub fn window_removed(&mut self, window: String) -> anyhow::Result<()> {
-        self.monitors[self.active_monitor_index].state.window_removed(window)
+    /// The previous managed monitor, or the active one when there is no other.
+    pub fn prev_monitor(&self) -> u8 {
+        let len = self.monitors.len();
+        (1..len)
+            .map(|offset| (self.active_monitor_index + len - offset) % len)
+            .find(|i| self.is_managed(*i))
+            .unwrap_or(self.active_monitor_index) as u8
     }
 
-    pub fn move_window_to_monitor(&mut self, dest_monitor: u8, window: Option<String>) -> anyhow::Result<()> {
-        let window = window.or_else(|| {
-            self.monitors[self.active_monitor_index].state.active_window()
-        });
-        let window = match window {
-            Some(w) => w,
-            None => bail!("Couldn't detect window"),
-        };
+    pub fn set_unmanaged_monitors(&mut self, names: Vec<String>) {
+        self.unmanaged = names.into_iter().collect();
+    }
 
-        tracing::debug!(%window, %dest_monitor, "move_window_to_monitor");
+    fn is_managed(&self, index: usize) -> bool {
+        self.monitors.get(index).is_some_and(|m| !self.unmanaged.contains(&m.name))
+    }
 
-        let window_removed = self.monitors.iter_mut().find_map(|m| {
-            match m.state.window_removed(window.clone()) {
-                Ok(_) => Some(true),
-                Err(_) => None,
-            }
-        });
+    /// Fails for commands targeting a monitor hyprtag leaves alone.
+    fn ensure_managed(&self, index: usize) -> anyhow::Result<()> {
+        match self.monitors.get(index) {
+            Some(m) if self.unmanaged.contains(&m.name) => bail!("monitor:{} is not managed by hyprtag", m.name),
+            _ => Ok(()),
+        }
+    }
 
-        if window_removed.is_some() {
-            self.monitors[dest_monitor as usize].state.new_window_added(window)
-        } else {
-            bail!("no such window: {}", window)
+    /// Index of the active monitor, failing when it isn't managed.
+    pub fn active_managed(&self) -> anyhow::Result<usize> {
+        self.ensure_managed(self.active_monitor_index)?;
+        Ok(self.active_monitor_index)
+    }
+
+    /// Resolves `next`, `prev` or a monitor name to a monitor index.
+    pub fn resolve_monitor(&self, selector: &str) -> anyhow::Result<usize> {
+        match selector {
+            "next" => Ok(self.next_monitor() as usize),
+            "prev" => Ok(self.prev_monitor() as usize),
+            name => match self.linked_index(name) {
+                Some(index) => Ok(index),
+                None => bail!("no such monitor:{}", name),
+            },
         }
     }
 
-    pub fn focus_window_changed(&mut self, window: String) -> anyhow::Result<()> {
-        let new_window = self.monitors.iter().find(|m| {
-            m.state.find_window_tag_index(&window).is_some()
-        }).is_none();
+    /// Index of the named monitor, or of the one it mirrors.
+    fn linked_index(&self, name: &str) -> Option<usize> {
+        let name = self.linked.get(name).map_or(name, String::as_str);
+        self.monitors.iter().position(|m| m.name == name)
+    }
 
-        self.monitors[self.active_monitor_index].state.focus_window_changed(window, new_window)
+    /// The monitor Hyprland mirrors onto the named output.
+    pub fn mirror_source(&self, name: &str) -> Option<&str> {
+        self.linked.get(name).map(String::as_str)
     }
 
-    pub fn move_window(&mut self, dest_tag: u8, window: Option<String>) -> anyhow::Result<Changes> {
-        let changes = self.monitors[self.active_monitor_index].state.move_window(dest_tag, window)?;
-        Ok(Changes {
-            active_monitor_index: self.active_monitor_index,
-            changes,
-        })
+    /// Makes the monitor active right away, ahead of the focusedmon event the dispatch will cause.
+    /// Returns the monitor name and its last focused window.
+    pub fn focus_monitor(&mut self, index: usize) -> anyhow::Result<(String, Option<WindowAddr>)> {
+        let monitor = match self.monitors.get(index) {
+            Some(m) => m,
+            None => bail!("no such monitor index:{}", index),
+        };
+        self.ensure_managed(index)?;
+
+        self.active_monitor_index = index;
+        self.expected_focusedmon.push_back(monitor.name.clone());
+
+        Ok((monitor.name.clone(), monitor.state.active_window()))
     }
 
-    pub fn set_visible_tags(&mut self, tags: u32) -> anyhow::Result<Changes> {
-        let changes = self.monitors[self.active_monitor_index].state.set_visible_tags(tags)?;
-        Ok(Changes {
-            active_monitor_index: self.active_monitor_index,
-            changes,
-        })
+    pub fn focused_monitor_changed(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.expected_focusedmon.front().is_some_and(|n| n == name) {
+            self.expected_focusedmon.pop_front();
+            tracing::debug!(name, "ignore focusedmon caused by focus_monitor");
+            return Ok(());
+        }
+        self.expected_focusedmon.clear();
+
+        match self.linked_index(name) {
+            Some(index) => {
+                self.active_monitor_index = index;
+                Ok(())
+            },
+
+            None =>  bail!("no such monitor:{}", name)
+        }
     }
 
-    pub fn toggle_tag(&mut self, tag: u8) -> anyhow::Result<Changes> {
-        let changes = self.monitors[self.active_monitor_index].state.toggle_tag(tag)?;
-        Ok(Changes {
-            active_monitor_index: self.active_monitor_index,
-            changes,
-        })
+    /// Like `focused_monitor_changed`, with the monitor given by Hyprland's monitor id.
+    pub fn focused_monitor_changed_by_num(&mut self, n: u8) -> anyhow::Result<()> {
+        match self.monitors.iter().find(|m| m.id == n) {
+            Some(m) => {
+                let name = m.name.clone();
+                self.focused_monitor_changed(&name)
+            },
+            None => bail!("no such monitor id:{}", n),
+        }
     }
 
-    pub fn restore_prev_tags(&mut self) -> anyhow::Result<Changes> {
-        let changes = self.monitors[self.active_monitor_index].state.restore_prev_tags()?;
-        Ok(Changes {
-            active_monitor_index: self.active_monitor_index,
-            changes,
-        })
+    /// Handles focusedmonv2, which names the monitor in most Hyprland versions and gives its id in some.
+    pub fn focused_monitor_changed_v2(&mut self, monitor: &str) -> anyhow::Result<()> {
+        if self.linked_index(monitor).is_some() {
+            return self.focused_monitor_changed(monitor);
+        }
+        match monitor.parse() {
+            Ok(n) => self.focused_monitor_changed_by_num(n),
+            Err(_) => bail!("no such monitor:{}", monitor),
+        }
     }
 
-    pub fn monitor_removed(&mut self, name: &str) -> anyhow::Result<(usize, usize, Vec<String>)> {
-        let (removed_index, monitor) = match self.monitors.iter().enumerate().find(|(_, m)| m.name == name) {
-            Some(m) => m,
-            None => bail!("No such monitor: {}", name),
-        };
+    pub fn set_new_window_tag(&mut self, new_window_tag: NewWindowTag) {
+        self.new_window_tag = new_window_tag;
+    }
 
-        let (index, first_monitor) = match self.monitors.iter().enumerate().find(|(_, m)| m.name != name) {
-            Some(m) => m,
-            None => bail!("All monitors were removed?"), // TODO: care this case
-        };
-        let first_monitor = first_monitor.clone();
+    pub fn set_xwayland_policy(&mut self, policy: XwaylandPolicy) {
+        self.xwayland_policy = policy;
+    }
 
-        let windows = monitor.state.all_window_addrs();
-        for w in windows.iter() {
-            self.move_window_to_monitor(first_monitor.id, Some(w.clone()))?;
+    /// Sets how every monitor, and the ones connected later, picks its active tag.
+    pub fn set_active_tag_policy(&mut self, policy: ActiveTagPolicy) {
+        self.active_tag_policy = policy;
+        for monitor in self.monitors.iter_mut() {
+            monitor.state.set_active_tag_policy(policy);
         }
+    }
 
-        self.monitors.remove(removed_index);
+    pub fn set_inherit_tag_from_parent(&mut self, on: bool) {
+        self.inherit_tag_from_parent = on;
+    }
 
-        Ok((index, first_monitor.state.active_tag_index(), windows))
+    /// Names the tags of every monitor, and of the ones connected later.
+    pub fn set_tag_names(&mut self, names: &BTreeMap<u8, String>) {
+        self.tag_names = names.clone();
+        for monitor in self.monitors.iter_mut() {
+            monitor.state.set_tag_names(names);
+        }
     }
 
-    pub(crate) fn monitor_added(&mut self, name: &str, tx: mpsc::Sender<Ctrl>) -> anyhow::Result<()> {
-        if let Some(_) = self.monitors.iter().find(|m| m.name == name) {
-            bail!("monitor:{} is already registered", name);
+    pub fn set_tag_monitors(&mut self, tag_monitors: BTreeMap<u8, String>) {
+        self.tag_monitors = tag_monitors;
+    }
+
+    /// Index of the monitor the tag is pinned to. Err with why not when it's pinned to a monitor which isn't there
+    /// to use, None when it isn't pinned.
+    pub fn pinned_monitor(&self, tag: u8) -> Result<Option<usize>, String> {
+        let Some(name) = self.tag_monitors.get(&tag) else { return Ok(None) };
+        match self.linked_index(name) {
+            Some(index) if self.is_managed(index) => Ok(Some(index)),
+            Some(_) => Err(format!("tag {} is pinned to {}, which is unmanaged", tag, name)),
+            None => Err(format!("tag {} is pinned to {}, which isn't connected", tag, name)),
         }
+    }
 
-        let name = name.to_string();
-        tokio::spawn(async move {
-            let monitors = match hyprctl_monitors().await {
-                Ok(m) => m,
-                Err(err) => {
-                    tracing::error!(%err, "failed to fetch monitor info");
-                    return
-                },
-            };
+    /// The monitor to act on for the tag: the one it's pinned to, or `index`.
+    pub fn monitor_for_tag(&self, tag: u8, index: usize) -> usize {
+        match self.pinned_monitor(tag) {
+            Ok(pinned) => pinned.unwrap_or(index),
+            Err(why) => {
+                tracing::warn!(why, "using the focused monitor for the pinned tag");
+                index
+            },
+        }
+    }
 
-            let info = match monitors.iter().find(|m| m.name == name) {
-                Some(info) => info,
+    pub fn set_hidden_tags(&mut self, tags: u64) {
+        self.hidden_tags = tags;
+    }
+
+    /// Whether new windows need a clients query to learn their pid.
+    pub fn inherits_tags(&self) -> bool {
+        self.inherit_tag_from_parent
+    }
+
+    /// Replaces where window times and process parents come from, for a replay which has neither.
+    pub fn set_sources(&mut self, clock: Box<dyn Clock>, ancestry: Box<dyn Ancestry>) {
+        self.activity = Activity::new(clock);
+        self.lineage = Lineage::new(ancestry);
+    }
+
+    /// Records the class, XWayland flag and unmanaged monitor of a window which existed before we started.
+    pub fn client_known(&mut self, client: &ClientInfo) {
+        let window = match client.window() {
+            Ok(w) => w,
+            Err(err) => {
+                tracing::error!(%err, "skipping client");
+                return;
+            },
+        };
+        self.validity.confirm(window);
+        if client.xwayland {
+            self.xwayland.insert(window);
+        }
+        if self.monitors.iter().any(|m| m.id as i64 == client.monitor && self.unmanaged.contains(&m.name)) {
+            self.unmanaged_windows.insert(window);
+        }
+        if let Some(pid) = client.pid() {
+            self.lineage.window_pid(window, pid);
+        }
+        self.apps.window_opened(window, &client.class);
+    }
+
+    /// Puts windows which existed before we started on tags, after `client_known` saw them. A window on a hidden
+    /// workspace of a managed monitor, left there by a previous run, goes back on the tag the workspace is for, and one
+    /// on any other workspace on the tag active on its monitor. Windows on special workspaces and unmanaged monitors
+    /// stay untracked. The changes bring back into view windows hidden on tags which are visible now.
+    pub fn bootstrap(&mut self, clients: &[ClientInfo]) -> Vec<Changes> {
+        let mut all_changes = vec![];
+        for client in clients {
+            let Ok(window) = client.window() else { continue };
+            if client.workspace.id < 0 || self.unmanaged_windows.contains(&window)
+                || self.monitors.iter().any(|m| m.state.find_window_tag_index(&window).is_some()) {
+                continue;
+            }
+            let hidden = hidden_workspace_tag(self.tag_count, client.workspace.id).filter(|(index, _)| self.is_managed(*index));
+            let (index, tag) = match hidden {
+                Some(placement) => placement,
                 None => {
-                    tracing::error!("no such window: name={}", name);
-                    return
+                    let index = self.monitors.iter().position(|m| m.id as i64 == client.monitor).unwrap_or(self.active_monitor_index);
+                    if !self.is_managed(index) {
+                        continue;
+                    }
+                    (index, self.monitors[index].state.active_tag_index() as u8 + 1)
                 },
             };
-
-            let monitor = Monitor {
-                id: info.id.into(),
-                name: info.name.to_string(),
-                state: State::new(),
-            };
-
-            if let Err(err) = tx.send(Ctrl::MonitorAdded(monitor)).await {
-                tracing::error!(%err, "failed to send Ctrl::MonitorAdded");
+            let state = &mut self.monitors[index].state;
+            if let Err(err) = state.new_window_added_to_tag(window, tag) {
+                tracing::error!(%err, %window, "failed to adopt window");
+                continue;
             }
-        });
+            tracing::info!(%window, monitor = self.monitors[index].name, tag, workspace = client.workspace.id, "adopted existing window");
+            self.activity.window_added(&window);
+            // hidden workspaces are left alone for windows of hidden tags
+            let stranded = hidden_workspace_tag(self.tag_count, client.workspace.id).is_some();
+            if stranded && self.monitors[index].state.visible_tags() & 1<<(tag - 1) != 0 {
+                let changes = MonitorChanges { window_added: vec![WindowInfo { addr: window, tag }], ..Default::default() };
+                all_changes.push(Changes {
+                    source: self.source.clone(),
+                    active_monitor_index: index,
+                    tag_count: self.tag_count,
+                    changes: self.for_view(index, changes),
+                });
+            }
+        }
+        all_changes
+    }
 
-        Ok(())
+    /// The tags of the managed monitors and their windows, to restore after a restart. Provisional windows may not
+    /// exist and are left out.
+    #[cfg(feature = "persistence")]
+    pub fn saved_state(&self) -> SavedState {
+        let monitors: Vec<SavedMonitor> = self.monitors.iter().enumerate()
+            .filter(|(index, _)| self.is_managed(*index))
+            .map(|(_, m)| SavedMonitor {
+                name: m.name.clone(),
+                visible_tags: m.state.visible_tags(),
+                prev_tags: m.state.prev_tags(),
+                tags: m.state.tag_windows()
+                    .map(|(tag, windows)| (tag, windows.iter().filter(|w| !self.validity.is_provisional(w)).copied().collect::<Vec<_>>()))
+                    .filter(|(_, windows)| !windows.is_empty())
+                    .collect(),
+            })
+            .collect();
+        let times = monitors.iter()
+            .flat_map(|m| m.tags.values().flatten())
+            .filter_map(|w| Some((*w, self.activity.times(w)?)))
+            .collect();
+        SavedState { signature: std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok(), monitors, times }
     }
 
-    pub(crate) fn monitor_added_with_object(&mut self, monitor: Monitor) -> anyhow::Result<()> {
-        if let Some(_) = self.monitors.iter().find(|m| m.name == monitor.name) {
-            bail!("monitor:{} is already registered", monitor.name);
+    /// Puts the windows Hyprland still has back on the tags they were saved on, and shows the tags which were.
+    /// Runs before `bootstrap`, which adopts the rest.
+    #[cfg(feature = "persistence")]
+    pub fn restore(&mut self, saved: &SavedState, clients: &[ClientInfo]) -> Vec<Changes> {
+        let clients: HashMap<WindowAddr, &ClientInfo> = clients.iter()
+            .filter(|c| c.workspace.id >= 0)
+            .filter_map(|c| Some((c.window().ok()?, c)))
+            .filter(|(window, _)| !self.unmanaged_windows.contains(window))
+            .collect();
+        let mut all_changes = vec![];
+        for monitor in &saved.monitors {
+            let Some(index) = self.monitors.iter().position(|m| m.name == monitor.name).filter(|i| self.is_managed(*i)) else {
+                tracing::info!(monitor = monitor.name, "not restoring the tags of a monitor which isn't there");
+                continue;
+            };
+            let id = self.monitors[index].id as i64;
+            let mut changes = MonitorChanges::default();
+            // through the previous view, so `restore` goes back to it
+            for tags in [monitor.prev_tags, monitor.visible_tags] {
+                match self.monitors[index].state.set_visible_tags(tags) {
+                    Ok(c) => changes = c,
+                    Err(err) => tracing::error!(%err, tags, "failed to restore visible tags"),
+                }
+            }
+            for (tag, window) in monitor.tags.iter().flat_map(|(tag, windows)| windows.iter().map(move |w| (*tag, *w))) {
+                let Some(client) = clients.get(&window) else { continue };
+                if self.monitors.iter().any(|m| m.state.find_window_tag_index(&window).is_some()) {
+                    continue;
+                }
+                let state = &mut self.monitors[index].state;
+                match state.new_window_added_to_tag(window, tag) {
+                    Ok(c) => changes.window_removed.extend(c.window_removed),
+                    Err(err) => {
+                        tracing::error!(%err, %window, "failed to restore window");
+                        continue;
+                    },
+                }
+                // windows of visible tags come back from hidden workspaces and other monitors
+                let elsewhere = hidden_workspace_tag(self.tag_count, client.workspace.id).is_some() || client.monitor != id;
+                if state.visible_tags() & 1<<(tag - 1) != 0 && elsewhere {
+                    changes.window_added.push(WindowInfo { addr: window, tag });
+                }
+                match saved.times.get(&window) {
+                    Some(times) => self.activity.restore(window, *times),
+                    None => self.activity.window_added(&window),
+                }
+                tracing::info!(%window, monitor = monitor.name, tag, "restored window");
+            }
+            all_changes.push(Changes {
+                source: self.source.clone(),
+                active_monitor_index: index,
+                tag_count: self.tag_count,
+                changes: self.for_view(index, changes),
+            });
         }
+        all_changes
+    }
 
-        self.monitors.push(monitor);
+    /// Whether new windows need a clients query to learn their XWayland flag.
+    pub fn tracks_xwayland(&self) -> bool {
+        self.xwayland_policy != XwaylandPolicy::Normal
+    }
 
-        self.reset_monitor_workspaces();
+    /// Re-queries Hyprland's clients and sends the result back as `Ctrl::ClientsRefreshed`.
+    pub(crate) fn request_clients_refresh(&self, tx: mpsc::Sender<Ctrl>) {
+        tokio::spawn(refresh_clients(tx));
+    }
 
-        Ok(())
+    /// Re-queries Hyprland's clients now and once more when a provisional window adopted now would expire, so it's
+    /// either confirmed or dropped.
+    pub(crate) fn request_validity_checks(&self, tx: mpsc::Sender<Ctrl>) {
+        tokio::spawn(refresh_clients(tx.clone()));
+        tokio::spawn(async move {
+            tokio::time::sleep(PROVISIONAL_TIMEOUT).await;
+            refresh_clients(tx).await;
+        });
     }
 
-    fn reset_monitor_workspaces(&self) {
-        let args = self.monitors.iter().map(|m| {
-            format!(r#"dispatch moveworkspacetomonitor {} {}"#, m.id + 1, m.name)
-        }).collect();
-        hyprctl_batch(args);
+    /// Records XWayland flags and pids of tracked windows from a fresh query.
+    /// Provisional windows in it are confirmed, and ones which expired without showing up are dropped.
+    /// Newly flagged sticky windows on a hidden tag are brought into view,
+    /// and new windows waiting for their pid go to the tag of the window which launched them.
+    pub fn clients_refreshed(&mut self, clients: &[ClientInfo]) -> Vec<Changes> {
+        let present: HashSet<WindowAddr> = clients.iter().filter_map(|c| c.window().ok()).collect();
+        let mut all_changes: Vec<Changes> = present.iter().filter_map(|w| self.window_confirmed(*w)).collect();
+        for window in self.validity.expired(now()) {
+            if !present.contains(&window) {
+                tracing::info!(%window, "dropping provisional window Hyprland doesn't know");
+                self.drop_window(window);
+            }
+        }
+        all_changes.extend(self.inherit_tags(clients));
+        for client in clients.iter().filter(|c| c.xwayland) {
+            let window = match client.window() {
+                Ok(w) => w,
+                Err(err) => {
+                    tracing::error!(%err, "skipping client");
+                    continue;
+                },
+            };
+            let found = self.monitors.iter().enumerate().find_map(|(i, m)| {
+                m.state.find_window_tag_index(&window).map(|t| (i, t, m.state.visible_tags()))
+            });
+            let (index, tag_index, visible_tags) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            if !self.xwayland.insert(window) {
+                continue;
+            }
+            tracing::debug!(%window, "xwayland window");
+
+            let flags = self.window_flags(&window);
+            let flags = self.flags_changed(window, flags).unwrap_or_else(|conflict| conflict.resolved);
+            if flags.sticky && visible_tags & 1<<tag_index == 0 {
+                all_changes.push(Changes {
+                    source: self.source.clone(),
+                    active_monitor_index: index,
+                    tag_count: self.tag_count,
+                    changes: MonitorChanges {
+                        window_added: vec![WindowInfo { addr: window, tag: tag_index as u8 + 1 }],
+                        ..Default::default()
+                    },
+                });
+            }
+        }
+        all_changes
+    }
+
+    pub fn is_xwayland(&self, window: &WindowAddr) -> bool {
+        self.xwayland.contains(window)
+    }
+
+    /// Handles openwindow: a pending launch rule for the class takes precedence over window rules, which take
+    /// precedence over the new window policy. With `inherit_tag_from_parent` the window may move on once a clients
+    /// query tells its pid.
+    pub fn window_opened(&mut self, window: WindowAddr, class: &str, title: &str) -> anyhow::Result<Changes> {
+        self.apps.window_opened(window, class);
+        if self.validity.is_provisional(&window) {
+            // a focus change adopted it first, it stays where that put it
+            return Ok(self.window_confirmed(window).unwrap_or_else(|| Changes {
+                source: self.source.clone(),
+                active_monitor_index: self.active_monitor_index,
+                tag_count: self.tag_count,
+                changes: MonitorChanges::default(),
+            }));
+        }
+        self.validity.confirm(window);
+
+        let mut rule = self.apps.take_rule(class, now());
+        let mut rule_monitor = None;
+        if rule.is_none() {
+            if let Some(window_rule) = self.rules.matching(class, title).cloned() {
+                tracing::debug!(%window, class, rule = window_rule.name, "window rule matches");
+                if window_rule.ignore {
+                    self.unmanaged_windows.insert(window);
+                    return Ok(Changes {
+                        source: self.source.clone(),
+                        active_monitor_index: self.active_monitor_index,
+                        tag_count: self.tag_count,
+                        changes: MonitorChanges::default(),
+                    });
+                }
+                if window_rule.sticky {
+                    self.sticky_windows.insert(window);
+                }
+                rule = Some(window_rule.tag);
+                rule_monitor = window_rule.monitor.as_deref().and_then(|name| {
+                    let index = self.linked_index(name).filter(|i| self.is_managed(*i));
+                    if index.is_none() {
+                        tracing::info!(%window, rule = window_rule.name, monitor = name, "rule monitor isn't there, using the focused one");
+                    }
+                    index
+                });
+            }
+        }
+        let flags = WindowFlags { rule, unmanaged: !self.is_managed(self.active_monitor_index), ..Default::default() };
+        let flags = self.flags_changed(window, flags).unwrap_or_else(|conflict| conflict.resolved);
+        match flags.rule {
+            Some(tag) => {
+                tracing::debug!(%window, class, tag, "placing launched window");
+                self.source = ChangeSource::Rule { class: class.to_string(), tag };
+                let index = match rule_monitor {
+                    Some(index) => index,
+                    None => self.pinned_monitor(tag).unwrap_or_else(|why| {
+                        self.provenance.note(window, format!("{}, placed on the focused monitor", why));
+                        None
+                    }).unwrap_or(self.active_monitor_index),
+                };
+                self.place_new_window_on(index, window, NewWindowTag::Tag(tag))
+            },
+            None => {
+                if self.inherit_tag_from_parent && self.is_managed(self.active_monitor_index) {
+                    self.lineage.window_opened(window);
+                }
+                self.new_window_added(window)
+            },
+        }
+    }
+
+    /// Moves new windows to the monitor and tag of the window whose process is their closest ancestor.
+    fn inherit_tags(&mut self, clients: &[ClientInfo]) -> Vec<Changes> {
+        let pids: Vec<(WindowAddr, u32)> = clients.iter()
+            .filter_map(|c| Some((c.window().ok()?, c.pid()?)))
+            .collect();
+        for (window, pid) in &pids {
+            self.lineage.window_pid(*window, *pid);
+        }
+
+        let mut all_changes = vec![];
+        for (window, pid) in pids {
+            let parent = match self.lineage.launched_by(window, pid) {
+                Some(parent) => parent,
+                None => continue,
+            };
+            let found = self.monitors.iter().enumerate().find_map(|(i, m)| {
+                m.state.find_window_tag_index(&parent).map(|t| (i, t as u8 + 1))
+            });
+            let (index, tag) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            tracing::debug!(%window, %parent, tag, "placing window on its parent's tag");
+            match self.place_window(index, tag, Some(window)) {
+                Ok(changes) => all_changes.push(changes),
+                Err(err) => tracing::error!(%err, "failed to place window with its parent"),
+            }
+        }
+        all_changes
+    }
+
+    pub fn new_window_added(&mut self, window: WindowAddr) -> anyhow::Result<Changes> {
+        self.place_new_window(window, self.new_window_tag.clone())
+    }
+
+    fn place_new_window(&mut self, window: WindowAddr, new_window_tag: NewWindowTag) -> anyhow::Result<Changes> {
+        self.place_new_window_on(self.active_monitor_index, window, new_window_tag)
+    }
+
+    /// Puts a new window on a tag of the monitor at `index`. Hyprland opened it on the focused monitor, so on
+    /// another one it's moved over to where its tag is shown or hidden.
+    fn place_new_window_on(&mut self, index: usize, window: WindowAddr, new_window_tag: NewWindowTag) -> anyhow::Result<Changes> {
+        tracing::debug!(?window, monitor = self.monitors[index].name, "new_window_added");
+        if !self.is_managed(index) {
+            self.unmanaged_windows.insert(window);
+            return Ok(Changes {
+                source: self.source.clone(),
+                active_monitor_index: index,
+                tag_count: self.tag_count,
+                changes: MonitorChanges::default(),
+            });
+        }
+        for (i, monitor) in self.monitors.iter().enumerate() {
+            if i == index {
+                continue;
+            }
+
+            if monitor.state.find_window_tag_index(&window).is_some() {
+                bail!("window:{} is already in other tag", window);
+            }
+        }
+
+        let state = &mut self.monitors[index].state;
+        let mut changes = match new_window_tag {
+            // names are resolved to tags before they're set
+            NewWindowTag::Active | NewWindowTag::Named(_) => {
+                state.new_window_added(window)?;
+                MonitorChanges::default()
+            },
+            NewWindowTag::Tag(tag) => state.new_window_added_to_tag(window, tag)?,
+        };
+        if index != self.active_monitor_index && changes.window_removed.is_empty() {
+            let tag = state.find_window_tag_index(&window).map_or(1, |t| t as u8 + 1);
+            changes.window_added.push(WindowInfo { addr: window, tag });
+        }
+        self.activity.window_added(&window);
+
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    pub fn window_removed(&mut self, window: WindowAddr) -> anyhow::Result<()> {
+        if self.forget_window(&window) {
+            return Ok(());
+        }
+        self.monitors[self.active_monitor_index].state.window_removed(window)
+    }
+
+    /// Drops what we keep about the window besides its tag. True for unmanaged and overlay windows, which have none.
+    fn forget_window(&mut self, window: &WindowAddr) -> bool {
+        self.dimmed_windows.remove(window);
+        self.apps.window_closed(window);
+        self.lineage.window_closed(window);
+        self.activity.window_closed(window);
+        self.provenance.forget(window);
+        self.xwayland.remove(window);
+        self.sticky_windows.remove(window);
+        self.validity.forget(window);
+        if let Some(mirror) = &mut self.mirror {
+            mirror.windows.retain(|w| w != window);
+        }
+        for history in self.focus_history.values_mut() {
+            history.forget(window);
+        }
+        self.unmanaged_windows.remove(window) || self.forget_overlay(window)
+    }
+
+    /// Records that the window exists, as an event or clients query naming it proves. The moves held back while it
+    /// was provisional are due now, to wherever its tag is shown or hidden.
+    pub fn window_confirmed(&mut self, window: WindowAddr) -> Option<Changes> {
+        if !self.validity.confirm(window) {
+            return None;
+        }
+        let (index, tag_index, visible_tags) = self.monitors.iter().enumerate().find_map(|(i, m)| {
+            m.state.find_window_tag_index(&window).map(|t| (i, t, m.state.visible_tags()))
+        })?;
+        tracing::debug!(%window, "window confirmed, sending its held back moves");
+        let info = WindowInfo { addr: window, tag: tag_index as u8 + 1 };
+        let mut changes = MonitorChanges::default();
+        if visible_tags & 1<<tag_index == 0 {
+            changes.window_removed.push(info);
+        } else {
+            changes.window_added.push(info);
+        }
+        Some(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    /// Whether the window was adopted from a focus change and nothing confirmed it exists yet.
+    pub fn is_provisional(&self, window: &WindowAddr) -> bool {
+        self.validity.is_provisional(window)
+    }
+
+    /// Stops tracking a provisional window which never turned out to exist.
+    fn drop_window(&mut self, window: WindowAddr) {
+        if let Some(state) = self.monitors.iter_mut().map(|m| &mut m.state).find(|s| s.find_window_tag_index(&window).is_some()) {
+            if let Err(err) = state.window_removed(window) {
+                tracing::error!(%err, "failed to drop window");
+            }
+            if state.active_window() == Some(window) {
+                state.focus_cleared();
+            }
+        }
+        self.forget_window(&window);
+    }
+
+    /// Notes a window named by an event.
+    pub fn window_seen(&mut self, window: WindowAddr) {
+        self.recent_windows.seen(window, now());
+    }
+
+    /// `None` for windows on a tag, in an overlay or on an unmanaged monitor.
+    fn untracked(&mut self, window: &WindowAddr) -> Option<Untracked> {
+        if self.monitors.iter().any(|m| m.state.find_window_tag_index(window).is_some())
+            || self.unmanaged_windows.contains(window)
+            || self.is_overlay(window) {
+            return None;
+        }
+        if self.recent_windows.contains(window, now()) {
+            Some(Untracked::Lost)
+        } else {
+            Some(Untracked::NeverSeen)
+        }
+    }
+
+    /// Removes a closed window, then moves the view away as `policy` says when nothing is left visible.
+    pub fn window_closed(&mut self, window: WindowAddr, policy: OnTagEmptied) -> anyhow::Result<Option<Changes>> {
+        match self.untracked(&window) {
+            Some(Untracked::NeverSeen) => {
+                tracing::debug!(%window, "closed window was never tracked");
+                self.forget_window(&window);
+                return Ok(None);
+            },
+            Some(Untracked::Lost) => {
+                tracing::warn!(%window, "closed window was seen but isn't tracked, state is out of sync");
+                self.forget_window(&window);
+                return Ok(None);
+            },
+            None => (),
+        }
+        let index = self.active_monitor_index;
+        let tag_index = self.monitors[index].state.find_window_tag_index(&window);
+        self.window_removed(window)?;
+        match tag_index {
+            Some(tag_index) if policy != OnTagEmptied::Stay => self.tag_emptied(index, tag_index as u8 + 1, policy),
+            _ => Ok(None),
+        }
+    }
+
+    fn tag_emptied(&mut self, index: usize, tag: u8, policy: OnTagEmptied) -> anyhow::Result<Option<Changes>> {
+        let hidden = self.hidden_tags;
+        let state = &mut self.monitors[index].state;
+        if state.visible_tags() & 1<<(tag - 1) == 0 || !state.visible_windows().is_empty() {
+            return Ok(None);
+        }
+
+        let changes = match policy {
+            OnTagEmptied::Stay => return Ok(None),
+            OnTagEmptied::RestorePrev => {
+                if state.prev_tags() == state.visible_tags() {
+                    return Ok(None);
+                }
+                state.restore_prev_tags()?
+            },
+            OnTagEmptied::NearestOccupied => match state.nearest_occupied_tag(tag, hidden) {
+                Some(nearest) => state.set_visible_tags(1<<(nearest - 1))?,
+                None => return Ok(None),
+            },
+        };
+        tracing::info!(tag, ?policy, visible_tags = state.visible_tags(), "tag emptied");
+        Ok(Some(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        }))
+    }
+
+    pub fn move_window_to_monitor(&mut self, dest_monitor: u8, window: Option<WindowAddr>) -> anyhow::Result<()> {
+        let window = window.or_else(|| {
+            self.monitors[self.active_monitor_index].state.active_window()
+        });
+        let window = match window {
+            Some(w) => w,
+            None => bail!("no focused window"),
+        };
+
+        tracing::debug!(%window, %dest_monitor, "move_window_to_monitor");
+        self.ensure_managed(dest_monitor as usize)?;
+
+        let window_removed = self.monitors.iter_mut().find_map(|m| {
+            match m.state.window_removed(window) {
+                Ok(_) => Some(true),
+                Err(_) => None,
+            }
+        });
+
+        if window_removed.is_some() {
+            self.monitors[dest_monitor as usize].state.new_window_added(window)
+        } else {
+            bail!("no such window: {}", window)
+        }
+    }
+
+    pub fn focus_window_changed(&mut self, window: WindowAddr) -> anyhow::Result<Changes> {
+        if self.overlay_focused(window) {
+            return Ok(Changes {
+                source: self.source.clone(),
+                active_monitor_index: self.active_monitor_index,
+                tag_count: self.tag_count,
+                changes: MonitorChanges::default(),
+            });
+        }
+        let changes = self.adopt_window(window)?;
+        self.apps.window_focused(&window);
+        self.activity.window_focused(&window);
+        if self.is_managed(self.active_monitor_index) {
+            self.monitors[self.active_monitor_index].state.focus_window_changed(window, false)?;
+        }
+        Ok(changes)
+    }
+
+    /// Adds the window to the active monitor unless some monitor already tracks it.
+    /// On an unmanaged monitor the window leaves tag tracking instead, and coming back it lands on the active tag.
+    pub fn adopt_window(&mut self, window: WindowAddr) -> anyhow::Result<Changes> {
+        let tracked_by = self.monitors.iter().position(|m| {
+            m.state.find_window_tag_index(&window).is_some()
+        });
+
+        if !self.is_managed(self.active_monitor_index) {
+            if let Some(index) = tracked_by {
+                tracing::debug!(%window, "window moved to an unmanaged monitor");
+                self.monitors[index].state.window_removed(window)?;
+                if let Some(mirror) = &mut self.mirror {
+                    mirror.windows.retain(|w| *w != window);
+                }
+            }
+            self.unmanaged_windows.insert(window);
+            Ok(Changes {
+                source: self.source.clone(),
+                active_monitor_index: self.active_monitor_index,
+                tag_count: self.tag_count,
+                changes: MonitorChanges::default(),
+            })
+        } else if self.unmanaged_windows.remove(&window) {
+            tracing::debug!(%window, "window moved back from an unmanaged monitor");
+            self.place_new_window(window, NewWindowTag::Active)
+        } else if tracked_by.is_none() {
+            let provisional = self.validity.track(window, now());
+            let changes = self.new_window_added(window);
+            if provisional && changes.is_err() {
+                self.validity.forget(&window);
+            }
+            changes
+        } else {
+            Ok(Changes {
+                source: self.source.clone(),
+                active_monitor_index: self.active_monitor_index,
+                tag_count: self.tag_count,
+                changes: MonitorChanges::default(),
+            })
+        }
+    }
+
+    /// Handles movewindowv2. A window which went to another monitor's visible workspace, like dragged there with the
+    /// mouse, goes on the tag active on that monitor, and an overlay window into that monitor's overlay. The echoes of
+    /// our own dispatches find the window already tracked there and change nothing.
+    pub fn window_moved(&mut self, window: WindowAddr, workspace: i64) -> anyhow::Result<()> {
+        // hidden and special workspaces are only ever ours
+        let dest = match usize::try_from(workspace - 1).ok().filter(|i| *i < self.monitors.len()) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        if self.is_mirrored(&window) {
+            return Ok(());
+        }
+        let dest_name = self.monitors[dest].name.clone();
+
+        if let Some(name) = self.overlay.iter().find(|(_, windows)| windows.contains(&window)).map(|(name, _)| name.clone()) {
+            if name != dest_name {
+                tracing::info!(%window, from = name, to = dest_name, "overlay window moved to another monitor");
+                self.overlay.get_mut(&name).into_iter().for_each(|windows| windows.retain(|w| *w != window));
+                self.overlay.retain(|_, windows| !windows.is_empty());
+                self.overlay.entry(dest_name).or_default().push(window);
+            }
+            return Ok(());
+        }
+
+        let src = self.monitors.iter().position(|m| m.state.find_window_tag_index(&window).is_some());
+        if src == Some(dest) {
+            return Ok(());
+        }
+        if let Some(src) = src {
+            let state = &mut self.monitors[src].state;
+            state.window_removed(window)?;
+            if state.active_window() == Some(window) {
+                state.focus_cleared();
+            }
+        } else if !self.unmanaged_windows.remove(&window) {
+            // not a window we know, openwindow will place it
+            return Ok(());
+        }
+
+        if !self.is_managed(dest) {
+            tracing::info!(%window, monitor = dest_name, "window moved to an unmanaged monitor");
+            self.unmanaged_windows.insert(window);
+            return Ok(());
+        }
+        let tag = self.monitors[dest].state.active_tag_index() as u8 + 1;
+        tracing::info!(%window, monitor = dest_name, tag, "window moved to another monitor");
+        self.monitors[dest].state.new_window_added_to_tag(window, tag)?;
+        Ok(())
+    }
+
+    pub(crate) fn focus_window_changed_debounced(&mut self, window: WindowAddr, delay: Duration, tx: mpsc::Sender<Ctrl>) -> anyhow::Result<Changes> {
+        if self.overlay_focused(window) {
+            if let Some(task) = self.pending_focus.take() {
+                task.abort();
+            }
+            return Ok(Changes {
+                source: self.source.clone(),
+                active_monitor_index: self.active_monitor_index,
+                tag_count: self.tag_count,
+                changes: MonitorChanges::default(),
+            });
+        }
+        // new windows are adopted right away, only the active window update is delayed
+        let changes = self.adopt_window(window)?;
+        self.apps.window_focused(&window);
+
+        if let Some(task) = self.pending_focus.take() {
+            task.abort();
+        }
+
+        self.pending_focus = Some(tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(err) = tx.send(Ctrl::FocusSettled(window)).await {
+                tracing::error!(%err, "failed to send Ctrl::FocusSettled");
+            }
+        }));
+
+        Ok(changes)
+    }
+
+    /// Notes whether the focused window is an overlay, which doesn't become any tag's active window.
+    fn overlay_focused(&mut self, window: WindowAddr) -> bool {
+        self.focused_overlay = Some(window).filter(|w| self.is_overlay(w));
+        self.focused_overlay.is_some()
+    }
+
+    /// Records a focus change the user made, as opposed to the echo of our own focus dispatch.
+    pub fn user_focused(&mut self, window: WindowAddr) {
+        let monitor = &self.monitors[self.active_monitor_index];
+        if monitor.state.find_window_tag_index(&window).is_some() {
+            self.focus_history.entry(monitor.name.clone()).or_default().focused(window);
+        }
+    }
+
+    /// Focuses the window the user focused before the current one on the active monitor, showing its tag if it's
+    /// hidden. Windows which closed or went to another monitor are skipped.
+    pub fn focus_last(&mut self) -> anyhow::Result<Changes> {
+        let index = self.active_managed()?;
+        let monitor = &self.monitors[index];
+        let current = monitor.state.active_window();
+        let last = self.focus_history.get(&monitor.name).and_then(|history| {
+            history.windows()
+                .find(|w| Some(**w) != current && monitor.state.find_window_tag_index(w).is_some())
+                .copied()
+        });
+        let window = match last {
+            Some(w) => w,
+            None => bail!("no window focused before"),
+        };
+
+        let changes = self.goto_window(&window)?;
+        // the pair swaps now, the echo of the focus dispatch won't count
+        let name = self.monitors[index].name.clone();
+        self.focus_history.entry(name).or_default().focused(window);
+        Ok(changes)
+    }
+
+    pub fn focus_settled(&mut self, window: WindowAddr) -> anyhow::Result<()> {
+        self.pending_focus = None;
+
+        let index = self.monitors.iter().position(|m| {
+            m.state.find_window_tag_index(&window).is_some()
+        });
+
+        match index {
+            Some(index) => {
+                self.activity.window_focused(&window);
+                self.monitors[index].state.focus_window_changed(window, false)
+            },
+            None => bail!("window:{} is gone before its focus settled", window),
+        }
+    }
+
+    /// Handles the submap event. An empty name is the default submap.
+    pub fn submap_changed(&mut self, name: &str) {
+        self.submap = Some(name.to_string()).filter(|n| !n.is_empty());
+    }
+
+    pub fn submap(&self) -> Option<&str> {
+        self.submap.as_deref()
+    }
+
+    pub fn burst(&mut self) -> &mut OpenBurst {
+        &mut self.burst
+    }
+
+    pub fn txn(&mut self) -> &mut Option<Transaction> {
+        &mut self.txn
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint { monitors: self.monitors.clone(), active_monitor_index: self.active_monitor_index }
+    }
+
+    /// Goes back to where windows were and which tags showed at the checkpoint. Windows opened or closed since stay
+    /// that way. The changes move windows back from wherever the dispatches made since may have taken them.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) -> anyhow::Result<Vec<Changes>> {
+        let names = |monitors: &[Monitor]| monitors.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+        if names(&checkpoint.monitors) != names(&self.monitors) {
+            bail!("monitors changed since the checkpoint");
+        }
+        let placements = |monitors: &[Monitor]| -> HashMap<WindowAddr, (usize, u8, bool)> {
+            monitors.iter().enumerate().flat_map(|(i, m)| {
+                let visible_tags = m.state.visible_tags();
+                m.state.tag_windows().flat_map(move |(tag, windows)| {
+                    windows.iter().map(move |w| (*w, (i, tag, visible_tags & 1<<(tag - 1) != 0)))
+                })
+            }).collect()
+        };
+        let current = placements(&self.monitors);
+        let visible_tags: Vec<u64> = self.monitors.iter().map(|m| m.state.visible_tags()).collect();
+        let focused = self.active_window();
+
+        let mut monitors = checkpoint.monitors;
+        let before = placements(&monitors);
+        for (i, m) in self.monitors.iter().enumerate() {
+            for (tag, windows) in m.state.tag_windows() {
+                for w in windows.iter().filter(|w| !before.contains_key(w)) {
+                    monitors[i].state.new_window_added_to_tag(*w, tag)?;
+                }
+            }
+        }
+        for (w, (i, _, _)) in before.iter().filter(|(w, _)| !current.contains_key(w)) {
+            monitors[*i].state.window_removed(*w)?;
+        }
+        self.monitors = monitors;
+        self.active_monitor_index = checkpoint.active_monitor_index;
+
+        let mut all_changes = vec![];
+        for (i, was_visible) in visible_tags.into_iter().enumerate() {
+            let state = &self.monitors[i].state;
+            let mut changes = MonitorChanges {
+                shown_tags: state.visible_tags() & !was_visible,
+                hidden_tags: was_visible & !state.visible_tags(),
+                active_tag: Some(state.active_tag_index() as u8 + 1),
+                ..Default::default()
+            };
+            for (tag, windows) in state.tag_windows() {
+                let visible = state.visible_tags() & 1<<(tag - 1) != 0;
+                for w in windows.iter().filter(|w| current.get(w) != Some(&(i, tag, visible))) {
+                    let info = WindowInfo { addr: *w, tag };
+                    if visible {
+                        changes.window_added.push(info);
+                    } else {
+                        changes.window_removed.push(info);
+                    }
+                }
+            }
+            if i == self.active_monitor_index && state.active_window() != focused {
+                changes.focus = state.active_window();
+            }
+            all_changes.push(Changes {
+                source: self.source.clone(),
+                active_monitor_index: i,
+                tag_count: self.tag_count,
+                changes: self.for_view(i, changes),
+            });
+        }
+        Ok(all_changes)
+    }
+
+    /// Handles activewindowv2 without an address: nothing is focused on the active monitor anymore.
+    pub fn focus_cleared(&mut self) {
+        // a debounced focus from before must not bring the window back
+        if let Some(task) = self.pending_focus.take() {
+            task.abort();
+        }
+        self.focused_overlay = None;
+        self.monitors[self.active_monitor_index].state.focus_cleared();
+    }
+
+    /// Counts the urgent window towards its tag's badge when the tag is hidden.
+    pub fn window_urgent(&mut self, window: &WindowAddr) -> anyhow::Result<()> {
+        let index = match self.monitors.iter().position(|m| m.state.find_window_tag_index(window).is_some()) {
+            Some(index) => index,
+            None => bail!("no such window: {}", window),
+        };
+        self.monitors[index].state.window_urgent(window)?;
+        Ok(())
+    }
+
+    /// Per monitor name, tags with windows added or marked urgent since they were last visible.
+    pub fn badges(&self) -> BTreeMap<String, BTreeMap<u8, usize>> {
+        self.monitors.iter().map(|m| (m.name.clone(), m.state.new_since_seen().collect())).collect()
+    }
+
+    /// Aborts the timers started for this state, which is dropped once Hyprland went away.
+    pub fn stop_timers(&mut self) {
+        if let Some(task) = self.pending_focus.take() {
+            task.abort();
+        }
+        self.burst.take();
+        self.autostart.stop();
+        self.removals.cancel();
+        if let Some(txn) = self.txn.take() {
+            txn.end();
+        }
+    }
+
+    /// Sets what the changes made from now on come from.
+    pub fn set_source(&mut self, source: ChangeSource) {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> &ChangeSource {
+        &self.source
+    }
+
+    /// Records windows which got onto another monitor or tag as assigned by the current source, and counts them and
+    /// the focused window's tag towards the summary.
+    pub fn record_placements(&mut self) {
+        let at = self.activity.now();
+        let offset = summary::local_offset(at);
+        let active_window = self.active_window();
+        let mut focused_tag = None;
+        for m in self.monitors.iter() {
+            for (tag, windows) in m.state.tag_windows() {
+                for window in windows {
+                    let placement = snapshot::Placement { monitor: m.name.clone(), tag };
+                    self.provenance.placed(*window, placement, &self.source, at);
+                    // the summary is saved, which a window that may not exist has no place in
+                    if !self.validity.is_provisional(window) {
+                        self.summary.window_on_tag(tag, *window, at, offset);
+                    }
+                    if Some(*window) == active_window {
+                        focused_tag = Some(tag);
+                    }
+                }
+            }
+        }
+        self.summary.focus(focused_tag, at, offset);
+    }
+
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
+    }
+
+    /// Tags shown empty on a managed monitor whose `autostart` commands didn't run yet this session, with each of
+    /// their commands. The next windows to open are captured onto those tags.
+    pub fn autostart_due(&mut self, config: &Config) -> Vec<(u8, String)> {
+        let mut due = vec![];
+        for (tag, t) in config.tags.iter().filter(|(tag, t)| !t.autostart.is_empty() && (1..=self.tag_count).contains(*tag)) {
+            let shown_empty = self.monitors.iter().enumerate().any(|(index, m)| {
+                self.is_managed(index) && m.state.visible_tags() & 1<<(tag - 1) != 0
+                    && m.state.tag_windows().any(|(n, windows)| n == *tag && windows.is_empty())
+            });
+            if !shown_empty || !self.autostart.start(*tag) {
+                continue;
+            }
+            for command in t.autostart.iter() {
+                self.apps.arm_capture(*tag, command, now() + AUTOSTART_TIMEOUT);
+                due.push((*tag, command.clone()));
+            }
+        }
+        due
+    }
+
+    /// Runs the autostart commands which are due, see `autostart_due`.
+    pub(crate) fn run_autostart(&mut self, config: &Config, tx: mpsc::Sender<Ctrl>) {
+        let due = self.autostart_due(config);
+        if due.is_empty() {
+            return;
+        }
+        self.autostart.schedule_expiry(tx.clone());
+        for (tag, command) in due {
+            if let Err(err) = autostart::spawn(tag, &command, tx.clone()) {
+                self.autostart_failed(tag, &command, format!("failed to start: {}", err));
+            }
+        }
+    }
+
+    /// Records that the command started for the tag failed, unless its window came up already.
+    pub fn autostart_failed(&mut self, tag: u8, command: &str, reason: String) {
+        if !self.apps.cancel_capture(tag, command) {
+            tracing::debug!(tag, command, reason, "autostarted command failed after its window came up");
+            return;
+        }
+        let at = self.activity.now();
+        self.provenance.autostart_failed(AutostartFailure { tag, command: command.to_string(), reason, at });
+    }
+
+    /// Records the commands no window came up for in time as failed.
+    pub fn autostart_expired(&mut self) {
+        let at = self.activity.now();
+        for (tag, command) in self.apps.expired_captures(now()) {
+            let reason = format!("no window within {}s", AUTOSTART_TIMEOUT.as_secs());
+            self.provenance.autostart_failed(AutostartFailure { tag, command, reason, at });
+        }
+    }
+
+    /// Lets the tag, or every tag, run its autostart commands again the next time it's shown empty.
+    pub fn autostart_reset(&mut self, tag: Option<u8>) {
+        self.autostart.reset(tag);
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    pub fn rules_mut(&mut self) -> &mut Rules {
+        &mut self.rules
+    }
+
+    /// Takes over totals loaded from an earlier run.
+    pub fn set_summary(&mut self, summary: Summary) {
+        self.summary = summary;
+    }
+
+    pub fn set_summary_settings(&mut self, settings: SummarySettings) {
+        self.summary.set_settings(settings);
+    }
+
+    /// Per tag foreground time, windows since the reset, and windows now, named by `names`.
+    pub fn summary(&mut self, names: &BTreeMap<u8, String>) -> summary::Report {
+        let now = self.activity.now();
+        self.summary.roll_over(now, summary::local_offset(now));
+        let mut occupancy: BTreeMap<u8, usize> = BTreeMap::new();
+        for m in self.monitors.iter() {
+            for (tag, windows) in m.state.tag_windows().filter(|(_, w)| !w.is_empty()) {
+                *occupancy.entry(tag).or_default() += windows.len();
+            }
+        }
+        self.summary.report(now, &occupancy, names)
+    }
+
+    pub fn reset_summary(&mut self) {
+        self.summary.reset(self.activity.now());
+    }
+
+    /// The focused window of the active monitor.
+    pub fn active_window(&self) -> Option<WindowAddr> {
+        self.monitors[self.active_monitor_index].state.active_window()
+    }
+
+    /// Windows not focused for at least `secs`, least recently focused first.
+    pub fn stale_windows(&self, secs: u64) -> Vec<(WindowAddr, WindowTimes)> {
+        self.activity.stale(secs).into_iter()
+            .filter(|(w, _)| self.monitors.iter().any(|m| m.state.find_window_tag_index(w).is_some()))
+            .collect()
+    }
+
+    /// Moves each window to `tag` on the monitor tracking it.
+    pub fn move_windows(&mut self, tag: u8, windows: &[WindowAddr]) -> Vec<Changes> {
+        let mut all_changes = vec![];
+        for window in windows {
+            if self.is_mirrored(window) {
+                tracing::warn!(%window, "not moving mirrored window");
+                continue;
+            }
+            if self.is_tag_pinned(window) {
+                tracing::warn!(%window, "not moving xwayland window pinned to its tag");
+                continue;
+            }
+            let index = self.window_monitor_index(Some(window));
+            match self.monitors[index].state.move_window(tag, Some(*window)) {
+                Ok(changes) => all_changes.push(Changes {
+                    source: self.source.clone(),
+                    active_monitor_index: index,
+                    tag_count: self.tag_count,
+                    changes: self.for_view(index, changes),
+                }),
+                Err(err) => tracing::error!(%err, %window, "failed to move window"),
+            }
+        }
+        all_changes
+    }
+
+    /// The most recently used window of the class which we track.
+    pub fn app_window(&self, class: &str) -> Option<WindowAddr> {
+        self.apps.windows_of(class).into_iter().find(|w| {
+            self.monitors.iter().any(|m| m.state.find_window_tag_index(w).is_some())
+        })
+    }
+
+    /// Shows the window's tag on its monitor, makes that monitor active and focuses the window.
+    pub fn goto_window(&mut self, window: &WindowAddr) -> anyhow::Result<Changes> {
+        let (index, tag_index) = match self.monitors.iter().enumerate().find_map(|(i, m)| {
+            m.state.find_window_tag_index(window).map(|t| (i, t))
+        }) {
+            Some(found) => found,
+            None => bail!("no such window: {}", window),
+        };
+
+        if index != self.active_monitor_index {
+            // the focuswindow dispatch moves monitor focus too
+            self.focus_monitor(index)?;
+        }
+
+        let state = &mut self.monitors[index].state;
+        let mut changes = if state.visible_tags() & 1<<tag_index == 0 {
+            state.set_visible_tags(1<<tag_index)?
+        } else {
+            MonitorChanges::default()
+        };
+        changes.focus = Some(*window);
+
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes,
+        })
+    }
+
+    /// Shows `tag` on the active monitor and places the next window of the class there.
+    pub fn prepare_launch(&mut self, class: &str, tag: u8) -> anyhow::Result<Changes> {
+        check_tag(tag, self.tag_count)?;
+
+        let changes = self.set_visible_tags(1<<(tag-1))?;
+        self.apps.arm(class, tag, now() + LAUNCH_TIMEOUT);
+        Ok(changes)
+    }
+
+    /// Moves the window, or the active one, to `dest_tag`. XWayland windows pinned to their tag need `force`.
+    pub fn move_window(&mut self, dest_tag: u8, window: Option<WindowAddr>, force: bool) -> anyhow::Result<Changes> {
+        check_tag(dest_tag, self.tag_count)?;
+        let target = self.move_target(window, force)?;
+
+        let index = self.active_managed()?;
+        // a tag pinned elsewhere takes the window over there
+        match self.pinned_monitor(dest_tag) {
+            Ok(Some(pinned)) if pinned != index => return self.place_window(pinned, dest_tag, target),
+            Err(why) => if let Some(window) = target {
+                self.provenance.note(window, format!("{}, moved on the focused monitor", why));
+            },
+            _ => {},
+        }
+        let changes = self.monitors[index].state.move_window(dest_tag, target)?;
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    /// The window, or the focused one, once it's one a move can take. An overlay window goes back on a tag first.
+    fn move_target(&mut self, window: Option<WindowAddr>, force: bool) -> anyhow::Result<Option<WindowAddr>> {
+        let target = window.or(self.focused_overlay).or_else(|| self.monitors[self.active_monitor_index].state.active_window());
+        if let Some(w) = target.filter(|w| self.is_overlay(w)) {
+            self.overlay_remove(Some(w))?;
+        }
+        if let Some(w) = target.as_ref().filter(|w| self.is_mirrored(w)) {
+            bail!("window:{} is mirrored", w);
+        }
+        if let Some(w) = target.as_ref().filter(|w| !force && self.is_tag_pinned(w)) {
+            bail!("window:{} is an XWayland window pinned to its tag by xwayland_policy, use move --force", w);
+        }
+        Ok(target)
+    }
+
+    /// Moves the window, or the active one, to `tag` and shows only that tag with focus on the window. On the
+    /// active monitor that's a single change, leaving the tags visible before as the previous ones. A tag pinned to
+    /// another monitor gets the window placed there first.
+    pub fn move_show(&mut self, tag: u8, window: Option<WindowAddr>) -> anyhow::Result<Vec<Changes>> {
+        check_tag(tag, self.tag_count)?;
+        let window = match self.move_target(window, false)? {
+            Some(w) => w,
+            None => bail!("no focused window"),
+        };
+
+        let index = self.active_managed()?;
+        match self.pinned_monitor(tag) {
+            Ok(Some(pinned)) if pinned != index => {
+                let placed = self.place_window(pinned, tag, Some(window))?;
+                let mut shown = self.show_on(pinned, tag, true)?;
+                shown.changes.focus = Some(window);
+                return Ok(vec![placed, shown]);
+            },
+            Err(why) => self.provenance.note(window, format!("{}, moved on the focused monitor", why)),
+            _ => {},
+        }
+        let changes = self.monitors[index].state.move_window_and_show(tag, Some(window))?;
+        Ok(vec![Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        }])
+    }
+
+    /// Shows only `tag` on the monitor at `index`, which doesn't need to be the active one.
+    /// Without `focus` the keyboard focus stays where it is.
+    pub fn show_on(&mut self, index: usize, tag: u8, focus: bool) -> anyhow::Result<Changes> {
+        if index >= self.monitors.len() {
+            bail!("no such monitor index:{}", index);
+        }
+        self.ensure_managed(index)?;
+        check_tag(tag, self.tag_count)?;
+        let mut changes = self.monitors[index].state.set_visible_tags(1<<(tag - 1))?;
+        if !focus {
+            changes.focus = None;
+        }
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    pub fn active_monitor_index(&self) -> usize {
+        self.active_monitor_index
+    }
+
+    pub fn tag_count(&self) -> u8 {
+        self.tag_count
+    }
+
+    pub fn monitor_name(&self, index: usize) -> Option<&str> {
+        self.monitors.get(index).map(|m| m.name.as_str())
+    }
+
+    /// Visible tags of the monitor at `index`.
+    pub fn visible_tags_of(&self, index: usize) -> Option<u64> {
+        self.monitors.get(index).map(|m| m.state.visible_tags())
+    }
+
+    /// The lowest tag without windows on the active monitor, except hidden ones.
+    pub fn first_empty_tag(&self) -> anyhow::Result<u8> {
+        self.monitors[self.active_managed()?].state.first_empty_tag(self.hidden_tags)
+    }
+
+    /// Moves the window, or the active one, to `tag` on the monitor at `dest_index` in a single step.
+    /// The changes move it straight to the workspace the tag maps to on that monitor; placing a window
+    /// where it already is changes nothing.
+    pub fn place_window(&mut self, dest_index: usize, tag: u8, window: Option<WindowAddr>) -> anyhow::Result<Changes> {
+        if dest_index >= self.monitors.len() {
+            bail!("no such monitor index:{}", dest_index);
+        }
+        self.ensure_managed(dest_index)?;
+        check_tag(tag, self.tag_count)?;
+        let window = match window.or_else(|| self.active_window()) {
+            Some(w) => w,
+            None => bail!("no focused window"),
+        };
+        if self.is_mirrored(&window) {
+            bail!("window:{} is mirrored", window);
+        }
+        if self.is_tag_pinned(&window) {
+            bail!("window:{} is an XWayland window pinned to its tag by xwayland_policy", window);
+        }
+
+        let (index, tag_index) = match self.monitors.iter().enumerate().find_map(|(i, m)| {
+            m.state.find_window_tag_index(&window).map(|t| (i, t))
+        }) {
+            Some(found) => found,
+            None => bail!("no such window: {}", window),
+        };
+
+        let mut changes = MonitorChanges::default();
+        if index == dest_index && tag_index + 1 == tag as usize {
+            return Ok(Changes { active_monitor_index: dest_index, tag_count: self.tag_count, changes, source: self.source.clone() });
+        }
+
+        tracing::debug!(%window, dest = self.monitors[dest_index].name, tag, "place_window");
+        self.monitors[index].state.window_removed(window)?;
+        let dest = &mut self.monitors[dest_index].state;
+        dest.new_window_added_to_tag(window, tag)?;
+
+        let info = WindowInfo { addr: window, tag };
+        if dest.visible_tags() & 1<<(tag - 1) != 0 || self.is_sticky(&window) {
+            changes.window_added.push(info);
+        } else {
+            changes.window_removed.push(info);
+        }
+        Ok(Changes { active_monitor_index: dest_index, tag_count: self.tag_count, changes, source: self.source.clone() })
+    }
+
+    /// The tag new windows go to on the monitor at `index`.
+    pub fn active_tag(&self, index: usize) -> Option<u8> {
+        self.monitors.get(index).map(|m| m.state.active_tag_index() as u8 + 1)
+    }
+
+    pub fn promote_window(&mut self, window: Option<WindowAddr>) -> anyhow::Result<()> {
+        let index = self.window_monitor_index(window.as_ref());
+        self.ensure_managed(index)?;
+        self.monitors[index].state.promote_window(window)
+    }
+
+    pub fn demote_window(&mut self, window: Option<WindowAddr>) -> anyhow::Result<()> {
+        let index = self.window_monitor_index(window.as_ref());
+        self.ensure_managed(index)?;
+        self.monitors[index].state.demote_window(window)
+    }
+
+    pub fn reorder_tag(&mut self, tag: u8, windows: Vec<WindowAddr>) -> anyhow::Result<()> {
+        let index = self.active_managed()?;
+        self.monitors[index].state.reorder_tag(tag, windows)
+    }
+
+    /// Index of the monitor tracking the window, or the active monitor.
+    fn window_monitor_index(&self, window: Option<&WindowAddr>) -> usize {
+        window.and_then(|w| {
+            self.monitors.iter().position(|m| m.state.find_window_tag_index(w).is_some())
+        }).unwrap_or(self.active_monitor_index)
+    }
+
+    /// Visible tags of the active monitor.
+    pub fn visible_tags(&self) -> u64 {
+        self.monitors[self.active_monitor_index].state.visible_tags()
+    }
+
+    pub fn set_visible_tags(&mut self, tags: u64) -> anyhow::Result<Changes> {
+        let index = self.active_managed()?;
+        self.set_visible_tags_on(index, tags)
+    }
+
+    pub fn set_visible_tags_on(&mut self, index: usize, tags: u64) -> anyhow::Result<Changes> {
+        self.ensure_managed(index)?;
+        let changes = self.monitors[index].state.set_visible_tags(tags)?;
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    /// Shows the next or previous tag with windows on the active monitor, counting from the lowest visible one.
+    /// Hidden tags are passed over.
+    pub fn cycle_tag(&mut self, forward: bool) -> anyhow::Result<Changes> {
+        let index = self.active_managed()?;
+        let state = &self.monitors[index].state;
+        let from = state.visible_tags().trailing_zeros().min(MAX_TAGS as u32 - 1) as u8 + 1;
+        let tag = match cycle_tag(from, forward, state.occupied_tags() & !self.hidden_tags) {
+            Some(tag) => tag,
+            None => bail!("no tag to cycle to"),
+        };
+        self.set_visible_tags(1<<(tag - 1))
+    }
+
+    pub fn toggle_tag(&mut self, tag: u8, focus: bool) -> anyhow::Result<Changes> {
+        let index = self.active_managed()?;
+        self.toggle_tag_on(index, tag, focus)
+    }
+
+    pub fn toggle_tag_on(&mut self, index: usize, tag: u8, focus: bool) -> anyhow::Result<Changes> {
+        self.ensure_managed(index)?;
+        let changes = self.monitors[index].state.toggle_tag(tag, focus)?;
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    pub fn restore_prev_tags(&mut self) -> anyhow::Result<Changes> {
+        let index = self.active_managed()?;
+        self.restore_prev_tags_on(index)
+    }
+
+    pub fn restore_prev_tags_on(&mut self, index: usize) -> anyhow::Result<Changes> {
+        self.ensure_managed(index)?;
+        let changes = self.monitors[index].state.restore_prev_tags()?;
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes: self.for_view(index, changes),
+        })
+    }
+
+    /// Shows the active monitor's tag on another monitor by moving its windows to that monitor's visible workspace.
+    /// The windows stay on their tag and can't be moved until `unmirror`.
+    pub fn mirror(&mut self, tag: u8, dest: &str) -> anyhow::Result<Changes> {
+        check_tag(tag, self.tag_count)?;
+        if let Some(mirror) = &self.mirror {
+            bail!("tag {} is already mirrored to {}", mirror.tag, mirror.dest);
+        }
+
+        self.active_managed()?;
+        let dest_index = self.resolve_monitor(dest)?;
+        self.ensure_managed(dest_index)?;
+        if dest_index == self.active_monitor_index {
+            bail!("can't mirror a tag onto its own monitor");
+        }
+
+        let source = &self.monitors[self.active_monitor_index];
+        let mut windows: Vec<WindowAddr> = match source.state.tag_windows().find(|(id, _)| *id == tag) {
+            Some((_, windows)) if !windows.is_empty() => windows.to_vec(),
+            Some(_) => bail!("tag {} has no windows", tag),
+            None => bail!("no such tag: {}", tag),
+        };
+        windows.retain(|w| {
+            let flags = WindowFlags { mirrored: true, ..self.window_flags(w) };
+            self.flags_changed(*w, flags).is_ok()
+        });
+        let source = &self.monitors[self.active_monitor_index];
+
+        tracing::info!(tag, from = source.name, to = self.monitors[dest_index].name, "mirroring tag");
+        self.mirror = Some(Mirror {
+            tag,
+            source: source.name.clone(),
+            dest: self.monitors[dest_index].name.clone(),
+            windows: windows.clone(),
+        });
+
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: dest_index,
+            tag_count: self.tag_count,
+            changes: MonitorChanges {
+                window_added: windows.into_iter().map(|addr| WindowInfo { addr, tag }).collect(),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Returns mirrored windows to where their monitor's tags say they belong.
+    pub fn unmirror(&mut self) -> anyhow::Result<Changes> {
+        let mirror = match self.mirror.take() {
+            Some(m) => m,
+            None => bail!("no tag is mirrored"),
+        };
+
+        let index = match self.monitors.iter().position(|m| m.name == mirror.source) {
+            Some(index) => index,
+            None => bail!("mirror source monitor:{} is gone", mirror.source),
+        };
+        let state = &self.monitors[index].state;
+
+        let mut changes = MonitorChanges::default();
+        for addr in mirror.windows {
+            let tag_index = match state.find_window_tag_index(&addr) {
+                Some(i) => i,
+                None => continue,
+            };
+            let info = WindowInfo { addr, tag: tag_index as u8 + 1 };
+            if state.visible_tags() & 1<<tag_index != 0 {
+                changes.window_added.push(info);
+            } else {
+                changes.window_removed.push(info);
+            }
+        }
+
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes,
+        })
+    }
+
+    /// Ends a mirror involving the removed monitor. Windows of a removed source were already moved by
+    /// `monitor_removed`; a removed destination returns them to the source monitor.
+    pub fn mirror_monitor_removed(&mut self, name: &str) -> Option<Changes> {
+        let mirror = self.mirror.as_ref()?;
+        if mirror.dest == name {
+            match self.unmirror() {
+                Ok(changes) => Some(changes),
+                Err(err) => {
+                    tracing::error!(%err, "failed to end mirror");
+                    None
+                },
+            }
+        } else {
+            if mirror.source == name {
+                self.mirror = None;
+            }
+            None
+        }
+    }
+
+    fn is_mirrored(&self, window: &WindowAddr) -> bool {
+        self.mirror.as_ref().is_some_and(|m| m.windows.contains(window))
+    }
+
+    fn is_sticky(&self, window: &WindowAddr) -> bool {
+        self.sticky_windows.contains(window) || self.xwayland_policy == XwaylandPolicy::Sticky && self.is_xwayland(window)
+    }
+
+    fn is_tag_pinned(&self, window: &WindowAddr) -> bool {
+        self.xwayland_policy == XwaylandPolicy::PinTag && self.is_xwayland(window)
+    }
+
+    /// The flags the window has now.
+    pub fn window_flags(&self, window: &WindowAddr) -> WindowFlags {
+        WindowFlags {
+            unmanaged: self.unmanaged_windows.contains(window),
+            mirrored: self.is_mirrored(window),
+            overlay: self.is_overlay(window),
+            sticky: self.is_sticky(window),
+            pinned: self.is_tag_pinned(window),
+            rule: None,
+        }
+    }
+
+    /// Resolves the flags a window is about to have. A conflict is logged and kept in the window's provenance.
+    fn flags_changed(&mut self, window: WindowAddr, flags: WindowFlags) -> Result<WindowFlags, FlagConflict> {
+        resolve_flags(flags).map_err(|conflict| {
+            tracing::warn!(%window, %conflict, "window flags conflict");
+            self.provenance.conflicted(window, conflict.clone(), &self.source, self.activity.now());
+            conflict
+        })
+    }
+
+    /// Changes as the monitor at `index` shows them: without held windows, and in dim mode
+    /// windows of hidden tags get dimmed where they are instead of moving away.
+    fn for_view(&mut self, index: usize, changes: MonitorChanges) -> MonitorChanges {
+        let mut changes = self.without_held(changes);
+        if !self.dim.contains(&self.monitors[index].name) {
+            return changes;
+        }
+        for w in std::mem::take(&mut changes.window_removed) {
+            if self.dimmed_windows.insert(w.addr) {
+                changes.dimmed.push(w.addr);
+            }
+        }
+        let mut window_added = vec![];
+        for w in std::mem::take(&mut changes.window_added) {
+            if self.dimmed_windows.remove(&w.addr) {
+                changes.undimmed.push(w.addr);
+            } else {
+                window_added.push(w);
+            }
+        }
+        changes.window_added = window_added;
+        changes
+    }
+
+    /// Switches how the active monitor shows hidden tags. Entering dim mode brings their windows onto the
+    /// visible workspace dimmed, leaving it undims them and moves them back to their hidden workspaces.
+    pub fn set_view_mode(&mut self, mode: ViewMode) -> anyhow::Result<Changes> {
+        let index = self.active_managed()?;
+        let name = self.monitors[index].name.clone();
+        let state = &self.monitors[index].state;
+        let visible_tags = state.visible_tags();
+        let windows: Vec<WindowInfo> = state.tag_windows()
+            .flat_map(|(tag, windows)| windows.iter().map(move |w| WindowInfo { addr: *w, tag }))
+            .collect();
+        let hidden = |w: &WindowInfo| visible_tags & 1<<(w.tag - 1) == 0;
+
+        let mut changes = MonitorChanges::default();
+        match mode {
+            ViewMode::Dim => {
+                if !self.dim.insert(name) {
+                    return Ok(Changes { active_monitor_index: index, tag_count: self.tag_count, changes, source: self.source.clone() });
+                }
+                changes.window_added = windows.into_iter().filter(hidden).collect();
+                changes = self.without_held(changes);
+                for w in &changes.window_added {
+                    self.dimmed_windows.insert(w.addr);
+                    changes.dimmed.push(w.addr);
+                }
+            },
+            ViewMode::Normal => {
+                if !self.dim.remove(&name) {
+                    return Ok(Changes { active_monitor_index: index, tag_count: self.tag_count, changes, source: self.source.clone() });
+                }
+                changes.window_removed = windows.iter().filter(|w| hidden(w)).cloned().collect();
+                changes = self.without_held(changes);
+                changes.undimmed = windows.iter().map(|w| w.addr).filter(|w| self.dimmed_windows.remove(w)).collect();
+            },
+        }
+        Ok(Changes { active_monitor_index: index, tag_count: self.tag_count, changes, source: self.source.clone() })
+    }
+
+    pub fn view_mode(&self) -> ViewMode {
+        if self.dim.contains(&self.monitors[self.active_monitor_index].name) {
+            ViewMode::Dim
+        } else {
+            ViewMode::Normal
+        }
+    }
+
+    /// Turns presentation mode on or off for the monitor at `index`, or for every monitor without one.
+    /// Turning it off everywhere also ends it for single monitors.
+    pub fn set_presentation(&mut self, on: bool, index: Option<usize>) -> anyhow::Result<()> {
+        let index = match index {
+            Some(index) => index,
+            None => {
+                self.presentation_everywhere = on;
+                if !on {
+                    self.presentation.clear();
+                }
+                return Ok(());
+            },
+        };
+        let name = match self.monitors.get(index) {
+            Some(m) => m.name.clone(),
+            None => bail!("no such monitor index: {}", index),
+        };
+        if !on && self.presentation_everywhere {
+            bail!("presentation mode is on for all monitors");
+        }
+        if on {
+            self.presentation.insert(name);
+        } else {
+            self.presentation.remove(&name);
+        }
+        Ok(())
+    }
+
+    /// Whether the monitor at `index` is in presentation mode.
+    pub fn is_presenting(&self, index: usize) -> bool {
+        self.monitors.get(index).is_some_and(|m| self.is_presenting_named(&m.name))
+    }
+
+    /// Whether the monitor with the connector name is in presentation mode.
+    pub fn is_presenting_named(&self, name: &str) -> bool {
+        self.presentation_everywhere || self.presentation.contains(name)
+    }
+
+    /// Hyprland ids of the monitors in presentation mode.
+    pub fn presenting_monitor_ids(&self) -> Vec<u8> {
+        (0..self.monitors.len()).filter(|i| self.is_presenting(*i)).map(|i| self.monitors[i].id).collect()
+    }
+
+    /// Takes the window, or the focused one, off its tag into its monitor's overlay, which stays on the visible
+    /// workspace whatever tags are shown. A window of a hidden tag is brought onto it.
+    pub fn overlay_add(&mut self, window: Option<WindowAddr>) -> anyhow::Result<Changes> {
+        let window = match window.or(self.focused_overlay).or_else(|| self.monitors[self.active_monitor_index].state.active_window()) {
+            Some(w) => w,
+            None => bail!("no focused window"),
+        };
+        if self.is_overlay(&window) {
+            bail!("window:{} is already an overlay", window);
+        }
+        let flags = WindowFlags { overlay: true, ..self.window_flags(&window) };
+        if let Err(conflict) = self.flags_changed(window, flags) {
+            bail!("window:{} can't be an overlay: {}", window, conflict);
+        }
+        let index = match self.monitors.iter().position(|m| m.state.find_window_tag_index(&window).is_some()) {
+            Some(index) => index,
+            None => bail!("no such window: {}", window),
+        };
+        self.ensure_managed(index)?;
+
+        let state = &mut self.monitors[index].state;
+        let tag_index = state.find_window_tag_index(&window).unwrap_or_default();
+        let hidden = state.visible_tags() & 1<<tag_index == 0;
+        state.window_removed(window)?;
+        if state.active_window() == Some(window) {
+            state.focus_cleared();
+        }
+
+        let mut changes = MonitorChanges::default();
+        if self.dimmed_windows.remove(&window) {
+            changes.undimmed.push(window);
+        } else if hidden {
+            changes.window_added.push(WindowInfo { addr: window, tag: tag_index as u8 + 1 });
+        }
+        tracing::info!(%window, monitor = self.monitors[index].name, "window added to overlay");
+        self.overlay.entry(self.monitors[index].name.clone()).or_default().push(window);
+        self.focused_overlay = None;
+
+        Ok(Changes {
+            source: self.source.clone(),
+            active_monitor_index: index,
+            tag_count: self.tag_count,
+            changes,
+        })
+    }
+
+    /// Puts the overlay window, or the focused one, back on the active tag of its monitor, where it already is.
+    pub fn overlay_remove(&mut self, window: Option<WindowAddr>) -> anyhow::Result<()> {
+        let window = match window.or(self.focused_overlay) {
+            Some(w) => w,
+            None => bail!("no focused overlay window"),
+        };
+        let name = match self.overlay.iter().find(|(_, windows)| windows.contains(&window)) {
+            Some((name, _)) => name.clone(),
+            None => bail!("window:{} is not an overlay", window),
+        };
+        self.forget_overlay(&window);
+
+        tracing::info!(%window, monitor = name, "window removed from overlay");
+        match self.monitors.iter().position(|m| m.name == name).filter(|i| self.is_managed(*i)) {
+            Some(index) => self.monitors[index].state.new_window_added(window),
+            None => {
+                self.unmanaged_windows.insert(window);
+                Ok(())
+            },
+        }
+    }
+
+    fn is_overlay(&self, window: &WindowAddr) -> bool {
+        self.overlay.values().any(|windows| windows.contains(window))
+    }
+
+    fn forget_overlay(&mut self, window: &WindowAddr) -> bool {
+        if self.focused_overlay.as_ref() == Some(window) {
+            self.focused_overlay = None;
+        }
+        let mut found = false;
+        self.overlay.retain(|_, windows| {
+            found |= windows.contains(window);
+            windows.retain(|w| w != window);
+            !windows.is_empty()
+        });
+        found
+    }
+
+    /// Drops moves of windows which stay where they are: mirrored windows until `unmirror`, sticky XWayland windows,
+    /// and provisional windows until they're confirmed, which also aren't focused.
+    fn without_held(&mut self, mut changes: MonitorChanges) -> MonitorChanges {
+        let held = |w: &WindowInfo| self.is_mirrored(&w.addr) || self.is_sticky(&w.addr);
+        changes.window_added.retain(|w| !held(w));
+        changes.window_removed.retain(|w| !held(w));
+        let validity = &mut self.validity;
+        changes.window_added.retain(|w| !validity.hold_back(&w.addr));
+        changes.window_removed.retain(|w| !validity.hold_back(&w.addr));
+        changes.focus = changes.focus.filter(|w| !validity.is_provisional(w));
+        changes
+    }
+
+    pub fn monitor_removed(&mut self, name: &str) -> anyhow::Result<(usize, usize, Vec<WindowAddr>)> {
+        let first_monitor_name = match self.monitors.iter().enumerate().find(|(i, m)| m.name != name && self.is_managed(*i)) {
+            Some((_, m)) => m.name.clone(),
+            None => bail!("All monitors were removed?"), // TODO: care this case
+        };
+        self.remove_monitor(name, first_monitor_name)
+    }
+
+    /// Stops tracking a monitor Hyprland now mirrors `source` onto, moving its windows there like on removal.
+    pub fn monitor_linked(&mut self, name: &str, source: &str) -> anyhow::Result<(usize, usize, Vec<WindowAddr>)> {
+        if name == source {
+            bail!("monitor:{} can't mirror itself", name);
+        }
+        tracing::info!(monitor = name, source, "monitor mirrors another, linking it");
+        let r = self.remove_monitor(name, source.to_string())?;
+        self.linked.insert(name.to_string(), source.to_string());
+        Ok(r)
+    }
+
+    /// Forgets a mirroring output Hyprland removed. False when it isn't one.
+    pub fn mirror_output_removed(&mut self, name: &str) -> bool {
+        let linked = self.linked.remove(name).is_some();
+        if linked {
+            tracing::info!(monitor = name, "mirroring monitor removed");
+        }
+        linked
+    }
+
+    /// Removes the monitor, moving its windows to the active tag of the named one.
+    fn remove_monitor(&mut self, name: &str, first_monitor_name: String) -> anyhow::Result<(usize, usize, Vec<WindowAddr>)> {
+        let (removed_index, monitor) = match self.monitors.iter().enumerate().find(|(_, m)| m.name == name) {
+            Some(m) => m,
+            None => bail!("No such monitor: {}", name),
+        };
+        let index = match self.monitors.iter().position(|m| m.name == first_monitor_name) {
+            Some(index) => index,
+            None => bail!("No such monitor: {}", first_monitor_name),
+        };
+
+        let windows = monitor.state.all_window_addrs();
+        for w in windows.iter() {
+            self.move_window_to_monitor(index as u8, Some(*w))?;
+        }
+
+        let active_name = self.monitors[self.active_monitor_index].name.clone();
+        self.monitors.remove(removed_index);
+        self.dim.remove(name);
+        self.presentation.remove(name);
+        self.focus_history.remove(name);
+        // outputs mirroring it are left showing nothing, until a refresh finds them on their own
+        self.linked.retain(|_, source| source != name);
+        // Hyprland moves the workspace along with the overlay windows on it
+        if let Some(windows) = self.overlay.remove(name) {
+            self.overlay.entry(first_monitor_name.clone()).or_default().extend(windows);
+        }
+
+        // indexes after the removed monitor shift down, so look everything up by name again
+        let index = self.monitors.iter().position(|m| m.name == first_monitor_name).unwrap_or(0);
+        self.active_monitor_index = self.monitors.iter().position(|m| m.name == active_name).unwrap_or(index);
+
+        self.reset_monitor_workspaces();
+
+        Ok((index, self.monitors[index].state.active_tag_index(), windows))
+    }
+
+    /// Handles monitorremoved by giving the monitor `grace` to come back before its windows move away.
+    pub(crate) fn monitor_removed_later(&mut self, name: &str, grace: Duration, tx: mpsc::Sender<Ctrl>) -> anyhow::Result<()> {
+        let monitor = match self.monitors.iter().find(|m| m.name == name) {
+            Some(m) => m,
+            None => bail!("No such monitor: {}", name),
+        };
+        tracing::info!(monitor = name, grace_ms = grace.as_millis() as u64, "monitor removed, waiting for it to come back");
+        self.removals.schedule(name, &monitor.description, grace, tx);
+        Ok(())
+    }
+
+    /// Takes back a monitor added within the grace period of its removal, possibly under another connector name,
+    /// keeping its windows and tags. Returns the sender its removal was scheduled with, `None` when it wasn't waited
+    /// for.
+    pub(crate) fn monitor_returned(&mut self, monitor: &Monitor) -> Option<mpsc::Sender<Ctrl>> {
+        let (removed, tx) = self.removals.returned(&monitor.name, &monitor.description)?;
+        tracing::info!(monitor = monitor.name, removed, "monitor came back, keeping its state");
+        if let Some(m) = self.monitors.iter_mut().find(|m| m.name == removed) {
+            m.id = monitor.id;
+            m.name = monitor.name.clone();
+        }
+        if removed != monitor.name {
+            self.monitor_renamed(&removed, &monitor.name);
+        }
+        self.reset_monitor_workspaces();
+        Some(tx)
+    }
+
+    /// Moves what's kept by monitor name over to the monitor's new name.
+    fn monitor_renamed(&mut self, old: &str, new: &str) {
+        if self.dim.remove(old) {
+            self.dim.insert(new.to_string());
+        }
+        if self.presentation.remove(old) {
+            self.presentation.insert(new.to_string());
+        }
+        if let Some(history) = self.focus_history.remove(old) {
+            self.focus_history.insert(new.to_string(), history);
+        }
+        if let Some(windows) = self.overlay.remove(old) {
+            self.overlay.insert(new.to_string(), windows);
+        }
+        if let Some(mirror) = self.mirror.as_mut() {
+            for name in [&mut mirror.source, &mut mirror.dest] {
+                if name == old {
+                    *name = new.to_string();
+                }
+            }
+        }
+    }
+
+    pub fn removals(&mut self) -> &mut PendingRemovals {
+        &mut self.removals
+    }
+
+    /// Queries Hyprland's clients and sends them back as `Ctrl::PlacementsChecked`.
+    pub(crate) fn request_placement_check(&self, tx: mpsc::Sender<Ctrl>) {
+        tokio::spawn(async move {
+            match hyprctl_clients().await {
+                Ok(clients) => {
+                    if let Err(err) = tx.send(Ctrl::PlacementsChecked(clients)).await {
+                        tracing::error!(%err, "failed to send Ctrl::PlacementsChecked");
+                    }
+                },
+                Err(err) => tracing::error!(%err, "failed to fetch clients"),
+            }
+        });
+    }
+
+    /// Queries the cursor position and the monitor geometries, and sends them back as `Ctrl::CursorQueried` along with
+    /// the window to move there.
+    pub(crate) fn request_cursor_monitor(&self, tag: Option<u8>, window: WindowAddr, sync: Option<SyncReply>, tx: mpsc::Sender<Ctrl>) {
+        tokio::spawn(async move {
+            match tokio::try_join!(hyprctl_cursorpos(), hyprctl_monitors()) {
+                Ok((cursor, monitors)) => {
+                    if let Err(err) = tx.send(Ctrl::CursorQueried(tag, window, cursor, monitors, sync)).await {
+                        tracing::error!(%err, "failed to send Ctrl::CursorQueried");
+                    }
+                },
+                Err(err) => {
+                    tracing::error!(%err, "failed to find the monitor under the cursor");
+                    if let Some(sync) = sync {
+                        sync.done(&Err(err.context("failed to find the monitor under the cursor")));
+                    }
+                },
+            }
+        });
+    }
+
+    /// Queries where the cursor is for a command acting on the monitor under it. A failed query is sent on as
+    /// `None`, so the command still runs on the focused monitor.
+    pub(crate) fn request_command_target(&self, msg: Ctrl, sync: Option<SyncReply>, tx: mpsc::Sender<Ctrl>) {
+        let source = self.source.clone();
+        tokio::spawn(async move {
+            let query = match tokio::try_join!(hyprctl_cursorpos(), hyprctl_monitors()) {
+                Ok(query) => Some(query),
+                Err(err) => {
+                    tracing::warn!(%err, "failed to find the monitor under the cursor");
+                    None
+                },
+            };
+            if let Err(err) = tx.send(Ctrl::TargetQueried(Box::new(msg), source, query, sync)).await {
+                tracing::error!(%err, "failed to send Ctrl::TargetQueried");
+            }
+        });
+    }
+
+    /// The monitor under the cursor for a command targeted at it. Callers fall back to the active monitor when the
+    /// query failed or the cursor isn't on a managed monitor.
+    pub fn cursor_target(&self, query: Option<&(CursorPos, Vec<MonitorInfo>)>) -> anyhow::Result<usize> {
+        match query {
+            Some((cursor, infos)) => self.monitor_at(*cursor, infos),
+            None => bail!("cursor position unknown"),
+        }
+    }
+
+    /// Index of the managed monitor the cursor is on, looked up in the geometries of a monitors query.
+    pub fn monitor_at(&self, cursor: CursorPos, infos: &[MonitorInfo]) -> anyhow::Result<usize> {
+        let info = match infos.iter().find(|i| i.contains(cursor.x, cursor.y)) {
+            Some(info) => info,
+            None => bail!("no monitor at {},{}", cursor.x, cursor.y),
+        };
+        let index = match self.linked_index(&info.name) {
+            Some(index) => index,
+            None => bail!("no such monitor:{}", info.name),
+        };
+        self.ensure_managed(index)?;
+        Ok(index)
+    }
+
+    /// Places the window on the monitor under the cursor, on the given tag or the one active there.
+    pub fn move_to_cursor_monitor(&mut self, cursor: CursorPos, infos: &[MonitorInfo], tag: Option<u8>, window: WindowAddr) -> anyhow::Result<Changes> {
+        let index = self.monitor_at(cursor, infos)?;
+        let tag = tag.unwrap_or_else(|| self.monitors[index].state.active_tag_index() as u8 + 1);
+        self.place_window(index, tag, Some(window))
+    }
+
+    /// Dispatches moving tracked windows Hyprland has on another workspace back where their tag puts them.
+    pub fn misplaced_dispatches(&self, clients: &[ClientInfo]) -> Vec<String> {
+        let now = self.snapshot();
+        snapshot::diff(&now, &now, clients, self.tag_count).misplaced.iter()
+            .map(|m| format!("dispatch movetoworkspacesilent {},address:0x{}", m.expected_workspace, m.addr))
+            .collect()
+    }
+
+    pub(crate) fn monitor_added(&mut self, name: &str, tx: mpsc::Sender<Ctrl>) -> anyhow::Result<()> {
+        if self.monitors.iter().any(|m| m.name == name) {
+            tracing::info!("monitor:{} is already registered, refreshing its id", name);
+        }
+
+        let name = name.to_string();
+        let tag_count = self.tag_count;
+        tokio::spawn(async move {
+            let monitors = match hyprctl_monitors().await {
+                Ok(m) => m,
+                Err(err) => {
+                    tracing::error!(%err, "failed to fetch monitor info");
+                    return
+                },
+            };
+
+            let info = match monitors.iter().find(|m| m.name == name) {
+                Some(info) => info,
+                None => {
+                    tracing::error!("no such monitor: name={}", name);
+                    return
+                },
+            };
+            // a mirror is linked to its source rather than added
+            if info.mirror_of.is_some() {
+                if let Err(err) = tx.send(Ctrl::MonitorsRefreshed(monitors)).await {
+                    tracing::error!(%err, "failed to send Ctrl::MonitorsRefreshed");
+                }
+                return
+            }
+
+            let monitor = Monitor::new(info, tag_count);
+
+            if let Err(err) = tx.send(Ctrl::MonitorAdded(monitor)).await {
+                tracing::error!(%err, "failed to send Ctrl::MonitorAdded");
+            }
+        });
+
+        Ok(())
+    }
+
+    pub(crate) fn monitor_added_with_object(&mut self, monitor: Monitor) -> anyhow::Result<()> {
+        if self.linked.remove(&monitor.name).is_some() {
+            tracing::info!(monitor = monitor.name, "monitor stopped mirroring");
+        }
+        match self.monitors.iter_mut().find(|m| m.name == monitor.name) {
+            // same connector reported again, possibly under a new id: keep its state
+            Some(m) => {
+                if m.id == monitor.id {
+                    bail!("monitor:{} is already registered", monitor.name);
+                }
+                tracing::info!("monitor:{} id changed {} -> {}", m.name, m.id, monitor.id);
+                m.id = monitor.id;
+            },
+            None => {
+                let mut monitor = monitor;
+                monitor.state.set_tag_names(&self.tag_names);
+                monitor.state.set_active_tag_policy(self.active_tag_policy);
+                self.monitors.push(monitor);
+            },
+        }
+
+        self.reset_monitor_workspaces();
+
+        Ok(())
+    }
+
+    /// Re-queries Hyprland's monitors and sends the result back as `Ctrl::MonitorsRefreshed`.
+    pub(crate) fn request_monitors_refresh(&self, tx: mpsc::Sender<Ctrl>) {
+        request_monitors(tx, Ctrl::MonitorsRefreshed);
+    }
+
+    /// Re-queries Hyprland's monitors after a config reload and sends the result back as `Ctrl::MonitorsReloaded`.
+    pub(crate) fn request_monitors_reload(&self, tx: mpsc::Sender<Ctrl>) {
+        request_monitors(tx, Ctrl::MonitorsReloaded);
+    }
+
+    /// How the monitors Hyprland reports differ from the ones we track.
+    pub fn monitors_diff(&self, infos: &[MonitorInfo]) -> MonitorsDiff {
+        let links = mirror_links(infos);
+        MonitorsDiff {
+            added: infos.iter()
+                .filter(|i| !self.monitors.iter().any(|m| m.name == i.name))
+                .filter(|i| !links.contains_key(&i.name) && !self.linked.contains_key(&i.name))
+                .map(|i| i.name.clone())
+                .collect(),
+            removed: self.monitors.iter()
+                .filter(|m| !infos.iter().any(|i| i.name == m.name))
+                .map(|m| m.name.clone())
+                .collect(),
+            ids_changed: self.monitors.iter()
+                .filter_map(|m| infos.iter().find(|i| i.name == m.name && i.id != m.id).map(|i| (m.name.clone(), m.id, i.id)))
+                .collect(),
+            linked: links.iter()
+                .filter(|(name, _)| self.monitors.iter().any(|m| &m.name == *name))
+                .map(|(name, source)| (name.clone(), source.clone()))
+                .collect(),
+            unlinked: self.linked.keys()
+                .filter(|name| !links.contains_key(*name) && infos.iter().any(|i| &i.name == *name))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Puts every managed monitor's visible workspace back on it, as a config reload may have moved them.
+    pub(crate) fn reassert_monitor_workspaces(&self) {
+        self.reset_monitor_workspaces();
+    }
+
+    /// Updates stored monitor ids from a fresh query, matching monitors by connector name.
+    /// Returns true when any id changed.
+    pub fn refresh_monitor_ids(&mut self, infos: &[MonitorInfo]) -> bool {
+        let mut changed = false;
+        for monitor in self.monitors.iter_mut() {
+            if let Some(info) = infos.iter().find(|i| i.name == monitor.name) {
+                if info.id != monitor.id {
+                    tracing::info!("monitor:{} id changed {} -> {}", monitor.name, monitor.id, info.id);
+                    monitor.id = info.id;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Registers unknown monitors and takes over the focused one, which a focusedmon for a monitor we
+    /// didn't know yet couldn't set.
+    /// Mirrors are linked to their source, unless still tracked, which `monitor_linked` takes care of. Ones which
+    /// stopped mirroring are registered with no windows.
+    pub(crate) fn monitors_refreshed(&mut self, infos: Vec<MonitorInfo>) {
+        let mut changed = self.refresh_monitor_ids(&infos);
+        let focused = infos.iter().find(|i| i.focused).map(|i| i.name.clone());
+        let links = mirror_links(&infos);
+        for name in self.linked.keys().filter(|name| !links.contains_key(*name)) {
+            tracing::info!(monitor = name, "monitor stopped mirroring");
+        }
+        self.linked = links;
+
+        for info in infos {
+            if self.monitors.iter().any(|m| m.name == info.name) || self.linked.contains_key(&info.name) {
+                continue;
+            }
+            tracing::info!("found unregistered monitor:{}", info.name);
+            let mut monitor = Monitor::new(&info, self.tag_count);
+            monitor.state.set_tag_names(&self.tag_names);
+            monitor.state.set_active_tag_policy(self.active_tag_policy);
+            self.monitors.push(monitor);
+            changed = true;
+        }
+        let monitors = &self.monitors;
+        self.linked.retain(|name, _| !monitors.iter().any(|m| &m.name == name));
+
+        if changed {
+            self.reset_monitor_workspaces();
+        }
+
+        if let Some(index) = focused.and_then(|name| self.linked_index(&name)) {
+            if index != self.active_monitor_index {
+                tracing::info!(monitor = self.monitors[index].name, "focused monitor caught up after refresh");
+                self.active_monitor_index = index;
+            }
+        }
+    }
+
+    /// moveworkspacetomonitor arguments pinning each monitor's visible workspace, addressed by connector name.
+    fn workspace_assignments(&self) -> Vec<String> {
+        self.monitors.iter().enumerate()
+            .filter(|(i, _)| self.is_managed(*i))
+            .map(|(i, m)| format!(r#"dispatch moveworkspacetomonitor {} {}"#, visible_workspace(i), m.name))
+            .collect()
+    }
+
+    fn reset_monitor_workspaces(&self) {
+        hyprctl_batch(self.workspace_assignments());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{addr, activity::{tests::ManualClock, Activity}, autostart::AUTOSTART_TIMEOUT, config::{Config, NewWindowTag, OnTagEmptied, XwaylandPolicy}, dispatch::dispatches, fullscreen::Fullscreen, hyprctl::{ClientInfo, CursorPos, MonitorInfo, WorkspaceRef}, lineage::{tests::FakeAncestry, Lineage}, rules::WindowRule, state::DEFAULT_TAG_COUNT, validity::PROVISIONAL_TIMEOUT};
+
+    use super::{hidden_workspace, hidden_workspace_in, hidden_workspace_tag, hidden_workspace_tag_in, visible_workspace, ChangeSource, MonitorsState, Untracked, ViewMode};
+
+    fn info(id: u8, name: &str, focused: bool) -> MonitorInfo {
+        MonitorInfo { id, name: name.into(), description: String::new(), focused, ..Default::default() }
+    }
+
+    fn client(address: &str, xwayland: bool) -> ClientInfo {
+        ClientInfo {
+            address: address.into(),
+            workspace: WorkspaceRef { id: 1, name: "1".into() },
+            monitor: 0,
+            class: String::new(),
+            title: String::new(),
+            fullscreen: 0,
+            xwayland,
+            pid: 0,
+            at: [0, 0],
+            size: [0, 0],
+        }
+    }
+
+    fn monitors() -> MonitorsState {
+        MonitorsState::new(vec![
+            info(0, "DP-1", true),
+            info(1, "DP-2", false),
+        ], DEFAULT_TAG_COUNT)
+    }
+
+    #[test]
+    fn bootstrap_existing_windows() {
+        let mut state = monitors();
+        let on = |address: &str, workspace: i64, monitor: i64| ClientInfo {
+            workspace: WorkspaceRef { id: workspace, name: workspace.to_string() },
+            monitor,
+            ..client(address, false)
+        };
+        let clients = vec![
+            on("0xaa", 1, 0),
+            // a workspace of a session started without us
+            on("0xbb", 5, 1),
+            // left on hidden workspaces by a previous run, of a hidden and a visible tag
+            on("0xcc", hidden_workspace(DEFAULT_TAG_COUNT, 0, 3), 0),
+            on("0xdd", hidden_workspace(DEFAULT_TAG_COUNT, 1, 1), 1),
+            // a scratchpad
+            on("0xee", -98, 0),
+        ];
+        clients.iter().for_each(|c| state.client_known(c));
+        let changes = state.bootstrap(&clients);
+
+        let tag_of = |state: &MonitorsState, w: &str| state.monitors.iter()
+            .find_map(|m| m.state.find_window_tag_index(&addr(w)).map(|i| (m.name.clone(), i as u8 + 1)));
+        assert_eq!(tag_of(&state, "aa"), Some(("DP-1".into(), 1)));
+        assert_eq!(tag_of(&state, "bb"), Some(("DP-2".into(), 1)));
+        assert_eq!(tag_of(&state, "cc"), Some(("DP-1".into(), 3)));
+        assert_eq!(tag_of(&state, "dd"), Some(("DP-2".into(), 1)));
+        assert_eq!(tag_of(&state, "ee"), None);
+        // only the window stranded on a hidden workspace of a visible tag moves
+        assert_eq!(changes.iter().flat_map(|c| dispatches(c, &Fullscreen::default())).collect::<Vec<_>>(), vec![
+            "dispatch movetoworkspacesilent 2,address:0xdd",
+        ]);
+
+        // and they can be moved like any other
+        state.focus_window_changed(addr("aa")).unwrap();
+        state.move_window(2, None, false).unwrap();
+        assert_eq!(tag_of(&state, "aa"), Some(("DP-1".into(), 2)));
+        // running it again changes nothing
+        assert!(state.bootstrap(&clients).is_empty());
+
+        assert_eq!(hidden_workspace_tag(DEFAULT_TAG_COUNT, hidden_workspace(DEFAULT_TAG_COUNT, 1, 32)), Some((1, 32)));
+        assert_eq!(hidden_workspace_tag(DEFAULT_TAG_COUNT, hidden_workspace(DEFAULT_TAG_COUNT, 0, 1)), Some((0, 1)));
+        assert_eq!(hidden_workspace_tag(DEFAULT_TAG_COUNT, visible_workspace(0)), None);
+
+        // with more tags the monitors' ranges move apart
+        assert_eq!(hidden_workspace_in(100, 40, 0, 40), 140);
+        assert_eq!(hidden_workspace_in(100, 40, 1, 1), 141);
+        assert_eq!(hidden_workspace_tag_in(100, 40, 141), Some((1, 1)));
+        assert_eq!(hidden_workspace_tag_in(100, 40, 140), Some((0, 40)));
+        assert_eq!(hidden_workspace_tag_in(100, 5, hidden_workspace_in(100, 5, 2, 5)), Some((2, 5)));
+        // compacted right after the visible workspaces
+        assert_eq!(hidden_workspace_in(10, 32, 1, 1), 43);
+        assert_eq!(hidden_workspace_tag_in(10, 32, 43), Some((1, 1)));
+        assert_eq!(hidden_workspace_tag_in(10, 32, 10), None);
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn restore_saved_tags() {
+        let mut state = monitors();
+        for w in ["aa", "bb", "cc"] {
+            state.window_opened(addr(w), "kitty", "").unwrap();
+        }
+        state.focus_window_changed(addr("bb")).unwrap();
+        state.move_window(3, None, false).unwrap();
+        state.focus_window_changed(addr("cc")).unwrap();
+        state.move_window(3, None, false).unwrap();
+        state.set_visible_tags(0b10).unwrap();
+        // focused before Hyprland told of it, so it may not exist
+        state.focus_window_changed(addr("ee")).unwrap();
+        let saved = state.saved_state();
+        assert!(saved.monitors[0].tags.values().flatten().all(|w| *w != addr("ee")));
+        assert_eq!(saved.times.len(), 3);
+
+        // after a restart, with cc closed in the meantime
+        let mut state = monitors();
+        let on = |address: &str, workspace: i64| ClientInfo { workspace: WorkspaceRef { id: workspace, name: workspace.to_string() }, ..client(address, false) };
+        let clients = vec![on("0xaa", hidden_workspace(DEFAULT_TAG_COUNT, 0, 1)), on("0xbb", 1)];
+        let changes = state.restore(&saved, &clients);
+        assert_eq!(state.monitors[0].state.tag_windows().filter(|(_, w)| !w.is_empty()).collect::<Vec<_>>(), vec![
+            (1, [addr("aa")].as_slice()),
+            (3, [addr("bb")].as_slice()),
+        ]);
+        assert_eq!((state.monitors[0].state.visible_tags(), state.monitors[0].state.prev_tags()), (0b10, 0b1));
+        // both were where the new view doesn't want them
+        let mut dispatched: Vec<String> = changes.iter().flat_map(|c| dispatches(c, &Fullscreen::default())).collect();
+        dispatched.sort();
+        assert_eq!(dispatched, vec![
+            format!("dispatch movetoworkspacesilent {},address:0xaa", hidden_workspace(DEFAULT_TAG_COUNT, 0, 1)),
+            format!("dispatch movetoworkspacesilent {},address:0xbb", hidden_workspace(DEFAULT_TAG_COUNT, 0, 3)),
+        ]);
+        assert_eq!(state.activity.times(&addr("bb")), saved.times.get(&addr("bb")).copied());
+
+        // show 3 brings it back
+        let changes = state.set_visible_tags(0b100).unwrap();
+        assert_eq!(changes.changes.window_added.iter().map(|w| w.addr).collect::<Vec<_>>(), vec![addr("bb")]);
+    }
+
+    #[test]
+    fn tag_count_of_the_state() {
+        let mut state = MonitorsState::new(vec![
+            MonitorInfo { id: 0, name: "DP-1".into(), focused: true, ..Default::default() },
+            MonitorInfo { id: 1, name: "DP-2".into(), ..Default::default() },
+        ], 8);
+        state.set_tag_monitors(std::collections::BTreeMap::from([(5, "DP-2".to_string())]));
+        state.window_opened(addr("aa"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("aa")).unwrap();
+
+        // the second monitor's hidden workspaces start right after the first one's 8
+        let changes = state.move_window(5, None, false).unwrap();
+        assert_eq!(dispatches(&changes, &Fullscreen::default()), vec!["dispatch movetoworkspacesilent 113,address:0xaa"]);
+        assert_eq!(state.move_window(9, None, false).unwrap_err().to_string(), "no such tag: 9, tags go up to 8");
+    }
+
+    #[test]
+    fn pinned_tags() {
+        let mut state = monitors();
+        state.set_tag_monitors(std::collections::BTreeMap::from([(9, "DP-2".to_string()), (5, "HDMI-A-1".to_string())]));
+        state.rules_mut().set_file_rules(&[
+            WindowRule { name: "chat".into(), class: "discord".into(), title: None, tag: 9, monitor: None, sticky: false, ignore: false },
+        ]);
+        let tag_of = |state: &MonitorsState, w: &str| state.monitors.iter()
+            .find_map(|m| m.state.find_window_tag_index(&addr(w)).map(|i| (m.name.clone(), i as u8 + 1)));
+
+        // moving to the pinned tag places the window on its monitor
+        state.window_opened(addr("aa"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("aa")).unwrap();
+        let changes = state.move_window(9, None, false).unwrap();
+        assert_eq!(tag_of(&state, "aa"), Some(("DP-2".into(), 9)));
+        assert_eq!(dispatches(&changes, &Fullscreen::default()), vec![
+            format!("dispatch movetoworkspacesilent {},address:0xaa", hidden_workspace(DEFAULT_TAG_COUNT, 1, 9)),
+        ]);
+
+        // and so do rules
+        state.window_opened(addr("d15c"), "discord", "").unwrap();
+        assert_eq!(tag_of(&state, "d15c"), Some(("DP-2".into(), 9)));
+
+        // a monitor which isn't connected leaves the focused one, with a note why
+        state.window_opened(addr("bb"), "kitty", "").unwrap();
+        state.focus_window_changed(addr("bb")).unwrap();
+        state.record_placements();
+        state.move_window(5, None, false).unwrap();
+        state.record_placements();
+        assert_eq!(tag_of(&state, "bb"), Some(("DP-1".into(), 5)));
+        assert_eq!(state.provenance().latest(&addr("bb")).unwrap().note.as_deref(),
+            Some("tag 5 is pinned to HDMI-A-1, which isn't connected, moved on the focused monitor"));
+    }
+
+    #[test]
+    fn adopt_window_keeps_active_window() {
+        let mut state = monitors();
+
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.adopt_window(addr("f1f0")).unwrap();
+
+        let active = &state.monitors[0].state;
+        assert_eq!(active.visible_windows().len(), 2);
+        assert_eq!(active.active_window(), Some(addr("7e3")));
+
+        state.focus_settled(addr("f1f0")).unwrap();
+        assert_eq!(state.monitors[0].state.active_window(), Some(addr("f1f0")));
+    }
+
+    #[test]
+    fn focus_monitor_before_event() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("7e3")).unwrap();
+
+        let index = state.resolve_monitor("next").unwrap();
+        assert_eq!(index, 1);
+        let (name, window) = state.focus_monitor(index).unwrap();
+        assert_eq!(name, "DP-2");
+        assert_eq!(window, None);
+        assert_eq!(state.active_monitor_index, 1);
+
+        let index = state.resolve_monitor("prev").unwrap();
+        let (name, window) = state.focus_monitor(index).unwrap();
+        assert_eq!(name, "DP-1");
+        assert_eq!(window, Some(addr("7e3")));
+        assert_eq!(state.active_monitor_index, 0);
+
+        // echoes of both dispatches arrive late and must not move focus back
+        state.focused_monitor_changed("DP-2").unwrap();
+        assert_eq!(state.active_monitor_index, 0);
+        state.focused_monitor_changed("DP-1").unwrap();
+        assert_eq!(state.active_monitor_index, 0);
+
+        // anything else is a real focus change
+        state.focused_monitor_changed("DP-2").unwrap();
+        assert_eq!(state.active_monitor_index, 1);
+
+        assert!(state.resolve_monitor("HDMI-A-1").is_err());
+        assert_eq!(state.resolve_monitor("DP-1").unwrap(), 0);
+    }
+
+    #[test]
+    fn unexpected_focusedmon_clears_echoes() {
+        let mut state = monitors();
+
+        state.focus_monitor(1).unwrap();
+        state.focused_monitor_changed("DP-1").unwrap();
+        assert_eq!(state.active_monitor_index, 0);
+
+        state.focused_monitor_changed("DP-2").unwrap();
+        assert_eq!(state.active_monitor_index, 1);
+    }
+
+    #[test]
+    fn focusedmon_by_id() {
+        let mut state = monitors();
+        state.refresh_monitor_ids(&[info(0, "DP-1", true), info(3, "DP-2", false)]);
+
+        state.focused_monitor_changed_by_num(3).unwrap();
+        assert_eq!(state.active_monitor_index, 1);
+        state.focused_monitor_changed_v2("0").unwrap();
+        assert_eq!(state.active_monitor_index, 0);
+        state.focused_monitor_changed_v2("DP-2").unwrap();
+        assert_eq!(state.active_monitor_index, 1);
+
+        // echoes of our own dispatches are still ignored
+        state.focus_monitor(0).unwrap();
+        state.focused_monitor_changed_by_num(0).unwrap();
+        state.focused_monitor_changed("DP-1").unwrap();
+        assert_eq!(state.active_monitor_index, 0);
+
+        assert!(state.focused_monitor_changed_by_num(1).is_err());
+        assert!(state.focused_monitor_changed_v2("HDMI-A-1").is_err());
+        assert_eq!(state.active_monitor_index, 0);
+
+        // the refresh requested after an unknown monitor catches up with the focus
+        state.monitors_refreshed(vec![info(0, "DP-1", false), info(3, "DP-2", false), info(4, "HDMI-A-1", true)]);
+        assert_eq!(state.monitors[state.active_monitor_index].name, "HDMI-A-1");
+    }
+
+    #[test]
+    fn mirrored_outputs() {
+        let mirror = |id, name, of, focused| MonitorInfo { mirror_of: Some(of), ..info(id, name, focused) };
+        let names = |state: &MonitorsState| state.monitors.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+
+        // linked from the start, and commands naming it go to its source
+        let state = MonitorsState::new(vec![info(0, "DP-1", false), mirror(1, "HDMI-A-1", 0, true)], DEFAULT_TAG_COUNT);
+        assert_eq!(names(&state), vec!["DP-1"]);
+        assert_eq!(state.active_monitor_index, 0);
+        assert_eq!(state.resolve_monitor("HDMI-A-1").unwrap(), 0);
+        assert_eq!(state.next_monitor(), 0);
+
+        // a tracked monitor starts mirroring, its windows go to the source
+        let mut state = monitors();
+        state.focused_monitor_changed("DP-2").unwrap();
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        let infos = vec![info(0, "DP-1", true), mirror(1, "DP-2", 0, false)];
+        let diff = state.monitors_diff(&infos);
+        assert_eq!(diff.linked, vec![("DP-2".to_string(), "DP-1".to_string())]);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(state.monitor_linked("DP-2", "DP-1").unwrap(), (0, 0, vec![addr("7e3")]));
+        state.monitors_refreshed(infos);
+        assert_eq!(names(&state), vec!["DP-1"]);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(0));
+        assert_eq!(state.mirror_source("DP-2"), Some("DP-1"));
+        assert_eq!(state.workspace_assignments(), vec!["dispatch moveworkspacetomonitor 1 DP-1"]);
+        state.focused_monitor_changed("DP-2").unwrap();
+        assert_eq!(state.active_monitor_index, 0);
+
+        // mirroring ends on a config reload, and it's a monitor of its own again, with nothing on it
+        let infos = vec![info(0, "DP-1", true), info(1, "DP-2", false)];
+        assert_eq!(state.monitors_diff(&infos).unlinked, vec!["DP-2"]);
+        assert!(state.monitors_diff(&infos).added.is_empty());
+        state.monitors_refreshed(infos);
+        assert_eq!(state.resolve_monitor("DP-2").unwrap(), 1);
+        assert!(state.monitors[1].state.all_window_addrs().is_empty());
+        assert_eq!(state.mirror_source("DP-2"), None);
+
+        // a mirroring output removed is forgotten
+        state.monitors_refreshed(vec![info(0, "DP-1", true), info(1, "DP-2", false), mirror(2, "HDMI-A-1", 1, false)]);
+        assert_eq!(state.mirror_source("HDMI-A-1"), Some("DP-2"));
+        assert!(state.mirror_output_removed("HDMI-A-1"));
+        assert!(!state.mirror_output_removed("DP-2"));
+        assert!(state.resolve_monitor("HDMI-A-1").is_err());
+    }
+
+    #[test]
+    fn monitor_id_changed() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("7e3")).unwrap();
+
+        let changed = state.refresh_monitor_ids(&[
+            info(0, "DP-1", true),
+            info(3, "DP-2", false),
+        ]);
+        assert!(changed);
+        assert_eq!(state.monitors.len(), 2);
+        assert_eq!(state.monitors[1].id, 3);
+
+        // workspaces are assigned by position and connector name, never by the stale id
+        assert_eq!(state.workspace_assignments(), vec![
+            "dispatch moveworkspacetomonitor 1 DP-1",
+            "dispatch moveworkspacetomonitor 2 DP-2",
+        ]);
+
+        let changed = state.refresh_monitor_ids(&[
+            info(0, "DP-1", true),
+            info(3, "DP-2", false),
+        ]);
+        assert!(!changed);
+        assert_eq!(state.monitors[0].state.active_window(), Some(addr("7e3")));
+    }
+
+    #[test]
+    fn new_window_tag() {
+        let mut state = monitors();
+        state.set_new_window_tag(NewWindowTag::Tag(9));
+
+        let changes = state.new_window_added(addr("51ac")).unwrap();
+        assert_eq!(changes.changes.window_removed.len(), 1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("51ac")), Some(8));
+
+        // windows adopted through focus follow the same default
+        state.client_known(&client("0x7e3", false));
+        let changes = state.focus_window_changed(addr("7e3")).unwrap();
+        assert_eq!(changes.changes.window_removed.len(), 1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(8));
+
+        let changes = state.focus_window_changed(addr("7e3")).unwrap();
+        assert!(changes.changes.window_removed.is_empty());
+    }
+
+    #[test]
+    fn move_after_focus_cleared() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("f1f0")).unwrap();
+
+        state.focus_cleared();
+        assert_eq!(state.active_window(), None);
+        let err = state.move_window(3, None, false).unwrap_err();
+        assert_eq!(err.to_string(), "no focused window");
+        assert!(state.promote_window(None).is_err());
+        assert!(state.move_window_to_monitor(1, None).is_err());
+
+        // tags are untouched, and explicit windows still move
+        assert_eq!(state.monitors[0].state.visible_windows().len(), 2);
+        state.move_window(3, Some(addr("f1f0")), false).unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("f1f0")), Some(2));
+    }
+
+    #[test]
+    fn place_window() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("f1f0")).unwrap();
+
+        // a hidden tag on the other monitor
+        let changes = state.place_window(1, 4, None).unwrap();
+        assert_eq!(changes.active_monitor_index, 1);
+        assert!(changes.changes.window_added.is_empty());
+        assert_eq!(changes.changes.window_removed[0].addr, addr("f1f0"));
+        assert_eq!(changes.changes.window_removed[0].tag, 4);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("f1f0")), None);
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("f1f0")), Some(3));
+
+        // already there
+        let changes = state.place_window(1, 4, Some(addr("f1f0"))).unwrap();
+        assert!(changes.changes.window_added.is_empty());
+        assert!(changes.changes.window_removed.is_empty());
+
+        // a visible tag
+        let changes = state.place_window(1, 1, Some(addr("f1f0"))).unwrap();
+        assert_eq!(changes.changes.window_added[0].addr, addr("f1f0"));
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("f1f0")), Some(0));
+
+        assert!(state.place_window(2, 1, Some(addr("7e3"))).is_err());
+        assert!(state.place_window(1, 33, Some(addr("7e3"))).is_err());
+        assert!(state.place_window(1, 0, Some(addr("7e3"))).is_err());
+        assert!(state.place_window(1, 1, Some(addr("404"))).is_err());
+    }
+
+    /// A drawing tablet between two managed monitors.
+    fn with_tablet() -> MonitorsState {
+        let mut state = MonitorsState::new(vec![
+            info(0, "DP-1", true),
+            info(1, "HDMI-A-1", false),
+            info(2, "DP-2", false),
+        ], DEFAULT_TAG_COUNT);
+        state.set_unmanaged_monitors(vec!["HDMI-A-1".into()]);
+        state
+    }
+
+    #[test]
+    fn move_to_cursor_monitor() {
+        let mut state = with_tablet();
+        // a portrait monitor left of and above the main one, the tablet on the right at half scale
+        let geometry = |id, name: &str, x, y, width, height, scale, transform| MonitorInfo {
+            id, name: name.into(), x, y, width, height, scale, transform, ..Default::default()
+        };
+        let infos = vec![
+            geometry(0, "DP-1", 0, 0, 2560, 1440, 1.0, 0),
+            geometry(1, "HDMI-A-1", 2560, 0, 3840, 2160, 2.0, 0),
+            geometry(2, "DP-2", -1080, -480, 1920, 1080, 1.0, 3),
+        ];
+        let at = |x, y| CursorPos { x, y };
+        assert_eq!(state.monitor_at(at(0, 0), &infos).unwrap(), 0);
+        assert_eq!(state.monitor_at(at(-1, -480), &infos).unwrap(), 2);
+        assert_eq!(state.monitor_at(at(-1080, 1439), &infos).unwrap(), 2);
+        assert_eq!(state.monitor_at(at(4479, 1079), &infos).unwrap_err().to_string(), "monitor:HDMI-A-1 is not managed by hyprtag");
+        assert_eq!(state.monitor_at(at(-500, 1440), &infos).unwrap_err().to_string(), "no monitor at -500,1440");
+
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.monitors[2].state.set_visible_tags(0b100).unwrap();
+        // onto the tag active there
+        let changes = state.move_to_cursor_monitor(at(-200, 100), &infos, None, addr("7e3")).unwrap();
+        assert_eq!(changes.active_monitor_index, 2);
+        assert_eq!(changes.changes.window_added[0].addr, addr("7e3"));
+        assert_eq!(state.monitors[2].state.find_window_tag_index(&addr("7e3")), Some(2));
+
+        let changes = state.move_to_cursor_monitor(at(100, 100), &infos, Some(5), addr("7e3")).unwrap();
+        assert_eq!(changes.changes.window_removed[0].tag, 5);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(4));
+        assert!(state.move_to_cursor_monitor(at(3000, 100), &infos, None, addr("7e3")).is_err());
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(4));
+    }
+
+    #[test]
+    fn unmanaged_monitor() {
+        let mut state = with_tablet();
+        state.focus_window_changed(addr("7e3")).unwrap();
+
+        assert_eq!(state.next_monitor(), 2);
+        assert_eq!(state.prev_monitor(), 2);
+        assert_eq!(state.workspace_assignments(), vec![
+            "dispatch moveworkspacetomonitor 1 DP-1",
+            "dispatch moveworkspacetomonitor 3 DP-2",
+        ]);
+        let index = state.resolve_monitor("HDMI-A-1").unwrap();
+        assert!(state.focus_monitor(index).is_err());
+        assert!(state.mirror(1, "HDMI-A-1").is_err());
+
+        // windows opened and focused on the tablet stay off every tag, and leave DP-1's focus alone
+        state.focused_monitor_changed("HDMI-A-1").unwrap();
+        let changes = state.window_opened(addr("c4a7"), "krita", "").unwrap();
+        assert!(changes.changes.window_added.is_empty());
+        assert!(changes.changes.window_removed.is_empty());
+        state.focus_window_changed(addr("c4a7")).unwrap();
+        assert!(state.monitors.iter().all(|m| m.state.find_window_tag_index(&addr("c4a7")).is_none()));
+        assert_eq!(state.monitors[0].state.active_window(), Some(addr("7e3")));
+
+        let err = state.set_visible_tags(0b10).unwrap_err();
+        assert!(err.to_string().contains("monitor:HDMI-A-1 is not managed"));
+        assert!(state.toggle_tag(2, false).is_err());
+        assert!(state.move_window(2, None, false).is_err());
+        assert!(state.snapshot().monitors[1].unmanaged);
+
+        state.window_removed(addr("c4a7")).unwrap();
+    }
+
+    #[test]
+    fn cross_unmanaged_monitor() {
+        let mut state = with_tablet();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.set_new_window_tag(NewWindowTag::Tag(5));
+
+        // moved to the tablet, the window leaves tag tracking
+        state.focused_monitor_changed("HDMI-A-1").unwrap();
+        let changes = state.focus_window_changed(addr("7e3")).unwrap();
+        assert!(changes.changes.window_added.is_empty());
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), None);
+
+        // moved on to DP-2, it's adopted onto the active tag instead of new_window_tag
+        state.focused_monitor_changed("DP-2").unwrap();
+        state.set_visible_tags(0b100).unwrap();
+        let changes = state.focus_window_changed(addr("7e3")).unwrap();
+        assert!(changes.changes.window_added.is_empty());
+        assert_eq!(state.monitors[2].state.find_window_tag_index(&addr("7e3")), Some(2));
+        assert_eq!(state.monitors[2].state.active_window(), Some(addr("7e3")));
+    }
+
+    #[test]
+    fn default_tags() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("7e3")).unwrap();
+
+        let defaults = HashMap::from([
+            ("DP-2".to_string(), vec![2, 3]),
+            ("HDMI-A-1".to_string(), vec![1]),
+        ]);
+        let changes = state.show_default_tags(&defaults);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].active_monitor_index, 1);
+        assert_eq!(state.monitors[1].state.visible_tags(), 0b110);
+
+        // tag assignments are left alone
+        assert_eq!(state.monitors[0].state.visible_tags(), 0b1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(0));
+    }
+
+    #[test]
+    fn window_rules() {
+        let mut state = monitors();
+        state.rules_mut().set_file_rules(&[
+            WindowRule { name: "chat".into(), class: "^discord$".into(), title: None, tag: 5, monitor: None, sticky: false, ignore: false },
+            WindowRule { name: "auth".into(), class: "polkit".into(), title: None, tag: 1, monitor: None, sticky: false, ignore: true },
+            WindowRule { name: "pip".into(), class: "firefox".into(), title: Some("^Picture-in-Picture$".into()), tag: 1, monitor: None, sticky: true, ignore: false },
+        ]);
+
+        let changes = state.window_opened(addr("d15c"), "discord", "General").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("d15c")), Some(4));
+        assert_eq!(changes.source, ChangeSource::Rule { class: "discord".into(), tag: 5 });
+        // a launch rule comes first
+        state.prepare_launch("discord", 2).unwrap();
+        state.window_opened(addr("d15d"), "discord", "General").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("d15d")), Some(1));
+
+        state.window_opened(addr("a17"), "polkit-gnome-authentication-agent-1", "").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("a17")), None);
+        assert!(state.window_flags(&addr("a17")).unmanaged);
+
+        // the title has to match too, and the sticky window stays in view
+        state.set_visible_tags(0b1).unwrap();
+        state.window_opened(addr("f1f0"), "firefox", "Mozilla Firefox").unwrap();
+        assert!(!state.window_flags(&addr("f1f0")).sticky);
+        state.window_opened(addr("919"), "firefox", "Picture-in-Picture").unwrap();
+        assert!(state.window_flags(&addr("919")).sticky);
+        let changes = state.set_visible_tags(0b100).unwrap();
+        assert_eq!(changes.changes.window_removed.iter().map(|w| w.addr).collect::<Vec<_>>(), vec![addr("f1f0")]);
+    }
+
+    #[test]
+    fn window_rules_on_other_monitors() {
+        let mut state = monitors();
+        let rule = |name: &str, class: &str, tag, monitor: &str| WindowRule {
+            name: name.into(), class: class.into(), title: None, tag, monitor: Some(monitor.into()), sticky: false, ignore: false,
+        };
+        state.rules_mut().set_file_rules(&[
+            rule("music", "spotify", 9, "DP-2"),
+            rule("web", "firefox", 1, "DP-2"),
+            rule("gone", "kitty", 3, "HDMI-A-1"),
+        ]);
+
+        // opened on the focused DP-1, sent to a hidden tag of DP-2
+        let changes = state.window_opened(addr("91a7"), "spotify", "").unwrap();
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("91a7")), Some(8));
+        assert_eq!(dispatches(&changes, &Fullscreen::default()), vec![
+            format!("dispatch movetoworkspacesilent {},address:0x91a7", hidden_workspace(DEFAULT_TAG_COUNT, 1, 9)),
+        ]);
+        // and to a visible one
+        let changes = state.window_opened(addr("f1f0"), "firefox", "").unwrap();
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("f1f0")), Some(0));
+        assert_eq!(dispatches(&changes, &Fullscreen::default()), vec!["dispatch movetoworkspacesilent 2,address:0xf1f0"]);
+        assert_eq!(state.active_monitor_index, 0);
+
+        // a monitor which isn't connected falls back to the focused one
+        state.window_opened(addr("c177"), "kitty", "").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("c177")), Some(2));
+    }
+
+    #[test]
+    fn activate_app() {
+        let mut state = monitors();
+        state.window_opened(addr("91a7"), "spotify", "").unwrap();
+        state.focus_window_changed(addr("91a7")).unwrap();
+        state.move_window(9, Some(addr("91a7")), false).unwrap();
+
+        // launched windows go to the app tag, other windows are unaffected
+        state.focus_monitor(1).unwrap();
+        state.prepare_launch("spotify", 4).unwrap();
+        assert_eq!(state.monitors[1].state.visible_tags(), 0b1000);
+        state.window_opened(addr("c177"), "kitty", "").unwrap();
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("c177")), Some(3));
+        let changes = state.window_opened(addr("91a72"), "spotify", "").unwrap();
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("91a72")), Some(3));
+        assert_eq!(changes.source, ChangeSource::Rule { class: "spotify".into(), tag: 4 });
+
+        // the rule is one-shot
+        state.set_visible_tags(1).unwrap();
+        state.window_opened(addr("91a73"), "spotify", "").unwrap();
+        assert_eq!(state.monitors[1].state.find_window_tag_index(&addr("91a73")), Some(0));
+
+        // the most recently used window wins
+        state.focus_window_changed(addr("91a72")).unwrap();
+        state.focus_monitor(0).unwrap();
+        state.focus_window_changed(addr("91a7")).unwrap();
+        assert_eq!(state.app_window("spotify"), Some(addr("91a7")));
+
+        state.focus_monitor(1).unwrap();
+        let changes = state.goto_window(&addr("91a7")).unwrap();
+        assert_eq!(changes.active_monitor_index, 0);
+        assert_eq!(changes.changes.focus, Some(addr("91a7")));
+        assert_eq!(state.active_monitor_index, 0);
+        assert_eq!(state.monitors[0].state.visible_tags(), 1<<8);
+
+        state.window_removed(addr("91a7")).unwrap();
+        assert_eq!(state.app_window("spotify"), Some(addr("91a72")));
+        assert_eq!(state.app_window("firefox"), None);
+    }
+
+    #[test]
+    fn stale_windows() {
+        let clock = ManualClock::default();
+        let mut state = monitors();
+        state.activity = Activity::new(Box::new(clock.clone()));
+
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("f1f0")).unwrap();
+        clock.advance(86400);
+        state.focus_window_changed(addr("f1f0")).unwrap();
+
+        let stale = state.stale_windows(86400);
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].0, addr("7e3"));
+        assert_eq!(stale[0].1.opened_at, 0);
+
+        let windows: Vec<_> = stale.into_iter().map(|(w, _)| w).collect();
+        let changes = state.move_windows(9, &windows);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(8));
+
+        state.window_removed(addr("7e3")).unwrap();
+        assert!(state.stale_windows(0).iter().all(|(w, _)| *w != addr("7e3")));
+    }
+
+    #[test]
+    fn mirror_tag() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("511de5")).unwrap();
+        state.focus_window_changed(addr("4073")).unwrap();
+        state.move_window(5, Some(addr("511de5")), false).unwrap();
+        state.set_visible_tags(0b10001).unwrap();
+
+        assert!(state.mirror(5, "DP-1").is_err());
+        let changes = state.mirror(5, "DP-2").unwrap();
+        assert_eq!(changes.active_monitor_index, 1);
+        assert_eq!(changes.changes.window_added.len(), 1);
+        assert_eq!(changes.changes.window_added[0].addr, addr("511de5"));
+        assert!(state.mirror(1, "DP-2").is_err());
+
+        // tag operations leave mirrored windows alone
+        let changes = state.toggle_tag(5, false).unwrap();
+        assert!(changes.changes.window_removed.is_empty());
+        assert!(state.move_window(3, Some(addr("511de5")), false).is_err());
+        state.move_window(3, Some(addr("4073")), false).unwrap();
+
+        // tag 5 got hidden meanwhile, so the window goes to its hidden workspace
+        let changes = state.unmirror().unwrap();
+        assert_eq!(changes.active_monitor_index, 0);
+        assert_eq!(changes.changes.window_removed.len(), 1);
+        assert_eq!(changes.changes.window_removed[0].tag, 5);
+        assert!(state.unmirror().is_err());
+    }
+
+    #[test]
+    fn flag_conflicts() {
+        use crate::flags::Flag;
+
+        // focus moved to the tablet before the launched window opened
+        let mut state = with_tablet();
+        state.prepare_launch("gimp", 4).unwrap();
+        state.focused_monitor_changed("HDMI-A-1").unwrap();
+        state.window_opened(addr("91a7"), "gimp", "").unwrap();
+        assert!(state.window_flags(&addr("91a7")).unmanaged);
+        let recorded = state.provenance().conflict(&addr("91a7")).unwrap();
+        assert_eq!((recorded.conflict.kept, recorded.conflict.dropped), (Flag::Unmanaged, Flag::Rule));
+
+        let mut state = monitors();
+        state.focus_window_changed(addr("511de5")).unwrap();
+        state.mirror(1, "DP-2").unwrap();
+        let err = state.overlay_add(Some(addr("511de5"))).unwrap_err();
+        assert_eq!(err.to_string(), "window:511de5 can't be an overlay: overlay conflicts with mirrored, which wins");
+        assert_eq!(state.provenance().conflicts().len(), 1);
+        assert!(state.window_flags(&addr("511de5")).mirrored);
+
+        state.window_removed(addr("511de5")).unwrap();
+        assert!(state.provenance().conflicts().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mirror_destination_removed() {
+        let mut state = monitors();
+        state.focus_window_changed(addr("511de5")).unwrap();
+        state.mirror(1, "DP-2").unwrap();
+
+        state.monitor_removed("DP-2").unwrap();
+        let changes = state.mirror_monitor_removed("DP-2").unwrap();
+        assert_eq!(changes.active_monitor_index, 0);
+        assert_eq!(changes.changes.window_added.len(), 1);
+        assert!(state.mirror.is_none());
+
+        state.mirror_monitor_removed("DP-2");
+        assert!(state.unmirror().is_err());
+    }
+
+    /// An XWayland IDE on tag 2 next to a native terminal on tag 1, both shown.
+    fn xwayland_state(policy: XwaylandPolicy) -> MonitorsState {
+        let mut state = monitors();
+        state.set_xwayland_policy(policy);
+        state.client_known(&client("0x1de", true));
+        state.client_known(&client("0x7e3", false));
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        state.move_window(2, Some(addr("1de")), true).unwrap();
+        state.set_visible_tags(0b11).unwrap();
+        state
+    }
+
+    #[test]
+    fn xwayland_normal() {
+        let mut state = xwayland_state(XwaylandPolicy::Normal);
+        assert!(state.is_xwayland(&addr("1de")));
+        assert!(!state.is_xwayland(&addr("7e3")));
+        assert!(!state.tracks_xwayland());
+
+        let changes = state.set_visible_tags(0b1).unwrap();
+        assert_eq!(changes.changes.window_removed.len(), 1);
+        state.move_window(3, Some(addr("1de")), false).unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(2));
+    }
+
+    #[test]
+    fn xwayland_sticky() {
+        let mut state = xwayland_state(XwaylandPolicy::Sticky);
+
+        // hiding its tag leaves the window on the visible workspace, and showing it again doesn't move it
+        let changes = state.set_visible_tags(0b1).unwrap();
+        assert!(changes.changes.window_removed.is_empty());
+        let changes = state.restore_prev_tags().unwrap();
+        assert!(changes.changes.window_added.is_empty());
+
+        let changes = state.toggle_tag(1, false).unwrap();
+        assert_eq!(changes.changes.window_removed.len(), 1);
+        assert_eq!(changes.changes.window_removed[0].addr, addr("7e3"));
+
+        // a window only found to be XWayland after it was placed on a hidden tag comes into view
+        state.client_known(&client("0x9a3e", false));
+        state.focus_window_changed(addr("9a3e")).unwrap();
+        state.move_window(5, Some(addr("9a3e")), false).unwrap();
+        let changes = state.clients_refreshed(&[client("0x9a3e", true), client("0x7e3", false)]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].changes.window_added[0].addr, addr("9a3e"));
+        assert!(state.clients_refreshed(&[client("0x9a3e", true)]).is_empty());
+        assert_eq!(state.snapshot().xwayland, vec!["1de", "9a3e"]);
+
+        state.window_removed(addr("9a3e")).unwrap();
+        assert!(!state.is_xwayland(&addr("9a3e")));
+    }
+
+    #[test]
+    fn xwayland_pin_tag() {
+        let mut state = xwayland_state(XwaylandPolicy::PinTag);
+
+        let err = state.move_window(3, Some(addr("1de")), false).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(state.move_windows(3, &[addr("1de"), addr("7e3")]).len(), 1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(1));
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(2));
+
+        // tag changes still hide it
+        let changes = state.set_visible_tags(0b100).unwrap();
+        assert_eq!(changes.changes.window_removed.len(), 1);
+
+        state.move_window(3, Some(addr("1de")), true).unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(2));
+    }
+
+    #[test]
+    fn dragged_windows() {
+        let mut state = with_tablet();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        let dp2 = state.resolve_monitor("DP-2").unwrap();
+        state.show_on(dp2, 4, false).unwrap();
+
+        // its own monitor, hidden and special workspaces change nothing
+        for workspace in [1, 104, 200, -98] {
+            state.window_moved(addr("1de"), workspace).unwrap();
+        }
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(0));
+
+        // the tag active on DP-2, not the one active on DP-1
+        state.window_moved(addr("1de"), dp2 as i64 + 1).unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), None);
+        assert_eq!(state.monitors[dp2].state.find_window_tag_index(&addr("1de")), Some(3));
+
+        // off every tag on the tablet, and back on the active tag from there
+        state.window_moved(addr("1de"), 2).unwrap();
+        assert!(state.monitors.iter().all(|m| m.state.find_window_tag_index(&addr("1de")).is_none()));
+        state.window_moved(addr("1de"), 1).unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(0));
+
+        // overlay windows stay in the overlay
+        state.overlay_add(Some(addr("7e3"))).unwrap();
+        state.window_moved(addr("7e3"), dp2 as i64 + 1).unwrap();
+        assert!(state.snapshot().monitors[0].overlay.is_empty());
+        assert_eq!(state.snapshot().monitors[dp2].overlay, vec!["7e3"]);
+    }
+
+    #[test]
+    fn overlay_windows() {
+        let mut state = monitors();
+        let fullscreen = Fullscreen::default();
+        for window in ["0x7e3", "0x1de", "0xa99"] {
+            state.client_known(&client(window, false));
+        }
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        state.move_window(2, Some(addr("1de")), false).unwrap();
+        state.focus_window_changed(addr("a99")).unwrap();
+
+        // the focused window is already on the visible workspace
+        let changes = state.overlay_add(None).unwrap();
+        assert!(dispatches(&changes, &fullscreen).is_empty());
+        // one from a hidden tag is brought there
+        let changes = state.overlay_add(Some(addr("1de"))).unwrap();
+        assert_eq!(dispatches(&changes, &fullscreen), vec!["dispatch movetoworkspacesilent 1,address:0x1de"]);
+        assert!(state.overlay_add(Some(addr("1de"))).is_err());
+        assert_eq!(state.snapshot().monitors[0].overlay, vec!["a99", "1de"]);
+        assert!(state.snapshot().monitors[0].tags.values().flatten().all(|w| w == "7e3"));
+
+        // tag switches leave them alone
+        for tags in [0b10, 0b110, 0b1] {
+            let args = dispatches(&state.set_visible_tags(tags).unwrap(), &fullscreen);
+            assert!(!args.iter().any(|a| a.contains("0xa99") || a.contains("0x1de")), "{:?}", args);
+        }
+
+        // and they never get focus from us, nor become the active window when focused
+        state.focus_window_changed(addr("a99")).unwrap();
+        assert_eq!(state.monitors[0].state.active_window(), None);
+        assert_eq!(state.set_visible_tags(0b10).unwrap().changes.focus, None);
+        assert_eq!(state.set_visible_tags(0b1).unwrap().changes.focus, Some(addr("7e3")));
+
+        // moving the focused overlay window puts it on the tag
+        state.focus_window_changed(addr("a99")).unwrap();
+        let changes = state.move_window(3, None, false).unwrap();
+        assert_eq!(changes.changes.window_removed.iter().map(|w| w.addr).collect::<Vec<_>>(), vec![addr("a99")]);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("a99")), Some(2));
+        assert_eq!(state.snapshot().monitors[0].overlay, vec!["1de"]);
+
+        state.overlay_remove(Some(addr("1de"))).unwrap();
+        assert!(state.overlay_remove(Some(addr("1de"))).is_err());
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(0));
+    }
+
+    /// Windows 7e3 on tag 1, 1de on tag 2 and 5a on tag 5, with tag 3 visible after tag 1.
+    fn emptying_tags() -> MonitorsState {
+        let mut state = monitors();
+        for window in ["0x7e3", "0x1de", "0x5a", "0x3c"] {
+            state.client_known(&client(window, false));
+        }
+        state.focus_window_changed(addr("7e3")).unwrap();
+        for (window, tag) in [("1de", 2), ("5a", 5), ("3c", 3)] {
+            state.focus_window_changed(addr(window)).unwrap();
+            state.move_window(tag, Some(addr(window)), false).unwrap();
+        }
+        state.set_visible_tags(0b1).unwrap();
+        state.set_visible_tags(0b100).unwrap();
+        state
+    }
+
+    #[test]
+    fn cycle_skips_hidden_tags() {
+        let mut state = monitors();
+        state.window_opened(addr("a"), "kitty", "").unwrap();
+        for (window, tag) in [("b", 3), ("c", 25), ("d", 30)] {
+            state.window_opened(addr(window), "kitty", "").unwrap();
+            state.focus_window_changed(addr(window)).unwrap();
+            state.move_window(tag, Some(addr(window)), false).unwrap();
+        }
+        state.set_hidden_tags(1<<24 | 1<<29);
+
+        state.cycle_tag(true).unwrap();
+        assert_eq!(state.visible_tags(), 0b100);
+        // wraps around past the hidden 25 and 30
+        state.cycle_tag(true).unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        state.cycle_tag(false).unwrap();
+        assert_eq!(state.visible_tags(), 0b100);
+
+        // a hidden tag shown directly cycles on to the others
+        state.set_visible_tags(1<<24).unwrap();
+        state.cycle_tag(true).unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!(state.first_empty_tag().unwrap(), 2);
+
+        // with every other tag hidden, cycling stays where it is
+        state.set_hidden_tags(!0b1);
+        let changes = state.cycle_tag(true).unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!((changes.changes.shown_tags, changes.changes.hidden_tags), (0, 0));
+    }
+
+    #[test]
+    fn focus_last_across_tags() {
+        let mut state = emptying_tags();
+        state.set_visible_tags(0b1).unwrap();
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.user_focused(addr("1de"));
+        state.user_focused(addr("7e3"));
+
+        // 1de is on the hidden tag 2, which is shown with the view saved for restore
+        let changes = state.focus_last().unwrap();
+        assert_eq!(changes.changes.focus, Some(addr("1de")));
+        assert_eq!(state.visible_tags(), 0b10);
+        assert_eq!(state.monitors[0].state.prev_tags(), 0b1);
+        state.focus_window_changed(addr("1de")).unwrap();
+
+        // and back again
+        let changes = state.focus_last().unwrap();
+        assert_eq!(changes.changes.focus, Some(addr("7e3")));
+        assert_eq!(state.visible_tags(), 0b1);
+    }
+
+    #[test]
+    fn focus_last_on_same_tag() {
+        let mut state = monitors();
+        for window in ["aa", "bb"] {
+            state.window_opened(addr(window), "kitty", "").unwrap();
+            state.focus_window_changed(addr(window)).unwrap();
+            state.user_focused(addr(window));
+        }
+        let changes = state.focus_last().unwrap();
+        assert_eq!(changes.changes.focus, Some(addr("aa")));
+        assert_eq!((changes.changes.shown_tags, changes.changes.hidden_tags), (0, 0));
+        assert_eq!(state.visible_tags(), 0b1);
+    }
+
+    #[test]
+    fn focus_last_skips_closed_windows() {
+        let mut state = monitors();
+        for window in ["aa", "bb", "cc"] {
+            state.window_opened(addr(window), "kitty", "").unwrap();
+            state.focus_window_changed(addr(window)).unwrap();
+            state.user_focused(addr(window));
+        }
+        state.window_closed(addr("bb"), OnTagEmptied::Stay).unwrap();
+        assert_eq!(state.focus_last().unwrap().changes.focus, Some(addr("aa")));
+
+        // windows on another monitor don't count either
+        let mut state = monitors();
+        state.user_focused(addr("dd"));
+        assert!(state.focus_last().is_err());
+    }
+
+    #[test]
+    fn close_untracked_windows() {
+        let mut state = monitors();
+        // a tooltip closing before anything announced it
+        assert_eq!(state.untracked(&addr("7001")), Some(Untracked::NeverSeen));
+        assert!(state.window_closed(addr("7001"), OnTagEmptied::Stay).unwrap().is_none());
+
+        state.window_seen(addr("aa"));
+        state.window_opened(addr("aa"), "kitty", "").unwrap();
+        assert_eq!(state.untracked(&addr("aa")), None);
+        // dropped from the tags without closing, so a later closewindow means we lost it
+        state.window_removed(addr("aa")).unwrap();
+        assert_eq!(state.untracked(&addr("aa")), Some(Untracked::Lost));
+        assert!(state.window_closed(addr("aa"), OnTagEmptied::Stay).unwrap().is_none());
+
+        // tracked windows close as usual
+        state.window_opened(addr("bb"), "kitty", "").unwrap();
+        assert!(state.window_closed(addr("bb"), OnTagEmptied::Stay).is_ok());
+        assert!(state.snapshot().monitors[0].tags.is_empty());
+    }
+
+    #[test]
+    fn tag_emptied_policies() {
+        let mut state = emptying_tags();
+        assert!(state.window_closed(addr("3c"), OnTagEmptied::Stay).unwrap().is_none());
+        assert_eq!(state.visible_tags(), 0b100);
+
+        let mut state = emptying_tags();
+        let changes = state.window_closed(addr("3c"), OnTagEmptied::RestorePrev).unwrap().unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!(dispatches(&changes, &Fullscreen::default()), vec![
+            "dispatch movetoworkspacesilent 1,address:0x7e3",
+            "dispatch focuswindow address:0x7e3",
+        ]);
+
+        // tags 2 and 5 have windows, and 2 is closer
+        let mut state = emptying_tags();
+        let changes = state.window_closed(addr("3c"), OnTagEmptied::NearestOccupied).unwrap().unwrap();
+        assert_eq!(state.visible_tags(), 0b10);
+        assert_eq!(changes.changes.focus, Some(addr("1de")));
+
+        // a window closing on a hidden tag leaves the view alone
+        let mut state = emptying_tags();
+        assert!(state.window_closed(addr("5a"), OnTagEmptied::NearestOccupied).unwrap().is_none());
+        assert_eq!(state.visible_tags(), 0b100);
+    }
+
+    #[test]
+    fn tag_emptied_with_other_visible_tag() {
+        for policy in [OnTagEmptied::RestorePrev, OnTagEmptied::NearestOccupied] {
+            let mut state = emptying_tags();
+            state.set_visible_tags(0b10100).unwrap();
+            assert!(state.window_closed(addr("3c"), policy).unwrap().is_none());
+            assert_eq!(state.visible_tags(), 0b10100);
+
+            // the last visible window closing does trigger it
+            let changes = state.window_closed(addr("5a"), policy).unwrap();
+            assert!(changes.is_some(), "{:?}", policy);
+            assert_ne!(state.visible_tags(), 0b10100);
+        }
+    }
+
+    #[test]
+    fn dim_mode() {
+        let mut state = monitors();
+        state.client_known(&client("0x7e3", false));
+        state.client_known(&client("0x1de", false));
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        state.move_window(2, Some(addr("1de")), false).unwrap();
+
+        // the hidden tag's window comes onto the visible workspace, dimmed
+        let changes = state.set_view_mode(ViewMode::Dim).unwrap();
+        assert_eq!(state.view_mode(), ViewMode::Dim);
+        assert_eq!(dispatches(&changes, &Fullscreen::default()), vec![
+            "dispatch movetoworkspacesilent 1,address:0x1de",
+            "dispatch setprop address:0x1de alpha 0.6",
+        ]);
+        assert!(dispatches(&state.set_view_mode(ViewMode::Dim).unwrap(), &Fullscreen::default()).is_empty());
+
+        // switching tags only changes opacity
+        for tags in [0b10, 0b11, 0b1] {
+            let args = dispatches(&state.set_visible_tags(tags).unwrap(), &Fullscreen::default());
+            assert!(!args.iter().any(|a| a.contains("movetoworkspace")), "{:?}", args);
+        }
+        let args = dispatches(&state.move_window(3, Some(addr("7e3")), false).unwrap(), &Fullscreen::default());
+        assert_eq!(args, vec!["dispatch setprop address:0x7e3 alpha 0.6"]);
+        assert_eq!(state.dimmed_windows.len(), 2);
+
+        // leaving undims everything and moves hidden tags away again
+        let mut args = dispatches(&state.set_view_mode(ViewMode::Normal).unwrap(), &Fullscreen::default());
+        args.sort();
+        assert_eq!(args, vec![
+            "dispatch movetoworkspacesilent 102,address:0x1de",
+            "dispatch movetoworkspacesilent 103,address:0x7e3",
+            "dispatch setprop address:0x1de alpha unset",
+            "dispatch setprop address:0x7e3 alpha unset",
+        ]);
+        assert!(state.dimmed_windows.is_empty());
+        assert_eq!(state.view_mode(), ViewMode::Normal);
+    }
+
+    #[test]
+    fn inherit_tag_from_parent() {
+        // terminal pid 100 -> shell 200 -> app 300
+        let mut state = monitors();
+        state.lineage = Lineage::new(Box::new(FakeAncestry(HashMap::from([(200, 100), (300, 200)]))));
+        state.set_inherit_tag_from_parent(true);
+        let terminal = ClientInfo { pid: 100, ..client("0x7e3", false) };
+        state.client_known(&terminal);
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.move_window(3, Some(addr("7e3")), true).unwrap();
+        assert!(state.inherits_tags());
+
+        // the app appears on the active tag, and follows the terminal to tag 3 once its pid is known
+        state.window_opened(addr("a99"), "app", "").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("a99")), Some(0));
+        let app = ClientInfo { pid: 300, ..client("0xa99", false) };
+        let changes = state.clients_refreshed(&[terminal.clone(), app.clone()]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].changes.window_removed.len(), 1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("a99")), Some(2));
+
+        // later queries leave it alone
+        state.move_window(1, Some(addr("a99")), true).unwrap();
+        assert!(state.clients_refreshed(&[terminal.clone(), app]).is_empty());
+
+        state.set_inherit_tag_from_parent(false);
+        state.window_opened(addr("b0b"), "app", "").unwrap();
+        let app = ClientInfo { pid: 300, ..client("0xb0b", false) };
+        assert!(state.clients_refreshed(&[terminal, app]).is_empty());
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("b0b")), Some(0));
+    }
+
+    #[test]
+    fn badges() {
+        let mut state = monitors();
+        state.set_new_window_tag(NewWindowTag::Tag(4));
+        state.window_opened(addr("3a11"), "thunderbird", "").unwrap();
+        state.focus_monitor(1).unwrap();
+        state.window_opened(addr("c4a7"), "slack", "").unwrap();
+
+        let badges = state.badges();
+        assert_eq!(badges["DP-1"], [(4, 1)].into());
+        assert_eq!(badges["DP-2"], [(4, 1)].into());
+        assert_eq!(state.snapshot().monitors[1].new_since_seen, [(4, 1)].into());
+
+        state.set_visible_tags(0b1000).unwrap();
+        state.window_urgent(&addr("3a11")).unwrap();
+        assert_eq!(state.badges()["DP-1"], [(4, 2)].into());
+        assert!(state.badges()["DP-2"].is_empty());
+        assert!(state.window_urgent(&addr("90e")).is_err());
+    }
+
+    #[test]
+    fn focus_settled_on_closed_window() {
+        let mut state = monitors();
+
+        state.adopt_window(addr("7e3")).unwrap();
+        state.window_removed(addr("7e3")).unwrap();
+
+        assert!(state.focus_settled(addr("7e3")).is_err());
+    }
+
+    #[test]
+    fn provisional_windows() {
+        let mut state = monitors();
+        let fullscreen = Fullscreen::default();
+        state.client_known(&client("0x7e3", false));
+        state.focus_window_changed(addr("7e3")).unwrap();
+        // the focus change comes before the window's openwindow
+        state.focus_window_changed(addr("1de")).unwrap();
+        assert!(state.is_provisional(&addr("1de")));
+        assert!(!state.is_provisional(&addr("7e3")));
+
+        // its moves are held back, and it doesn't get focus from us
+        let args = dispatches(&state.move_window(2, Some(addr("1de")), false).unwrap(), &fullscreen);
+        assert!(!args.iter().any(|a| a.contains("0x1de")), "{:?}", args);
+        let args = dispatches(&state.set_visible_tags(0b10).unwrap(), &fullscreen);
+        assert_eq!(args, vec!["dispatch movetoworkspacesilent 101,address:0x7e3"]);
+        state.set_visible_tags(0b1).unwrap();
+
+        // once confirmed it goes where its tag is, which the openwindow doesn't change
+        let changes = state.window_opened(addr("1de"), "kitty", "").unwrap();
+        assert_eq!(dispatches(&changes, &fullscreen), vec!["dispatch movetoworkspacesilent 102,address:0x1de"]);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("1de")), Some(1));
+        assert!(state.window_confirmed(addr("1de")).is_none());
+        let args = dispatches(&state.set_visible_tags(0b10).unwrap(), &fullscreen);
+        assert!(args.contains(&"dispatch movetoworkspacesilent 1,address:0x1de".to_string()), "{:?}", args);
+
+        // one which was never moved has nothing due
+        state.focus_window_changed(addr("a99")).unwrap();
+        assert!(state.clients_refreshed(&[client("0xa99", false)]).is_empty());
+        assert!(!state.is_provisional(&addr("a99")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn provisional_windows_expire() {
+        let mut state = monitors();
+        // a stale address, then a window whose openwindow got lost
+        state.focus_window_changed(addr("5a1e")).unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+
+        // too early to tell
+        assert!(state.clients_refreshed(&[]).is_empty());
+        assert!(state.is_provisional(&addr("5a1e")));
+
+        tokio::time::advance(PROVISIONAL_TIMEOUT).await;
+        state.clients_refreshed(&[client("0x1de", false)]);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("5a1e")), None);
+        assert!(!state.is_provisional(&addr("5a1e")));
+        assert_eq!(state.monitors[0].state.active_window(), Some(addr("1de")));
+        assert!(!state.is_provisional(&addr("1de")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn autostart() {
+        let config = Config::parse(r#"{"tags": {"1": {"autostart": ["foot"]}, "3": {"autostart": ["foot", "firefox"]}}}"#).unwrap();
+        let mut state = monitors();
+        // tag 1 is shown empty on both monitors, but runs its commands once
+        assert_eq!(state.autostart_due(&config), vec![(1, "foot".to_string())]);
+        assert!(state.autostart_due(&config).is_empty());
+        state.window_opened(addr("f00"), "foot", "").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("f00")), Some(0));
+
+        state.set_visible_tags(0b100).unwrap();
+        assert_eq!(state.autostart_due(&config), vec![(3, "foot".to_string()), (3, "firefox".to_string())]);
+        state.set_visible_tags(0b1).unwrap();
+        state.window_opened(addr("f01"), "foot", "").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("f01")), Some(2));
+
+        // firefox never came up
+        tokio::time::advance(AUTOSTART_TIMEOUT).await;
+        state.autostart_expired();
+        let failures: Vec<_> = state.provenance().autostart_failures().map(|f| (f.tag, f.command.as_str(), f.reason.as_str())).collect();
+        assert_eq!(failures, vec![(3, "firefox", "no window within 30s")]);
+        state.window_opened(addr("f1f0"), "firefox", "").unwrap();
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("f1f0")), Some(0));
+
+        // a tag shown with windows on it doesn't start anything, until it's shown empty again after a reset
+        state.autostart_reset(Some(3));
+        state.move_window(3, Some(addr("f1f0")), false).unwrap();
+        state.set_visible_tags(0b100).unwrap();
+        assert!(state.autostart_due(&config).is_empty());
+        // tag 1 is still empty on DP-2
+        state.autostart_reset(None);
+        assert_eq!(state.autostart_due(&config), vec![(1, "foot".to_string())]);
+        state.move_window(4, Some(addr("f1f0")), false).unwrap();
+        state.move_window(4, Some(addr("f01")), false).unwrap();
+        state.set_visible_tags(0b100).unwrap();
+        assert_eq!(state.autostart_due(&config).len(), 2);
+    }
+
+    #[test]
+    fn rollback() {
+        let mut state = monitors();
+        let fullscreen = Fullscreen::default();
+        for w in ["0x7e3", "0x1de", "0xa99"] {
+            state.client_known(&client(w, false));
+        }
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        state.window_opened(addr("1de"), "kitty", "").unwrap();
+        state.window_opened(addr("a99"), "kitty", "").unwrap();
+        let checkpoint = state.checkpoint();
+
+        state.move_window(2, Some(addr("7e3")), false).unwrap();
+        state.set_visible_tags(0b10).unwrap();
+        state.window_opened(addr("f1f0"), "kitty", "").unwrap();
+        state.window_closed(addr("a99"), OnTagEmptied::Stay).unwrap();
+
+        let changes = state.rollback(checkpoint).unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("7e3")), Some(0));
+        // opened and closed since stays so
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("f1f0")), Some(1));
+        assert_eq!(state.monitors[0].state.find_window_tag_index(&addr("a99")), None);
+        let args: Vec<String> = changes.iter().flat_map(|c| dispatches(c, &fullscreen)).collect();
+        assert!(args.contains(&"dispatch movetoworkspacesilent 1,address:0x7e3".to_string()), "{:?}", args);
+        assert!(args.contains(&"dispatch movetoworkspacesilent 1,address:0x1de".to_string()), "{:?}", args);
+        assert!(args.contains(&"dispatch movetoworkspacesilent 102,address:0xf1f0".to_string()), "{:?}", args);
+        assert!(!args.iter().any(|a| a.contains("0xa99")), "{:?}", args);
+
+        // not onto other monitors
+        let checkpoint = state.checkpoint();
+        state.monitor_removed("DP-2").unwrap();
+        assert!(state.rollback(checkpoint).is_err());
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn provisional_windows_arent_saved() {
+        use std::collections::BTreeMap;
+
+        use crate::summary::Summary;
+
+        let path = std::env::temp_dir().join(format!("hyprtag-provisional-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let saved_windows = || {
+            let report = Summary::load(path.clone()).report(0, &BTreeMap::new(), &BTreeMap::new());
+            report.tags.iter().map(|t| (t.tag, t.windows)).collect::<Vec<_>>()
+        };
+
+        let mut state = monitors();
+        state.set_summary(Summary::load(path.clone()));
+        state.client_known(&client("0x7e3", false));
+        state.focus_window_changed(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("1de")).unwrap();
+        state.record_placements();
+        assert_eq!(saved_windows(), vec![(1, 1)]);
+
+        state.window_opened(addr("1de"), "kitty", "").unwrap();
+        state.record_placements();
+        assert_eq!(saved_windows(), vec![(1, 2)]);
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,150 @@
+use std::{fs::{File, OpenOptions}, io::Write, path::{Path, PathBuf}};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Starts the last line of a saved file, which has the length and checksum of what comes before it.
+const FOOTER: &str = "# hyprtag";
+
+/// Which copy of a saved file was loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loaded {
+    Primary,
+    /// The one before the last save, used when the primary is missing, truncated or unreadable.
+    Backup,
+}
+
+/// Saves `value` to `path` as JSON, so a crash or power loss at any point leaves a complete copy to load. It's
+/// written to a temporary file in the same directory, synced and renamed over the primary, whose last good version
+/// becomes the backup. Saves hold an exclusive lock on `<path>.lock`, so writers from other processes don't
+/// interleave.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    let lock = OpenOptions::new().create(true).truncate(false).write(true).open(sibling(path, "lock"))?;
+    lock.lock()?;
+
+    let json = serde_json::to_string(value)?;
+    let tmp = sibling(path, &format!("tmp.{}", std::process::id()));
+    let mut file = File::create(&tmp)?;
+    file.write_all(json.as_bytes())?;
+    file.write_all(footer(&json).as_bytes())?;
+    file.sync_all()?;
+
+    // a damaged primary isn't worth keeping, the backup stays until a good one replaces it
+    if intact(path) {
+        std::fs::rename(path, sibling(path, "bak"))?;
+    }
+    std::fs::rename(&tmp, path)?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// The value saved at `path`, falling back to the backup when the primary doesn't check out. None when neither
+/// does. Files saved without a footer load when they parse.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Option<(T, Loaded)> {
+    [(path.to_path_buf(), Loaded::Primary), (sibling(path, "bak"), Loaded::Backup)].into_iter().find_map(|(path, loaded)| {
+        let s = std::fs::read_to_string(&path).ok()?;
+        let parsed = verified(&s).ok_or_else(|| anyhow::anyhow!("length or checksum mismatch"))
+            .and_then(|json| Ok(serde_json::from_str(json)?));
+        match parsed {
+            Ok(value) => Some((value, loaded)),
+            Err(err) => {
+                tracing::warn!(%err, path = %path.display(), "ignoring damaged file");
+                None
+            },
+        }
+    })
+}
+
+/// Whether the file checks out and is JSON.
+fn intact(path: &Path) -> bool {
+    std::fs::read_to_string(path).ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(verified(&s)?).ok())
+        .is_some()
+}
+
+/// `path` with `ext` appended, like `summary.json.bak`.
+fn sibling(path: &Path, ext: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(".");
+    s.push(ext);
+    PathBuf::from(s)
+}
+
+fn footer(json: &str) -> String {
+    format!("\n{} len={} fnv={:016x}\n", FOOTER, json.len(), fnv1a(json.as_bytes()))
+}
+
+/// The JSON in the file when its footer matches it. Without a footer, the whole file.
+fn verified(s: &str) -> Option<&str> {
+    let json = match s.trim_end_matches('\n').rsplit_once('\n') {
+        Some((json, last)) if last.starts_with(FOOTER) => json,
+        _ if !s.contains(FOOTER) => return Some(s),
+        _ => return None,
+    };
+    (footer(json) == s[json.len()..]).then_some(json)
+}
+
+/// 64-bit FNV-1a, enough to tell a torn write from a complete one.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{load, save, sibling, Loaded};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hyprtag-persist-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("state.json")
+    }
+
+    #[test]
+    fn falls_back_to_backup() {
+        let path = temp_path("backup");
+        assert_eq!(load::<Vec<u8>>(&path), None);
+
+        save(&path, &vec![1u8]).unwrap();
+        assert_eq!(load(&path), Some((vec![1u8], Loaded::Primary)));
+        save(&path, &vec![1u8, 2]).unwrap();
+
+        // cut short by a crash mid-write
+        let full = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, &full[..full.len() - 4]).unwrap();
+        assert_eq!(load(&path), Some((vec![1u8], Loaded::Backup)));
+        // or whose checksum is off
+        std::fs::write(&path, full.replace("1,2", "1,3")).unwrap();
+        assert_eq!(load(&path), Some((vec![1u8], Loaded::Backup)));
+
+        // the next save repairs the primary, and the good backup stays
+        save(&path, &vec![4u8]).unwrap();
+        assert_eq!(load(&path), Some((vec![4u8], Loaded::Primary)));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(load(&path), Some((vec![1u8], Loaded::Backup)));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn loads_files_without_footer() {
+        let path = temp_path("legacy");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "[5,6]").unwrap();
+        assert_eq!(load(&path), Some((vec![5u8, 6], Loaded::Primary)));
+        std::fs::write(&path, "[5,").unwrap();
+        assert_eq!(load::<Vec<u8>>(&path), None);
+        // and leave no temporary files behind
+        save(&path, &vec![7u8]).unwrap();
+        assert!(sibling(&path, "lock").exists());
+        let mut names: Vec<String> = std::fs::read_dir(path.parent().unwrap()).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["state.json", "state.json.lock"]);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use std::{sync::{Mutex, OnceLock}, time::{Duration, Instant}};
+
+use anyhow::bail;
+
+use crate::ctrl::Ctrl;
+
+/// How long `prefix N` waits for the command completing the tag.
+pub const PREFIX_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// First digit of a two digit tag, so `prefix 1` then `show 3` shows tag 13.
+/// Shared by all connections, as each keybind runs its own hyprtagctl.
+#[derive(Debug, Default)]
+pub struct Prefix {
+    pending: Option<(u8, Instant)>,
+}
+
+impl Prefix {
+    /// Arms the digit, failing when no tag of the `tag_count` starts with it.
+    pub fn arm(&mut self, digit: u8, tag_count: u8, now: Instant) -> anyhow::Result<()> {
+        if digit == 0 || digit > tag_count / 10 {
+            bail!("invalid prefix: {}, tags only go up to {}", digit, tag_count);
+        }
+        self.pending = Some((digit, now + PREFIX_TIMEOUT));
+        Ok(())
+    }
+
+    pub fn abort(&mut self) {
+        self.pending = None;
+    }
+
+    /// The armed digit, unless it expired.
+    pub fn pending(&self, now: Instant) -> Option<u8> {
+        self.pending.filter(|(_, expires)| now < *expires).map(|(digit, _)| digit)
+    }
+
+    /// Completes the tag of a tag-taking command with the pending digit, which is used up.
+    /// Other commands leave it pending. The completed tag has to be one of the `tag_count`.
+    pub fn apply(&mut self, msg: &mut Ctrl, tag_count: u8, now: Instant) -> anyhow::Result<()> {
+        match msg {
+            Ctrl::ShowTag(tag)
+            | Ctrl::ToggleTag(tag, _)
+            | Ctrl::MoveToTag(tag, _, _)
+            | Ctrl::MoveShow(tag)
+            | Ctrl::Reorder(tag, _)
+            | Ctrl::Mirror(tag, _)
+            | Ctrl::Place(_, tag, _)
+            | Ctrl::ShowOn(_, tag, _)
+            | Ctrl::Throw(_, tag, _)
+            | Ctrl::Stale(_, Some(tag), _) => *tag = self.take_tag(*tag, tag_count, now)?,
+            Ctrl::Target(_, msg) => self.apply(msg, tag_count, now)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn take_tag(&mut self, tag: u8, tag_count: u8, now: Instant) -> anyhow::Result<u8> {
+        let digit = match self.pending.take().filter(|(_, expires)| now < *expires) {
+            Some((digit, _)) => digit,
+            None => return Ok(tag),
+        };
+        if tag > 9 {
+            bail!("tag {} can't follow prefix {}", tag, digit);
+        }
+        match digit * 10 + tag {
+            tag if tag <= tag_count => Ok(tag),
+            tag => bail!("no such tag: {}, tags go up to {}", tag, tag_count),
+        }
+    }
+}
+
+pub fn pending_prefix() -> &'static Mutex<Prefix> {
+    static PENDING_PREFIX: OnceLock<Mutex<Prefix>> = OnceLock::new();
+    PENDING_PREFIX.get_or_init(|| Mutex::new(Prefix::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::ctrl::Ctrl;
+
+    use super::{Prefix, PREFIX_TIMEOUT};
+
+    fn show(prefix: &mut Prefix, tag: u8, now: Instant) -> anyhow::Result<u8> {
+        let mut msg = Ctrl::ShowTag(tag);
+        prefix.apply(&mut msg, 32, now)?;
+        match msg {
+            Ctrl::ShowTag(tag) => Ok(tag),
+            msg => panic!("unexpected {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn two_digit_tags() {
+        let now = Instant::now();
+        let mut prefix = Prefix::default();
+        assert_eq!(show(&mut prefix, 3, now).unwrap(), 3);
+
+        prefix.arm(1, 32, now).unwrap();
+        assert_eq!(prefix.pending(now), Some(1));
+        // commands without a tag leave the prefix pending
+        let mut msg = Ctrl::RestorePrevTags;
+        prefix.apply(&mut msg, 32, now).unwrap();
+        assert_eq!(prefix.pending(now), Some(1));
+
+        let mut msg = Ctrl::MoveToTag(0, None, false);
+        prefix.apply(&mut msg, 32, now).unwrap();
+        assert!(matches!(msg, Ctrl::MoveToTag(10, None, false)));
+        // used up
+        assert_eq!(prefix.pending(now), None);
+        assert_eq!(show(&mut prefix, 3, now).unwrap(), 3);
+
+        prefix.arm(3, 32, now).unwrap();
+        assert!(show(&mut prefix, 3, now).is_err());
+        prefix.arm(2, 32, now).unwrap();
+        assert!(show(&mut prefix, 12, now).is_err());
+        assert!(prefix.arm(4, 32, now).is_err());
+        assert!(prefix.arm(0, 32, now).is_err());
+        // with more tags configured
+        assert!(prefix.arm(4, 40, now).is_ok());
+    }
+
+    #[test]
+    fn prefix_expiry_and_abort() {
+        let now = Instant::now();
+        let mut prefix = Prefix::default();
+
+        prefix.arm(1, 32, now).unwrap();
+        let later = now + PREFIX_TIMEOUT - Duration::from_millis(1);
+        assert_eq!(show(&mut prefix, 3, later).unwrap(), 13);
+
+        prefix.arm(1, 32, now).unwrap();
+        let expired = now + PREFIX_TIMEOUT;
+        assert_eq!(prefix.pending(expired), None);
+        assert_eq!(show(&mut prefix, 3, expired).unwrap(), 3);
+
+        prefix.arm(2, 32, now).unwrap();
+        prefix.abort();
+        assert_eq!(show(&mut prefix, 3, now).unwrap(), 3);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{hyprctl::hyprctl_with_cmd, monitor::{hidden_workspace_in, set_hidden_base, visible_workspace, DEFAULT_HIDDEN_BASE}};
+
+/// Base of the compact numbering, right after the visible workspaces of up to this many monitors.
+pub const COMPACT_HIDDEN_BASE: i64 = 10;
+
+/// How the hidden workspaces are numbered, as the startup probe found Hyprland to take them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Numbering {
+    /// From `DEFAULT_HIDDEN_BASE`, which Hyprland accepts.
+    Default,
+    /// From `COMPACT_HIDDEN_BASE`, as Hyprland rejects the default range but not this one.
+    Compact,
+    /// Hyprland rejects both, the default range is kept and windows on its rejected tags don't hide.
+    Unusable,
+}
+
+impl Numbering {
+    pub fn base(self) -> i64 {
+        match self {
+            Numbering::Compact => COMPACT_HIDDEN_BASE,
+            Numbering::Default | Numbering::Unusable => DEFAULT_HIDDEN_BASE,
+        }
+    }
+}
+
+/// The last hidden workspace of `monitor_count` monitors with `tag_count` tags each, numbered from `base`.
+fn top_workspace(base: i64, tag_count: u8, monitor_count: usize) -> i64 {
+    hidden_workspace_in(base, tag_count, monitor_count.max(1) - 1, tag_count)
+}
+
+/// Whether the compact numbering leaves room for the visible workspaces of the monitors.
+fn compact_fits(monitor_count: usize) -> bool {
+    visible_workspace(monitor_count.max(1) - 1) <= COMPACT_HIDDEN_BASE
+}
+
+/// Whether Hyprland accepts the workspace, tried by switching to it and back, which leaves it to be destroyed
+/// as the empty workspace it is. Switching back is a dispatch of its own, as some Hyprland versions carry on with
+/// a batch past a rejected dispatch and would move the user away from the workspace they are on.
+async fn accepts(workspace: i64) -> bool {
+    if let Err(err) = hyprctl_with_cmd(vec![format!("dispatch focusworkspaceoncurrentmonitor {}", workspace)]).await {
+        tracing::warn!(%err, workspace, "Hyprland rejects the workspace");
+        return false;
+    }
+    if let Err(err) = hyprctl_with_cmd(vec!["dispatch focusworkspaceoncurrentmonitor previous".to_string()]).await {
+        tracing::warn!(%err, workspace, "failed to switch back from the probed workspace");
+    }
+    true
+}
+
+/// Tries the top of the hidden workspaces of `monitor_count` monitors with `tag_count` tags each, compacting their
+/// numbering when Hyprland rejects it, and numbers them as chosen.
+pub async fn probe_hidden_workspaces(monitor_count: usize, tag_count: u8) -> Numbering {
+    let default_top = top_workspace(DEFAULT_HIDDEN_BASE, tag_count, monitor_count);
+    let compact_top = top_workspace(COMPACT_HIDDEN_BASE, tag_count, monitor_count);
+    let numbering = if accepts(default_top).await {
+        Numbering::Default
+    } else if compact_fits(monitor_count) && accepts(compact_top).await {
+        Numbering::Compact
+    } else {
+        Numbering::Unusable
+    };
+
+    let base = numbering.base();
+    set_hidden_base(base);
+    let (first, last) = (base + 1, top_workspace(base, tag_count, monitor_count));
+    match numbering {
+        Numbering::Default => tracing::debug!(first, last, "hidden workspaces accepted"),
+        Numbering::Compact => tracing::warn!(rejected = default_top, first, last, "Hyprland rejects the default hidden workspaces, numbering them compactly"),
+        Numbering::Unusable => tracing::error!(rejected = default_top, first, last,
+            "Hyprland rejects hidden workspaces even compacted, windows on the highest tags may not hide, lower tag_count"),
+    }
+    numbering
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact_fits, top_workspace, Numbering, COMPACT_HIDDEN_BASE};
+
+    #[test]
+    fn numbering_ranges() {
+        assert_eq!(top_workspace(100, 32, 1), 132);
+        assert_eq!(top_workspace(100, 32, 2), 164);
+        assert_eq!(top_workspace(COMPACT_HIDDEN_BASE, 32, 2), 74);
+        assert_eq!(top_workspace(COMPACT_HIDDEN_BASE, 32, 0), 42);
+        assert_eq!(top_workspace(COMPACT_HIDDEN_BASE, 9, 3), 37);
+
+        assert!(compact_fits(10));
+        assert!(!compact_fits(11));
+        assert_eq!(Numbering::Compact.base(), 10);
+        assert_eq!(Numbering::Unusable.base(), 100);
+    }
+}
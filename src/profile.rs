@@ -0,0 +1,158 @@
+use anyhow::bail;
+
+use crate::config::{Config, Profile};
+
+/// Tracks which config profile is active and the config it results in.
+#[derive(Debug)]
+pub struct Profiles {
+    base: Config,
+    active: Option<String>,
+    forced: bool,
+    effective: Config,
+}
+
+impl Profiles {
+    pub fn new(base: Config) -> Self {
+        Self {
+            effective: base.clone(),
+            base,
+            active: None,
+            forced: false,
+        }
+    }
+
+    /// The base config with the active profile's overrides applied.
+    pub fn config(&self) -> &Config {
+        &self.effective
+    }
+
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    pub fn active_profile(&self) -> Option<&Profile> {
+        self.active.as_ref().and_then(|name| self.base.profiles.get(name))
+    }
+
+    /// Picks the profile matching the connected monitors, unless one was forced.
+    /// Each monitor is identified by its name and description. Returns true when the active profile changed.
+    pub fn select(&mut self, monitors: &[(String, String)]) -> bool {
+        if self.forced {
+            return false;
+        }
+
+        let name = matching_profile(&self.base, monitors).map(|s| s.to_string());
+        self.activate(name)
+    }
+
+    /// Forces a profile regardless of the connected monitors. `auto` goes back to automatic selection.
+    pub fn force(&mut self, name: &str, monitors: &[(String, String)]) -> anyhow::Result<bool> {
+        if name == "auto" {
+            self.forced = false;
+            return Ok(self.select(monitors));
+        }
+
+        if !self.base.profiles.contains_key(name) {
+            bail!("no such profile: {}", name);
+        }
+
+        self.forced = true;
+        Ok(self.activate(Some(name.to_string())))
+    }
+
+    fn activate(&mut self, name: Option<String>) -> bool {
+        if self.active == name {
+            return false;
+        }
+
+        tracing::info!(from = ?self.active, to = ?name, "switching profile");
+        self.effective = match &name {
+            Some(name) => self.base.with_profile(name),
+            None => self.base.clone(),
+        };
+        self.active = name;
+
+        true
+    }
+}
+
+/// The profile whose monitors are all connected, preferring the one listing the most monitors.
+fn matching_profile<'a>(config: &'a Config, monitors: &[(String, String)]) -> Option<&'a str> {
+    let connected = |m: &String| monitors.iter().any(|(name, desc)| name == m || desc == m);
+
+    config.profiles.iter()
+        .filter(|(_, p)| !p.monitors.is_empty() && p.monitors.iter().all(connected))
+        .fold(None, |best: Option<(&String, &Profile)>, (name, p)| match best {
+            Some((_, b)) if b.monitors.len() >= p.monitors.len() => best,
+            _ => Some((name, p)),
+        })
+        .map(|(name, _)| name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, NewWindowTag};
+
+    use super::Profiles;
+
+    fn config() -> Config {
+        Config::parse(r#"{
+            "new_window_tag": "active",
+            "profiles": {
+                "docked": {
+                    "monitors": ["eDP-1", "Dell Inc. DELL U2720Q"],
+                    "new_window_tag": 9,
+                    "default_tags": {"eDP-1": [1, 2]}
+                },
+                "mobile": {
+                    "monitors": ["eDP-1"]
+                }
+            }
+        }"#).unwrap()
+    }
+
+    fn monitor(name: &str, desc: &str) -> (String, String) {
+        (name.to_string(), desc.to_string())
+    }
+
+    #[test]
+    fn select_by_monitors() {
+        let mut profiles = Profiles::new(config());
+        assert_eq!(profiles.active(), None);
+
+        assert!(profiles.select(&[monitor("eDP-1", "")]));
+        assert_eq!(profiles.active(), Some("mobile"));
+        assert_eq!(profiles.config().new_window_tag, NewWindowTag::Active);
+
+        // description matches too, and the more specific profile wins
+        assert!(profiles.select(&[monitor("eDP-1", ""), monitor("DP-3", "Dell Inc. DELL U2720Q")]));
+        assert_eq!(profiles.active(), Some("docked"));
+        assert_eq!(profiles.config().new_window_tag, NewWindowTag::Tag(9));
+        assert_eq!(profiles.active_profile().unwrap().default_tags["eDP-1"], vec![1, 2]);
+
+        assert!(!profiles.select(&[monitor("eDP-1", ""), monitor("DP-3", "Dell Inc. DELL U2720Q")]));
+
+        assert!(profiles.select(&[monitor("HDMI-A-1", "")]));
+        assert_eq!(profiles.active(), None);
+        assert_eq!(profiles.config().new_window_tag, NewWindowTag::Active);
+    }
+
+    #[test]
+    fn force_profile() {
+        let monitors = [monitor("eDP-1", "")];
+        let mut profiles = Profiles::new(config());
+        profiles.select(&monitors);
+
+        assert!(profiles.force("docked", &monitors).unwrap());
+        assert_eq!(profiles.active(), Some("docked"));
+
+        // forced profiles stick across monitor changes
+        assert!(!profiles.select(&monitors));
+        assert_eq!(profiles.active(), Some("docked"));
+
+        assert!(profiles.force("nope", &monitors).is_err());
+
+        assert!(profiles.force("auto", &monitors).unwrap());
+        assert_eq!(profiles.active(), Some("mobile"));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{borrow::Cow, fmt, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::session;
+
+/// The daemon's control socket, in the Hyprland session directory.
+pub const SOCKET: &str = ".hyprtagctl.sock";
+
+/// What a command's argument is, for completing it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arg {
+    /// A tag number or name.
+    Tag,
+    /// A tag, a comma separated list of them or a `mask:` of tag bits.
+    Tags,
+    /// A monitor name, description or number.
+    Monitor,
+    /// A window address.
+    Window,
+    /// Another command, which the command wraps.
+    Command,
+    /// One of the words, separated by `|`.
+    Words(Cow<'static, str>),
+    /// Anything else, like a name or a path.
+    Text,
+}
+
+/// A ctrl command as `help` and completions describe it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandInfo {
+    pub name: Cow<'static, str>,
+    /// The arguments, like `<tag> [--force]`.
+    pub syntax: Cow<'static, str>,
+    /// What each argument is, in order.
+    pub args: Cow<'static, [Arg]>,
+    pub about: Cow<'static, str>,
+    /// The cargo feature the daemon needs to be built with for the command.
+    pub feature: Option<Cow<'static, str>>,
+}
+
+impl CommandInfo {
+    /// The command with its arguments, like `move <tag> [--force]`.
+    pub fn usage(&self) -> String {
+        match self.syntax.as_ref() {
+            "" => self.name.to_string(),
+            syntax => format!("{} {}", self.name, syntax),
+        }
+    }
+}
+
+const fn info(name: &'static str, syntax: &'static str, args: &'static [Arg], about: &'static str, feature: Option<&'static str>) -> CommandInfo {
+    CommandInfo {
+        name: Cow::Borrowed(name),
+        syntax: Cow::Borrowed(syntax),
+        args: Cow::Borrowed(args),
+        about: Cow::Borrowed(about),
+        feature: match feature {
+            Some(feature) => Some(Cow::Borrowed(feature)),
+            None => None,
+        },
+    }
+}
+
+const fn command(name: &'static str, syntax: &'static str, args: &'static [Arg], about: &'static str) -> CommandInfo {
+    info(name, syntax, args, about, None)
+}
+
+/// A command replying with JSON, which needs the json-proto feature.
+const fn json_command(name: &'static str, syntax: &'static str, args: &'static [Arg], about: &'static str) -> CommandInfo {
+    info(name, syntax, args, about, Some("json-proto"))
+}
+
+/// Commands `parse_request` knows, which `help` describes and unknown ones are matched against for a suggestion.
+pub const COMMANDS: &[CommandInfo] = &[
+    command("move", "<tag> [--force]", &[Arg::Tag, Arg::Words(Cow::Borrowed("--force"))],
+        "Moves the focused window to the tag. --force moves XWayland windows pinned by xwayland_policy too."),
+    command("move_show", "<tag>", &[Arg::Tag], "Moves the focused window to the tag and shows it, which one restore undoes."),
+    command("move_to_empty", "[follow]", &[Arg::Words(Cow::Borrowed("follow"))],
+        "Moves the focused window to the lowest empty tag, showing it with follow. Replies with the tag."),
+    command("show", "<tag>|<tag>,<tag>...|mask:<bits>", &[Arg::Tags], "Shows only the tag, or the listed tags at once."),
+    command("cycle_next", "", &[], "Shows the next tag with windows."),
+    command("cycle_prev", "", &[], "Shows the previous tag with windows."),
+    command("toggle", "<tag> [focus]", &[Arg::Tag, Arg::Words(Cow::Borrowed("focus"))],
+        "Shows or hides the tag next to the visible ones, with focus on its first window when shown with focus."),
+    command("restore", "", &[], "Goes back to the tags visible before the last change."),
+    command("prefix", "<digit>", &[Arg::Text], "Takes the digit as the first of a two-digit tag for the next command."),
+    command("abort_prefix", "", &[], "Drops a pending prefix."),
+    command("profile", "<name>", &[Arg::Text], "Switches to the config profile."),
+    command("move_to_next_monitor", "", &[], "Moves the focused window to the active tag of the next monitor."),
+    command("move_to_cursor_monitor", "[tag]", &[Arg::Tag],
+        "Moves the focused window to the monitor under the cursor, on the tag or the one active there."),
+    command("place", "<monitor> <tag> [address]", &[Arg::Monitor, Arg::Tag, Arg::Window],
+        "Puts the window, or the focused one, on the monitor's tag in one step."),
+    command("show_on", "<monitor> <tag> [nofocus]", &[Arg::Monitor, Arg::Tag, Arg::Words(Cow::Borrowed("nofocus"))], "Shows only the tag on the monitor."),
+    command("throw", "<monitor> <tag>", &[Arg::Monitor, Arg::Tag],
+        "Puts the focused window on the monitor's tag and shows it there, keeping focus where it is."),
+    command("focus_monitor", "<monitor>", &[Arg::Monitor], "Focuses the monitor."),
+    command("focus_last", "", &[], "Focuses the window focused before the current one, like alt-tab."),
+    command("promote", "[address]", &[Arg::Window], "Moves the window, or the focused one, to the front of its tag."),
+    command("demote", "[address]", &[Arg::Window], "Moves the window, or the focused one, to the back of its tag."),
+    command("reorder", "<tag> <address>...", &[Arg::Tag, Arg::Window], "Puts the windows at the front of the tag in the given order."),
+    command("activate", "<class> <command>", &[Arg::Text, Arg::Text],
+        "Focuses a window of the class, or runs the command to start one on the class's tag."),
+    command("autostart_reset", "[tag]", &[Arg::Tag], "Lets the tag, or every tag, run its autostart commands again."),
+    command("mirror", "<tag> <monitor>", &[Arg::Tag, Arg::Monitor], "Shows the active monitor's tag on the other monitor too."),
+    command("unmirror", "", &[], "Puts mirrored windows back on their monitor."),
+    command("mode", "normal|dim", &[Arg::Words(Cow::Borrowed("normal|dim"))], "Switches how the active monitor shows hidden tags."),
+    command("overlay", "add|remove [address]", &[Arg::Words(Cow::Borrowed("add|remove")), Arg::Window],
+        "Keeps the window, or the focused one, visible off any tag, or puts it back on its tag."),
+    command("presentation", "on|off [monitor]", &[Arg::Words(Cow::Borrowed("on|off")), Arg::Monitor],
+        "Turns presentation mode, which rejects tag changes, on or off for the monitor or all of them."),
+    command("stale", "<seconds> [move <tag>]", &[Arg::Text, Arg::Words(Cow::Borrowed("move")), Arg::Tag],
+        "Replies with the windows not focused for that long, moving them to the tag with move."),
+    command("summary", "[reset]", &[Arg::Words(Cow::Borrowed("reset"))], "Replies with each tag's foreground time, windows and occupancy, or starts them over."),
+    command("conflicts", "", &[], "Replies with the windows whose flags conflicted."),
+    command("rule", "list|add|remove|test|save [args...]", &[Arg::Words(Cow::Borrowed("list|add|remove|test|save")), Arg::Text],
+        "Lists, adds, removes, tests or saves the window rules."),
+    command("txn", "begin|commit|abort", &[Arg::Words(Cow::Borrowed("begin|commit|abort"))],
+        "Begins, commits or aborts a transaction, whose commands dispatch together on commit."),
+    command("sync", "<command>", &[Arg::Command], "Runs the command and replies ok or err once its dispatches ran."),
+    json_command("snapshot", "", &[], "Replies with the whole state."),
+    json_command("badges", "", &[], "Replies with the tags with windows added or urgent since they were last visible."),
+    json_command("query", "[full]", &[Arg::Words(Cow::Borrowed("full"))],
+        "Replies with each monitor's tags and windows, full adding how each window got on its tag."),
+    json_command("subscribe", "", &[], "Streams a line per monitor whose tags, windows or focus change."),
+    json_command("outputs", "", &[], "Replies with every monitor with its workspaces and visible tags."),
+    json_command("pager", "", &[], "Replies with every monitor's tags as a grid."),
+    command("pager_goto", "<monitor> <tag>", &[Arg::Monitor, Arg::Tag], "Shows the tag on the monitor, as a click on a pager cell."),
+    json_command("config", "[check <path>]", &[Arg::Words(Cow::Borrowed("check")), Arg::Text],
+        "Replies with the effective config, or checks the config file without applying it."),
+    json_command("diff", "<snapshot>", &[Arg::Text], "Replies with what changed since the snapshot."),
+    json_command("explain", "<address>", &[Arg::Window], "Replies with the window's recent moves and what made them."),
+    json_command("preview", "<command>", &[Arg::Command], "Replies with what the command would dispatch and change, without running it."),
+    json_command("help", "[command]", &[Arg::Command], "Replies with this list, or with the command's entry."),
+];
+
+/// The command of the registry named `name`.
+pub fn command_info(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// A command missing from `COMMANDS`, with the closest known one if it looks like a typo.
+#[derive(Debug, PartialEq)]
+pub struct UnknownCommand {
+    pub cmd: String,
+    pub suggestion: Option<&'static str>,
+}
+
+impl UnknownCommand {
+    pub fn new(cmd: &str) -> Self {
+        Self { cmd: cmd.to_string(), suggestion: suggest(cmd) }
+    }
+}
+
+impl fmt::Display for UnknownCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown command '{}'", self.cmd)?;
+        if let Some(suggestion) = self.suggestion {
+            write!(f, ", did you mean '{}'?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownCommand {}
+
+/// The known command closest to `cmd`, if it's at most two edits away and not a rewrite of most of it.
+fn suggest(cmd: &str) -> Option<&'static str> {
+    COMMANDS.iter()
+        .map(|c| (edit_distance(cmd, &c.name), c.name.as_ref()))
+        .filter(|(d, _)| *d <= 2 && *d < cmd.chars().count())
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            row.push(substitution.min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+/// The control socket of the session picked like the daemon picks it: `signature`, HYPRTAG_SIGNATURE,
+/// HYPRLAND_INSTANCE_SIGNATURE or the only live session.
+pub fn socket_path(signature: Option<&str>) -> anyhow::Result<PathBuf> {
+    Ok(session::select(signature, &session::runtime_roots())?.join(SOCKET))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_info, COMMANDS};
+
+    #[test]
+    fn registry() {
+        for (i, cmd) in COMMANDS.iter().enumerate() {
+            assert!(COMMANDS[..i].iter().all(|c| c.name != cmd.name), "{} twice", cmd.name);
+            // an argument described for every word of the syntax
+            assert_eq!(cmd.syntax.split_whitespace().count(), cmd.args.len(), "{}", cmd.name);
+            assert!(cmd.about.ends_with('.'), "{}", cmd.name);
+        }
+        assert_eq!(command_info("toggle").unwrap().usage(), "toggle <tag> [focus]");
+        assert_eq!(command_info("restore").unwrap().usage(), "restore");
+        assert_eq!(command_info("bogus"), None);
+    }
+}
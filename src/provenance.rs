@@ -0,0 +1,165 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Serialize;
+
+use crate::{flags::FlagConflict, monitor::ChangeSource, snapshot::Placement, state::WindowAddr};
+
+/// Assignments remembered per window.
+pub const PROVENANCE_ENTRIES: usize = 5;
+
+/// A window put on a monitor's tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Assignment {
+    pub monitor: String,
+    pub tag: u8,
+    /// Where it was before, `None` when it first got a tag.
+    pub from: Option<Placement>,
+    pub source: ChangeSource,
+    /// Epoch seconds.
+    pub at: u64,
+    /// Why it went somewhere else than asked, like a pinned tag's monitor being disconnected.
+    pub note: Option<String>,
+}
+
+impl Assignment {
+    fn placement(&self) -> Placement {
+        Placement { monitor: self.monitor.clone(), tag: self.tag }
+    }
+}
+
+/// Flags a window couldn't have together, with what it went on with.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedConflict {
+    #[serde(flatten)]
+    pub conflict: FlagConflict,
+    pub source: ChangeSource,
+    /// Epoch seconds.
+    pub at: u64,
+}
+
+/// A command autostarted for a tag which didn't bring up a window there.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AutostartFailure {
+    pub tag: u8,
+    pub command: String,
+    /// Like `no window within 30s` or `exited with exit status: 127`.
+    pub reason: String,
+    /// Epoch seconds.
+    pub at: u64,
+}
+
+/// Why each window is on its tag: its last few assignments, oldest first, and its latest flag conflict. Also the
+/// last few autostarted commands which brought up no window.
+#[derive(Debug, Default)]
+pub struct Provenance {
+    windows: HashMap<WindowAddr, VecDeque<Assignment>>,
+    conflicts: HashMap<WindowAddr, RecordedConflict>,
+    /// Notes for the windows' next assignments.
+    notes: HashMap<WindowAddr, String>,
+    autostart_failures: VecDeque<AutostartFailure>,
+}
+
+impl Provenance {
+    /// Records the placement when it differs from the window's last one.
+    pub fn placed(&mut self, window: WindowAddr, placement: Placement, source: &ChangeSource, at: u64) {
+        let history = self.windows.entry(window).or_default();
+        let from = history.back().map(|a| a.placement());
+        if from.as_ref() == Some(&placement) {
+            return;
+        }
+        tracing::debug!(%window, monitor = placement.monitor, tag = placement.tag, %source, "window assigned");
+        let note = self.notes.remove(&window);
+        history.push_back(Assignment { monitor: placement.monitor, tag: placement.tag, from, source: source.clone(), at, note });
+        if history.len() > PROVENANCE_ENTRIES {
+            history.pop_front();
+        }
+    }
+
+    /// Attaches the note to the window's next assignment.
+    pub fn note(&mut self, window: WindowAddr, note: String) {
+        self.notes.insert(window, note);
+    }
+
+    pub fn conflicted(&mut self, window: WindowAddr, conflict: FlagConflict, source: &ChangeSource, at: u64) {
+        self.conflicts.insert(window, RecordedConflict { conflict, source: source.clone(), at });
+    }
+
+    pub fn forget(&mut self, window: &WindowAddr) {
+        self.windows.remove(window);
+        self.notes.remove(window);
+        self.conflicts.remove(window);
+    }
+
+    pub fn conflict(&self, window: &WindowAddr) -> Option<&RecordedConflict> {
+        self.conflicts.get(window)
+    }
+
+    /// Windows with a flag conflict, by address.
+    pub fn conflicts(&self) -> Vec<(WindowAddr, &RecordedConflict)> {
+        let mut conflicts: Vec<_> = self.conflicts.iter().map(|(w, c)| (*w, c)).collect();
+        conflicts.sort_by_key(|(w, _)| *w);
+        conflicts
+    }
+
+    pub fn history(&self, window: &WindowAddr) -> Option<&VecDeque<Assignment>> {
+        self.windows.get(window)
+    }
+
+    pub fn latest(&self, window: &WindowAddr) -> Option<&Assignment> {
+        self.windows.get(window)?.back()
+    }
+
+    pub fn autostart_failed(&mut self, failure: AutostartFailure) {
+        tracing::warn!(tag = failure.tag, command = failure.command, reason = failure.reason, "autostart failed");
+        self.autostart_failures.push_back(failure);
+        if self.autostart_failures.len() > PROVENANCE_ENTRIES {
+            self.autostart_failures.pop_front();
+        }
+    }
+
+    /// The last few autostart failures, oldest first.
+    pub fn autostart_failures(&self) -> impl Iterator<Item = &AutostartFailure> {
+        self.autostart_failures.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{monitor::ChangeSource, snapshot::Placement};
+
+    use super::{Provenance, PROVENANCE_ENTRIES};
+
+    fn on(monitor: &str, tag: u8) -> Placement {
+        Placement { monitor: monitor.into(), tag }
+    }
+
+    #[test]
+    fn bounded_history() {
+        let window = "aa".parse().unwrap();
+        let source = ChangeSource::Ctrl { command: "move".into() };
+        let mut provenance = Provenance::default();
+
+        provenance.placed(window, on("DP-1", 1), &ChangeSource::Startup, 10);
+        // staying put isn't an assignment
+        provenance.placed(window, on("DP-1", 1), &source, 20);
+        assert_eq!(provenance.history(&window).unwrap().len(), 1);
+
+        for tag in 2..=PROVENANCE_ENTRIES as u8 + 1 {
+            provenance.placed(window, on("DP-1", tag), &source, 20 + tag as u64);
+        }
+        let history = provenance.history(&window).unwrap();
+        assert_eq!(history.len(), PROVENANCE_ENTRIES);
+        assert_eq!(history[0].tag, 2);
+        assert_eq!(history[0].from, Some(on("DP-1", 1)));
+        assert_eq!(provenance.latest(&window).unwrap().tag, PROVENANCE_ENTRIES as u8 + 1);
+
+        provenance.note(window, "fell back".into());
+        provenance.placed(window, on("DP-2", 1), &source, 40);
+        assert_eq!(provenance.latest(&window).unwrap().note.as_deref(), Some("fell back"));
+        provenance.placed(window, on("DP-2", 2), &source, 41);
+        assert_eq!(provenance.latest(&window).unwrap().note, None);
+
+        provenance.forget(&window);
+        assert!(provenance.history(&window).is_none());
+    }
+}
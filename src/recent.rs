@@ -0,0 +1,74 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use crate::state::WindowAddr;
+
+/// How many window addresses are remembered.
+pub const RECENT_WINDOWS: usize = 256;
+
+/// How long an address is remembered after it was last seen.
+pub const RECENT_WINDOW_TTL: Duration = Duration::from_secs(600);
+
+/// Window addresses seen in recent events, least recently seen first.
+#[derive(Debug)]
+pub struct RecentWindows {
+    capacity: usize,
+    ttl: Duration,
+    entries: VecDeque<(WindowAddr, Instant)>,
+}
+
+impl Default for RecentWindows {
+    fn default() -> Self {
+        Self::new(RECENT_WINDOWS, RECENT_WINDOW_TTL)
+    }
+}
+
+impl RecentWindows {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn seen(&mut self, window: WindowAddr, now: Instant) {
+        self.entries.retain(|(w, _)| *w != window);
+        self.entries.push_back((window, now));
+        if self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Whether the window was seen within the TTL. Expired addresses are dropped on the way.
+    pub fn contains(&mut self, window: &WindowAddr, now: Instant) -> bool {
+        while self.entries.front().is_some_and(|(_, at)| now.duration_since(*at) >= self.ttl) {
+            self.entries.pop_front();
+        }
+        self.entries.iter().any(|(w, _)| w == window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RecentWindows;
+
+    #[test]
+    fn bounded_and_expiring() {
+        let now = Instant::now();
+        let mut recent = RecentWindows::new(2, Duration::from_secs(10));
+        let (a, b, c) = ("a".parse().unwrap(), "b".parse().unwrap(), "c".parse().unwrap());
+
+        recent.seen(a, now);
+        recent.seen(b, now);
+        // seeing it again makes it the most recent
+        recent.seen(a, now + Duration::from_secs(5));
+        recent.seen(c, now + Duration::from_secs(5));
+        assert!(!recent.contains(&b, now + Duration::from_secs(5)));
+        assert!(recent.contains(&a, now + Duration::from_secs(5)));
+
+        assert!(recent.contains(&c, now + Duration::from_secs(14)));
+        assert!(!recent.contains(&a, now + Duration::from_secs(15)));
+    }
+}
@@ -0,0 +1,368 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    activity::Clock,
+    adopt,
+    config::Config,
+    ctrl::{handle_ctrl, parse_request, Ctrl},
+    dispatch,
+    events::{event_line, handle_event, Event},
+    fullscreen::fullscreen_windows,
+    health,
+    hooks::Hooks,
+    hyprctl::{hyprctl_clients, hyprctl_monitors, mock, use_mock, ClientInfo, MockHyprland, MonitorInfo},
+    lineage::Ancestry,
+    monitor::MonitorsState,
+    probe,
+    profile::Profiles,
+    profile_changed,
+    snapshot::StateSnapshot,
+};
+#[cfg(feature = "json-proto")]
+use crate::subscribe;
+
+/// How long the replay keeps going after the last record, so debounced focus changes and queries finish.
+const SETTLE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    capture: PathBuf,
+    /// Wall clock pace relative to the capture, as fast as possible without it. Either way the daemon runs on a
+    /// virtual clock, so the output doesn't depend on the pace.
+    speed: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct Record {
+    /// Time since the capture started.
+    pub at: Duration,
+    pub kind: Kind,
+}
+
+#[derive(Debug)]
+pub enum Kind {
+    /// A line from Hyprland's event socket.
+    Event(String),
+    /// A ctrl command as hyprtagctl sends it.
+    Ctrl(String),
+    /// What `hyprctl monitors -j` answers from then on.
+    Monitors(Vec<MonitorInfo>),
+    /// What `hyprctl clients -j` answers from then on.
+    Clients(Vec<ClientInfo>),
+    /// What `hyprctl getoption` answers from then on, keyed by option.
+    Options(HashMap<String, String>),
+    /// The config the session ran with, before the first event or command.
+    Config(Box<Config>),
+    /// hyprctl hanging for this long from then on, to see the daemon keep up without it.
+    Stall(Duration),
+    /// hyprctl failing batches containing the text from then on.
+    Reject(String),
+    /// hyprctl running batches containing the text again, as a `reject` of it made them fail.
+    Accept(String),
+    /// A subscriber, whose lines are logged from then on.
+    #[cfg(feature = "json-proto")]
+    Subscribe,
+}
+
+/// Seconds since the replay started, so window times don't depend on when it runs.
+#[derive(Debug)]
+struct ReplayClock(Instant);
+
+impl Clock for ReplayClock {
+    fn now(&self) -> u64 {
+        self.0.elapsed().as_secs()
+    }
+}
+
+/// The captured session's processes are gone, so no window launched another.
+#[derive(Debug)]
+struct NoProcesses;
+
+impl Ancestry for NoProcesses {
+    fn parent(&self, _pid: u32) -> Option<u32> {
+        None
+    }
+}
+
+/// `hyprtag --replay <capture> [--speed N]`: runs a captured session through the daemon against a mock Hyprland,
+/// then prints what was sent to Hyprland and the final state.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let options = parse_args(args)?;
+    let capture = std::fs::read_to_string(&options.capture)
+        .with_context(|| format!("failed to read {}", options.capture.display()))?;
+    let records = parse_capture(&capture)?;
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()?;
+    print!("{}", rt.block_on(replay(records, options.speed))?);
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> anyhow::Result<Options> {
+    let mut capture = None;
+    let mut speed = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--replay" => capture = Some(PathBuf::from(args.next().context("--replay requires a capture file")?)),
+            "--speed" => {
+                let n: f64 = args.next().context("--speed requires a value")?.parse().context("invalid --speed")?;
+                if !(n > 0.0 && n.is_finite()) {
+                    bail!("--speed must be positive");
+                }
+                speed = Some(n);
+            },
+            arg => bail!("unknown replay arg: {}", arg),
+        }
+    }
+    Ok(Options {
+        capture: capture.context("--replay requires a capture file")?,
+        speed,
+    })
+}
+
+/// Parses a capture, one `<ms> <kind> <payload>` record per line with the milliseconds since the capture started.
+/// Kinds are `event`, `ctrl`, `monitors`, `clients`, `options` and `config`, the last four with JSON payloads,
+/// `stall` with the milliseconds hyprctl hangs for, `reject` with text which makes batches containing it fail and
+/// `accept` with text which doesn't anymore, and `subscribe` without a payload. Blank lines and lines starting with `#` are skipped.
+pub fn parse_capture(capture: &str) -> anyhow::Result<Vec<Record>> {
+    let mut records: Vec<Record> = vec![];
+    for (i, line) in capture.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let record = parse_record(line).with_context(|| format!("capture line {}", i + 1))?;
+        if records.last().is_some_and(|r| r.at > record.at) {
+            bail!("capture line {}: time goes backwards", i + 1);
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn parse_record(line: &str) -> anyhow::Result<Record> {
+    let (at, rest) = line.split_once(' ').context("missing record kind")?;
+    let at = Duration::from_millis(at.parse().context("invalid time")?);
+    let (kind, payload) = rest.split_once(' ').unwrap_or((rest, ""));
+    let kind = match kind {
+        // as read from the socket
+        "event" => Kind::Event(format!("{}\n", payload)),
+        "ctrl" => Kind::Ctrl(payload.to_string()),
+        "monitors" => Kind::Monitors(serde_json::from_str(payload)?),
+        "clients" => Kind::Clients(serde_json::from_str(payload)?),
+        "options" => Kind::Options(serde_json::from_str(payload)?),
+        "config" => Kind::Config(Box::new(Config::parse(payload)?)),
+        "stall" => Kind::Stall(Duration::from_millis(payload.parse().context("invalid stall")?)),
+        "reject" if !payload.is_empty() => Kind::Reject(payload.to_string()),
+        "accept" if !payload.is_empty() => Kind::Accept(payload.to_string()),
+        #[cfg(feature = "json-proto")]
+        "subscribe" if payload.is_empty() => Kind::Subscribe,
+        kind => bail!("unknown record kind: {}", kind),
+    };
+    Ok(Record { at, kind })
+}
+
+async fn replay(mut records: Vec<Record>, speed: Option<f64>) -> anyhow::Result<String> {
+    let start = records.iter().position(|r| !matches!(r.kind, Kind::Monitors(_) | Kind::Clients(_) | Kind::Options(_) | Kind::Config(_) | Kind::Reject(_)))
+        .unwrap_or(records.len());
+    let session = records.split_off(start);
+
+    let mut backend = MockHyprland::default();
+    let mut config = Config::default();
+    for record in records {
+        match record.kind {
+            Kind::Monitors(monitors) => backend.monitors = monitors,
+            Kind::Clients(clients) => backend.clients = clients,
+            Kind::Options(options) => backend.options = options,
+            Kind::Config(c) => config = *c,
+            Kind::Reject(text) => backend.rejects.push(text),
+            _ => unreachable!(),
+        }
+    }
+    if session.iter().any(|r| matches!(r.kind, Kind::Config(_))) {
+        bail!("config has to come before the first event or command");
+    }
+    use_mock(backend)?;
+
+    // the daemon's startup, with the mock's monitors and clients
+    let mut profiles = Profiles::new(config);
+    let monitors = hyprctl_monitors().await?;
+    let monitor_count = monitors.len();
+    let mut state = MonitorsState::new(monitors, profiles.config().tag_count());
+    probe::probe_hidden_workspaces(monitor_count, state.tag_count()).await;
+    state.set_sources(Box::new(ReplayClock(Instant::now())), Box::new(NoProcesses));
+    profiles.select(&state.monitor_ids());
+    let mut hooks = Hooks::new(profiles.config().on_show.clone());
+    profile_changed(&mut state, &profiles, &mut hooks);
+    let clients = hyprctl_clients().await?;
+    clients.iter().for_each(|c| state.client_known(c));
+    fullscreen_windows().lock().unwrap().adopt_clients(&clients);
+    health::dispatch_health().lock().unwrap().adopt_clients(&clients);
+    let (tx, mut rx) = mpsc::channel(10);
+    adopt::begin(&hooks, state.bootstrap(&clients), &profiles.config().adoption, tx.clone());
+    state.record_placements();
+    state.run_autostart(profiles.config(), tx.clone());
+
+    let (event_tx, mut event_rx) = mpsc::channel(100);
+    let feeder = tokio::spawn(feed(session, event_tx, tx.clone(), speed));
+    let done = async {
+        let r = feeder.await;
+        tokio::time::sleep(SETTLE).await;
+        r
+    };
+    tokio::pin!(done);
+
+    #[cfg(feature = "json-proto")]
+    let mut watch = subscribe::Watch::default();
+    let r = loop {
+        tokio::select! {
+            Some(event) = event_rx.recv() => {
+                handle_event(&mut state, &mut profiles, &mut hooks, event, tx.clone());
+            }
+
+            Some(msg) = rx.recv() => {
+                handle_ctrl(&mut state, &mut profiles, &mut hooks, msg, tx.clone());
+            }
+
+            _ = health::desync_changed().notified() => {}
+
+            r = &mut done => break r,
+        }
+        state.run_autostart(profiles.config(), tx.clone());
+        #[cfg(feature = "json-proto")]
+        watch.publish(&state, profiles.config());
+    };
+    r.map_err(|err| anyhow!("replay panicked: {}", err))??;
+
+    let mut output = String::new();
+    if let Some(mock) = mock() {
+        for line in mock.lock().unwrap().log.iter() {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    let snapshot = StateSnapshot { seq: dispatch::seq(), ..state.snapshot() };
+    output.push_str(&format!("snapshot {}\n", serde_json::to_string_pretty(&snapshot)?));
+    Ok(output)
+}
+
+/// Sends each record at its time, like the event socket and ctrl connections would. Replies are logged.
+async fn feed(records: Vec<Record>, events: mpsc::Sender<Event>, ctrl: mpsc::Sender<Ctrl>, speed: Option<f64>) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let mut last = Duration::ZERO;
+    for record in records {
+        if let Some(speed) = speed {
+            // holds up the whole runtime, which has nothing else to do until the record's time
+            std::thread::sleep((record.at - last).div_f64(speed));
+            last = record.at;
+        }
+        tokio::time::sleep_until(start + record.at).await;
+
+        match record.kind {
+            Kind::Event(line) => {
+                if let Some(event) = event_line(&line, false) {
+                    events.send(event).await.map_err(|_| anyhow!("event receiver closed"))?;
+                }
+            },
+            Kind::Ctrl(line) => {
+                let (msg, reply) = match parse_request(&line) {
+                    Ok(r) => r,
+                    Err(err) => {
+                        log_reply(&line, &format!("err: {}", err));
+                        continue;
+                    },
+                };
+                ctrl.send(Ctrl::Request(line.clone(), Box::new(msg))).await.map_err(|_| anyhow!("ctrl receiver closed"))?;
+                if let Some(reply) = reply {
+                    tokio::spawn(async move {
+                        if let Ok(reply) = reply.await {
+                            log_reply(&line, &reply);
+                        }
+                    });
+                }
+            },
+            Kind::Monitors(monitors) => with_mock(|m| m.monitors = monitors),
+            Kind::Clients(clients) => with_mock(|m| m.clients = clients),
+            Kind::Options(options) => with_mock(|m| m.options = options),
+            Kind::Config(_) => (),
+            Kind::Stall(stall) => with_mock(|m| m.stalled_until = Some(Instant::now() + stall)),
+            Kind::Reject(text) => with_mock(|m| m.rejects.push(text)),
+            Kind::Accept(text) => with_mock(|m| m.rejects.retain(|r| *r != text)),
+            #[cfg(feature = "json-proto")]
+            Kind::Subscribe => {
+                let mut lines = subscribe::events().subscribe();
+                tokio::spawn(async move {
+                    while let Ok(line) = lines.recv().await {
+                        with_mock(|m| m.record("subscribe", &line));
+                    }
+                });
+            },
+        }
+    }
+    Ok(())
+}
+
+fn with_mock(f: impl FnOnce(&mut MockHyprland)) {
+    if let Some(mock) = mock() {
+        f(&mut mock.lock().unwrap());
+    }
+}
+
+fn log_reply(cmd: &str, reply: &str) {
+    with_mock(|m| m.record("reply", &format!("{}: {}", cmd, reply)));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{parse_args, parse_capture, Kind, Options};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn replay_args() {
+        assert_eq!(parse_args(&args("--replay a.capture")).unwrap(), Options { capture: PathBuf::from("a.capture"), speed: None });
+        assert_eq!(parse_args(&args("--replay a.capture --speed 4")).unwrap().speed, Some(4.0));
+        assert!(parse_args(&args("--replay a.capture --speed 0")).is_err());
+        assert!(parse_args(&args("--replay")).is_err());
+        assert!(parse_args(&args("--replay a.capture --strict-events")).is_err());
+    }
+
+    #[test]
+    fn parse_records() {
+        let records = parse_capture(r#"
+# setup
+0 monitors [{"id": 0, "name": "DP-1", "focused": true}]
+0 config {"focus_debounce_ms": 0}
+0 options {"general:gaps_in": "5"}
+15 event openwindow>>7e3,1,kitty,~
+20 ctrl show 2
+30 stall 1500
+40 reject address:0x7e3
+50 accept address:0x7e3
+"#).unwrap();
+        assert_eq!(records.len(), 8);
+        assert!(matches!(&records[0].kind, Kind::Monitors(m) if m[0].name == "DP-1"));
+        assert!(matches!(&records[1].kind, Kind::Config(c) if c.focus_debounce_ms == 0));
+        assert!(matches!(&records[2].kind, Kind::Options(o) if o["general:gaps_in"] == "5"));
+        assert!(matches!(&records[3].kind, Kind::Event(line) if line == "openwindow>>7e3,1,kitty,~\n"));
+        assert!(matches!(&records[4].kind, Kind::Ctrl(cmd) if cmd == "show 2"));
+        assert_eq!(records[4].at, Duration::from_millis(20));
+        assert!(matches!(&records[5].kind, Kind::Stall(d) if *d == Duration::from_millis(1500)));
+        assert!(matches!(&records[6].kind, Kind::Reject(text) if text == "address:0x7e3"));
+        assert!(matches!(&records[7].kind, Kind::Accept(text) if text == "address:0x7e3"));
+
+        let err = parse_capture("5 ctrl show 1\n3 ctrl show 2").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(parse_capture("5 window 7e3").is_err());
+        assert!(parse_capture("soon ctrl show 1").is_err());
+    }
+}
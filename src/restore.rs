@@ -0,0 +1,122 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{activity::WindowTimes, persist::{self, Loaded}, state::WindowAddr};
+
+/// What's saved of the tags, to put windows back on them when hyprtag restarts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedState {
+    /// The Hyprland instance the windows are of. Their addresses mean nothing to another one.
+    pub signature: Option<String>,
+    pub monitors: Vec<SavedMonitor>,
+    /// When the saved windows opened and were last focused.
+    #[serde(default)]
+    pub times: BTreeMap<WindowAddr, WindowTimes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedMonitor {
+    /// The connector name.
+    pub name: String,
+    pub visible_tags: u64,
+    pub prev_tags: u64,
+    /// Windows of each occupied tag, in the tag's order.
+    pub tags: BTreeMap<u8, Vec<WindowAddr>>,
+}
+
+/// `$XDG_RUNTIME_DIR/hyprtag/state.json`, which goes away with the login session like the windows do.
+pub fn state_path() -> Option<PathBuf> {
+    let dir = std::env::var("XDG_RUNTIME_DIR").ok()?;
+    Some(PathBuf::from(dir).join("hyprtag").join("state.json"))
+}
+
+/// The state saved at `path` for the Hyprland instance `signature`, none when there's no such file or it's of
+/// another instance.
+pub fn load(path: &Path, signature: Option<&str>) -> Option<SavedState> {
+    let saved: SavedState = match persist::load(path)? {
+        (saved, Loaded::Primary) => saved,
+        (saved, Loaded::Backup) => {
+            tracing::warn!(path = %path.display(), "saved state damaged, loaded its backup");
+            saved
+        },
+    };
+    if saved.signature.as_deref() != signature {
+        tracing::info!(saved = saved.signature, "ignoring state saved for another Hyprland instance");
+        return None;
+    }
+    Some(saved)
+}
+
+/// Saves the state to a file whenever its tags change.
+#[derive(Debug)]
+pub struct Saver {
+    path: PathBuf,
+    last: Option<SavedState>,
+}
+
+impl Saver {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, last: None }
+    }
+
+    /// Saves `state` unless its tags are as last saved. Window times alone change with every focus, they're saved
+    /// along with the next change of the tags.
+    pub fn save(&mut self, state: SavedState) -> bool {
+        if self.last.as_ref().is_some_and(|last| last.monitors == state.monitors && last.signature == state.signature) {
+            return false;
+        }
+        if let Err(err) = persist::save(&self.path, &state) {
+            tracing::warn!(%err, path = %self.path.display(), "failed to save the state");
+        }
+        // a failed save is tried again with the next change rather than after every event
+        self.last = Some(state);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{addr, activity::WindowTimes};
+
+    use super::{load, SavedMonitor, SavedState, Saver};
+
+    fn saved(windows: &[&str]) -> SavedState {
+        SavedState {
+            signature: Some("sig".into()),
+            monitors: vec![SavedMonitor {
+                name: "DP-1".into(),
+                visible_tags: 0b1,
+                prev_tags: 0b100,
+                tags: BTreeMap::from([(3, windows.iter().map(|w| addr(w)).collect())]),
+            }],
+            times: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn saved_on_change() {
+        let dir = std::env::temp_dir().join(format!("hyprtag-restore-{}", std::process::id()));
+        let path = dir.join("state.json");
+        let mut saver = Saver::new(path.clone());
+
+        assert!(saver.save(saved(&["aa"])));
+        // only the times changed
+        let mut focused = saved(&["aa"]);
+        focused.times.insert(addr("aa"), WindowTimes { opened_at: 1, last_focus: 2 });
+        assert!(!saver.save(focused));
+        assert_eq!(load(&path, Some("sig")), Some(saved(&["aa"])));
+
+        let mut moved = saved(&["aa", "bb"]);
+        moved.times.insert(addr("bb"), WindowTimes { opened_at: 3, last_focus: 3 });
+        assert!(saver.save(moved.clone()));
+        assert_eq!(load(&path, Some("sig")), Some(moved));
+
+        // not for another Hyprland
+        assert_eq!(load(&path, Some("other")), None);
+        assert_eq!(load(&dir.join("missing.json"), Some("sig")), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::state::MAX_TAGS;
+
+/// Where a window goes when it opens, by its class and title.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowRule {
+    pub name: String,
+    /// Regex found anywhere in the class, anchor it with `^` and `$` to match all of it.
+    pub class: String,
+    /// Regex found anywhere in the title, any title when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub tag: u8,
+    /// The monitor whose tag it goes on, by connector name. The focused one when not given or not connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<String>,
+    /// Always visible, hiding its tag leaves the window where it is.
+    #[serde(default)]
+    pub sticky: bool,
+    /// Left alone on no tag, like a window on an unmanaged monitor.
+    #[serde(default)]
+    pub ignore: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    /// From the config file or a drop-in in `rules.d`.
+    File,
+    /// Added with `rule add`, or saved with `rule save` before.
+    Runtime,
+}
+
+/// A rule with its regexes compiled.
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    pub rule: WindowRule,
+    class: Regex,
+    title: Option<Regex>,
+}
+
+impl CompiledRule {
+    pub fn new(rule: WindowRule) -> anyhow::Result<Self> {
+        if rule.name.is_empty() || rule.name.contains(char::is_whitespace) {
+            bail!("invalid rule name: '{}'", rule.name);
+        }
+        if !(1..=MAX_TAGS).contains(&rule.tag) {
+            bail!("rule {}: tag {} is out of range 1-{}", rule.name, rule.tag, MAX_TAGS);
+        }
+        let class = compile("class", &rule.class)?;
+        let title = rule.title.as_deref().map(|title| compile("title", title)).transpose()?;
+        Ok(Self { rule, class, title })
+    }
+
+    /// Whether the rule matches, with why.
+    fn check(&self, class: &str, title: &str) -> (bool, String) {
+        if !self.class.is_match(class) {
+            return (false, format!("class '{}' doesn't match /{}/", class, self.rule.class));
+        }
+        let mut why = format!("class '{}' matches /{}/", class, self.rule.class);
+        if let Some(re) = &self.title {
+            if !re.is_match(title) {
+                return (false, format!("{}, but title '{}' doesn't match /{}/", why, title, re));
+            }
+            why += &format!(", title '{}' matches /{}/", title, re);
+        }
+        (true, why)
+    }
+}
+
+/// Compiles the regex, with where it went wrong in the error.
+fn compile(what: &str, pattern: &str) -> anyhow::Result<Regex> {
+    if let Err(err) = regex_syntax::Parser::new().parse(pattern) {
+        let (span, kind) = match &err {
+            regex_syntax::Error::Parse(err) => (err.span(), err.kind().to_string()),
+            regex_syntax::Error::Translate(err) => (err.span(), err.kind().to_string()),
+            _ => bail!("invalid {} regex /{}/: {}", what, pattern, err),
+        };
+        bail!("invalid {} regex /{}/ at column {}: {}", what, pattern, span.start.column, kind);
+    }
+    Regex::new(pattern).with_context(|| format!("invalid {} regex /{}/", what, pattern))
+}
+
+/// A rule in the `rule list` reply.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ListedRule {
+    #[serde(flatten)]
+    pub rule: WindowRule,
+    pub origin: Origin,
+    /// A file rule with a runtime rule of the same name, which is used instead.
+    pub shadowed: bool,
+}
+
+/// A rule checked by `rule test`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RuleCheck {
+    pub name: String,
+    pub origin: Origin,
+    pub matches: bool,
+    pub why: String,
+}
+
+/// The `rule test` reply.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RuleTest {
+    pub class: String,
+    pub title: String,
+    /// The first matching rule, which is the one applied.
+    pub matched: Option<String>,
+    pub checked: Vec<RuleCheck>,
+}
+
+/// Window rules, the ones added at runtime kept apart from the config's. Runtime rules come first and replace file
+/// rules of the same name, otherwise the first matching rule wins.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    file: Vec<CompiledRule>,
+    runtime: Vec<CompiledRule>,
+}
+
+impl Rules {
+    /// Replaces the file rules, leaving out the ones which don't compile.
+    pub fn set_file_rules(&mut self, rules: &[WindowRule]) {
+        self.file = rules.iter().filter_map(|rule| match CompiledRule::new(rule.clone()) {
+            Ok(rule) => Some(rule),
+            Err(err) => {
+                tracing::warn!(%err, "ignoring window rule");
+                None
+            },
+        }).collect();
+    }
+
+    /// Adds the runtime rule, replacing the one of the same name. True when it replaced one.
+    pub fn add(&mut self, rule: CompiledRule) -> bool {
+        match self.runtime.iter_mut().find(|r| r.rule.name == rule.rule.name) {
+            Some(r) => {
+                *r = rule;
+                true
+            },
+            None => {
+                self.runtime.push(rule);
+                false
+            },
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<()> {
+        if let Some(i) = self.runtime.iter().position(|r| r.rule.name == name) {
+            self.runtime.remove(i);
+            return Ok(());
+        }
+        if self.file.iter().any(|r| r.rule.name == name) {
+            bail!("rule {} comes from the config, edit it there", name);
+        }
+        bail!("no rule named {}", name)
+    }
+
+    pub fn list(&self) -> Vec<ListedRule> {
+        let runtime = self.runtime.iter().map(|r| ListedRule { rule: r.rule.clone(), origin: Origin::Runtime, shadowed: false });
+        let file = self.file.iter().map(|r| ListedRule { rule: r.rule.clone(), origin: Origin::File, shadowed: self.shadowed(r) });
+        runtime.chain(file).collect()
+    }
+
+    fn shadowed(&self, rule: &CompiledRule) -> bool {
+        self.runtime.iter().any(|r| r.rule.name == rule.rule.name)
+    }
+
+    /// The rules in the order they're tried.
+    fn effective(&self) -> impl Iterator<Item = (&CompiledRule, Origin)> {
+        self.runtime.iter().map(|r| (r, Origin::Runtime))
+            .chain(self.file.iter().filter(|r| !self.shadowed(r)).map(|r| (r, Origin::File)))
+    }
+
+    /// The rule applied to a window opening with the class and title.
+    pub fn matching(&self, class: &str, title: &str) -> Option<&WindowRule> {
+        self.effective().find(|(r, _)| r.check(class, title).0).map(|(r, _)| &r.rule)
+    }
+
+    /// Every rule checked against the class and title, for `rule test`.
+    pub fn test(&self, class: &str, title: &str) -> RuleTest {
+        let checked: Vec<RuleCheck> = self.effective().map(|(r, origin)| {
+            let (matches, why) = r.check(class, title);
+            RuleCheck { name: r.rule.name.clone(), origin, matches, why }
+        }).collect();
+        RuleTest {
+            class: class.to_string(),
+            title: title.to_string(),
+            matched: checked.iter().find(|c| c.matches).map(|c| c.name.clone()),
+            checked,
+        }
+    }
+
+    /// Loads the runtime rules saved at `path`, none when there's no such file.
+    pub fn load_runtime(&mut self, path: &Path) {
+        let rules = match read_rules(path) {
+            Ok(Some(rules)) => rules,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::warn!(%err, path = %path.display(), "ignoring saved window rules");
+                return;
+            },
+        };
+        for rule in rules {
+            match CompiledRule::new(rule) {
+                Ok(rule) => {
+                    self.add(rule);
+                },
+                Err(err) => tracing::warn!(%err, path = %path.display(), "ignoring saved window rule"),
+            }
+        }
+    }
+
+    /// Writes the runtime rules to `path`, which they're loaded from on the next start. Replies with how many.
+    pub fn save(&self, path: &Path) -> anyhow::Result<usize> {
+        let rules: Vec<&WindowRule> = self.runtime.iter().map(|r| &r.rule).collect();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&rules)? + "\n")
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(rules.len())
+    }
+}
+
+/// The rules in a drop-in, a JSON array of rules. `None` when there's no such file.
+pub fn read_rules(path: &Path) -> anyhow::Result<Option<Vec<WindowRule>>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(Some(serde_json::from_str(&s).with_context(|| format!("invalid rules in {}", path.display()))?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// The drop-in `rule save` writes, in `rules.d` next to the config.
+pub fn runtime_rules_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("rules.d").join(RUNTIME_RULES)
+}
+
+/// File name of the drop-in holding saved runtime rules, which are loaded as runtime rules rather than file rules.
+pub const RUNTIME_RULES: &str = "runtime.json";
+
+#[cfg(test)]
+mod tests {
+    use super::{CompiledRule, Origin, Rules, WindowRule};
+
+    fn rule(name: &str, class: &str, title: Option<&str>, tag: u8) -> WindowRule {
+        WindowRule { name: name.into(), class: class.into(), title: title.map(|t| t.into()), tag, monitor: None, sticky: false, ignore: false }
+    }
+
+    fn compiled(name: &str, class: &str, title: Option<&str>, tag: u8) -> CompiledRule {
+        CompiledRule::new(rule(name, class, title, tag)).unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        let mut rules = Rules::default();
+        rules.set_file_rules(&[rule("chat", "^(slack|discord)$", None, 5), rule("term", "kitty", None, 2), rule("bad", "(", None, 1)]);
+        assert_eq!(rules.matching("discord", "").map(|r| r.tag), Some(5));
+        assert_eq!(rules.matching("firefox", ""), None);
+
+        // a runtime rule comes first and replaces the file rule of the same name
+        assert!(!rules.add(compiled("video", "firefox", Some("YouTube"), 7)));
+        assert!(!rules.add(compiled("chat", "^slack$", None, 6)));
+        assert_eq!(rules.matching("slack", "").map(|r| r.tag), Some(6));
+        assert_eq!(rules.matching("discord", ""), None);
+        assert_eq!(rules.matching("firefox", "Cats - YouTube").map(|r| r.tag), Some(7));
+        assert_eq!(rules.matching("firefox", "Mail"), None);
+
+        let listed = rules.list();
+        let listed: Vec<(&str, Origin, bool)> = listed.iter().map(|r| (r.rule.name.as_str(), r.origin, r.shadowed)).collect();
+        assert_eq!(listed, vec![("video", Origin::Runtime, false), ("chat", Origin::Runtime, false), ("chat", Origin::File, true), ("term", Origin::File, false)]);
+
+        // the file rule is back once the runtime one is gone, and can't be removed itself
+        rules.remove("chat").unwrap();
+        assert_eq!(rules.matching("discord", "").map(|r| r.tag), Some(5));
+        assert_eq!(rules.remove("chat").unwrap_err().to_string(), "rule chat comes from the config, edit it there");
+        assert!(rules.remove("nope").is_err());
+    }
+
+    #[test]
+    fn explains_matches() {
+        let mut rules = Rules::default();
+        rules.set_file_rules(&[rule("term", "^kitty$", Some("^vim"), 2), rule("any-term", "kitty", None, 3)]);
+        let test = rules.test("kitty", "htop");
+        assert_eq!(test.matched.as_deref(), Some("any-term"));
+        assert_eq!(test.checked[0].why, "class 'kitty' matches /^kitty$/, but title 'htop' doesn't match /^vim/");
+        assert_eq!(test.checked[1].why, "class 'kitty' matches /kitty/");
+        assert!(rules.test("foot", "").matched.is_none());
+    }
+
+    #[test]
+    fn regex_errors() {
+        let err = CompiledRule::new(rule("x", "^(kitty", None, 1)).unwrap_err();
+        assert_eq!(err.to_string(), "invalid class regex /^(kitty/ at column 2: unclosed group");
+        let err = CompiledRule::new(rule("x", "kitty", Some("a{2,1}"), 1)).unwrap_err();
+        assert!(err.to_string().starts_with("invalid title regex /a{2,1}/ at column 2: "), "{}", err);
+        assert!(CompiledRule::new(rule("x", "kitty", None, 65)).is_err());
+        assert!(CompiledRule::new(rule("", "kitty", None, 1)).is_err());
+    }
+
+    #[test]
+    fn saved_rules() {
+        let dir = std::env::temp_dir().join(format!("hyprtag-rules-{}", std::process::id()));
+        let path = super::runtime_rules_path(&dir);
+        let mut rules = Rules::default();
+        rules.add(compiled("video", "firefox", Some("YouTube"), 7));
+        rules.set_file_rules(&[rule("term", "kitty", None, 2)]);
+        assert_eq!(rules.save(&path).unwrap(), 1);
+
+        let mut loaded = Rules::default();
+        loaded.load_runtime(&path);
+        assert_eq!(loaded.list().iter().map(|r| (&r.rule, r.origin)).collect::<Vec<_>>(), vec![(&rule("video", "firefox", Some("YouTube"), 7), Origin::Runtime)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,516 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    flags::WindowFlags,
+    monitor::ChangeSource,
+    provenance::{Assignment, RecordedConflict},
+    snapshot::{LayoutGroup, VisibleTagsChange, WindowChange},
+};
+
+/// Version of what `query`, `pager` and `subscribe` reply. Bumped when a field is renamed or removed or changes its type, not
+/// when one is added.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Identifies a monitor's tag across replies, like `DP-1:3`.
+pub fn tag_id(monitor: &str, tag: u8) -> String {
+    format!("{}:{}", monitor, tag)
+}
+
+/// What `query` replies: what a status bar needs to render, in one line. Every field is always there, the ones
+/// which may be unknown or unset as null. Monitors are in hyprtag's order, tags by number and windows by address.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryReply {
+    pub schema_version: u32,
+    /// Sequence number of the last state change reflected, as in snapshots.
+    pub seq: u64,
+    /// Id of the active monitor, null without monitors.
+    pub active_monitor: Option<String>,
+    /// The config profile in use.
+    pub profile: Option<String>,
+    /// Active Hyprland submap, null for the default one.
+    pub submap: Option<String>,
+    /// The tag prefix key pressed, waiting for the tag it completes.
+    pub pending_prefix: Option<u8>,
+    /// Whether a `txn` is open.
+    pub txn_open: bool,
+    /// False while some windows keep failing to go where their tag says.
+    pub healthy: bool,
+    /// Events seen which hyprtag neither handles nor knows to ignore, counted with `--strict-events`.
+    pub unknown_events: u64,
+    pub monitors: Vec<MonitorQuery>,
+    /// Windows on tags, by address.
+    pub windows: BTreeMap<String, WindowQuery>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorQuery {
+    /// The connector name, which stays the same while the monitor is connected.
+    pub id: String,
+    pub name: String,
+    pub visible_tags: u64,
+    /// Tags with at least one window.
+    pub occupied_tags: Vec<u8>,
+    pub active_window: Option<String>,
+    /// Windows kept on the visible workspace outside of any tag.
+    pub overlay: Vec<String>,
+    pub presentation: bool,
+    /// Every tag of the monitor, by number.
+    pub tags: Vec<TagQuery>,
+    /// The monitor's tags in the order bars show them.
+    pub layout: Vec<LayoutGroup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagQuery {
+    /// From `tag_id`.
+    pub id: String,
+    pub tag: u8,
+    /// From `tag_names`, the number without one.
+    pub name: String,
+    /// The monitor the tag is pinned to by its `monitor` setting, whether or not it's this one.
+    pub pinned_to: Option<String>,
+    pub visible: bool,
+    pub windows: usize,
+    /// Windows added or marked urgent since the tag was last visible.
+    pub new_since_seen: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowQuery {
+    pub monitor: String,
+    pub tag: u8,
+    /// The `tag_id` of its tag.
+    pub tag_id: String,
+    /// Epoch seconds.
+    pub opened_at: Option<u64>,
+    /// Epoch seconds.
+    pub last_focus: Option<u64>,
+    pub xwayland: bool,
+    pub flags: WindowFlags,
+    /// How the window got on its tag, in `query full` only.
+    pub assignment: Option<Assignment>,
+}
+
+/// A monitor as a row of the `pager` grid, with a cell per tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PagerRow {
+    /// The connector name.
+    pub id: String,
+    pub monitor: String,
+    pub managed: bool,
+    pub cells: Vec<PagerCell>,
+}
+
+/// A tag of a monitor with what a pager needs to render it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PagerCell {
+    /// From `tag_id`.
+    pub id: String,
+    pub tag: u8,
+    /// From `tag_names`, the number without one.
+    pub name: String,
+    pub windows: usize,
+    pub visible: bool,
+    /// A window of the tag asked for attention while the tag was hidden.
+    pub urgent: bool,
+    /// The active tag of the active monitor. Exactly one cell has it.
+    pub focused: bool,
+}
+
+/// What `preview` replies: what the command would do, run on a copy of the state.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PreviewReply {
+    /// The hyprctl commands it would send.
+    pub dispatches: Vec<String>,
+    pub visible_tags: Vec<VisibleTagsChange>,
+    pub windows: Vec<WindowChange>,
+}
+
+/// What `throw` replies: where the focused window went, and the tags shown there after.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ThrowReply {
+    pub window: String,
+    pub monitor: String,
+    pub tag: u8,
+    pub visible_tags: u64,
+}
+
+/// A window of what `stale` replies.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StaleWindow {
+    pub addr: String,
+    /// Epoch seconds.
+    pub opened_at: u64,
+    /// Epoch seconds.
+    pub last_focus: u64,
+}
+
+/// A window of what `conflicts` replies, with its latest flag conflict.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WindowConflict {
+    pub addr: String,
+    pub conflict: RecordedConflict,
+    /// The conflict in words, like `pinned conflicts with sticky, which wins`.
+    pub message: String,
+}
+
+/// What `explain` replies: why the window is on its tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExplainReply {
+    pub window: String,
+    /// The latest assignment, the last of `history`.
+    pub current: Option<Assignment>,
+    /// The last few assignments, oldest first.
+    pub history: Vec<Assignment>,
+    pub conflict: Option<RecordedConflict>,
+}
+
+/// What `rule save` replies.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleSaveReply {
+    /// How many rules were saved.
+    pub saved: usize,
+    pub path: PathBuf,
+}
+
+/// What `config` replies: the config in use, with the profile applied.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigReply {
+    /// The file it was loaded from, null for the defaults.
+    pub source: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub config: Config,
+}
+
+/// A line of the `subscribe` stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubscribeLine {
+    pub schema_version: u32,
+    /// Sequence number of the last state change reflected, as in `query`.
+    pub seq: u64,
+    /// What made the change, null for the state sent on subscribing.
+    pub source: Option<ChangeSource>,
+    #[serde(flatten)]
+    pub event: SubscribeEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SubscribeEvent {
+    /// A monitor's tags, windows or focus changed, or it was added.
+    Monitor(MonitorEvent),
+    MonitorRemoved { monitor: String },
+    /// The subscriber fell behind and missed this many lines. `query` has the current state.
+    Lagged { missed: u64 },
+    /// Windows whose dispatches hyprctl rejected too many times in a row, so they may not be where their tags say.
+    Desync { windows: Vec<DesyncedWindow> },
+    /// Windows back in sync, as a dispatch for them went through or they closed.
+    Resync { windows: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DesyncedWindow {
+    pub addr: String,
+    /// Where the rejected dispatch moves the window to, if it moves it.
+    pub expected_workspace: Option<i64>,
+    /// Where Hyprland last put the window, if that's known.
+    pub actual_workspace: Option<i64>,
+    /// Consecutive rejected dispatches.
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonitorEvent {
+    /// The connector name, as the `id` of `query`'s monitors.
+    pub monitor: String,
+    /// Whether it's the active monitor.
+    pub focused: bool,
+    pub visible_tags: u64,
+    /// Mask of the tags with at least one window.
+    pub occupied_tags: u64,
+    pub active_window: Option<String>,
+    /// Window count of each occupied tag.
+    pub windows: BTreeMap<u8, usize>,
+    /// The monitor's tags in the order bars show them.
+    pub layout: Vec<LayoutGroup>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        config::Config,
+        flags::{Flag, FlagConflict, WindowFlags},
+        monitor::ChangeSource,
+        provenance::{Assignment, RecordedConflict},
+        snapshot::{LayoutGroup, LayoutTag, Placement, VisibleTagsChange, WindowChange},
+    };
+
+    use super::{
+        tag_id, ConfigReply, DesyncedWindow, ExplainReply, MonitorEvent, MonitorQuery, PagerCell, PagerRow, PreviewReply, QueryReply,
+        RuleSaveReply, StaleWindow, SubscribeEvent, SubscribeLine, TagQuery, ThrowReply, WindowConflict, WindowQuery, SCHEMA_VERSION,
+    };
+
+    fn minimal() -> QueryReply {
+        QueryReply {
+            schema_version: SCHEMA_VERSION,
+            seq: 0,
+            active_monitor: None,
+            profile: None,
+            submap: None,
+            pending_prefix: None,
+            txn_open: false,
+            healthy: true,
+            unknown_events: 0,
+            monitors: vec![],
+            windows: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn minimal_query() {
+        assert_eq!(serde_json::to_value(minimal()).unwrap(), serde_json::json!({
+            "schema_version": 1, "seq": 0, "active_monitor": null, "profile": null, "submap": null,
+            "pending_prefix": null, "txn_open": false, "healthy": true, "unknown_events": 0, "monitors": [], "windows": {},
+        }));
+    }
+
+    /// Renaming a field breaks this. Bump `SCHEMA_VERSION` along with the expected JSON when that's intended.
+    #[test]
+    fn full_query() {
+        let reply = QueryReply {
+            seq: 42,
+            active_monitor: Some("DP-1".into()),
+            profile: Some("desk".into()),
+            submap: Some("resize".into()),
+            pending_prefix: Some(1),
+            txn_open: true,
+            healthy: false,
+            unknown_events: 2,
+            monitors: vec![MonitorQuery {
+                id: "DP-1".into(),
+                name: "DP-1".into(),
+                visible_tags: 0b1,
+                occupied_tags: vec![2],
+                active_window: Some("7e3".into()),
+                overlay: vec!["1de".into()],
+                presentation: true,
+                tags: vec![
+                    TagQuery { id: tag_id("DP-1", 1), tag: 1, name: "web".into(), pinned_to: None, visible: true, windows: 0, new_since_seen: 0 },
+                    TagQuery { id: tag_id("DP-1", 2), tag: 2, name: "2".into(), pinned_to: Some("HDMI-A-1".into()), visible: false, windows: 1, new_since_seen: 1 },
+                ],
+                layout: vec![LayoutGroup {
+                    name: None,
+                    tags: vec![LayoutTag { tag: 1, name: "web".into(), icon: Some("W".into()), hidden: false }],
+                }],
+            }],
+            windows: BTreeMap::from([("7e3".to_string(), WindowQuery {
+                monitor: "DP-1".into(),
+                tag: 2,
+                tag_id: tag_id("DP-1", 2),
+                opened_at: Some(1_700_000_000),
+                last_focus: Some(1_700_000_060),
+                xwayland: true,
+                flags: WindowFlags { sticky: true, rule: Some(2), ..Default::default() },
+                assignment: Some(Assignment {
+                    monitor: "DP-1".into(),
+                    tag: 2,
+                    from: None,
+                    source: ChangeSource::Ctrl { command: "move 2".into() },
+                    at: 1_700_000_030,
+                    note: None,
+                }),
+            })]),
+            ..minimal()
+        };
+
+        assert_eq!(serde_json::to_value(reply).unwrap(), serde_json::json!({
+            "schema_version": 1, "seq": 42, "active_monitor": "DP-1", "profile": "desk", "submap": "resize",
+            "pending_prefix": 1, "txn_open": true, "healthy": false, "unknown_events": 2,
+            "monitors": [{
+                "id": "DP-1", "name": "DP-1", "visible_tags": 1, "occupied_tags": [2], "active_window": "7e3",
+                "overlay": ["1de"], "presentation": true,
+                "tags": [
+                    {"id": "DP-1:1", "tag": 1, "name": "web", "pinned_to": null, "visible": true, "windows": 0, "new_since_seen": 0},
+                    {"id": "DP-1:2", "tag": 2, "name": "2", "pinned_to": "HDMI-A-1", "visible": false, "windows": 1, "new_since_seen": 1},
+                ],
+                "layout": [{"name": null, "tags": [{"tag": 1, "name": "web", "icon": "W", "hidden": false}]}],
+            }],
+            "windows": {"7e3": {
+                "monitor": "DP-1", "tag": 2, "tag_id": "DP-1:2", "opened_at": 1_700_000_000, "last_focus": 1_700_000_060,
+                "xwayland": true,
+                "flags": {"unmanaged": false, "mirrored": false, "overlay": false, "sticky": true, "pinned": false, "rule": 2},
+                "assignment": {
+                    "monitor": "DP-1", "tag": 2, "from": null, "source": {"kind": "ctrl", "command": "move 2"},
+                    "at": 1_700_000_030, "note": null,
+                },
+            }},
+        }));
+    }
+
+    #[test]
+    fn pager_row() {
+        let row = PagerRow {
+            id: "DP-1".into(),
+            monitor: "DP-1".into(),
+            managed: true,
+            cells: vec![PagerCell { id: tag_id("DP-1", 1), tag: 1, name: "1".into(), windows: 2, visible: true, urgent: false, focused: true }],
+        };
+        assert_eq!(serde_json::to_value(row).unwrap(), serde_json::json!({
+            "id": "DP-1", "monitor": "DP-1", "managed": true,
+            "cells": [{"id": "DP-1:1", "tag": 1, "name": "1", "windows": 2, "visible": true, "urgent": false, "focused": true}],
+        }));
+    }
+
+    #[test]
+    fn subscribe_lines() {
+        let line = SubscribeLine {
+            schema_version: SCHEMA_VERSION,
+            seq: 7,
+            source: Some(ChangeSource::Ctrl { command: "show 2".into() }),
+            event: SubscribeEvent::Monitor(MonitorEvent {
+                monitor: "DP-1".into(),
+                focused: true,
+                visible_tags: 0b10,
+                occupied_tags: 0b11,
+                active_window: None,
+                windows: BTreeMap::from([(1, 2), (2, 1)]),
+                layout: vec![],
+            }),
+        };
+        assert_eq!(serde_json::to_value(line).unwrap(), serde_json::json!({
+            "schema_version": 1, "seq": 7, "source": {"kind": "ctrl", "command": "show 2"}, "event": "monitor",
+            "monitor": "DP-1", "focused": true, "visible_tags": 2, "occupied_tags": 3, "active_window": null,
+            "windows": {"1": 2, "2": 1}, "layout": [],
+        }));
+
+        let removed = SubscribeLine { schema_version: SCHEMA_VERSION, seq: 8, source: None, event: SubscribeEvent::MonitorRemoved { monitor: "DP-2".into() } };
+        assert_eq!(serde_json::to_value(removed).unwrap(), serde_json::json!({
+            "schema_version": 1, "seq": 8, "source": null, "event": "monitor_removed", "monitor": "DP-2",
+        }));
+        let lagged = SubscribeLine { schema_version: SCHEMA_VERSION, seq: 8, source: None, event: SubscribeEvent::Lagged { missed: 3 } };
+        assert_eq!(serde_json::to_value(lagged).unwrap()["missed"], 3);
+
+        let desync = SubscribeLine { schema_version: SCHEMA_VERSION, seq: 9, source: None, event: SubscribeEvent::Desync {
+            windows: vec![DesyncedWindow { addr: "7e3".into(), expected_workspace: Some(101), actual_workspace: Some(1), count: 3 }],
+        } };
+        assert_eq!(serde_json::to_value(desync).unwrap(), serde_json::json!({
+            "schema_version": 1, "seq": 9, "source": null, "event": "desync",
+            "windows": [{"addr": "7e3", "expected_workspace": 101, "actual_workspace": 1, "count": 3}],
+        }));
+        let resync = SubscribeLine { schema_version: SCHEMA_VERSION, seq: 10, source: None, event: SubscribeEvent::Resync { windows: vec!["7e3".into()] } };
+        assert_eq!(serde_json::to_value(resync).unwrap(), serde_json::json!({
+            "schema_version": 1, "seq": 10, "source": null, "event": "resync", "windows": ["7e3"],
+        }));
+    }
+
+    fn assignment() -> Assignment {
+        Assignment {
+            monitor: "DP-1".into(),
+            tag: 2,
+            from: Some(Placement { monitor: "DP-2".into(), tag: 1 }),
+            source: ChangeSource::Ctrl { command: "move 2".into() },
+            at: 1_700_000_030,
+            note: Some("DP-2 is disconnected".into()),
+        }
+    }
+
+    fn conflict() -> RecordedConflict {
+        RecordedConflict {
+            conflict: FlagConflict { kept: Flag::Sticky, dropped: Flag::Pinned, resolved: WindowFlags { sticky: true, ..Default::default() } },
+            source: ChangeSource::Rule { class: "mpv".into(), tag: 3 },
+            at: 1_700_000_040,
+        }
+    }
+
+    #[test]
+    fn preview_reply() {
+        let minimal = PreviewReply { dispatches: vec![], visible_tags: vec![], windows: vec![] };
+        assert_eq!(serde_json::to_value(minimal).unwrap(), serde_json::json!({"dispatches": [], "visible_tags": [], "windows": []}));
+
+        let full = PreviewReply {
+            dispatches: vec!["dispatch workspace 2".into()],
+            visible_tags: vec![VisibleTagsChange { monitor: "DP-1".into(), before: Some(0b1), after: Some(0b10) }],
+            windows: vec![WindowChange {
+                addr: "7e3".into(),
+                before: Some(Placement { monitor: "DP-1".into(), tag: 1 }),
+                after: None,
+            }],
+        };
+        assert_eq!(serde_json::to_value(full).unwrap(), serde_json::json!({
+            "dispatches": ["dispatch workspace 2"],
+            "visible_tags": [{"monitor": "DP-1", "before": 1, "after": 2}],
+            "windows": [{"addr": "7e3", "before": {"monitor": "DP-1", "tag": 1}, "after": null}],
+        }));
+    }
+
+    #[test]
+    fn throw_reply() {
+        let reply = ThrowReply { window: "7e3".into(), monitor: "HDMI-A-1".into(), tag: 5, visible_tags: 0b10000 };
+        assert_eq!(serde_json::to_value(reply).unwrap(), serde_json::json!({
+            "window": "7e3", "monitor": "HDMI-A-1", "tag": 5, "visible_tags": 16,
+        }));
+    }
+
+    #[test]
+    fn stale_and_conflict_windows() {
+        let stale = StaleWindow { addr: "7e3".into(), opened_at: 1_700_000_000, last_focus: 1_700_000_060 };
+        assert_eq!(serde_json::to_value(stale).unwrap(), serde_json::json!({
+            "addr": "7e3", "opened_at": 1_700_000_000, "last_focus": 1_700_000_060,
+        }));
+
+        let conflict = WindowConflict { addr: "1de".into(), conflict: conflict(), message: conflict().conflict.to_string() };
+        assert_eq!(serde_json::to_value(conflict).unwrap(), serde_json::json!({
+            "addr": "1de",
+            "conflict": {
+                "kept": "sticky", "dropped": "pinned",
+                "resolved": {"unmanaged": false, "mirrored": false, "overlay": false, "sticky": true, "pinned": false, "rule": null},
+                "source": {"kind": "rule", "class": "mpv", "tag": 3}, "at": 1_700_000_040,
+            },
+            "message": "pinned conflicts with sticky, which wins",
+        }));
+    }
+
+    #[test]
+    fn explain_reply() {
+        let minimal = ExplainReply { window: "7e3".into(), current: None, history: vec![], conflict: None };
+        assert_eq!(serde_json::to_value(minimal).unwrap(), serde_json::json!({
+            "window": "7e3", "current": null, "history": [], "conflict": null,
+        }));
+
+        let full = ExplainReply { window: "7e3".into(), current: Some(assignment()), history: vec![assignment()], conflict: Some(conflict()) };
+        let assignment = serde_json::json!({
+            "monitor": "DP-1", "tag": 2, "from": {"monitor": "DP-2", "tag": 1}, "source": {"kind": "ctrl", "command": "move 2"},
+            "at": 1_700_000_030, "note": "DP-2 is disconnected",
+        });
+        let reply = serde_json::to_value(full).unwrap();
+        assert_eq!((&reply["current"], &reply["history"]), (&assignment, &serde_json::json!([assignment])));
+        assert_eq!(reply["conflict"]["dropped"], "pinned");
+    }
+
+    #[test]
+    fn rule_save_reply() {
+        let reply = RuleSaveReply { saved: 2, path: "/home/me/.config/hyprtag/rules.json".into() };
+        assert_eq!(serde_json::to_value(reply).unwrap(), serde_json::json!({
+            "saved": 2, "path": "/home/me/.config/hyprtag/rules.json",
+        }));
+    }
+
+    #[test]
+    fn config_reply() {
+        let minimal = ConfigReply { source: None, profile: None, config: Config::default() };
+        assert_eq!(serde_json::to_value(minimal).unwrap(), serde_json::json!({
+            "source": null, "profile": null, "config": serde_json::to_value(Config::default()).unwrap(),
+        }));
+
+        let config = Config::parse(r#"{"tag_count": 12}"#).unwrap();
+        let full = ConfigReply { source: Some("/home/me/.config/hyprtag/config.json".into()), profile: Some("desk".into()), config };
+        let reply = serde_json::to_value(full).unwrap();
+        assert_eq!((&reply["source"], &reply["profile"]), (&serde_json::json!("/home/me/.config/hyprtag/config.json"), &serde_json::json!("desk")));
+        assert_eq!(reply["config"]["tag_count"], 12);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{os::unix::net::UnixStream, path::{Path, PathBuf}, sync::Mutex, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::exit::Fatal;
+
+/// Hyprland session directory picked at startup, or the one of the new instance after Hyprland restarted.
+static SESSION_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Directories Hyprland creates its session directories in, newest location first.
+pub fn runtime_roots() -> Vec<PathBuf> {
+    let mut roots = vec![];
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+        roots.push(Path::new(&dir).join("hypr"));
+    }
+    roots.push(PathBuf::from("/tmp/hypr"));
+    roots
+}
+
+/// The session directory: the one picked at startup, or the one named by HYPRLAND_INSTANCE_SIGNATURE.
+pub fn session_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = SESSION_DIR.lock().unwrap().as_ref() {
+        return Ok(dir.clone());
+    }
+    let sig = std::env::var("HYPRLAND_INSTANCE_SIGNATURE").context(Fatal::NoHyprland)?;
+    Ok(signature_dir(&runtime_roots(), &sig))
+}
+
+/// Picks the session to talk to, from `--signature`, HYPRTAG_SIGNATURE or HYPRLAND_INSTANCE_SIGNATURE in that order.
+/// Without any of them, the only live session found in `roots` is used.
+/// HYPRLAND_INSTANCE_SIGNATURE is set to the result, so hyprctl talks to the same session.
+pub fn select(signature: Option<&str>, roots: &[PathBuf]) -> anyhow::Result<PathBuf> {
+    let sig = signature.map(|s| s.to_string())
+        .or_else(|| std::env::var("HYPRTAG_SIGNATURE").ok())
+        .or_else(|| std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok())
+        .filter(|s| !s.is_empty());
+
+    let (sig, dir) = match sig {
+        Some(sig) => {
+            let dir = signature_dir(roots, &sig);
+            (sig, dir)
+        },
+        None => {
+            let (sig, dir) = only_live_session(roots).context(Fatal::NoHyprland)?;
+            tracing::info!(sig, dir = %dir.display(), "HYPRLAND_INSTANCE_SIGNATURE is unset, using the only live session");
+            (sig, dir)
+        },
+    };
+
+    std::env::set_var("HYPRLAND_INSTANCE_SIGNATURE", &sig);
+    SESSION_DIR.lock().unwrap().get_or_insert(dir.clone());
+    Ok(dir)
+}
+
+/// Talks to the session of a restarted Hyprland from now on. HYPRLAND_INSTANCE_SIGNATURE follows, so hyprctl does too.
+pub fn rehome(sig: &str, dir: &Path) {
+    std::env::set_var("HYPRLAND_INSTANCE_SIGNATURE", sig);
+    *SESSION_DIR.lock().unwrap() = Some(dir.to_path_buf());
+}
+
+/// The live session of a Hyprland restarted under a new signature, with `current` being the one which went away.
+/// `None` while there's none, or when several sessions besides it are live and it's unclear which one took over.
+pub fn new_instance(roots: &[PathBuf], current: &Path) -> Option<(String, PathBuf)> {
+    let mut sessions: Vec<(String, PathBuf)> = live_sessions(roots).into_iter().filter(|(_, dir)| dir != current).collect();
+    match sessions.len() {
+        1 => sessions.pop(),
+        0 => None,
+        _ => {
+            let sigs: Vec<&str> = sessions.iter().map(|(sig, _)| sig.as_str()).collect();
+            tracing::warn!(sessions = sigs.join(", "), "several new Hyprland sessions, not following any");
+            None
+        },
+    }
+}
+
+/// Polls for a new instance until `timeout`, as the restarted Hyprland takes a moment to open its sockets.
+pub async fn wait_for_new_instance(roots: &[PathBuf], current: &Path, timeout: Duration, poll: Duration) -> Option<(String, PathBuf)> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(found) = new_instance(roots, current) {
+            return Some(found);
+        }
+        if tokio::time::Instant::now() + poll > deadline {
+            return None;
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
+
+/// The directory of the signature under the first root which has it.
+fn signature_dir(roots: &[PathBuf], sig: &str) -> PathBuf {
+    roots.iter().map(|r| r.join(sig)).find(|d| d.is_dir())
+        .unwrap_or_else(|| Path::new("/tmp/hypr").join(sig))
+}
+
+fn only_live_session(roots: &[PathBuf]) -> anyhow::Result<(String, PathBuf)> {
+    let mut sessions = live_sessions(roots);
+    match sessions.len() {
+        0 => bail!("HYPRLAND_INSTANCE_SIGNATURE is unset and no live Hyprland session was found in {:?}", roots),
+        1 => Ok(sessions.remove(0)),
+        _ => {
+            let sigs: Vec<&str> = sessions.iter().map(|(sig, _)| sig.as_str()).collect();
+            Err(anyhow!("HYPRLAND_INSTANCE_SIGNATURE is unset and there are several Hyprland sessions, pick one with --signature or HYPRTAG_SIGNATURE: {}", sigs.join(", ")))
+        },
+    }
+}
+
+/// Session directories whose event socket accepts connections. Sockets left behind by a crash refuse them.
+pub fn live_sessions(roots: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut sessions = vec![];
+    for root in roots {
+        let entries = match std::fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            let sig = entry.file_name().to_string_lossy().to_string();
+            if sessions.iter().any(|(s, _)| *s == sig) {
+                continue;
+            }
+            match UnixStream::connect(dir.join(".socket2.sock")) {
+                Ok(_) => sessions.push((sig, dir)),
+                Err(err) => tracing::debug!(%err, dir = %dir.display(), "skipping session"),
+            }
+        }
+    }
+    sessions.sort();
+    sessions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixListener, time::Duration};
+
+    use super::{live_sessions, new_instance, only_live_session, wait_for_new_instance};
+
+    #[test]
+    fn find_live_sessions() {
+        let root = std::env::temp_dir().join(format!("hyprtag-sessions-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let runtime = root.join("run/hypr");
+        let tmp = root.join("tmp/hypr");
+        for dir in [runtime.join("live"), runtime.join("crashed"), tmp.join("old"), root.join("empty")] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let roots = vec![runtime.clone(), tmp.clone(), root.join("missing")];
+
+        let _live = UnixListener::bind(runtime.join("live/.socket2.sock")).unwrap();
+        // bound and closed, so the file stays but nobody listens
+        drop(UnixListener::bind(runtime.join("crashed/.socket2.sock")).unwrap());
+
+        assert_eq!(live_sessions(&roots), vec![("live".to_string(), runtime.join("live"))]);
+        assert_eq!(only_live_session(&roots).unwrap().0, "live");
+
+        let _other = UnixListener::bind(tmp.join("old/.socket2.sock")).unwrap();
+        let err = only_live_session(&roots).unwrap_err().to_string();
+        assert!(err.contains("several Hyprland sessions"), "{}", err);
+        assert!(err.contains("live, old"), "{}", err);
+
+        assert!(only_live_session(&[root.join("empty")]).is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn follow_restarted_hyprland() {
+        let root = std::env::temp_dir().join(format!("hyprtag-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (old, new) = (root.join("old"), root.join("new"));
+        for dir in [&old, &new] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let roots = vec![root.clone()];
+        let poll = Duration::from_millis(10);
+
+        let live = UnixListener::bind(old.join(".socket2.sock")).unwrap();
+        assert_eq!(new_instance(&roots, &old), None);
+        // Hyprland quits, leaving its socket behind
+        drop(live);
+        assert_eq!(new_instance(&roots, &old), None);
+        assert_eq!(wait_for_new_instance(&roots, &old, Duration::from_millis(50), poll).await, None);
+
+        let bind_later = tokio::spawn({
+            let new = new.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                UnixListener::bind(new.join(".socket2.sock")).unwrap()
+            }
+        });
+        let found = wait_for_new_instance(&roots, &old, Duration::from_secs(5), poll).await;
+        assert_eq!(found, Some(("new".to_string(), new.clone())));
+        let _live = bind_later.await.unwrap();
+
+        // with another session around it's unclear which one is the restarted Hyprland
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        let _other = UnixListener::bind(root.join("other/.socket2.sock")).unwrap();
+        assert_eq!(new_instance(&roots, &old), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{activity::WindowTimes, config::{Config, PresentationConfig, XwaylandPolicy}, hyprctl::{hyprctl_clients, hyprctl_monitors, ClientInfo, MonitorInfo}, monitor::{hidden_workspace, visible_workspace}, state::WindowAddr};
+
+/// Stands in for what presentation mode hides.
+pub const REDACTED: &str = "redacted";
+
+/// hyprtag's state together with what Hyprland reported at the same moment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub state: StateSnapshot,
+    /// Left out by `hyprtagctl diff`, which only sends the state the daemon compares against.
+    #[serde(default)]
+    pub clients: Vec<ClientInfo>,
+    #[serde(default)]
+    pub monitors: Vec<MonitorInfo>,
+}
+
+impl Snapshot {
+    /// Pairs the given state with freshly queried clients and monitors.
+    pub async fn take(state: StateSnapshot) -> anyhow::Result<Self> {
+        Ok(Self {
+            state,
+            clients: hyprctl_clients().await?,
+            monitors: hyprctl_monitors().await?,
+        })
+    }
+
+    /// Hides the titles and classes of windows on the given monitors, as configured for presentation mode.
+    pub fn redact(&mut self, monitors: &[u8], config: &PresentationConfig) {
+        for client in self.clients.iter_mut().filter(|c| monitors.iter().any(|m| i64::from(*m) == c.monitor)) {
+            if config.redact_titles {
+                client.title = REDACTED.to_string();
+            }
+            if config.redact_classes {
+                client.class = REDACTED.to_string();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// Sequence number of the last state change reflected, later snapshots have higher ones.
+    #[serde(default)]
+    pub seq: u64,
+    pub active_monitor: usize,
+    pub monitors: Vec<MonitorSnapshot>,
+    /// Open and last focus time of each window, in epoch seconds.
+    #[serde(default)]
+    pub window_times: BTreeMap<String, WindowTimes>,
+    /// Active Hyprland submap, if not the default one.
+    #[serde(default)]
+    pub submap: Option<String>,
+    #[serde(default)]
+    pub xwayland_policy: XwaylandPolicy,
+    /// Tags configured as hidden, left out of cycling and bars.
+    #[serde(default)]
+    pub hidden_tags: u64,
+    /// Tracked windows Hyprland reported as XWayland clients.
+    #[serde(default)]
+    pub xwayland: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitorSnapshot {
+    pub name: String,
+    pub id: u8,
+    pub visible_tags: u64,
+    pub prev_tags: u64,
+    pub active_window: Option<String>,
+    /// Non-empty tags with their window addresses.
+    pub tags: BTreeMap<u8, Vec<String>>,
+    /// Tags with windows added or marked urgent since they were last visible, with how many.
+    #[serde(default)]
+    pub new_since_seen: BTreeMap<u8, usize>,
+    /// Left alone by hyprtag, so its windows aren't expected on any tag.
+    #[serde(default)]
+    pub unmanaged: bool,
+    /// In dim mode, so windows of hidden tags stay on the visible workspace.
+    #[serde(default)]
+    pub dim: bool,
+    /// Windows kept on the visible workspace outside of any tag.
+    #[serde(default)]
+    pub overlay: Vec<String>,
+    /// In presentation mode, which rejects commands changing its tags and redacts its windows in snapshots.
+    #[serde(default)]
+    pub presentation: bool,
+}
+
+/// A monitor as the `outputs` command lists it, without its windows.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputInfo {
+    /// Position in hyprtag's monitor list, which the workspace numbers derive from.
+    pub index: usize,
+    /// Connector name, like `DP-1`.
+    pub name: String,
+    pub description: String,
+    /// Hyprland's monitor id.
+    pub id: u8,
+    pub managed: bool,
+    /// Workspace showing the visible tags.
+    pub visible_workspace: i64,
+    /// Tag N's windows are parked on this workspace plus N while the tag is hidden.
+    pub hidden_workspace_base: i64,
+    pub visible_tags: u64,
+    pub tag_count: usize,
+    /// The monitor's tags in the order bars show them.
+    pub layout: Vec<LayoutGroup>,
+}
+
+/// Tags bars show together, with a separator between groups.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayoutGroup {
+    /// `None` for the tags in no group.
+    pub name: Option<String>,
+    pub tags: Vec<LayoutTag>,
+}
+
+/// A tag with what bars need to render it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayoutTag {
+    pub tag: u8,
+    /// From `tag_names`, the number without one.
+    pub name: String,
+    pub icon: Option<String>,
+    pub hidden: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    /// True when hyprtag and Hyprland agree on every window.
+    pub clean: bool,
+    pub visible_tags: Vec<VisibleTagsChange>,
+    pub windows: Vec<WindowChange>,
+    /// Tracked windows sitting on a workspace other than the one their tag maps to.
+    pub misplaced: Vec<Misplaced>,
+    /// Windows Hyprland has that hyprtag doesn't track.
+    pub untracked: Vec<String>,
+    /// Tracked windows Hyprland doesn't know about anymore.
+    pub dead: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct VisibleTagsChange {
+    pub monitor: String,
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WindowChange {
+    pub addr: String,
+    pub before: Option<Placement>,
+    pub after: Option<Placement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Placement {
+    pub monitor: String,
+    pub tag: u8,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Misplaced {
+    pub addr: String,
+    pub expected_workspace: i64,
+    pub actual_workspace: i64,
+}
+
+impl StateSnapshot {
+    /// Where each tracked window is, with the workspace it should be on.
+    /// Addresses are parsed, so snapshots written with padded or prefixed addresses compare equal.
+    fn placements(&self, tag_count: u8) -> HashMap<WindowAddr, (Placement, i64)> {
+        let mut placements = HashMap::new();
+        for (index, monitor) in self.monitors.iter().enumerate() {
+            for (tag, windows) in monitor.tags.iter() {
+                let workspace = if monitor.dim || monitor.visible_tags & 1 << (tag - 1) != 0 {
+                    visible_workspace(index)
+                } else {
+                    hidden_workspace(tag_count, index, *tag)
+                };
+                for w in windows {
+                    let w: WindowAddr = match w.parse() {
+                        Ok(w) => w,
+                        Err(err) => {
+                            tracing::warn!(%err, "skipping window in snapshot");
+                            continue;
+                        },
+                    };
+                    // sticky windows never leave the visible workspace
+                    let workspace = if self.xwayland_policy == XwaylandPolicy::Sticky && self.xwayland.iter().any(|x| x.parse().ok() == Some(w)) {
+                        visible_workspace(index)
+                    } else {
+                        workspace
+                    };
+                    let placement = Placement { monitor: monitor.name.clone(), tag: *tag };
+                    placements.insert(w, (placement, workspace));
+                }
+            }
+        }
+        placements
+    }
+}
+
+/// The tags up to `tag_count` in display order: the configured groups, then the tags in none by number.
+pub fn layout(config: &Config, tag_count: usize) -> Vec<LayoutGroup> {
+    let tag = |tag: u8| LayoutTag {
+        tag,
+        name: config.tag_names.get(&tag).cloned().unwrap_or_else(|| tag.to_string()),
+        icon: config.tags.get(&tag).and_then(|t| t.icon.clone()),
+        hidden: config.tags.get(&tag).is_some_and(|t| t.hidden),
+    };
+    let in_range = |t: &&u8| (1..=tag_count).contains(&(**t as usize));
+
+    let mut layout: Vec<LayoutGroup> = config.tag_groups.iter().map(|group| LayoutGroup {
+        name: Some(group.name.clone()),
+        tags: group.tags.iter().filter(in_range).map(|t| tag(*t)).collect(),
+    }).collect();
+    let ungrouped: Vec<LayoutTag> = (1..=tag_count as u8)
+        .filter(|t| !config.tag_groups.iter().any(|g| g.tags.contains(t)))
+        .map(tag)
+        .collect();
+    if !ungrouped.is_empty() {
+        layout.push(LayoutGroup { name: None, tags: ungrouped });
+    }
+    layout
+}
+
+/// Compares the state from an earlier snapshot with the current one, and the current state with live clients.
+/// Hidden workspaces are numbered by `tag_count`, the tags of each monitor.
+pub fn diff(before: &StateSnapshot, now: &StateSnapshot, clients: &[ClientInfo], tag_count: u8) -> DiffReport {
+    let mut report = DiffReport::default();
+
+    let mut monitor_names: Vec<&str> = before.monitors.iter().chain(now.monitors.iter()).map(|m| m.name.as_str()).collect();
+    monitor_names.sort();
+    monitor_names.dedup();
+    for name in monitor_names {
+        let b = before.monitors.iter().find(|m| m.name == name).map(|m| m.visible_tags);
+        let a = now.monitors.iter().find(|m| m.name == name).map(|m| m.visible_tags);
+        if b != a {
+            report.visible_tags.push(VisibleTagsChange { monitor: name.to_string(), before: b, after: a });
+        }
+    }
+
+    let before_placements = before.placements(tag_count);
+    let now_placements = now.placements(tag_count);
+
+    let mut addrs: Vec<WindowAddr> = before_placements.keys().chain(now_placements.keys()).copied().collect();
+    addrs.sort();
+    addrs.dedup();
+    for addr in addrs {
+        let b = before_placements.get(&addr).map(|(p, _)| p.clone());
+        let a = now_placements.get(&addr).map(|(p, _)| p.clone());
+        if b != a {
+            report.windows.push(WindowChange { addr: addr.to_string(), before: b, after: a });
+        }
+    }
+
+    let live: HashMap<WindowAddr, &ClientInfo> = clients.iter()
+        .filter_map(|c| Some((c.window().ok()?, c)))
+        .collect();
+
+    let mut tracked: Vec<(&WindowAddr, &(Placement, i64))> = now_placements.iter().collect();
+    tracked.sort_by_key(|(addr, _)| **addr);
+    for (addr, (_, expected)) in tracked {
+        match live.get(addr) {
+            Some(client) if client.workspace.id != *expected => report.misplaced.push(Misplaced {
+                addr: addr.to_string(),
+                expected_workspace: *expected,
+                actual_workspace: client.workspace.id,
+            }),
+            Some(_) => (),
+            None => report.dead.push(addr.to_string()),
+        }
+    }
+
+    let unmanaged: Vec<i64> = now.monitors.iter().filter(|m| m.unmanaged).map(|m| m.id as i64).collect();
+    let overlay: Vec<WindowAddr> = now.monitors.iter().flat_map(|m| m.overlay.iter().filter_map(|w| w.parse().ok())).collect();
+    let mut untracked: Vec<WindowAddr> = live.iter()
+        .filter(|(addr, client)| !now_placements.contains_key(*addr) && !unmanaged.contains(&client.monitor) && !overlay.contains(addr))
+        .map(|(addr, _)| addr)
+        .copied()
+        .collect();
+    untracked.sort();
+    report.untracked = untracked.iter().map(|addr| addr.to_string()).collect();
+
+    report.clean = report.misplaced.is_empty() && report.untracked.is_empty() && report.dead.is_empty();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{config::{Config, PresentationConfig, XwaylandPolicy}, hyprctl::{ClientInfo, WorkspaceRef}, state::DEFAULT_TAG_COUNT};
+
+    use super::{diff, layout, MonitorSnapshot, Placement, Snapshot, StateSnapshot};
+
+    fn state(visible_tags: u64, tags: &[(u8, &[&str])]) -> StateSnapshot {
+        StateSnapshot {
+            seq: 0,
+            active_monitor: 0,
+            monitors: vec![MonitorSnapshot {
+                name: "DP-1".into(),
+                id: 0,
+                visible_tags,
+                prev_tags: 1,
+                active_window: None,
+                tags: tags.iter().map(|(t, w)| (*t, w.iter().map(|w| w.to_string()).collect())).collect::<BTreeMap<_, _>>(),
+                new_since_seen: BTreeMap::new(),
+                unmanaged: false,
+                dim: false,
+                overlay: vec![],
+                presentation: false,
+            }],
+            window_times: BTreeMap::new(),
+            submap: None,
+            xwayland_policy: XwaylandPolicy::Normal,
+            hidden_tags: 0,
+            xwayland: vec![],
+        }
+    }
+
+    fn client(address: &str, workspace: i64) -> ClientInfo {
+        ClientInfo {
+            address: address.into(),
+            workspace: WorkspaceRef { id: workspace, name: workspace.to_string() },
+            monitor: 0,
+            class: String::new(),
+            title: String::new(),
+            fullscreen: 0,
+            xwayland: false,
+            pid: 0,
+            at: [0, 0],
+            size: [0, 0],
+        }
+    }
+
+    #[test]
+    fn clean_diff() {
+        let s = state(0b1, &[(1, &["aa"]), (2, &["bb"])]);
+        let report = diff(&s, &s, &[client("0xaa", 1), client("0xbb", 102)], DEFAULT_TAG_COUNT);
+
+        assert!(report.clean);
+        assert!(report.visible_tags.is_empty());
+        assert!(report.windows.is_empty());
+
+        // the same windows written with padded or uppercase addresses
+        let padded = state(0b1, &[(1, &["0x00AA"]), (2, &["000bb"])]);
+        let report = diff(&padded, &s, &[client("0x0000aa", 1), client("0xBB", 102)], DEFAULT_TAG_COUNT);
+        assert!(report.clean);
+        assert!(report.windows.is_empty());
+
+        // sticky XWayland windows stay visible while their tag is hidden
+        let mut s = s;
+        s.xwayland_policy = XwaylandPolicy::Sticky;
+        s.xwayland = vec!["bb".into()];
+        assert!(diff(&s, &s, &[client("0xaa", 1), client("0xbb", 1)], DEFAULT_TAG_COUNT).clean);
+    }
+
+    #[test]
+    fn state_changes_and_discrepancies() {
+        let before = state(0b1, &[(1, &["aa", "bb"]), (2, &["cc"])]);
+        let now = state(0b10, &[(1, &["aa"]), (2, &["cc", "bb"]), (3, &["dd"])]);
+
+        let report = diff(&before, &now, &[
+            client("0xaa", 1),   // should be hidden on 101
+            client("0xbb", 1),
+            client("0xcc", 1),
+            client("0xee", 1),
+        ], DEFAULT_TAG_COUNT);
+
+        assert!(!report.clean);
+        assert_eq!(report.visible_tags.len(), 1);
+        assert_eq!(report.visible_tags[0].before, Some(0b1));
+        assert_eq!(report.visible_tags[0].after, Some(0b10));
+
+        assert_eq!(report.windows.len(), 2);
+        assert_eq!(report.windows[0].addr, "bb");
+        assert_eq!(report.windows[0].after, Some(Placement { monitor: "DP-1".into(), tag: 2 }));
+        assert_eq!(report.windows[1].addr, "dd");
+        assert_eq!(report.windows[1].before, None);
+
+        assert_eq!(report.misplaced.len(), 1);
+        assert_eq!(report.misplaced[0].addr, "aa");
+        assert_eq!(report.misplaced[0].expected_workspace, 101);
+        assert_eq!(report.untracked, vec!["ee"]);
+        assert_eq!(report.dead, vec!["dd"]);
+
+        // overlay windows are on no tag, but still tracked
+        let mut now = now;
+        now.monitors[0].overlay = vec!["ee".into()];
+        assert!(diff(&before, &now, &[client("0xee", 1)], DEFAULT_TAG_COUNT).untracked.is_empty());
+    }
+
+    #[test]
+    fn redact_presenting_monitors() {
+        let named = |address: &str, monitor: i64| ClientInfo { monitor, class: "chat".into(), title: "dm from alice".into(), ..client(address, 1) };
+        let mut snapshot = Snapshot { state: state(0b1, &[]), clients: vec![named("0xaa", 0), named("0xbb", 1)], monitors: vec![] };
+
+        snapshot.redact(&[0], &PresentationConfig { redact_titles: true, redact_classes: false });
+        assert_eq!((snapshot.clients[0].class.as_str(), snapshot.clients[0].title.as_str()), ("chat", "redacted"));
+        // other monitors aren't presenting
+        assert_eq!((snapshot.clients[1].class.as_str(), snapshot.clients[1].title.as_str()), ("chat", "dm from alice"));
+
+        snapshot.redact(&[0, 1], &PresentationConfig::default());
+        assert!(snapshot.clients.iter().all(|c| c.class == "redacted" && c.title == "redacted"));
+    }
+
+    #[test]
+    fn layout_schema() {
+        let config = Config::parse(r#"{
+            "tag_names": {"1": "web", "3": "mail"},
+            "tags": {"1": {"icon": "W"}, "4": {"hidden": true}},
+            "tag_groups": [{"name": "work", "tags": [3, 1]}, {"name": "chat", "tags": [5, 40]}]
+        }"#).unwrap();
+
+        assert_eq!(serde_json::to_value(layout(&config, 5)).unwrap(), serde_json::json!([
+            {"name": "work", "tags": [
+                {"tag": 3, "name": "mail", "icon": null, "hidden": false},
+                {"tag": 1, "name": "web", "icon": "W", "hidden": false},
+            ]},
+            {"name": "chat", "tags": [
+                {"tag": 5, "name": "5", "icon": null, "hidden": false},
+            ]},
+            {"name": null, "tags": [
+                {"tag": 2, "name": "2", "icon": null, "hidden": false},
+                {"tag": 4, "name": "4", "icon": null, "hidden": true},
+            ]},
+        ]));
+
+        let layout = layout(&Config::default(), 32);
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].tags.iter().map(|t| t.tag).collect::<Vec<_>>(), (1..=32).collect::<Vec<_>>());
+    }
+}
@@ -1,26 +1,132 @@
-use std::{collections::HashSet, hash::Hash};
+use std::{collections::{BTreeMap, HashSet}, fmt, hash::Hash, str::FromStr};
 
 use anyhow::bail;
 
+/// Most tags a monitor can have, as many as a tag mask has bits.
+pub const MAX_TAGS: u8 = 64;
+
+/// Tags of each monitor unless `tag_count` is configured.
+pub const DEFAULT_TAG_COUNT: u8 = 32;
+
+/// Fails for tags outside `1..=tag_count`, like a keybind's `show 0`.
+pub fn check_tag(tag: u8, tag_count: u8) -> anyhow::Result<()> {
+    if !(1..=tag_count).contains(&tag) {
+        bail!("no such tag: {}, tags go up to {}", tag, tag_count);
+    }
+    Ok(())
+}
+
+/// Fails for a mask of no tags, or with tags beyond `tag_count`.
+pub fn check_tags(tags: u64, tag_count: u8) -> anyhow::Result<()> {
+    if tags == 0 {
+        bail!("no tags given");
+    }
+    check_tag(64 - tags.leading_zeros() as u8, tag_count)
+}
+
+/// Which of the visible tags is the active one, the tag new windows go to, when several are visible.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActiveTagPolicy {
+    /// The tag of the focused window, the lowest visible tag while no window on them has focus. What hyprtag went
+    /// by before there was a choice, now also following focus between visible tags.
+    #[default]
+    FocusedWindow,
+    /// The lowest visible tag, wherever focus is.
+    LowestVisible,
+    /// The tag that last became visible, the lowest of them when several did at once. Hiding it falls back to the
+    /// lowest visible tag.
+    LastShown,
+}
+
 #[derive(Debug, Clone)]
 pub struct State {
     tags: Vec<Tag>,
-    visible_tags: u32,
-    prev_tags: u32,
+    visible_tags: u64,
+    prev_tags: u64,
     active_tag_index: usize,
-    active_window: Option<String>,
+    active_tag_policy: ActiveTagPolicy,
+    active_window: Option<WindowAddr>,
+}
+
+/// Window address as reported by Hyprland.
+///
+/// Parsed to the pointer value, so padded, prefixed and uppercase spellings of an address are the same window.
+/// Displayed as lowercase hex without the `0x` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WindowAddr(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidWindowAddr(String);
+
+impl fmt::Display for InvalidWindowAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid window address: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidWindowAddr {}
+
+impl FromStr for WindowAddr {
+    type Err = InvalidWindowAddr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(InvalidWindowAddr(s.to_string()));
+        }
+        u64::from_str_radix(hex, 16).map(Self).map_err(|_| InvalidWindowAddr(s.to_string()))
+    }
+}
+
+impl From<u64> for WindowAddr {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn addr(s: &str) -> WindowAddr {
+    s.parse().unwrap()
+}
+
+impl fmt::Display for WindowAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+
+/// As its displayed string, like the ctrl commands take it.
+impl serde::Serialize for WindowAddr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for WindowAddr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Changes {
     pub window_added: Vec<WindowInfo>,
     pub window_removed: Vec<WindowInfo>,
-    pub focus: Option<String>,
+    pub focus: Option<WindowAddr>,
+    pub shown_tags: u64,
+    pub hidden_tags: u64,
+    /// Windows to dim, on a monitor showing hidden tags dimmed instead of moving them away.
+    pub dimmed: Vec<WindowAddr>,
+    /// Dimmed windows to bring back to full opacity.
+    pub undimmed: Vec<WindowAddr>,
+    /// The active tag after the visible tags changed, for hooks.
+    pub active_tag: Option<u8>,
 }
 
 #[derive(Debug, Clone, Eq)]
 pub struct WindowInfo {
-    pub addr: String,
+    pub addr: WindowAddr,
     pub tag: u8,
 }
 
@@ -37,12 +143,15 @@ impl Hash for WindowInfo {
 }
 
 impl State {
-    pub fn new() -> Self {
+    /// A state with tags 1 to `count`, at most `MAX_TAGS`.
+    pub fn new(count: u8) -> Self {
+        let count = count.clamp(1, MAX_TAGS);
         State {
-            tags: (1..=32).map(|n| Tag::new(n)).collect(),
+            tags: (1..=count).map(Tag::new).collect(),
             visible_tags: 1,
             prev_tags: 1,
             active_tag_index: 0,
+            active_tag_policy: ActiveTagPolicy::default(),
             active_window: None,
         }
     }
@@ -50,48 +159,147 @@ impl State {
     pub fn debug_dump(&self) -> String {
         let mut s = String::new();
         for tag in self.tags.iter() {
-            if tag.window_addrs.len() == 0 {
+            if tag.window_addrs.is_empty() {
                 continue;
             }
-            s += format!("{}: {}\n", tag.id, tag.window_addrs.join(",")).as_str();
+            let addrs: Vec<String> = tag.window_addrs.iter().map(|w| w.to_string()).collect();
+            s += format!("{}: {}\n", tag.id, addrs.join(",")).as_str();
         }
         s
     }
 
-    pub fn visible_tags(&self) -> u32 {
+    pub fn visible_tags(&self) -> u64 {
         self.visible_tags
     }
 
-    pub fn active_window(&self) -> Option<String> {
-        match &self.active_window {
-            Some(w) => Some(w.clone()),
-            None => None,
+    pub fn prev_tags(&self) -> u64 {
+        self.prev_tags
+    }
+
+    /// Tags with windows added or marked urgent since they were last visible, with how many.
+    pub fn new_since_seen(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        self.tags.iter()
+            .map(|t| (t.id, t.window_addrs.len().saturating_sub(t.seen_count) + t.urgent))
+            .filter(|(_, n)| *n > 0)
+    }
+
+    /// Tags with urgent windows since they were last visible.
+    pub fn urgent_tags(&self) -> impl Iterator<Item = u8> + '_ {
+        self.tags.iter().filter(|t| t.urgent > 0).map(|t| t.id)
+    }
+
+    /// Tag ids with their windows, in tag order.
+    pub fn tag_windows(&self) -> impl Iterator<Item = (u8, &[WindowAddr])> {
+        self.tags.iter().map(|t| (t.id, t.window_addrs.as_slice()))
+    }
+
+    /// Names the tags, the ones missing from `names` going by their number. Their numbers stay the same.
+    pub fn set_tag_names(&mut self, names: &BTreeMap<u8, String>) {
+        for tag in self.tags.iter_mut() {
+            tag.name = names.get(&tag.id).cloned();
         }
     }
 
+    /// The tag's name, its number without one.
+    pub fn tag_name(&self, tag: u8) -> String {
+        self.tags.get(tag as usize - 1).and_then(|t| t.name.clone()).unwrap_or_else(|| tag.to_string())
+    }
+
+    pub fn active_window(&self) -> Option<WindowAddr> {
+        self.active_window
+    }
+
     pub fn active_tag_index(&self) -> usize {
         self.active_tag_index
     }
 
-    pub fn set_visible_tags(&mut self, tags: u32) -> anyhow::Result<Changes> {
+    /// Number of tags, as the state was created with.
+    pub fn tag_count(&self) -> u8 {
+        self.tags.len() as u8
+    }
+
+    pub fn set_active_tag_policy(&mut self, policy: ActiveTagPolicy) {
+        self.active_tag_policy = policy;
+        self.update_active_tag(0);
+    }
+
+    /// Picks the active tag by the policy, `shown` being the tags which just became visible.
+    fn update_active_tag(&mut self, shown: u64) {
+        let visible = |i: &usize| self.visible_tags & 1<<i != 0;
+        let lowest = self.visible_tags.trailing_zeros() as usize;
+        self.active_tag_index = match self.active_tag_policy {
+            ActiveTagPolicy::FocusedWindow => self.active_window
+                .and_then(|w| self.find_window_tag_index(&w))
+                .filter(visible)
+                .unwrap_or(lowest),
+            ActiveTagPolicy::LowestVisible => lowest,
+            ActiveTagPolicy::LastShown if shown != 0 => shown.trailing_zeros() as usize,
+            ActiveTagPolicy::LastShown => Some(self.active_tag_index).filter(visible).unwrap_or(lowest),
+        };
+    }
+
+    /// Index of the tag, failing for 0 and tags beyond this state's.
+    fn tag_index(&self, tag: u8) -> anyhow::Result<usize> {
+        match tag.checked_sub(1).filter(|i| (*i as usize) < self.tags.len()) {
+            Some(i) => Ok(i as usize),
+            None => bail!("no such tag: {}", tag),
+        }
+    }
+
+    /// Bits of a tag mask that refer to existing tags.
+    fn tag_mask(&self) -> u64 {
+        if self.tags.len() >= MAX_TAGS as usize {
+            u64::MAX
+        } else {
+            (1 << self.tags.len()) - 1
+        }
+    }
+
+    /// Clears bits that don't refer to existing tags from the visible and previous masks.
+    fn heal_tag_masks(&mut self) {
+        let mask = self.tag_mask();
+
+        if self.visible_tags & !mask != 0 {
+            tracing::error!(visible_tags = self.visible_tags, "visible tags refer to non-existent tags, clearing them");
+            self.visible_tags &= mask;
+            if self.visible_tags == 0 {
+                self.visible_tags = 1;
+            }
+        }
+        if self.prev_tags & !mask != 0 {
+            tracing::error!(prev_tags = self.prev_tags, "previous tags refer to non-existent tags, clearing them");
+            self.prev_tags &= mask;
+            if self.prev_tags == 0 {
+                self.prev_tags = 1;
+            }
+        }
+    }
+
+    pub fn set_visible_tags(&mut self, tags: u64) -> anyhow::Result<Changes> {
+        if tags & !self.tag_mask() != 0 {
+            tracing::warn!(tags, "ignoring tags beyond tag count {}", self.tags.len());
+        }
+        let tags = tags & self.tag_mask();
         if tags == 0 {
             bail!("at least one tag need to be visible");
         }
 
+        self.heal_tag_masks();
+
         let w1 = self.visible_windows();
 
-        self.prev_tags = self.visible_tags;
+        let old_tags = self.visible_tags;
+        // showing what's already shown keeps the way back to the view before it
+        if tags != old_tags {
+            self.prev_tags = old_tags;
+        }
 
         let mut first_window = None;
-        let mut first_tag_index = None;
-        for n in 0..32 {
+        for n in 0..self.tags.len() {
             if tags & 1<<n != 0 {
                 self.visible_tags |= 1<<n;
-                if first_window.is_none() && self.tags[n].window_addrs.len() > 0 {
-                    first_window = Some(self.tags[n].window_addrs[0].clone());
-                }
-                if first_tag_index.is_none() {
-                    first_tag_index = Some(n);
+                if first_window.is_none() && !self.tags[n].window_addrs.is_empty() {
+                    first_window = Some(self.tags[n].window_addrs[0]);
                 }
             } else {
                 self.visible_tags &= !(1<<n);
@@ -102,67 +310,119 @@ impl State {
 
         let (window_added, window_removed) = window_diff(w1, w2);
 
-        let active_tag_index = if let Some(active_window) = &self.active_window {
-            self.find_window_tag_index(&active_window)
+        let active_window_tag_index = if let Some(active_window) = &self.active_window {
+            self.find_window_tag_index(active_window)
         } else {
             None
         };
 
-        let focus = if active_tag_index.is_some() && tags & 1<<active_tag_index.unwrap() != 0 {
-            self.active_tag_index = active_tag_index.unwrap();
-            self.active_window.clone()
+        let focus = if active_window_tag_index.is_some_and(|i| tags & 1<<i != 0) {
+            self.active_window
         } else {
-            self.active_tag_index = first_tag_index.unwrap();
             self.active_window = None;
             first_window
         };
 
+        let shown_tags = self.visible_tags & !old_tags;
+        self.update_active_tag(shown_tags);
+        self.update_seen_counts();
+
         Ok(Changes {
             window_added,
             window_removed,
             focus,
+            shown_tags,
+            hidden_tags: old_tags & !self.visible_tags,
+            active_tag: Some(self.active_tag_index as u8 + 1),
+            ..Default::default()
         })
     }
 
+    /// Swaps the visible and previous tags, so repeated restores go back and forth between two views
+    /// like Hyprland's `workspace previous`.
     pub fn restore_prev_tags(&mut self) -> anyhow::Result<Changes> {
-        self.set_visible_tags(self.prev_tags)
+        self.heal_tag_masks();
+        let (visible, prev) = (self.visible_tags, self.prev_tags);
+        let changes = self.set_visible_tags(prev)?;
+        self.prev_tags = visible;
+        Ok(changes)
     }
 
-    pub fn toggle_tag(&mut self, tag: u8) -> anyhow::Result<Changes> {
-        let tag_index = tag - 1;
+    /// Shows or hides the tag. With `focus`, a tag that becomes visible gets focus on its first window.
+    pub fn toggle_tag(&mut self, tag: u8, focus: bool) -> anyhow::Result<Changes> {
+        let tag_index = self.tag_index(tag)? as u8;
 
-        let tags = if self.visible_tags & 1<<tag_index != 0 {
-            self.visible_tags & !(1<<tag_index)
-        } else {
+        let showing = self.visible_tags & 1<<tag_index == 0;
+        let tags = if showing {
             self.visible_tags | 1<<tag_index
+        } else {
+            self.visible_tags & !(1<<tag_index)
         };
 
-        self.set_visible_tags(tags)
+        let mut changes = self.set_visible_tags(tags)?;
+
+        if focus && showing {
+            if let Some(window) = self.tags.get(tag_index as usize).and_then(|t| t.window_addrs.first()) {
+                // focus is on its way there
+                if self.active_tag_policy == ActiveTagPolicy::FocusedWindow {
+                    self.active_tag_index = tag_index as usize;
+                    changes.active_tag = Some(tag_index + 1);
+                }
+                changes.focus = Some(*window);
+            }
+        }
+
+        Ok(changes)
     }
 
-    pub fn new_window_added(&mut self, window: String) -> anyhow::Result<()> {
-        if let Some(_) = self.find_window_tag_index(&window) {
+    pub fn new_window_added(&mut self, window: WindowAddr) -> anyhow::Result<()> {
+        if self.find_window_tag_index(&window).is_some() {
             bail!("the window:{} is already in our state", window);
         }
 
         if let Some(tag) = self.tags.get_mut(self.active_tag_index) {
             tag.window_addrs.push(window);
         }
+        self.update_seen_counts();
 
         Ok(())
     }
 
-    pub fn focus_window_changed(&mut self, window: String, is_new: bool) -> anyhow::Result<()> {
+    /// Adds a new window to the given tag, hiding it right away when the tag isn't visible.
+    pub fn new_window_added_to_tag(&mut self, window: WindowAddr, tag: u8) -> anyhow::Result<Changes> {
+        if self.find_window_tag_index(&window).is_some() {
+            bail!("the window:{} is already in our state", window);
+        }
+
+        let tag_index = self.tag_index(tag)?;
+        self.tags[tag_index].window_addrs.push(window);
+        self.update_seen_counts();
+
+        let mut changes = Changes::default();
+        if self.visible_tags & 1<<tag_index == 0 {
+            changes.window_removed.push(WindowInfo { addr: window, tag });
+        }
+        Ok(changes)
+    }
+
+    pub fn focus_window_changed(&mut self, window: WindowAddr, is_new: bool) -> anyhow::Result<()> {
         if is_new {
-            self.new_window_added(window.clone())?;
+            self.new_window_added(window)?;
         }
 
         self.active_window = Some(window);
+        self.update_active_tag(0);
 
         Ok(())
     }
 
-    pub fn window_removed(&mut self, window: String) -> anyhow::Result<()> {
+    /// Focus went to the desktop or a layer surface. Tags are left as they are.
+    pub fn focus_cleared(&mut self) {
+        self.active_window = None;
+        self.update_active_tag(0);
+    }
+
+    pub fn window_removed(&mut self, window: WindowAddr) -> anyhow::Result<()> {
         let (tag_index, window_index) = match self.find_window_indexes(&window) {
             Some(indexes) => indexes,
             None => bail!("no such window in our states"),
@@ -171,15 +431,17 @@ impl State {
         if let Some(tag) = self.tags.get_mut(tag_index) {
             tag.window_addrs.remove(window_index);
         }
+        self.update_seen_counts();
+        self.update_active_tag(0);
 
         Ok(())
     }
 
-    pub fn move_window(&mut self, dest_tag: u8, window: Option<String>) -> anyhow::Result<Changes> {
-        let dest_tag_index = (dest_tag - 1) as usize;
-        let window = match window.or(self.active_window.clone()) {
+    pub fn move_window(&mut self, dest_tag: u8, window: Option<WindowAddr>) -> anyhow::Result<Changes> {
+        let dest_tag_index = self.tag_index(dest_tag)?;
+        let window = match window.or(self.active_window) {
             Some(w) => w,
-            None => bail!("couldn't find active window"),
+            None => bail!("no focused window"),
         };
 
         let (tag_index, window_index) = match self.find_window_indexes(&window) {
@@ -202,6 +464,8 @@ impl State {
         if let Some(tag) = self.tags.get_mut(tag_index) {
             tag.window_addrs.remove(window_index);
         }
+        self.update_seen_counts();
+        self.update_active_tag(0);
 
         let w2 = self.visible_windows();
 
@@ -210,25 +474,131 @@ impl State {
         Ok(Changes {
             window_added,
             window_removed,
-            focus: None,
+            ..Default::default()
         })
     }
 
-    pub fn visible_windows(&self) -> Vec<WindowInfo> {
-        let mut windows = vec![];
-        for n in 0..32 {
+    /// Moves the window, or the active one, to `dest_tag` and shows only that tag, as one change ending with focus
+    /// on the window. The tags visible before the move become the previous ones.
+    pub fn move_window_and_show(&mut self, dest_tag: u8, window: Option<WindowAddr>) -> anyhow::Result<Changes> {
+        let dest_tag_index = self.tag_index(dest_tag)?;
+        let window = match window.or(self.active_window) {
+            Some(w) => w,
+            None => bail!("no focused window"),
+        };
+
+        let w1 = self.visible_windows();
+        if self.find_window_tag_index(&window) != Some(dest_tag_index) {
+            self.move_window(dest_tag, Some(window))?;
+        }
+        let mut changes = self.set_visible_tags(1<<dest_tag_index)?;
+        // a window visible before and after stays where it is, wherever it went in between
+        (changes.window_added, changes.window_removed) = window_diff(w1, self.visible_windows());
+        changes.focus = Some(window);
+        Ok(changes)
+    }
+
+    /// Counts an urgent window on a hidden tag towards the tag's `new_since_seen`.
+    /// Returns false when the window's tag is visible.
+    pub fn window_urgent(&mut self, window: &WindowAddr) -> anyhow::Result<bool> {
+        let tag_index = match self.find_window_tag_index(window) {
+            Some(i) => i,
+            None => bail!("no such window in our states"),
+        };
+        if self.visible_tags & 1<<tag_index != 0 {
+            return Ok(false);
+        }
+        self.tags[tag_index].urgent += 1;
+        Ok(true)
+    }
+
+    /// Visible tags have been seen as they are. Hidden tags which lost windows count new ones from their
+    /// current size.
+    fn update_seen_counts(&mut self) {
+        for (n, tag) in self.tags.iter_mut().enumerate() {
             if self.visible_tags & 1<<n != 0 {
-                let tag = self.tags.get(n).unwrap();
-                windows.extend(tag.window_addrs.iter().map(|w| WindowInfo { addr: w.clone(), tag: tag.id }).collect::<Vec<WindowInfo>>());
+                tag.seen_count = tag.window_addrs.len();
+                tag.urgent = 0;
+            } else {
+                tag.seen_count = tag.seen_count.min(tag.window_addrs.len());
             }
         }
+    }
+
+    /// Moves the window to the front of its tag's window list.
+    pub fn promote_window(&mut self, window: Option<WindowAddr>) -> anyhow::Result<()> {
+        let (tag_index, window_index) = self.find_window_or_active(window)?;
+        let window_addrs = &mut self.tags[tag_index].window_addrs;
+        let w = window_addrs.remove(window_index);
+        window_addrs.insert(0, w);
+        Ok(())
+    }
+
+    /// Moves the window to the back of its tag's window list.
+    pub fn demote_window(&mut self, window: Option<WindowAddr>) -> anyhow::Result<()> {
+        let (tag_index, window_index) = self.find_window_or_active(window)?;
+        let window_addrs = &mut self.tags[tag_index].window_addrs;
+        let w = window_addrs.remove(window_index);
+        window_addrs.push(w);
+        Ok(())
+    }
+
+    /// Puts the given windows at the front of the tag in the given order.
+    /// Windows not listed keep their relative order after them.
+    pub fn reorder_tag(&mut self, tag: u8, windows: Vec<WindowAddr>) -> anyhow::Result<()> {
+        let tag = match tag.checked_sub(1).and_then(|i| self.tags.get_mut(i as usize)) {
+            Some(tag) => tag,
+            None => bail!("no such tag: {}", tag),
+        };
+
+        for (i, w) in windows.iter().enumerate() {
+            if !tag.window_addrs.contains(w) {
+                bail!("window:{} is not in tag {}", w, tag.id);
+            }
+            if windows[..i].contains(w) {
+                bail!("window:{} is listed twice", w);
+            }
+        }
+
+        let rest: Vec<WindowAddr> = tag.window_addrs.iter().filter(|w| !windows.contains(w)).cloned().collect();
+        tag.window_addrs = windows;
+        tag.window_addrs.extend(rest);
+
+        Ok(())
+    }
+
+    fn find_window_or_active(&self, window: Option<WindowAddr>) -> anyhow::Result<(usize, usize)> {
+        let window = match window.or(self.active_window) {
+            Some(w) => w,
+            None => bail!("no focused window"),
+        };
+
+        match self.find_window_indexes(&window) {
+            Some(indexes) => Ok(indexes),
+            None => bail!("no such window in our states"),
+        }
+    }
+
+    pub fn visible_windows(&self) -> Vec<WindowInfo> {
+        let visible = || self.tags.iter().enumerate().filter(|(n, _)| self.visible_tags & 1<<n != 0);
+
+        let len = visible().map(|(_, tag)| tag.window_addrs.len()).sum();
+        let mut windows = Vec::with_capacity(len);
+        for (_, tag) in visible() {
+            windows.extend(tag.window_addrs.iter().map(|w| WindowInfo { addr: *w, tag: tag.id }));
+        }
         windows
     }
 
-    pub fn find_window_indexes(&self, addr: &str) -> Option<(usize, usize)> {
+    #[cfg(test)]
+    fn set_visible_tags_unchecked(&mut self, tags: u64) {
+        self.visible_tags = tags;
+    }
+
+    pub fn find_window_indexes(&self, addr: &WindowAddr) -> Option<(usize, usize)> {
         self.tags.iter().enumerate().find_map(|(tag_index, tag)| {
             tag.window_addrs.iter().enumerate().find_map(|(window_index, w)| {
-                if *w == addr {
+                if w == addr {
                     Some((tag_index, window_index))
                 } else {
                     None
@@ -237,16 +607,36 @@ impl State {
         })
     }
 
-    pub fn find_window_tag_index(&self, addr: &str) -> Option<usize> {
+    pub fn find_window_tag_index(&self, addr: &WindowAddr) -> Option<usize> {
         self.tags.iter().enumerate().find_map(|(tag_index, tag)| {
-            match tag.window_addrs.iter().find(|w| *w == addr) {
-                Some(_) => Some(tag_index),
-                None => None,
-            }
+            tag.window_addrs.iter().find(|w| *w == addr).map(|_| tag_index)
         })
     }
 
-    pub fn all_window_addrs(&self) -> Vec<String> {
+    /// The lowest tag without windows, except the `hidden` ones.
+    pub fn first_empty_tag(&self, hidden: u64) -> anyhow::Result<u8> {
+        match self.tags.iter().find(|t| t.window_addrs.is_empty() && hidden & t.mask() == 0) {
+            Some(tag) => Ok(tag.id),
+            None => bail!("no empty tag"),
+        }
+    }
+
+    /// The tag with windows closest to `tag`, the lower one when two are as close. `hidden` tags don't count.
+    pub fn nearest_occupied_tag(&self, tag: u8, hidden: u64) -> Option<u8> {
+        self.tags.iter()
+            .filter(|t| !t.window_addrs.is_empty() && hidden & t.mask() == 0)
+            .min_by_key(|t| (t.id.abs_diff(tag), t.id))
+            .map(|t| t.id)
+    }
+
+    /// Mask of the tags with windows.
+    pub fn occupied_tags(&self) -> u64 {
+        self.tags.iter()
+            .filter(|t| !t.window_addrs.is_empty())
+            .fold(0, |mask, t| mask | t.mask())
+    }
+
+    pub fn all_window_addrs(&self) -> Vec<WindowAddr> {
         self.tags.iter().flat_map(|t| t.window_addrs.clone()).collect()
     }
 }
@@ -254,21 +644,42 @@ impl State {
 #[derive(Debug, Clone)]
 pub struct Tag {
     id: u8,
-    window_addrs: Vec<String>,
+    /// What commands and bars call it besides its number.
+    name: Option<String>,
+    window_addrs: Vec<WindowAddr>,
+    /// Window count when the tag was last visible.
+    seen_count: usize,
+    /// Urgent events while hidden.
+    urgent: usize,
 }
 
 impl Tag {
     fn new(id: u8) -> Self {
         Self {
             id,
+            name: None,
             window_addrs: vec![],
+            seen_count: 0,
+            urgent: 0,
         }
     }
+
+    fn mask(&self) -> u64 {
+        1<<(self.id - 1)
+    }
+}
+
+/// The tag after `from` in the direction among the `candidates` mask, wrapping around. `from` itself when it's the
+/// only candidate, `None` without any.
+pub fn cycle_tag(from: u8, forward: bool, candidates: u64) -> Option<u8> {
+    (1..=MAX_TAGS)
+        .map(|offset| if forward { (from - 1 + offset) % MAX_TAGS + 1 } else { (from + MAX_TAGS - 1 - offset) % MAX_TAGS + 1 })
+        .find(|tag| candidates & 1<<(tag - 1) != 0)
 }
 
 fn window_diff(a: Vec<WindowInfo>, b: Vec<WindowInfo>) -> (Vec<WindowInfo>, Vec<WindowInfo>) {
-    let a: HashSet<_> = a.iter().cloned().collect();
-    let b: HashSet<_> = b.iter().cloned().collect();
+    let a: HashSet<_> = a.into_iter().collect();
+    let b: HashSet<_> = b.into_iter().collect();
 
     let added = b.difference(&a).cloned().collect();
     let deleted = a.difference(&b).cloned().collect();
@@ -278,7 +689,7 @@ fn window_diff(a: Vec<WindowInfo>, b: Vec<WindowInfo>) -> (Vec<WindowInfo>, Vec<
 
 #[cfg(test)]
 mod tests {
-    use super::State;
+    use super::{addr, cycle_tag, ActiveTagPolicy, State, WindowAddr, DEFAULT_TAG_COUNT};
 
     fn sorted(v: Vec<String>) -> Vec<String> {
         let mut v = v.clone();
@@ -286,82 +697,250 @@ mod tests {
         v
     }
 
+    #[test]
+    fn parse_window_addr() {
+        let unpadded = addr("55ab12cd3e40");
+        for s in ["0x000055ab12cd3e40", "0x55ab12cd3e40", "000055ab12cd3e40", "55AB12CD3E40", "0X55Ab12cD3e40"] {
+            assert_eq!(addr(s), unpadded, "{}", s);
+        }
+        assert_eq!(unpadded.to_string(), "55ab12cd3e40");
+        assert_eq!(addr(&unpadded.to_string()), unpadded);
+        assert_eq!(WindowAddr::from(0x55ab12cd3e40), unpadded);
+
+        for s in ["", "0x", "terminal", "55ab 12", "-55ab", "0x0x55ab", "1ffffffffffffffff"] {
+            let err = s.parse::<WindowAddr>().unwrap_err();
+            assert!(err.to_string().contains("invalid window address"), "{}", s);
+        }
+    }
+
     #[test]
     fn simple_test() {
-        let mut state = State::new();
+        let mut state = State::new(DEFAULT_TAG_COUNT);
 
-        state.new_window_added("terminal".into()).unwrap();
-        state.new_window_added("firefox".into()).unwrap();
-        assert_eq!(state.visible_windows().iter().map(|w| w.addr.clone()).collect::<Vec<String>>(), vec!["terminal", "firefox"]);
+        state.new_window_added(addr("7e3")).unwrap();
+        state.new_window_added(addr("f1f0")).unwrap();
+        assert_eq!(state.visible_windows().iter().map(|w| w.addr.to_string()).collect::<Vec<String>>(), vec!["7e3", "f1f0"]);
 
         let changes = state.set_visible_tags(1<<1).unwrap();
         assert_eq!(state.visible_windows().len(), 0);
         assert_eq!(changes.window_added.len(), 0);
-        assert_eq!(sorted(changes.window_removed.iter().map(|w| w.addr.clone()).collect()), sorted(vec!["terminal".to_string(), "firefox".to_string()]));
+        assert_eq!(sorted(changes.window_removed.iter().map(|w| w.addr.to_string()).collect()), sorted(vec!["7e3".to_string(), "f1f0".to_string()]));
 
         state.set_visible_tags(1<<0).unwrap();
-        assert_eq!(state.visible_windows().iter().map(|w| w.addr.clone()).collect::<Vec<String>>(), vec!["terminal", "firefox"]);
+        assert_eq!(state.visible_windows().iter().map(|w| w.addr.to_string()).collect::<Vec<String>>(), vec!["7e3", "f1f0"]);
 
-        state.move_window(2, Some("firefox".into())).unwrap();
-        assert_eq!(state.visible_windows().iter().map(|w| w.addr.clone()).collect::<Vec<String>>(), vec!["terminal"]);
+        state.move_window(2, Some(addr("f1f0"))).unwrap();
+        assert_eq!(state.visible_windows().iter().map(|w| w.addr.to_string()).collect::<Vec<String>>(), vec!["7e3"]);
 
         state.set_visible_tags(1<<1).unwrap();
-        assert_eq!(state.visible_windows().iter().map(|w| w.addr.clone()).collect::<Vec<String>>(), vec!["firefox"]);
+        assert_eq!(state.visible_windows().iter().map(|w| w.addr.to_string()).collect::<Vec<String>>(), vec!["f1f0"]);
 
         state.set_visible_tags(1<<0 | 1<<1).unwrap();
-        assert_eq!(state.visible_windows().iter().map(|w| w.addr.clone()).collect::<Vec<String>>(), vec!["terminal", "firefox"]);
+        assert_eq!(state.visible_windows().iter().map(|w| w.addr.to_string()).collect::<Vec<String>>(), vec!["7e3", "f1f0"]);
     }
 
     #[test]
     fn toggle_tag() {
-        let mut state = State::new();
+        let mut state = State::new(DEFAULT_TAG_COUNT);
 
-        state.new_window_added("terminal".into()).unwrap();
-        state.new_window_added("firefox".into()).unwrap();
-        state.new_window_added("emacs".into()).unwrap();
+        state.new_window_added(addr("7e3")).unwrap();
+        state.new_window_added(addr("f1f0")).unwrap();
+        state.new_window_added(addr("e3ac5")).unwrap();
 
-        state.move_window(2, Some("firefox".into())).unwrap();
-        state.move_window(3, Some("emacs".into())).unwrap();
+        state.move_window(2, Some(addr("f1f0"))).unwrap();
+        state.move_window(3, Some(addr("e3ac5"))).unwrap();
 
         assert_eq!(state.visible_windows().len(), 1);
         assert_eq!(state.visible_tags(), 0b01);
 
-        state.toggle_tag(2).unwrap();
+        state.toggle_tag(2, false).unwrap();
         assert_eq!(state.visible_windows().len(), 2);
         assert_eq!(state.visible_tags(), 0b11);
 
-        state.toggle_tag(3).unwrap();
+        let changes = state.toggle_tag(3, false).unwrap();
         assert_eq!(state.visible_windows().len(), 3);
         assert_eq!(state.visible_tags(), 0b111);
+        assert_eq!(changes.shown_tags, 0b100);
+        assert_eq!(changes.hidden_tags, 0);
 
-        state.toggle_tag(2).unwrap();
+        let changes = state.toggle_tag(2, false).unwrap();
         assert_eq!(state.visible_windows().len(), 2);
         assert_eq!(state.visible_tags(), 0b101);
+        assert_eq!(changes.shown_tags, 0);
+        assert_eq!(changes.hidden_tags, 0b10);
+    }
+
+    #[test]
+    fn invalid_tags() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        state.new_window_added(addr("7e3")).unwrap();
+
+        for tag in [0, 33, 255] {
+            assert_eq!(state.toggle_tag(tag, true).unwrap_err().to_string(), format!("no such tag: {}", tag));
+            assert_eq!(state.move_window(tag, None).unwrap_err().to_string(), format!("no such tag: {}", tag));
+            assert!(state.new_window_added_to_tag(addr("f1f0"), tag).is_err());
+        }
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!(state.find_window_tag_index(&addr("7e3")), Some(0));
+    }
+
+    #[test]
+    fn toggle_tag_focus() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+
+        state.new_window_added(addr("7e3")).unwrap();
+        state.new_window_added(addr("f1f0")).unwrap();
+        state.focus_window_changed(addr("7e3"), false).unwrap();
+        state.move_window(2, Some(addr("f1f0"))).unwrap();
+
+        let changes = state.toggle_tag(2, false).unwrap();
+        assert_eq!(changes.focus, Some(addr("7e3")));
+        state.toggle_tag(2, false).unwrap();
+
+        let changes = state.toggle_tag(2, true).unwrap();
+        assert_eq!(changes.focus, Some(addr("f1f0")));
+        assert_eq!(state.active_tag_index, 1);
+
+        // hiding behaves the same with or without focus
+        state.focus_window_changed(addr("7e3"), false).unwrap();
+        let changes = state.toggle_tag(2, true).unwrap();
+        assert_eq!(changes.focus, Some(addr("7e3")));
+        assert_eq!(changes.hidden_tags, 0b10);
+
+        // nothing to focus on an empty tag
+        let changes = state.toggle_tag(3, true).unwrap();
+        assert_eq!(changes.focus, Some(addr("7e3")));
     }
 
     #[test]
     fn new_window_on_empty_tag() {
-        let mut state = State::new();
+        let mut state = State::new(DEFAULT_TAG_COUNT);
 
-        state.new_window_added("terminal".into()).unwrap();
+        state.new_window_added(addr("7e3")).unwrap();
 
         assert_eq!(state.visible_windows().len(), 1);
         state.set_visible_tags(0b10).unwrap();
         assert_eq!(state.visible_windows().len(), 0);
         assert_eq!(state.active_tag_index, 1);
 
-        state.new_window_added("firefox".into()).unwrap();
+        state.new_window_added(addr("f1f0")).unwrap();
         assert_eq!(state.visible_windows().len(), 1);
 
         state.set_visible_tags(0b1).unwrap();
         assert_eq!(state.visible_windows().len(), 1);
     }
 
+    fn addrs(state: &State) -> Vec<String> {
+        state.visible_windows().iter().map(|w| w.addr.to_string()).collect()
+    }
+
+    #[test]
+    fn reorder_windows() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+
+        for w in ["a", "b", "c", "d"] {
+            state.new_window_added(addr(w)).unwrap();
+        }
+
+        state.promote_window(Some(addr("c"))).unwrap();
+        assert_eq!(addrs(&state), vec!["c", "a", "b", "d"]);
+
+        state.demote_window(Some(addr("a"))).unwrap();
+        assert_eq!(addrs(&state), vec!["c", "b", "d", "a"]);
+
+        state.reorder_tag(1, vec![addr("d"), addr("b")]).unwrap();
+        assert_eq!(addrs(&state), vec!["d", "b", "c", "a"]);
+
+        assert!(state.reorder_tag(1, vec![addr("ff")]).is_err());
+        assert!(state.reorder_tag(1, vec![addr("a"), addr("a")]).is_err());
+        assert!(state.reorder_tag(0, vec![]).is_err());
+        assert_eq!(addrs(&state), vec!["d", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn remove_from_middle_keeps_order() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+
+        for w in ["a", "b", "c", "d"] {
+            state.new_window_added(addr(w)).unwrap();
+        }
+
+        state.window_removed(addr("b")).unwrap();
+        assert_eq!(addrs(&state), vec!["a", "c", "d"]);
+
+        state.move_window(2, Some(addr("c"))).unwrap();
+        assert_eq!(addrs(&state), vec!["a", "d"]);
+
+        state.move_window(1, Some(addr("c"))).unwrap();
+        assert_eq!(addrs(&state), vec!["a", "d", "c"]);
+    }
+
+    #[test]
+    fn new_window_on_hidden_tag() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+
+        let changes = state.new_window_added_to_tag(addr("7e3"), 1).unwrap();
+        assert!(changes.window_removed.is_empty());
+
+        let changes = state.new_window_added_to_tag(addr("51ac"), 9).unwrap();
+        assert_eq!(changes.window_removed.len(), 1);
+        assert_eq!(changes.window_removed[0].tag, 9);
+        assert_eq!(addrs(&state), vec!["7e3"]);
+        assert_eq!(state.find_window_tag_index(&addr("51ac")), Some(8));
+
+        assert!(state.new_window_added_to_tag(addr("51ac"), 2).is_err());
+        assert!(state.new_window_added_to_tag(addr("f1f0"), 0).is_err());
+        assert!(state.new_window_added_to_tag(addr("f1f0"), 33).is_err());
+    }
+
+    #[test]
+    fn more_than_32_tags() {
+        let mut state = State::new(40);
+        state.new_window_added(addr("7e3")).unwrap();
+        state.new_window_added_to_tag(addr("51ac"), 36).unwrap();
+        assert!(state.new_window_added_to_tag(addr("f1f0"), 41).is_err());
+
+        state.toggle_tag(36, false).unwrap();
+        assert_eq!(state.visible_tags(), 1 | 1<<35);
+        assert_eq!(addrs(&state), vec!["7e3", "51ac"]);
+        let changes = state.set_visible_tags(1<<35).unwrap();
+        assert_eq!(changes.window_removed.len(), 1);
+        assert_eq!(addrs(&state), vec!["51ac"]);
+        assert_eq!(state.occupied_tags(), 1 | 1<<35);
+        assert_eq!(cycle_tag(36, true, state.occupied_tags()), Some(1));
+        assert_eq!(cycle_tag(1, false, state.occupied_tags()), Some(36));
+        // beyond the count, even within the mask
+        assert!(state.set_visible_tags(1<<45).is_err());
+    }
+
+    #[test]
+    fn heal_out_of_range_tags() {
+        let mut state = State::new(4);
+        state.new_window_added(addr("7e3")).unwrap();
+
+        state.set_visible_tags_unchecked(0b1 | 1<<10);
+        assert_eq!(addrs(&state), vec!["7e3"]);
+
+        let changes = state.toggle_tag(2, false).unwrap();
+        assert_eq!(state.visible_tags(), 0b11);
+        assert!(changes.window_removed.is_empty());
+
+        state.set_visible_tags_unchecked(1<<10);
+        assert!(state.visible_windows().is_empty());
+        state.restore_prev_tags().unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!(addrs(&state), vec!["7e3"]);
+
+        assert!(state.set_visible_tags(1<<20).is_err());
+        state.set_visible_tags(0b10 | 1<<20).unwrap();
+        assert_eq!(state.visible_tags(), 0b10);
+    }
+
     #[test]
     fn active_tag_index() {
-        let mut state = State::new();
+        let mut state = State::new(DEFAULT_TAG_COUNT);
 
-        state.focus_window_changed("terminal".into(), true).unwrap();
+        state.focus_window_changed(addr("7e3"), true).unwrap();
         assert_eq!(state.visible_windows().len(), 1);
         assert!(state.active_window.is_some());
         assert_eq!(state.active_tag_index, 0);
@@ -371,4 +950,205 @@ mod tests {
         assert_eq!(state.active_tag_index, 1);
         assert!(state.active_window.is_none());
     }
+
+    #[test]
+    fn active_tag_policies() {
+        enum Event { Show(u64), Toggle(u8), Focus(&'static str) }
+        use Event::*;
+
+        // the active tag after each event, by focused-window, lowest-visible and last-shown
+        let steps = [
+            (Show(0b11), [1, 1, 2]),
+            (Focus("b"), [2, 1, 2]),
+            (Toggle(3), [2, 1, 3]),
+            (Focus("a"), [1, 1, 3]),
+            // hides the focused window
+            (Toggle(1), [2, 2, 3]),
+            (Toggle(3), [2, 2, 2]),
+            (Show(0b101), [1, 1, 1]),
+            (Focus("c"), [3, 1, 1]),
+        ];
+        let policies = [ActiveTagPolicy::FocusedWindow, ActiveTagPolicy::LowestVisible, ActiveTagPolicy::LastShown];
+        for (p, policy) in policies.into_iter().enumerate() {
+            let mut state = State::new(DEFAULT_TAG_COUNT);
+            state.set_active_tag_policy(policy);
+            for (w, tag) in [("a", 1), ("b", 2), ("c", 3)] {
+                state.new_window_added_to_tag(addr(w), tag).unwrap();
+            }
+            state.focus_window_changed(addr("a"), false).unwrap();
+
+            for (n, (event, expected)) in steps.iter().enumerate() {
+                let changes = match event {
+                    Show(tags) => Some(state.set_visible_tags(*tags).unwrap()),
+                    Toggle(tag) => Some(state.toggle_tag(*tag, false).unwrap()),
+                    Focus(w) => {
+                        state.focus_window_changed(addr(w), false).unwrap();
+                        None
+                    },
+                };
+                let tag = state.active_tag_index() as u8 + 1;
+                assert_eq!(tag, expected[p], "{:?} after step {}", policy, n);
+                if let Some(changes) = changes {
+                    assert_eq!(changes.active_tag, Some(tag), "{:?} after step {}", policy, n);
+                }
+            }
+
+            // new windows go to the active tag
+            state.new_window_added(addr("d")).unwrap();
+            assert_eq!(state.find_window_tag_index(&addr("d")).unwrap() as u8 + 1, steps[steps.len() - 1].1[p]);
+        }
+    }
+
+    #[test]
+    fn new_since_seen() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        let badges = |state: &State| state.new_since_seen().collect::<Vec<_>>();
+
+        // a rule placing a window on a hidden tag
+        state.new_window_added_to_tag(addr("c4a7"), 3).unwrap();
+        assert_eq!(badges(&state), vec![(3, 1)]);
+
+        state.new_window_added(addr("7e3")).unwrap();
+        assert_eq!(badges(&state), vec![(3, 1)]);
+
+        state.set_visible_tags(0b100).unwrap();
+        assert!(badges(&state).is_empty());
+
+        // added while visible
+        state.new_window_added_to_tag(addr("c4a72"), 3).unwrap();
+        assert!(badges(&state).is_empty());
+
+        state.set_visible_tags(0b1).unwrap();
+        assert!(!state.window_urgent(&addr("7e3")).unwrap());
+        assert!(state.window_urgent(&addr("c4a7")).unwrap());
+        assert!(state.window_urgent(&addr("404")).is_err());
+        assert_eq!(badges(&state), vec![(3, 1)]);
+
+        // a window closing on a hidden tag doesn't hide one added after it
+        state.window_removed(addr("c4a72")).unwrap();
+        state.move_window(3, Some(addr("7e3"))).unwrap();
+        assert_eq!(badges(&state), vec![(3, 2)]);
+
+        state.toggle_tag(3, false).unwrap();
+        assert!(badges(&state).is_empty());
+    }
+
+    #[test]
+    fn redundant_show_keeps_prev_tags() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+
+        state.set_visible_tags(0b10).unwrap();
+        state.set_visible_tags(0b10).unwrap();
+        assert_eq!(state.prev_tags(), 0b1);
+
+        state.restore_prev_tags().unwrap();
+        assert_eq!(state.visible_tags(), 0b1);
+        assert_eq!(state.prev_tags(), 0b10);
+    }
+
+    #[test]
+    fn restore_back_and_forth() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        let mut views = vec![];
+
+        // show 1 is a no-op on a fresh state, show 2 is the only real transition
+        state.set_visible_tags(0b1).unwrap();
+        state.set_visible_tags(0b10).unwrap();
+        views.push(state.visible_tags());
+        for _ in 0..3 {
+            state.restore_prev_tags().unwrap();
+            views.push(state.visible_tags());
+        }
+        assert_eq!(views, vec![0b10, 0b1, 0b10, 0b1]);
+
+        // a redundant show in between doesn't break the ping-pong
+        state.set_visible_tags(0b1).unwrap();
+        state.restore_prev_tags().unwrap();
+        assert_eq!(state.visible_tags(), 0b10);
+
+        // restoring to the same view, as on a fresh state, changes nothing
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        let changes = state.restore_prev_tags().unwrap();
+        assert_eq!(changes.shown_tags | changes.hidden_tags, 0);
+        assert_eq!((state.visible_tags(), state.prev_tags()), (0b1, 0b1));
+    }
+
+    #[test]
+    fn move_and_show() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        assert_eq!(state.move_window_and_show(2, None).unwrap_err().to_string(), "no focused window");
+
+        state.new_window_added(addr("7e3")).unwrap();
+        state.focus_window_changed(addr("f1f0"), true).unwrap();
+        state.new_window_added_to_tag(addr("e3ac5"), 2).unwrap();
+        state.set_visible_tags(0b101).unwrap();
+
+        let changes = state.move_window_and_show(2, None).unwrap();
+        assert_eq!((state.visible_tags(), state.prev_tags()), (0b10, 0b101));
+        // the moved window stays in view, only the others come and go
+        assert_eq!(changes.window_added.iter().map(|w| w.addr).collect::<Vec<_>>(), vec![addr("e3ac5")]);
+        assert_eq!(changes.window_removed.iter().map(|w| w.addr).collect::<Vec<_>>(), vec![addr("7e3")]);
+        assert_eq!(changes.focus, Some(addr("f1f0")));
+        assert_eq!((changes.shown_tags, changes.hidden_tags), (0b10, 0b101));
+
+        // one restore undoes it
+        state.restore_prev_tags().unwrap();
+        assert_eq!(state.visible_tags(), 0b101);
+
+        // a window already there just gets its tag shown
+        let changes = state.move_window_and_show(2, Some(addr("e3ac5"))).unwrap();
+        assert_eq!(state.visible_tags(), 0b10);
+        assert_eq!(changes.focus, Some(addr("e3ac5")));
+        assert!(state.move_window_and_show(33, None).is_err());
+    }
+
+    #[test]
+    fn first_empty_tag() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        assert_eq!(state.first_empty_tag(0).unwrap(), 1);
+
+        for tag in 1..=32 {
+            state.new_window_added_to_tag(WindowAddr::from(tag as u64), tag).unwrap();
+        }
+        assert!(state.first_empty_tag(0).is_err());
+
+        state.window_removed(WindowAddr::from(6)).unwrap();
+        state.window_removed(WindowAddr::from(9)).unwrap();
+        assert_eq!(state.first_empty_tag(0).unwrap(), 6);
+        // hidden tags are never picked
+        assert_eq!(state.first_empty_tag(1<<5).unwrap(), 9);
+        assert!(state.first_empty_tag(1<<5 | 1<<8).is_err());
+    }
+
+    #[test]
+    fn nearest_occupied_tag() {
+        let mut state = State::new(DEFAULT_TAG_COUNT);
+        assert_eq!(state.nearest_occupied_tag(3, 0), None);
+
+        state.new_window_added_to_tag(WindowAddr::from(1), 1).unwrap();
+        state.new_window_added_to_tag(WindowAddr::from(5), 5).unwrap();
+        assert_eq!(state.nearest_occupied_tag(2, 0), Some(1));
+        assert_eq!(state.nearest_occupied_tag(4, 0), Some(5));
+        // a tie goes to the lower tag
+        assert_eq!(state.nearest_occupied_tag(3, 0), Some(1));
+        assert_eq!(state.nearest_occupied_tag(32, 0), Some(5));
+        assert_eq!(state.nearest_occupied_tag(2, 0b1), Some(5));
+        assert_eq!(state.occupied_tags(), 0b10001);
+    }
+
+    #[test]
+    fn cycle_tags() {
+        let candidates = 0b1001_0110;
+        assert_eq!(cycle_tag(2, true, candidates), Some(3));
+        assert_eq!(cycle_tag(3, true, candidates), Some(5));
+        assert_eq!(cycle_tag(8, true, candidates), Some(2));
+        assert_eq!(cycle_tag(2, false, candidates), Some(8));
+        // from a tag which isn't a candidate itself
+        assert_eq!(cycle_tag(4, false, candidates), Some(3));
+        assert_eq!(cycle_tag(32, true, candidates), Some(2));
+        assert_eq!(cycle_tag(1, false, 1<<31), Some(32));
+        // wraps around to where it started when the other tags are hidden
+        assert_eq!(cycle_tag(3, true, 0b100), Some(3));
+        assert_eq!(cycle_tag(3, false, 0), None);
+    }
 }
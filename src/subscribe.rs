@@ -0,0 +1,300 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt, BufStream}, net::UnixStream, sync::broadcast};
+
+use crate::{
+    config::Config,
+    dispatch,
+    health::{dispatch_health, DispatchHealth},
+    monitor::{ChangeSource, MonitorsState},
+    schema::{DesyncedWindow, MonitorEvent, SubscribeEvent, SubscribeLine, SCHEMA_VERSION},
+};
+
+/// Lines a subscriber may fall behind by before it misses some.
+const BUFFER: usize = 256;
+
+/// Where state changes go out to subscribers, as JSON lines. Sending never waits for them.
+pub fn events() -> &'static broadcast::Sender<String> {
+    static EVENTS: OnceLock<broadcast::Sender<String>> = OnceLock::new();
+    EVENTS.get_or_init(|| broadcast::channel(BUFFER).0)
+}
+
+/// A new subscriber's start: the state when it subscribed, then the changes after.
+#[derive(Debug)]
+pub struct Subscription {
+    pub initial: Vec<String>,
+    pub events: broadcast::Receiver<String>,
+}
+
+/// Subscribes to `events`, starting from the current state.
+pub fn subscribe(state: &MonitorsState, config: &Config) -> Subscription {
+    let mut initial: Vec<String> = state.monitor_events(config).into_iter()
+        .map(|event| line(None, SubscribeEvent::Monitor(event)))
+        .collect();
+    let desynced = desynced(&dispatch_health().lock().unwrap());
+    if !desynced.is_empty() {
+        initial.push(line(None, SubscribeEvent::Desync { windows: desynced.into_values().collect() }));
+    }
+    Subscription { initial, events: events().subscribe() }
+}
+
+/// Windows out of sync, by address.
+fn desynced(health: &DispatchHealth) -> BTreeMap<String, DesyncedWindow> {
+    health.desynced()
+        .map(|(addr, failure)| (addr.to_string(), DesyncedWindow {
+            addr: addr.to_string(),
+            expected_workspace: failure.expected_workspace,
+            actual_workspace: health.workspace(addr),
+            count: failure.count,
+        }))
+        .collect()
+}
+
+fn line(source: Option<&ChangeSource>, event: SubscribeEvent) -> String {
+    let line = SubscribeLine { schema_version: SCHEMA_VERSION, seq: dispatch::seq(), source: source.cloned(), event };
+    serde_json::to_string(&line).unwrap_or_default()
+}
+
+/// What subscribers were last told about each monitor and which windows are out of sync, to tell what changed since.
+#[derive(Debug, Default)]
+pub struct Watch {
+    monitors: BTreeMap<String, MonitorEvent>,
+    desynced: Vec<String>,
+}
+
+impl Watch {
+    /// Publishes a line for each monitor which changed since the last call, and for each one which went away. Windows
+    /// which went out of sync or back in since get a line too.
+    pub fn publish(&mut self, state: &MonitorsState, config: &Config) {
+        let lines = self.changes(state, config, &dispatch_health().lock().unwrap());
+        for line in lines {
+            // only fails without subscribers
+            let _ = events().send(line);
+        }
+    }
+
+    fn changes(&mut self, state: &MonitorsState, config: &Config, health: &DispatchHealth) -> Vec<String> {
+        // a presenting monitor's bar may be in the screen share
+        let source = |monitor: &str| match state.is_presenting_named(monitor) {
+            true => state.source().redacted(&config.presentation),
+            false => state.source().clone(),
+        };
+        let mut lines = vec![];
+        let mut monitors = BTreeMap::new();
+        for event in state.monitor_events(config) {
+            if self.monitors.get(&event.monitor) != Some(&event) {
+                lines.push(line(Some(&source(&event.monitor)), SubscribeEvent::Monitor(event.clone())));
+            }
+            monitors.insert(event.monitor.clone(), event);
+        }
+        for monitor in self.monitors.keys().filter(|m| !monitors.contains_key(*m)) {
+            lines.push(line(Some(&source(monitor)), SubscribeEvent::MonitorRemoved { monitor: monitor.clone() }));
+        }
+        self.monitors = monitors;
+
+        let desynced = desynced(health);
+        let resynced: Vec<String> = self.desynced.iter().filter(|a| !desynced.contains_key(*a)).cloned().collect();
+        if !resynced.is_empty() {
+            lines.push(line(None, SubscribeEvent::Resync { windows: resynced }));
+        }
+        let new: Vec<DesyncedWindow> = desynced.values().filter(|w| !self.desynced.contains(&w.addr)).cloned().collect();
+        if !new.is_empty() {
+            lines.push(line(None, SubscribeEvent::Desync { windows: new }));
+        }
+        self.desynced = desynced.into_keys().collect();
+        lines
+    }
+}
+
+/// Writes the subscription's lines to the connection until either end goes away. A subscriber too slow to keep up
+/// is told how many lines it missed instead of holding anything up. Anything it sends is ignored, and closing its
+/// end of the connection ends the subscription.
+pub async fn stream(stream: &mut BufStream<UnixStream>, mut subscription: Subscription) {
+    let r = async {
+        for line in &subscription.initial {
+            write(stream, line).await?;
+        }
+        let mut buf = [0; 256];
+        loop {
+            tokio::select! {
+                received = subscription.events.recv() => match received {
+                    Ok(line) => write(stream, &line).await?,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "subscriber fell behind");
+                        write(stream, &line(None, SubscribeEvent::Lagged { missed })).await?;
+                    },
+                    Err(broadcast::error::RecvError::Closed) => return Ok::<_, std::io::Error>(()),
+                },
+                read = stream.read(&mut buf) => if read? == 0 {
+                    return Ok(());
+                },
+            }
+        }
+    };
+    if let Err(err) = r.await {
+        tracing::debug!(%err, "subscriber went away");
+    }
+}
+
+async fn write(stream: &mut BufStream<UnixStream>, line: &str) -> std::io::Result<()> {
+    stream.write_all(line.as_bytes()).await?;
+    stream.write_all(b"\n").await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufStream}, net::UnixStream, sync::broadcast};
+
+    use crate::{addr, config::{Config, PresentationConfig}, health::{DispatchHealth, DESYNC_THRESHOLD}, hyprctl::MonitorInfo, monitor::{ChangeSource, MonitorsState}, state::DEFAULT_TAG_COUNT};
+
+    use super::{stream, Subscription, Watch};
+
+    fn events(lines: Vec<String>) -> Vec<serde_json::Value> {
+        lines.iter().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn changed_monitors() {
+        let config = Config::default();
+        let mut state = MonitorsState::new(vec![
+            MonitorInfo { id: 0, name: "DP-1".into(), focused: true, ..Default::default() },
+            MonitorInfo { id: 1, name: "DP-2".into(), focused: false, ..Default::default() },
+        ], DEFAULT_TAG_COUNT);
+        let mut watch = Watch::default();
+        let health = DispatchHealth::default();
+        assert_eq!(events(watch.changes(&state, &config, &health)).iter().map(|e| e["monitor"].clone()).collect::<Vec<_>>(), ["DP-1", "DP-2"]);
+        assert!(watch.changes(&state, &config, &health).is_empty());
+
+        state.window_opened(addr("7e3"), "kitty", "").unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!(changed.len(), 1);
+        assert_eq!((&changed[0]["monitor"], &changed[0]["occupied_tags"]), (&serde_json::json!("DP-1"), &serde_json::json!(1)));
+        assert_eq!(changed[0]["windows"], serde_json::json!({"1": 1}));
+        assert_eq!(changed[0]["event"], "monitor");
+
+        // focus moving to the other monitor changes both
+        state.focused_monitor_changed("DP-2").unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!(changed.iter().map(|e| (e["monitor"].clone(), e["focused"].clone())).collect::<Vec<_>>(), [
+            (serde_json::json!("DP-1"), serde_json::json!(false)),
+            (serde_json::json!("DP-2"), serde_json::json!(true)),
+        ]);
+
+        state.monitor_removed("DP-2").unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!(changed.last().unwrap()["event"], "monitor_removed");
+        assert_eq!(changed.last().unwrap()["monitor"], "DP-2");
+    }
+
+    #[test]
+    fn redacted_while_presenting() {
+        let mut config = Config::default();
+        let mut state = MonitorsState::new(vec![
+            MonitorInfo { id: 0, name: "DP-1".into(), focused: true, ..Default::default() },
+            MonitorInfo { id: 1, name: "DP-2".into(), focused: false, ..Default::default() },
+        ], DEFAULT_TAG_COUNT);
+        let mut watch = Watch::default();
+        let health = DispatchHealth::default();
+        watch.changes(&state, &config, &health);
+        state.set_presentation(true, Some(0)).unwrap();
+
+        state.set_source(ChangeSource::Rule { class: "discord".into(), tag: 3 });
+        state.window_opened(addr("7e3"), "discord", "").unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0]["source"], serde_json::json!({"kind": "rule", "class": "redacted", "tag": 3}));
+
+        // the other monitor isn't presenting
+        state.set_source(ChangeSource::Ctrl { command: "activate discord".into() });
+        state.focused_monitor_changed("DP-2").unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!(changed.iter().map(|e| (e["monitor"].clone(), e["source"]["command"].clone())).collect::<Vec<_>>(), [
+            (serde_json::json!("DP-1"), serde_json::json!("activate redacted")),
+            (serde_json::json!("DP-2"), serde_json::json!("activate discord")),
+        ]);
+
+        state.focused_monitor_changed("DP-1").unwrap();
+        watch.changes(&state, &config, &health);
+
+        // classes shown as configured, command arguments still hidden for the titles they may name
+        config.presentation = PresentationConfig { redact_titles: true, redact_classes: false };
+        state.set_source(ChangeSource::Rule { class: "discord".into(), tag: 3 });
+        state.window_opened(addr("1de"), "discord", "").unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!((&changed[0]["monitor"], &changed[0]["source"]["class"]), (&serde_json::json!("DP-1"), &serde_json::json!("discord")));
+        assert_eq!(ChangeSource::Ctrl { command: "show 2".into() }.redacted(&config.presentation), ChangeSource::Ctrl { command: "show redacted".into() });
+        assert_eq!(ChangeSource::Ctrl { command: "restore".into() }.redacted(&config.presentation), ChangeSource::Ctrl { command: "restore".into() });
+
+        state.set_presentation(false, Some(0)).unwrap();
+        state.set_source(ChangeSource::Ctrl { command: "show 2".into() });
+        state.set_visible_tags(0b10).unwrap();
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!((&changed[0]["monitor"], &changed[0]["source"]["command"]), (&serde_json::json!("DP-1"), &serde_json::json!("show 2")));
+    }
+
+    #[test]
+    fn desynced_windows() {
+        let config = Config::default();
+        let state = MonitorsState::new(vec![MonitorInfo { id: 0, name: "DP-1".into(), focused: true, ..Default::default() }], DEFAULT_TAG_COUNT);
+        let mut watch = Watch::default();
+        let mut health = DispatchHealth::default();
+        watch.changes(&state, &config, &health);
+
+        let batch = vec![
+            "dispatch movetoworkspacesilent 101,address:0x7e3".to_string(),
+            "dispatch movetoworkspacesilent 1,address:0x1de".to_string(),
+        ];
+        health.window_moved(addr("7e3"), 1);
+        for _ in 1..DESYNC_THRESHOLD {
+            health.record(&batch, false);
+            assert!(watch.changes(&state, &config, &health).is_empty());
+        }
+        health.record(&batch, false);
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!(changed, [serde_json::json!({
+            "schema_version": 1, "seq": changed[0]["seq"], "source": null, "event": "desync", "windows": [
+                {"addr": "1de", "expected_workspace": 1, "actual_workspace": null, "count": 3},
+                {"addr": "7e3", "expected_workspace": 101, "actual_workspace": 1, "count": 3},
+            ],
+        })]);
+
+        // told once, not on every further failure
+        health.record(&batch, false);
+        assert!(watch.changes(&state, &config, &health).is_empty());
+
+        health.record(&batch[..1], true);
+        health.window_closed(&addr("1de"));
+        let changed = events(watch.changes(&state, &config, &health));
+        assert_eq!((&changed[0]["event"], &changed[0]["windows"]), (&serde_json::json!("resync"), &serde_json::json!(["1de", "7e3"])));
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber() {
+        let (tx, rx) = broadcast::channel(2);
+        let (client, server) = UnixStream::pair().unwrap();
+        let subscription = Subscription { initial: vec!["first".into()], events: rx };
+        // more than the buffer before the subscriber reads any
+        for line in ["a", "b", "c", "d"] {
+            tx.send(line.to_string()).unwrap();
+        }
+        let handler = tokio::spawn(async move { stream(&mut BufStream::new(server), subscription).await });
+
+        let mut client = BufReader::new(client);
+        let mut lines = vec![];
+        for _ in 0..4 {
+            let mut line = String::new();
+            client.read_line(&mut line).await.unwrap();
+            lines.push(line.trim_end().to_string());
+        }
+        assert_eq!(lines[0], "first");
+        let lagged: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!((&lagged["event"], &lagged["missed"]), (&serde_json::json!("lagged"), &serde_json::json!(2)));
+        assert_eq!(lines[2..], ["c", "d"]);
+
+        // closing the connection ends the subscription
+        client.get_mut().shutdown().await.unwrap();
+        handler.await.unwrap();
+        assert_eq!(tx.receiver_count(), 0);
+    }
+}
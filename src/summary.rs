@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "persistence")]
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "persistence")]
+use crate::persist::{self, Loaded};
+use crate::state::WindowAddr;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// When the day's totals start over and how long a single focus counts at most.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SummarySettings {
+    /// Seconds after local midnight.
+    pub reset_at: u32,
+    /// Longer intervals without a focus change, like a suspend, only count this many seconds.
+    pub max_interval: u64,
+}
+
+impl Default for SummarySettings {
+    fn default() -> Self {
+        Self { reset_at: 4 * 60 * 60, max_interval: 600 }
+    }
+}
+
+/// A tag's foreground time and windows since the last reset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TagTotals {
+    /// Seconds one of its windows had focus.
+    pub foreground_secs: u64,
+    /// Windows which were on the tag.
+    pub windows: BTreeSet<WindowAddr>,
+}
+
+/// Per tag time tracking for `summary`, starting over daily at a local time or when reset.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Summary {
+    /// Epoch seconds of the last reset.
+    since: u64,
+    tags: BTreeMap<u8, TagTotals>,
+    /// The tag of the focused window, with when its current interval started.
+    focused: Option<(u8, u64)>,
+    #[serde(skip)]
+    settings: SummarySettings,
+    /// Where the totals are saved, so they survive restarts.
+    #[cfg(feature = "persistence")]
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+/// A tag in the `summary` reply.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TagSummary {
+    pub tag: u8,
+    pub name: String,
+    pub foreground_secs: u64,
+    /// Windows which were on the tag since the reset.
+    pub windows: usize,
+    /// Windows on it now, across monitors.
+    pub occupancy: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Report {
+    /// Epoch seconds of the last reset.
+    pub since: u64,
+    pub tags: Vec<TagSummary>,
+}
+
+impl Summary {
+    pub fn set_settings(&mut self, settings: SummarySettings) {
+        self.settings = settings;
+    }
+
+    /// Counts the time so far towards the focused tag and starts counting towards `tag`, nothing when `None`.
+    pub fn focus(&mut self, tag: Option<u8>, now: u64, offset: i64) {
+        self.roll_over(now, offset);
+        if self.focused.map(|(t, _)| t) == tag {
+            return;
+        }
+        self.account(now);
+        self.focused = tag.map(|t| (t, now));
+        self.save();
+    }
+
+    pub fn window_on_tag(&mut self, tag: u8, window: WindowAddr, now: u64, offset: i64) {
+        self.roll_over(now, offset);
+        if self.tags.entry(tag).or_default().windows.insert(window) {
+            self.save();
+        }
+    }
+
+    /// Starts the totals over, as `summary reset`.
+    pub fn reset(&mut self, now: u64) {
+        self.tags.clear();
+        self.since = now;
+        self.focused = self.focused.map(|(tag, _)| (tag, now));
+        self.save();
+    }
+
+    /// Starts the totals over once the daily reset time passed since the last reset. `offset` is the local
+    /// time's offset from UTC in seconds.
+    pub fn roll_over(&mut self, now: u64, offset: i64) {
+        let boundary = last_reset(now, offset, self.settings.reset_at);
+        if self.since >= boundary {
+            return;
+        }
+        tracing::info!(since = self.since, "summary starts a new day");
+        self.tags.clear();
+        self.since = boundary;
+        self.focused = self.focused.map(|(tag, start)| (tag, start.max(boundary)));
+        self.save();
+    }
+
+    /// The totals with the current focus interval counted, and tags without any but with windows now.
+    pub fn report(&self, now: u64, occupancy: &BTreeMap<u8, usize>, names: &BTreeMap<u8, String>) -> Report {
+        let mut foreground: BTreeMap<u8, u64> = self.tags.iter().map(|(tag, t)| (*tag, t.foreground_secs)).collect();
+        if let Some((tag, start)) = self.focused {
+            *foreground.entry(tag).or_default() += self.interval(start, now);
+        }
+        let tags: BTreeSet<u8> = self.tags.keys().chain(foreground.keys()).chain(occupancy.keys()).copied().collect();
+        Report {
+            since: self.since,
+            tags: tags.into_iter().map(|tag| TagSummary {
+                tag,
+                name: names.get(&tag).cloned().unwrap_or_else(|| tag.to_string()),
+                foreground_secs: foreground.get(&tag).copied().unwrap_or(0),
+                windows: self.tags.get(&tag).map_or(0, |t| t.windows.len()),
+                occupancy: occupancy.get(&tag).copied().unwrap_or(0),
+            }).collect(),
+        }
+    }
+
+    fn account(&mut self, now: u64) {
+        if let Some((tag, start)) = self.focused {
+            let secs = self.interval(start, now);
+            self.tags.entry(tag).or_default().foreground_secs += secs;
+            self.focused = Some((tag, now));
+        }
+    }
+
+    fn interval(&self, start: u64, now: u64) -> u64 {
+        now.saturating_sub(start).min(self.settings.max_interval)
+    }
+
+    /// The totals saved at `path`, or none when there's no such file. They're saved there from now on.
+    #[cfg(feature = "persistence")]
+    pub fn load(path: PathBuf) -> Self {
+        let mut summary = match persist::load(&path) {
+            Some((summary, Loaded::Primary)) => summary,
+            Some((summary, Loaded::Backup)) => {
+                tracing::warn!(path = %path.display(), "summary damaged, loaded its backup");
+                summary
+            },
+            None => Self::default(),
+        };
+        summary.path = Some(path);
+        summary
+    }
+
+    #[cfg(feature = "persistence")]
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(err) = persist::save(path, self) {
+            tracing::warn!(%err, path = %path.display(), "failed to save the summary");
+        }
+    }
+
+    #[cfg(not(feature = "persistence"))]
+    fn save(&self) {}
+}
+
+/// Epoch seconds of the latest daily reset at or before `now`.
+fn last_reset(now: u64, offset: i64, reset_at: u32) -> u64 {
+    let local = now as i64 + offset - reset_at as i64;
+    (local.div_euclid(DAY) * DAY + reset_at as i64 - offset).max(0) as u64
+}
+
+/// Seconds the local time zone is ahead of UTC at `now`.
+pub fn local_offset(now: u64) -> i64 {
+    let time = now as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the call, localtime_r keeps neither
+    match unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        true => 0,
+        false => tm.tm_gmtoff,
+    }
+}
+
+/// `$XDG_STATE_HOME/hyprtag/summary.json`, with the state home defaulting to `~/.local/state`.
+#[cfg(feature = "persistence")]
+pub fn summary_path() -> Option<PathBuf> {
+    let dir = match std::env::var("XDG_STATE_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".local/state"),
+    };
+    Some(dir.join("hyprtag").join("summary.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::addr;
+
+    use super::{last_reset, Summary, SummarySettings};
+
+    const HOUR: u64 = 60 * 60;
+    /// 2024-01-02 00:00 UTC.
+    const MIDNIGHT: u64 = 1_704_153_600;
+
+    fn foreground(summary: &Summary, now: u64) -> Vec<(u8, u64)> {
+        summary.report(now, &BTreeMap::new(), &BTreeMap::new()).tags.iter().map(|t| (t.tag, t.foreground_secs)).collect()
+    }
+
+    #[test]
+    fn focus_intervals() {
+        let mut summary = Summary::default();
+        summary.set_settings(SummarySettings { reset_at: 4 * HOUR as u32, max_interval: 600 });
+        let start = MIDNIGHT + 9 * HOUR;
+        summary.reset(start);
+
+        summary.focus(Some(1), start, 0);
+        summary.focus(Some(2), start + 120, 0);
+        // focus staying on the tag doesn't end its interval
+        summary.focus(Some(2), start + 200, 0);
+        summary.focus(None, start + 300, 0);
+        assert_eq!(foreground(&summary, start + 1000), vec![(1, 120), (2, 180)]);
+
+        // the current interval counts in the report, capped like a suspended one
+        summary.focus(Some(1), start + 1000, 0);
+        assert_eq!(foreground(&summary, start + 1100), vec![(1, 220), (2, 180)]);
+        summary.focus(Some(2), start + 1000 + 3 * HOUR, 0);
+        assert_eq!(foreground(&summary, start + 1000 + 3 * HOUR), vec![(1, 720), (2, 180)]);
+    }
+
+    #[test]
+    fn windows_and_names() {
+        let mut summary = Summary::default();
+        summary.window_on_tag(3, addr("7e3"), 100, 0);
+        summary.window_on_tag(3, addr("7e3"), 110, 0);
+        summary.window_on_tag(3, addr("f1f0"), 120, 0);
+
+        let occupancy = BTreeMap::from([(3, 1), (5, 2)]);
+        let names = BTreeMap::from([(3, "mail".to_string())]);
+        let report = summary.report(130, &occupancy, &names);
+        let tags: Vec<(u8, &str, usize, usize)> = report.tags.iter().map(|t| (t.tag, t.name.as_str(), t.windows, t.occupancy)).collect();
+        assert_eq!(tags, vec![(3, "mail", 2, 1), (5, "5", 0, 2)]);
+    }
+
+    #[test]
+    fn daily_reset() {
+        let reset_at = 4 * HOUR as u32;
+        assert_eq!(last_reset(MIDNIGHT + 5 * HOUR, 0, reset_at), MIDNIGHT + 4 * HOUR);
+        assert_eq!(last_reset(MIDNIGHT + 3 * HOUR, 0, reset_at), MIDNIGHT - 20 * HOUR);
+        // 04:00 at UTC+2 is 02:00 UTC
+        assert_eq!(last_reset(MIDNIGHT + 3 * HOUR, 2 * HOUR as i64, reset_at), MIDNIGHT + 2 * HOUR);
+
+        let mut summary = Summary::default();
+        summary.set_settings(SummarySettings { reset_at, max_interval: 600 });
+        summary.reset(MIDNIGHT + HOUR);
+        summary.window_on_tag(1, addr("7e3"), MIDNIGHT + HOUR, 0);
+        summary.focus(Some(1), MIDNIGHT + 4 * HOUR - 100, 0);
+
+        // the day's totals are gone, the interval carrying over counts from the reset
+        summary.focus(Some(2), MIDNIGHT + 4 * HOUR + 50, 0);
+        let report = summary.report(MIDNIGHT + 4 * HOUR + 50, &BTreeMap::new(), &BTreeMap::new());
+        assert_eq!(report.since, MIDNIGHT + 4 * HOUR);
+        assert_eq!(foreground(&summary, MIDNIGHT + 4 * HOUR + 50), vec![(1, 50), (2, 0)]);
+        assert_eq!(report.tags[0].windows, 0);
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn survives_restarts() {
+        let path = std::env::temp_dir().join(format!("hyprtag-summary-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut summary = Summary::load(path.clone());
+        summary.reset(MIDNIGHT + 9 * HOUR);
+        summary.window_on_tag(2, addr("7e3"), MIDNIGHT + 9 * HOUR, 0);
+        summary.focus(Some(2), MIDNIGHT + 9 * HOUR, 0);
+        summary.focus(Some(1), MIDNIGHT + 9 * HOUR + 60, 0);
+
+        let loaded = Summary::load(path.clone());
+        assert_eq!(loaded.since, MIDNIGHT + 9 * HOUR);
+        assert_eq!(loaded.tags, summary.tags);
+        assert_eq!(loaded.focused, Some((1, MIDNIGHT + 9 * HOUR + 60)));
+        std::fs::remove_file(path).unwrap();
+    }
+}
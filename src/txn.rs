@@ -0,0 +1,64 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{ctrl::Ctrl, monitor::{Changes, Checkpoint}};
+
+/// How long a transaction may stay open. It's aborted after that, so an abandoned `txn begin` doesn't hold up
+/// other commands for good.
+pub const TXN_TIMEOUT: Duration = Duration::from_secs(30);
+
+static IDS: AtomicU64 = AtomicU64::new(0);
+
+/// What `txn` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxnOp {
+    Begin,
+    Commit,
+    Abort,
+}
+
+/// A command run in a transaction, with the changes whose dispatches wait for the commit.
+#[derive(Debug)]
+pub struct Step {
+    pub command: String,
+    pub changes: Vec<Changes>,
+}
+
+/// Commands between `txn begin` and `txn commit`. They change the state as they come, but their dispatches are
+/// only sent on commit, and the state goes back to the checkpoint when one of them fails.
+#[derive(Debug)]
+pub struct Transaction {
+    id: u64,
+    checkpoint: Checkpoint,
+    steps: Vec<Step>,
+    timer: JoinHandle<()>,
+}
+
+impl Transaction {
+    /// Starts a transaction which is aborted by `Ctrl::TxnExpired` unless it ends within `TXN_TIMEOUT`.
+    pub fn begin(checkpoint: Checkpoint, tx: mpsc::Sender<Ctrl>) -> Self {
+        let id = IDS.fetch_add(1, Ordering::Relaxed) + 1;
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(TXN_TIMEOUT).await;
+            if let Err(err) = tx.send(Ctrl::TxnExpired(id)).await {
+                tracing::error!(%err, "failed to send Ctrl::TxnExpired");
+            }
+        });
+        Self { id, checkpoint, steps: vec![], timer }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    /// Ends the transaction, with the state it began with and its steps.
+    pub fn end(self) -> (Checkpoint, Vec<Step>) {
+        self.timer.abort();
+        (self.checkpoint, self.steps)
+    }
+}
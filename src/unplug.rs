@@ -0,0 +1,114 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{ctrl::Ctrl, events::Event};
+
+/// Monitors Hyprland removed which get a grace period to come back, as turning displays off and on removes and
+/// adds them again on some hardware. Until they come back or their period ends, the state is left as it was and
+/// events are held, so windows aren't moved away and back for nothing.
+#[derive(Debug, Default)]
+pub struct PendingRemovals {
+    /// Keyed by monitor name.
+    pending: HashMap<String, Pending>,
+    held: Vec<Event>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    description: String,
+    end: JoinHandle<()>,
+    tx: mpsc::Sender<Ctrl>,
+}
+
+impl PendingRemovals {
+    /// Sends `Ctrl::MonitorGone` once `grace` passed without the monitor coming back.
+    pub(crate) fn schedule(&mut self, name: &str, description: &str, grace: Duration, tx: mpsc::Sender<Ctrl>) {
+        let gone = name.to_string();
+        let end_tx = tx.clone();
+        let end = tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            if let Err(err) = end_tx.send(Ctrl::MonitorGone(gone)).await {
+                tracing::error!(%err, "failed to send Ctrl::MonitorGone");
+            }
+        });
+        let pending = Pending { description: description.to_string(), end, tx };
+        if let Some(previous) = self.pending.insert(name.to_string(), pending) {
+            previous.end.abort();
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn hold(&mut self, event: Event) {
+        self.held.push(event);
+    }
+
+    /// Cancels the removal of a monitor added back under the same name, or with the same description under another
+    /// connector. Returns the name it was removed as, with the sender its removal was scheduled with.
+    pub(crate) fn returned(&mut self, name: &str, description: &str) -> Option<(String, mpsc::Sender<Ctrl>)> {
+        let removed = self.pending.iter()
+            .find(|(n, p)| *n == name || (!description.is_empty() && p.description == description))
+            .map(|(n, _)| n.clone())?;
+        let pending = self.pending.remove(&removed)?;
+        pending.end.abort();
+        Some((removed, pending.tx))
+    }
+
+    /// Ends the grace period of a monitor which didn't come back. `None` when it isn't waited for anymore.
+    pub(crate) fn gone(&mut self, name: &str) -> Option<mpsc::Sender<Ctrl>> {
+        self.pending.remove(name).map(|p| p.tx)
+    }
+
+    /// Stops waiting for every removed monitor, dropping the held events.
+    pub fn cancel(&mut self) {
+        for (_, pending) in self.pending.drain() {
+            pending.end.abort();
+        }
+        self.held.clear();
+    }
+
+    /// The held events, once no monitor is waited for anymore.
+    pub fn take_held(&mut self) -> Vec<Event> {
+        if self.is_pending() {
+            return vec![];
+        }
+        std::mem::take(&mut self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use crate::{ctrl::Ctrl, events::Event};
+
+    use super::PendingRemovals;
+
+    #[tokio::test(start_paused = true)]
+    async fn grace_period() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut removals = PendingRemovals::default();
+
+        removals.schedule("DP-1", "Dell U2720Q", Duration::from_millis(500), tx.clone());
+        removals.schedule("DP-2", "LG 27GL850", Duration::from_millis(1000), tx);
+        removals.hold(Event::Submap(String::new()));
+
+        // back under another connector, found by its description
+        let (removed, _) = removals.returned("DP-3", "Dell U2720Q").unwrap();
+        assert_eq!(removed, "DP-1");
+        assert!(removals.returned("DP-3", "").is_none());
+        assert!(removals.take_held().is_empty());
+
+        // only the one which didn't come back ends
+        assert!(matches!(rx.recv().await, Some(Ctrl::MonitorGone(name)) if name == "DP-2"));
+        assert!(removals.gone("DP-2").is_some());
+        assert!(removals.gone("DP-2").is_none());
+        assert!(!removals.is_pending());
+        assert_eq!(removals.take_held(), vec![Event::Submap(String::new())]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
+
+use crate::state::WindowAddr;
+
+/// How long a provisional window may go unconfirmed before a clients query finding no such window drops it.
+pub const PROVISIONAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+struct Provisional {
+    since: Instant,
+    /// Whether moves of the window were held back, which are due once it's confirmed.
+    held_back: bool,
+}
+
+/// Whether the addresses of tracked windows are known to exist. A window first seen by a focus change may be one
+/// whose openwindow is still to come, or a stale address, so it stays provisional until an openwindow, movewindowv2
+/// or urgent event or a clients query names it. Dispatches leave provisional windows alone.
+#[derive(Debug, Clone, Default)]
+pub struct Validity {
+    confirmed: HashSet<WindowAddr>,
+    provisional: HashMap<WindowAddr, Provisional>,
+}
+
+impl Validity {
+    /// Starts tracking a window nothing confirmed yet. True when it's provisional.
+    pub fn track(&mut self, window: WindowAddr, now: Instant) -> bool {
+        if self.confirmed.contains(&window) {
+            return false;
+        }
+        self.provisional.entry(window).or_insert(Provisional { since: now, held_back: false });
+        true
+    }
+
+    /// Records that the window exists. True when it was provisional with moves held back, which are due now.
+    pub fn confirm(&mut self, window: WindowAddr) -> bool {
+        self.confirmed.insert(window);
+        self.provisional.remove(&window).is_some_and(|p| p.held_back)
+    }
+
+    pub fn is_provisional(&self, window: &WindowAddr) -> bool {
+        self.provisional.contains_key(window)
+    }
+
+    /// Notes a move of the window held back for now. True when it's provisional.
+    pub fn hold_back(&mut self, window: &WindowAddr) -> bool {
+        match self.provisional.get_mut(window) {
+            Some(p) => {
+                p.held_back = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Provisional windows older than `PROVISIONAL_TIMEOUT`.
+    pub fn expired(&self, now: Instant) -> Vec<WindowAddr> {
+        self.provisional.iter()
+            .filter(|(_, p)| now.saturating_duration_since(p.since) >= PROVISIONAL_TIMEOUT)
+            .map(|(w, _)| *w)
+            .collect()
+    }
+
+    pub fn forget(&mut self, window: &WindowAddr) {
+        self.confirmed.remove(window);
+        self.provisional.remove(window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::addr;
+
+    use super::{Validity, PROVISIONAL_TIMEOUT};
+
+    #[test]
+    fn provisional_until_confirmed() {
+        let mut validity = Validity::default();
+        let start = Instant::now();
+        validity.confirm(addr("aa"));
+        assert!(!validity.track(addr("aa"), start));
+
+        assert!(validity.track(addr("bb"), start));
+        assert!(validity.track(addr("cc"), start + Duration::from_secs(1)));
+        assert!(validity.hold_back(&addr("bb")));
+        assert!(!validity.hold_back(&addr("aa")));
+        assert_eq!(validity.expired(start + PROVISIONAL_TIMEOUT), vec![addr("bb")]);
+
+        // only held back moves are due on confirmation
+        assert!(validity.confirm(addr("bb")));
+        assert!(!validity.confirm(addr("cc")));
+        assert!(!validity.is_provisional(&addr("bb")));
+        assert!(validity.expired(start + PROVISIONAL_TIMEOUT * 2).is_empty());
+
+        validity.forget(&addr("aa"));
+        assert!(validity.track(addr("aa"), start));
+    }
+}
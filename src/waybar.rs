@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context};
+use serde_json::{json, Map, Value};
+use tokio::{io::{AsyncWriteExt, BufStream}, net::UnixStream};
+
+use crate::{config::TagConfig, hyprland_dir, line::read_reply, protocol, state::{cycle_tag, MAX_TAGS}};
+
+/// Tags in the generated config without `--tags`.
+const DEFAULT_TAGS: u8 = 9;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    tags: u8,
+    monitor: Option<String>,
+    /// Keeps the tags configured as hidden.
+    include_hidden: bool,
+}
+
+/// Names and settings of the tags from the daemon's config.
+#[derive(Debug, Default)]
+struct Tags {
+    names: BTreeMap<u8, String>,
+    settings: BTreeMap<u8, TagConfig>,
+}
+
+impl Tags {
+    fn hidden(&self, tag: u8) -> bool {
+        self.settings.get(&tag).is_some_and(|t| t.hidden)
+    }
+}
+
+/// `hyprtag waybar-config [--tags N] [--monitor NAME] [--include-hidden]`: prints waybar modules for the tags, named
+/// as the running daemon's config names them, without the hidden ones. Left click shows the tag, right click toggles
+/// it, scrolling goes through the tags.
+pub async fn run(args: &[String]) -> anyhow::Result<()> {
+    let options = parse_args(args)?;
+    let tags = match daemon_tags().await {
+        Ok(tags) => tags,
+        Err(err) => {
+            eprintln!("hyprtag waybar-config: tags go by their number, no names from the daemon: {:#}", err);
+            Tags::default()
+        },
+    };
+    println!("{}", serde_json::to_string_pretty(&generate(&options, &tags))?);
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> anyhow::Result<Options> {
+    let mut options = Options { tags: DEFAULT_TAGS, monitor: None, include_hidden: false };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tags" => {
+                options.tags = args.next().context("--tags requires a value")?.parse().context("invalid --tags")?;
+                if !(1..=MAX_TAGS).contains(&options.tags) {
+                    bail!("--tags must be 1-{}", MAX_TAGS);
+                }
+            },
+            "--monitor" => options.monitor = Some(args.next().context("--monitor requires a value")?.clone()),
+            "--include-hidden" => options.include_hidden = true,
+            arg => bail!("unknown waybar-config arg: {}", arg),
+        }
+    }
+    Ok(options)
+}
+
+/// Tag names and settings from the running daemon's config.
+async fn daemon_tags() -> anyhow::Result<Tags> {
+    let mut ctrl = BufStream::new(UnixStream::connect(hyprland_dir()?.join(protocol::SOCKET)).await?);
+    ctrl.write_all(b"config\n").await?;
+    ctrl.flush().await?;
+    let reply = read_reply(&mut ctrl).await?.context("daemon closed the connection")?;
+    let mut config: Value = serde_json::from_str(&reply).with_context(|| format!("unexpected reply: {}", reply))?;
+    Ok(Tags {
+        names: serde_json::from_value(config["config"]["tag_names"].take()).unwrap_or_default(),
+        settings: serde_json::from_value(config["config"]["tags"].take()).unwrap_or_default(),
+    })
+}
+
+/// The waybar config snippet: the module list and a module per tag.
+fn generate(options: &Options, tags: &Tags) -> Value {
+    let mut config = Map::new();
+    let prefix = match &options.monitor {
+        Some(monitor) => {
+            config.insert("output".into(), monitor.as_str().into());
+            format!("custom/hyprtag-{}", monitor)
+        },
+        None => "custom/hyprtag".to_string(),
+    };
+
+    let shown: Vec<u8> = (1..=options.tags).filter(|tag| options.include_hidden || !tags.hidden(*tag)).collect();
+    let mask = shown.iter().fold(0u64, |mask, tag| mask | 1<<(tag - 1));
+    let modules: Vec<String> = shown.iter().map(|tag| format!("{}-{}", prefix, tag)).collect();
+    for (&tag, module) in shown.iter().zip(modules.iter()) {
+        let name = tags.names.get(&tag).cloned().unwrap_or_else(|| tag.to_string());
+        // scrolling wraps around, over the tags in the bar
+        let prev = cycle_tag(tag, false, mask).unwrap_or(tag);
+        let next = cycle_tag(tag, true, mask).unwrap_or(tag);
+        config.insert(module.clone(), json!({
+            "format": escape_format(&name),
+            "tooltip": false,
+            "on-click": show(options, tag),
+            "on-click-right": toggle(options, tag),
+            "on-scroll-up": show(options, prev),
+            "on-scroll-down": show(options, next),
+        }));
+    }
+    config.insert("modules-left".into(), modules.into());
+    Value::Object(config)
+}
+
+fn show(options: &Options, tag: u8) -> String {
+    match &options.monitor {
+        Some(monitor) => format!("hyprtagctl show_on {} {}", shell_quote(monitor), tag),
+        None => format!("hyprtagctl show {}", tag),
+    }
+}
+
+/// `toggle` acts on the active monitor, so the bar's monitor is focused first.
+fn toggle(options: &Options, tag: u8) -> String {
+    match &options.monitor {
+        Some(monitor) => format!("hyprtagctl sync focus_monitor {} && hyprtagctl toggle {}", shell_quote(monitor), tag),
+        None => format!("hyprtagctl toggle {}", tag),
+    }
+}
+
+/// Waybar formats are Pango markup with `{}` placeholders.
+fn escape_format(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('{', "{{")
+        .replace('}', "}}")
+}
+
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::config::TagConfig;
+
+    use super::{generate, parse_args, shell_quote, Options, Tags};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn waybar_args() {
+        assert_eq!(parse_args(&[]).unwrap(), Options { tags: 9, monitor: None, include_hidden: false });
+        assert_eq!(
+            parse_args(&args("--tags 3 --monitor DP-1 --include-hidden")).unwrap(),
+            Options { tags: 3, monitor: Some("DP-1".into()), include_hidden: true },
+        );
+        assert!(parse_args(&args("--tags 0")).is_err());
+        assert!(parse_args(&args("--tags 65")).is_err());
+        assert!(parse_args(&args("--monitor")).is_err());
+    }
+
+    #[test]
+    fn generated_config() {
+        let names = BTreeMap::from([(1, "web".to_string()), (2, "<b>{chat}</b> & co".to_string())]);
+        let tags = Tags { names, ..Default::default() };
+        let config = generate(&Options { tags: 3, monitor: None, include_hidden: false }, &tags);
+        assert_eq!(serde_json::to_string_pretty(&config).unwrap(), r#"{
+  "custom/hyprtag-1": {
+    "format": "web",
+    "on-click": "hyprtagctl show 1",
+    "on-click-right": "hyprtagctl toggle 1",
+    "on-scroll-down": "hyprtagctl show 2",
+    "on-scroll-up": "hyprtagctl show 3",
+    "tooltip": false
+  },
+  "custom/hyprtag-2": {
+    "format": "&lt;b&gt;{{chat}}&lt;/b&gt; &amp; co",
+    "on-click": "hyprtagctl show 2",
+    "on-click-right": "hyprtagctl toggle 2",
+    "on-scroll-down": "hyprtagctl show 3",
+    "on-scroll-up": "hyprtagctl show 1",
+    "tooltip": false
+  },
+  "custom/hyprtag-3": {
+    "format": "3",
+    "on-click": "hyprtagctl show 3",
+    "on-click-right": "hyprtagctl toggle 3",
+    "on-scroll-down": "hyprtagctl show 1",
+    "on-scroll-up": "hyprtagctl show 2",
+    "tooltip": false
+  },
+  "modules-left": [
+    "custom/hyprtag-1",
+    "custom/hyprtag-2",
+    "custom/hyprtag-3"
+  ]
+}"#);
+    }
+
+    #[test]
+    fn generated_config_for_monitor() {
+        let config = generate(&Options { tags: 1, monitor: Some("HDMI-A-1".into()), include_hidden: false }, &Tags::default());
+        assert_eq!(serde_json::to_string_pretty(&config).unwrap(), r#"{
+  "custom/hyprtag-HDMI-A-1-1": {
+    "format": "1",
+    "on-click": "hyprtagctl show_on HDMI-A-1 1",
+    "on-click-right": "hyprtagctl sync focus_monitor HDMI-A-1 && hyprtagctl toggle 1",
+    "on-scroll-down": "hyprtagctl show_on HDMI-A-1 1",
+    "on-scroll-up": "hyprtagctl show_on HDMI-A-1 1",
+    "tooltip": false
+  },
+  "modules-left": [
+    "custom/hyprtag-HDMI-A-1-1"
+  ],
+  "output": "HDMI-A-1"
+}"#);
+
+        assert_eq!(shell_quote("it's mine"), r"'it'\''s mine'");
+    }
+
+    #[test]
+    fn hidden_tags_left_out() {
+        let hidden = TagConfig { hidden: true, ..Default::default() };
+        let tags = Tags { settings: BTreeMap::from([(2, hidden.clone()), (4, hidden)]), ..Default::default() };
+        let config = generate(&Options { tags: 4, monitor: None, include_hidden: false }, &tags);
+        assert_eq!(config["modules-left"], serde_json::json!(["custom/hyprtag-1", "custom/hyprtag-3"]));
+        // scrolling skips them, wrapping around
+        assert_eq!(config["custom/hyprtag-1"]["on-scroll-down"], "hyprtagctl show 3");
+        assert_eq!(config["custom/hyprtag-3"]["on-scroll-down"], "hyprtagctl show 1");
+        assert_eq!(config["custom/hyprtag-1"]["on-scroll-up"], "hyprtagctl show 3");
+
+        let config = generate(&Options { tags: 4, monitor: None, include_hidden: true }, &tags);
+        assert_eq!(config["modules-left"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn wide_names() {
+        let names = BTreeMap::from([(1, "仕事".to_string()), (2, "🎵 <{音楽}>".to_string()), (3, "café".to_string())]);
+        let config = generate(&Options { tags: 3, monitor: Some("モニター".into()), include_hidden: false }, &Tags { names, ..Default::default() });
+        assert_eq!(config["custom/hyprtag-モニター-1"]["format"], "仕事");
+        assert_eq!(config["custom/hyprtag-モニター-2"]["format"], "🎵 &lt;{{音楽}}&gt;");
+        assert_eq!(config["custom/hyprtag-モニター-3"]["on-click"], "hyprtagctl show_on 'モニター' 3");
+        assert!(serde_json::to_string(&config).unwrap().contains(r#""format":"café""#));
+    }
+}
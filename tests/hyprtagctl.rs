@@ -0,0 +1,206 @@
+use std::{io::{BufRead, BufReader, Write}, os::unix::net::UnixListener, path::PathBuf, process::{Command, Output}, thread};
+
+use hyprtag::protocol::{CommandInfo, COMMANDS};
+
+/// A daemon's ctrl socket under a runtime dir of its own, answering each connection by `answer`.
+struct Daemon {
+    runtime: PathBuf,
+    server: thread::JoinHandle<Vec<String>>,
+}
+
+impl Daemon {
+    fn start(name: &str, connections: usize, answer: fn(&str) -> Option<String>) -> Self {
+        let runtime = std::env::temp_dir().join(format!("hyprtagctl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&runtime);
+        let dir = runtime.join("hypr/sig");
+        std::fs::create_dir_all(&dir).unwrap();
+        let listener = UnixListener::bind(dir.join(".hyprtagctl.sock")).unwrap();
+        let server = thread::spawn(move || {
+            let mut lines = vec![];
+            for stream in listener.incoming().take(connections) {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_string();
+                if let Some(reply) = answer(&line) {
+                    writeln!(stream.get_mut(), "{}", reply).unwrap();
+                } else {
+                    // holds the connection open past the client's timeout
+                    let mut rest = String::new();
+                    let _ = stream.read_line(&mut rest);
+                }
+                lines.push(line);
+            }
+            lines
+        });
+        Self { runtime, server }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_hyprtagctl"))
+            .args(args)
+            .env("XDG_RUNTIME_DIR", &self.runtime)
+            .env("XDG_CACHE_HOME", self.cache())
+            .env("HYPRTAG_SIGNATURE", "sig")
+            .output()
+            .unwrap()
+    }
+
+    fn cache(&self) -> PathBuf {
+        self.runtime.join("cache")
+    }
+
+    /// The lines the daemon got.
+    fn stop(self) -> Vec<String> {
+        let lines = self.server.join().unwrap();
+        std::fs::remove_dir_all(&self.runtime).unwrap();
+        lines
+    }
+}
+
+fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+#[test]
+fn commands_and_replies() {
+    let daemon = Daemon::start("replies", 4, |line| Some(match line {
+        "show 3" => "ok".to_string(),
+        "move 40" => "err: no such tag: 40, tags go up to 32".to_string(),
+        "query" => r#"{"active_monitor":"DP-1","monitors":[{"id":"DP-1"},{"id":"HDMI-A-1"}]}"#.to_string(),
+        line => format!("unexpected {}", line),
+    }));
+
+    let out = daemon.run(&["show", "3"]);
+    assert_eq!((out.status.code(), stdout(&out)), (Some(0), String::new()));
+    let out = daemon.run(&["move", "40"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(stderr(&out), "err: no such tag: 40, tags go up to 32\n");
+    let out = daemon.run(&["query"]);
+    assert_eq!((out.status.code(), stdout(&out)), (Some(0), "{\"active_monitor\":\"DP-1\",\"monitors\":[{\"id\":\"DP-1\"},{\"id\":\"HDMI-A-1\"}]}\n".to_string()));
+    let out = daemon.run(&["query", "--format", "{monitors.id|join: }"]);
+    assert_eq!((out.status.code(), stdout(&out)), (Some(0), "DP-1 HDMI-A-1\n".to_string()));
+
+    // unknown commands don't get sent
+    let out = daemon.run(&["shwo", "3"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).starts_with("hyprtagctl: unknown command 'shwo', did you mean 'show'?"), "{}", stderr(&out));
+
+    assert_eq!(daemon.stop(), ["show 3", "move 40", "query", "query"]);
+}
+
+#[test]
+fn slow_daemon() {
+    let daemon = Daemon::start("slow", 2, |line| line.starts_with("id:").then(|| "ok".to_string()));
+
+    let out = daemon.run(&["--timeout", "0.2", "show", "3"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(stderr(&out), "hyprtagctl: no reply from hyprtag\n");
+    let out = daemon.run(&["--retry", "sync", "show", "3"]);
+    assert_eq!((out.status.code(), stdout(&out)), (Some(0), "ok\n".to_string()));
+
+    let lines = daemon.stop();
+    assert_eq!(lines[0], "show 3");
+    assert!(lines[1].starts_with("id:") && lines[1].ends_with(" sync show 3"), "{}", lines[1]);
+}
+
+#[test]
+fn no_daemon() {
+    let runtime = std::env::temp_dir().join(format!("hyprtagctl-none-{}", std::process::id()));
+    std::fs::create_dir_all(runtime.join("hypr/sig")).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_hyprtagctl"))
+        .args(["show", "3"])
+        .env("XDG_RUNTIME_DIR", &runtime)
+        .env("XDG_CACHE_HOME", runtime.join("cache"))
+        .env("HYPRTAG_SIGNATURE", "sig")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).starts_with("hyprtagctl: cannot connect to hyprtag at "), "{}", stderr(&out));
+    std::fs::remove_dir_all(runtime).unwrap();
+}
+
+#[test]
+fn diff_of_a_long_session() {
+    let daemon = Daemon::start("diff", 1, |line| Some(match line.starts_with("diff ") {
+        true => r#"{"clean":true,"visible_tags":[],"windows":[],"misplaced":[],"untracked":[],"dead":[]}"#.to_string(),
+        false => format!("unexpected {}", line),
+    }));
+    let client = |i| format!(r#"{{"address":"0x{:x}","workspace":{{"id":1,"name":"1"}},"monitor":0,"class":"kitty","title":"{}"}}"#, i, "x".repeat(200));
+    let snapshot = format!(r#"{{"state":{{"active_monitor":0,"monitors":[]}},"clients":[{}],"monitors":[]}}"#,
+        (0..400).map(client).collect::<Vec<_>>().join(","));
+    assert!(snapshot.len() > 64 * 1024);
+    let path = daemon.runtime.join("snapshot.json");
+    std::fs::write(&path, snapshot).unwrap();
+
+    let out = daemon.run(&["--json", "diff", path.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr(&out));
+    assert_eq!(daemon.stop(), [r#"diff {"state":{"active_monitor":0,"monitors":[]}}"#]);
+}
+
+/// The bundled commands with one only the daemon knows.
+fn newer_commands() -> String {
+    let mut commands = COMMANDS.to_vec();
+    commands.push(CommandInfo { name: "swap".into(), syntax: "<tag>".into(), about: "Swaps the tags.".into(), ..COMMANDS[0].clone() });
+    serde_json::to_string(&commands).unwrap()
+}
+
+#[test]
+fn help_live_cached_bundled() {
+    let daemon = Daemon::start("help", 1, |line| Some(match line {
+        "help" => newer_commands(),
+        line => format!("unexpected {}", line),
+    }));
+
+    let out = daemon.run(&["help"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(stdout(&out).starts_with("commands of the running hyprtag:\n"), "{}", stdout(&out));
+    assert!(stdout(&out).contains("\n  swap <tag> "), "{}", stdout(&out));
+
+    // the daemon is gone, what it described was cached
+    let out = daemon.run(&["swap", "--help"]);
+    assert_eq!((out.status.code(), stdout(&out)), (Some(0), "usage: hyprtagctl swap <tag>\n\nSwaps the tags.\n".to_string()));
+    let out = daemon.run(&["help"]);
+    assert!(stdout(&out).starts_with("commands as cached from hyprtag, which isn't running:\n"), "{}", stdout(&out));
+    let out = daemon.run(&["help", "swap", "--json"]);
+    let swap: CommandInfo = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(swap.about, "Swaps the tags.");
+
+    // nothing cached either
+    std::fs::remove_dir_all(daemon.cache()).unwrap();
+    let out = daemon.run(&["help"]);
+    assert!(stdout(&out).starts_with("commands built into hyprtagctl, hyprtag isn't running:\n"), "{}", stdout(&out));
+    let out = daemon.run(&["help", "--json"]);
+    assert_eq!(serde_json::from_slice::<Vec<CommandInfo>>(&out.stdout).unwrap(), COMMANDS);
+    let out = daemon.run(&["swap", "--help"]);
+    assert_eq!(out.status.code(), Some(2));
+
+    assert_eq!(daemon.stop(), ["help"]);
+}
+
+#[test]
+fn completions() {
+    let daemon = Daemon::start("complete", 4, |line| Some(match line {
+        "help" => newer_commands(),
+        "query" => r#"{"active_monitor":"DP-1","monitors":[{"id":"DP-1","name":"DP-1","tags":[{"tag":1,"name":"web"},{"tag":2,"name":"2"}]}],"windows":{}}"#.to_string(),
+        line => format!("unexpected {}", line),
+    }));
+
+    let out = daemon.run(&["complete", "sw"]);
+    assert_eq!(stdout(&out), "swap\n");
+    let out = daemon.run(&["complete", "--json", "show", ""]);
+    assert_eq!(stdout(&out), "1\nweb\n2\n");
+    let out = daemon.run(&["complete", "toggle", "2", "f"]);
+    assert_eq!(stdout(&out), "focus\n");
+
+    let out = daemon.run(&["completions", "bash"]);
+    assert!(stdout(&out).ends_with("complete -F _hyprtagctl hyprtagctl\n"), "{}", stdout(&out));
+    let out = daemon.run(&["completions", "zsh"]);
+    assert_eq!(out.status.code(), Some(2));
+
+    assert_eq!(daemon.stop(), ["help", "help", "query", "help"]);
+}
@@ -0,0 +1,220 @@
+use std::{path::Path, process::Command};
+
+fn fixtures() -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Output of `hyprtag --replay` for the capture.
+fn replay(capture: &Path) -> String {
+    let out = Command::new(env!("CARGO_BIN_EXE_hyprtag"))
+        .arg("--replay")
+        .arg(capture)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}: {}", capture.display(), String::from_utf8_lossy(&out.stderr));
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+/// The hyprctl calls of the replay, after the startup probe of the hidden workspaces.
+fn hyprctl_calls(out: &str) -> Vec<&str> {
+    out.lines()
+        .filter(|l| l.split(' ').nth(1) == Some("hyprctl") && !l.contains("focusworkspaceoncurrentmonitor"))
+        .collect()
+}
+
+/// Captures which need a feature, with whether it's compiled in.
+const FIXTURE_FEATURES: &[(&str, bool)] = &[
+    ("tag_keywords.capture", cfg!(feature = "hooks")),
+    ("preview.capture", cfg!(feature = "json-proto")),
+    ("desync.capture", cfg!(feature = "json-proto")),
+];
+
+/// Replays each capture in tests/fixtures and compares the output with the `.golden` file next to it.
+/// After an intended change, regenerate with `hyprtag --replay <capture> > <golden>`.
+#[test]
+fn replay_fixtures() {
+    let mut captures: Vec<_> = std::fs::read_dir(fixtures()).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "capture"))
+        .filter(|p| FIXTURE_FEATURES.iter().all(|(name, on)| *on || !p.ends_with(name)))
+        .collect();
+    captures.sort();
+    assert!(!captures.is_empty());
+
+    for capture in captures {
+        let golden = std::fs::read_to_string(capture.with_extension("golden")).unwrap();
+        assert_eq!(replay(&capture), golden, "{}", capture.display());
+    }
+}
+
+/// Once the burst started, the rest of the restored windows are hidden in one batch.
+#[test]
+fn session_restore_burst() {
+    let out = replay(&fixtures().join("session_restore.capture"));
+    let batches = hyprctl_calls(&out);
+    // the first two windows came before there was a burst
+    assert_eq!(batches.len(), 3, "{}", out);
+    assert_eq!(batches[2].matches("movetoworkspacesilent 105").count(), 6);
+}
+
+/// Each batch of dispatches is preceded by what caused it.
+#[test]
+fn change_sources() {
+    let out = replay(&fixtures().join("tags.capture"));
+    let sources: Vec<&str> = out.lines().filter(|l| l.split(' ').nth(1) == Some("source")).collect();
+    assert_eq!(sources.first(), Some(&"400 source ctrl 'move 2'"), "{}", out);
+    assert!(sources.contains(&"900 source ctrl 'show 2'"), "{}", out);
+    assert_eq!(sources.last(), Some(&"2500 source event closewindow"), "{}", out);
+
+    let out = replay(&fixtures().join("session_restore.capture"));
+    assert!(out.lines().any(|l| l == "1000 source event openwindow"), "{}", out);
+}
+
+/// A tag's keywords are set before its windows move in and restored after they moved out.
+#[cfg(feature = "hooks")]
+#[test]
+fn tag_keywords() {
+    let out = replay(&fixtures().join("tag_keywords.capture"));
+    let calls = hyprctl_calls(&out);
+    assert_eq!(calls[1], "200 hyprctl keyword general:gaps_in 0;keyword general:border_size 0", "{}", out);
+    assert!(calls[2].starts_with("200 hyprctl dispatch movetoworkspacesilent 1,"), "{}", out);
+    assert!(calls[3].starts_with("300 hyprctl dispatch movetoworkspacesilent 102,"), "{}", out);
+    // the saved values, without a hide list
+    assert_eq!(calls[4], "300 hyprctl keyword general:gaps_in 5;keyword general:border_size 2", "{}", out);
+    assert_eq!(calls[6], "500 hyprctl keyword general:border_size 1", "{}", out);
+}
+
+/// A preview lists the dispatches the command sends when it runs right after, 10ms later in the capture.
+#[cfg(feature = "json-proto")]
+#[test]
+fn previews_match_runs() {
+    let out = replay(&fixtures().join("preview.capture"));
+    let mut previews = 0;
+    let replies = out.lines()
+        .filter_map(|l| l.split_once(" reply preview "))
+        .filter_map(|(at, reply)| Some((at, reply.split_once(": {")?.1)));
+    for (at, json) in replies {
+        let preview: serde_json::Value = serde_json::from_str(&format!("{{{}", json)).unwrap();
+        let dispatches: Vec<&str> = preview["dispatches"].as_array().unwrap().iter().map(|d| d.as_str().unwrap()).collect();
+
+        let ran = format!("{} hyprctl ", at.parse::<u64>().unwrap() + 10);
+        let sent: Vec<&str> = out.lines().filter_map(|l| l.strip_prefix(&ran)).flat_map(|l| l.split(';')).collect();
+        assert_eq!(dispatches, sent, "{} {}", at, json);
+        previews += 1;
+    }
+    assert_eq!(previews, 5, "{}", out);
+    assert!(out.contains("reply preview focus_monitor DP-2: err: focus_monitor can't be previewed"), "{}", out);
+}
+
+/// A window dragged to another monitor goes on the tag active there, while late echoes of our own moves are ignored.
+#[test]
+fn dragged_window() {
+    let out = replay(&fixtures().join("drag.capture"));
+    // placing it back on DP-1 moves it, so the drag put it on DP-2
+    assert!(out.lines().any(|l| l == "2000 hyprctl dispatch movetoworkspacesilent 1,address:0x7e3"), "{}", out);
+
+    let snapshot: serde_json::Value = serde_json::from_str(out.split_once("snapshot ").unwrap().1).unwrap();
+    assert_eq!(snapshot["monitors"][0]["tags"], serde_json::json!({}), "{}", out);
+    // firefox was there before the replay started, on the tag active then
+    assert_eq!(snapshot["monitors"][1]["tags"], serde_json::json!({"1": ["1de"], "3": ["7e3"]}), "{}", out);
+}
+
+/// While hyprctl hangs, events and commands are still handled and the oldest focus changes are dropped, the
+/// window moves all go out once it's back.
+#[test]
+fn stalled_hyprctl() {
+    let out = replay(&fixtures().join("stall.capture"));
+    assert!(out.lines().any(|l| l.starts_with("1500 reply stale 0: ") && l.contains("{\"addr\":\"1de\"")), "{}", out);
+
+    let calls = hyprctl_calls(&out);
+    assert!(calls.iter().all(|l| l.starts_with("3100 ")), "{}", out);
+    assert!(calls.iter().any(|l| l.contains("movetoworkspacesilent 102,address:0x1de")), "{}", out);
+    assert!(calls.iter().any(|l| l.contains("movetoworkspacesilent 102,address:0x2ab")), "{}", out);
+    let dropped: Vec<&str> = out.lines().filter(|l| l.split(' ').nth(1) == Some("dropped")).collect();
+    assert!(!dropped.is_empty() && dropped.iter().all(|l| l.contains("focusmonitor")), "{}", out);
+
+    let snapshot: serde_json::Value = serde_json::from_str(out.split_once("snapshot ").unwrap().1).unwrap();
+    assert_eq!(snapshot["monitors"][0]["tags"], serde_json::json!({"1": ["7e3"], "2": ["2ab"], "3": ["1de"]}), "{}", out);
+}
+
+/// Startup moves go out two windows per batch, 100ms apart, and a command moving a window still to come sends
+/// the window's own move first.
+#[test]
+fn adoption_chunks() {
+    let out = replay(&fixtures().join("adoption.capture"));
+    let batches = hyprctl_calls(&out);
+    assert_eq!(batches.len(), 3, "{}", out);
+    assert!(batches[0].starts_with("0 ") && batches[1].starts_with("100 "), "{}", out);
+    assert_eq!(batches[1].matches("movetoworkspacesilent 1,").count(), 2);
+    assert!(batches[2].starts_with("150 hyprctl dispatch movetoworkspacesilent 1,address:0xa5;dispatch movetoworkspacesilent 101,address:0xa5"), "{}", out);
+}
+
+/// A window closing while its dispatches are on the way doesn't take the rest of their batch down: what Hyprland
+/// skipped after the failure is sent again without the window, and batches still queued when its closewindow
+/// arrives leave it out.
+#[test]
+fn closed_mid_batch() {
+    let out = replay(&fixtures().join("closed_mid_batch.capture"));
+    let lines: Vec<&str> = out.lines().take_while(|l| !l.starts_with("snapshot")).collect();
+    let after = |line: &str| lines.iter().position(|l| *l == line).map(|i| lines[i + 1]);
+    assert_eq!(after("210 rejected dispatch movetoworkspacesilent 101,address:0x7e3"),
+        Some("210 hyprctl dispatch movetoworkspacesilent 1,address:0x1de;dispatch focuswindow address:0x1de"), "{}", out);
+    assert_eq!(after("1400 rejected dispatch movetoworkspacesilent 1,address:0x2ab"),
+        Some("1400 hyprctl dispatch focuswindow address:0x1de"), "{}", out);
+    assert_eq!(lines.last(), Some(&"1400 hyprctl dispatch movetoworkspacesilent 102,address:0x1de"), "{}", out);
+
+    let snapshot: serde_json::Value = serde_json::from_str(out.split_once("snapshot ").unwrap().1).unwrap();
+    assert_eq!(snapshot["monitors"][0]["tags"], serde_json::json!({"2": ["1de"]}), "{}", out);
+    assert_eq!(snapshot["monitors"][0]["visible_tags"], 4, "{}", out);
+}
+
+/// Subscribers are told a window is out of sync with the rejected dispatch which made its count reach the threshold,
+/// once, and that it's back in sync when one of its dispatches goes through or it closes.
+#[cfg(feature = "json-proto")]
+#[test]
+fn desync_events() {
+    const DESYNC_THRESHOLD: usize = 3;
+    let out = replay(&fixtures().join("desync.capture"));
+    let lines: Vec<&str> = out.lines().take_while(|l| !l.starts_with("snapshot")).collect();
+    let events: Vec<(usize, serde_json::Value)> = lines.iter().enumerate()
+        .filter_map(|(i, l)| l.split_once(" subscribe ").map(|(_, line)| (i, serde_json::from_str::<serde_json::Value>(line).unwrap())))
+        .filter(|(_, e)| e["event"] != "monitor")
+        .collect();
+    assert_eq!(events.len(), 4, "{}", out);
+
+    for (window, (i, event)) in ["7e3", "1de"].iter().zip(&events[..2]) {
+        let rejected: Vec<&&str> = lines[..*i].iter()
+            .filter(|l| l.contains(" rejected dispatch ") && l.ends_with(&format!("address:0x{}", window)))
+            .collect();
+        assert_eq!(rejected.len(), DESYNC_THRESHOLD, "{}", out);
+        assert!(rejected.last().unwrap().ends_with(&format!("movetoworkspacesilent 104,address:0x{}", window)), "{}", out);
+        assert_eq!(event["event"], "desync", "{}", out);
+        assert_eq!(event["windows"], serde_json::json!([
+            {"addr": window, "expected_workspace": 104, "actual_workspace": 1, "count": DESYNC_THRESHOLD},
+        ]), "{}", out);
+    }
+
+    // 7e3's next dispatch went through, 1de closed
+    assert!(lines[..events[2].0].contains(&"510 hyprctl dispatch movetoworkspacesilent 106,address:0x7e3"), "{}", out);
+    assert!(lines[events[2].0].starts_with("510 "), "{}", out);
+    assert_eq!((&events[2].1["event"], &events[2].1["windows"]), (&serde_json::json!("resync"), &serde_json::json!(["7e3"])), "{}", out);
+    assert!(lines[events[3].0].starts_with("600 "), "{}", out);
+    assert_eq!((&events[3].1["event"], &events[3].1["windows"]), (&serde_json::json!("resync"), &serde_json::json!(["1de"])), "{}", out);
+}
+
+/// Hidden workspaces are numbered from 11 when Hyprland rejects the top of the default range, and keep their
+/// default numbers when it rejects the compact one too. Only an accepted workspace gets switched back from.
+#[test]
+fn hidden_workspace_limits() {
+    let out = replay(&fixtures().join("workspace_limits.capture"));
+    assert!(out.starts_with("0 rejected dispatch focusworkspaceoncurrentmonitor 164\n0 hyprctl dispatch focusworkspaceoncurrentmonitor 74\n\
+        0 hyprctl dispatch focusworkspaceoncurrentmonitor previous\n4"), "{}", out);
+    assert!(out.lines().any(|l| l == "400 hyprctl dispatch movetoworkspacesilent 11,address:0x1de"), "{}", out);
+    assert!(out.lines().any(|l| l == "900 hyprctl dispatch movetoworkspacesilent 45,address:0x2ab"), "{}", out);
+
+    let out = replay(&fixtures().join("workspace_limits_unusable.capture"));
+    assert!(out.contains("0 rejected dispatch focusworkspaceoncurrentmonitor 42\n"), "{}", out);
+    assert!(!out.contains("previous"), "{}", out);
+    assert!(out.lines().any(|l| l == "400 hyprctl dispatch movetoworkspacesilent 101,address:0x1de"), "{}", out);
+}